@@ -128,6 +128,8 @@ struct RawRepoConfig {
 
   // Define hook available for use on bookmarks
   9: optional list<RawHookConfig> hooks;
+  // Defaults for the config of every hook, overridden by each hook's config
+  55: optional RawHookDefaults hook_defaults;
 
   // This enables or disables verification for censored blobstores
   11: optional bool redaction;
@@ -648,6 +650,9 @@ struct RawHookManagerParams {
   1: bool disable_acl_checker;
   2: bool all_hooks_bypassed;
   3: optional string bypassed_commits_scuba_table;
+  // How long a hook may run on a changeset, a file or a bookmark move,
+  // unless the hook sets its own timeout_ms. Hooks running longer reject.
+  4: optional i64 default_hook_timeout_ms;
 } (rust.exhaustive)
 
 struct RawHookConfig {
@@ -664,8 +669,45 @@ struct RawHookConfig {
   11: optional map<string, list<i64>> (
     rust.type = "HashMap",
   ) config_int_64_lists;
+  // If unset, the hook is looked up as a changeset hook, then as a file hook.
+  12: optional RawHookKind kind;
+  // How long the hook may run on a changeset, a file or a bookmark move.
+  // If unset, the default_hook_timeout_ms of the hook manager applies.
+  13: optional i64 timeout_ms;
+} (rust.exhaustive)
+
+// Defaults for the config of every hook of a repo. A hook's own bypass
+// commit string, bypass pushvar, and config values take precedence.
+struct RawHookDefaults {
+  1: optional string bypass_commit_string;
+  2: optional string bypass_pushvar;
+  3: optional map<string, string> (rust.type = "HashMap") config_strings;
+  4: optional map<string, i32> (rust.type = "HashMap") config_ints;
+  5: optional map<string, list<string>> (
+    rust.type = "HashMap",
+  ) config_string_lists;
+  6: optional map<string, list<i32>> (rust.type = "HashMap") config_int_lists;
+  7: optional map<string, i64> (rust.type = "HashMap") config_ints_64;
+  8: optional map<string, list<i64>> (
+    rust.type = "HashMap",
+  ) config_int_64_lists;
+  // How long each hook may run, in milliseconds, unless the hook sets its
+  // own timeout_ms.
+  9: optional i64 timeout_ms;
 } (rust.exhaustive)
 
+// The category of a hook, which determines what it is run against.
+enum RawHookKind {
+  /// Run once for every changeset.
+  CHANGESET = 0,
+
+  /// Run once for every file changed by a changeset.
+  FILE = 1,
+
+  /// Run once for every bookmark creation, move or deletion.
+  BOOKMARK = 2,
+}
+
 struct RawLfsParams {
   1: optional i64 threshold;
   // What percentage of client host gets lfs pointers
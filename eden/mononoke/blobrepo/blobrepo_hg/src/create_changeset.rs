@@ -334,6 +334,7 @@ impl CreateChangeset {
                 let completion_record = ChangesetInsert {
                     cs_id: bonsai_cs.get_changeset_id(),
                     parents: bonsai_cs.parents().collect(),
+                    extra: None,
                 };
                 complete_changesets
                     .add(&ctx, completion_record)
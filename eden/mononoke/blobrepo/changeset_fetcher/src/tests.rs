@@ -37,6 +37,7 @@ async fn test_prefetched_fetcher_no_prefetching(fb: FacebookInit) -> Result<()>
     let row = ChangesetInsert {
         cs_id: ONES_CSID,
         parents: vec![],
+        extra: None,
     };
     changesets.add(ctx, row).await?;
 
@@ -76,6 +77,7 @@ async fn test_prefetched_fetcher_no_overlap(fb: FacebookInit) -> Result<()> {
     let row = ChangesetInsert {
         cs_id: ONES_CSID,
         parents: vec![],
+        extra: None,
     };
     changesets.add(ctx, row).await?;
 
@@ -84,6 +86,7 @@ async fn test_prefetched_fetcher_no_overlap(fb: FacebookInit) -> Result<()> {
         cs_id: TWOS_CSID,
         parents: vec![ONES_CSID],
         gen: 5,
+        extra: None,
     });
 
     let prefetched_fetcher =
@@ -134,11 +137,13 @@ async fn test_prefetched_fetcher_overlap(fb: FacebookInit) -> Result<()> {
     let row = ChangesetInsert {
         cs_id: ONES_CSID,
         parents: vec![],
+        extra: None,
     };
     changesets.add(ctx, row).await?;
     let row = ChangesetInsert {
         cs_id: TWOS_CSID,
         parents: vec![ONES_CSID],
+        extra: None,
     };
     changesets.add(ctx, row).await?;
 
@@ -147,6 +152,7 @@ async fn test_prefetched_fetcher_overlap(fb: FacebookInit) -> Result<()> {
         cs_id: TWOS_CSID,
         parents: vec![THREES_CSID],
         gen: 5,
+        extra: None,
     });
 
     let prefetched_fetcher =
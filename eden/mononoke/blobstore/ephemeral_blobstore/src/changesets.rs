@@ -14,6 +14,7 @@ use blobstore::Loadable;
 use changesets::ChangesetEntry;
 use changesets::ChangesetInsert;
 use changesets::Changesets;
+use changesets::EnumerationEntry;
 use changesets::SortOrder;
 use context::CoreContext;
 use derivative::Derivative;
@@ -153,6 +154,7 @@ impl EphemeralChangesets {
                     cs_id,
                     parents: cs.parents().collect(),
                     gen: *gen,
+                    extra: None,
                 })
             })
             .collect())
@@ -243,14 +245,15 @@ impl Changesets for EphemeralChangesets {
         unimplemented!()
     }
 
-    fn list_enumeration_range(
+    fn list_enumeration_range_detailed(
         &self,
         _ctx: &CoreContext,
         _min_id: u64,
         _max_id: u64,
         _sort_and_limit: Option<(SortOrder, u64)>,
         _read_from_master: bool,
-    ) -> BoxStream<'_, Result<(ChangesetId, u64)>> {
+        _include_parents: bool,
+    ) -> BoxStream<'_, Result<EnumerationEntry>> {
         unimplemented!()
     }
 }
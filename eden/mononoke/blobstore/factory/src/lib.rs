@@ -62,7 +62,7 @@ impl ArgDefaults for ReadOnlyStorage {
 
 impl ReadOnlyStorage {
     pub fn from_args(args: &ReadOnlyStorageArgs) -> Self {
-        ReadOnlyStorage(args.with_readonly_storage)
+        ReadOnlyStorage(args.with_readonly_storage || args.force_readonly)
     }
 }
 
@@ -81,4 +81,9 @@ pub struct ReadOnlyStorageArgs {
         num_args = 0..=1,
     )]
     pub with_readonly_storage: bool,
+
+    /// Reject any attempts to write to storage or to repo metadata, such as
+    /// bookmarks and changesets. Implies --with-readonly-storage.
+    #[clap(long)]
+    pub force_readonly: bool,
 }
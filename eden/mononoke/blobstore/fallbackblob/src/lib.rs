@@ -0,0 +1,218 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Result;
+use async_trait::async_trait;
+use blobstore::Blobstore;
+use blobstore::BlobstoreGetData;
+use blobstore::BlobstoreIsPresent;
+use blobstore::BlobstorePutOps;
+use blobstore::OverwriteStatus;
+use blobstore::PutBehaviour;
+use context::CoreContext;
+use mononoke_types::BlobstoreBytes;
+use stats::prelude::*;
+
+define_stats! {
+    prefix = "mononoke.fallback_blobstore";
+    gets_from_fallback: timeseries(Sum),
+    is_present_from_fallback: timeseries(Sum),
+}
+
+/// A layer over two blobstores for migrating from one to the other.
+///
+/// Reads go to the primary blobstore first and fall back to the secondary
+/// one if the blob isn't there, while writes only ever go to the primary.
+/// Reads satisfied by the secondary are counted, so that the progress of
+/// the migration can be tracked.
+#[derive(Debug)]
+pub struct FallbackReadBlobstore<T, U> {
+    primary: T,
+    secondary: U,
+}
+
+impl<T: std::fmt::Display, U: std::fmt::Display> std::fmt::Display for FallbackReadBlobstore<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "FallbackReadBlobstore<{}, {}>",
+            &self.primary, &self.secondary
+        )
+    }
+}
+
+impl<T, U> FallbackReadBlobstore<T, U> {
+    pub fn new(primary: T, secondary: U) -> Self {
+        Self { primary, secondary }
+    }
+}
+
+#[async_trait]
+impl<T: Blobstore, U: Blobstore> Blobstore for FallbackReadBlobstore<T, U> {
+    async fn get<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: &'a str,
+    ) -> Result<Option<BlobstoreGetData>> {
+        if let Some(data) = self.primary.get(ctx, key).await? {
+            return Ok(Some(data));
+        }
+        let data = self.secondary.get(ctx, key).await?;
+        if data.is_some() {
+            STATS::gets_from_fallback.add_value(1);
+        }
+        Ok(data)
+    }
+
+    #[inline]
+    async fn put<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+    ) -> Result<()> {
+        self.primary.put(ctx, key, value).await
+    }
+
+    async fn is_present<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: &'a str,
+    ) -> Result<BlobstoreIsPresent> {
+        let primary = self.primary.is_present(ctx, key).await?;
+        if let BlobstoreIsPresent::Present = primary {
+            return Ok(primary);
+        }
+        let secondary = self.secondary.is_present(ctx, key).await?;
+        Ok(match (primary, secondary) {
+            (_, BlobstoreIsPresent::Present) => {
+                STATS::is_present_from_fallback.add_value(1);
+                BlobstoreIsPresent::Present
+            }
+            // If either blobstore is unsure, so are we.
+            (BlobstoreIsPresent::Absent, secondary) => secondary,
+            (primary, _) => primary,
+        })
+    }
+}
+
+#[async_trait]
+impl<T: BlobstorePutOps, U: Blobstore> BlobstorePutOps for FallbackReadBlobstore<T, U> {
+    async fn put_explicit<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+        put_behaviour: PutBehaviour,
+    ) -> Result<OverwriteStatus> {
+        self.primary
+            .put_explicit(ctx, key, value, put_behaviour)
+            .await
+    }
+
+    async fn put_with_status<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        key: String,
+        value: BlobstoreBytes,
+    ) -> Result<OverwriteStatus> {
+        self.primary.put_with_status(ctx, key, value).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use borrowed::borrowed;
+    use bytes::Bytes;
+    use fbinit::FacebookInit;
+    use memblob::Memblob;
+
+    use super::*;
+
+    #[fbinit::test]
+    async fn test_fallback_reads(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        borrowed!(ctx);
+        let primary = Memblob::default();
+        let secondary = Memblob::default();
+        let wrapper = FallbackReadBlobstore::new(primary.clone(), secondary.clone());
+
+        primary
+            .put(
+                ctx,
+                "both".to_owned(),
+                BlobstoreBytes::from_bytes("primary"),
+            )
+            .await?;
+        secondary
+            .put(
+                ctx,
+                "both".to_owned(),
+                BlobstoreBytes::from_bytes("secondary"),
+            )
+            .await?;
+        secondary
+            .put(
+                ctx,
+                "secondary_only".to_owned(),
+                BlobstoreBytes::from_bytes("secondary"),
+            )
+            .await?;
+
+        for (key, expected) in [
+            ("both", Some("primary")),
+            ("secondary_only", Some("secondary")),
+            ("missing", None),
+        ] {
+            let data = wrapper.get(ctx, key).await?;
+            assert_eq!(
+                data.map(|data| data.into_raw_bytes()),
+                expected.map(Bytes::from)
+            );
+            let present = wrapper.is_present(ctx, key).await?.fail_if_unsure()?;
+            assert_eq!(present, expected.is_some());
+        }
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_writes_go_to_primary_only(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        borrowed!(ctx);
+        let primary = Memblob::default();
+        let secondary = Memblob::default();
+        let wrapper = FallbackReadBlobstore::new(primary.clone(), secondary.clone());
+
+        wrapper
+            .put(ctx, "put".to_owned(), BlobstoreBytes::from_bytes("value"))
+            .await?;
+        wrapper
+            .put_with_status(
+                ctx,
+                "put_with_status".to_owned(),
+                BlobstoreBytes::from_bytes("value"),
+            )
+            .await?;
+        wrapper
+            .put_explicit(
+                ctx,
+                "put_explicit".to_owned(),
+                BlobstoreBytes::from_bytes("value"),
+                PutBehaviour::Overwrite,
+            )
+            .await?;
+
+        for key in ["put", "put_with_status", "put_explicit"] {
+            assert!(primary.is_present(ctx, key).await?.fail_if_unsure()?);
+            assert!(!secondary.is_present(ctx, key).await?.fail_if_unsure()?);
+            assert!(wrapper.is_present(ctx, key).await?.fail_if_unsure()?);
+        }
+
+        Ok(())
+    }
+}
@@ -26,6 +26,7 @@ use repo_update_logger::BookmarkOperation;
 
 use crate::affected_changesets::AdditionalChangesets;
 use crate::affected_changesets::AffectedChangesets;
+use crate::hook_running::check_bookmark_hooks;
 use crate::repo_lock::check_repo_lock;
 use crate::restrictions::check_bookmark_sync_config;
 use crate::restrictions::BookmarkKindRestrictions;
@@ -149,6 +150,19 @@ impl<'op> CreateBookmarkOp<'op> {
             )
             .await?;
 
+        check_bookmark_hooks(
+            ctx,
+            authz,
+            repo,
+            hook_manager,
+            self.bookmark,
+            self.pushvars,
+            self.reason,
+            kind,
+            &BookmarkOperation::Create(self.target),
+        )
+        .await?;
+
         check_repo_lock(repo, kind, self.pushvars, ctx.metadata().identities()).await?;
 
         let mut txn = repo.bookmarks().create_transaction(ctx.clone());
@@ -12,6 +12,7 @@ use bookmarks_types::BookmarkKey;
 use bookmarks_types::BookmarkKind;
 use bytes::Bytes;
 use context::CoreContext;
+use hooks::HookManager;
 use mononoke_types::ChangesetId;
 use repo_authorization::AuthorizationContext;
 use repo_authorization::RepoWriteOperation;
@@ -19,6 +20,7 @@ use repo_update_logger::log_bookmark_operation;
 use repo_update_logger::BookmarkInfo;
 use repo_update_logger::BookmarkOperation;
 
+use crate::hook_running::check_bookmark_hooks;
 use crate::repo_lock::check_repo_lock;
 use crate::restrictions::check_bookmark_sync_config;
 use crate::restrictions::BookmarkKindRestrictions;
@@ -76,6 +78,7 @@ impl<'op> DeleteBookmarkOp<'op> {
         ctx: &'op CoreContext,
         authz: &'op AuthorizationContext,
         repo: &'op impl Repo,
+        hook_manager: &'op HookManager,
     ) -> Result<(), BookmarkMovementError> {
         let kind = self.kind_restrictions.check_kind(repo, self.bookmark)?;
 
@@ -110,6 +113,19 @@ impl<'op> DeleteBookmarkOp<'op> {
             });
         }
 
+        check_bookmark_hooks(
+            ctx,
+            authz,
+            repo,
+            hook_manager,
+            self.bookmark,
+            self.pushvars,
+            self.reason,
+            kind,
+            &BookmarkOperation::Delete(self.old_target),
+        )
+        .await?;
+
         check_repo_lock(repo, kind, self.pushvars, ctx.metadata().identities()).await?;
 
         ctx.scuba()
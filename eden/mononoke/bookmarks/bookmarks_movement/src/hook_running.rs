@@ -10,18 +10,29 @@ use std::collections::HashMap;
 use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
+use bookmarks::BookmarkUpdateReason;
 use bookmarks_types::BookmarkKey;
+use bookmarks_types::BookmarkKind;
 use bytes::Bytes;
+use commit_graph::CommitGraphRef;
 use context::CoreContext;
 use futures_stats::TimedFutureExt;
+use hooks::BookmarkHookData;
+use hooks::BookmarkOperationKind;
 use hooks::CrossRepoPushSource;
+use hooks::HookExecution;
 use hooks::HookManager;
 use hooks::HookOutcome;
+use hooks::HookRejection;
 use hooks::PushAuthoredBy;
 use mononoke_types::BonsaiChangeset;
+use repo_authorization::AuthorizationContext;
+use repo_update_logger::BookmarkOperation;
 use tunables::tunables;
 
+use crate::restrictions::should_run_hooks;
 use crate::BookmarkMovementError;
+use crate::Repo;
 
 pub async fn is_admin_bypass(
     ctx: &CoreContext,
@@ -117,6 +128,7 @@ pub async fn run_hooks(
             changesets,
             bookmark,
             pushvars,
+            ctx.metadata().identities(),
             cross_repo_push_source,
             push_authored_by,
         )
@@ -141,3 +153,213 @@ pub async fn run_hooks(
         Err(BookmarkMovementError::HookFailure(rejections))
     }
 }
+
+/// If this is a user-initiated operation on a public bookmark, run the
+/// bookmark hooks bound to `bookmark` against it. This also checks the
+/// pusher gates of the bookmark, even if no changeset is affected.
+///
+/// Rejections are reported against the changeset the bookmark is created
+/// at or moved to, or the one it pointed at when deleted.
+pub(crate) async fn check_bookmark_hooks(
+    ctx: &CoreContext,
+    authz: &AuthorizationContext,
+    repo: &impl Repo,
+    hook_manager: &HookManager,
+    bookmark: &BookmarkKey,
+    pushvars: Option<&HashMap<String, Bytes>>,
+    reason: BookmarkUpdateReason,
+    kind: BookmarkKind,
+    operation: &BookmarkOperation,
+) -> Result<(), BookmarkMovementError> {
+    if (kind != BookmarkKind::Publishing && kind != BookmarkKind::PullDefaultPublishing)
+        || !should_run_hooks(authz, reason)
+        || !hook_manager.hooks_exist_for_bookmark(bookmark)
+    {
+        return Ok(());
+    }
+    if reason == BookmarkUpdateReason::Push
+        && tunables().disable_hooks_on_plain_push().unwrap_or_default()
+    {
+        return Ok(());
+    }
+    if is_admin_bypass(ctx, hook_manager, pushvars).await? || hook_manager.all_hooks_bypassed() {
+        return Ok(());
+    }
+
+    let (operation, cs_id) = match *operation {
+        BookmarkOperation::Create(to) | BookmarkOperation::Pushrebase(None, to) => {
+            (BookmarkOperationKind::Create { to }, to)
+        }
+        BookmarkOperation::Update(from, to) | BookmarkOperation::Pushrebase(Some(from), to) => {
+            let is_fast_forward = repo.commit_graph().is_ancestor(ctx, from, to).await?;
+            let operation = BookmarkOperationKind::Move {
+                from,
+                to,
+                is_fast_forward,
+            };
+            (operation, to)
+        }
+        BookmarkOperation::Delete(from) => (BookmarkOperationKind::Delete { from }, from),
+    };
+    let data = BookmarkHookData {
+        bookmark: bookmark.clone(),
+        operation,
+        pusher: ctx.metadata().identities().clone(),
+    };
+
+    let (stats, outcomes) = hook_manager
+        .run_bookmark_hooks(ctx, &data, pushvars)
+        .timed()
+        .await;
+    let outcomes =
+        outcomes.with_context(|| format!("Failed to run bookmark hooks for {}", bookmark))?;
+
+    let rejections: Vec<_> = outcomes
+        .into_iter()
+        .filter_map(|outcome| match outcome.execution {
+            HookExecution::Accepted => None,
+            HookExecution::Rejected(reason) => Some(HookRejection {
+                hook_name: outcome.id.hook_name,
+                cs_id,
+                reason,
+            }),
+        })
+        .collect();
+
+    ctx.scuba()
+        .clone()
+        .add_future_stats(&stats)
+        .add("hook_rejections", rejections.len())
+        .log_with_msg("Executed bookmark hooks", None);
+
+    if rejections.is_empty() {
+        Ok(())
+    } else {
+        Err(BookmarkMovementError::HookFailure(rejections))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use async_trait::async_trait;
+    use blobrepo::AsBlobRepo;
+    use bookmarks::BookmarksRef;
+    use fbinit::FacebookInit;
+    use hooks::BookmarkHook;
+    use hooks::HookRejectionInfo;
+    use hooks_content_stores::InMemoryFileContentManager;
+    use mononoke_api_types::InnerRepo;
+    use test_repo_factory::TestRepoFactory;
+    use tests_utils::drawdag::create_from_dag;
+
+    use super::*;
+    use crate::BookmarkUpdatePolicy;
+    use crate::BookmarkUpdateTargets;
+    use crate::CreateBookmarkOp;
+    use crate::DeleteBookmarkOp;
+    use crate::UpdateBookmarkOp;
+
+    /// Rejects deleting bookmarks and moving them non-fast-forward.
+    struct ProtectedBookmarkHook;
+
+    #[async_trait]
+    impl BookmarkHook for ProtectedBookmarkHook {
+        async fn run(
+            &self,
+            _ctx: &CoreContext,
+            data: &BookmarkHookData,
+        ) -> Result<HookExecution, Error> {
+            Ok(match data.operation {
+                BookmarkOperationKind::Delete { .. } => {
+                    HookExecution::Rejected(HookRejectionInfo::new("deletion"))
+                }
+                BookmarkOperationKind::Move {
+                    is_fast_forward: false,
+                    ..
+                } => HookExecution::Rejected(HookRejectionInfo::new("non-fast-forward move")),
+                BookmarkOperationKind::Create { .. } | BookmarkOperationKind::Move { .. } => {
+                    HookExecution::Accepted
+                }
+            })
+        }
+    }
+
+    fn assert_rejected(
+        res: Result<(), BookmarkMovementError>,
+        cs_id: ChangesetId,
+        description: &str,
+    ) {
+        match res {
+            Err(BookmarkMovementError::HookFailure(rejections)) => {
+                assert_eq!(rejections.len(), 1);
+                assert_eq!(rejections[0].hook_name, "protected");
+                assert_eq!(rejections[0].cs_id, cs_id);
+                assert_eq!(rejections[0].reason.description, description);
+            }
+            res => panic!("expected a hook rejection, got {:?}", res),
+        }
+    }
+
+    #[fbinit::test]
+    async fn test_bookmark_hooks(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let authz = AuthorizationContext::new_bypass_access_control();
+        let repo: InnerRepo = TestRepoFactory::new(fb)?.build().await?;
+        let dag = create_from_dag(
+            &ctx,
+            repo.as_blob_repo(),
+            r##"
+                A-B-C
+                   \
+                    D
+            "##,
+        )
+        .await?;
+        let a = *dag.get("A").unwrap();
+        let c = *dag.get("C").unwrap();
+        let d = *dag.get("D").unwrap();
+
+        let bookmark = BookmarkKey::new("main")?;
+        let mut hook_manager = HookManager::new_test(
+            "repo".to_string(),
+            Box::new(InMemoryFileContentManager::new()),
+        );
+        hook_manager.register_bookmark_hook(
+            "protected",
+            Box::new(ProtectedBookmarkHook),
+            Default::default(),
+        )?;
+        hook_manager.set_hooks_for_bookmark(bookmark.clone().into(), vec!["protected".to_string()]);
+
+        CreateBookmarkOp::new(&bookmark, a, BookmarkUpdateReason::TestMove)
+            .run(&ctx, &authz, &repo, &hook_manager)
+            .await?;
+        UpdateBookmarkOp::new(
+            &bookmark,
+            BookmarkUpdateTargets { old: a, new: c },
+            BookmarkUpdatePolicy::AnyPermittedByConfig,
+            BookmarkUpdateReason::TestMove,
+        )
+        .run(&ctx, &authz, &repo, &hook_manager)
+        .await?;
+
+        let res = UpdateBookmarkOp::new(
+            &bookmark,
+            BookmarkUpdateTargets { old: c, new: d },
+            BookmarkUpdatePolicy::AnyPermittedByConfig,
+            BookmarkUpdateReason::TestMove,
+        )
+        .run(&ctx, &authz, &repo, &hook_manager)
+        .await;
+        assert_rejected(res, d, "non-fast-forward move");
+
+        let res = DeleteBookmarkOp::new(&bookmark, c, BookmarkUpdateReason::TestMove)
+            .run(&ctx, &authz, &repo, &hook_manager)
+            .await;
+        assert_rejected(res, c, "deletion");
+
+        assert_eq!(repo.bookmarks().get(ctx.clone(), &bookmark).await?, Some(c));
+        Ok(())
+    }
+}
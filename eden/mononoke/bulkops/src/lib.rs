@@ -10,7 +10,6 @@
 //! Utiltities for handling data in bulk.
 use std::cmp::max;
 use std::cmp::min;
-use std::collections::HashMap;
 use std::sync::Arc;
 
 use anyhow::bail;
@@ -116,13 +115,8 @@ impl PublicChangesetBulkFetch {
                             .into_iter()
                             .map(|r| r.map(|((id, _), _bounds)| id))
                             .collect::<Result<Vec<_>, Error>>()?;
-                        let entries = self.changesets.get_many(ctx, ids.clone()).await?;
-                        let mut entries_map: HashMap<_, _> =
-                            entries.into_iter().map(|e| (e.cs_id, e)).collect();
-                        let result = ids
-                            .into_iter()
-                            .filter_map(move |id| entries_map.remove(&id))
-                            .map(Ok);
+                        let entries = self.changesets.get_many_ordered(ctx, &ids).await?;
+                        let result = entries.into_iter().flatten().map(Ok);
                         Ok::<_, Error>(stream::iter(result))
                     })
                 })
@@ -151,17 +145,12 @@ impl PublicChangesetBulkFetch {
                             .into_iter()
                             .map(|r| r.map(|(cs_id_and_row_id, _bounds)| cs_id_and_row_id))
                             .collect::<Result<Vec<_>, Error>>()?;
-                        let entries = self
-                            .changesets
-                            .get_many(ctx, ids.iter().map(|(cs_id, _)| cs_id).copied().collect())
-                            .await?;
-                        let mut entries_map: HashMap<_, _> =
-                            entries.into_iter().map(|e| (e.cs_id, e)).collect();
+                        let cs_ids: Vec<_> = ids.iter().map(|(cs_id, _)| *cs_id).collect();
+                        let entries = self.changesets.get_many_ordered(ctx, &cs_ids).await?;
                         let result = ids
                             .into_iter()
-                            .filter_map(move |(cs_id, id)| {
-                                entries_map.remove(&cs_id).map(|entry| (entry, id))
-                            })
+                            .zip(entries)
+                            .filter_map(|((_, id), entry)| entry.map(|entry| (entry, id)))
                             .map(Ok);
                         Ok::<_, Error>(stream::iter(result))
                     })
@@ -85,6 +85,7 @@ pub async fn save_changesets(
             let completion_record = ChangesetInsert {
                 cs_id: bcs_id,
                 parents: bcs.parents().collect(),
+                extra: None,
             };
             bonsai_complete_futs.push(complete_changesets.add(ctx, completion_record));
         }
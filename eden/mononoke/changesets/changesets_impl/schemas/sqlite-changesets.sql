@@ -11,6 +11,7 @@ CREATE TABLE IF NOT EXISTS changesets (
   repo_id INTEGER NOT NULL,
   cs_id VARBINARY(32) NOT NULL,
   gen BIGINT NOT NULL,
+  extra BLOB,
   UNIQUE (repo_id, cs_id)
 );
 
@@ -31,6 +31,7 @@ use changeset_entry_thrift as thrift;
 use changesets::ChangesetEntry;
 use changesets::ChangesetInsert;
 use changesets::Changesets;
+use changesets::EnumerationEntry;
 use changesets::SortOrder;
 use context::CoreContext;
 use fbthrift::compact_protocol;
@@ -42,7 +43,6 @@ use mononoke_types::ChangesetIdPrefix;
 use mononoke_types::ChangesetIdsResolvedFromPrefix;
 use mononoke_types::Generation;
 use mononoke_types::RepositoryId;
-use ref_cast::RefCast;
 use stats::prelude::*;
 use vec1::Vec1;
 
@@ -54,12 +54,45 @@ define_stats! {
 }
 
 pub fn get_cache_key(repo_id: RepositoryId, cs_id: &ChangesetId) -> String {
-    format!("{}.{}", repo_id.prefix(), cs_id)
+    // Cachelib keys aren't versioned by the keygen, so include the code
+    // version to avoid decoding entries cached with a different layout.
+    format!("{}.v{}.{}", repo_id.prefix(), thrift::MC_CODEVER, cs_id)
 }
 
-#[derive(Clone, Debug, Abomonation, RefCast)]
-#[repr(transparent)]
-pub struct ChangesetEntryWrapper(ChangesetEntry);
+/// Serialisable counterpart of `ChangesetEntry` for cachelib. Fields mimic
+/// the original struct except for types that cannot be abomonated.
+#[derive(Clone, Debug, Abomonation)]
+pub struct ChangesetEntryWrapper {
+    repo_id: RepositoryId,
+    cs_id: ChangesetId,
+    parents: Vec<ChangesetId>,
+    gen: u64,
+    extra: Option<Vec<u8>>,
+}
+
+impl From<ChangesetEntry> for ChangesetEntryWrapper {
+    fn from(entry: ChangesetEntry) -> Self {
+        Self {
+            repo_id: entry.repo_id,
+            cs_id: entry.cs_id,
+            parents: entry.parents,
+            gen: entry.gen,
+            extra: entry.extra.map(|extra| extra.to_vec()),
+        }
+    }
+}
+
+impl From<ChangesetEntryWrapper> for ChangesetEntry {
+    fn from(entry: ChangesetEntryWrapper) -> Self {
+        Self {
+            repo_id: entry.repo_id,
+            cs_id: entry.cs_id,
+            parents: entry.parents,
+            gen: entry.gen,
+            extra: entry.extra.map(Bytes::from),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct CachingChangesets {
@@ -153,7 +186,7 @@ impl Changesets for CachingChangesets {
         STATS::gets.add_value(1);
         let ctx = (ctx, self);
         let mut map = get_or_fill(&ctx, hashset![cs_id]).await?;
-        Ok(map.remove(&cs_id).map(|entry| entry.0))
+        Ok(map.remove(&cs_id).map(ChangesetEntry::from))
     }
 
     async fn get_many(
@@ -166,7 +199,7 @@ impl Changesets for CachingChangesets {
         let res = get_or_fill_chunked(&ctx, cs_ids.into_iter().collect(), 1000, 2)
             .await?
             .into_values()
-            .map(|val| val.0)
+            .map(ChangesetEntry::from)
             .collect();
         Ok(res)
     }
@@ -197,7 +230,7 @@ impl Changesets for CachingChangesets {
             let key = get_cache_key(self.repo_id, &cs.cs_id);
             let _ = self
                 .cachelib
-                .set_cached(&key, ChangesetEntryWrapper::ref_cast(cs), None);
+                .set_cached(&key, &ChangesetEntryWrapper::from(cs.clone()), None);
         }
     }
 
@@ -212,33 +245,35 @@ impl Changesets for CachingChangesets {
             .await
     }
 
-    fn list_enumeration_range(
+    fn list_enumeration_range_detailed(
         &self,
         ctx: &CoreContext,
         min_id: u64,
         max_id: u64,
         sort_and_limit: Option<(SortOrder, u64)>,
         read_from_master: bool,
-    ) -> BoxStream<'_, Result<(ChangesetId, u64), Error>> {
-        self.changesets.list_enumeration_range(
+        include_parents: bool,
+    ) -> BoxStream<'_, Result<EnumerationEntry, Error>> {
+        self.changesets.list_enumeration_range_detailed(
             ctx,
             min_id,
             max_id,
             sort_and_limit,
             read_from_master,
+            include_parents,
         )
     }
 }
 
 impl MemcacheEntity for ChangesetEntryWrapper {
     fn serialize(&self) -> Bytes {
-        compact_protocol::serialize(&self.0.clone().into_thrift())
+        compact_protocol::serialize(&ChangesetEntry::from(self.clone()).into_thrift())
     }
 
     fn deserialize(bytes: Bytes) -> McResult<Self> {
         compact_protocol::deserialize(bytes)
             .and_then(ChangesetEntry::from_thrift)
-            .map(ChangesetEntryWrapper)
+            .map(ChangesetEntryWrapper::from)
             .map_err(|_| McErrorKind::Deserialization)
     }
 }
@@ -288,7 +323,7 @@ impl KeyedEntityStore<ChangesetId, ChangesetEntryWrapper> for CacheRequest<'_> {
 
         Result::<_, Error>::Ok(
             res.into_iter()
-                .map(|e| (e.cs_id, ChangesetEntryWrapper(e)))
+                .map(|e| (e.cs_id, ChangesetEntryWrapper::from(e)))
                 .collect(),
         )
     }
@@ -0,0 +1,505 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use anyhow::Error;
+use async_trait::async_trait;
+use changesets::ChangesetEntry;
+use changesets::ChangesetInsert;
+use changesets::Changesets;
+use changesets::EnumerationEntry;
+use changesets::SortOrder;
+use context::CoreContext;
+use futures::stream::BoxStream;
+use futures::stream::TryStreamExt;
+use mononoke_types::ChangesetId;
+use mononoke_types::ChangesetIdPrefix;
+use mononoke_types::ChangesetIdsResolvedFromPrefix;
+use mononoke_types::Generation;
+use mononoke_types::RepositoryId;
+use parking_lot::Mutex;
+use parking_lot::RwLock;
+use stats::prelude::*;
+use vec1::Vec1;
+
+define_stats! {
+    prefix = "mononoke.changesets.filter";
+    rejected: timeseries(Rate, Sum),
+    passed: timeseries(Rate, Sum),
+}
+
+/// Default number of filter bits per changeset. About 1% of the absent
+/// changesets pass a filter of this size.
+const DEFAULT_BITS_PER_CHANGESET: u64 = 10;
+
+/// Changesets are enumerated in chunks of this size to build the filter.
+const ENUMERATION_CHUNK_SIZE: u64 = 10_000;
+
+/// Changesets added concurrently may become visible out of the order of
+/// their ids, leaving gaps in the enumerated ids that fill later. Gaps among
+/// this many most recent ids are enumerated again on refresh.
+const GAP_RESCAN_WINDOW: u64 = 1_000;
+
+/// Wrapper answering probes for absent changesets without querying the
+/// wrapped `Changesets`, using an approximate membership filter of all the
+/// changesets of the repo.
+///
+/// The filter is built in the background on first use, or by calling
+/// `build_filter`, by enumerating all the changesets. It is only consulted
+/// once complete. Its size is derived from `enumeration_bounds`, with some
+/// headroom for the changesets added afterwards, at `bits_per_changeset`
+/// bits each, so it is only built if enabled.
+///
+/// Changesets added through this wrapper are inserted in the filter.
+/// Changesets added by other writers are inserted when the filter is
+/// refreshed, which happens before any probe is answered as absent, so
+/// existing changesets are never reported absent. Concurrent probes share
+/// refreshes.
+pub struct FilteredChangesets {
+    changesets: Arc<dyn Changesets>,
+    enabled: bool,
+    bits_per_changeset: u64,
+    state: Arc<FilterState>,
+}
+
+/// Status of the filter of a `FilteredChangesets`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FilterStatus {
+    /// The filter is not used.
+    Disabled,
+    /// The filter is enabled, but nothing queried changesets yet.
+    NotStarted,
+    /// Changesets are being enumerated. `expected` is an upper bound of the
+    /// number of changesets to enumerate.
+    Building { enumerated: u64, expected: u64 },
+    /// The filter is used to answer probes.
+    Complete { enumerated: u64 },
+    /// Building the filter failed. It is not used until built again with
+    /// `build_filter`.
+    Failed(String),
+}
+
+struct FilterState {
+    /// Whether the build is `NotStarted`, `Building`, `Complete` or `Failed`.
+    status: Mutex<FilterStatus>,
+    /// Set once the status is `Complete`, to check it without locking.
+    complete: AtomicBool,
+    filter: RwLock<Option<Arc<BloomFilter>>>,
+    /// Held while enumerating.
+    cursor: futures::lock::Mutex<Cursor>,
+    /// Number of enumerations started.
+    enumerations: AtomicU64,
+    enumerated: AtomicU64,
+    expected: AtomicU64,
+}
+
+/// Where the next enumeration of changesets into the filter starts.
+#[derive(Default)]
+struct Cursor {
+    /// Next unique id never enumerated.
+    next_id: u64,
+    /// First id of a gap among the enumerated ids, enumerated again.
+    rescan_from: Option<u64>,
+    /// Number of the last enumeration that completed, see
+    /// `FilterState::enumerations`.
+    completed: u64,
+}
+
+impl FilteredChangesets {
+    pub fn new(changesets: Arc<dyn Changesets>, enabled: bool) -> Self {
+        let state = FilterState {
+            status: Mutex::new(if enabled {
+                FilterStatus::NotStarted
+            } else {
+                FilterStatus::Disabled
+            }),
+            complete: AtomicBool::new(false),
+            filter: RwLock::new(None),
+            cursor: futures::lock::Mutex::new(Cursor::default()),
+            enumerations: AtomicU64::new(0),
+            enumerated: AtomicU64::new(0),
+            expected: AtomicU64::new(0),
+        };
+        Self {
+            changesets,
+            enabled,
+            bits_per_changeset: DEFAULT_BITS_PER_CHANGESET,
+            state: Arc::new(state),
+        }
+    }
+
+    /// Trade memory for fewer absent changesets passing the filter.
+    pub fn with_bits_per_changeset(mut self, bits_per_changeset: u64) -> Self {
+        self.bits_per_changeset = bits_per_changeset.max(1);
+        self
+    }
+
+    pub fn filter_status(&self) -> FilterStatus {
+        let status = self.state.status.lock();
+        match *status {
+            FilterStatus::Building { .. } => FilterStatus::Building {
+                enumerated: self.state.enumerated.load(Ordering::Relaxed),
+                expected: self.state.expected.load(Ordering::Relaxed),
+            },
+            FilterStatus::Complete { .. } => FilterStatus::Complete {
+                enumerated: self.state.enumerated.load(Ordering::Relaxed),
+            },
+            ref status => status.clone(),
+        }
+    }
+
+    /// Build the filter now, unless it is disabled, already built or being
+    /// built. A failed build is tried again.
+    pub async fn build_filter(&self, ctx: &CoreContext) -> Result<(), Error> {
+        if !self.start_build(true) {
+            return Ok(());
+        }
+        build(
+            ctx,
+            self.changesets.clone(),
+            self.state.clone(),
+            self.bits_per_changeset,
+        )
+        .await
+    }
+
+    /// Insert the changesets added by other writers since the filter was
+    /// built.
+    pub async fn refresh_filter(&self, ctx: &CoreContext) -> Result<(), Error> {
+        if !self.state.complete.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        enumerate_new(ctx, self.changesets.as_ref(), &self.state, None).await
+    }
+
+    /// Remove from `cs_ids` the ids of changesets that are known to be
+    /// absent. The filter is refreshed before removing any, unless another
+    /// probe refreshed it since this one started.
+    async fn retain_may_exist(
+        &self,
+        ctx: &CoreContext,
+        cs_ids: &mut Vec<ChangesetId>,
+    ) -> Result<(), Error> {
+        let enumerations = self.state.enumerations.load(Ordering::Acquire);
+        let filter = match self.complete_filter(ctx) {
+            Some(filter) => filter,
+            None => return Ok(()),
+        };
+        let probed = cs_ids.len();
+        if cs_ids.iter().any(|cs_id| !filter.may_contain(cs_id)) {
+            enumerate_new(
+                ctx,
+                self.changesets.as_ref(),
+                &self.state,
+                Some(enumerations),
+            )
+            .await?;
+            cs_ids.retain(|cs_id| filter.may_contain(cs_id));
+        }
+        STATS::rejected.add_value((probed - cs_ids.len()) as i64);
+        STATS::passed.add_value(cs_ids.len() as i64);
+        Ok(())
+    }
+
+    /// Move the status to `Building`, and return whether it was
+    /// `NotStarted`, or `Failed` if `retry_failed`.
+    fn start_build(&self, retry_failed: bool) -> bool {
+        let mut status = self.state.status.lock();
+        match *status {
+            FilterStatus::NotStarted => {}
+            FilterStatus::Failed(_) if retry_failed => {}
+            _ => return false,
+        }
+        *status = FilterStatus::Building {
+            enumerated: 0,
+            expected: 0,
+        };
+        true
+    }
+
+    /// The filter if it is complete, starting to build it in the background
+    /// otherwise.
+    fn complete_filter(&self, ctx: &CoreContext) -> Option<Arc<BloomFilter>> {
+        if !self.enabled {
+            return None;
+        }
+        if self.state.complete.load(Ordering::Acquire) {
+            return self.state.filter.read().clone();
+        }
+        if self.start_build(false) {
+            let ctx = ctx.clone();
+            let changesets = self.changesets.clone();
+            let state = self.state.clone();
+            let bits_per_changeset = self.bits_per_changeset;
+            tokio::spawn(async move {
+                // Failures are reported by `filter_status`.
+                let _ = build(&ctx, changesets, state, bits_per_changeset).await;
+            });
+        }
+        None
+    }
+
+    fn insert(&self, cs_ids: impl IntoIterator<Item = ChangesetId>) {
+        if let Some(filter) = self.state.filter.read().as_ref() {
+            for cs_id in cs_ids {
+                filter.insert(&cs_id);
+            }
+        }
+    }
+}
+
+async fn build(
+    ctx: &CoreContext,
+    changesets: Arc<dyn Changesets>,
+    state: Arc<FilterState>,
+    bits_per_changeset: u64,
+) -> Result<(), Error> {
+    let result = async {
+        // Read from master: the filter must be allocated before the
+        // enumeration starts, so that the changesets added through the
+        // wrapper meanwhile are either inserted or enumerated.
+        let bounds = changesets.enumeration_bounds(ctx, true, vec![]).await?;
+        let expected = bounds.map_or(0, |(lo, hi)| hi - lo + 1);
+        state.expected.store(expected, Ordering::Relaxed);
+        state.enumerated.store(0, Ordering::Relaxed);
+        *state.filter.write() = Some(Arc::new(BloomFilter::new(
+            expected + expected / 4,
+            bits_per_changeset,
+        )));
+        {
+            let mut cursor = state.cursor.lock().await;
+            cursor.next_id = bounds.map_or(0, |(lo, _)| lo);
+            cursor.rescan_from = None;
+        }
+        enumerate_new(ctx, changesets.as_ref(), &state, None).await
+    };
+    let result = result.await;
+    let mut status = state.status.lock();
+    match &result {
+        Ok(()) => {
+            *status = FilterStatus::Complete { enumerated: 0 };
+            state.complete.store(true, Ordering::Release);
+        }
+        Err(e) => {
+            *status = FilterStatus::Failed(format!("{:#}", e));
+            *state.filter.write() = None;
+        }
+    }
+    result
+}
+
+/// Insert the changesets from the cursor up to the current upper
+/// enumeration bound in the filter, unless an enumeration started after the
+/// `after`th one completed meanwhile.
+async fn enumerate_new(
+    ctx: &CoreContext,
+    changesets: &dyn Changesets,
+    state: &FilterState,
+    after: Option<u64>,
+) -> Result<(), Error> {
+    let mut cursor = state.cursor.lock().await;
+    if after.is_some_and(|after| cursor.completed > after) {
+        return Ok(());
+    }
+    let enumeration = state.enumerations.fetch_add(1, Ordering::AcqRel) + 1;
+    let filter = match state.filter.read().clone() {
+        Some(filter) => filter,
+        None => return Ok(()),
+    };
+    let hi = match changesets.enumeration_bounds(ctx, true, vec![]).await? {
+        Some((_, hi)) => hi,
+        None => {
+            cursor.completed = enumeration;
+            return Ok(());
+        }
+    };
+    let mut next_id = cursor.rescan_from.unwrap_or(cursor.next_id);
+    let mut first_gap = None;
+    while next_id <= hi {
+        let chunk: Vec<_> = changesets
+            .list_enumeration_range(
+                ctx,
+                next_id,
+                hi + 1,
+                Some((SortOrder::Ascending, ENUMERATION_CHUNK_SIZE)),
+                true,
+            )
+            .try_collect()
+            .await?;
+        if chunk.is_empty() {
+            break;
+        }
+        for (cs_id, id) in &chunk {
+            if *id > next_id && first_gap.is_none() {
+                first_gap = Some(next_id);
+            }
+            filter.insert(cs_id);
+            if *id >= cursor.next_id {
+                state.enumerated.fetch_add(1, Ordering::Relaxed);
+            }
+            next_id = id + 1;
+        }
+    }
+    if next_id <= hi && first_gap.is_none() {
+        first_gap = Some(next_id);
+    }
+    cursor.next_id = cursor.next_id.max(hi + 1);
+    cursor.rescan_from = first_gap.map(|gap| gap.max((hi + 1).saturating_sub(GAP_RESCAN_WINDOW)));
+    cursor.completed = enumeration;
+    Ok(())
+}
+
+/// Bloom filter of changeset ids. Changeset ids are hashes already, so their
+/// bytes are used as the hashes of the filter.
+struct BloomFilter {
+    bits: Vec<AtomicU64>,
+    num_bits: u64,
+    num_hashes: u64,
+}
+
+impl BloomFilter {
+    fn new(capacity: u64, bits_per_changeset: u64) -> Self {
+        let words = (capacity.max(1) * bits_per_changeset).div_ceil(64);
+        Self {
+            bits: (0..words).map(|_| AtomicU64::new(0)).collect(),
+            num_bits: words * 64,
+            // The optimal number of hashes is ln(2) bits per changeset.
+            num_hashes: (bits_per_changeset * 69 / 100).max(1),
+        }
+    }
+
+    fn positions(&self, cs_id: &ChangesetId) -> impl Iterator<Item = u64> {
+        let bytes = cs_id.blake2().as_ref();
+        let h1 = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(bytes[8..16].try_into().unwrap()) | 1;
+        let num_bits = self.num_bits;
+        (0..self.num_hashes).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+
+    fn insert(&self, cs_id: &ChangesetId) {
+        for pos in self.positions(cs_id) {
+            self.bits[(pos / 64) as usize].fetch_or(1 << (pos % 64), Ordering::Release);
+        }
+    }
+
+    fn may_contain(&self, cs_id: &ChangesetId) -> bool {
+        self.positions(cs_id).all(|pos| {
+            self.bits[(pos / 64) as usize].load(Ordering::Acquire) & (1 << (pos % 64)) != 0
+        })
+    }
+}
+
+#[async_trait]
+impl Changesets for FilteredChangesets {
+    fn repo_id(&self) -> RepositoryId {
+        self.changesets.repo_id()
+    }
+
+    async fn add(&self, ctx: &CoreContext, cs: ChangesetInsert) -> Result<bool, Error> {
+        let cs_id = cs.cs_id;
+        // Insert before adding, so the changeset is never found in the
+        // wrapped changesets but rejected by the filter, and after, in case
+        // the filter was allocated meanwhile.
+        self.insert([cs_id]);
+        let added = self.changesets.add(ctx, cs).await?;
+        self.insert([cs_id]);
+        Ok(added)
+    }
+
+    async fn add_many(
+        &self,
+        ctx: &CoreContext,
+        css: Vec1<(ChangesetInsert, Generation)>,
+    ) -> Result<(), Error> {
+        let cs_ids: Vec<_> = css.iter().map(|(cs, _)| cs.cs_id).collect();
+        self.insert(cs_ids.iter().copied());
+        self.changesets.add_many(ctx, css).await?;
+        self.insert(cs_ids);
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        ctx: &CoreContext,
+        cs_id: ChangesetId,
+    ) -> Result<Option<ChangesetEntry>, Error> {
+        let mut cs_ids = vec![cs_id];
+        self.retain_may_exist(ctx, &mut cs_ids).await?;
+        if cs_ids.is_empty() {
+            return Ok(None);
+        }
+        self.changesets.get(ctx, cs_id).await
+    }
+
+    async fn get_many(
+        &self,
+        ctx: &CoreContext,
+        mut cs_ids: Vec<ChangesetId>,
+    ) -> Result<Vec<ChangesetEntry>, Error> {
+        self.retain_may_exist(ctx, &mut cs_ids).await?;
+        if cs_ids.is_empty() {
+            return Ok(vec![]);
+        }
+        self.changesets.get_many(ctx, cs_ids).await
+    }
+
+    async fn get_many_by_prefix(
+        &self,
+        ctx: &CoreContext,
+        cs_prefix: ChangesetIdPrefix,
+        limit: usize,
+    ) -> Result<ChangesetIdsResolvedFromPrefix, Error> {
+        if let Some(cs_id) = cs_prefix.into_changeset_id() {
+            let mut cs_ids = vec![cs_id];
+            self.retain_may_exist(ctx, &mut cs_ids).await?;
+            if cs_ids.is_empty() {
+                return Ok(ChangesetIdsResolvedFromPrefix::NoMatch);
+            }
+        }
+        self.changesets
+            .get_many_by_prefix(ctx, cs_prefix, limit)
+            .await
+    }
+
+    fn prime_cache(&self, ctx: &CoreContext, changesets: &[ChangesetEntry]) {
+        self.changesets.prime_cache(ctx, changesets)
+    }
+
+    async fn enumeration_bounds(
+        &self,
+        ctx: &CoreContext,
+        read_from_master: bool,
+        known_heads: Vec<ChangesetId>,
+    ) -> Result<Option<(u64, u64)>, Error> {
+        self.changesets
+            .enumeration_bounds(ctx, read_from_master, known_heads)
+            .await
+    }
+
+    fn list_enumeration_range_detailed(
+        &self,
+        ctx: &CoreContext,
+        min_id: u64,
+        max_id: u64,
+        sort_and_limit: Option<(SortOrder, u64)>,
+        read_from_master: bool,
+        include_parents: bool,
+    ) -> BoxStream<'_, Result<EnumerationEntry, Error>> {
+        self.changesets.list_enumeration_range_detailed(
+            ctx,
+            min_id,
+            max_id,
+            sort_and_limit,
+            read_from_master,
+            include_parents,
+        )
+    }
+}
@@ -6,11 +6,18 @@
  */
 
 mod caching;
+mod filter;
+mod memory;
+mod notify;
 mod sql;
 #[cfg(test)]
 mod test;
 
 pub use crate::caching::get_cache_key;
 pub use crate::caching::CachingChangesets;
+pub use crate::filter::FilterStatus;
+pub use crate::filter::FilteredChangesets;
+pub use crate::memory::InMemoryChangesets;
+pub use crate::notify::NotifyingChangesets;
 pub use crate::sql::SqlChangesets;
 pub use crate::sql::SqlChangesetsBuilder;
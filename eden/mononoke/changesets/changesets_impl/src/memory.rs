@@ -0,0 +1,300 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use anyhow::Error;
+use anyhow::Result;
+use async_trait::async_trait;
+use changesets::ChangesetEntry;
+use changesets::ChangesetInsert;
+use changesets::Changesets;
+use changesets::EnumerationEntry;
+use changesets::SortOrder;
+use context::CoreContext;
+use futures::stream;
+use futures::stream::BoxStream;
+use futures::stream::StreamExt;
+use mononoke_types::ChangesetId;
+use mononoke_types::ChangesetIdPrefix;
+use mononoke_types::ChangesetIdsResolvedFromPrefix;
+use mononoke_types::Generation;
+use mononoke_types::RepositoryId;
+use parking_lot::RwLock;
+use vec1::Vec1;
+
+use crate::sql::SqlChangesetsError;
+
+/// In-memory changesets storage, for tests and tools that don't need the
+/// changesets to outlive the process.
+///
+/// Behaves like `SqlChangesets`, including its errors. Each changeset is
+/// assigned a unique id when it is added, in insertion order, which is what
+/// the enumeration methods use.
+pub struct InMemoryChangesets {
+    repo_id: RepositoryId,
+    state: RwLock<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Changesets, along with their unique id.
+    changesets: HashMap<ChangesetId, (u64, ChangesetEntry)>,
+    /// The last assigned unique id.
+    last_id: u64,
+}
+
+impl State {
+    fn insert(&mut self, entry: ChangesetEntry) {
+        self.last_id += 1;
+        self.changesets.insert(entry.cs_id, (self.last_id, entry));
+    }
+
+    /// Generation of a changeset with the given parents, which must exist.
+    fn generation(&self, parents: &[ChangesetId]) -> Result<u64, SqlChangesetsError> {
+        let mut missing = HashSet::new();
+        let mut max_gen = 0;
+        for parent in parents {
+            match self.changesets.get(parent) {
+                Some((_, entry)) => max_gen = max_gen.max(entry.gen),
+                None => {
+                    missing.insert(*parent);
+                }
+            }
+        }
+        if missing.is_empty() {
+            Ok(max_gen + 1)
+        } else {
+            Err(SqlChangesetsError::MissingParents(
+                missing.into_iter().collect(),
+            ))
+        }
+    }
+}
+
+impl InMemoryChangesets {
+    pub fn new(repo_id: RepositoryId) -> Self {
+        Self {
+            repo_id,
+            state: Default::default(),
+        }
+    }
+
+    /// Create storage seeded with `entries`, e.g. from a serialized dump.
+    /// Entries must be in topological order. Their generation numbers are
+    /// kept as they are.
+    pub fn from_entries(repo_id: RepositoryId, entries: Vec<ChangesetEntry>) -> Result<Self> {
+        let mut state = State::default();
+        for entry in entries {
+            if entry.repo_id != repo_id {
+                return Err(anyhow!(
+                    "Changeset {} is from repo {}, expected repo {}",
+                    entry.cs_id,
+                    entry.repo_id,
+                    repo_id,
+                ));
+            }
+            state.generation(&entry.parents)?;
+            if let Some((_, existing)) = state.changesets.get(&entry.cs_id) {
+                if existing.parents != entry.parents {
+                    return Err(SqlChangesetsError::DuplicateInsertionInconsistency(
+                        entry.cs_id,
+                        existing.parents.clone(),
+                        entry.parents,
+                    )
+                    .into());
+                }
+                continue;
+            }
+            state.insert(entry);
+        }
+        Ok(Self {
+            repo_id,
+            state: RwLock::new(state),
+        })
+    }
+}
+
+#[async_trait]
+impl Changesets for InMemoryChangesets {
+    fn repo_id(&self) -> RepositoryId {
+        self.repo_id
+    }
+
+    async fn add(&self, _ctx: &CoreContext, cs: ChangesetInsert) -> Result<bool, Error> {
+        let mut state = self.state.write();
+        let gen = state.generation(&cs.parents)?;
+        if let Some((_, existing)) = state.changesets.get(&cs.cs_id) {
+            return if existing.parents == cs.parents {
+                Ok(false)
+            } else {
+                Err(SqlChangesetsError::DuplicateInsertionInconsistency(
+                    cs.cs_id,
+                    existing.parents.clone(),
+                    cs.parents,
+                )
+                .into())
+            };
+        }
+        state.insert(ChangesetEntry {
+            repo_id: self.repo_id,
+            cs_id: cs.cs_id,
+            parents: cs.parents,
+            gen,
+            extra: cs.extra,
+        });
+        Ok(true)
+    }
+
+    async fn add_many(
+        &self,
+        _ctx: &CoreContext,
+        css: Vec1<(ChangesetInsert, Generation)>,
+    ) -> Result<(), Error> {
+        let mut state = self.state.write();
+        if css
+            .iter()
+            .all(|(insert, _)| state.changesets.contains_key(&insert.cs_id))
+        {
+            return Ok(());
+        }
+        // Parents may be earlier in the batch, or later since the whole
+        // batch is added at once.
+        let batch: HashSet<_> = css.iter().map(|(insert, _)| insert.cs_id).collect();
+        let missing: HashSet<_> = css
+            .iter()
+            .flat_map(|(insert, _)| insert.parents.iter().copied())
+            .filter(|parent| !batch.contains(parent) && !state.changesets.contains_key(parent))
+            .collect();
+        if !missing.is_empty() {
+            return Err(SqlChangesetsError::MissingParents(missing.into_iter().collect()).into());
+        }
+        for (insert, gen) in css {
+            if !state.changesets.contains_key(&insert.cs_id) {
+                state.insert(ChangesetEntry {
+                    repo_id: self.repo_id,
+                    cs_id: insert.cs_id,
+                    parents: insert.parents,
+                    gen: gen.value(),
+                    extra: insert.extra,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        _ctx: &CoreContext,
+        cs_id: ChangesetId,
+    ) -> Result<Option<ChangesetEntry>, Error> {
+        Ok(self
+            .state
+            .read()
+            .changesets
+            .get(&cs_id)
+            .map(|(_, entry)| entry.clone()))
+    }
+
+    async fn get_many(
+        &self,
+        _ctx: &CoreContext,
+        cs_ids: Vec<ChangesetId>,
+    ) -> Result<Vec<ChangesetEntry>, Error> {
+        let state = self.state.read();
+        Ok(cs_ids
+            .into_iter()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .filter_map(|cs_id| state.changesets.get(&cs_id).map(|(_, entry)| entry.clone()))
+            .collect())
+    }
+
+    async fn get_many_by_prefix(
+        &self,
+        _ctx: &CoreContext,
+        cs_prefix: ChangesetIdPrefix,
+        limit: usize,
+    ) -> Result<ChangesetIdsResolvedFromPrefix, Error> {
+        let (min, max) = (cs_prefix.min_bound(), cs_prefix.max_bound());
+        let mut matches: Vec<_> = self
+            .state
+            .read()
+            .changesets
+            .keys()
+            .filter(|cs_id| (min..=max).contains(*cs_id))
+            .copied()
+            .collect();
+        matches.sort();
+        matches.truncate(limit.saturating_add(1));
+        Ok(ChangesetIdsResolvedFromPrefix::from_vec_and_limit(
+            matches, limit,
+        ))
+    }
+
+    fn prime_cache(&self, _ctx: &CoreContext, _changesets: &[ChangesetEntry]) {
+        // No-op
+    }
+
+    async fn enumeration_bounds(
+        &self,
+        _ctx: &CoreContext,
+        _read_from_master: bool,
+        known_heads: Vec<ChangesetId>,
+    ) -> Result<Option<(u64, u64)>, Error> {
+        let state = self.state.read();
+        let ids = state.changesets.values().map(|(id, _)| *id);
+        let (mut lo, hi) = match (ids.clone().min(), ids.max()) {
+            (Some(lo), Some(hi)) => (lo, hi),
+            _ => return Ok(None),
+        };
+        if !known_heads.is_empty() {
+            let max_id = known_heads
+                .iter()
+                .filter_map(|cs_id| state.changesets.get(cs_id).map(|(id, _)| *id))
+                .max()
+                // We want to skip the commits we've been given
+                .map_or(lo, |i| i + 1);
+            lo = lo.max(max_id);
+        }
+        Ok(Some((lo, hi)))
+    }
+
+    fn list_enumeration_range_detailed(
+        &self,
+        _ctx: &CoreContext,
+        min_id: u64,
+        max_id: u64,
+        sort_and_limit: Option<(SortOrder, u64)>,
+        _read_from_master: bool,
+        include_parents: bool,
+    ) -> BoxStream<'_, Result<EnumerationEntry, Error>> {
+        let mut rows: Vec<_> = self
+            .state
+            .read()
+            .changesets
+            .values()
+            .filter(|(id, _)| (min_id..max_id).contains(id))
+            .map(|(id, entry)| EnumerationEntry {
+                cs_id: entry.cs_id,
+                unique_id: *id,
+                gen: entry.gen,
+                parents: include_parents.then(|| entry.parents.clone()),
+            })
+            .collect();
+        rows.sort_by_key(|entry| entry.unique_id);
+        if let Some((order, limit)) = sort_and_limit {
+            if order == SortOrder::Descending {
+                rows.reverse();
+            }
+            rows.truncate(limit as usize);
+        }
+        stream::iter(rows.into_iter().map(Ok)).boxed()
+    }
+}
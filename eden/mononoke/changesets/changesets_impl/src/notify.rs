@@ -0,0 +1,173 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::sync::Arc;
+
+use anyhow::Error;
+use async_trait::async_trait;
+use changesets::ChangesetEntry;
+use changesets::ChangesetInsert;
+use changesets::ChangesetSubscriber;
+use changesets::Changesets;
+use changesets::EnumerationEntry;
+use changesets::SortOrder;
+use context::CoreContext;
+use futures::stream::BoxStream;
+use mononoke_types::ChangesetId;
+use mononoke_types::ChangesetIdPrefix;
+use mononoke_types::ChangesetIdsResolvedFromPrefix;
+use mononoke_types::Generation;
+use mononoke_types::RepositoryId;
+use vec1::Vec1;
+
+/// Wrapper notifying subscribers of the changesets added through it, so
+/// that they don't have to poll the enumeration of changesets.
+///
+/// Subscribers are notified in-process, after the add succeeded. Changesets
+/// added by other writers, including other processes, are not notified.
+///
+/// `add` notifies the changeset only if it was inserted. `add_many` doesn't
+/// report which changesets already existed, so it notifies all of them.
+pub struct NotifyingChangesets {
+    changesets: Arc<dyn Changesets>,
+    subscribers: Vec<Arc<dyn ChangesetSubscriber>>,
+}
+
+impl NotifyingChangesets {
+    pub fn new(changesets: Arc<dyn Changesets>) -> Self {
+        Self {
+            changesets,
+            subscribers: Vec::new(),
+        }
+    }
+
+    pub fn with_subscriber(mut self, subscriber: Arc<dyn ChangesetSubscriber>) -> Self {
+        self.subscribers.push(subscriber);
+        self
+    }
+
+    fn notify(&self, ctx: &CoreContext, entries: &[ChangesetEntry]) {
+        for subscriber in &self.subscribers {
+            subscriber.notify_added(ctx, entries);
+        }
+    }
+}
+
+#[async_trait]
+impl Changesets for NotifyingChangesets {
+    fn repo_id(&self) -> RepositoryId {
+        self.changesets.repo_id()
+    }
+
+    async fn add(&self, ctx: &CoreContext, cs: ChangesetInsert) -> Result<bool, Error> {
+        if self.subscribers.is_empty() {
+            return self.changesets.add(ctx, cs).await;
+        }
+        // Compute the generation before adding, like the wrapped changesets
+        // do, so that the changeset doesn't have to be read back. Missing
+        // parents make the add fail.
+        let parents = self.changesets.get_many(ctx, cs.parents.clone()).await?;
+        let gen = parents.iter().map(|parent| parent.gen).max().unwrap_or(0) + 1;
+        let entry = ChangesetEntry {
+            repo_id: self.changesets.repo_id(),
+            cs_id: cs.cs_id,
+            parents: cs.parents.clone(),
+            gen,
+            extra: cs.extra.clone(),
+        };
+        let added = self.changesets.add(ctx, cs).await?;
+        if added {
+            self.notify(ctx, &[entry]);
+        }
+        Ok(added)
+    }
+
+    async fn add_many(
+        &self,
+        ctx: &CoreContext,
+        css: Vec1<(ChangesetInsert, Generation)>,
+    ) -> Result<(), Error> {
+        if self.subscribers.is_empty() {
+            return self.changesets.add_many(ctx, css).await;
+        }
+        let repo_id = self.changesets.repo_id();
+        let entries: Vec<_> = css
+            .iter()
+            .map(|(cs, gen)| ChangesetEntry {
+                repo_id,
+                cs_id: cs.cs_id,
+                parents: cs.parents.clone(),
+                gen: gen.value(),
+                extra: cs.extra.clone(),
+            })
+            .collect();
+        self.changesets.add_many(ctx, css).await?;
+        self.notify(ctx, &entries);
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        ctx: &CoreContext,
+        cs_id: ChangesetId,
+    ) -> Result<Option<ChangesetEntry>, Error> {
+        self.changesets.get(ctx, cs_id).await
+    }
+
+    async fn get_many(
+        &self,
+        ctx: &CoreContext,
+        cs_ids: Vec<ChangesetId>,
+    ) -> Result<Vec<ChangesetEntry>, Error> {
+        self.changesets.get_many(ctx, cs_ids).await
+    }
+
+    async fn get_many_by_prefix(
+        &self,
+        ctx: &CoreContext,
+        cs_prefix: ChangesetIdPrefix,
+        limit: usize,
+    ) -> Result<ChangesetIdsResolvedFromPrefix, Error> {
+        self.changesets
+            .get_many_by_prefix(ctx, cs_prefix, limit)
+            .await
+    }
+
+    fn prime_cache(&self, ctx: &CoreContext, changesets: &[ChangesetEntry]) {
+        self.changesets.prime_cache(ctx, changesets)
+    }
+
+    async fn enumeration_bounds(
+        &self,
+        ctx: &CoreContext,
+        read_from_master: bool,
+        known_heads: Vec<ChangesetId>,
+    ) -> Result<Option<(u64, u64)>, Error> {
+        self.changesets
+            .enumeration_bounds(ctx, read_from_master, known_heads)
+            .await
+    }
+
+    fn list_enumeration_range_detailed(
+        &self,
+        ctx: &CoreContext,
+        min_id: u64,
+        max_id: u64,
+        sort_and_limit: Option<(SortOrder, u64)>,
+        read_from_master: bool,
+        include_parents: bool,
+    ) -> BoxStream<'_, Result<EnumerationEntry, Error>> {
+        self.changesets.list_enumeration_range_detailed(
+            ctx,
+            min_id,
+            max_id,
+            sort_and_limit,
+            read_from_master,
+            include_parents,
+        )
+    }
+}
@@ -13,9 +13,11 @@ use anyhow::Context;
 use anyhow::Error;
 use anyhow::Result;
 use async_trait::async_trait;
+use bytes::Bytes;
 use changesets::ChangesetEntry;
 use changesets::ChangesetInsert;
 use changesets::Changesets;
+use changesets::EnumerationEntry;
 use changesets::SortOrder;
 use context::CoreContext;
 use context::PerfCounterType;
@@ -87,9 +89,9 @@ pub struct SqlChangesets {
 }
 
 mononoke_queries! {
-    write InsertChangeset(values: (repo_id: RepositoryId, cs_id: ChangesetId, gen: u64)) {
+    write InsertChangeset(values: (repo_id: RepositoryId, cs_id: ChangesetId, gen: u64, extra: Option<Vec<u8>>)) {
         insert_or_ignore,
-        "{insert_or_ignore} INTO changesets (repo_id, cs_id, gen) VALUES {values}"
+        "{insert_or_ignore} INTO changesets (repo_id, cs_id, gen, extra) VALUES {values}"
     }
 
     write InsertParents(values: (cs_id: u64, parent_id: u64, seq: i32)) {
@@ -97,10 +99,10 @@ mononoke_queries! {
         "{insert_or_ignore} INTO csparents (cs_id, parent_id, seq) VALUES {values}"
     }
 
-    read SelectChangeset(repo_id: RepositoryId, cs_id: ChangesetId, tok: i32) -> (u64, Option<ChangesetId>, Option<u64>, i32) {
+    read SelectChangeset(repo_id: RepositoryId, cs_id: ChangesetId, tok: i32) -> (u64, Option<Vec<u8>>, Option<ChangesetId>, Option<u64>, i32) {
         // NOTE: This selects seq even though we don't need it in order to sort by it.
         "
-        SELECT cs0.gen AS gen, cs1.cs_id AS parent_id, csparents.seq AS seq, {tok}
+        SELECT cs0.gen AS gen, cs0.extra AS extra, cs1.cs_id AS parent_id, csparents.seq AS seq, {tok}
         FROM csparents
         INNER JOIN changesets cs0 ON cs0.id = csparents.cs_id
         INNER JOIN changesets cs1 ON cs1.id = csparents.parent_id
@@ -108,7 +110,7 @@ mononoke_queries! {
 
         UNION
 
-        SELECT cs0.gen AS gen, NULL AS parent_id, NULL as seq, {tok}
+        SELECT cs0.gen AS gen, cs0.extra AS extra, NULL AS parent_id, NULL as seq, {tok}
         FROM changesets cs0
         WHERE cs0.repo_id = {repo_id} and cs0.cs_id = {cs_id}
 
@@ -116,9 +118,9 @@ mononoke_queries! {
         "
     }
 
-    read SelectManyChangesets(repo_id: RepositoryId, tok: i32, >list cs_id: ChangesetId) -> (ChangesetId, u64, Option<ChangesetId>, Option<u64>, i32) {
+    read SelectManyChangesets(repo_id: RepositoryId, tok: i32, >list cs_id: ChangesetId) -> (ChangesetId, u64, Option<Vec<u8>>, Option<ChangesetId>, Option<u64>, i32) {
         "
-        SELECT cs0.cs_id AS cs_id, cs0.gen AS gen, cs1.cs_id AS parent_id, csparents.seq AS seq, {tok}
+        SELECT cs0.cs_id AS cs_id, cs0.gen AS gen, cs0.extra AS extra, cs1.cs_id AS parent_id, csparents.seq AS seq, {tok}
         FROM csparents
         INNER JOIN changesets cs0 ON cs0.id = csparents.cs_id
         INNER JOIN changesets cs1 ON cs1.id = csparents.parent_id
@@ -126,7 +128,7 @@ mononoke_queries! {
 
         UNION
 
-        SELECT cs0.cs_id AS cs_id, cs0.gen AS gen, NULL AS parent_id, NULL as seq, {tok}
+        SELECT cs0.cs_id AS cs_id, cs0.gen AS gen, cs0.extra AS extra, NULL AS parent_id, NULL as seq, {tok}
         FROM changesets cs0
         WHERE cs0.repo_id = {repo_id} and cs0.cs_id IN {cs_id}
 
@@ -150,16 +152,16 @@ mononoke_queries! {
         "
     }
 
-    read SelectAllChangesetsIdsInRange(repo_id: RepositoryId, min_id: u64, max_id: u64) -> (ChangesetId, u64) {
+    read SelectAllChangesetsIdsInRange(repo_id: RepositoryId, min_id: u64, max_id: u64) -> (ChangesetId, u64, u64) {
         mysql(
-            "SELECT cs_id, id
+            "SELECT cs_id, id, gen
             FROM changesets FORCE INDEX(repo_id_id)
             WHERE repo_id = {repo_id}
             AND id BETWEEN {min_id} AND {max_id}
             ORDER BY id"
         )
         sqlite(
-            "SELECT cs_id, id
+            "SELECT cs_id, id, gen
             FROM changesets
             WHERE repo_id = {repo_id}
             AND id BETWEEN {min_id} AND {max_id}
@@ -167,9 +169,9 @@ mononoke_queries! {
         )
     }
 
-    read SelectAllChangesetsIdsInRangeLimitAsc(repo_id: RepositoryId, min_id: u64, max_id: u64, limit: u64) -> (ChangesetId, u64) {
+    read SelectAllChangesetsIdsInRangeLimitAsc(repo_id: RepositoryId, min_id: u64, max_id: u64, limit: u64) -> (ChangesetId, u64, u64) {
         mysql(
-            "SELECT cs_id, id
+            "SELECT cs_id, id, gen
             FROM changesets FORCE INDEX(repo_id_id)
             WHERE repo_id = {repo_id}
             AND id BETWEEN {min_id} AND {max_id}
@@ -177,7 +179,7 @@ mononoke_queries! {
             LIMIT {limit}"
         )
         sqlite(
-            "SELECT cs_id, id
+            "SELECT cs_id, id, gen
             FROM changesets
             WHERE repo_id = {repo_id}
             AND id BETWEEN {min_id} AND {max_id}
@@ -186,9 +188,9 @@ mononoke_queries! {
         )
     }
 
-    read SelectAllChangesetsIdsInRangeLimitDesc(repo_id: RepositoryId, min_id: u64, max_id: u64, limit: u64) -> (ChangesetId, u64) {
+    read SelectAllChangesetsIdsInRangeLimitDesc(repo_id: RepositoryId, min_id: u64, max_id: u64, limit: u64) -> (ChangesetId, u64, u64) {
         mysql(
-            "SELECT cs_id, id
+            "SELECT cs_id, id, gen
             FROM changesets FORCE INDEX(repo_id_id)
             WHERE repo_id = {repo_id}
               AND id BETWEEN {min_id} AND {max_id}
@@ -196,7 +198,7 @@ mononoke_queries! {
             LIMIT {limit}"
         )
         sqlite(
-            "SELECT cs_id, id
+            "SELECT cs_id, id, gen
             FROM changesets
             WHERE repo_id = {repo_id}
               AND id BETWEEN {min_id} AND {max_id}
@@ -205,6 +207,16 @@ mononoke_queries! {
         )
     }
 
+    read SelectParentsInRange(repo_id: RepositoryId, min_id: u64, max_id: u64) -> (u64, ChangesetId) {
+        "SELECT csparents.cs_id, cs1.cs_id
+         FROM csparents
+         INNER JOIN changesets cs0 ON cs0.id = csparents.cs_id
+         INNER JOIN changesets cs1 ON cs1.id = csparents.parent_id
+         WHERE csparents.cs_id BETWEEN {min_id} AND {max_id}
+           AND cs0.repo_id = {repo_id}
+         ORDER BY csparents.cs_id, csparents.seq"
+    }
+
     read SelectChangesetsIdsBounds(repo_id: RepositoryId) -> (u64, u64) {
         "SELECT min(id), max(id)
          FROM changesets
@@ -272,10 +284,11 @@ impl Changesets for SqlChangesets {
         };
         check_missing_rows(&cs.parents, &parent_rows)?;
         let gen = parent_rows.iter().map(|row| row.2).max().unwrap_or(0) + 1;
+        let extra = cs.extra.as_ref().map(|extra| extra.to_vec());
         let transaction = self.write_connection.start_transaction().await?;
         let (transaction, result) = InsertChangeset::query_with_transaction(
             transaction,
-            &[(&self.repo_id, &cs.cs_id, &gen)],
+            &[(&self.repo_id, &cs.cs_id, &gen, &extra)],
         )
         .await?;
 
@@ -303,12 +316,17 @@ impl Changesets for SqlChangesets {
         STATS::adds.add_value(css.len() as i64);
         ctx.perf_counters()
             .increment_counter(PerfCounterType::SqlWrites);
+        let extras = css
+            .iter()
+            .map(|(insert, _)| insert.extra.as_ref().map(|extra| extra.to_vec()))
+            .collect::<Vec<_>>();
         let transaction = self.write_connection.start_transaction().await?;
         // Part 1 - Add all changesets to the SQL table.
         let (transaction, result) = InsertChangeset::query_with_transaction(
             transaction,
             css.iter()
-                .map(|(insert, gen)| (&self.repo_id, &insert.cs_id, gen.as_ref()))
+                .zip(extras.iter())
+                .map(|((insert, gen), extra)| (&self.repo_id, &insert.cs_id, gen.as_ref(), extra))
                 .collect::<Vec<_>>()
                 .as_slice(),
         )
@@ -494,24 +512,25 @@ impl Changesets for SqlChangesets {
         }
     }
 
-    fn list_enumeration_range(
+    fn list_enumeration_range_detailed(
         &self,
         _ctx: &CoreContext,
         min_id: u64,
         max_id: u64,
         sort_and_limit: Option<(SortOrder, u64)>,
         read_from_master: bool,
-    ) -> BoxStream<'_, Result<(ChangesetId, u64), Error>> {
+        include_parents: bool,
+    ) -> BoxStream<'_, Result<EnumerationEntry, Error>> {
         // We expect the range [min_id, max_id), so subtract 1 from max_id as
         // SQL request is BETWEEN, which means both bounds are inclusive.
         let max_id = max_id - 1;
         let conn = self.read_conn(read_from_master);
 
         async move {
-            match sort_and_limit {
+            let rows = match sort_and_limit {
                 None => {
                     SelectAllChangesetsIdsInRange::query(conn, &self.repo_id, &min_id, &max_id)
-                        .await
+                        .await?
                 }
                 Some((SortOrder::Ascending, limit)) => {
                     SelectAllChangesetsIdsInRangeLimitAsc::query(
@@ -521,7 +540,7 @@ impl Changesets for SqlChangesets {
                         &max_id,
                         &limit,
                     )
-                    .await
+                    .await?
                 }
                 Some((SortOrder::Descending, limit)) => {
                     SelectAllChangesetsIdsInRangeLimitDesc::query(
@@ -531,14 +550,25 @@ impl Changesets for SqlChangesets {
                         &max_id,
                         &limit,
                     )
-                    .await
+                    .await?
                 }
-            }
+            };
+            let mut parents = if include_parents {
+                select_parents_of_rows(conn, self.repo_id, &rows).await?
+            } else {
+                HashMap::new()
+            };
+            let entries = rows.into_iter().map(move |(cs_id, unique_id, gen)| {
+                Ok(EnumerationEntry {
+                    cs_id,
+                    unique_id,
+                    gen,
+                    parents: include_parents
+                        .then(|| parents.remove(&unique_id).unwrap_or_default()),
+                })
+            });
+            Ok::<_, Error>(stream::iter(entries))
         }
-        .map_ok(|rows| {
-            let changesets_ids = rows.into_iter().map(|row| Ok((row.0, row.1)));
-            stream::iter(changesets_ids)
-        })
         .try_flatten_stream()
         .boxed()
     }
@@ -581,6 +611,27 @@ impl SqlChangesets {
     }
 }
 
+/// Parents of the changesets of `rows`, keyed by unique id, with a single
+/// query. `rows` are a page of `list_enumeration_range_detailed`, so they
+/// are all the changesets of the repo between their lowest and highest id.
+async fn select_parents_of_rows(
+    connection: &Connection,
+    repo_id: RepositoryId,
+    rows: &[(ChangesetId, u64, u64)],
+) -> Result<HashMap<u64, Vec<ChangesetId>>, Error> {
+    let ids = rows.iter().map(|(_, id, _)| *id);
+    let (min_id, max_id) = match (ids.clone().min(), ids.max()) {
+        (Some(min_id), Some(max_id)) => (min_id, max_id),
+        _ => return Ok(HashMap::new()),
+    };
+    let parent_rows = SelectParentsInRange::query(connection, &repo_id, &min_id, &max_id).await?;
+    let mut parents: HashMap<u64, Vec<ChangesetId>> = HashMap::new();
+    for (id, parent) in parent_rows {
+        parents.entry(id).or_default().push(parent);
+    }
+    Ok(parents)
+}
+
 fn check_missing_rows(
     expected: &[ChangesetId],
     actual: &[(u64, ChangesetId, u64)],
@@ -665,11 +716,13 @@ async fn select_changeset(
         None
     } else {
         let gen = rows[0].0;
+        let extra = rows[0].1.clone().map(Bytes::from);
         Some(ChangesetEntry {
             repo_id,
             cs_id,
-            parents: rows.into_iter().filter_map(|row| row.1).collect(),
+            parents: rows.into_iter().filter_map(|row| row.2).collect(),
             gen,
+            extra,
         })
     };
     Ok(result)
@@ -698,14 +751,15 @@ async fn select_many_changesets(
                     SelectManyChangesets::query(&conn, &repo_id, &tok, &cs_ids[..]).await?;
 
                 let mut cs_id_to_cs_entry = HashMap::new();
-                for (cs_id, gen, maybe_parent, _, _) in fetched_changesets {
+                for (cs_id, gen, extra, maybe_parent, _, _) in fetched_changesets {
                     cs_id_to_cs_entry
                         .entry(cs_id)
-                        .or_insert(ChangesetEntry {
+                        .or_insert_with(|| ChangesetEntry {
                             repo_id,
                             cs_id,
                             parents: vec![],
                             gen,
+                            extra: extra.map(Bytes::from),
                         })
                         .parents
                         .extend(maybe_parent.into_iter());
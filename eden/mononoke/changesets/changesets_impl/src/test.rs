@@ -6,34 +6,58 @@
  */
 
 //! Tests for the Changesets store.
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::str::FromStr;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use anyhow::Error;
+use anyhow::Result;
 use assert_matches::assert_matches;
+use async_trait::async_trait;
+use bytes::Bytes;
 use caching_ext::MockStoreStats;
 use changesets::ChangesetEntry;
 use changesets::ChangesetInsert;
+use changesets::ChangesetSubscriber;
 use changesets::Changesets;
 use changesets::ChangesetsRef;
+use changesets::EnumerationEntry;
+use changesets::HgPrefixResolver;
+use changesets::PrefixResolution;
+use changesets::PrefixResolver;
+use changesets::PrefixScheme;
 use changesets::SortOrder;
 use context::CoreContext;
 use fbinit::FacebookInit;
+use futures::stream::BoxStream;
 use futures::Future;
 use futures::TryStreamExt;
 use maplit::hashset;
+use mononoke_types::hash::Blake2;
+use mononoke_types::ChangesetId;
 use mononoke_types::ChangesetIdPrefix;
 use mononoke_types::ChangesetIdsResolvedFromPrefix;
 use mononoke_types::Generation;
+use mononoke_types::RepositoryId;
 use mononoke_types_mocks::changesetid::*;
 use mononoke_types_mocks::repo::*;
+use parking_lot::Mutex;
 use pretty_assertions::assert_eq;
+use rand::rngs::SmallRng;
+use rand::Rng;
+use rand::SeedableRng;
 use rendezvous::RendezVousOptions;
 use sql_construct::SqlConstruct;
 use vec1::Vec1;
 
 use super::CachingChangesets;
+use super::FilterStatus;
+use super::FilteredChangesets;
+use super::InMemoryChangesets;
+use super::NotifyingChangesets;
 use super::SqlChangesets;
 use super::SqlChangesetsBuilder;
 use crate::sql::SqlChangesetsError;
@@ -68,6 +92,15 @@ where
     Ok(())
 }
 
+async fn run_in_memory_test<F, FO>(fb: FacebookInit, test_fn: F) -> Result<(), Error>
+where
+    F: FnOnce(FacebookInit, InMemoryChangesets) -> FO,
+    FO: Future<Output = Result<(), Error>>,
+{
+    test_fn(fb, InMemoryChangesets::new(REPO_ZERO)).await?;
+    Ok(())
+}
+
 async fn add_and_get<C: Changesets + 'static>(
     fb: FacebookInit,
     changesets: C,
@@ -78,6 +111,7 @@ async fn add_and_get<C: Changesets + 'static>(
     let row = ChangesetInsert {
         cs_id: ONES_CSID,
         parents: vec![],
+        extra: None,
     };
 
     changesets.add(ctx, row).await?;
@@ -89,11 +123,93 @@ async fn add_and_get<C: Changesets + 'static>(
             cs_id: ONES_CSID,
             parents: vec![],
             gen: 1,
+            extra: None,
         }),
     );
     Ok(())
 }
 
+async fn add_and_get_extra<C: Changesets + 'static>(
+    fb: FacebookInit,
+    changesets: C,
+) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let ctx = &ctx;
+
+    let extra = Bytes::from_static(b"import_batch=42");
+    let row1 = ChangesetInsert {
+        cs_id: ONES_CSID,
+        parents: vec![],
+        extra: Some(extra.clone()),
+    };
+    let row2 = ChangesetInsert {
+        cs_id: TWOS_CSID,
+        parents: vec![ONES_CSID],
+        extra: None,
+    };
+    changesets.add(ctx, row1.clone()).await?;
+    changesets.add(ctx, row2).await?;
+
+    let row3 = ChangesetInsert {
+        cs_id: THREES_CSID,
+        parents: vec![TWOS_CSID],
+        extra: Some(Bytes::new()),
+    };
+    let row4 = ChangesetInsert {
+        cs_id: FOURS_CSID,
+        parents: vec![THREES_CSID],
+        extra: Some(Bytes::from_static(b"\0\xff")),
+    };
+    changesets
+        .add_many(
+            ctx,
+            Vec1::try_from(vec![(row3, Generation::new(3)), (row4, Generation::new(4))])?,
+        )
+        .await?;
+
+    let entry = |cs_id, parents, gen, extra| ChangesetEntry {
+        repo_id: REPO_ZERO,
+        cs_id,
+        parents,
+        gen,
+        extra,
+    };
+    let expected = vec![
+        entry(ONES_CSID, vec![], 1, Some(extra.clone())),
+        entry(TWOS_CSID, vec![ONES_CSID], 2, None),
+        entry(THREES_CSID, vec![TWOS_CSID], 3, Some(Bytes::new())),
+        entry(
+            FOURS_CSID,
+            vec![THREES_CSID],
+            4,
+            Some(Bytes::from_static(b"\0\xff")),
+        ),
+    ];
+
+    assert_eq!(
+        changesets.get(ctx, ONES_CSID).await?,
+        Some(expected[0].clone())
+    );
+    let mut actual = changesets
+        .get_many(ctx, vec![ONES_CSID, TWOS_CSID, THREES_CSID, FOURS_CSID])
+        .await?;
+    actual.sort_by_key(|entry| entry.gen);
+    assert_eq!(actual, expected);
+
+    // Adding the changeset again keeps the extra it was first added with.
+    let duplicate = ChangesetInsert {
+        extra: Some(Bytes::from_static(b"other")),
+        ..row1
+    };
+    assert!(!changesets.add(ctx, duplicate).await?);
+    assert_eq!(
+        changesets.get(ctx, ONES_CSID).await?,
+        Some(expected[0].clone())
+    );
+
+    Ok(())
+}
+
 async fn add_missing_parents<C: Changesets>(fb: FacebookInit, changesets: C) -> Result<(), Error> {
     let ctx = CoreContext::test_mock(fb);
     let ctx = &ctx;
@@ -101,6 +217,7 @@ async fn add_missing_parents<C: Changesets>(fb: FacebookInit, changesets: C) ->
     let row = ChangesetInsert {
         cs_id: ONES_CSID,
         parents: vec![TWOS_CSID],
+        extra: None,
     };
 
     let result = changesets
@@ -131,6 +248,7 @@ async fn duplicate<C: Changesets + 'static>(fb: FacebookInit, changesets: C) ->
     let row = ChangesetInsert {
         cs_id: ONES_CSID,
         parents: vec![],
+        extra: None,
     };
 
     assert!(
@@ -154,6 +272,7 @@ async fn broken_duplicate<C: Changesets + 'static>(
     let row = ChangesetInsert {
         cs_id: ONES_CSID,
         parents: vec![],
+        extra: None,
     };
     assert!(
         changesets.add(ctx, row).await?,
@@ -163,6 +282,7 @@ async fn broken_duplicate<C: Changesets + 'static>(
     let row = ChangesetInsert {
         cs_id: TWOS_CSID,
         parents: vec![],
+        extra: None,
     };
     assert!(
         changesets.add(ctx, row).await?,
@@ -172,6 +292,7 @@ async fn broken_duplicate<C: Changesets + 'static>(
     let row = ChangesetInsert {
         cs_id: ONES_CSID,
         parents: vec![TWOS_CSID],
+        extra: None,
     };
     let result = changesets
         .add(ctx, row)
@@ -192,30 +313,35 @@ async fn complex<C: Changesets>(fb: FacebookInit, changesets: C) -> Result<(), E
     let row1 = ChangesetInsert {
         cs_id: ONES_CSID,
         parents: vec![],
+        extra: None,
     };
     changesets.add(ctx, row1).await?;
 
     let row2 = ChangesetInsert {
         cs_id: TWOS_CSID,
         parents: vec![],
+        extra: None,
     };
     changesets.add(ctx, row2).await?;
 
     let row3 = ChangesetInsert {
         cs_id: THREES_CSID,
         parents: vec![TWOS_CSID],
+        extra: None,
     };
     changesets.add(ctx, row3).await?;
 
     let row4 = ChangesetInsert {
         cs_id: FOURS_CSID,
         parents: vec![ONES_CSID, THREES_CSID],
+        extra: None,
     };
     changesets.add(ctx, row4).await?;
 
     let row5 = ChangesetInsert {
         cs_id: FIVES_CSID,
         parents: vec![ONES_CSID, TWOS_CSID, FOURS_CSID],
+        extra: None,
     };
     changesets.add(ctx, row5).await?;
 
@@ -226,6 +352,7 @@ async fn complex<C: Changesets>(fb: FacebookInit, changesets: C) -> Result<(), E
             cs_id: ONES_CSID,
             parents: vec![],
             gen: 1,
+            extra: None,
         }),
     );
 
@@ -236,6 +363,7 @@ async fn complex<C: Changesets>(fb: FacebookInit, changesets: C) -> Result<(), E
             cs_id: TWOS_CSID,
             parents: vec![],
             gen: 1,
+            extra: None,
         }),
     );
 
@@ -246,6 +374,7 @@ async fn complex<C: Changesets>(fb: FacebookInit, changesets: C) -> Result<(), E
             cs_id: THREES_CSID,
             parents: vec![TWOS_CSID],
             gen: 2,
+            extra: None,
         }),
     );
 
@@ -256,6 +385,7 @@ async fn complex<C: Changesets>(fb: FacebookInit, changesets: C) -> Result<(), E
             cs_id: FOURS_CSID,
             parents: vec![ONES_CSID, THREES_CSID],
             gen: 3,
+            extra: None,
         }),
     );
 
@@ -266,6 +396,7 @@ async fn complex<C: Changesets>(fb: FacebookInit, changesets: C) -> Result<(), E
             cs_id: FIVES_CSID,
             parents: vec![ONES_CSID, TWOS_CSID, FOURS_CSID],
             gen: 4,
+            extra: None,
         }),
     );
 
@@ -279,30 +410,35 @@ async fn get_many<C: Changesets>(fb: FacebookInit, changesets: C) -> Result<(),
     let row1 = ChangesetInsert {
         cs_id: ONES_CSID,
         parents: vec![],
+        extra: None,
     };
     changesets.add(ctx, row1).await?;
 
     let row2 = ChangesetInsert {
         cs_id: TWOS_CSID,
         parents: vec![],
+        extra: None,
     };
     changesets.add(ctx, row2).await?;
 
     let row3 = ChangesetInsert {
         cs_id: THREES_CSID,
         parents: vec![TWOS_CSID],
+        extra: None,
     };
     changesets.add(ctx, row3).await?;
 
     let row4 = ChangesetInsert {
         cs_id: FOURS_CSID,
         parents: vec![ONES_CSID, THREES_CSID],
+        extra: None,
     };
     changesets.add(ctx, row4).await?;
 
     let row5 = ChangesetInsert {
         cs_id: FIVES_CSID,
         parents: vec![THREES_CSID, ONES_CSID, TWOS_CSID, FOURS_CSID],
+        extra: None,
     };
     changesets.add(ctx, row5).await?;
 
@@ -316,12 +452,14 @@ async fn get_many<C: Changesets>(fb: FacebookInit, changesets: C) -> Result<(),
                 cs_id: ONES_CSID,
                 parents: vec![],
                 gen: 1,
+                extra: None,
             },
             ChangesetEntry {
                 repo_id: REPO_ZERO,
                 cs_id: TWOS_CSID,
                 parents: vec![],
                 gen: 1,
+                extra: None,
             },
         ]
     );
@@ -337,18 +475,21 @@ async fn get_many<C: Changesets>(fb: FacebookInit, changesets: C) -> Result<(),
                 cs_id: ONES_CSID,
                 parents: vec![],
                 gen: 1,
+                extra: None,
             },
             ChangesetEntry {
                 repo_id: REPO_ZERO,
                 cs_id: TWOS_CSID,
                 parents: vec![],
                 gen: 1,
+                extra: None,
             },
             ChangesetEntry {
                 repo_id: REPO_ZERO,
                 cs_id: THREES_CSID,
                 parents: vec![TWOS_CSID],
                 gen: 2,
+                extra: None,
             },
         ]
     );
@@ -367,12 +508,14 @@ async fn get_many<C: Changesets>(fb: FacebookInit, changesets: C) -> Result<(),
                 cs_id: ONES_CSID,
                 parents: vec![],
                 gen: 1,
+                extra: None,
             },
             ChangesetEntry {
                 repo_id: REPO_ZERO,
                 cs_id: FOURS_CSID,
                 parents: vec![ONES_CSID, THREES_CSID],
                 gen: 3,
+                extra: None,
             },
         ]
     );
@@ -388,18 +531,21 @@ async fn get_many<C: Changesets>(fb: FacebookInit, changesets: C) -> Result<(),
                 cs_id: ONES_CSID,
                 parents: vec![],
                 gen: 1,
+                extra: None,
             },
             ChangesetEntry {
                 repo_id: REPO_ZERO,
                 cs_id: FOURS_CSID,
                 parents: vec![ONES_CSID, THREES_CSID],
                 gen: 3,
+                extra: None,
             },
             ChangesetEntry {
                 repo_id: REPO_ZERO,
                 cs_id: FIVES_CSID,
                 parents: vec![THREES_CSID, ONES_CSID, TWOS_CSID, FOURS_CSID],
                 gen: 4,
+                extra: None,
             },
         ]
     );
@@ -414,12 +560,14 @@ async fn get_many_missing<C: Changesets>(fb: FacebookInit, changesets: C) -> Res
     let row1 = ChangesetInsert {
         cs_id: ONES_CSID,
         parents: vec![],
+        extra: None,
     };
     changesets.add(ctx, row1).await?;
 
     let row2 = ChangesetInsert {
         cs_id: TWOS_CSID,
         parents: vec![],
+        extra: None,
     };
     changesets.add(ctx, row2).await?;
 
@@ -434,12 +582,14 @@ async fn get_many_missing<C: Changesets>(fb: FacebookInit, changesets: C) -> Res
                 cs_id: ONES_CSID,
                 parents: vec![],
                 gen: 1,
+                extra: None,
             },
             ChangesetEntry {
                 repo_id: REPO_ZERO,
                 cs_id: TWOS_CSID,
                 parents: vec![],
                 gen: 1,
+                extra: None,
             },
         ]
     );
@@ -447,6 +597,82 @@ async fn get_many_missing<C: Changesets>(fb: FacebookInit, changesets: C) -> Res
     Ok(())
 }
 
+async fn get_many_ordered<C: Changesets>(fb: FacebookInit, changesets: C) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let ctx = &ctx;
+    add_stack(ctx, &changesets, None, &[ONES_CSID, TWOS_CSID]).await?;
+    let entry = |cs_id, parents, gen| {
+        Some(ChangesetEntry {
+            repo_id: REPO_ZERO,
+            cs_id,
+            parents,
+            gen,
+            extra: None,
+        })
+    };
+
+    // Missing ids are interleaved with stored ones, and duplicates get the
+    // same entry at each of their indexes.
+    let actual = changesets
+        .get_many_ordered(
+            ctx,
+            &[
+                TWOS_CSID,
+                THREES_CSID,
+                ONES_CSID,
+                TWOS_CSID,
+                FOURS_CSID,
+                TWOS_CSID,
+            ],
+        )
+        .await?;
+    assert_eq!(
+        actual,
+        vec![
+            entry(TWOS_CSID, vec![ONES_CSID], 2),
+            None,
+            entry(ONES_CSID, vec![], 1),
+            entry(TWOS_CSID, vec![ONES_CSID], 2),
+            None,
+            entry(TWOS_CSID, vec![ONES_CSID], 2),
+        ]
+    );
+    assert!(changesets.get_many_ordered(ctx, &[]).await?.is_empty());
+
+    // Enough ids for several chunks of fetches, with every other one stored.
+    let mut rng = SmallRng::seed_from_u64(1);
+    let cs_ids = random_cs_ids(&mut rng, 2500);
+    let inserts: Vec<_> = cs_ids
+        .iter()
+        .step_by(2)
+        .map(|cs_id| {
+            let insert = ChangesetInsert {
+                cs_id: *cs_id,
+                parents: vec![],
+                extra: None,
+            };
+            (insert, Generation::new(1))
+        })
+        .collect();
+    changesets
+        .add_many(ctx, Vec1::try_from_vec(inserts)?)
+        .await?;
+    let requested: Vec<_> = cs_ids.iter().chain(cs_ids.iter().rev()).copied().collect();
+    let actual = changesets.get_many_ordered(ctx, &requested).await?;
+    assert_eq!(actual.len(), requested.len());
+    let stored: HashSet<_> = cs_ids.iter().step_by(2).collect();
+    for (cs_id, actual) in requested.iter().zip(actual) {
+        let expected = if stored.contains(cs_id) {
+            entry(*cs_id, vec![], 1)
+        } else {
+            None
+        };
+        assert_eq!(actual, expected);
+    }
+
+    Ok(())
+}
+
 async fn get_many_by_prefix<C: Changesets>(fb: FacebookInit, changesets: C) -> Result<(), Error> {
     let ctx = CoreContext::test_mock(fb);
     let ctx = &ctx;
@@ -454,18 +680,22 @@ async fn get_many_by_prefix<C: Changesets>(fb: FacebookInit, changesets: C) -> R
     let row1 = ChangesetInsert {
         cs_id: ONES_CSID,
         parents: vec![],
+        extra: None,
     };
     let row2 = ChangesetInsert {
         cs_id: TWOS_CSID,
         parents: vec![],
+        extra: None,
     };
     let row3 = ChangesetInsert {
         cs_id: FS_ES_CSID,
         parents: vec![],
+        extra: None,
     };
     let row4 = ChangesetInsert {
         cs_id: FS_CSID,
         parents: vec![],
+        extra: None,
     };
 
     changesets.add(ctx, row1).await?;
@@ -541,6 +771,119 @@ async fn get_many_by_prefix<C: Changesets>(fb: FacebookInit, changesets: C) -> R
     Ok(())
 }
 
+/// Resolves hg prefixes from a fixed mapping of hg changeset ids.
+struct FakeHgPrefixResolver(HashMap<&'static str, ChangesetId>);
+
+#[async_trait]
+impl HgPrefixResolver for FakeHgPrefixResolver {
+    async fn resolve_hg_prefix(
+        &self,
+        _ctx: &CoreContext,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<ChangesetIdsResolvedFromPrefix> {
+        assert!(prefix.len() <= 40, "not an hg prefix: {}", prefix);
+        let mut cs_ids: Vec<_> = self
+            .0
+            .iter()
+            .filter(|(hg_id, _)| hg_id.starts_with(prefix))
+            .map(|(_, cs_id)| *cs_id)
+            .collect();
+        cs_ids.sort();
+        Ok(ChangesetIdsResolvedFromPrefix::from_vec_and_limit(
+            cs_ids, limit,
+        ))
+    }
+}
+
+async fn resolve_prefix<C: Changesets + 'static>(
+    fb: FacebookInit,
+    changesets: C,
+) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let ctx = &ctx;
+
+    for cs_id in [ONES_CSID, FS_ES_CSID, FS_CSID] {
+        changesets
+            .add(
+                ctx,
+                ChangesetInsert {
+                    cs_id,
+                    parents: vec![],
+                    extra: None,
+                },
+            )
+            .await?;
+    }
+    let hg_resolver = FakeHgPrefixResolver(HashMap::from([
+        ("abcdef0123456789abcdef0123456789abcdef01", TWOS_CSID),
+        ("fffe000000000000000000000000000000000000", THREES_CSID),
+    ]));
+    let resolver =
+        PrefixResolver::new(Arc::new(changesets)).with_hg_resolver(Arc::new(hg_resolver));
+
+    // bonsai prefix
+    let actual = resolver.resolve_prefix(ctx, "1111", 10).await?;
+    assert_eq!(
+        actual,
+        PrefixResolution::Matched {
+            scheme: PrefixScheme::Bonsai,
+            resolved: ChangesetIdsResolvedFromPrefix::Single(ONES_CSID),
+        }
+    );
+
+    // full bonsai id, too long to be an hg prefix
+    let actual = resolver
+        .resolve_prefix(ctx, &ONES_CSID.to_string(), 10)
+        .await?;
+    assert_eq!(actual.scheme(), Some(PrefixScheme::Bonsai));
+
+    // hg prefix and full hg id
+    for prefix in ["abcd", "abcdef0123456789abcdef0123456789abcdef01"] {
+        let actual = resolver.resolve_prefix(ctx, prefix, 10).await?;
+        assert_eq!(
+            actual,
+            PrefixResolution::Matched {
+                scheme: PrefixScheme::Hg,
+                resolved: ChangesetIdsResolvedFromPrefix::Single(TWOS_CSID),
+            }
+        );
+    }
+
+    // both bonsai and hg prefix
+    let actual = resolver.resolve_prefix(ctx, "fff", 10).await?;
+    assert_eq!(
+        actual,
+        PrefixResolution::AmbiguousSchemes {
+            bonsai: ChangesetIdsResolvedFromPrefix::Multiple(vec![FS_ES_CSID, FS_CSID]),
+            hg: ChangesetIdsResolvedFromPrefix::Single(THREES_CSID),
+        }
+    );
+    assert_eq!(actual.scheme(), None);
+    assert_eq!(
+        actual.clone().into_resolved(10),
+        ChangesetIdsResolvedFromPrefix::Multiple(vec![FS_ES_CSID, FS_CSID, THREES_CSID]),
+    );
+    assert_eq!(
+        actual.into_resolved(2),
+        ChangesetIdsResolvedFromPrefix::TooMany(vec![FS_ES_CSID, FS_CSID]),
+    );
+
+    // neither
+    let actual = resolver.resolve_prefix(ctx, "0123", 10).await?;
+    assert_eq!(actual, PrefixResolution::NoMatch);
+
+    // not hex
+    assert!(resolver.resolve_prefix(ctx, "xyz", 10).await.is_err());
+
+    // without an hg resolver, only bonsai prefixes are resolved
+    let resolver = PrefixResolver::new(Arc::new(InMemoryChangesets::new(REPO_ZERO)));
+    let actual = resolver.resolve_prefix(ctx, "abcd", 10).await?;
+    assert_eq!(actual, PrefixResolution::NoMatch);
+
+    Ok(())
+}
+
 async fn caching_fill<C: Changesets + 'static>(
     fb: FacebookInit,
     changesets: C,
@@ -553,14 +896,17 @@ async fn caching_fill<C: Changesets + 'static>(
     let row1 = ChangesetInsert {
         cs_id: ONES_CSID,
         parents: vec![],
+        extra: None,
     };
     let row2 = ChangesetInsert {
         cs_id: TWOS_CSID,
         parents: vec![],
+        extra: None,
     };
     let row3 = ChangesetInsert {
         cs_id: THREES_CSID,
         parents: vec![],
+        extra: None,
     };
 
     changesets.add(ctx, row1).await?;
@@ -726,14 +1072,17 @@ async fn caching_shared<C: Changesets + 'static>(
     let row1 = ChangesetInsert {
         cs_id: ONES_CSID,
         parents: vec![],
+        extra: None,
     };
     let row2 = ChangesetInsert {
         cs_id: TWOS_CSID,
         parents: vec![],
+        extra: None,
     };
     let row3 = ChangesetInsert {
         cs_id: THREES_CSID,
         parents: vec![],
+        extra: None,
     };
 
     changesets.add(ctx, row1).await?;
@@ -814,6 +1163,148 @@ async fn caching_shared<C: Changesets + 'static>(
     Ok(())
 }
 
+async fn enumeration<C: Changesets>(fb: FacebookInit, changesets: C) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let ctx = &ctx;
+
+    assert_eq!(
+        changesets.enumeration_bounds(ctx, false, vec![]).await?,
+        None
+    );
+
+    for (cs_id, parents) in [
+        (ONES_CSID, vec![]),
+        (TWOS_CSID, vec![ONES_CSID]),
+        (THREES_CSID, vec![TWOS_CSID]),
+    ] {
+        changesets
+            .add(
+                ctx,
+                ChangesetInsert {
+                    cs_id,
+                    parents,
+                    extra: None,
+                },
+            )
+            .await?;
+    }
+
+    let (lo, hi) = changesets
+        .enumeration_bounds(ctx, false, vec![])
+        .await?
+        .expect("bounds of non-empty changesets");
+    let all: Vec<_> = changesets
+        .list_enumeration_range(ctx, lo, hi + 1, None, false)
+        .try_collect()
+        .await?;
+    assert_eq!(
+        all.iter().map(|(cs_id, _)| *cs_id).collect::<Vec<_>>(),
+        vec![ONES_CSID, TWOS_CSID, THREES_CSID]
+    );
+    assert_eq!((all[0].1, all[2].1), (lo, hi));
+
+    let last_two: Vec<_> = changesets
+        .list_enumeration_range(ctx, lo, hi + 1, Some((SortOrder::Descending, 2)), false)
+        .try_collect()
+        .await?;
+    assert_eq!(last_two, vec![all[2], all[1]]);
+
+    // Enumeration after known heads skips them.
+    assert_eq!(
+        changesets
+            .enumeration_bounds(ctx, false, vec![ONES_CSID])
+            .await?,
+        Some((all[1].1, hi))
+    );
+
+    Ok(())
+}
+
+async fn enumeration_detailed<C: Changesets>(fb: FacebookInit, changesets: C) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let ctx = &ctx;
+
+    for (cs_id, parents) in [
+        (ONES_CSID, vec![]),
+        (TWOS_CSID, vec![ONES_CSID]),
+        (THREES_CSID, vec![ONES_CSID]),
+        (FOURS_CSID, vec![THREES_CSID, TWOS_CSID]),
+    ] {
+        changesets
+            .add(
+                ctx,
+                ChangesetInsert {
+                    cs_id,
+                    parents,
+                    extra: None,
+                },
+            )
+            .await?;
+    }
+    let (lo, hi) = changesets
+        .enumeration_bounds(ctx, false, vec![])
+        .await?
+        .expect("bounds of non-empty changesets");
+
+    let all: Vec<EnumerationEntry> = changesets
+        .list_enumeration_range_detailed(ctx, lo, hi + 1, None, false, true)
+        .try_collect()
+        .await?;
+    let expected: HashMap<_, _> = changesets
+        .get_many(ctx, vec![ONES_CSID, TWOS_CSID, THREES_CSID, FOURS_CSID])
+        .await?
+        .into_iter()
+        .map(|entry| (entry.cs_id, entry))
+        .collect();
+    assert_eq!(
+        all.iter().map(|entry| entry.cs_id).collect::<Vec<_>>(),
+        vec![ONES_CSID, TWOS_CSID, THREES_CSID, FOURS_CSID]
+    );
+    for entry in &all {
+        let expected = &expected[&entry.cs_id];
+        assert_eq!(entry.gen, expected.gen);
+        assert_eq!(entry.parents.as_ref(), Some(&expected.parents));
+    }
+    // The root commit has no parents, and the order of the merge commit
+    // parents is kept.
+    assert_eq!(all[0].parents, Some(vec![]));
+    assert_eq!(all[3].parents, Some(vec![THREES_CSID, TWOS_CSID]));
+
+    // Pages have the parents of their changesets.
+    let last_two: Vec<_> = changesets
+        .list_enumeration_range_detailed(
+            ctx,
+            lo,
+            hi + 1,
+            Some((SortOrder::Descending, 2)),
+            false,
+            true,
+        )
+        .try_collect()
+        .await?;
+    assert_eq!(last_two, vec![all[3].clone(), all[2].clone()]);
+
+    // Without parents, this matches `list_enumeration_range`.
+    let without_parents: Vec<_> = changesets
+        .list_enumeration_range_detailed(ctx, lo, hi + 1, None, false, false)
+        .try_collect()
+        .await?;
+    assert!(without_parents.iter().all(|entry| entry.parents.is_none()));
+    let ids: Vec<_> = changesets
+        .list_enumeration_range(ctx, lo, hi + 1, None, false)
+        .try_collect()
+        .await?;
+    assert_eq!(
+        without_parents
+            .iter()
+            .map(|entry| (entry.cs_id, entry.unique_id))
+            .collect::<Vec<_>>(),
+        ids
+    );
+
+    Ok(())
+}
+
 async fn test_add_many_fixture<F: fixtures::TestRepoFixture + Send, C: Changesets>(
     fb: FacebookInit,
     changesets: &C,
@@ -842,6 +1333,7 @@ async fn test_add_many_fixture<F: fixtures::TestRepoFixture + Send, C: Changeset
                     ChangesetInsert {
                         cs_id: entry.cs_id,
                         parents: entry.parents,
+                        extra: None,
                     },
                     Generation::new(entry.gen),
                 )
@@ -865,9 +1357,9 @@ async fn test_add_many_fixture<F: fixtures::TestRepoFixture + Send, C: Changeset
     Ok(())
 }
 
-// NOTE: Use this wrapper macro to make sure tests are executed both with Changesets and
-// CachingChangesets. Define tests using #[test] if you need to only execute them for Changesets or
-// CachingChangesets.
+// NOTE: Use this wrapper macro to make sure tests are executed with SqlChangesets,
+// CachingChangesets and InMemoryChangesets. Define tests using #[test] if you need to only
+// execute them for one of them.
 macro_rules! testify {
     ($test: ident) => {
         paste::item! {
@@ -880,11 +1372,17 @@ macro_rules! testify {
             async fn [<test_caching_ $test>](fb: FacebookInit) -> Result<(), Error> {
                 run_caching_test(fb, $test).await
             }
+
+            #[fbinit::test]
+            async fn [<test_in_memory_ $test>](fb: FacebookInit) -> Result<(), Error> {
+                run_in_memory_test(fb, $test).await
+            }
         }
     };
 }
 
 testify!(add_and_get);
+testify!(add_and_get_extra);
 testify!(add_missing_parents);
 testify!(missing);
 testify!(duplicate);
@@ -892,7 +1390,48 @@ testify!(broken_duplicate);
 testify!(complex);
 testify!(get_many);
 testify!(get_many_by_prefix);
+testify!(resolve_prefix);
 testify!(get_many_missing);
+testify!(get_many_ordered);
+testify!(enumeration);
+testify!(enumeration_detailed);
+
+#[fbinit::test]
+async fn test_caching_get_many_ordered_fetches_once(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let ctx = &ctx;
+    let changesets = Arc::new(
+        SqlChangesetsBuilder::with_sqlite_in_memory()?
+            .build(RendezVousOptions::for_test(), REPO_ZERO),
+    );
+    add_stack(ctx, changesets.as_ref(), None, &[ONES_CSID, TWOS_CSID]).await?;
+    let cc = CachingChangesets::mocked(changesets);
+
+    let actual = cc
+        .get_many_ordered(
+            ctx,
+            &[ONES_CSID, TWOS_CSID, ONES_CSID, THREES_CSID, ONES_CSID],
+        )
+        .await?;
+    let found: Vec<_> = actual
+        .iter()
+        .map(|entry| entry.as_ref().map(|entry| entry.cs_id))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            Some(ONES_CSID),
+            Some(TWOS_CSID),
+            Some(ONES_CSID),
+            None,
+            Some(ONES_CSID)
+        ]
+    );
+    // Each distinct id is looked up once.
+    assert_eq!(cc.cachelib_stats().gets, 3);
+    assert_eq!(cc.memcache_stats().gets, 3);
+    Ok(())
+}
 
 #[fbinit::test]
 async fn test_caching_fill(fb: FacebookInit) -> Result<(), Error> {
@@ -904,6 +1443,112 @@ async fn test_caching_shared(fb: FacebookInit) -> Result<(), Error> {
     run_test(fb, caching_shared).await
 }
 
+async fn caching_extra<C: Changesets + 'static>(
+    fb: FacebookInit,
+    changesets: C,
+) -> Result<(), Error> {
+    let changesets = Arc::new(changesets);
+    let cc = CachingChangesets::mocked(changesets.clone());
+    let ctx = CoreContext::test_mock(fb);
+    let ctx = &ctx;
+
+    let extra = Bytes::from_static(b"provenance=sync");
+    let row1 = ChangesetInsert {
+        cs_id: ONES_CSID,
+        parents: vec![],
+        extra: Some(extra.clone()),
+    };
+    let row2 = ChangesetInsert {
+        cs_id: TWOS_CSID,
+        parents: vec![],
+        extra: None,
+    };
+    changesets.add(ctx, row1).await?;
+    changesets.add(ctx, row2).await?;
+
+    let mut expected = changesets.get_many(ctx, vec![ONES_CSID, TWOS_CSID]).await?;
+    expected.sort_by_key(|entry| entry.cs_id);
+    assert_eq!(expected[0].extra, Some(extra));
+    assert_eq!(expected[1].extra, None);
+
+    // Fill both caches, then read from cachelib.
+    let _ = cc.get_many(ctx, vec![ONES_CSID, TWOS_CSID]).await?;
+    let mut actual = cc.get_many(ctx, vec![ONES_CSID, TWOS_CSID]).await?;
+    actual.sort_by_key(|entry| entry.cs_id);
+    assert_eq!(actual, expected, "read from cachelib");
+    assert_eq!(cc.cachelib_stats().hits, 2);
+
+    // A fresh cachelib reads from memcache.
+    let cc = cc.fork_cachelib();
+    let mut actual = cc.get_many(ctx, vec![ONES_CSID, TWOS_CSID]).await?;
+    actual.sort_by_key(|entry| entry.cs_id);
+    assert_eq!(actual, expected, "read from memcache");
+    assert_eq!(cc.memcache_stats().hits, 2);
+
+    // Primed entries keep their extra too.
+    let cc = cc.fork_cachelib();
+    cc.prime_cache(ctx, &expected);
+    let mut actual = cc.get_many(ctx, vec![ONES_CSID, TWOS_CSID]).await?;
+    actual.sort_by_key(|entry| entry.cs_id);
+    assert_eq!(actual, expected, "read primed entries");
+    assert_eq!(cc.cachelib_stats().hits, 2);
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_caching_extra(fb: FacebookInit) -> Result<(), Error> {
+    run_test(fb, caching_extra).await
+}
+
+#[fbinit::test]
+async fn test_in_memory_from_entries(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let entry = |cs_id, parents, gen| ChangesetEntry {
+        repo_id: REPO_ZERO,
+        cs_id,
+        parents,
+        gen,
+        extra: None,
+    };
+    let entries = vec![
+        entry(ONES_CSID, vec![], 1),
+        entry(TWOS_CSID, vec![ONES_CSID], 2),
+        entry(THREES_CSID, vec![ONES_CSID, TWOS_CSID], 3),
+    ];
+    let changesets = InMemoryChangesets::from_entries(REPO_ZERO, entries.clone())?;
+
+    let mut stored = changesets
+        .get_many(&ctx, vec![ONES_CSID, TWOS_CSID, THREES_CSID])
+        .await?;
+    stored.sort_by_key(|entry| entry.gen);
+    assert_eq!(stored, entries);
+
+    // Later additions get generations computed from the seeded parents.
+    changesets
+        .add(
+            &ctx,
+            ChangesetInsert {
+                cs_id: FOURS_CSID,
+                parents: vec![THREES_CSID],
+                extra: None,
+            },
+        )
+        .await?;
+    assert_eq!(
+        changesets.get(&ctx, FOURS_CSID).await?.map(|e| e.gen),
+        Some(4)
+    );
+
+    let result =
+        InMemoryChangesets::from_entries(REPO_ZERO, vec![entry(TWOS_CSID, vec![ONES_CSID], 2)]);
+    assert_matches!(
+        result.map(|_| ()).unwrap_err().downcast::<SqlChangesetsError>(),
+        Ok(SqlChangesetsError::MissingParents(ref x)) if x == &vec![ONES_CSID]
+    );
+    Ok(())
+}
+
 macro_rules! add_many_tests {
     ($fixture: ident) => {
         paste::item! {
@@ -931,3 +1576,358 @@ add_many_tests!(
     UnsharedMergeUneven,
     ManyDiamonds
 );
+
+/// Counts the reads of changesets, to check which probes reach the storage.
+struct CountingChangesets {
+    changesets: Arc<dyn Changesets>,
+    reads: AtomicUsize,
+}
+
+impl CountingChangesets {
+    fn new_sqlite() -> Result<Self, Error> {
+        let changesets = SqlChangesetsBuilder::with_sqlite_in_memory()?
+            .build(RendezVousOptions::for_test(), REPO_ZERO);
+        Ok(Self {
+            changesets: Arc::new(changesets),
+            reads: AtomicUsize::new(0),
+        })
+    }
+
+    fn reads(&self) -> usize {
+        self.reads.load(Ordering::Relaxed)
+    }
+}
+
+#[async_trait]
+impl Changesets for CountingChangesets {
+    fn repo_id(&self) -> RepositoryId {
+        self.changesets.repo_id()
+    }
+
+    async fn add(&self, ctx: &CoreContext, cs: ChangesetInsert) -> Result<bool, Error> {
+        self.changesets.add(ctx, cs).await
+    }
+
+    async fn add_many(
+        &self,
+        ctx: &CoreContext,
+        css: Vec1<(ChangesetInsert, Generation)>,
+    ) -> Result<(), Error> {
+        self.changesets.add_many(ctx, css).await
+    }
+
+    async fn get(
+        &self,
+        ctx: &CoreContext,
+        cs_id: ChangesetId,
+    ) -> Result<Option<ChangesetEntry>, Error> {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.changesets.get(ctx, cs_id).await
+    }
+
+    async fn get_many(
+        &self,
+        ctx: &CoreContext,
+        cs_ids: Vec<ChangesetId>,
+    ) -> Result<Vec<ChangesetEntry>, Error> {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.changesets.get_many(ctx, cs_ids).await
+    }
+
+    async fn get_many_by_prefix(
+        &self,
+        ctx: &CoreContext,
+        cs_prefix: ChangesetIdPrefix,
+        limit: usize,
+    ) -> Result<ChangesetIdsResolvedFromPrefix, Error> {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.changesets
+            .get_many_by_prefix(ctx, cs_prefix, limit)
+            .await
+    }
+
+    fn prime_cache(&self, ctx: &CoreContext, changesets: &[ChangesetEntry]) {
+        self.changesets.prime_cache(ctx, changesets)
+    }
+
+    async fn enumeration_bounds(
+        &self,
+        ctx: &CoreContext,
+        read_from_master: bool,
+        known_heads: Vec<ChangesetId>,
+    ) -> Result<Option<(u64, u64)>, Error> {
+        self.changesets
+            .enumeration_bounds(ctx, read_from_master, known_heads)
+            .await
+    }
+
+    fn list_enumeration_range_detailed(
+        &self,
+        ctx: &CoreContext,
+        min_id: u64,
+        max_id: u64,
+        sort_and_limit: Option<(SortOrder, u64)>,
+        read_from_master: bool,
+        include_parents: bool,
+    ) -> BoxStream<'_, Result<EnumerationEntry, Error>> {
+        self.changesets.list_enumeration_range_detailed(
+            ctx,
+            min_id,
+            max_id,
+            sort_and_limit,
+            read_from_master,
+            include_parents,
+        )
+    }
+}
+
+fn random_cs_ids(rng: &mut SmallRng, count: usize) -> Vec<ChangesetId> {
+    (0..count)
+        .map(|_| ChangesetId::new(Blake2::from_byte_array(rng.gen())))
+        .collect()
+}
+
+/// Add `cs_ids` as a linear stack on top of `parent`.
+async fn add_stack(
+    ctx: &CoreContext,
+    changesets: &dyn Changesets,
+    mut parent: Option<ChangesetId>,
+    cs_ids: &[ChangesetId],
+) -> Result<(), Error> {
+    for cs_id in cs_ids {
+        let row = ChangesetInsert {
+            cs_id: *cs_id,
+            parents: parent.into_iter().collect(),
+            extra: None,
+        };
+        changesets.add(ctx, row).await?;
+        parent = Some(*cs_id);
+    }
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_filtered_absent_probes(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let ctx = &ctx;
+    let mut rng = SmallRng::seed_from_u64(1);
+    let counting = Arc::new(CountingChangesets::new_sqlite()?);
+    let present = random_cs_ids(&mut rng, 100);
+    add_stack(ctx, counting.as_ref(), None, &present).await?;
+
+    // Large enough for no absent changeset to pass the filter.
+    let filtered = FilteredChangesets::new(counting.clone(), true).with_bits_per_changeset(64);
+    assert_eq!(filtered.filter_status(), FilterStatus::NotStarted);
+    filtered.build_filter(ctx).await?;
+    assert_eq!(
+        filtered.filter_status(),
+        FilterStatus::Complete { enumerated: 100 }
+    );
+
+    let absent = random_cs_ids(&mut rng, 10_000);
+    let reads = counting.reads();
+    assert_eq!(filtered.get_many(ctx, absent.clone()).await?, vec![]);
+    for cs_id in &absent[..100] {
+        assert!(!filtered.exists(ctx, *cs_id).await?);
+        assert_eq!(
+            filtered
+                .get_many_by_prefix(
+                    ctx,
+                    ChangesetIdPrefix::from_bytes(cs_id.blake2().as_ref())?,
+                    10
+                )
+                .await?,
+            ChangesetIdsResolvedFromPrefix::NoMatch
+        );
+    }
+    assert_eq!(counting.reads(), reads);
+
+    assert_eq!(filtered.get_many(ctx, present.clone()).await?.len(), 100);
+    assert_eq!(counting.reads(), reads + 1);
+
+    // Changesets added after the build are found.
+    let fresh = random_cs_ids(&mut rng, 2);
+    add_stack(ctx, &filtered, present.last().copied(), &fresh[..1]).await?;
+    filtered
+        .add_many(
+            ctx,
+            Vec1::new((
+                ChangesetInsert {
+                    cs_id: fresh[1],
+                    parents: vec![fresh[0]],
+                    extra: None,
+                },
+                Generation::new(102),
+            )),
+        )
+        .await?;
+    let mut found = filtered.get_many(ctx, fresh.clone()).await?;
+    found.sort_by_key(|entry| entry.gen);
+    assert_eq!(
+        found.iter().map(|entry| entry.cs_id).collect::<Vec<_>>(),
+        fresh
+    );
+
+    // Changesets added by other writers are found, as the filter is
+    // refreshed before reporting them absent.
+    let other = random_cs_ids(&mut rng, 2);
+    add_stack(ctx, counting.as_ref(), Some(fresh[1]), &other[..1]).await?;
+    assert_eq!(filtered.get(ctx, other[0]).await?.map(|e| e.gen), Some(103));
+    assert_eq!(
+        filtered.filter_status(),
+        FilterStatus::Complete { enumerated: 103 }
+    );
+    add_stack(ctx, counting.as_ref(), Some(other[0]), &other[1..]).await?;
+    filtered.refresh_filter(ctx).await?;
+    assert_eq!(
+        filtered.filter_status(),
+        FilterStatus::Complete { enumerated: 104 }
+    );
+    assert_eq!(filtered.get(ctx, other[1]).await?.map(|e| e.gen), Some(104));
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_filtered_shared_storage(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let ctx = &ctx;
+    let mut rng = SmallRng::seed_from_u64(3);
+    let counting = Arc::new(CountingChangesets::new_sqlite()?);
+    let present = random_cs_ids(&mut rng, 10);
+    add_stack(ctx, counting.as_ref(), None, &present).await?;
+
+    let first = FilteredChangesets::new(counting.clone(), true).with_bits_per_changeset(64);
+    let second = FilteredChangesets::new(counting.clone(), true).with_bits_per_changeset(64);
+    first.build_filter(ctx).await?;
+    second.build_filter(ctx).await?;
+
+    // Each writer finds the changesets added through the other one.
+    let added = random_cs_ids(&mut rng, 4);
+    add_stack(ctx, &first, present.last().copied(), &added[..2]).await?;
+    add_stack(ctx, &second, Some(added[1]), &added[2..]).await?;
+    for filtered in [&first, &second] {
+        for cs_id in &added {
+            assert!(filtered.exists(ctx, *cs_id).await?);
+        }
+        let mut found = filtered.get_many(ctx, added.clone()).await?;
+        found.sort_by_key(|entry| entry.gen);
+        assert_eq!(
+            found.iter().map(|entry| entry.cs_id).collect::<Vec<_>>(),
+            added
+        );
+    }
+
+    // Absent changesets are still rejected without reading the storage.
+    let absent = random_cs_ids(&mut rng, 100);
+    let reads = counting.reads();
+    assert_eq!(first.get_many(ctx, absent.clone()).await?, vec![]);
+    assert_eq!(second.get_many(ctx, absent).await?, vec![]);
+    assert_eq!(counting.reads(), reads);
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_filtered_background_build(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let ctx = &ctx;
+    let mut rng = SmallRng::seed_from_u64(2);
+    let counting = Arc::new(CountingChangesets::new_sqlite()?);
+    let present = random_cs_ids(&mut rng, 10);
+    add_stack(ctx, counting.as_ref(), None, &present).await?;
+    let absent = random_cs_ids(&mut rng, 1)[0];
+
+    // Disabled filters are never built.
+    let filtered = FilteredChangesets::new(counting.clone(), false);
+    assert!(!filtered.exists(ctx, absent).await?);
+    filtered.build_filter(ctx).await?;
+    assert_eq!(filtered.filter_status(), FilterStatus::Disabled);
+    assert_eq!(counting.reads(), 1);
+
+    // The first probe is answered by the storage while the filter is built
+    // in the background.
+    let filtered = FilteredChangesets::new(counting.clone(), true).with_bits_per_changeset(64);
+    assert!(!filtered.exists(ctx, absent).await?);
+    assert_eq!(counting.reads(), 2);
+    while filtered.filter_status() != (FilterStatus::Complete { enumerated: 10 }) {
+        assert_matches!(filtered.filter_status(), FilterStatus::Building { .. });
+        tokio::task::yield_now().await;
+    }
+    assert!(!filtered.exists(ctx, absent).await?);
+    assert!(filtered.exists(ctx, present[0]).await?);
+    assert_eq!(counting.reads(), 3);
+
+    Ok(())
+}
+
+/// Records the changesets it is notified of.
+#[derive(Default)]
+struct RecordingSubscriber {
+    added: Mutex<Vec<ChangesetEntry>>,
+}
+
+impl ChangesetSubscriber for RecordingSubscriber {
+    fn notify_added(&self, _ctx: &CoreContext, entries: &[ChangesetEntry]) {
+        self.added.lock().extend_from_slice(entries);
+    }
+}
+
+#[fbinit::test]
+async fn test_notifying(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let ctx = &ctx;
+    let first = Arc::new(RecordingSubscriber::default());
+    let second = Arc::new(RecordingSubscriber::default());
+    let changesets = NotifyingChangesets::new(Arc::new(InMemoryChangesets::new(REPO_ZERO)))
+        .with_subscriber(first.clone())
+        .with_subscriber(second.clone());
+
+    let entry = |cs_id, parents: Vec<ChangesetId>, gen| ChangesetEntry {
+        repo_id: REPO_ZERO,
+        cs_id,
+        parents,
+        gen,
+        extra: None,
+    };
+    let insert = |entry: &ChangesetEntry| ChangesetInsert {
+        cs_id: entry.cs_id,
+        parents: entry.parents.clone(),
+        extra: entry.extra.clone(),
+    };
+    let root = entry(ONES_CSID, vec![], 1);
+    let child = ChangesetEntry {
+        extra: Some(Bytes::from_static(b"import_batch=42")),
+        ..entry(TWOS_CSID, vec![ONES_CSID], 2)
+    };
+    let merge = entry(THREES_CSID, vec![TWOS_CSID, ONES_CSID], 3);
+    let many = entry(FOURS_CSID, vec![THREES_CSID], 4);
+
+    assert!(changesets.add(ctx, insert(&root)).await?);
+    assert!(changesets.add(ctx, insert(&child)).await?);
+    assert!(changesets.add(ctx, insert(&merge)).await?);
+    // Duplicates are not notified.
+    assert!(!changesets.add(ctx, insert(&root)).await?);
+    changesets
+        .add_many(ctx, Vec1::new((insert(&many), Generation::new(many.gen))))
+        .await?;
+    // Nor are failed adds.
+    assert_matches!(
+        changesets
+            .add(ctx, insert(&entry(FIVES_CSID, vec![SIXES_CSID], 1)))
+            .await,
+        Err(_)
+    );
+
+    let expected = vec![root, child, merge, many];
+    assert_eq!(*first.added.lock(), expected);
+    assert_eq!(*second.added.lock(), expected);
+    for entry in &expected {
+        assert_eq!(
+            changesets.get(ctx, entry.cs_id).await?.as_ref(),
+            Some(entry)
+        );
+    }
+
+    Ok(())
+}
@@ -9,7 +9,7 @@ include "eden/mononoke/mononoke_types/if/mononoke_types_thrift.thrift"
 
 # Memcache constants. Should be change when we want to invalidate memcache
 # entries
-const i32 MC_CODEVER = 0;
+const i32 MC_CODEVER = 1;
 const i32 MC_SITEVER = 0;
 
 typedef i32 RepoId (rust.newtype)
@@ -22,4 +22,5 @@ struct ChangesetEntry {
   2: required mononoke_types_thrift.ChangesetId cs_id;
   3: required list<mononoke_types_thrift.ChangesetId> parents;
   4: required GenerationNum gen;
+  5: optional mononoke_types_thrift.binary_bytes extra;
 } (rust.exhaustive)
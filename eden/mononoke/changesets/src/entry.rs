@@ -5,19 +5,21 @@
  * GNU General Public License version 2.
  */
 
-use abomonation_derive::Abomonation;
 use anyhow::Result;
 use bytes::Bytes;
 use fbthrift::compact_protocol;
 use mononoke_types::ChangesetId;
 use mononoke_types::RepositoryId;
 
-#[derive(Abomonation, Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct ChangesetEntry {
     pub repo_id: RepositoryId,
     pub cs_id: ChangesetId,
     pub parents: Vec<ChangesetId>,
     pub gen: u64,
+    /// Opaque metadata stored along with the changeset when it was added,
+    /// if any.
+    pub extra: Option<Bytes>,
 }
 
 impl ChangesetEntry {
@@ -33,6 +35,7 @@ impl ChangesetEntry {
             cs_id: ChangesetId::from_thrift(thrift_entry.cs_id)?,
             parents: parents?,
             gen: thrift_entry.gen.0 as u64,
+            extra: thrift_entry.extra,
         })
     }
 
@@ -42,21 +45,16 @@ impl ChangesetEntry {
             cs_id: self.cs_id.into_thrift(),
             parents: self.parents.into_iter().map(|p| p.into_thrift()).collect(),
             gen: changeset_entry_thrift::GenerationNum(self.gen as i64),
+            extra: self.extra,
         }
     }
 }
 
 pub fn serialize_cs_entries(cs_entries: Vec<ChangesetEntry>) -> Bytes {
-    let mut thrift_entries = vec![];
-    for entry in cs_entries {
-        let thrift_entry = changeset_entry_thrift::ChangesetEntry {
-            repo_id: changeset_entry_thrift::RepoId(entry.repo_id.id()),
-            cs_id: entry.cs_id.into_thrift(),
-            parents: entry.parents.into_iter().map(|p| p.into_thrift()).collect(),
-            gen: changeset_entry_thrift::GenerationNum(entry.gen as i64),
-        };
-        thrift_entries.push(thrift_entry);
-    }
+    let thrift_entries: Vec<_> = cs_entries
+        .into_iter()
+        .map(ChangesetEntry::into_thrift)
+        .collect();
 
     compact_protocol::serialize(&thrift_entries)
 }
@@ -64,39 +62,67 @@ pub fn serialize_cs_entries(cs_entries: Vec<ChangesetEntry>) -> Bytes {
 pub fn deserialize_cs_entries(blob: &Bytes) -> Result<Vec<ChangesetEntry>> {
     let thrift_entries: Vec<changeset_entry_thrift::ChangesetEntry> =
         compact_protocol::deserialize(blob)?;
-    let mut entries = vec![];
-    for thrift_entry in thrift_entries {
-        let parents = thrift_entry
-            .parents
-            .into_iter()
-            .map(ChangesetId::from_thrift)
-            .collect::<Result<Vec<_>>>()?;
-        let entry = ChangesetEntry {
-            repo_id: RepositoryId::new(thrift_entry.repo_id.0),
-            cs_id: ChangesetId::from_thrift(thrift_entry.cs_id)?,
-            parents,
-            gen: thrift_entry.gen.0 as u64,
-        };
-        entries.push(entry);
-    }
-    Ok(entries)
+    thrift_entries
+        .into_iter()
+        .map(ChangesetEntry::from_thrift)
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn serialize_deserialize() {
-        let entry = ChangesetEntry {
+    fn entry(extra: Option<Bytes>) -> ChangesetEntry {
+        ChangesetEntry {
             repo_id: RepositoryId::new(0),
             cs_id: mononoke_types_mocks::changesetid::ONES_CSID,
             parents: vec![mononoke_types_mocks::changesetid::TWOS_CSID],
             gen: 2,
-        };
+            extra,
+        }
+    }
+
+    #[test]
+    fn serialize_deserialize() {
+        let entry = entry(None);
 
         let res = deserialize_cs_entries(&serialize_cs_entries(vec![entry.clone(), entry.clone()]))
             .unwrap();
         assert_eq!(vec![entry.clone(), entry], res);
     }
+
+    #[test]
+    fn serialize_deserialize_extra() {
+        let entries = vec![
+            entry(Some(Bytes::from_static(b"import_batch=42"))),
+            entry(Some(Bytes::new())),
+            entry(None),
+        ];
+
+        let res = deserialize_cs_entries(&serialize_cs_entries(entries.clone())).unwrap();
+        assert_eq!(entries, res);
+    }
+
+    #[test]
+    fn deserialize_legacy_entries() {
+        // A list with a single entry, serialized with compact protocol before
+        // the extra field existed.
+        let mut blob = vec![0x1c];
+        // 1: repo_id
+        blob.extend([0x15, 0x00]);
+        // 2: cs_id, a union with a 32 byte blake2 hash
+        blob.extend([0x1c, 0x18, 0x20]);
+        blob.extend([0x11; 32]);
+        blob.push(0x00);
+        // 3: parents, a list with one changeset id
+        blob.extend([0x19, 0x1c, 0x18, 0x20]);
+        blob.extend([0x22; 32]);
+        blob.push(0x00);
+        // 4: gen
+        blob.extend([0x16, 0x04]);
+        blob.push(0x00);
+
+        let res = deserialize_cs_entries(&Bytes::from(blob)).unwrap();
+        assert_eq!(vec![entry(None)], res);
+    }
 }
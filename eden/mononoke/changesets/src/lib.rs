@@ -5,12 +5,18 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::HashMap;
+use std::collections::HashSet;
+
 use anyhow::Error;
 use anyhow::Result;
 use async_trait::async_trait;
 use auto_impl::auto_impl;
+use bytes::Bytes;
 use context::CoreContext;
 use futures::stream::BoxStream;
+use futures::stream::StreamExt;
+use futures::stream::TryStreamExt;
 use mononoke_types::ChangesetId;
 use mononoke_types::ChangesetIdPrefix;
 use mononoke_types::ChangesetIdsResolvedFromPrefix;
@@ -19,15 +25,35 @@ use mononoke_types::RepositoryId;
 use vec1::Vec1;
 
 mod entry;
+mod prefix;
 
 pub use crate::entry::deserialize_cs_entries;
 pub use crate::entry::serialize_cs_entries;
 pub use crate::entry::ChangesetEntry;
+pub use crate::prefix::HgPrefixResolver;
+pub use crate::prefix::PrefixResolution;
+pub use crate::prefix::PrefixResolver;
+pub use crate::prefix::PrefixScheme;
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct ChangesetInsert {
     pub cs_id: ChangesetId,
     pub parents: Vec<ChangesetId>,
+    /// Opaque metadata to store along with the changeset, in the same
+    /// transaction. Returned as `ChangesetEntry::extra`. If the changeset
+    /// was already added, the metadata it was added with is kept.
+    pub extra: Option<Bytes>,
+}
+
+/// A changeset listed by `Changesets::list_enumeration_range_detailed`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct EnumerationEntry {
+    pub cs_id: ChangesetId,
+    /// The unique integer id of the changeset used for enumeration.
+    pub unique_id: u64,
+    pub gen: u64,
+    /// The parents of the changeset, in order, if they were requested.
+    pub parents: Option<Vec<ChangesetId>>,
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -36,6 +62,17 @@ pub enum SortOrder {
     Descending,
 }
 
+/// Receives the changesets added to a `Changesets`, see
+/// `changesets_impl::NotifyingChangesets`.
+pub trait ChangesetSubscriber: Send + Sync {
+    /// Called after `entries` were added, in the order they were added.
+    ///
+    /// This is called synchronously on the path of the add, so it must not
+    /// block. Subscribers with heavy work to do should queue it, e.g. on a
+    /// channel, and do it elsewhere.
+    fn notify_added(&self, ctx: &CoreContext, entries: &[ChangesetEntry]);
+}
+
 /// Interface to storage of changesets that have been completely stored in Mononoke.
 #[facet::facet]
 #[async_trait]
@@ -68,13 +105,41 @@ pub trait Changesets: Send + Sync {
         Ok(self.get(ctx, cs_id).await?.is_some())
     }
 
-    /// Retrieve the rows for all the commits if available
+    /// Retrieve the rows for all the commits if available.
+    ///
+    /// Rows are returned in no particular order, once per commit even if its
+    /// id is repeated in `cs_ids`. Commits that are not stored are skipped.
+    /// Use `get_many_ordered` to match rows with the requested ids.
     async fn get_many(
         &self,
         ctx: &CoreContext,
         cs_ids: Vec<ChangesetId>,
     ) -> Result<Vec<ChangesetEntry>, Error>;
 
+    /// Retrieve the rows for `cs_ids`, aligned with them: the row at each
+    /// index is the one of the id at the same index in `cs_ids`, or `None`
+    /// if that commit is not stored. Ids repeated in `cs_ids` get the same
+    /// row at each of their indexes.
+    ///
+    /// Each commit is fetched once, with a single `get_many`.
+    async fn get_many_ordered(
+        &self,
+        ctx: &CoreContext,
+        cs_ids: &[ChangesetId],
+    ) -> Result<Vec<Option<ChangesetEntry>>, Error> {
+        let unique: HashSet<_> = cs_ids.iter().copied().collect();
+        let entries: HashMap<_, _> = self
+            .get_many(ctx, unique.into_iter().collect())
+            .await?
+            .into_iter()
+            .map(|entry| (entry.cs_id, entry))
+            .collect();
+        Ok(cs_ids
+            .iter()
+            .map(|cs_id| entries.get(cs_id).cloned())
+            .collect())
+    }
+
     /// Retrieve the rows for all the commits with the given prefix up to the given limit
     async fn get_many_by_prefix(
         &self,
@@ -128,5 +193,29 @@ pub trait Changesets: Send + Sync {
         max_id: u64,
         sort_and_limit: Option<(SortOrder, u64)>,
         read_from_master: bool,
-    ) -> BoxStream<'_, Result<(ChangesetId, u64), Error>>;
+    ) -> BoxStream<'_, Result<(ChangesetId, u64), Error>> {
+        self.list_enumeration_range_detailed(
+            ctx,
+            min_id,
+            max_id,
+            sort_and_limit,
+            read_from_master,
+            false,
+        )
+        .map_ok(|entry| (entry.cs_id, entry.unique_id))
+        .boxed()
+    }
+
+    /// Like `list_enumeration_range`, but also returns the generation of
+    /// the changesets and, if `include_parents` is true, their parents, so
+    /// that the graph can be built without calling `get_many`.
+    fn list_enumeration_range_detailed(
+        &self,
+        ctx: &CoreContext,
+        min_id: u64,
+        max_id: u64,
+        sort_and_limit: Option<(SortOrder, u64)>,
+        read_from_master: bool,
+        include_parents: bool,
+    ) -> BoxStream<'_, Result<EnumerationEntry, Error>>;
 }
@@ -0,0 +1,159 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::Context;
+use anyhow::Result;
+use async_trait::async_trait;
+use context::CoreContext;
+use mononoke_types::ChangesetIdPrefix;
+use mononoke_types::ChangesetIdsResolvedFromPrefix;
+
+use crate::ArcChangesets;
+
+/// Length of a full hg changeset id, in hex digits.
+const HG_HASH_LENGTH_HEX: usize = 40;
+
+/// Resolves prefixes of hg changeset ids to bonsai changesets, usually
+/// through the bonsai-hg mapping, which this crate can't depend on.
+#[async_trait]
+pub trait HgPrefixResolver: Send + Sync {
+    /// Resolve `prefix`, a string of at most 40 hex digits, to the bonsai
+    /// changesets whose hg changeset id starts with it, up to `limit`.
+    async fn resolve_hg_prefix(
+        &self,
+        ctx: &CoreContext,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<ChangesetIdsResolvedFromPrefix>;
+}
+
+/// The kind of changeset id a prefix matched.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum PrefixScheme {
+    Bonsai,
+    Hg,
+}
+
+/// The result of resolving a prefix with `PrefixResolver`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PrefixResolution {
+    /// The prefix only matched changeset ids of `scheme`.
+    Matched {
+        scheme: PrefixScheme,
+        resolved: ChangesetIdsResolvedFromPrefix,
+    },
+    /// The prefix matched both bonsai and hg changeset ids, so which one
+    /// was meant is unknown.
+    AmbiguousSchemes {
+        bonsai: ChangesetIdsResolvedFromPrefix,
+        hg: ChangesetIdsResolvedFromPrefix,
+    },
+    /// The prefix matched no changeset id.
+    NoMatch,
+}
+
+impl PrefixResolution {
+    /// The scheme of the matched changesets, if there was a single one.
+    pub fn scheme(&self) -> Option<PrefixScheme> {
+        match self {
+            Self::Matched { scheme, .. } => Some(*scheme),
+            Self::AmbiguousSchemes { .. } | Self::NoMatch => None,
+        }
+    }
+
+    /// The changesets matched by any scheme, up to `limit`.
+    pub fn into_resolved(self, limit: usize) -> ChangesetIdsResolvedFromPrefix {
+        match self {
+            Self::Matched { resolved, .. } => resolved,
+            Self::AmbiguousSchemes { bonsai, hg } => {
+                let too_many = matches!(bonsai, ChangesetIdsResolvedFromPrefix::TooMany(_))
+                    || matches!(hg, ChangesetIdsResolvedFromPrefix::TooMany(_));
+                let mut cs_ids = bonsai.to_vec();
+                for cs_id in hg.to_vec() {
+                    if !cs_ids.contains(&cs_id) {
+                        cs_ids.push(cs_id);
+                    }
+                }
+                if too_many {
+                    cs_ids.truncate(limit);
+                    ChangesetIdsResolvedFromPrefix::TooMany(cs_ids)
+                } else {
+                    ChangesetIdsResolvedFromPrefix::from_vec_and_limit(cs_ids, limit)
+                }
+            }
+            Self::NoMatch => ChangesetIdsResolvedFromPrefix::NoMatch,
+        }
+    }
+}
+
+/// Resolves hex prefixes of changeset ids, which are either bonsai ids or,
+/// if an `HgPrefixResolver` is given, hg ids.
+#[derive(Clone)]
+pub struct PrefixResolver {
+    changesets: ArcChangesets,
+    hg_resolver: Option<Arc<dyn HgPrefixResolver>>,
+}
+
+impl PrefixResolver {
+    pub fn new(changesets: ArcChangesets) -> Self {
+        Self {
+            changesets,
+            hg_resolver: None,
+        }
+    }
+
+    /// Also resolve prefixes of hg changeset ids with `hg_resolver`.
+    pub fn with_hg_resolver(mut self, hg_resolver: Arc<dyn HgPrefixResolver>) -> Self {
+        self.hg_resolver = Some(hg_resolver);
+        self
+    }
+
+    /// Resolve the hex `prefix` to changesets, up to `limit` per scheme.
+    ///
+    /// Bonsai changesets are looked up first with
+    /// `Changesets::get_many_by_prefix`. Prefixes short enough to be part of
+    /// an hg changeset id are then looked up with the hg resolver, even if
+    /// bonsai changesets matched, so that a prefix matching both is reported
+    /// as `AmbiguousSchemes` rather than silently resolved to one of them.
+    pub async fn resolve_prefix(
+        &self,
+        ctx: &CoreContext,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<PrefixResolution> {
+        let bonsai_prefix = ChangesetIdPrefix::from_str(prefix)
+            .with_context(|| format!("Invalid changeset id prefix: {}", prefix))?;
+        let bonsai = self
+            .changesets
+            .get_many_by_prefix(ctx, bonsai_prefix, limit)
+            .await?;
+
+        let hg = match &self.hg_resolver {
+            Some(hg_resolver) if prefix.len() <= HG_HASH_LENGTH_HEX => {
+                hg_resolver.resolve_hg_prefix(ctx, prefix, limit).await?
+            }
+            _ => ChangesetIdsResolvedFromPrefix::NoMatch,
+        };
+
+        use ChangesetIdsResolvedFromPrefix::NoMatch;
+        Ok(match (bonsai, hg) {
+            (NoMatch, NoMatch) => PrefixResolution::NoMatch,
+            (resolved, NoMatch) => PrefixResolution::Matched {
+                scheme: PrefixScheme::Bonsai,
+                resolved,
+            },
+            (NoMatch, resolved) => PrefixResolution::Matched {
+                scheme: PrefixScheme::Hg,
+                resolved,
+            },
+            (bonsai, hg) => PrefixResolution::AmbiguousSchemes { bonsai, hg },
+        })
+    }
+}
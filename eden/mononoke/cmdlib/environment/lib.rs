@@ -64,6 +64,10 @@ pub struct MononokeEnvironment {
     pub mysql_options: MysqlOptions,
     pub blobstore_options: BlobstoreOptions,
     pub readonly_storage: ReadOnlyStorage,
+    /// Force every repo built from this environment into read-only mode.
+    /// Implies `readonly_storage`, and additionally makes repo factories
+    /// reject writes to repo metadata (bookmarks, changesets, mappings...).
+    pub force_readonly: bool,
     pub rendezvous_options: RendezVousOptions,
     pub megarepo_configs_options: MononokeMegarepoConfigsOptions,
     pub remote_derivation_options: RemoteDerivationOptions,
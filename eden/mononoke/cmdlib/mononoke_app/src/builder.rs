@@ -342,6 +342,7 @@ impl MononokeAppBuilder {
         .context("Failed to parse blobstore options")?;
 
         let readonly_storage = ReadOnlyStorage::from_args(&readonly_storage_args);
+        let force_readonly = readonly_storage_args.force_readonly;
 
         let rendezvous_options = rendezvous_args.into();
 
@@ -374,6 +375,7 @@ impl MononokeAppBuilder {
             mysql_options,
             blobstore_options,
             readonly_storage,
+            force_readonly,
             acl_provider,
             rendezvous_options,
             megarepo_configs_options,
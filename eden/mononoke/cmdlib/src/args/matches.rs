@@ -231,6 +231,7 @@ impl<'a> MononokeMatches<'a> {
                     mysql_options,
                     blobstore_options,
                     readonly_storage,
+                    force_readonly: false,
                     acl_provider,
                     rendezvous_options,
                     megarepo_configs_options,
@@ -38,6 +38,7 @@ use cross_repo_sync::validation::BookmarkDiff;
 use cross_repo_sync::CommitSyncContext;
 use cross_repo_sync::CommitSyncRepos;
 use cross_repo_sync::CommitSyncer;
+use cross_repo_sync::ParentOverrideStrictness;
 use cross_repo_sync::PluralCommitSyncOutcome;
 use cross_repo_sync::Syncers;
 use cross_repo_sync::CHANGE_XREPO_MAPPING_EXTRA;
@@ -432,6 +433,7 @@ async fn run_pushredirection_subcommand<'a>(
                     Some(hashmap! {
                       large_bookmark_value.0.clone() => small_bookmark_value.0.clone(),
                     }),
+                    ParentOverrideStrictness::Strict,
                     &mapping_version,
                     CommitSyncContext::AdminChangeMapping,
                 )
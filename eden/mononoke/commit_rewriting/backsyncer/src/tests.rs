@@ -38,6 +38,7 @@ use cross_repo_sync::CommitSyncDataProvider;
 use cross_repo_sync::CommitSyncOutcome;
 use cross_repo_sync::CommitSyncRepos;
 use cross_repo_sync::CommitSyncer;
+use cross_repo_sync::ParentOverrideStrictness;
 use cross_repo_sync::CHANGE_XREPO_MAPPING_EXTRA;
 use cross_repo_sync_test_utils::TestRepo;
 use fbinit::FacebookInit;
@@ -768,6 +769,7 @@ async fn backsync_change_mapping(fb: FacebookInit) -> Result<(), Error> {
             &ctx,
             root_cs_id,
             None,
+            ParentOverrideStrictness::Strict,
             &current_version,
             CommitSyncContext::Tests,
         )
@@ -164,6 +164,31 @@ impl CommitSyncDataProvider {
         }
     }
 
+    pub async fn get_all_versions(
+        &self,
+        repo_id: RepositoryId,
+    ) -> Result<Vec<CommitSyncConfigVersion>, Error> {
+        match self {
+            Self::Live(live_commit_sync_config) => {
+                let versions = live_commit_sync_config
+                    .get_all_commit_sync_config_versions(repo_id)
+                    .await?;
+                Ok(versions.into_keys().collect())
+            }
+        }
+    }
+
+    pub fn get_current_version(
+        &self,
+        repo_id: RepositoryId,
+    ) -> Result<Option<CommitSyncConfigVersion>, Error> {
+        match self {
+            Self::Live(live_commit_sync_config) => {
+                live_commit_sync_config.get_current_commit_sync_config_version(repo_id)
+            }
+        }
+    }
+
     pub async fn get_common_pushrebase_bookmarks(
         &self,
         repo_id: RepositoryId,
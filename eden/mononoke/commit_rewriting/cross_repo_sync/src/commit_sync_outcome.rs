@@ -5,6 +5,7 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::HashMap;
 use std::fmt;
 use std::pin::Pin;
 
@@ -13,13 +14,17 @@ use anyhow::Error;
 use bookmarks::BookmarkKey;
 use context::CoreContext;
 use futures::future::try_join_all;
+use futures::stream;
 use futures::Future;
+use futures::StreamExt;
+use futures::TryStreamExt;
 use metaconfig_types::CommitSyncConfigVersion;
 use metaconfig_types::CommitSyncDirection;
 use mononoke_types::ChangesetId;
 use mononoke_types::RepositoryId;
 use slog::debug;
 use synced_commit_mapping::SyncedCommitMapping;
+use synced_commit_mapping::SyncedCommitSourceRepo;
 use synced_commit_mapping::WorkingCopyEquivalence;
 
 use crate::commit_sync_data_provider::CommitSyncDataProvider;
@@ -40,6 +45,48 @@ pub enum CommitSyncOutcome {
     EquivalentWorkingCopyAncestor(ChangesetId, CommitSyncConfigVersion),
 }
 
+/// The result of `CommitSyncer::sync_commit_detailed`, which unlike
+/// `CommitSyncOutcome` also tells whether the target commit was created
+/// by the sync call
+#[derive(Clone, Debug, PartialEq)]
+pub enum DetailedSyncOutcome {
+    /// The commit was rewritten and uploaded to the target repo by this call
+    CreatedTarget(ChangesetId, CommitSyncConfigVersion),
+    /// The commit had already been synced, either earlier or by a concurrent
+    /// sync, and this is the existing target commit
+    AlreadySynced(ChangesetId, CommitSyncConfigVersion),
+    /// The commit was rewritten to nothing, and `wc_equivalent` is the target
+    /// commit with the same working copy. Commits that rewrite to nothing and
+    /// have no working copy equivalent at all are reported as `NotSyncCandidate`
+    RewrittenToNothing {
+        wc_equivalent: ChangesetId,
+        version: CommitSyncConfigVersion,
+    },
+    /// Not suitable for syncing to this repo
+    NotSyncCandidate(CommitSyncConfigVersion),
+}
+
+impl DetailedSyncOutcome {
+    /// The target commit, as returned by `CommitSyncer::sync_commit`
+    pub fn target_cs_id(&self) -> Option<ChangesetId> {
+        match self {
+            Self::CreatedTarget(cs_id, _) | Self::AlreadySynced(cs_id, _) => Some(*cs_id),
+            Self::RewrittenToNothing { wc_equivalent, .. } => Some(*wc_equivalent),
+            Self::NotSyncCandidate(_) => None,
+        }
+    }
+
+    /// Name of the outcome, used for logging
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::CreatedTarget(..) => "created_target",
+            Self::AlreadySynced(..) => "already_synced",
+            Self::RewrittenToNothing { .. } => "rewritten_to_nothing",
+            Self::NotSyncCandidate(_) => "not_sync_candidate",
+        }
+    }
+}
+
 /// The state of a source repo commit in a target repo, which
 /// allows for multiple `RewrittenAs` options
 #[derive(Debug, PartialEq)]
@@ -52,6 +99,17 @@ pub enum PluralCommitSyncOutcome {
     EquivalentWorkingCopyAncestor(ChangesetId, CommitSyncConfigVersion),
 }
 
+impl PluralCommitSyncOutcome {
+    /// Name of the outcome, used for logging
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::NotSyncCandidate(_) => "not_sync_candidate",
+            Self::RewrittenAs(_) => "rewritten_as",
+            Self::EquivalentWorkingCopyAncestor(..) => "equivalent_working_copy_ancestor",
+        }
+    }
+}
+
 /// A hint to the synced commit selection algorithm
 /// See the docstring for `get_plural_commit_sync_outcome`
 /// for why this is needed.
@@ -193,24 +251,126 @@ pub async fn get_plural_commit_sync_outcome<'a, M: SyncedCommitMapping>(
         .get(ctx, source_repo_id.0, source_cs_id.0, target_repo_id.0)
         .await?;
     if !remapped.is_empty() {
-        let remapped: Result<Vec<_>, Error> = remapped.into_iter()
-            .map(|(cs_id, maybe_version, _maybe_source_repo)| {
-                let version = maybe_version.ok_or_else(||
-                    anyhow!(
-                        "no sync commit version specified for remapping of {} -> {} (source repo {}, target repo {})",
-                        source_cs_id.0, cs_id,
-                        source_repo_id,
-                        target_repo_id,
-                    )
-                )?;
+        return Ok(Some(rewritten_as_outcome(
+            source_repo_id,
+            target_repo_id,
+            source_cs_id,
+            remapped,
+        )?));
+    }
 
-                Ok((cs_id, version))
-            })
-            .collect();
-        let remapped = remapped?;
-        return Ok(Some(PluralCommitSyncOutcome::RewrittenAs(remapped)));
+    get_unmapped_plural_commit_sync_outcome(
+        ctx,
+        source_repo_id,
+        target_repo_id,
+        source_cs_id,
+        mapping,
+        direction,
+        commit_sync_data_provider,
+    )
+    .await
+}
+
+/// Same as `get_plural_commit_sync_outcome`, but for many commits at once.
+/// The mapping entries of all the commits are fetched in one batch, so this
+/// is much cheaper than looking up the commits one by one. Every commit of
+/// `source_cs_ids` is present in the result.
+pub async fn get_plural_commit_sync_outcomes<'a, M: SyncedCommitMapping>(
+    ctx: &'a CoreContext,
+    source_repo_id: Source<RepositoryId>,
+    target_repo_id: Target<RepositoryId>,
+    source_cs_ids: &[ChangesetId],
+    mapping: &'a M,
+    direction: CommitSyncDirection,
+    commit_sync_data_provider: &CommitSyncDataProvider,
+) -> Result<HashMap<ChangesetId, Option<PluralCommitSyncOutcome>>, Error> {
+    let mut remapped = mapping
+        .get_many(ctx, source_repo_id.0, source_cs_ids, target_repo_id.0)
+        .await?;
+
+    let mut outcomes = HashMap::new();
+    let mut unmapped = vec![];
+    for source_cs_id in source_cs_ids {
+        match remapped.remove(source_cs_id) {
+            Some(remapped) => {
+                let outcome = rewritten_as_outcome(
+                    source_repo_id,
+                    target_repo_id,
+                    Source(*source_cs_id),
+                    remapped,
+                )?;
+                outcomes.insert(*source_cs_id, Some(outcome));
+            }
+            None => unmapped.push(*source_cs_id),
+        }
     }
 
+    // Commits that weren't rewritten are rare, so their working copy
+    // equivalences are looked up one by one
+    let unmapped_outcomes: Vec<_> = stream::iter(unmapped)
+        .map(|source_cs_id| async move {
+            let outcome = get_unmapped_plural_commit_sync_outcome(
+                ctx,
+                source_repo_id,
+                target_repo_id,
+                Source(source_cs_id),
+                mapping,
+                direction,
+                commit_sync_data_provider,
+            )
+            .await?;
+            Ok::<_, Error>((source_cs_id, outcome))
+        })
+        .buffered(UNMAPPED_OUTCOMES_CONCURRENCY)
+        .try_collect()
+        .await?;
+    outcomes.extend(unmapped_outcomes);
+
+    Ok(outcomes)
+}
+
+/// Number of commits without mapping entries whose outcomes
+/// `get_plural_commit_sync_outcomes` looks up concurrently
+const UNMAPPED_OUTCOMES_CONCURRENCY: usize = 100;
+
+fn rewritten_as_outcome(
+    source_repo_id: Source<RepositoryId>,
+    target_repo_id: Target<RepositoryId>,
+    source_cs_id: Source<ChangesetId>,
+    remapped: Vec<(
+        ChangesetId,
+        Option<CommitSyncConfigVersion>,
+        Option<SyncedCommitSourceRepo>,
+    )>,
+) -> Result<PluralCommitSyncOutcome, Error> {
+    let remapped: Result<Vec<_>, Error> = remapped.into_iter()
+        .map(|(cs_id, maybe_version, _maybe_source_repo)| {
+            let version = maybe_version.ok_or_else(||
+                anyhow!(
+                    "no sync commit version specified for remapping of {} -> {} (source repo {}, target repo {})",
+                    source_cs_id.0, cs_id,
+                    source_repo_id,
+                    target_repo_id,
+                )
+            )?;
+
+            Ok((cs_id, version))
+        })
+        .collect();
+    Ok(PluralCommitSyncOutcome::RewrittenAs(remapped?))
+}
+
+/// The outcome of a commit that has no mapping entries, i.e. that wasn't
+/// rewritten into the target repo
+async fn get_unmapped_plural_commit_sync_outcome<'a, M: SyncedCommitMapping>(
+    ctx: &'a CoreContext,
+    source_repo_id: Source<RepositoryId>,
+    target_repo_id: Target<RepositoryId>,
+    source_cs_id: Source<ChangesetId>,
+    mapping: &'a M,
+    direction: CommitSyncDirection,
+    commit_sync_data_provider: &CommitSyncDataProvider,
+) -> Result<Option<PluralCommitSyncOutcome>, Error> {
     let maybe_wc_equivalence = mapping
         .get_equivalent_working_copy(ctx, source_repo_id.0, source_cs_id.0, target_repo_id.0)
         .await?;
@@ -366,6 +526,58 @@ pub async fn get_commit_sync_outcome_with_hint<'a, M: SyncedCommitMapping, R: Re
     Ok(maybe_commit_sync_outcome)
 }
 
+/// Same as `get_commit_sync_outcome_with_hint`, but for many commits at once,
+/// see `get_plural_commit_sync_outcomes`. The `hint` is resolved once, and
+/// applied to every commit that was rewritten as multiple commits.
+pub async fn get_commit_sync_outcomes_with_hint<'a, M: SyncedCommitMapping, R: Repo>(
+    ctx: &'a CoreContext,
+    source_repo_id: Source<RepositoryId>,
+    target_repo_id: Target<RepositoryId>,
+    source_cs_ids: &[ChangesetId],
+    mapping: &'a M,
+    hint: CandidateSelectionHint<R>,
+    direction: CommitSyncDirection,
+    commit_sync_data_provider: &CommitSyncDataProvider,
+) -> Result<HashMap<ChangesetId, Option<CommitSyncOutcome>>, Error> {
+    let plural_outcomes = get_plural_commit_sync_outcomes(
+        ctx,
+        source_repo_id,
+        target_repo_id,
+        source_cs_ids,
+        mapping,
+        direction,
+        commit_sync_data_provider,
+    )
+    .await?;
+    let desired_relationship = hint.try_into_desired_relationship(ctx).await?;
+
+    let mut outcomes = HashMap::new();
+    for (source_cs_id, maybe_plural_outcome) in plural_outcomes {
+        let maybe_outcome = match maybe_plural_outcome {
+            Some(plural_outcome) => Some(match &desired_relationship {
+                None => {
+                    plural_outcome
+                        .try_into_commit_sync_outcome(Source(source_cs_id))
+                        .await?
+                }
+                Some(desired_relationship) => {
+                    plural_outcome
+                        .try_into_commit_sync_outcome_with_desired_relationship(
+                            ctx,
+                            Source(source_cs_id),
+                            target_repo_id,
+                            desired_relationship.clone(),
+                        )
+                        .await?
+                }
+            }),
+            None => None,
+        };
+        outcomes.insert(source_cs_id, maybe_outcome);
+    }
+    Ok(outcomes)
+}
+
 trait SelectedCandidateFuture =
     Future<Output = Result<(ChangesetId, CommitSyncConfigVersion), Error>>;
 
@@ -408,6 +620,7 @@ fn get_only_item_selector<'a>(
 /// This struct is a simplified version of `CandidateSelectionHint`:
 /// - it does not deal with bookmarks
 /// - it deos not deal with the expectation of having only one candidate in the list
+#[derive(Clone)]
 enum DesiredRelationship<R: Repo> {
     /// Changeset should be an ancestor of this variant's payload
     /// Note: in this case any changeset is an ancestor of itself
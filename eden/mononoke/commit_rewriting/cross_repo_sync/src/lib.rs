@@ -9,11 +9,13 @@
 #![feature(trait_alias)]
 #![feature(never_type)]
 
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::fmt;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::time::Duration;
 use std::time::Instant;
 
@@ -25,13 +27,18 @@ use blobstore::Loadable;
 use bonsai_hg_mapping::BonsaiHgMapping;
 use bonsai_hg_mapping::BonsaiHgMappingRef;
 use bookmark_renaming::BookmarkRenamer;
+use bookmarks::BookmarkCategory;
 use bookmarks::BookmarkKey;
+use bookmarks::BookmarkKind;
+use bookmarks::BookmarkPagination;
+use bookmarks::BookmarkPrefix;
 use bookmarks::BookmarkUpdateLog;
 use bookmarks::BookmarkUpdateLogArc;
 use bookmarks::BookmarkUpdateLogRef;
 use bookmarks::Bookmarks;
 use bookmarks::BookmarksArc;
 use bookmarks::BookmarksRef;
+use bookmarks::Freshness;
 use cacheblob::InProcessLease;
 use cacheblob::LeaseOps;
 use cacheblob::MemcacheOps;
@@ -47,8 +54,14 @@ use commit_transformation::rewrite_commit as multi_mover_rewrite_commit;
 use commit_transformation::upload_commits;
 pub use commit_transformation::CommitRewrittenToEmpty;
 pub use commit_transformation::EmptyCommitFromLargeRepo;
+pub use commit_transformation::ExtrasPolicy;
 use commit_transformation::MultiMover;
+pub use commit_transformation::PathCollisionResolution;
 pub use commit_transformation::RewriteOpts;
+pub use commit_transformation::TargetPathLimits;
+pub use commit_transformation::TargetPathPolicy;
+pub use commit_transformation::TargetPathViolation;
+pub use commit_transformation::SYNCED_FROM_EXTRA;
 use context::CoreContext;
 use derived_data::BonsaiDerived;
 use environment::Caching;
@@ -60,6 +73,7 @@ use futures::future;
 use futures::future::try_join;
 use futures::future::TryFutureExt;
 use futures::stream;
+use futures::stream::FuturesUnordered;
 use futures::stream::StreamExt;
 use futures::stream::TryStreamExt;
 use futures::FutureExt;
@@ -92,11 +106,17 @@ use repo_derived_data::RepoDerivedData;
 use repo_derived_data::RepoDerivedDataRef;
 use repo_identity::RepoIdentity;
 use repo_identity::RepoIdentityRef;
+use reporting::log_detailed_rewrite;
 use reporting::log_rewrite;
 pub use reporting::CommitSyncContext;
-use scuba_ext::MononokeScubaSampleBuilder;
+pub use reporting::LogSyncReporter;
+pub use reporting::MarkReport;
+pub use reporting::RewriteReport;
+pub use reporting::ScubaSyncReporter;
+pub use reporting::SyncReporter;
 use slog::debug;
 use slog::info;
+use slog::warn;
 use static_assertions::assert_impl_all;
 use sync_config_version_utils::get_mapping_change_version;
 use sync_config_version_utils::get_version;
@@ -106,6 +126,7 @@ use synced_commit_mapping::EquivalentWorkingCopyEntry;
 use synced_commit_mapping::SyncedCommitMapping;
 use synced_commit_mapping::SyncedCommitMappingEntry;
 use synced_commit_mapping::SyncedCommitSourceRepo;
+use synced_commit_mapping::WorkingCopyEquivalence;
 use thiserror::Error;
 use topo_sort::sort_topological;
 use tunables::tunables;
@@ -116,9 +137,11 @@ use crate::pushrebase_hook::CrossRepoSyncPushrebaseHook;
 
 mod commit_sync_data_provider;
 pub mod commit_sync_outcome;
+pub mod message_rewrite;
 mod pushrebase_hook;
 mod reporting;
 mod sync_config_version_utils;
+mod sync_plan;
 pub mod types;
 pub mod validation;
 
@@ -127,12 +150,22 @@ pub use commit_sync_data_provider::CommitSyncDataProvider;
 pub use crate::commit_sync_outcome::commit_sync_outcome_exists;
 pub use crate::commit_sync_outcome::get_commit_sync_outcome;
 pub use crate::commit_sync_outcome::get_commit_sync_outcome_with_hint;
+pub use crate::commit_sync_outcome::get_commit_sync_outcomes_with_hint;
 pub use crate::commit_sync_outcome::get_plural_commit_sync_outcome;
+pub use crate::commit_sync_outcome::get_plural_commit_sync_outcomes;
 pub use crate::commit_sync_outcome::CandidateSelectionHint;
 pub use crate::commit_sync_outcome::CommitSyncOutcome;
+pub use crate::commit_sync_outcome::DetailedSyncOutcome;
 pub use crate::commit_sync_outcome::PluralCommitSyncOutcome;
+pub use crate::message_rewrite::MessageRewriteRules;
+pub use crate::sync_plan::SyncPlanDecision;
+pub use crate::sync_plan::SyncPlanEntry;
 
 const LEASE_WARNING_THRESHOLD: Duration = Duration::from_secs(60);
+const BOOKMARK_DIFF_PAGE_SIZE: u64 = 1000;
+/// Default number of independent ancestors synced concurrently by
+/// `sync_commit`. Kept low to limit contention on mapping writes.
+const DEFAULT_ANCESTORS_SYNC_CONCURRENCY: usize = 4;
 
 #[derive(Debug, Error)]
 pub enum ErrorKind {
@@ -158,6 +191,151 @@ pub enum ErrorKind {
     },
     #[error("X-repo sync is temporarily disabled, contact source control oncall")]
     XRepoSyncDisabled,
+    #[error(
+        "{cs_id} changes mapping to unknown version {version}, known versions: {}",
+        .known_versions.iter().map(|v| v.0.as_str()).collect::<Vec<_>>().join(", ")
+    )]
+    UnknownMappingChangeVersion {
+        cs_id: ChangesetId,
+        version: CommitSyncConfigVersion,
+        known_versions: Vec<CommitSyncConfigVersion>,
+    },
+    #[error(
+        "invalid parent overrides for {cs_id}: {}",
+        describe_invalid_parent_overrides(.missing_in_target, .not_parents)
+    )]
+    InvalidParentOverrides {
+        cs_id: ChangesetId,
+        /// Overrides `(parent, target parent)` whose target parent is not
+        /// in the target repo.
+        missing_in_target: Vec<(ChangesetId, ChangesetId)>,
+        /// Overridden commits that are not parents of `cs_id`.
+        not_parents: Vec<ChangesetId>,
+    },
+    #[error(
+        "Only large repo commits can be marked as not sync candidates, {0} is a small repo commit"
+    )]
+    MarkInSmallRepo(ChangesetId),
+    #[error("{cs_id} already has a {outcome} sync outcome, refusing to override it without force")]
+    SyncOutcomeExists {
+        cs_id: ChangesetId,
+        outcome: &'static str,
+    },
+    #[error("{0} is not marked as not a sync candidate")]
+    NotMarkedNotSyncCandidate(ChangesetId),
+    #[error(
+        "failed to sync {} of the {} commits of the batch",
+        .failed.len(),
+        .failed.len() + .synced.len()
+    )]
+    PartialBatchSync {
+        /// Outcomes of the commits of the batch that were synced, as
+        /// returned by `CommitSyncer::sync_commit_batch` on success.
+        synced: HashMap<ChangesetId, Option<ChangesetId>>,
+        /// Commits of the batch that were not synced.
+        failed: Vec<ChangesetId>,
+        #[source]
+        source: Error,
+    },
+}
+
+fn describe_invalid_parent_overrides(
+    missing_in_target: &[(ChangesetId, ChangesetId)],
+    not_parents: &[ChangesetId],
+) -> String {
+    missing_in_target
+        .iter()
+        .map(|(parent, target_parent)| {
+            format!(
+                "{} => {}: {} is not in the target repo",
+                parent, target_parent, target_parent
+            )
+        })
+        .chain(
+            not_parents
+                .iter()
+                .map(|cs_id| format!("{} is not a parent", cs_id)),
+        )
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// What `unsafe_always_rewrite_sync_commit` does with parent overrides for
+/// commits that are not parents of the synced commit. Overrides with a
+/// target parent that is not in the target repo always fail the sync.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParentOverrideStrictness {
+    /// Fail the sync.
+    Strict,
+    /// Log a warning and ignore them.
+    WarnOnExtraParents,
+}
+
+/// Measurements of the sync of a single commit, see
+/// `CommitSyncer::sync_commit_with_report` and friends
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyncReport {
+    /// The commit sync config version the commit was synced with
+    pub version: CommitSyncConfigVersion,
+    /// Time spent remapping the parents of the commit and rewriting it
+    pub rewrite_time: Duration,
+    /// Time spent uploading the rewritten commit to the target repo
+    pub upload_time: Duration,
+    /// Time spent recording the sync in the synced commit mapping
+    pub mapping_update_time: Duration,
+    /// Time spent pushrebasing the rewritten commit, for pushrebase syncs
+    pub pushrebase_time: Duration,
+    /// Number of file changes of the source commit kept by the rewrite
+    pub rewritten_file_changes: usize,
+    /// Number of file changes of the source commit the mover dropped
+    pub dropped_file_changes: usize,
+    /// Number of deletions added to the rewritten commit for files that
+    /// the source commit deletes implicitly
+    pub implicit_deletes: usize,
+    /// Number of times pushrebase was retried, for pushrebase syncs
+    pub pushrebase_retries: usize,
+}
+
+impl SyncReport {
+    fn new(version: CommitSyncConfigVersion, rewrite_time: Duration) -> Self {
+        Self {
+            version,
+            rewrite_time,
+            upload_time: Duration::ZERO,
+            mapping_update_time: Duration::ZERO,
+            pushrebase_time: Duration::ZERO,
+            rewritten_file_changes: 0,
+            dropped_file_changes: 0,
+            implicit_deletes: 0,
+            pushrebase_retries: 0,
+        }
+    }
+
+    /// Count the file changes of a source commit changing `source_paths`
+    /// that `mover` kept or dropped, and the implicit deletes added to
+    /// `rewritten`, the rewritten commit if it wasn't rewritten to nothing
+    fn count_file_changes(
+        &mut self,
+        source_paths: &[MPath],
+        rewritten: Option<&BonsaiChangesetMut>,
+        mover: &Mover,
+    ) -> Result<(), Error> {
+        let mut dropped = 0;
+        for path in source_paths {
+            if mover(path)?.is_none() {
+                dropped += 1;
+            }
+        }
+        self.dropped_file_changes = dropped;
+        self.rewritten_file_changes = source_paths.len() - dropped;
+        self.implicit_deletes = rewritten.map_or(0, |rewritten| {
+            rewritten
+                .file_changes
+                .len()
+                .saturating_sub(self.rewritten_file_changes)
+        });
+        Ok(())
+    }
 }
 
 #[must_use]
@@ -187,14 +365,35 @@ pub enum PushrebaseRewriteDates {
     No,
 }
 
+/// Differences between bookmarks of the source and the target repo, after
+/// source bookmarks were renamed with the bookmark renamer.
+/// All lists are sorted by bookmark.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BookmarkDiff {
+    /// Renamed source bookmarks that don't exist in the target repo, with
+    /// the source commit they point to.
+    pub missing_in_target: Vec<(BookmarkKey, Source<ChangesetId>)>,
+    /// Target bookmarks that no source bookmark is renamed to, i.e.
+    /// candidates for deletion. Target bookmarks that the reverse renamer
+    /// doesn't map back to the source repo are not managed by the sync and
+    /// are not listed.
+    pub only_in_target: Vec<(BookmarkKey, Target<ChangesetId>)>,
+    /// Bookmarks that exist in both repos, but the target bookmark doesn't
+    /// point to the commit the source commit was synced to.
+    pub different_values: Vec<(BookmarkKey, Source<ChangesetId>, Target<ChangesetId>)>,
+}
+
 impl CommitSyncInMemoryResult {
-    /// Write the changes to blobstores and mappings
+    /// Write the changes to blobstores and mappings, and record the time
+    /// it took in `report`
     async fn write<M: SyncedCommitMapping + Clone + 'static, R: Repo>(
         self,
         ctx: &CoreContext,
         syncer: &CommitSyncer<M, R>,
+        report: &mut SyncReport,
     ) -> Result<Option<ChangesetId>, Error> {
         use CommitSyncInMemoryResult::*;
+        let before = Instant::now();
         match self {
             NoSyncCandidate {
                 source_cs_id,
@@ -203,6 +402,7 @@ impl CommitSyncInMemoryResult {
                 syncer
                     .set_no_sync_candidate(ctx, source_cs_id, version)
                     .await?;
+                report.mapping_update_time = before.elapsed();
                 Ok(None)
             }
             WcEquivalence {
@@ -213,6 +413,7 @@ impl CommitSyncInMemoryResult {
                 syncer
                     .update_wc_equivalence_with_version(ctx, source_cs_id, remapped_id, version)
                     .await?;
+                report.mapping_update_time = before.elapsed();
                 Ok(None)
             }
             Rewritten {
@@ -220,11 +421,39 @@ impl CommitSyncInMemoryResult {
                 rewritten,
                 version,
             } => syncer
-                .upload_rewritten_and_update_mapping(ctx, source_cs_id, rewritten, version)
+                .upload_rewritten_and_update_mapping(ctx, source_cs_id, rewritten, version, report)
                 .await
                 .map(Some),
         }
     }
+
+    /// The report of the rewrite of a commit changing `source_paths` into
+    /// this result, which took `rewrite_time`
+    async fn report<M: SyncedCommitMapping + Clone + 'static, R: Repo>(
+        &self,
+        syncer: &CommitSyncer<M, R>,
+        source_paths: &[MPath],
+        rewrite_time: Duration,
+    ) -> Result<SyncReport, Error> {
+        use CommitSyncInMemoryResult::*;
+        let (version, rewritten) = match self {
+            NoSyncCandidate { version, .. } => {
+                // The version may not have a mover for the target repo at
+                // all, and none of the changes are synced anyway
+                let mut report = SyncReport::new(version.clone(), rewrite_time);
+                report.dropped_file_changes = source_paths.len();
+                return Ok(report);
+            }
+            WcEquivalence { version, .. } => (version, None),
+            Rewritten {
+                version, rewritten, ..
+            } => (version, Some(rewritten)),
+        };
+        let mover = syncer.get_mover_by_version(version).await?;
+        let mut report = SyncReport::new(version.clone(), rewrite_time);
+        report.count_file_changes(source_paths, rewritten, &mover)?;
+        Ok(report)
+    }
 }
 
 /// Create a version of `cs` with `Mover` applied to all changes
@@ -297,6 +526,64 @@ async fn remap_parents<'a, M: SyncedCommitMapping + Clone + 'static, R: Repo>(
     Ok(remapped_parents)
 }
 
+/// Check parent overrides of `cs` before it's rewritten with them. Checking
+/// them afterwards is too late: the rewritten commit is uploaded without
+/// checking its parents, and a dangling parent only breaks later, when
+/// derived data or clients walk the history. All invalid overrides are
+/// reported at once.
+async fn validate_parent_overrides<R: Repo>(
+    ctx: &CoreContext,
+    cs_id: ChangesetId,
+    cs: &BonsaiChangesetMut,
+    parents: &HashMap<ChangesetId, ChangesetId>,
+    target_repo: &R,
+    strictness: ParentOverrideStrictness,
+) -> Result<(), Error> {
+    let target_parents: Vec<ChangesetId> = parents
+        .values()
+        .copied()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let existing: HashSet<ChangesetId> = target_repo
+        .changesets()
+        .get_many(ctx, target_parents)
+        .await?
+        .into_iter()
+        .map(|entry| entry.cs_id)
+        .collect();
+
+    let mut missing_in_target: Vec<_> = parents
+        .iter()
+        .filter(|(_, target_parent)| !existing.contains(target_parent))
+        .map(|(parent, target_parent)| (*parent, *target_parent))
+        .collect();
+    missing_in_target.sort();
+    let mut not_parents: Vec<_> = parents
+        .keys()
+        .filter(|parent| !cs.parents.contains(parent))
+        .copied()
+        .collect();
+    not_parents.sort();
+
+    if !not_parents.is_empty() && strictness == ParentOverrideStrictness::WarnOnExtraParents {
+        warn!(
+            ctx.logger(),
+            "ignoring parent overrides for {:?}, which are not parents of {}", not_parents, cs_id,
+        );
+        not_parents.clear();
+    }
+    if missing_in_target.is_empty() && not_parents.is_empty() {
+        return Ok(());
+    }
+    Err(ErrorKind::InvalidParentOverrides {
+        cs_id,
+        missing_in_target,
+        not_parents,
+    }
+    .into())
+}
+
 #[derive(Clone, Default)]
 pub struct SyncedAncestorsVersions {
     // Versions of all synced ancestors
@@ -339,6 +626,21 @@ pub async fn find_toposorted_unsynced_ancestors<M, R>(
     commit_syncer: &CommitSyncer<M, R>,
     start_cs_id: ChangesetId,
 ) -> Result<(Vec<ChangesetId>, SyncedAncestorsVersions), Error>
+where
+    M: SyncedCommitMapping + Clone + 'static,
+    R: Repo,
+{
+    find_toposorted_unsynced_ancestors_of_many(ctx, commit_syncer, vec![start_cs_id]).await
+}
+
+/// Like `find_toposorted_unsynced_ancestors`, for the union of the unsynced
+/// ancestors of all of `start_cs_ids`, which are traversed once even if
+/// they are shared.
+pub async fn find_toposorted_unsynced_ancestors_of_many<M, R>(
+    ctx: &CoreContext,
+    commit_syncer: &CommitSyncer<M, R>,
+    start_cs_ids: Vec<ChangesetId>,
+) -> Result<(Vec<ChangesetId>, SyncedAncestorsVersions), Error>
 where
     M: SyncedCommitMapping + Clone + 'static,
     R: Repo,
@@ -346,11 +648,18 @@ where
     let mut synced_ancestors_versions = SyncedAncestorsVersions::default();
     let source_repo = commit_syncer.get_source_repo();
 
-    let mut visited = hashset! { start_cs_id };
+    let mut visited = HashSet::new();
     let mut q = VecDeque::new();
-    q.push_back(start_cs_id);
+    q.extend(
+        start_cs_ids
+            .iter()
+            .copied()
+            .filter(|cs_id| visited.insert(*cs_id)),
+    );
 
     let mut commits_to_backsync = HashMap::new();
+    // Mapping change versions that are known to exist.
+    let mut validated_versions = HashSet::new();
 
     let mut traversed_num = 0;
     while let Some(cs_id) = q.pop_front() {
@@ -360,7 +669,7 @@ where
                 ctx.logger(),
                 "traversed {} commits while listing unsynced ancestors, starting from {}",
                 traversed_num,
-                start_cs_id
+                describe_commits(&start_cs_ids),
             );
         }
 
@@ -397,6 +706,9 @@ where
                     try_join(maybe_mapping_change, parents).await?;
 
                 if let Some(version) = maybe_mapping_change {
+                    commit_syncer
+                        .validate_mapping_change_version(cs_id, &version, &mut validated_versions)
+                        .await?;
                     synced_ancestors_versions.versions.insert(version);
                 }
                 commits_to_backsync.insert(cs_id, parents.clone());
@@ -420,6 +732,44 @@ where
     ))
 }
 
+/// List all publishing bookmarks of `repo`, fetching at most `page_size`
+/// of them at a time.
+async fn list_publishing_bookmarks(
+    ctx: &CoreContext,
+    repo: &impl BookmarksRef,
+    page_size: u64,
+) -> Result<HashMap<BookmarkKey, ChangesetId>, Error> {
+    let mut bookmarks = HashMap::new();
+    let mut pagination = BookmarkPagination::FromStart;
+    loop {
+        let page: Vec<_> = repo
+            .bookmarks()
+            .list(
+                ctx.clone(),
+                Freshness::MaybeStale,
+                &BookmarkPrefix::empty(),
+                BookmarkCategory::ALL,
+                BookmarkKind::ALL_PUBLISHING,
+                &pagination,
+                page_size,
+            )
+            .try_collect()
+            .await?;
+        let page_len = page.len() as u64;
+        if let Some((last, _)) = page.last() {
+            pagination = BookmarkPagination::After(last.name().clone());
+        }
+        bookmarks.extend(
+            page.into_iter()
+                .map(|(bookmark, cs_id)| (bookmark.into_key(), cs_id)),
+        );
+        if page_len < page_size {
+            break;
+        }
+    }
+    Ok(bookmarks)
+}
+
 pub trait Repo = BookmarksArc
     + BookmarksRef
     + BookmarkUpdateLogArc
@@ -555,8 +905,17 @@ pub struct CommitSyncer<M, R> {
     pub mapping: M,
     pub repos: CommitSyncRepos<R>,
     pub commit_sync_data_provider: CommitSyncDataProvider,
-    pub scuba_sample: MononokeScubaSampleBuilder,
+    pub reporter: Arc<dyn SyncReporter>,
     pub x_repo_sync_lease: Arc<dyn LeaseOps>,
+    // Whether a commit with no parent may be synced with the current version
+    // when its synced ancestors don't agree on a single version.
+    allow_ambiguous_ancestor_version_fallback: bool,
+    // Policy the paths of rewritten commits must follow, if any.
+    target_path_policy: Option<Arc<dyn TargetPathPolicy>>,
+    // How messages of rewritten commits are rewritten, if at all.
+    message_rewrite_rules: Option<Arc<MessageRewriteRules>>,
+    // What is done with the extras of rewritten commits.
+    extras_policy: ExtrasPolicy,
 }
 
 impl<M, R> fmt::Debug for CommitSyncer<M, R>
@@ -602,6 +961,27 @@ where
         )
     }
 
+    /// Same as `new_with_provider`, but reports sync function calls to
+    /// `reporter` instead of logging them to scuba.
+    pub fn new_with_provider_and_reporter(
+        mapping: M,
+        repos: CommitSyncRepos<R>,
+        commit_sync_data_provider: CommitSyncDataProvider,
+        reporter: Arc<dyn SyncReporter>,
+    ) -> Self {
+        Self {
+            mapping,
+            repos,
+            commit_sync_data_provider,
+            reporter,
+            x_repo_sync_lease: Arc::new(InProcessLease::new()),
+            allow_ambiguous_ancestor_version_fallback: false,
+            target_path_policy: None,
+            message_rewrite_rules: None,
+            extras_policy: ExtrasPolicy::default(),
+        }
+    }
+
     fn new_with_provider_impl(
         ctx: &CoreContext,
         mapping: M,
@@ -609,17 +989,69 @@ where
         commit_sync_data_provider: CommitSyncDataProvider,
         x_repo_sync_lease: Arc<dyn LeaseOps>,
     ) -> Self {
-        let scuba_sample = reporting::get_scuba_sample(
+        let reporter = Arc::new(ScubaSyncReporter::new(
             ctx,
             repos.get_source_repo().repo_identity().name(),
             repos.get_target_repo().repo_identity().name(),
-        );
+        ));
         Self {
             mapping,
             repos,
             commit_sync_data_provider,
-            scuba_sample,
+            reporter,
             x_repo_sync_lease,
+            allow_ambiguous_ancestor_version_fallback: false,
+            target_path_policy: None,
+            message_rewrite_rules: None,
+            extras_policy: ExtrasPolicy::default(),
+        }
+    }
+
+    /// Allow syncing a commit with no parent with the current version of the
+    /// source repo when its already synced ancestors were synced with more
+    /// than one version. By default such commits fail to sync.
+    pub fn with_ambiguous_ancestor_version_fallback(mut self, allow: bool) -> Self {
+        self.allow_ambiguous_ancestor_version_fallback = allow;
+        self
+    }
+
+    /// Fail to sync commits rewritten to paths that `policy` doesn't allow,
+    /// with `commit_transformation::ErrorKind::TargetPathPolicyViolation`.
+    /// By default all paths are allowed.
+    pub fn with_target_path_policy(mut self, policy: Arc<dyn TargetPathPolicy>) -> Self {
+        self.target_path_policy = Some(policy);
+        self
+    }
+
+    /// Rewrite the messages of synced commits according to `rules`. By
+    /// default messages are kept as they are.
+    pub fn with_message_rewrite_rules(mut self, rules: MessageRewriteRules) -> Self {
+        self.message_rewrite_rules = Some(Arc::new(rules));
+        self
+    }
+
+    /// Strip or add extras of synced commits according to `policy`, e.g. to
+    /// keep markers like `CHANGE_XREPO_MAPPING_EXTRA` out of the target repo.
+    /// By default extras are kept as they are.
+    pub fn with_extras_policy(mut self, policy: ExtrasPolicy) -> Self {
+        self.extras_policy = policy;
+        self
+    }
+
+    fn rewrite_opts(&self) -> RewriteOpts {
+        RewriteOpts {
+            target_path_policy: self.target_path_policy.clone(),
+            extras_policy: self.extras_policy.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Apply the message rewrite rules to `rewritten`, the rewrite of
+    /// `source_cs_id`.
+    fn rewrite_message(&self, source_cs_id: ChangesetId, rewritten: &mut BonsaiChangesetMut) {
+        if let Some(rules) = &self.message_rewrite_rules {
+            let source_repo = self.get_source_repo().repo_identity().name();
+            rewritten.message = rules.rewrite(&rewritten.message, source_repo, source_cs_id);
         }
     }
 
@@ -673,6 +1105,36 @@ where
             .await
     }
 
+    /// Check that `version`, set by the mapping change extra of `cs_id`,
+    /// exists, so that a typo'd or not yet deployed version fails the sync
+    /// before anything is written. Versions in `validated` aren't checked
+    /// again, and `version` is added to it if it exists.
+    async fn validate_mapping_change_version(
+        &self,
+        cs_id: ChangesetId,
+        version: &CommitSyncConfigVersion,
+        validated: &mut HashSet<CommitSyncConfigVersion>,
+    ) -> Result<(), Error> {
+        if validated.contains(version) {
+            return Ok(());
+        }
+        if !self.version_exists(version).await? {
+            let mut known_versions = self
+                .commit_sync_data_provider
+                .get_all_versions(self.get_target_repo_id())
+                .await?;
+            known_versions.sort();
+            return Err(ErrorKind::UnknownMappingChangeVersion {
+                cs_id,
+                version: version.clone(),
+                known_versions,
+            }
+            .into());
+        }
+        validated.insert(version.clone());
+        Ok(())
+    }
+
     pub async fn get_mover_by_version(
         &self,
         version: &CommitSyncConfigVersion,
@@ -729,6 +1191,87 @@ where
         Ok(self.get_bookmark_renamer().await?(bookmark))
     }
 
+    /// Rename many bookmarks at once. Unlike calling `rename_bookmark` for
+    /// each of them, the renamer is fetched only once.
+    pub async fn rename_bookmarks(
+        &self,
+        bookmarks: Vec<BookmarkKey>,
+    ) -> Result<HashMap<BookmarkKey, Option<BookmarkKey>>, Error> {
+        let bookmark_renamer = self.get_bookmark_renamer().await?;
+        Ok(bookmarks
+            .into_iter()
+            .map(|bookmark| {
+                let renamed = bookmark_renamer(&bookmark);
+                (bookmark, renamed)
+            })
+            .collect())
+    }
+
+    /// Compare publishing bookmarks of the source and the target repo.
+    /// See `BookmarkDiff` for what is reported. Bookmarks are listed in
+    /// pages, so this works for repos with a very large number of them.
+    pub async fn bookmark_diff(&self, ctx: &CoreContext) -> Result<BookmarkDiff, Error> {
+        let (source_bookmarks, target_bookmarks) = try_join(
+            list_publishing_bookmarks(ctx, self.get_source_repo(), BOOKMARK_DIFF_PAGE_SIZE),
+            list_publishing_bookmarks(ctx, self.get_target_repo(), BOOKMARK_DIFF_PAGE_SIZE),
+        )
+        .await?;
+        let bookmark_renamer = self.get_bookmark_renamer().await?;
+        let reverse_bookmark_renamer = self.get_reverse_bookmark_renamer().await?;
+
+        let renamed_source_bookmarks: HashMap<_, _> = source_bookmarks
+            .into_iter()
+            .filter_map(|(bookmark, cs_id)| Some((bookmark_renamer(&bookmark)?, cs_id)))
+            .collect();
+
+        let mut diff = BookmarkDiff::default();
+        let mut in_both = vec![];
+        for (bookmark, source_cs_id) in &renamed_source_bookmarks {
+            match target_bookmarks.get(bookmark) {
+                Some(target_cs_id) => in_both.push((bookmark, *source_cs_id, *target_cs_id)),
+                None => diff
+                    .missing_in_target
+                    .push((bookmark.clone(), Source(*source_cs_id))),
+            }
+        }
+        for (bookmark, target_cs_id) in &target_bookmarks {
+            if !renamed_source_bookmarks.contains_key(bookmark)
+                && reverse_bookmark_renamer(bookmark).is_some()
+            {
+                diff.only_in_target
+                    .push((bookmark.clone(), Target(*target_cs_id)));
+            }
+        }
+
+        diff.different_values = stream::iter(in_both.into_iter().map(
+            |(bookmark, source_cs_id, target_cs_id)| async move {
+                let maybe_outcome = self.get_commit_sync_outcome(ctx, source_cs_id).await?;
+                use CommitSyncOutcome::*;
+                let remapped_cs_id = match maybe_outcome {
+                    Some(RewrittenAs(cs_id, _)) | Some(EquivalentWorkingCopyAncestor(cs_id, _)) => {
+                        Some(cs_id)
+                    }
+                    Some(NotSyncCandidate(_)) | None => None,
+                };
+                let res = if remapped_cs_id == Some(target_cs_id) {
+                    None
+                } else {
+                    Some((bookmark.clone(), Source(source_cs_id), Target(target_cs_id)))
+                };
+                Result::<_, Error>::Ok(res)
+            },
+        ))
+        .buffered(100)
+        .try_filter_map(future::ok)
+        .try_collect()
+        .await?;
+
+        diff.missing_in_target.sort_by(|a, b| a.0.cmp(&b.0));
+        diff.only_in_target.sort_by(|a, b| a.0.cmp(&b.0));
+        diff.different_values.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(diff)
+    }
+
     pub async fn get_plural_commit_sync_outcome<'a>(
         &'a self,
         ctx: &'a CoreContext,
@@ -763,6 +1306,38 @@ where
         .await
     }
 
+    /// Get the outcomes of many commits at once, batching the mapping lookups.
+    /// Every commit of `source_cs_ids` is present in the result.
+    pub async fn get_plural_commit_sync_outcomes<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        source_cs_ids: Vec<ChangesetId>,
+    ) -> Result<HashMap<ChangesetId, Option<PluralCommitSyncOutcome>>, Error> {
+        get_plural_commit_sync_outcomes(
+            ctx,
+            Source(self.repos.get_source_repo().repo_identity().id()),
+            Target(self.repos.get_target_repo().repo_identity().id()),
+            &source_cs_ids,
+            &self.mapping,
+            self.repos.get_direction(),
+            &self.commit_sync_data_provider,
+        )
+        .await
+    }
+
+    /// Same as `get_commit_sync_outcome`, but for many commits at once. Like
+    /// `get_commit_sync_outcome`, this fails if any of the commits was
+    /// rewritten as multiple commits, use `get_plural_commit_sync_outcomes` or
+    /// `get_commit_sync_outcomes_with_hint` if that's expected.
+    pub async fn get_commit_sync_outcomes<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        source_cs_ids: Vec<ChangesetId>,
+    ) -> Result<HashMap<ChangesetId, Option<CommitSyncOutcome>>, Error> {
+        self.get_commit_sync_outcomes_with_hint(ctx, source_cs_ids, CandidateSelectionHint::Only)
+            .await
+    }
+
     pub async fn commit_sync_outcome_exists<'a>(
         &'a self,
         ctx: &'a CoreContext,
@@ -799,6 +1374,27 @@ where
         .await
     }
 
+    /// Same as `get_commit_sync_outcome_with_hint`, but for many commits at
+    /// once, applying the same `hint` to all of them
+    pub async fn get_commit_sync_outcomes_with_hint<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        source_cs_ids: Vec<ChangesetId>,
+        hint: CandidateSelectionHint<R>,
+    ) -> Result<HashMap<ChangesetId, Option<CommitSyncOutcome>>, Error> {
+        get_commit_sync_outcomes_with_hint(
+            ctx,
+            Source(self.repos.get_source_repo().repo_identity().id()),
+            Target(self.repos.get_target_repo().repo_identity().id()),
+            &source_cs_ids,
+            &self.mapping,
+            hint,
+            self.repos.get_direction(),
+            &self.commit_sync_data_provider,
+        )
+        .await
+    }
+
     /// This is the function that safely syncs a commit and all of its unsynced ancestors from a
     /// source repo to target repo. If commit is already synced then it just does a lookup.
     /// But safety comes with flexibility cost - not all of the syncs are allowed. For example,
@@ -808,6 +1404,10 @@ where
     ///    way of ensuring only a single job does the sync, this sync is forbidden completely.
     /// 2) If large repo is a source of truth, then there should never be a case with public
     ///    commit in a small repo not having an equivalent in the large repo.
+    ///
+    /// `ancestor_selection_hint` selects the remapping of `source_cs_id` and
+    /// of the parents of the synced commits, if they were synced to several
+    /// commits. See `sync_commit_for_bookmark` for the common case.
     pub async fn sync_commit(
         &self,
         ctx: &CoreContext,
@@ -816,21 +1416,117 @@ where
         commit_sync_context: CommitSyncContext,
         disable_lease: bool,
     ) -> Result<Option<ChangesetId>, Error> {
+        let outcome = self
+            .sync_commit_detailed(
+                ctx,
+                source_cs_id,
+                ancestor_selection_hint,
+                commit_sync_context,
+                disable_lease,
+            )
+            .await?;
+        Ok(outcome.target_cs_id())
+    }
+
+    /// Same as `sync_commit`, but select the remapping of commits synced
+    /// several times to the target repo, like commits synced to several
+    /// branches or synced twice around a megarepo merge, among the ancestors
+    /// of `target_bookmark` in the target repo.
+    ///
+    /// Prefer this over passing `CandidateSelectionHint::Only` to
+    /// `sync_commit` when the synced commit is meant to land on a known
+    /// bookmark, which would otherwise fail on such commits. If
+    /// `target_bookmark` does not exist, this behaves as `Only`.
+    pub async fn sync_commit_for_bookmark(
+        &self,
+        ctx: &CoreContext,
+        source_cs_id: ChangesetId,
+        target_bookmark: BookmarkKey,
+        commit_sync_context: CommitSyncContext,
+        disable_lease: bool,
+    ) -> Result<Option<ChangesetId>, Error> {
+        let hint = CandidateSelectionHint::OnlyOrAncestorOfBookmark(
+            Target(target_bookmark),
+            Target(self.get_target_repo().clone()),
+        );
+        self.sync_commit(ctx, source_cs_id, hint, commit_sync_context, disable_lease)
+            .await
+    }
+
+    /// Same as `sync_commit`, but also tells whether the target commit was
+    /// created by this call, already existed, or whether the commit was not
+    /// synced at all. See `DetailedSyncOutcome` for details.
+    pub async fn sync_commit_detailed(
+        &self,
+        ctx: &CoreContext,
+        source_cs_id: ChangesetId,
+        ancestor_selection_hint: CandidateSelectionHint<R>,
+        commit_sync_context: CommitSyncContext,
+        disable_lease: bool,
+    ) -> Result<DetailedSyncOutcome, Error> {
+        let (outcome, _report) = self
+            .sync_commit_detailed_with_report(
+                ctx,
+                source_cs_id,
+                ancestor_selection_hint,
+                commit_sync_context,
+                disable_lease,
+            )
+            .await?;
+        Ok(outcome)
+    }
+
+    /// Same as `sync_commit`, but also returns a `SyncReport` of the sync of
+    /// `source_cs_id`, if its target commit was created by this call. Commits
+    /// that were already synced or that were rewritten to nothing, as well
+    /// as the ancestors synced along, have no report.
+    pub async fn sync_commit_with_report(
+        &self,
+        ctx: &CoreContext,
+        source_cs_id: ChangesetId,
+        ancestor_selection_hint: CandidateSelectionHint<R>,
+        commit_sync_context: CommitSyncContext,
+        disable_lease: bool,
+    ) -> Result<(Option<ChangesetId>, Option<SyncReport>), Error> {
+        let (outcome, report) = self
+            .sync_commit_detailed_with_report(
+                ctx,
+                source_cs_id,
+                ancestor_selection_hint,
+                commit_sync_context,
+                disable_lease,
+            )
+            .await?;
+        Ok((outcome.target_cs_id(), report))
+    }
+
+    async fn sync_commit_detailed_with_report(
+        &self,
+        ctx: &CoreContext,
+        source_cs_id: ChangesetId,
+        ancestor_selection_hint: CandidateSelectionHint<R>,
+        commit_sync_context: CommitSyncContext,
+        disable_lease: bool,
+    ) -> Result<(DetailedSyncOutcome, Option<SyncReport>), Error> {
         let before = Instant::now();
         let res = self
             .sync_commit_impl(ctx, source_cs_id, ancestor_selection_hint, disable_lease)
             .await;
         let elapsed = before.elapsed();
-        log_rewrite(
+        let (res, report) = match res {
+            Ok((outcome, report)) => (Ok(outcome), report),
+            Err(err) => (Err(err), None),
+        };
+        log_detailed_rewrite(
             ctx,
-            self.scuba_sample.clone(),
+            self.reporter.as_ref(),
             source_cs_id,
             "sync_commit",
             commit_sync_context,
             elapsed,
             &res,
         );
-        res
+        Ok((res?, report))
     }
 
     async fn sync_commit_impl(
@@ -839,12 +1535,227 @@ where
         source_cs_id: ChangesetId,
         ancestor_selection_hint: CandidateSelectionHint<R>,
         disable_lease: bool,
-    ) -> Result<Option<ChangesetId>, Error> {
+    ) -> Result<(DetailedSyncOutcome, Option<SyncReport>), Error> {
+        // Fast path: if `source_cs_id` is synced, so are its ancestors.
+        let maybe_outcome = self
+            .get_commit_sync_outcome_with_hint(
+                ctx,
+                Source(source_cs_id),
+                ancestor_selection_hint.clone(),
+            )
+            .await?;
+        let (commit_sync_outcome, report) = match maybe_outcome {
+            Some(outcome) => (outcome, None),
+            None => {
+                let report = self
+                    .sync_unsynced_ancestors(
+                        ctx,
+                        source_cs_id,
+                        &ancestor_selection_hint,
+                        disable_lease,
+                    )
+                    .await?;
+                let outcome = self
+                    .get_commit_sync_outcome_with_hint(
+                        ctx,
+                        Source(source_cs_id),
+                        ancestor_selection_hint,
+                    )
+                    .await?
+                    .ok_or_else(|| {
+                        format_err!("was not able to remap a commit {}", source_cs_id)
+                    })?;
+                (outcome, report)
+            }
+        };
+        let created_target = report.is_some();
+        Ok((
+            detailed_sync_outcome(commit_sync_outcome, created_target),
+            report,
+        ))
+    }
+
+    /// Sync each of `source_cs_ids` like `sync_commit` does, along with their
+    /// unsynced ancestors, and return the outcome `sync_commit` would return
+    /// for each of them.
+    ///
+    /// Unlike calling `sync_commit` on each commit, the unsynced ancestors of
+    /// the whole batch are listed with a single traversal, which makes
+    /// catching up on a long range of commits linear rather than quadratic.
+    /// They are synced in topological order, each under its own lease.
+    ///
+    /// If public small repo commits would have to be synced, nothing is
+    /// synced. If some commit fails to sync, those not descending from it
+    /// are still synced, and the error is an `ErrorKind::PartialBatchSync`
+    /// telling which commits of the batch were synced.
+    pub async fn sync_commit_batch(
+        &self,
+        ctx: &CoreContext,
+        source_cs_ids: Vec<ChangesetId>,
+        ancestor_selection_hint: CandidateSelectionHint<R>,
+        commit_sync_context: CommitSyncContext,
+        disable_lease: bool,
+    ) -> Result<HashMap<ChangesetId, Option<ChangesetId>>, Error> {
+        let before = Instant::now();
+        let mut outcomes = HashMap::new();
+        let mut unsynced = Vec::new();
+        let mut seen = HashSet::new();
+        for source_cs_id in source_cs_ids {
+            if !seen.insert(source_cs_id) {
+                continue;
+            }
+            match self
+                .get_commit_sync_outcome_with_hint(
+                    ctx,
+                    Source(source_cs_id),
+                    ancestor_selection_hint.clone(),
+                )
+                .await?
+            {
+                Some(outcome) => {
+                    outcomes.insert(source_cs_id, detailed_sync_outcome(outcome, false));
+                }
+                None => unsynced.push(source_cs_id),
+            }
+        }
+
+        let mut sync_error = None;
+        // Commits of the batch left unsynced.
+        let mut failed = Vec::new();
+        if !unsynced.is_empty() {
+            let (unsynced_ancestors, synced_ancestors_versions) =
+                find_toposorted_unsynced_ancestors_of_many(ctx, self, unsynced.clone()).await?;
+            self.check_no_public_unsynced_ancestors(ctx, &unsynced_ancestors)
+                .await?;
+            let syncing = format!("a batch of {} commits", unsynced.len());
+            let created = Mutex::new(HashMap::new());
+            sync_error = self
+                .sync_toposorted_ancestors(
+                    ctx,
+                    &syncing,
+                    &unsynced_ancestors,
+                    &synced_ancestors_versions,
+                    &ancestor_selection_hint,
+                    disable_lease,
+                    OnRunError::SkipDescendants,
+                    &created,
+                )
+                .await
+                .err();
+            let created = created.into_inner().expect("lock poisoned");
+            for source_cs_id in unsynced {
+                if let Some(outcome) = self
+                    .get_commit_sync_outcome_with_hint(
+                        ctx,
+                        Source(source_cs_id),
+                        ancestor_selection_hint.clone(),
+                    )
+                    .await?
+                {
+                    let created_target = created.contains_key(&source_cs_id);
+                    outcomes.insert(source_cs_id, detailed_sync_outcome(outcome, created_target));
+                } else {
+                    failed.push(source_cs_id);
+                }
+            }
+        }
+
+        let elapsed = before.elapsed();
+        for (source_cs_id, outcome) in &outcomes {
+            log_detailed_rewrite(
+                ctx,
+                self.reporter.as_ref(),
+                *source_cs_id,
+                "sync_commit_batch",
+                commit_sync_context,
+                elapsed,
+                &Ok(outcome.clone()),
+            );
+        }
+        let synced: HashMap<_, _> = outcomes
+            .into_iter()
+            .map(|(source_cs_id, outcome)| (source_cs_id, outcome.target_cs_id()))
+            .collect();
+        if failed.is_empty() {
+            return Ok(synced);
+        }
+        let source = sync_error.unwrap_or_else(|| {
+            format_err!(
+                "was not able to remap commits {}",
+                describe_commits(&failed)
+            )
+        });
+        for source_cs_id in &failed {
+            log_detailed_rewrite(
+                ctx,
+                self.reporter.as_ref(),
+                *source_cs_id,
+                "sync_commit_batch",
+                commit_sync_context,
+                elapsed,
+                &Err(format_err!("{:#}", source)),
+            );
+        }
+        Err(ErrorKind::PartialBatchSync {
+            synced,
+            failed,
+            source,
+        }
+        .into())
+    }
+
+    /// Key of the lease protecting the sync of `cs_id` from the source repo
+    /// to the target repo.
+    fn sync_lease_key(&self, cs_id: ChangesetId) -> String {
+        format!(
+            "sourcerepo_{}_targetrepo_{}.{}",
+            self.get_source_repo_id().id(),
+            self.get_target_repo_id().id(),
+            cs_id,
+        )
+    }
+
+    /// Sync the unsynced ancestors of `source_cs_id`, and `source_cs_id`
+    /// itself. Returns the report of the sync of `source_cs_id` if it was
+    /// uploaded to the target repo by this call, rather than by a concurrent
+    /// sync.
+    async fn sync_unsynced_ancestors(
+        &self,
+        ctx: &CoreContext,
+        source_cs_id: ChangesetId,
+        ancestor_selection_hint: &CandidateSelectionHint<R>,
+        disable_lease: bool,
+    ) -> Result<Option<SyncReport>, Error> {
         let (unsynced_ancestors, synced_ancestors_versions) =
             find_toposorted_unsynced_ancestors(ctx, self, source_cs_id).await?;
+        self.check_no_public_unsynced_ancestors(ctx, &unsynced_ancestors)
+            .await?;
+        let created = Mutex::new(HashMap::new());
+        self.sync_toposorted_ancestors(
+            ctx,
+            &source_cs_id.to_string(),
+            &unsynced_ancestors,
+            &synced_ancestors_versions,
+            ancestor_selection_hint,
+            disable_lease,
+            OnRunError::Stop,
+            &created,
+        )
+        .await?;
+        Ok(created
+            .into_inner()
+            .expect("lock poisoned")
+            .remove(&source_cs_id))
+    }
 
+    /// Refuse to sync public small repo commits among `unsynced_ancestors`,
+    /// see `sync_commit`.
+    async fn check_no_public_unsynced_ancestors(
+        &self,
+        ctx: &CoreContext,
+        unsynced_ancestors: &[ChangesetId],
+    ) -> Result<(), Error> {
         let source_repo = self.repos.get_source_repo();
-        let target_repo = self.repos.get_target_repo();
 
         let small_repo = self.get_small_repo();
         let source_repo_is_small =
@@ -855,7 +1766,7 @@ where
                 .phases()
                 .get_public(
                     ctx,
-                    unsynced_ancestors.clone(),
+                    unsynced_ancestors.to_vec(),
                     false, /* ephemeral_derive */
                 )
                 .await?;
@@ -867,97 +1778,176 @@ where
                 ));
             }
         }
+        Ok(())
+    }
 
-        for ancestor in unsynced_ancestors {
-            let lease_key = format!(
-                "sourcerepo_{}_targetrepo_{}.{}",
-                source_repo.repo_identity().id().id(),
-                target_repo.repo_identity().id().id(),
-                source_cs_id,
-            );
-
-            let checker = || async {
-                let maybe_outcome = self.get_commit_sync_outcome(ctx, source_cs_id).await?;
-                Result::<_, Error>::Ok(maybe_outcome.is_some())
-            };
-            let sync = || async {
+    /// Sync `unsynced_ancestors` of `syncing`, which must be in topological
+    /// order. Those uploaded to the target repo by this call, rather than by
+    /// a concurrent sync, are added to `created` with the report of their sync.
+    async fn sync_toposorted_ancestors(
+        &self,
+        ctx: &CoreContext,
+        syncing: &str,
+        unsynced_ancestors: &[ChangesetId],
+        synced_ancestors_versions: &SyncedAncestorsVersions,
+        ancestor_selection_hint: &CandidateSelectionHint<R>,
+        disable_lease: bool,
+        on_error: OnRunError,
+        created: &Mutex<HashMap<ChangesetId, SyncReport>>,
+    ) -> Result<(), Error> {
+        let parents: HashMap<ChangesetId, Vec<ChangesetId>> =
+            stream::iter(unsynced_ancestors.iter().map(|ancestor| async move {
                 let parents = self
                     .get_source_repo()
                     .changeset_fetcher()
-                    .get_parents(ctx, ancestor)
+                    .get_parents(ctx, *ancestor)
                     .await?;
-                if parents.is_empty() {
-                    let version = self
-                        .get_version_for_syncing_commit_with_no_parent(
+                Result::<_, Error>::Ok((*ancestor, parents))
+            }))
+            .buffered(100)
+            .try_collect()
+            .await?;
+
+        let sync_ancestor = |ancestor: ChangesetId| {
+            let parents = &parents[&ancestor];
+            async move {
+                // Each ancestor is synced under its own lease, so that
+                // concurrent syncs of commits sharing unsynced ancestors
+                // sync each of them once, and independent ancestors are
+                // synced concurrently.
+                let lease_key = self.sync_lease_key(ancestor);
+
+                let checker = || async {
+                    let maybe_outcome = self
+                        .get_commit_sync_outcome_with_hint(
+                            ctx,
+                            Source(ancestor),
+                            ancestor_selection_hint.clone(),
+                        )
+                        .await?;
+                    Result::<_, Error>::Ok(maybe_outcome.is_some())
+                };
+                let sync = || async {
+                    let expected_version = if parents.is_empty() {
+                        let version = self
+                            .get_version_for_syncing_commit_with_no_parent(
+                                ctx,
+                                ancestor,
+                                synced_ancestors_versions,
+                            )
+                            .await
+                            .with_context(|| {
+                                format_err!("failed to sync ancestor {} of {}", ancestor, syncing)
+                            })?;
+
+                        Some(version)
+                    } else {
+                        None
+                    };
+                    let (synced, report) = self
+                        .unsafe_sync_commit_impl(
                             ctx,
                             ancestor,
-                            &synced_ancestors_versions,
+                            ancestor_selection_hint.clone(),
+                            expected_version,
                         )
-                        .await
-                        .with_context(|| {
-                            format_err!("failed to sync ancestor {} of {}", ancestor, source_cs_id)
-                        })?;
+                        .await?;
+                    if synced.is_some() {
+                        created
+                            .lock()
+                            .expect("lock poisoned")
+                            .insert(ancestor, report);
+                    }
+                    Ok(())
+                };
 
-                    self.unsafe_sync_commit_impl(
-                        ctx,
-                        ancestor,
-                        ancestor_selection_hint.clone(),
-                        Some(version),
-                    )
-                    .await?;
+                if tunables()
+                    .xrepo_disable_commit_sync_lease()
+                    .unwrap_or_default()
+                    || disable_lease
+                {
+                    sync().await
                 } else {
-                    self.unsafe_sync_commit_impl(
-                        ctx,
-                        ancestor,
-                        ancestor_selection_hint.clone(),
-                        None,
-                    )
-                    .await?;
+                    run_with_lease(ctx, &self.x_repo_sync_lease, lease_key, checker, sync).await
                 }
-                Ok(())
-            };
-
-            if tunables()
-                .xrepo_disable_commit_sync_lease()
-                .unwrap_or_default()
-                || disable_lease
-            {
-                sync().await?;
-            } else {
-                run_with_lease(ctx, &self.x_repo_sync_lease, lease_key, checker, sync).await?;
             }
-        }
+        };
 
-        let commit_sync_outcome = self
-            .get_commit_sync_outcome(ctx, source_cs_id)
-            .await?
-            .ok_or_else(|| format_err!("was not able to remap a commit {}", source_cs_id))?;
-        use CommitSyncOutcome::*;
-        let res = match commit_sync_outcome {
-            NotSyncCandidate(_) => None,
-            RewrittenAs(cs_id, _) | EquivalentWorkingCopyAncestor(cs_id, _) => Some(cs_id),
+        let concurrency = match tunables()
+            .xrepo_sync_ancestors_concurrency()
+            .unwrap_or_default()
+        {
+            0 => DEFAULT_ANCESTORS_SYNC_CONCURRENCY,
+            concurrency => concurrency.try_into().unwrap_or(1),
         };
-        Ok(res)
+        run_in_topological_order(
+            unsynced_ancestors,
+            &parents,
+            concurrency,
+            on_error,
+            sync_ancestor,
+        )
+        .await
     }
 
     // Get a version to use while syncing ancestor with no parent  of `source_cs_id`
     // We only allow syncing such commits if we an unambiguously decide on the CommitSyncConfig version to use,
     // and we do that by ensuring that there is exactly one unique version among the commit sync outcomes
-    // of all the already-synced ancestors of `source_cs_id`
+    // of all the already-synced ancestors of `source_cs_id`.
+    // If the ancestors were synced with several versions, the current version of the source repo
+    // is used instead, but only if `allow_ambiguous_ancestor_version_fallback` is set.
     async fn get_version_for_syncing_commit_with_no_parent(
         &self,
         ctx: &CoreContext,
         commit_with_no_parent: ChangesetId,
         synced_ancestors_versions: &SyncedAncestorsVersions,
     ) -> Result<CommitSyncConfigVersion, Error> {
+        let versions = &synced_ancestors_versions.versions;
         let maybe_version =
             get_version(ctx, self.get_source_repo(), commit_with_no_parent, vec![]).await?;
-        let version = match maybe_version {
-            Some(version) => version,
-            None => synced_ancestors_versions
-                .get_only_version()?
-                .ok_or_else(|| format_err!("no versions found for {}", commit_with_no_parent))?,
-        };
+        if let Some(version) = maybe_version {
+            info!(
+                ctx.logger(),
+                "syncing {} with version {} set in its extras, synced ancestors versions: {:?}",
+                commit_with_no_parent,
+                version,
+                versions,
+            );
+            return Ok(version);
+        }
+
+        if versions.len() > 1 && self.allow_ambiguous_ancestor_version_fallback {
+            let source_repo_id = self.get_source_repo_id();
+            let version = self
+                .commit_sync_data_provider
+                .get_current_version(source_repo_id)?
+                .ok_or_else(|| {
+                    format_err!(
+                        "cannot find single ancestor version: {:?}, and {} has no current version",
+                        versions,
+                        source_repo_id,
+                    )
+                })?;
+            info!(
+                ctx.logger(),
+                "syncing {} with current version {} of {}, synced ancestors versions: {:?}",
+                commit_with_no_parent,
+                version,
+                source_repo_id,
+                versions,
+            );
+            return Ok(version);
+        }
+
+        let version = synced_ancestors_versions
+            .get_only_version()?
+            .ok_or_else(|| format_err!("no versions found for {}", commit_with_no_parent))?;
+        info!(
+            ctx.logger(),
+            "syncing {} with the only version {} of its synced ancestors",
+            commit_with_no_parent,
+            version,
+        );
         Ok(version)
     }
 
@@ -978,6 +1968,26 @@ where
         parent_mapping_selection_hint: CandidateSelectionHint<R>,
         commit_sync_context: CommitSyncContext,
     ) -> Result<Option<ChangesetId>, Error> {
+        self.unsafe_sync_commit_with_report(
+            ctx,
+            source_cs_id,
+            parent_mapping_selection_hint,
+            commit_sync_context,
+        )
+        .await
+        .map(|(target_cs_id, _)| target_cs_id)
+    }
+
+    /// Same as `unsafe_sync_commit`, but also returns a `SyncReport` telling
+    /// how long each phase of the sync took and how the file changes of
+    /// `source_cs_id` were rewritten
+    pub async fn unsafe_sync_commit_with_report(
+        &self,
+        ctx: &CoreContext,
+        source_cs_id: ChangesetId,
+        parent_mapping_selection_hint: CandidateSelectionHint<R>,
+        commit_sync_context: CommitSyncContext,
+    ) -> Result<(Option<ChangesetId>, SyncReport), Error> {
         let before = Instant::now();
         let res = self
             .unsafe_sync_commit_impl(ctx, source_cs_id, parent_mapping_selection_hint, None)
@@ -985,12 +1995,12 @@ where
         let elapsed = before.elapsed();
         log_rewrite(
             ctx,
-            self.scuba_sample.clone(),
+            self.reporter.as_ref(),
             source_cs_id,
             "unsafe_sync_commit",
             commit_sync_context,
             elapsed,
-            &res,
+            &without_report(&res),
         );
         res
     }
@@ -1008,18 +2018,26 @@ where
         commit_sync_context: CommitSyncContext,
     ) -> Result<Option<ChangesetId>, Error> {
         let before = Instant::now();
-        let res = self
-            .unsafe_sync_commit_impl(
+        let res = async {
+            let cs_info = ChangesetInfo::derive(ctx, self.get_source_repo(), source_cs_id).await?;
+            if let Some(version) = get_mapping_change_version(&cs_info)? {
+                self.validate_mapping_change_version(source_cs_id, &version, &mut HashSet::new())
+                    .await?;
+            }
+            self.unsafe_sync_commit_impl(
                 ctx,
                 source_cs_id,
                 parent_mapping_selection_hint,
                 Some(expected_version),
             )
-            .await;
+            .await
+            .map(|(target_cs_id, _)| target_cs_id)
+        }
+        .await;
         let elapsed = before.elapsed();
         log_rewrite(
             ctx,
-            self.scuba_sample.clone(),
+            self.reporter.as_ref(),
             source_cs_id,
             "unsafe_sync_commit_with_expected_version",
             commit_sync_context,
@@ -1035,7 +2053,7 @@ where
         source_cs_id: ChangesetId,
         mut parent_mapping_selection_hint: CandidateSelectionHint<R>,
         expected_version: Option<CommitSyncConfigVersion>,
-    ) -> Result<Option<ChangesetId>, Error> {
+    ) -> Result<(Option<ChangesetId>, SyncReport), Error> {
         debug!(
             ctx.logger(),
             "{:?}: unsafe_sync_commit called for {}, with hint: {:?}",
@@ -1043,6 +2061,7 @@ where
             source_cs_id,
             parent_mapping_selection_hint
         );
+        let before = Instant::now();
         let source_repo = self.get_source_repo();
         let cs = source_cs_id.load(ctx, source_repo.repo_blobstore()).await?;
         if cs.parents().count() > 1 {
@@ -1062,18 +2081,25 @@ where
         .buffered(100)
         .try_collect()
         .await?;
-        CommitInMemorySyncer {
+        let source_paths: Vec<_> = cs.file_changes().map(|(path, _)| path.clone()).collect();
+        let result = CommitInMemorySyncer {
             ctx,
             source_repo: Source(self.get_source_repo()),
             mapped_parents: &mapped_parents,
             target_repo_id: Target(self.get_target_repo_id()),
             provider: &self.commit_sync_data_provider,
             small_to_large: matches!(self.repos, CommitSyncRepos::SmallToLarge { .. }),
+            target_path_policy: self.target_path_policy.clone(),
+            message_rewrite_rules: self.message_rewrite_rules.clone(),
+            extras_policy: self.extras_policy.clone(),
         }
         .unsafe_sync_commit_in_memory(cs, expected_version)
-        .await?
-        .write(ctx, self)
-        .await
+        .await?;
+        let rewrite_time = before.elapsed();
+
+        let mut report = result.report(self, &source_paths, rewrite_time).await?;
+        let target_cs_id = result.write(ctx, self, &mut report).await?;
+        Ok((target_cs_id, report))
     }
 
     /// Rewrite a commit and creates in target repo if parents are already created.
@@ -1093,11 +2119,17 @@ where
     /// Normally this function is able to find the parents for the synced commit automatically
     /// but in case it can't then `maybe_parents` parameter allows us to overwrite parents of
     /// the synced commit.
+    ///
+    /// The overrides are checked before anything is written: every overriding commit must exist
+    /// in the target repo, and every overridden commit must be a parent of the synced commit,
+    /// unless `parents_strictness` allows extra ones. Otherwise this fails with
+    /// `ErrorKind::InvalidParentOverrides` listing all invalid overrides.
     pub async fn unsafe_always_rewrite_sync_commit<'a>(
         &'a self,
         ctx: &'a CoreContext,
         source_cs_id: ChangesetId,
         maybe_parents: Option<HashMap<ChangesetId, ChangesetId>>,
+        parents_strictness: ParentOverrideStrictness,
         sync_config_version: &CommitSyncConfigVersion,
         commit_sync_context: CommitSyncContext,
     ) -> Result<Option<ChangesetId>, Error> {
@@ -1107,13 +2139,14 @@ where
                 ctx,
                 source_cs_id,
                 maybe_parents,
+                parents_strictness,
                 sync_config_version,
             )
             .await;
         let elapsed = before.elapsed();
         log_rewrite(
             ctx,
-            self.scuba_sample.clone(),
+            self.reporter.as_ref(),
             source_cs_id,
             "unsafe_always_rewrite_sync_commit",
             commit_sync_context,
@@ -1128,6 +2161,7 @@ where
         ctx: &'a CoreContext,
         source_cs_id: ChangesetId,
         maybe_parents: Option<HashMap<ChangesetId, ChangesetId>>,
+        parents_strictness: ParentOverrideStrictness,
         sync_config_version: &CommitSyncConfigVersion,
     ) -> Result<Option<ChangesetId>, Error> {
         let (source_repo, target_repo) = self.get_source_target();
@@ -1136,7 +2170,18 @@ where
 
         let source_cs = source_cs.clone().into_mut();
         let remapped_parents = match maybe_parents {
-            Some(parents) => parents,
+            Some(parents) => {
+                validate_parent_overrides(
+                    ctx,
+                    source_cs_id,
+                    &source_cs,
+                    &parents,
+                    &target_repo,
+                    parents_strictness,
+                )
+                .await?;
+                parents
+            }
             None => remap_parents(ctx, &source_cs, self, CandidateSelectionHint::Only).await?, // TODO: check if only is ok
         };
 
@@ -1146,7 +2191,7 @@ where
             &remapped_parents,
             mover,
             &source_repo,
-            Default::default(),
+            self.rewrite_opts(),
         )
         .await?;
         match rewritten_commit {
@@ -1155,8 +2200,9 @@ where
                     .await?;
                 Ok(None)
             }
-            Some(rewritten) => {
+            Some(mut rewritten) => {
                 // Sync commit
+                self.rewrite_message(source_cs_id, &mut rewritten);
                 let frozen = rewritten.freeze()?;
                 let frozen_cs_id = frozen.get_changeset_id();
                 upload_commits(ctx, vec![frozen], &source_repo, &target_repo).await?;
@@ -1184,6 +2230,27 @@ where
         commit_sync_context: CommitSyncContext,
         rewritedates: PushrebaseRewriteDates,
     ) -> Result<Option<ChangesetId>, Error> {
+        self.unsafe_sync_commit_pushrebase_with_report(
+            ctx,
+            source_cs,
+            bookmark,
+            commit_sync_context,
+            rewritedates,
+        )
+        .await
+        .map(|(target_cs_id, _)| target_cs_id)
+    }
+
+    /// Same as `unsafe_sync_commit_pushrebase`, but also returns a
+    /// `SyncReport` of the sync, including the time spent pushrebasing
+    pub async fn unsafe_sync_commit_pushrebase_with_report<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        source_cs: BonsaiChangeset,
+        bookmark: BookmarkKey,
+        commit_sync_context: CommitSyncContext,
+        rewritedates: PushrebaseRewriteDates,
+    ) -> Result<(Option<ChangesetId>, SyncReport), Error> {
         let source_cs_id = source_cs.get_changeset_id();
         let before = Instant::now();
         let res = self
@@ -1193,12 +2260,12 @@ where
 
         log_rewrite(
             ctx,
-            self.scuba_sample.clone(),
+            self.reporter.as_ref(),
             source_cs_id,
             "unsafe_sync_commit_pushrebase",
             commit_sync_context,
             elapsed,
-            &res,
+            &without_report(&res),
         );
         res
     }
@@ -1215,7 +2282,8 @@ where
         source_cs: BonsaiChangeset,
         bookmark: BookmarkKey,
         rewritedates: PushrebaseRewriteDates,
-    ) -> Result<Option<ChangesetId>, Error> {
+    ) -> Result<(Option<ChangesetId>, SyncReport), Error> {
+        let before = Instant::now();
         let hash = source_cs.get_changeset_id();
         let (source_repo, target_repo) = self.get_source_target();
 
@@ -1278,16 +2346,20 @@ where
         let source_cs_mut = source_cs.clone().into_mut();
         let remapped_parents =
             remap_parents(ctx, &source_cs_mut, self, parent_selection_hint).await?;
+        let source_paths: Vec<_> = source_cs_mut.file_changes.keys().cloned().collect();
         let rewritten = rewrite_commit(
             ctx,
             source_cs_mut,
             &remapped_parents,
-            mover,
+            mover.clone(),
             &source_repo,
-            Default::default(),
+            self.rewrite_opts(),
         )
         .await?;
+        let mut report = SyncReport::new(version_name.clone(), before.elapsed());
+        report.count_file_changes(&source_paths, rewritten.as_ref(), &mover)?;
 
+        let before = Instant::now();
         match rewritten {
             None => {
                 if remapped_parents_outcome.is_empty() {
@@ -1317,11 +2389,13 @@ where
                     )
                     .into());
                 }
+                report.mapping_update_time = before.elapsed();
 
-                Ok(None)
+                Ok((None, report))
             }
-            Some(rewritten) => {
+            Some(mut rewritten) => {
                 // Sync commit
+                self.rewrite_message(hash, &mut rewritten);
                 let frozen = rewritten.freeze()?;
                 let rewritten_list = hashset![frozen];
                 upload_commits(
@@ -1331,6 +2405,7 @@ where
                     &target_repo,
                 )
                 .await?;
+                report.upload_time = before.elapsed();
 
                 let pushrebase_flags = PushrebaseFlags {
                     rewritedates: rewritedates == PushrebaseRewriteDates::Yes,
@@ -1340,6 +2415,7 @@ where
                     ..Default::default()
                 };
 
+                let before = Instant::now();
                 let pushrebase_res = do_pushrebase_bonsai(
                     ctx,
                     &target_repo,
@@ -1355,8 +2431,11 @@ where
                 .await;
                 let pushrebase_res =
                     pushrebase_res.map_err(|e| Error::from(ErrorKind::PushrebaseFailure(e)))?;
+                // The mapping is updated by the pushrebase hook
+                report.pushrebase_time = before.elapsed();
+                report.pushrebase_retries = pushrebase_res.retry_num.0;
                 let pushrebased_changeset = pushrebase_res.head;
-                Ok(Some(pushrebased_changeset))
+                Ok((Some(pushrebased_changeset), report))
             }
         }
     }
@@ -1368,15 +2447,19 @@ where
         source_cs_id: ChangesetId,
         rewritten: BonsaiChangesetMut,
         version: CommitSyncConfigVersion,
+        report: &mut SyncReport,
     ) -> Result<ChangesetId, Error> {
         let (source_repo, target_repo) = self.get_source_target();
 
+        let before = Instant::now();
         let frozen = rewritten.freeze()?;
         let target_cs_id = frozen.get_changeset_id();
         upload_commits(ctx, vec![frozen], &source_repo, &target_repo).await?;
+        report.upload_time = before.elapsed();
 
         // update_mapping also updates working copy equivalence, so no need
         // to do it separately
+        let before = Instant::now();
         update_mapping_with_version(
             ctx,
             hashmap! { source_cs_id =>  target_cs_id},
@@ -1384,6 +2467,7 @@ where
             &version,
         )
         .await?;
+        report.mapping_update_time = before.elapsed();
         Ok(target_cs_id)
     }
 
@@ -1402,6 +2486,240 @@ where
         }
     }
 
+    /// Mark `source_cs_id` as not a sync candidate, so that it's never
+    /// synced to the target repo, like a commit that must not be synced
+    /// because of its content.
+    ///
+    /// Only large repo commits can be marked. Marking fails if the commit
+    /// was already synced, see `force_mark_not_sync_candidate`, and does
+    /// nothing if it's already not a sync candidate. The mark is recorded
+    /// with the version the parents of the commit were synced with, or the
+    /// current version if none of them was synced.
+    ///
+    /// Descendants of a marked commit sync like the descendants of any
+    /// commit that is not a sync candidate: commits with a single parent
+    /// are not sync candidates either, and merges are synced without the
+    /// marked parent.
+    ///
+    /// `reason` and the user of `ctx` are reported to the `SyncReporter`,
+    /// whether marking succeeds or not.
+    pub async fn mark_not_sync_candidate(
+        &self,
+        ctx: &CoreContext,
+        source_cs_id: ChangesetId,
+        reason: String,
+    ) -> Result<(), Error> {
+        let mut report = mark_report(ctx, source_cs_id, "mark_not_sync_candidate", reason, false);
+        let result = self
+            .mark_not_sync_candidate_impl(ctx, source_cs_id, false, &mut report.previous_outcome)
+            .await;
+        self.report_mark(ctx, report, &result);
+        result
+    }
+
+    /// Same as `mark_not_sync_candidate`, but a commit that was already
+    /// synced is marked too: its mapping entries and working copy
+    /// equivalence are deleted first, and the mark is recorded with the
+    /// version it was synced with.
+    ///
+    /// The target commits it was synced to stay in the target repo, as do
+    /// the descendants already synced on top of them. Only commits synced
+    /// afterwards see the mark.
+    pub async fn force_mark_not_sync_candidate(
+        &self,
+        ctx: &CoreContext,
+        source_cs_id: ChangesetId,
+        reason: String,
+    ) -> Result<(), Error> {
+        let mut report = mark_report(
+            ctx,
+            source_cs_id,
+            "force_mark_not_sync_candidate",
+            reason,
+            true,
+        );
+        let result = self
+            .mark_not_sync_candidate_impl(ctx, source_cs_id, true, &mut report.previous_outcome)
+            .await;
+        self.report_mark(ctx, report, &result);
+        result
+    }
+
+    /// Remove the mark set by `mark_not_sync_candidate`, so that
+    /// `source_cs_id` is synced like any commit that wasn't synced yet.
+    ///
+    /// Fails if the commit has any other sync outcome, or if it's not a
+    /// sync candidate without being marked, like commits whose version
+    /// doesn't have the target repo. Commits found not to be sync
+    /// candidates while syncing are recorded like marked commits, so they
+    /// can be unmarked too. Descendants that were synced while the commit
+    /// was marked keep their outcome.
+    ///
+    /// `reason` and the user of `ctx` are reported like for
+    /// `mark_not_sync_candidate`.
+    pub async fn unmark_not_sync_candidate(
+        &self,
+        ctx: &CoreContext,
+        source_cs_id: ChangesetId,
+        reason: String,
+    ) -> Result<(), Error> {
+        let mut report = mark_report(
+            ctx,
+            source_cs_id,
+            "unmark_not_sync_candidate",
+            reason,
+            false,
+        );
+        let result = self
+            .unmark_not_sync_candidate_impl(ctx, source_cs_id, &mut report.previous_outcome)
+            .await;
+        self.report_mark(ctx, report, &result);
+        result
+    }
+
+    async fn unmark_not_sync_candidate_impl(
+        &self,
+        ctx: &CoreContext,
+        source_cs_id: ChangesetId,
+        previous_outcome: &mut Option<&'static str>,
+    ) -> Result<(), Error> {
+        self.check_can_mark(source_cs_id)?;
+        let outcome = self
+            .get_plural_commit_sync_outcome(ctx, source_cs_id)
+            .await?;
+        *previous_outcome = outcome.as_ref().map(PluralCommitSyncOutcome::name);
+
+        let (source_repo_id, target_repo_id) =
+            (self.get_source_repo_id(), self.get_target_repo_id());
+        let wc_equivalence = self
+            .mapping
+            .get_equivalent_working_copy(ctx, source_repo_id, source_cs_id, target_repo_id)
+            .await?;
+        match (outcome, wc_equivalence) {
+            (
+                Some(PluralCommitSyncOutcome::NotSyncCandidate(_)),
+                Some(WorkingCopyEquivalence::NoWorkingCopy(_)),
+            ) => {
+                self.mapping
+                    .delete_equivalent_working_copy(
+                        ctx,
+                        source_repo_id,
+                        source_cs_id,
+                        target_repo_id,
+                    )
+                    .await?;
+                Ok(())
+            }
+            _ => Err(ErrorKind::NotMarkedNotSyncCandidate(source_cs_id).into()),
+        }
+    }
+
+    async fn mark_not_sync_candidate_impl(
+        &self,
+        ctx: &CoreContext,
+        source_cs_id: ChangesetId,
+        force: bool,
+        previous_outcome: &mut Option<&'static str>,
+    ) -> Result<(), Error> {
+        self.check_can_mark(source_cs_id)?;
+        let outcome = self
+            .get_plural_commit_sync_outcome(ctx, source_cs_id)
+            .await?;
+        *previous_outcome = outcome.as_ref().map(PluralCommitSyncOutcome::name);
+
+        use PluralCommitSyncOutcome::*;
+        let version = match outcome {
+            None => {
+                self.get_version_for_not_sync_candidate(ctx, source_cs_id)
+                    .await?
+            }
+            Some(NotSyncCandidate(_)) => return Ok(()),
+            Some(outcome) if !force => {
+                return Err(ErrorKind::SyncOutcomeExists {
+                    cs_id: source_cs_id,
+                    outcome: outcome.name(),
+                }
+                .into());
+            }
+            Some(RewrittenAs(cs_ids_versions)) => {
+                // The mapping keeps the version of the large repo commit, so
+                // the mark has to use it.
+                let (_, version) = cs_ids_versions
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| format_err!("no rewritten commit for {}", source_cs_id))?;
+                version
+            }
+            Some(EquivalentWorkingCopyAncestor(_, version)) => version,
+        };
+
+        let (source_repo_id, target_repo_id) =
+            (self.get_source_repo_id(), self.get_target_repo_id());
+        self.mapping
+            .delete(ctx, source_repo_id, source_cs_id, target_repo_id)
+            .await?;
+        self.mapping
+            .delete_equivalent_working_copy(ctx, source_repo_id, source_cs_id, target_repo_id)
+            .await?;
+        self.set_no_sync_candidate(ctx, source_cs_id, version).await
+    }
+
+    /// Check that commits of the source repo can be marked as not sync
+    /// candidates, and that syncs aren't disabled.
+    fn check_can_mark(&self, source_cs_id: ChangesetId) -> Result<(), Error> {
+        if tunables().xrepo_sync_disable_all_syncs().unwrap_or(false) {
+            return Err(ErrorKind::XRepoSyncDisabled.into());
+        }
+        // Small repo commits are always synced to the large repo, so there
+        // is nowhere to record they are not.
+        if self.repos.get_direction() != CommitSyncDirection::LargeToSmall {
+            return Err(ErrorKind::MarkInSmallRepo(source_cs_id).into());
+        }
+        Ok(())
+    }
+
+    /// Version to mark `source_cs_id` as not a sync candidate with: the one
+    /// it would be synced with, given the versions its parents were synced
+    /// with, or the current version.
+    async fn get_version_for_not_sync_candidate(
+        &self,
+        ctx: &CoreContext,
+        source_cs_id: ChangesetId,
+    ) -> Result<CommitSyncConfigVersion, Error> {
+        let parents = self
+            .get_source_repo()
+            .changeset_fetcher()
+            .get_parents(ctx, source_cs_id)
+            .await?;
+        let mut parent_versions = vec![];
+        for parent in parents {
+            use PluralCommitSyncOutcome::*;
+            match self.get_plural_commit_sync_outcome(ctx, parent).await? {
+                Some(NotSyncCandidate(version))
+                | Some(EquivalentWorkingCopyAncestor(_, version)) => parent_versions.push(version),
+                Some(RewrittenAs(cs_ids_versions)) => {
+                    parent_versions.extend(cs_ids_versions.into_iter().map(|(_, version)| version))
+                }
+                None => {}
+            }
+        }
+
+        if let Some(version) =
+            get_version(ctx, self.get_source_repo(), source_cs_id, &parent_versions).await?
+        {
+            return Ok(version);
+        }
+        let source_repo_id = self.get_source_repo_id();
+        self.commit_sync_data_provider
+            .get_current_version(source_repo_id)?
+            .ok_or_else(|| format_err!("{} has no current version", source_repo_id))
+    }
+
+    fn report_mark(&self, ctx: &CoreContext, mut report: MarkReport, result: &Result<(), Error>) {
+        report.result = result.as_ref().copied().map_err(|e| format!("{}", e));
+        self.reporter.report_mark(ctx, &report);
+    }
+
     async fn set_no_sync_candidate<'a>(
         &'a self,
         ctx: &'a CoreContext,
@@ -1483,6 +2801,26 @@ where
     }
 }
 
+/// Report of a call of `mark_fn`, for `CommitSyncer::report_mark` to fill
+/// in the result of.
+fn mark_report(
+    ctx: &CoreContext,
+    source_cs_id: ChangesetId,
+    mark_fn: &'static str,
+    reason: String,
+    forced: bool,
+) -> MarkReport {
+    MarkReport {
+        source_cs_id,
+        mark_fn,
+        reason,
+        actor: ctx.metadata().unix_name().map(|name| name.to_string()),
+        forced,
+        previous_outcome: None,
+        result: Ok(()),
+    }
+}
+
 impl<R: Repo> CommitSyncRepos<R> {
     pub fn get_source_repo(&self) -> &R {
         match self {
@@ -1522,6 +2860,9 @@ pub struct CommitInMemorySyncer<'a, R: Repo> {
     pub provider: &'a CommitSyncDataProvider,
     pub mapped_parents: &'a HashMap<ChangesetId, CommitSyncOutcome>,
     pub small_to_large: bool,
+    pub target_path_policy: Option<Arc<dyn TargetPathPolicy>>,
+    pub message_rewrite_rules: Option<Arc<MessageRewriteRules>>,
+    pub extras_policy: ExtrasPolicy,
 }
 
 impl<'a, R: Repo> CommitInMemorySyncer<'a, R> {
@@ -1529,6 +2870,14 @@ impl<'a, R: Repo> CommitInMemorySyncer<'a, R> {
         Source(self.source_repo.repo_identity().id())
     }
 
+    fn rewrite_opts(&self) -> RewriteOpts {
+        RewriteOpts {
+            target_path_policy: self.target_path_policy.clone(),
+            extras_policy: self.extras_policy.clone(),
+            ..Default::default()
+        }
+    }
+
     fn source_repo_name(&self) -> Source<&str> {
         Source(self.source_repo.repo_identity().name())
     }
@@ -1538,10 +2887,12 @@ impl<'a, R: Repo> CommitInMemorySyncer<'a, R> {
         cs: BonsaiChangeset,
         expected_version: Option<CommitSyncConfigVersion>,
     ) -> Result<CommitSyncInMemoryResult, Error> {
+        let message_rewrite_rules = self.message_rewrite_rules.clone();
+        let source_repo_name = self.source_repo.repo_identity().name().to_string();
         let parent_count = cs.parents().count();
-        if parent_count == 0 {
+        let mut result = if parent_count == 0 {
             match expected_version {
-                Some(version) => self.sync_commit_no_parents_in_memory(cs, version).await,
+                Some(version) => self.sync_commit_no_parents_in_memory(cs, version).await?,
                 None => bail!(
                     "no version specified for remapping commit {} with no parents",
                     cs.get_changeset_id(),
@@ -1549,10 +2900,22 @@ impl<'a, R: Repo> CommitInMemorySyncer<'a, R> {
             }
         } else if parent_count == 1 {
             self.sync_commit_single_parent_in_memory(cs, expected_version)
-                .await
+                .await?
         } else {
-            self.sync_merge_in_memory(cs, expected_version).await
+            self.sync_merge_in_memory(cs, expected_version).await?
+        };
+        if let (
+            Some(rules),
+            CommitSyncInMemoryResult::Rewritten {
+                source_cs_id,
+                rewritten,
+                ..
+            },
+        ) = (message_rewrite_rules, &mut result)
+        {
+            rewritten.message = rules.rewrite(&rewritten.message, &source_repo_name, *source_cs_id);
         }
+        Ok(result)
     }
 
     async fn sync_commit_no_parents_in_memory(
@@ -1587,7 +2950,7 @@ impl<'a, R: Repo> CommitInMemorySyncer<'a, R> {
             &HashMap::new(),
             mover,
             self.source_repo.0,
-            Default::default(),
+            self.rewrite_opts(),
         )
         .await?
         {
@@ -1695,6 +3058,7 @@ impl<'a, R: Repo> CommitInMemorySyncer<'a, R> {
                     RewriteOpts {
                         commit_rewritten_to_empty,
                         empty_commit_from_large_repo,
+                        ..self.rewrite_opts()
                     },
                 )
                 .await?;
@@ -1844,7 +3208,7 @@ impl<'a, R: Repo> CommitInMemorySyncer<'a, R> {
                 &new_parents,
                 mover,
                 self.source_repo.0,
-                Default::default(),
+                self.rewrite_opts(),
             )
             .await?
             {
@@ -2048,6 +3412,149 @@ where
     })
 }
 
+/// The outcome `sync_commit` returns for a commit synced with
+/// `commit_sync_outcome`, whose target commit was `created_target` by it.
+fn detailed_sync_outcome(
+    commit_sync_outcome: CommitSyncOutcome,
+    created_target: bool,
+) -> DetailedSyncOutcome {
+    match commit_sync_outcome {
+        CommitSyncOutcome::NotSyncCandidate(version) => {
+            DetailedSyncOutcome::NotSyncCandidate(version)
+        }
+        CommitSyncOutcome::RewrittenAs(cs_id, version) => {
+            if created_target {
+                DetailedSyncOutcome::CreatedTarget(cs_id, version)
+            } else {
+                DetailedSyncOutcome::AlreadySynced(cs_id, version)
+            }
+        }
+        CommitSyncOutcome::EquivalentWorkingCopyAncestor(cs_id, version) => {
+            DetailedSyncOutcome::RewrittenToNothing {
+                wc_equivalent: cs_id,
+                version,
+            }
+        }
+    }
+}
+
+/// The result of a sync with a report, for `log_rewrite`
+fn without_report(
+    res: &Result<(Option<ChangesetId>, SyncReport), Error>,
+) -> Result<Option<ChangesetId>, Error> {
+    match res {
+        Ok((target_cs_id, _)) => Ok(*target_cs_id),
+        Err(err) => Err(format_err!("{}", err)),
+    }
+}
+
+/// `cs_ids`, for logs and errors.
+fn describe_commits(cs_ids: &[ChangesetId]) -> String {
+    cs_ids
+        .iter()
+        .map(ChangesetId::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// What `run_in_topological_order` does once a run fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OnRunError {
+    /// No new runs are started.
+    Stop,
+    /// Runs of the commits that don't descend from a failed one are still
+    /// started.
+    SkipDescendants,
+}
+
+/// Run `func` on each of `cs_ids`, which must be in topological order, with
+/// up to `concurrency` runs at a time. A commit is only started once all of
+/// its `parents` that are among `cs_ids` are done. Merge commits are run on
+/// their own, with nothing else running concurrently.
+///
+/// After an error, runs are started according to `on_error`, and those in
+/// progress are allowed to finish. The first error is then returned.
+async fn run_in_topological_order<Func, Fut>(
+    cs_ids: &[ChangesetId],
+    parents: &HashMap<ChangesetId, Vec<ChangesetId>>,
+    concurrency: usize,
+    on_error: OnRunError,
+    func: Func,
+) -> Result<(), Error>
+where
+    Func: Fn(ChangesetId) -> Fut,
+    Fut: futures::Future<Output = Result<(), Error>>,
+{
+    let index: HashMap<ChangesetId, usize> = cs_ids
+        .iter()
+        .enumerate()
+        .map(|(i, cs_id)| (*cs_id, i))
+        .collect();
+    let mut pending_parents = vec![0; cs_ids.len()];
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); cs_ids.len()];
+    for (i, cs_id) in cs_ids.iter().enumerate() {
+        for parent in parents.get(cs_id).into_iter().flatten() {
+            if let Some(parent_index) = index.get(parent) {
+                pending_parents[i] += 1;
+                children[*parent_index].push(i);
+            }
+        }
+    }
+    let is_merge = |i: usize| parents.get(&cs_ids[i]).map_or(0, Vec::len) > 1;
+
+    // Ready commits are started in the order of `cs_ids`, so that with a
+    // concurrency of 1 they are run exactly in that order.
+    let mut ready: BTreeSet<usize> = (0..cs_ids.len())
+        .filter(|i| pending_parents[*i] == 0)
+        .collect();
+    let mut running = FuturesUnordered::new();
+    let mut running_merge = false;
+    let mut first_error = None;
+    loop {
+        while (first_error.is_none() || on_error == OnRunError::SkipDescendants)
+            && !running_merge
+            && running.len() < concurrency.max(1)
+        {
+            let i = match ready.first() {
+                Some(i) => *i,
+                None => break,
+            };
+            if is_merge(i) {
+                if !running.is_empty() {
+                    break;
+                }
+                running_merge = true;
+            }
+            ready.remove(&i);
+            running.push(func(cs_ids[i]).map(move |res| (i, res)));
+        }
+
+        let (i, res) = match running.next().await {
+            Some(done) => done,
+            None => break,
+        };
+        running_merge = false;
+        match res {
+            Ok(()) => {
+                for child in &children[i] {
+                    pending_parents[*child] -= 1;
+                    if pending_parents[*child] == 0 {
+                        ready.insert(*child);
+                    }
+                }
+            }
+            Err(err) => {
+                first_error.get_or_insert(err);
+            }
+        }
+    }
+
+    if let Some(err) = first_error {
+        return Err(err);
+    }
+    Ok(())
+}
+
 async fn run_with_lease<CheckerFunc, CheckerFut, Func, Fut>(
     ctx: &CoreContext,
     lease: &Arc<dyn LeaseOps>,
@@ -2107,6 +3614,11 @@ where
         };
         lease.renew_lease_until(ctx.clone(), &lease_key, receiver.map(|_| ()).boxed());
 
+        // The previous holder of the lease may have done it between the
+        // check and taking the lease.
+        if checker().await? {
+            break;
+        }
         func().await?;
         break;
     }
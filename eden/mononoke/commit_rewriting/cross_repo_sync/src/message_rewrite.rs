@@ -0,0 +1,234 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Rewriting of the messages of synced commits, see `MessageRewriteRules`.
+
+use mononoke_types::ChangesetId;
+use regex::Regex;
+
+/// Key of the line referring to the source of a synced commit, like
+/// `Synced-from: large-repo 0123...`.
+pub const SOURCE_REFERENCE_KEY: &str = "Synced-from";
+
+/// How a `CommitSyncer` rewrites the messages of the commits it syncs.
+///
+/// Rules are applied in this order:
+/// 1. A source reference added by a syncer, in either direction, is
+///    removed. This way commits synced back and forth don't accumulate
+///    references, and messages are rewritten the same way however many
+///    times they are synced.
+/// 2. Lines of the last paragraph matching `strip_trailer_patterns` are
+///    removed. The first paragraph, which has the title, is never changed.
+/// 3. `prefix` is prepended to messages that don't start with it, unless
+///    they are blank.
+/// 4. If `append_source_reference` is set, the source reference is
+///    appended, as the last paragraph.
+///
+/// Trailing whitespace is removed from messages with a source reference,
+/// so that it is separated from the rest of the message by exactly one
+/// blank line.
+#[derive(Clone, Debug, Default)]
+pub struct MessageRewriteRules {
+    /// Append `Synced-from: <source repo> <source changeset id>`.
+    pub append_source_reference: bool,
+    /// Remove trailer lines matching any of these, like internal-only
+    /// trailers.
+    pub strip_trailer_patterns: Vec<Regex>,
+    /// Prepend this to messages, like `[large-repo] `.
+    pub prefix: Option<String>,
+}
+
+impl MessageRewriteRules {
+    /// Rewrite `message` of `source_cs_id` from `source_repo`.
+    pub fn rewrite(&self, message: &str, source_repo: &str, source_cs_id: ChangesetId) -> String {
+        let message = strip_source_reference(message);
+        let mut message = self.strip_trailers(message);
+        if let Some(prefix) = &self.prefix {
+            if !message.trim().is_empty() && !message.starts_with(prefix.as_str()) {
+                message.insert_str(0, prefix);
+            }
+        }
+        if self.append_source_reference {
+            let reference = source_reference(source_repo, source_cs_id);
+            message.truncate(message.trim_end().len());
+            if !message.is_empty() {
+                message.push_str("\n\n");
+            }
+            message.push_str(&reference);
+        }
+        message
+    }
+
+    fn strip_trailers(&self, message: &str) -> String {
+        if self.strip_trailer_patterns.is_empty() {
+            return message.to_string();
+        }
+        let (body, trailers) = match message.rsplit_once("\n\n") {
+            Some(split) => split,
+            None => return message.to_string(),
+        };
+        let kept: Vec<&str> = trailers
+            .lines()
+            .filter(|line| {
+                !self
+                    .strip_trailer_patterns
+                    .iter()
+                    .any(|pattern| pattern.is_match(line))
+            })
+            .collect();
+        if kept.is_empty() {
+            body.to_string()
+        } else {
+            let trailing_newline = if trailers.ends_with('\n') { "\n" } else { "" };
+            format!("{}\n\n{}{}", body, kept.join("\n"), trailing_newline)
+        }
+    }
+}
+
+/// The line referring to `source_cs_id` of `source_repo`.
+pub fn source_reference(source_repo: &str, source_cs_id: ChangesetId) -> String {
+    format!("{}: {} {}", SOURCE_REFERENCE_KEY, source_repo, source_cs_id)
+}
+
+/// Remove the source reference appended by `MessageRewriteRules::rewrite`
+/// from `message`, if any.
+pub fn strip_source_reference(message: &str) -> &str {
+    let (body, last_line) = match message.rsplit_once("\n\n") {
+        Some((body, last_line)) => (body, last_line),
+        None => ("", message),
+    };
+    if is_source_reference(last_line) {
+        body
+    } else {
+        message
+    }
+}
+
+fn is_source_reference(line: &str) -> bool {
+    let value = match line
+        .strip_prefix(SOURCE_REFERENCE_KEY)
+        .and_then(|rest| rest.strip_prefix(": "))
+    {
+        Some(value) => value,
+        None => return false,
+    };
+    match value.split_once(' ') {
+        Some((repo, cs_id)) => {
+            !repo.is_empty()
+                && !repo.contains(char::is_whitespace)
+                && cs_id.parse::<ChangesetId>().is_ok()
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mononoke_types_mocks::changesetid::ONES_CSID;
+    use mononoke_types_mocks::changesetid::TWOS_CSID;
+
+    use super::*;
+
+    fn rules() -> MessageRewriteRules {
+        MessageRewriteRules {
+            append_source_reference: true,
+            strip_trailer_patterns: vec![Regex::new("^Internal-[A-Za-z-]+:").unwrap()],
+            prefix: None,
+        }
+    }
+
+    #[test]
+    fn test_append_source_reference() {
+        let reference = source_reference("large", ONES_CSID);
+        let rewritten = rules().rewrite("title\n\nbody\n", "large", ONES_CSID);
+        assert_eq!(rewritten, format!("title\n\nbody\n\n{}", reference));
+        assert_eq!(strip_source_reference(&rewritten), "title\n\nbody");
+
+        // Only the reference of the last sync is kept.
+        let resynced = rules().rewrite(&rewritten, "small", TWOS_CSID);
+        assert_eq!(
+            resynced,
+            format!("title\n\nbody\n\n{}", source_reference("small", TWOS_CSID))
+        );
+
+        // Lines that only look like references are kept.
+        for message in [
+            "title\n\nSynced-from: large not-a-hash",
+            "title\n\nSynced-from: large",
+            "title\nSynced-from: large 1111111111111111111111111111111111111111111111111111111111111111",
+        ] {
+            assert_eq!(strip_source_reference(message), message);
+        }
+    }
+
+    #[test]
+    fn test_strip_trailers() {
+        let message = "title\n\nInternal-Only: yes\nReviewed-by: alice\nInternal-Task: T1\n";
+        let rules = MessageRewriteRules {
+            append_source_reference: false,
+            ..rules()
+        };
+        assert_eq!(
+            rules.rewrite(message, "large", ONES_CSID),
+            "title\n\nReviewed-by: alice\n"
+        );
+        assert_eq!(
+            rules.rewrite("title\n\nInternal-Only: yes", "large", ONES_CSID),
+            "title"
+        );
+        // The title is never stripped.
+        assert_eq!(
+            rules.rewrite("Internal-Only: yes", "large", ONES_CSID),
+            "Internal-Only: yes"
+        );
+    }
+
+    #[test]
+    fn test_prefix() {
+        let rules = MessageRewriteRules {
+            prefix: Some("[large] ".to_string()),
+            ..rules()
+        };
+        let rewritten = rules.rewrite("title", "large", ONES_CSID);
+        assert_eq!(
+            rewritten,
+            format!("[large] title\n\n{}", source_reference("large", ONES_CSID))
+        );
+        assert_eq!(rules.rewrite(&rewritten, "large", ONES_CSID), rewritten);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let message = "title\n\nSummary: sync me\n\nReviewed-by: alice";
+        let forward = rules();
+        // The reverse syncer doesn't add references, but still removes them.
+        let backward = MessageRewriteRules::default();
+
+        let synced = forward.rewrite(message, "large", ONES_CSID);
+        let synced_back = backward.rewrite(&synced, "small", TWOS_CSID);
+        assert_eq!(synced_back, message);
+        assert_eq!(forward.rewrite(&synced_back, "large", ONES_CSID), synced);
+        assert_eq!(forward.rewrite(&synced, "large", ONES_CSID), synced);
+    }
+
+    #[test]
+    fn test_empty_message() {
+        let reference = source_reference("large", ONES_CSID);
+        let rules = MessageRewriteRules {
+            prefix: Some("[large] ".to_string()),
+            ..rules()
+        };
+        assert_eq!(rules.rewrite("", "large", ONES_CSID), reference);
+        assert_eq!(rules.rewrite("\n", "large", ONES_CSID), reference);
+        assert_eq!(rules.rewrite(&reference, "large", ONES_CSID), reference);
+        assert_eq!(strip_source_reference(&reference), "");
+        assert_eq!(
+            MessageRewriteRules::default().rewrite("", "large", ONES_CSID),
+            ""
+        );
+    }
+}
@@ -12,8 +12,13 @@ use anyhow::Error;
 use context::CoreContext;
 use mononoke_types::ChangesetId;
 use scuba_ext::MononokeScubaSampleBuilder;
+use slog::debug;
+use slog::info;
+use slog::warn;
 use tunables::tunables;
 
+use crate::commit_sync_outcome::DetailedSyncOutcome;
+
 const SCUBA_TABLE: &str = "mononoke_x_repo_mapping";
 
 const SOURCE_REPO: &str = "source_repo";
@@ -26,6 +31,11 @@ const DURATION_MS: &str = "duration_ms";
 const ERROR: &str = "error";
 const SUCCESS: &str = "success";
 const SESSION_ID: &str = "session_id";
+const SYNC_OUTCOME: &str = "sync_outcome";
+const REASON: &str = "reason";
+const ACTOR: &str = "actor";
+const FORCED: &str = "forced";
+const PREVIOUS_OUTCOME: &str = "previous_outcome";
 
 /// Context of a commit sync function being called
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -61,48 +71,245 @@ impl fmt::Display for CommitSyncContext {
     }
 }
 
+/// What a commit sync function reports about one of its calls.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RewriteReport {
+    pub source_cs_id: ChangesetId,
+    /// Name of the commit sync function, like `sync_commit`.
+    pub sync_fn: &'static str,
+    pub commit_sync_context: CommitSyncContext,
+    pub duration: Duration,
+    /// The target commit, if the source commit was synced to one, or the
+    /// error the sync failed with.
+    pub result: Result<Option<ChangesetId>, String>,
+    /// Name of the `DetailedSyncOutcome` the sync resolved to, for functions
+    /// that resolve to one.
+    pub sync_outcome: Option<&'static str>,
+}
+
+/// What `CommitSyncer::mark_not_sync_candidate` and its variants report
+/// about one of their calls, as an audit trail of manual changes to the
+/// mapping.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MarkReport {
+    pub source_cs_id: ChangesetId,
+    /// Name of the function, like `mark_not_sync_candidate`.
+    pub mark_fn: &'static str,
+    /// Why the commit was marked or unmarked, as given by the caller.
+    pub reason: String,
+    /// Unix name of the user of the `CoreContext`, if any.
+    pub actor: Option<String>,
+    /// Whether an existing sync outcome was overridden.
+    pub forced: bool,
+    /// Name of the outcome the commit had before the call, if any, like
+    /// `rewritten_as`.
+    pub previous_outcome: Option<&'static str>,
+    /// The error the call failed with, if any.
+    pub result: Result<(), String>,
+}
+
+/// Reports the outcome of commit sync functions. A `CommitSyncer` reports
+/// every call of its sync functions to the reporter it was created with.
+pub trait SyncReporter: Send + Sync {
+    fn report_rewrite(&self, ctx: &CoreContext, report: &RewriteReport);
+
+    /// Report a commit manually marked as not to be synced, or unmarked.
+    fn report_mark(&self, ctx: &CoreContext, report: &MarkReport);
+}
+
+/// Logs reports to the `mononoke_x_repo_mapping` scuba table, if enabled
+/// by the `enable_logging_commit_rewrite_data` tunable.
+pub struct ScubaSyncReporter {
+    sample: MononokeScubaSampleBuilder,
+}
+
+impl ScubaSyncReporter {
+    pub fn new(
+        ctx: &CoreContext,
+        source_repo: impl AsRef<str>,
+        target_repo: impl AsRef<str>,
+    ) -> Self {
+        Self {
+            sample: get_scuba_sample(ctx, source_repo, target_repo),
+        }
+    }
+}
+
+impl SyncReporter for ScubaSyncReporter {
+    fn report_rewrite(&self, ctx: &CoreContext, report: &RewriteReport) {
+        if !tunables()
+            .enable_logging_commit_rewrite_data()
+            .unwrap_or_default()
+        {
+            return;
+        }
+
+        let mut sample = self.sample.clone();
+        sample
+            .add(DURATION_MS, report.duration.as_millis() as u64)
+            .add(SOURCE_CS_ID, format!("{}", report.source_cs_id))
+            .add(SYNC_FN, report.sync_fn)
+            .add(
+                SESSION_ID,
+                format!("session {}", ctx.metadata().session_id()),
+            )
+            .add(SYNC_CONTEXT, format!("{}", report.commit_sync_context));
+        if let Some(sync_outcome) = report.sync_outcome {
+            sample.add(SYNC_OUTCOME, sync_outcome);
+        }
+
+        match &report.result {
+            Ok(maybe_target_cs_id) => {
+                sample.add(SUCCESS, 1);
+                if let Some(target_cs_id) = maybe_target_cs_id {
+                    sample.add(TARGET_CS_ID, format!("{}", target_cs_id));
+                }
+            }
+            Err(e) => {
+                sample.add(SUCCESS, 0).add(ERROR, e.as_str());
+            }
+        }
+
+        sample.log();
+    }
+
+    /// Unlike rewrites, marks are always logged: they are rare, and they
+    /// are the only record of who changed the mapping by hand, and why.
+    fn report_mark(&self, ctx: &CoreContext, report: &MarkReport) {
+        let mut sample = self.sample.clone();
+        sample
+            .add(SOURCE_CS_ID, format!("{}", report.source_cs_id))
+            .add(SYNC_FN, report.mark_fn)
+            .add(
+                SESSION_ID,
+                format!("session {}", ctx.metadata().session_id()),
+            )
+            .add(REASON, report.reason.as_str())
+            .add(FORCED, report.forced);
+        if let Some(actor) = &report.actor {
+            sample.add(ACTOR, actor.as_str());
+        }
+        if let Some(previous_outcome) = report.previous_outcome {
+            sample.add(PREVIOUS_OUTCOME, previous_outcome);
+        }
+
+        match &report.result {
+            Ok(()) => {
+                sample.add(SUCCESS, 1);
+            }
+            Err(e) => {
+                sample.add(SUCCESS, 0).add(ERROR, e.as_str());
+            }
+        }
+
+        sample.log();
+    }
+}
+
+/// Logs reports to the logger of the `CoreContext`, for builds and tools
+/// that don't log to scuba.
+pub struct LogSyncReporter {
+    source_repo: String,
+    target_repo: String,
+}
+
+impl LogSyncReporter {
+    pub fn new(source_repo: impl AsRef<str>, target_repo: impl AsRef<str>) -> Self {
+        Self {
+            source_repo: source_repo.as_ref().to_string(),
+            target_repo: target_repo.as_ref().to_string(),
+        }
+    }
+}
+
+impl SyncReporter for LogSyncReporter {
+    fn report_rewrite(&self, ctx: &CoreContext, report: &RewriteReport) {
+        let what = format!(
+            "{} of {} from {} to {} ({}, {}ms)",
+            report.sync_fn,
+            report.source_cs_id,
+            self.source_repo,
+            self.target_repo,
+            report.commit_sync_context,
+            report.duration.as_millis(),
+        );
+        match &report.result {
+            Ok(maybe_target_cs_id) => debug!(
+                ctx.logger(),
+                "{} succeeded: target {:?}, outcome {}",
+                what,
+                maybe_target_cs_id,
+                report.sync_outcome.unwrap_or("unknown"),
+            ),
+            Err(e) => warn!(ctx.logger(), "{} failed: {}", what, e),
+        }
+    }
+
+    fn report_mark(&self, ctx: &CoreContext, report: &MarkReport) {
+        let what = format!(
+            "{} of {} from {} to {} by {} (forced: {}, previous outcome: {}, reason: {})",
+            report.mark_fn,
+            report.source_cs_id,
+            self.source_repo,
+            self.target_repo,
+            report.actor.as_deref().unwrap_or("unknown"),
+            report.forced,
+            report.previous_outcome.unwrap_or("none"),
+            report.reason,
+        );
+        match &report.result {
+            Ok(()) => info!(ctx.logger(), "{} succeeded", what),
+            Err(e) => warn!(ctx.logger(), "{} failed: {}", what, e),
+        }
+    }
+}
+
 pub fn log_rewrite(
     ctx: &CoreContext,
-    mut sample: MononokeScubaSampleBuilder,
+    reporter: &dyn SyncReporter,
     source_cs_id: ChangesetId,
-    sync_fn: &str,
+    sync_fn: &'static str,
     commit_sync_context: CommitSyncContext,
     duration: Duration,
     sync_result: &Result<Option<ChangesetId>, Error>,
 ) {
-    if !tunables()
-        .enable_logging_commit_rewrite_data()
-        .unwrap_or_default()
-    {
-        return;
-    }
-
-    sample
-        .add(DURATION_MS, duration.as_millis() as u64)
-        .add(SOURCE_CS_ID, format!("{}", source_cs_id))
-        .add(SYNC_FN, sync_fn)
-        .add(
-            SESSION_ID,
-            format!("session {}", ctx.metadata().session_id()),
-        )
-        .add(SYNC_CONTEXT, format!("{}", commit_sync_context));
-
-    match sync_result {
-        Ok(maybe_target_cs_id) => {
-            sample.add(SUCCESS, 1);
-            if let Some(target_cs_id) = maybe_target_cs_id {
-                sample.add(TARGET_CS_ID, format!("{}", target_cs_id));
-            }
-        }
-        Err(e) => {
-            sample.add(SUCCESS, 0).add(ERROR, format!("{}", e));
-        }
-    }
+    let report = RewriteReport {
+        source_cs_id,
+        sync_fn,
+        commit_sync_context,
+        duration,
+        result: sync_result.as_ref().copied().map_err(|e| format!("{}", e)),
+        sync_outcome: None,
+    };
+    reporter.report_rewrite(ctx, &report);
+}
 
-    sample.log();
+/// Same as `log_rewrite`, but also logs which `DetailedSyncOutcome` the sync
+/// resolved to
+pub fn log_detailed_rewrite(
+    ctx: &CoreContext,
+    reporter: &dyn SyncReporter,
+    source_cs_id: ChangesetId,
+    sync_fn: &'static str,
+    commit_sync_context: CommitSyncContext,
+    duration: Duration,
+    sync_result: &Result<DetailedSyncOutcome, Error>,
+) {
+    let report = RewriteReport {
+        source_cs_id,
+        sync_fn,
+        commit_sync_context,
+        duration,
+        result: sync_result
+            .as_ref()
+            .map(DetailedSyncOutcome::target_cs_id)
+            .map_err(|e| format!("{}", e)),
+        sync_outcome: sync_result.as_ref().ok().map(DetailedSyncOutcome::name),
+    };
+    reporter.report_rewrite(ctx, &report);
 }
 
-pub fn get_scuba_sample(
+fn get_scuba_sample(
     ctx: &CoreContext,
     source_repo: impl AsRef<str>,
     target_repo: impl AsRef<str>,
@@ -0,0 +1,315 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Previews of what syncing a stack of commits would do, see
+//! `CommitSyncer::plan_sync`.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use anyhow::format_err;
+use anyhow::Error;
+use blobstore::Loadable;
+use changeset_fetcher::ChangesetFetcherRef;
+use context::CoreContext;
+use metaconfig_types::CommitSyncConfigVersion;
+use mononoke_types::ChangesetId;
+use phases::PhasesRef;
+use repo_blobstore::RepoBlobstoreRef;
+use repo_identity::RepoIdentityRef;
+use synced_commit_mapping::SyncedCommitMapping;
+use tunables::tunables;
+
+use crate::find_toposorted_unsynced_ancestors;
+use crate::types::Source;
+use crate::types::Target;
+use crate::CandidateSelectionHint;
+use crate::CommitInMemorySyncer;
+use crate::CommitSyncInMemoryResult;
+use crate::CommitSyncOutcome;
+use crate::CommitSyncRepos;
+use crate::CommitSyncer;
+use crate::ErrorKind;
+use crate::Repo;
+use crate::SyncedAncestorsVersions;
+
+/// What syncing a commit would do.
+#[derive(Debug)]
+pub enum SyncPlanDecision {
+    /// The commit is already synced, with this outcome. Nothing would be done.
+    AlreadySynced(CommitSyncOutcome),
+    /// A target commit would be created, with id `target_cs_id`, from a
+    /// rewritten commit with `file_changes` file changes, implicit deletes
+    /// included.
+    WouldCreate {
+        target_cs_id: ChangesetId,
+        file_changes: usize,
+        version: CommitSyncConfigVersion,
+    },
+    /// No target commit would be created. The commit would be recorded as
+    /// having the working copy of `wc_equivalent_to`, or as not a sync
+    /// candidate if it's `None`.
+    WouldSkip {
+        wc_equivalent_to: Option<ChangesetId>,
+        version: CommitSyncConfigVersion,
+    },
+    /// Syncing the commit would fail with `error`, and so would syncing its
+    /// descendants.
+    WouldFail { error: Error },
+}
+
+/// The decision of `CommitSyncer::plan_sync` for `source_cs_id`.
+#[derive(Debug)]
+pub struct SyncPlanEntry {
+    pub source_cs_id: ChangesetId,
+    pub decision: SyncPlanDecision,
+}
+
+impl<M, R> CommitSyncer<M, R>
+where
+    M: SyncedCommitMapping + Clone + 'static,
+    R: Repo,
+{
+    /// Tell what `sync_commit` would do for each of `source_cs_ids`, synced
+    /// in this order, without doing it.
+    ///
+    /// The plan has an entry for each commit that would be synced, the
+    /// unsynced ancestors of `source_cs_ids` included, in the order they
+    /// would be synced, and one for each of `source_cs_ids` that is already
+    /// synced. Commits are rewritten like `sync_commit` does, including
+    /// version resolution and the validations of the rewrite, with the
+    /// parents that earlier commits of the plan would be synced to.
+    ///
+    /// Nothing is written: no commit is uploaded to the target repo, no
+    /// mapping or working copy equivalence is recorded, and no lease is
+    /// taken. Like any read, rewriting may derive data of source commits,
+    /// such as changeset info and manifests.
+    pub async fn plan_sync(
+        &self,
+        ctx: &CoreContext,
+        source_cs_ids: Vec<ChangesetId>,
+        ancestor_selection_hint: CandidateSelectionHint<R>,
+    ) -> Result<Vec<SyncPlanEntry>, Error> {
+        let mut plan = Vec::new();
+        // Outcomes that commits of the plan would be synced with.
+        let mut planned: HashMap<ChangesetId, CommitSyncOutcome> = HashMap::new();
+        let mut failed: HashSet<ChangesetId> = HashSet::new();
+
+        for source_cs_id in source_cs_ids {
+            if planned.contains_key(&source_cs_id) || failed.contains(&source_cs_id) {
+                continue;
+            }
+            if let Some(outcome) = self
+                .get_commit_sync_outcome_with_hint(
+                    ctx,
+                    Source(source_cs_id),
+                    ancestor_selection_hint.clone(),
+                )
+                .await?
+            {
+                plan.push(SyncPlanEntry {
+                    source_cs_id,
+                    decision: SyncPlanDecision::AlreadySynced(outcome),
+                });
+                continue;
+            }
+
+            let (unsynced_ancestors, synced_ancestors_versions) =
+                match self.find_plannable_ancestors(ctx, source_cs_id).await {
+                    Ok(found) => found,
+                    Err(error) => {
+                        failed.insert(source_cs_id);
+                        plan.push(SyncPlanEntry {
+                            source_cs_id,
+                            decision: SyncPlanDecision::WouldFail { error },
+                        });
+                        continue;
+                    }
+                };
+            for ancestor in unsynced_ancestors {
+                if planned.contains_key(&ancestor) || failed.contains(&ancestor) {
+                    continue;
+                }
+                let decision = match self
+                    .plan_commit(
+                        ctx,
+                        ancestor,
+                        &ancestor_selection_hint,
+                        &synced_ancestors_versions,
+                        &planned,
+                        &failed,
+                    )
+                    .await
+                {
+                    Ok((decision, outcome)) => {
+                        planned.insert(ancestor, outcome);
+                        decision
+                    }
+                    Err(error) => {
+                        failed.insert(ancestor);
+                        SyncPlanDecision::WouldFail { error }
+                    }
+                };
+                plan.push(SyncPlanEntry {
+                    source_cs_id: ancestor,
+                    decision,
+                });
+            }
+        }
+        Ok(plan)
+    }
+
+    /// The unsynced ancestors of `source_cs_id`, itself included, as listed
+    /// by `sync_commit`, which refuses to sync public small repo commits.
+    async fn find_plannable_ancestors(
+        &self,
+        ctx: &CoreContext,
+        source_cs_id: ChangesetId,
+    ) -> Result<(Vec<ChangesetId>, SyncedAncestorsVersions), Error> {
+        let (unsynced_ancestors, synced_ancestors_versions) =
+            find_toposorted_unsynced_ancestors(ctx, self, source_cs_id).await?;
+        let source_repo = self.get_source_repo();
+        let source_repo_is_small =
+            source_repo.repo_identity().id() == self.get_small_repo().repo_identity().id();
+        if source_repo_is_small {
+            let public_unsynced_ancestors = source_repo
+                .phases()
+                .get_public(
+                    ctx,
+                    unsynced_ancestors.clone(),
+                    true, /* ephemeral_derive */
+                )
+                .await?;
+            if !public_unsynced_ancestors.is_empty() {
+                return Err(format_err!(
+                    "unexpected sync lookup attempt - trying to sync \
+                     a public commit from small repo to a large repo. Syncing public commits is \
+                     only supported from a large repo to a small repo"
+                ));
+            }
+        }
+        Ok((unsynced_ancestors, synced_ancestors_versions))
+    }
+
+    /// Rewrite `source_cs_id` in memory like `sync_commit` would, with the
+    /// outcomes of the commits `planned` before it. Return the decision and
+    /// the outcome the commit would be synced with.
+    async fn plan_commit(
+        &self,
+        ctx: &CoreContext,
+        source_cs_id: ChangesetId,
+        ancestor_selection_hint: &CandidateSelectionHint<R>,
+        synced_ancestors_versions: &SyncedAncestorsVersions,
+        planned: &HashMap<ChangesetId, CommitSyncOutcome>,
+        failed: &HashSet<ChangesetId>,
+    ) -> Result<(SyncPlanDecision, CommitSyncOutcome), Error> {
+        let cs = source_cs_id
+            .load(ctx, self.get_source_repo().repo_blobstore())
+            .await?;
+        let parents = self
+            .get_source_repo()
+            .changeset_fetcher()
+            .get_parents(ctx, source_cs_id)
+            .await?;
+        let parent_mapping_selection_hint = if parents.len() > 1 {
+            CandidateSelectionHint::Only
+        } else {
+            ancestor_selection_hint.clone()
+        };
+
+        let mut mapped_parents = HashMap::new();
+        for parent in &parents {
+            if failed.contains(parent) {
+                return Err(format_err!("parent {} would fail to sync", parent));
+            }
+            let outcome = match planned.get(parent) {
+                Some(outcome) => outcome.clone(),
+                None => self
+                    .get_commit_sync_outcome_with_hint(
+                        ctx,
+                        Source(*parent),
+                        parent_mapping_selection_hint.clone(),
+                    )
+                    .await?
+                    .ok_or_else(|| format_err!("{} does not have CommitSyncOutcome", parent))?,
+            };
+            mapped_parents.insert(*parent, outcome);
+        }
+
+        let expected_version = if parents.is_empty() {
+            Some(
+                self.get_version_for_syncing_commit_with_no_parent(
+                    ctx,
+                    source_cs_id,
+                    synced_ancestors_versions,
+                )
+                .await?,
+            )
+        } else {
+            None
+        };
+        let result = CommitInMemorySyncer {
+            ctx,
+            source_repo: Source(self.get_source_repo()),
+            mapped_parents: &mapped_parents,
+            target_repo_id: Target(self.get_target_repo_id()),
+            provider: &self.commit_sync_data_provider,
+            small_to_large: matches!(self.repos, CommitSyncRepos::SmallToLarge { .. }),
+            target_path_policy: self.target_path_policy.clone(),
+            message_rewrite_rules: self.message_rewrite_rules.clone(),
+            extras_policy: self.extras_policy.clone(),
+        }
+        .unsafe_sync_commit_in_memory(cs, expected_version)
+        .await?;
+
+        // Writing the outcome would fail.
+        if tunables().xrepo_sync_disable_all_syncs().unwrap_or(false) {
+            return Err(ErrorKind::XRepoSyncDisabled.into());
+        }
+
+        use CommitSyncInMemoryResult::*;
+        Ok(match result {
+            NoSyncCandidate { version, .. }
+            | WcEquivalence {
+                remapped_id: None,
+                version,
+                ..
+            } => (
+                SyncPlanDecision::WouldSkip {
+                    wc_equivalent_to: None,
+                    version: version.clone(),
+                },
+                CommitSyncOutcome::NotSyncCandidate(version),
+            ),
+            WcEquivalence {
+                remapped_id: Some(remapped_id),
+                version,
+                ..
+            } => (
+                SyncPlanDecision::WouldSkip {
+                    wc_equivalent_to: Some(remapped_id),
+                    version: version.clone(),
+                },
+                CommitSyncOutcome::EquivalentWorkingCopyAncestor(remapped_id, version),
+            ),
+            Rewritten {
+                rewritten, version, ..
+            } => {
+                let file_changes = rewritten.file_changes.len();
+                let target_cs_id = rewritten.freeze()?.get_changeset_id();
+                (
+                    SyncPlanDecision::WouldCreate {
+                        target_cs_id,
+                        file_changes,
+                        version: version.clone(),
+                    },
+                    CommitSyncOutcome::RewrittenAs(target_cs_id, version),
+                )
+            }
+        })
+    }
+}
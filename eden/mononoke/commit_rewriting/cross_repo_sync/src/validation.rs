@@ -14,6 +14,7 @@ use std::sync::Arc;
 use anyhow::format_err;
 use anyhow::Error;
 use blobstore::Loadable;
+use blobstore::LoadableError;
 use bookmarks::BookmarkKey;
 use bookmarks::BookmarksMaybeStaleExt;
 use cloned::cloned;
@@ -32,6 +33,7 @@ use manifest::ManifestOps;
 use mercurial_types::FileType;
 use metaconfig_types::DefaultSmallToLargeCommitSyncPathAction;
 use mononoke_types::fsnode::FsnodeEntry;
+use mononoke_types::fsnode::FsnodeFile;
 use mononoke_types::typed_hash::FsnodeId;
 use mononoke_types::ChangesetId;
 use mononoke_types::ContentId;
@@ -49,6 +51,7 @@ use synced_commit_mapping::SyncedCommitMapping;
 use super::CommitSyncConfigVersion;
 use super::CommitSyncOutcome;
 use super::CommitSyncer;
+use super::PluralCommitSyncOutcome;
 use super::Repo;
 use crate::types::Source;
 use crate::types::Target;
@@ -1288,6 +1291,518 @@ async fn rename_and_remap_bookmarks<M: SyncedCommitMapping + Clone + 'static, R:
     Ok((remapped_bookmarks, no_sync_outcome))
 }
 
+/// Number of source commits whose sync outcome is fetched and checked
+/// concurrently by `check_sync_health`.
+const SYNC_HEALTH_CONCURRENCY: usize = 100;
+/// Maximum number of violations listed in a `SyncHealthReport`.
+const MAX_REPORTED_VIOLATIONS: usize = 100;
+
+/// An inconsistency found by `check_sync_health` for a source repo commit
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyncHealthViolation {
+    /// The commit has no sync outcome
+    NoSyncOutcome { source_cs_id: ChangesetId },
+    /// The commit was rewritten as a commit that can't be loaded from the
+    /// target repo
+    MissingTarget {
+        source_cs_id: ChangesetId,
+        target_cs_id: ChangesetId,
+    },
+    /// The working copy equivalent of the commit can't be loaded from the
+    /// target repo
+    MissingWorkingCopyEquivalent {
+        source_cs_id: ChangesetId,
+        target_cs_id: ChangesetId,
+    },
+    /// The commit sync config version recorded for the commit doesn't exist
+    /// anymore
+    UnknownVersion {
+        source_cs_id: ChangesetId,
+        version: CommitSyncConfigVersion,
+    },
+}
+
+/// The result of `check_sync_health`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncHealthReport {
+    /// Number of source commits that were checked
+    pub checked: u64,
+    /// Number of `SyncHealthViolation::NoSyncOutcome` violations
+    pub no_sync_outcome: u64,
+    /// Number of `SyncHealthViolation::MissingTarget` violations
+    pub missing_target: u64,
+    /// Number of `SyncHealthViolation::MissingWorkingCopyEquivalent` violations
+    pub missing_wc_equivalent: u64,
+    /// Number of `SyncHealthViolation::UnknownVersion` violations
+    pub unknown_version: u64,
+    /// The violations of the newest source commits, at most
+    /// `MAX_REPORTED_VIOLATIONS` of them
+    pub violations: Vec<SyncHealthViolation>,
+}
+
+impl SyncHealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.no_sync_outcome == 0
+            && self.missing_target == 0
+            && self.missing_wc_equivalent == 0
+            && self.unknown_version == 0
+    }
+
+    fn add_violation(&mut self, violation: SyncHealthViolation) {
+        use SyncHealthViolation::*;
+        match violation {
+            NoSyncOutcome { .. } => self.no_sync_outcome += 1,
+            MissingTarget { .. } => self.missing_target += 1,
+            MissingWorkingCopyEquivalent { .. } => self.missing_wc_equivalent += 1,
+            UnknownVersion { .. } => self.unknown_version += 1,
+        }
+        if self.violations.len() < MAX_REPORTED_VIOLATIONS {
+            self.violations.push(violation);
+        }
+    }
+}
+
+/// Check the sync state of the last `depth` commits of `bookmark` in the
+/// source repo, in reverse topological order. For every commit, this checks
+/// that:
+/// - it has a sync outcome
+/// - the commits it was rewritten as, or its working copy equivalent, exist
+///   in the target repo
+/// - the commit sync config versions of its outcome still exist
+///
+/// Sync outcomes and target commits of up to `SYNC_HEALTH_CONCURRENCY`
+/// commits are fetched concurrently, and every version is only looked up
+/// once, so that thousands of commits can be checked.
+pub async fn check_sync_health<M: SyncedCommitMapping + Clone + 'static, R: Repo>(
+    ctx: &CoreContext,
+    commit_syncer: &CommitSyncer<M, R>,
+    bookmark: &BookmarkKey,
+    depth: u64,
+) -> Result<SyncHealthReport, Error> {
+    let source_repo = commit_syncer.get_source_repo();
+    let target_repo = commit_syncer.get_target_repo();
+
+    let head = source_repo
+        .bookmarks()
+        .get(ctx.clone(), bookmark)
+        .await?
+        .ok_or_else(|| {
+            format_err!(
+                "{} not found in {}",
+                bookmark,
+                source_repo.repo_identity().name()
+            )
+        })?;
+
+    let mut outcomes = source_repo
+        .commit_graph()
+        .ancestors_difference_stream(ctx, vec![head], vec![])
+        .await?
+        .take(depth as usize)
+        .map_ok(|source_cs_id| async move {
+            let maybe_outcome = commit_syncer
+                .get_plural_commit_sync_outcome(ctx, source_cs_id)
+                .await?;
+            let missing_targets = match &maybe_outcome {
+                Some(outcome) => {
+                    find_missing_targets(ctx, target_repo, source_cs_id, outcome).await?
+                }
+                None => vec![],
+            };
+            Ok::<_, Error>((source_cs_id, maybe_outcome, missing_targets))
+        })
+        .try_buffered(SYNC_HEALTH_CONCURRENCY);
+
+    let mut report = SyncHealthReport::default();
+    let mut existing_versions: HashMap<CommitSyncConfigVersion, bool> = HashMap::new();
+    while let Some((source_cs_id, maybe_outcome, missing_targets)) = outcomes.try_next().await? {
+        report.checked += 1;
+        let outcome = match maybe_outcome {
+            Some(outcome) => outcome,
+            None => {
+                report.add_violation(SyncHealthViolation::NoSyncOutcome { source_cs_id });
+                continue;
+            }
+        };
+        for violation in missing_targets {
+            report.add_violation(violation);
+        }
+
+        use PluralCommitSyncOutcome::*;
+        let versions: HashSet<_> = match outcome {
+            NotSyncCandidate(version) | EquivalentWorkingCopyAncestor(_, version) => {
+                HashSet::from([version])
+            }
+            RewrittenAs(cs_ids_versions) => cs_ids_versions
+                .into_iter()
+                .map(|(_, version)| version)
+                .collect(),
+        };
+        for version in versions {
+            let exists = match existing_versions.get(&version) {
+                Some(exists) => *exists,
+                None => {
+                    let exists = commit_syncer.version_exists(&version).await?;
+                    existing_versions.insert(version.clone(), exists);
+                    exists
+                }
+            };
+            if !exists {
+                report.add_violation(SyncHealthViolation::UnknownVersion {
+                    source_cs_id,
+                    version,
+                });
+            }
+        }
+    }
+
+    if !report.is_healthy() {
+        info!(
+            ctx.logger(),
+            "{} out of {} commits of {} have sync violations",
+            report.no_sync_outcome
+                + report.missing_target
+                + report.missing_wc_equivalent
+                + report.unknown_version,
+            report.checked,
+            bookmark,
+        );
+    }
+    Ok(report)
+}
+
+/// Violations for the target commits of `outcome` that can't be loaded from
+/// `target_repo`
+async fn find_missing_targets(
+    ctx: &CoreContext,
+    target_repo: &impl RepoBlobstoreRef,
+    source_cs_id: ChangesetId,
+    outcome: &PluralCommitSyncOutcome,
+) -> Result<Vec<SyncHealthViolation>, Error> {
+    use PluralCommitSyncOutcome::*;
+    let targets: Vec<(ChangesetId, bool)> = match outcome {
+        NotSyncCandidate(_) => vec![],
+        RewrittenAs(cs_ids_versions) => cs_ids_versions
+            .iter()
+            .map(|(target_cs_id, _)| (*target_cs_id, false))
+            .collect(),
+        EquivalentWorkingCopyAncestor(target_cs_id, _) => vec![(*target_cs_id, true)],
+    };
+
+    let missing = future::try_join_all(targets.into_iter().map(
+        |(target_cs_id, is_wc_equivalent)| async move {
+            match target_cs_id.load(ctx, target_repo.repo_blobstore()).await {
+                Ok(_) => Ok(None),
+                Err(LoadableError::Missing(_)) if is_wc_equivalent => {
+                    Ok(Some(SyncHealthViolation::MissingWorkingCopyEquivalent {
+                        source_cs_id,
+                        target_cs_id,
+                    }))
+                }
+                Err(LoadableError::Missing(_)) => Ok(Some(SyncHealthViolation::MissingTarget {
+                    source_cs_id,
+                    target_cs_id,
+                })),
+                Err(LoadableError::Error(err)) => Err(err),
+            }
+        },
+    ))
+    .await?;
+    Ok(missing.into_iter().flatten().collect())
+}
+
+/// Number of commits whose working copies `verify_working_copy_range`
+/// compares concurrently.
+const WC_RANGE_CONCURRENCY: usize = 4;
+/// Number of paths `verify_working_copy_range` looks up in the other repo at
+/// once.
+const WC_RANGE_CHUNK_SIZE: usize = 1000;
+
+/// A path whose file differs between the working copy of a source repo commit
+/// (after moving its paths) and the working copy of its synced counterpart.
+/// `expected` is the type and content of the file in the source repo, and
+/// `actual` is the type and content of the file in the target repo.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WcPathMismatch {
+    /// The path in the target repo
+    pub path: MPath,
+    /// The path in the source repo, if the path moves into the source repo
+    pub source_path: Option<MPath>,
+    pub expected: Option<(FileType, ContentId)>,
+    pub actual: Option<(FileType, ContentId)>,
+}
+
+/// The working copy differences found by `verify_working_copy_range` for a
+/// source repo commit and its synced counterpart in the target repo
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WcMismatch {
+    pub source_cs_id: ChangesetId,
+    pub target_cs_id: ChangesetId,
+    pub version: CommitSyncConfigVersion,
+    /// Files of the source commit that are missing in the target commit
+    pub missing: Vec<WcPathMismatch>,
+    /// Files of the target commit that are missing in the source commit,
+    /// even though they move into the source repo
+    pub extra: Vec<WcPathMismatch>,
+    /// Files present in both commits, but with a different type or content
+    pub different: Vec<WcPathMismatch>,
+}
+
+impl WcMismatch {
+    fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.different.is_empty()
+    }
+}
+
+/// Verify that the working copy of every synced commit in the source repo
+/// range from `start_cs` to `end_cs` (both inclusive) matches the working copy
+/// of its counterpart in the target repo, once moved with the mover of the
+/// commit sync config version recorded for the commit. At most `limit` commits
+/// are checked, starting from `start_cs`. Commits that weren't synced, or
+/// that aren't sync candidates, are skipped.
+///
+/// Unlike `verify_working_copy` this doesn't list the whole working copy of
+/// either commit in memory: the files of each side are streamed, and looked up
+/// in the other side in chunks. Only commits that don't match are returned.
+pub async fn verify_working_copy_range<M: SyncedCommitMapping + Clone + 'static, R: Repo>(
+    ctx: &CoreContext,
+    commit_syncer: &CommitSyncer<M, R>,
+    start_cs: ChangesetId,
+    end_cs: ChangesetId,
+    limit: u64,
+) -> Result<Vec<WcMismatch>, Error> {
+    let source_repo = commit_syncer.get_source_repo();
+
+    let synced = source_repo
+        .commit_graph()
+        .range_stream(ctx, start_cs, end_cs)
+        .await?
+        .take(limit as usize)
+        .map(|source_cs_id| async move {
+            let outcome = commit_syncer
+                .get_commit_sync_outcome(ctx, source_cs_id)
+                .await?;
+            use CommitSyncOutcome::*;
+            let synced = match outcome {
+                None | Some(NotSyncCandidate(_)) => None,
+                Some(RewrittenAs(target_cs_id, version))
+                | Some(EquivalentWorkingCopyAncestor(target_cs_id, version)) => {
+                    Some((source_cs_id, target_cs_id, version))
+                }
+            };
+            Ok::<_, Error>(synced)
+        })
+        .buffered(WC_RANGE_CONCURRENCY)
+        .try_filter_map(future::ok)
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    let mut movers: HashMap<CommitSyncConfigVersion, (Mover, Mover)> = HashMap::new();
+    for (_, _, version) in &synced {
+        if !movers.contains_key(version) {
+            let mover = commit_syncer.get_mover_by_version(version).await?;
+            let reverse_mover = commit_syncer.get_reverse_mover_by_version(version).await?;
+            movers.insert(version.clone(), (mover, reverse_mover));
+        }
+    }
+
+    let movers = &movers;
+    let mismatches = stream::iter(synced)
+        .map(|(source_cs_id, target_cs_id, version)| async move {
+            let (mover, reverse_mover) = movers
+                .get(&version)
+                .expect("movers of all versions were just fetched");
+            find_wc_mismatch(
+                ctx,
+                commit_syncer,
+                Source(source_cs_id),
+                Target(target_cs_id),
+                version,
+                mover,
+                reverse_mover,
+            )
+            .await
+        })
+        .buffered(WC_RANGE_CONCURRENCY)
+        .try_filter_map(future::ok)
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    if !mismatches.is_empty() {
+        info!(
+            ctx.logger(),
+            "{} commits between {} and {} have a different working copy",
+            mismatches.len(),
+            start_cs,
+            end_cs,
+        );
+    }
+    Ok(mismatches)
+}
+
+/// Compare the working copies of `source_cs_id` and `target_cs_id` by
+/// streaming the files of each of them and looking them up in the other one
+async fn find_wc_mismatch<M: SyncedCommitMapping + Clone + 'static, R: Repo>(
+    ctx: &CoreContext,
+    commit_syncer: &CommitSyncer<M, R>,
+    source_cs_id: Source<ChangesetId>,
+    target_cs_id: Target<ChangesetId>,
+    version: CommitSyncConfigVersion,
+    mover: &Mover,
+    reverse_mover: &Mover,
+) -> Result<Option<WcMismatch>, Error> {
+    if *source_cs_id == *target_cs_id {
+        // The commit was preserved as is, so both working copies are the same
+        return Ok(None);
+    }
+    let mut mismatch = WcMismatch {
+        source_cs_id: *source_cs_id,
+        target_cs_id: *target_cs_id,
+        version,
+        missing: vec![],
+        extra: vec![],
+        different: vec![],
+    };
+
+    let source_repo = commit_syncer.get_source_repo();
+    let target_repo = commit_syncer.get_target_repo();
+    let (source_root, target_root) = try_join!(
+        RootFsnodeId::derive(ctx, source_repo, *source_cs_id),
+        RootFsnodeId::derive(ctx, target_repo, *target_cs_id),
+    )?;
+    let source_root = *source_root.fsnode_id();
+    let target_root = *target_root.fsnode_id();
+
+    // Every file of the source commit that moves into the target repo must
+    // be present in the target commit, with the same type and content
+    let mut source_chunks = source_root
+        .list_leaf_entries(ctx.clone(), source_repo.repo_blobstore().clone())
+        .and_then(|(source_path, file)| {
+            let moved = mover(&source_path)
+                .map(|path| path.map(|path| (path, (source_path, type_and_content(&file)))));
+            future::ready(moved)
+        })
+        .try_filter_map(future::ok)
+        .try_chunks(WC_RANGE_CHUNK_SIZE)
+        .map_err(|err| err.1);
+    while let Some(chunk) = source_chunks.try_next().await? {
+        let mut expected: HashMap<MPath, (MPath, (FileType, ContentId))> =
+            chunk.into_iter().collect();
+        let found = find_files(
+            ctx,
+            target_repo,
+            target_root,
+            expected.keys().cloned().collect(),
+        )
+        .await?;
+        for (path, actual) in found {
+            if let Some((source_path, expected)) = expected.remove(&path) {
+                if expected != actual {
+                    mismatch.different.push(WcPathMismatch {
+                        path,
+                        source_path: Some(source_path),
+                        expected: Some(expected),
+                        actual: Some(actual),
+                    });
+                }
+            }
+        }
+        mismatch
+            .missing
+            .extend(
+                expected
+                    .into_iter()
+                    .map(|(path, (source_path, expected))| WcPathMismatch {
+                        path,
+                        source_path: Some(source_path),
+                        expected: Some(expected),
+                        actual: None,
+                    }),
+            );
+    }
+
+    // Every file of the target commit that moves into the source repo must
+    // be present in the source commit. Files present in both were already
+    // compared above.
+    let mut target_chunks = target_root
+        .list_leaf_entries(ctx.clone(), target_repo.repo_blobstore().clone())
+        .and_then(|(path, file)| {
+            let moved = reverse_mover(&path).map(|source_path| {
+                source_path.map(|source_path| (source_path, (path, type_and_content(&file))))
+            });
+            future::ready(moved)
+        })
+        .try_filter_map(future::ok)
+        .try_chunks(WC_RANGE_CHUNK_SIZE)
+        .map_err(|err| err.1);
+    while let Some(chunk) = target_chunks.try_next().await? {
+        let mut actual: HashMap<MPath, (MPath, (FileType, ContentId))> =
+            chunk.into_iter().collect();
+        let found = find_files(
+            ctx,
+            source_repo,
+            source_root,
+            actual.keys().cloned().collect(),
+        )
+        .await?;
+        for (source_path, _) in found {
+            actual.remove(&source_path);
+        }
+        mismatch
+            .extra
+            .extend(
+                actual
+                    .into_iter()
+                    .map(|(source_path, (path, actual))| WcPathMismatch {
+                        path,
+                        source_path: Some(source_path),
+                        expected: None,
+                        actual: Some(actual),
+                    }),
+            );
+    }
+
+    if mismatch.is_empty() {
+        Ok(None)
+    } else {
+        error!(
+            ctx.logger(),
+            "{} in {} has {} missing, {} extra and {} different files in {} ({})",
+            mismatch.source_cs_id,
+            source_repo.repo_identity().name(),
+            mismatch.missing.len(),
+            mismatch.extra.len(),
+            mismatch.different.len(),
+            mismatch.target_cs_id,
+            target_repo.repo_identity().name(),
+        );
+        Ok(Some(mismatch))
+    }
+}
+
+fn type_and_content(file: &FsnodeFile) -> (FileType, ContentId) {
+    (*file.file_type(), *file.content_id())
+}
+
+/// Look up the files at `paths` in the fsnode manifest `root`. Paths that
+/// don't exist, or that are directories, are omitted from the result.
+async fn find_files(
+    ctx: &CoreContext,
+    repo: &impl RepoBlobstoreRef,
+    root: FsnodeId,
+    paths: Vec<MPath>,
+) -> Result<Vec<(MPath, (FileType, ContentId))>, Error> {
+    root.find_entries(ctx.clone(), repo.repo_blobstore().clone(), paths)
+        .try_filter_map(|(path, entry)| {
+            future::ok(match (path, entry) {
+                (Some(path), Entry::Leaf(file)) => Some((path, type_and_content(&file))),
+                _ => None,
+            })
+        })
+        .try_collect()
+        .await
+}
+
 #[cfg(test)]
 mod test {
     use std::str::FromStr;
@@ -1314,6 +1829,7 @@ mod test {
     use metaconfig_types::SmallRepoPermanentConfig;
     use mononoke_types::MPath;
     use mononoke_types::RepositoryId;
+    use mononoke_types_mocks::changesetid::ONES_CSID;
     use revset::AncestorsNodeStream;
     use sql_construct::SqlConstruct;
     use synced_commit_mapping::SqlSyncedCommitMapping;
@@ -1601,6 +2117,89 @@ mod test {
         Ok(())
     }
 
+    #[fbinit::test]
+    async fn test_check_sync_health(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let commit_syncer = init(fb, CommitSyncDirection::LargeToSmall).await?;
+        let large_repo = commit_syncer.get_large_repo();
+        let small_repo = commit_syncer.get_small_repo();
+        let master = BookmarkKey::new("master")?;
+
+        // All the commits of the fixture are synced
+        let report = check_sync_health(&ctx, &commit_syncer, &master, 1000).await?;
+        assert!(report.is_healthy());
+        let fixture_commits = report.checked;
+        assert!(fixture_commits > 2);
+
+        // Rewritten as a commit that doesn't exist in the small repo
+        let missing_target = CreateCommitContext::new(&ctx, &large_repo, vec!["master"])
+            .add_file("missing_target", "content")
+            .commit()
+            .await?;
+        // Synced with a version that isn't in the config
+        let stale_version = CreateCommitContext::new(&ctx, &large_repo, vec![missing_target])
+            .add_file("stale_version", "content")
+            .commit()
+            .await?;
+        let stale_version_target = CreateCommitContext::new(&ctx, &small_repo, vec!["master"])
+            .add_file("stale_version", "content")
+            .commit()
+            .await?;
+        bookmark(&ctx, &large_repo, "master")
+            .set_to(stale_version)
+            .await?;
+
+        let mapping = commit_syncer.get_mapping();
+        for (large_bcs_id, small_bcs_id, version) in [
+            (missing_target, ONES_CSID, "noop"),
+            (stale_version, stale_version_target, "stale"),
+        ] {
+            mapping
+                .add(
+                    &ctx,
+                    SyncedCommitMappingEntry {
+                        large_repo_id: large_repo.repo_identity().id(),
+                        small_repo_id: small_repo.repo_identity().id(),
+                        small_bcs_id,
+                        large_bcs_id,
+                        version_name: Some(CommitSyncConfigVersion(version.to_string())),
+                        source_repo: Some(commit_syncer.get_source_repo_type()),
+                    },
+                )
+                .await?;
+        }
+
+        let report = check_sync_health(&ctx, &commit_syncer, &master, 1000).await?;
+        assert_eq!(
+            report,
+            SyncHealthReport {
+                checked: fixture_commits + 2,
+                no_sync_outcome: 0,
+                missing_target: 1,
+                missing_wc_equivalent: 0,
+                unknown_version: 1,
+                violations: vec![
+                    SyncHealthViolation::UnknownVersion {
+                        source_cs_id: stale_version,
+                        version: CommitSyncConfigVersion("stale".to_string()),
+                    },
+                    SyncHealthViolation::MissingTarget {
+                        source_cs_id: missing_target,
+                        target_cs_id: ONES_CSID,
+                    },
+                ],
+            }
+        );
+
+        // Only the newest commit is checked
+        let report = check_sync_health(&ctx, &commit_syncer, &master, 1).await?;
+        assert_eq!(report.checked, 1);
+        assert_eq!(report.unknown_version, 1);
+        assert_eq!(report.missing_target, 0);
+
+        Ok(())
+    }
+
     fn prefix_mover(v: &MPath) -> Result<Option<MPath>, Error> {
         let prefix = MPath::new("prefix").unwrap();
         Ok(Some(MPath::join(&prefix, v)))
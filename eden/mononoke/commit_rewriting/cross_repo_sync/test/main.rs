@@ -8,13 +8,17 @@
 //! Tests for the synced commits mapping.
 use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
 
 use anyhow::anyhow;
 use anyhow::Error;
 use ascii::AsciiString;
 use assert_matches::assert_matches;
+use async_trait::async_trait;
 use blobrepo::save_bonsai_changesets;
 use blobstore::Loadable;
 use bonsai_hg_mapping::BonsaiHgMappingRef;
@@ -23,25 +27,41 @@ use bookmarks::BookmarkUpdateReason;
 use bookmarks::BookmarksRef;
 use cacheblob::InProcessLease;
 use changeset_fetcher::ChangesetFetcherRef;
+use commit_transformation::ErrorKind as RewriteErrorKind;
 use context::CoreContext;
+use cross_repo_sync::find_toposorted_unsynced_ancestors;
+use cross_repo_sync::types::Source;
 use cross_repo_sync::types::Target;
 use cross_repo_sync::update_mapping_with_version;
 use cross_repo_sync::validation::verify_working_copy;
+use cross_repo_sync::validation::verify_working_copy_range;
+use cross_repo_sync::validation::WcPathMismatch;
+use cross_repo_sync::BookmarkDiff;
 use cross_repo_sync::CandidateSelectionHint;
 use cross_repo_sync::CommitSyncContext;
 use cross_repo_sync::CommitSyncDataProvider;
 use cross_repo_sync::CommitSyncOutcome;
 use cross_repo_sync::CommitSyncRepos;
 use cross_repo_sync::CommitSyncer;
+use cross_repo_sync::DetailedSyncOutcome;
 use cross_repo_sync::ErrorKind;
+use cross_repo_sync::ExtrasPolicy;
+use cross_repo_sync::ParentOverrideStrictness;
 use cross_repo_sync::PluralCommitSyncOutcome;
 use cross_repo_sync::PushrebaseRewriteDates;
+use cross_repo_sync::SyncPlanDecision;
+use cross_repo_sync::TargetPathLimits;
+use cross_repo_sync::TargetPathViolation;
+use cross_repo_sync::CHANGE_XREPO_MAPPING_EXTRA;
+use cross_repo_sync::SYNCED_FROM_EXTRA;
 use cross_repo_sync_test_utils::rebase_root_on_master;
+use cross_repo_sync_test_utils::RecordingSyncReporter;
 use cross_repo_sync_test_utils::TestRepo;
 use fbinit::FacebookInit;
 use fixtures::Linear;
 use fixtures::ManyFilesDirs;
 use fixtures::TestRepoFixture;
+use futures::future::try_join;
 use futures::FutureExt;
 use futures::TryStreamExt;
 use live_commit_sync_config::TestLiveCommitSyncConfig;
@@ -49,6 +69,7 @@ use live_commit_sync_config::TestLiveCommitSyncConfigSource;
 use manifest::ManifestOps;
 use maplit::btreemap;
 use maplit::hashmap;
+use maplit::hashset;
 use mercurial_derivation::DeriveHgChangeset;
 use mercurial_types::HgChangesetId;
 use metaconfig_types::CommitSyncConfig;
@@ -61,6 +82,7 @@ use mononoke_types::BonsaiChangesetMut;
 use mononoke_types::ChangesetId;
 use mononoke_types::DateTime;
 use mononoke_types::FileChange;
+use mononoke_types::FileType;
 use mononoke_types::MPath;
 use mononoke_types::RepositoryId;
 use pushrebase::PushrebaseError;
@@ -69,9 +91,12 @@ use repo_identity::RepoIdentityRef;
 use sorted_vector_map::sorted_vector_map;
 use sql::rusqlite::Connection as SqliteConnection;
 use sql_construct::SqlConstruct;
+use synced_commit_mapping::EquivalentWorkingCopyEntry;
 use synced_commit_mapping::SqlSyncedCommitMapping;
 use synced_commit_mapping::SyncedCommitMapping;
 use synced_commit_mapping::SyncedCommitMappingEntry;
+use synced_commit_mapping::SyncedCommitSourceRepo;
+use synced_commit_mapping::WorkingCopyEquivalence;
 use test_repo_factory::TestRepoFactory;
 use tests_utils::bookmark;
 use tests_utils::resolve_cs_id;
@@ -267,6 +292,24 @@ fn create_small_to_large_commit_syncer(
     large_repo: TestRepo,
     prefix: &str,
     mapping: SqlSyncedCommitMapping,
+) -> Result<CommitSyncer<SqlSyncedCommitMapping, TestRepo>, Error> {
+    create_small_to_large_commit_syncer_with_bookmark_prefix(
+        ctx,
+        small_repo,
+        large_repo,
+        prefix,
+        mapping,
+        AsciiString::new(),
+    )
+}
+
+fn create_small_to_large_commit_syncer_with_bookmark_prefix(
+    ctx: &CoreContext,
+    small_repo: TestRepo,
+    large_repo: TestRepo,
+    prefix: &str,
+    mapping: SqlSyncedCommitMapping,
+    bookmark_prefix: AsciiString,
 ) -> Result<CommitSyncer<SqlSyncedCommitMapping, TestRepo>, Error> {
     let small_repo_id = small_repo.repo_identity().id();
     let large_repo_id = large_repo.repo_identity().id();
@@ -275,7 +318,7 @@ fn create_small_to_large_commit_syncer(
         common_pushrebase_bookmarks: vec![],
         small_repos: hashmap! {
             small_repo.repo_identity().id() => SmallRepoPermanentConfig {
-                bookmark_prefix: AsciiString::new(),
+                bookmark_prefix,
             }
         },
         large_repo_id: large_repo.repo_identity().id(),
@@ -1064,6 +1107,63 @@ async fn test_sync_parent_has_multiple_mappings(fb: FacebookInit) -> Result<(),
     Ok(())
 }
 
+#[fbinit::test]
+async fn test_sync_commit_for_bookmark_parent_has_multiple_mappings(
+    fb: FacebookInit,
+) -> Result<(), Error> {
+    let (
+        ctx,
+        small_repo,
+        megarepo,
+        megarepo_master_cs_id,
+        small_repo_master_cs_id,
+        small_to_large_syncer,
+    ) = get_multiple_master_mapping_setup(fb).await?;
+
+    // Create a small repo commit on top of master, which was synced twice
+    let to_sync = create_commit_from_parent_and_changes(
+        &ctx,
+        &small_repo,
+        small_repo_master_cs_id,
+        btreemap! {"foo" => "bar"},
+    )
+    .await;
+
+    // The parent is ambiguous without a hint
+    let e = small_to_large_syncer
+        .sync_commit(
+            &ctx,
+            to_sync,
+            CandidateSelectionHint::Only,
+            CommitSyncContext::Tests,
+            false,
+        )
+        .await
+        .expect_err("sync should have failed");
+    assert!(format!("{:?}", e).contains("Too many rewritten candidates for"));
+
+    // The bookmark selects the parent synced to it
+    let synced = small_to_large_syncer
+        .sync_commit_for_bookmark(
+            &ctx,
+            to_sync,
+            BookmarkKey::new("master").unwrap(),
+            CommitSyncContext::Tests,
+            false,
+        )
+        .await?
+        .expect("commit should have been synced");
+    assert_eq!(
+        megarepo
+            .changeset_fetcher()
+            .get_parents(&ctx, synced)
+            .await?,
+        vec![megarepo_master_cs_id]
+    );
+
+    Ok(())
+}
+
 #[fbinit::test]
 async fn test_sync_no_op_pushrebase_has_multiple_mappings(fb: FacebookInit) -> Result<(), Error> {
     let (
@@ -1254,280 +1354,1814 @@ async fn test_sync_with_mapping_change(fb: FacebookInit) -> Result<(), Error> {
 }
 
 #[fbinit::test]
-async fn test_sync_equivalent_wc_with_mapping_change(fb: FacebookInit) -> Result<(), Error> {
+async fn test_verify_working_copy_range(fb: FacebookInit) -> Result<(), Error> {
     let ctx = CoreContext::test_mock(fb);
-    let (old_version, new_version, large_to_small_syncer) =
+    let (_old_version, new_version, large_to_small_syncer) =
         prepare_commit_syncer_with_mapping_change(fb).await?;
     let megarepo = large_to_small_syncer.get_source_repo();
     let small_repo = large_to_small_syncer.get_target_repo();
 
-    println!("create commits with new mapping");
     let new_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "new_mapping").await?;
-    // Create a stack of commits on top of commit with new mapping.
-    // First commit should not rewrite into a small repo, but second should
-
-    let does_not_rewrite_large_cs_id =
-        CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
-            .add_file("somerandomfile", "1")
-            .commit()
-            .await?;
-
-    let synced = large_to_small_syncer
+    let synced_cs_id = CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
+        .add_file("prefix/dir/a", "a")
+        .commit()
+        .await?;
+    let small_synced_cs_id = large_to_small_syncer
         .sync_commit(
             &ctx,
-            does_not_rewrite_large_cs_id,
+            synced_cs_id,
             CandidateSelectionHint::Only,
             CommitSyncContext::Tests,
             false,
         )
+        .await?
+        .ok_or_else(|| anyhow!("{} was not synced", synced_cs_id))?;
+
+    let mismatches = verify_working_copy_range(
+        &ctx,
+        &large_to_small_syncer,
+        new_mapping_large_cs_id,
+        synced_cs_id,
+        10,
+    )
+    .await?;
+    assert!(mismatches.is_empty(), "{:?}", mismatches);
+
+    // Record a small repo commit that doesn't match the large repo commit
+    // as its synced counterpart
+    let broken_cs_id = CreateCommitContext::new(&ctx, &megarepo, vec![synced_cs_id])
+        .add_file("prefix/dir/b", "b")
+        .add_file("prefix/dir/missing", "missing")
+        .commit()
         .await?;
-    let parent_synced = large_to_small_syncer
+    let small_broken_cs_id = CreateCommitContext::new(&ctx, &small_repo, vec![small_synced_cs_id])
+        .add_file("dir/b", "not b")
+        .add_file("dir/extra", "extra")
+        .commit()
+        .await?;
+    let entry = SyncedCommitMappingEntry::new(
+        megarepo.repo_identity().id(),
+        broken_cs_id,
+        small_repo.repo_identity().id(),
+        small_broken_cs_id,
+        new_version.clone(),
+        large_to_small_syncer.get_source_repo_type(),
+    );
+    large_to_small_syncer.get_mapping().add(&ctx, entry).await?;
+
+    let mismatches = verify_working_copy_range(
+        &ctx,
+        &large_to_small_syncer,
+        new_mapping_large_cs_id,
+        broken_cs_id,
+        10,
+    )
+    .await?;
+    assert_eq!(mismatches.len(), 1);
+    let mismatch = &mismatches[0];
+    assert_eq!(mismatch.source_cs_id, broken_cs_id);
+    assert_eq!(mismatch.target_cs_id, small_broken_cs_id);
+    assert_eq!(mismatch.version, new_version);
+
+    let paths = |mismatches: &[WcPathMismatch]| -> Vec<MPath> {
+        mismatches.iter().map(|m| m.path.clone()).collect()
+    };
+    assert_eq!(paths(&mismatch.missing), vec![MPath::new("dir/missing")?]);
+    assert_eq!(
+        mismatch.missing[0].source_path,
+        Some(MPath::new("prefix/dir/missing")?)
+    );
+    assert!(mismatch.missing[0].expected.is_some());
+    assert!(mismatch.missing[0].actual.is_none());
+
+    assert_eq!(paths(&mismatch.extra), vec![MPath::new("dir/extra")?]);
+    assert!(mismatch.extra[0].expected.is_none());
+    assert!(mismatch.extra[0].actual.is_some());
+
+    assert_eq!(paths(&mismatch.different), vec![MPath::new("dir/b")?]);
+    assert_ne!(mismatch.different[0].expected, mismatch.different[0].actual);
+
+    // The limit only checks the first commits of the range
+    let mismatches = verify_working_copy_range(
+        &ctx,
+        &large_to_small_syncer,
+        new_mapping_large_cs_id,
+        broken_cs_id,
+        2,
+    )
+    .await?;
+    assert!(mismatches.is_empty(), "{:?}", mismatches);
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_get_commit_sync_outcomes(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let (_old_version, _new_version, large_to_small_syncer) =
+        prepare_commit_syncer_with_mapping_change(fb).await?;
+    let megarepo = large_to_small_syncer.get_source_repo();
+
+    let old_mapping_cs_id = resolve_cs_id(&ctx, &megarepo, "old_mapping").await?;
+    let new_mapping_cs_id = resolve_cs_id(&ctx, &megarepo, "new_mapping").await?;
+    // Rewritten to nothing in the small repo
+    let large_only_cs_id = CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_cs_id])
+        .add_file("large_only_file", "1")
+        .commit()
+        .await?;
+    large_to_small_syncer
         .sync_commit(
             &ctx,
-            new_mapping_large_cs_id,
+            large_only_cs_id,
             CandidateSelectionHint::Only,
             CommitSyncContext::Tests,
             false,
         )
         .await?;
-    // does_not_rewrite_large_cs_id commit was rewritten out, so sync_commit
-    // should return the same changeset id as the parent
-    assert_eq!(synced, parent_synced);
+    let unsynced_cs_id = CreateCommitContext::new(&ctx, &megarepo, vec![large_only_cs_id])
+        .add_file("prefix/unsynced", "1")
+        .commit()
+        .await?;
 
-    let new_mapping_cs_id =
-        CreateCommitContext::new(&ctx, &megarepo, vec![does_not_rewrite_large_cs_id])
-            .add_file("tools/newtool", "1")
-            .delete_file("tools/1.txt")
-            .add_file("tools/somefile", "somefile1")
-            .add_file("prefix/dir/file", "3")
-            .add_file("prefix/dir/newfile", "3")
-            .commit()
+    let cs_ids = vec![
+        old_mapping_cs_id,
+        new_mapping_cs_id,
+        large_only_cs_id,
+        unsynced_cs_id,
+    ];
+    let outcomes = large_to_small_syncer
+        .get_commit_sync_outcomes(&ctx, cs_ids.clone())
+        .await?;
+    assert_eq!(outcomes.len(), cs_ids.len());
+    for cs_id in &cs_ids {
+        let outcome = large_to_small_syncer
+            .get_commit_sync_outcome(&ctx, *cs_id)
             .await?;
+        assert_eq!(outcomes.get(cs_id), Some(&outcome));
+    }
+    assert!(matches!(
+        outcomes[&new_mapping_cs_id],
+        Some(CommitSyncOutcome::RewrittenAs(..))
+    ));
+    assert!(matches!(
+        outcomes[&large_only_cs_id],
+        Some(CommitSyncOutcome::EquivalentWorkingCopyAncestor(..))
+    ));
+    assert_eq!(outcomes[&unsynced_cs_id], None);
+
+    let plural_outcomes = large_to_small_syncer
+        .get_plural_commit_sync_outcomes(&ctx, cs_ids.clone())
+        .await?;
+    for cs_id in &cs_ids {
+        let outcome = large_to_small_syncer
+            .get_plural_commit_sync_outcome(&ctx, *cs_id)
+            .await?;
+        assert_eq!(plural_outcomes.get(cs_id), Some(&outcome));
+    }
+    Ok(())
+}
 
-    let synced = large_to_small_syncer
-        .sync_commit(
+#[fbinit::test]
+async fn test_sync_commit_with_report(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let (_old_version, new_version, large_to_small_syncer) =
+        prepare_commit_syncer_with_mapping_change(fb).await?;
+    let megarepo = large_to_small_syncer.get_source_repo();
+    let small_repo = large_to_small_syncer.get_target_repo();
+
+    let new_mapping_cs_id = resolve_cs_id(&ctx, &megarepo, "new_mapping").await?;
+    let cs_id = CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_cs_id])
+        .add_file("prefix/newfile", "1")
+        // Dropped, it's not in the small repo
+        .add_file("large_only_file", "1")
+        // Implicitly deletes "prefix/dir/file"
+        .add_file("prefix/dir", "1")
+        .commit()
+        .await?;
+
+    let (synced, report) = large_to_small_syncer
+        .sync_commit_with_report(
             &ctx,
-            new_mapping_cs_id,
+            cs_id,
             CandidateSelectionHint::Only,
             CommitSyncContext::Tests,
             false,
         )
         .await?;
-    assert!(synced.is_some());
-    let new_mapping_small_cs_id = synced.unwrap();
-
-    verify_working_copy(
-        ctx.clone(),
-        large_to_small_syncer.clone(),
-        new_mapping_cs_id,
-    )
-    .await?;
+    let small_cs_id = synced.ok_or_else(|| anyhow!("{} was not synced", cs_id))?;
+    let report = report.ok_or_else(|| anyhow!("no report for {}", cs_id))?;
+    assert_eq!(report.version, new_version);
+    assert_eq!(report.rewritten_file_changes, 2);
+    assert_eq!(report.dropped_file_changes, 1);
+    assert_eq!(report.implicit_deletes, 1);
+    assert_eq!(report.pushrebase_retries, 0);
+    assert_eq!(report.pushrebase_time, Duration::ZERO);
     assert_working_copy(
         &ctx,
         small_repo,
-        new_mapping_small_cs_id,
-        vec!["tools/somefile", "tools/newtool", "dir/file", "dir/newfile"],
+        small_cs_id,
+        vec!["tools/1.txt", "tools/somefile", "newfile", "dir"],
     )
     .await?;
 
-    let outcome = large_to_small_syncer
-        .get_commit_sync_outcome(&ctx, new_mapping_cs_id)
-        .await?;
-
-    match outcome {
-        Some(CommitSyncOutcome::RewrittenAs(_, version)) => {
-            assert_eq!(version, new_version);
-        }
-        _ => {
-            return Err(anyhow!("unexpected outcome: {:?}", outcome));
-        }
-    }
-
-    // Create a new commit on top of commit with old mapping.
-    println!("create commits with old mapping");
-
-    let old_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "old_mapping").await?;
-    let does_not_rewrite_large_cs_id =
-        CreateCommitContext::new(&ctx, &megarepo, vec![old_mapping_large_cs_id])
-            .add_file("somerandomfile", "1")
-            .commit()
-            .await?;
-
-    let old_mapping_cs_id =
-        CreateCommitContext::new(&ctx, &megarepo, vec![does_not_rewrite_large_cs_id])
-            .add_file("tools/3.txt", "2")
-            .add_file("prefix/file", "2")
-            .commit()
-            .await?;
-    let synced = large_to_small_syncer
-        .sync_commit(
+    // There is nothing to report for commits that were already synced
+    let (synced, report) = large_to_small_syncer
+        .sync_commit_with_report(
             &ctx,
-            old_mapping_cs_id,
+            cs_id,
             CandidateSelectionHint::Only,
             CommitSyncContext::Tests,
             false,
         )
         .await?;
-    assert!(synced.is_some());
-    let old_mapping_small_cs_id = synced.unwrap();
+    assert_eq!(synced, Some(small_cs_id));
+    assert_eq!(report, None);
+    Ok(())
+}
 
-    verify_working_copy(
-        ctx.clone(),
-        large_to_small_syncer.clone(),
-        old_mapping_cs_id,
-    )
-    .await?;
-    assert_working_copy(
-        &ctx,
-        small_repo,
-        old_mapping_small_cs_id,
-        vec!["dir/file", "file", "tools/1.txt"],
-    )
-    .await?;
+#[fbinit::test]
+async fn test_sync_extras_policy(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let (_old_version, new_version, large_to_small_syncer) =
+        prepare_commit_syncer_with_mapping_change(fb).await?;
+    let megarepo = large_to_small_syncer.get_source_repo();
+    let small_repo = large_to_small_syncer.get_target_repo();
+    let stripping_syncer = large_to_small_syncer
+        .clone()
+        .with_extras_policy(ExtrasPolicy {
+            strip_keys: vec![CHANGE_XREPO_MAPPING_EXTRA.to_string()],
+            add_synced_from: true,
+        });
 
-    let outcome = large_to_small_syncer
-        .get_commit_sync_outcome(&ctx, old_mapping_cs_id)
+    let new_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "new_mapping").await?;
+    let stripped = CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
+        .add_file("prefix/stripped", "1")
+        .add_extra(CHANGE_XREPO_MAPPING_EXTRA, new_version.0.clone())
+        .add_extra("kept", "value")
+        .commit()
+        .await?;
+    let kept = CreateCommitContext::new(&ctx, &megarepo, vec![stripped])
+        .add_file("prefix/kept", "1")
+        .add_extra(CHANGE_XREPO_MAPPING_EXTRA, new_version.0.clone())
+        .add_extra("kept", "value")
+        .commit()
         .await?;
 
-    match outcome {
-        Some(CommitSyncOutcome::RewrittenAs(_, version)) => {
-            assert_eq!(version, old_version);
-        }
-        _ => {
-            return Err(anyhow!("unexpected outcome: {:?}", outcome));
+    let sync = |commit_syncer: CommitSyncer<SqlSyncedCommitMapping, TestRepo>, cs_id| {
+        let ctx = ctx.clone();
+        let tunables = MononokeTunables::default();
+        tunables.update_bools(&hashmap! {"allow_change_xrepo_mapping_extra".to_string() => true});
+        with_tunables_async(
+            tunables,
+            async move {
+                commit_syncer
+                    .sync_commit(
+                        &ctx,
+                        cs_id,
+                        CandidateSelectionHint::Only,
+                        CommitSyncContext::Tests,
+                        false,
+                    )
+                    .await
+            }
+            .boxed(),
+        )
+    };
+    let small_extras = |cs_id: ChangesetId| {
+        let ctx = ctx.clone();
+        async move {
+            let cs = cs_id.load(&ctx, small_repo.repo_blobstore()).await?;
+            Result::<_, Error>::Ok(cs.into_mut().hg_extra)
         }
-    }
+    };
+
+    // The provenance extra round trips through the blobstore, and the
+    // mapping change marker is not propagated.
+    let stripped_small = sync(stripping_syncer, stripped)
+        .await?
+        .ok_or_else(|| anyhow!("{} was not synced", stripped))?;
+    let extras = small_extras(stripped_small).await?;
+    assert_eq!(extras.get("kept"), Some(&b"value".to_vec()));
+    assert_eq!(extras.get(CHANGE_XREPO_MAPPING_EXTRA), None);
+    assert_eq!(
+        extras.get(SYNCED_FROM_EXTRA),
+        Some(&stripped.to_string().into_bytes())
+    );
+
+    // By default, extras are kept as they are.
+    let kept_small = sync(large_to_small_syncer.clone(), kept)
+        .await?
+        .ok_or_else(|| anyhow!("{} was not synced", kept))?;
+    let extras = small_extras(kept_small).await?;
+    assert_eq!(extras.get("kept"), Some(&b"value".to_vec()));
+    assert_eq!(
+        extras.get(CHANGE_XREPO_MAPPING_EXTRA),
+        Some(&new_version.0.clone().into_bytes())
+    );
+    assert_eq!(extras.get(SYNCED_FROM_EXTRA), None);
     Ok(())
 }
 
 #[fbinit::test]
-async fn test_disabled_sync(fb: FacebookInit) -> Result<(), Error> {
+async fn test_sync_commit_detailed(fb: FacebookInit) -> Result<(), Error> {
     let ctx = CoreContext::test_mock(fb);
-    let (_, _, large_to_small_syncer) = prepare_commit_syncer_with_mapping_change(fb).await?;
+    let (_old_version, new_version, large_to_small_syncer) =
+        prepare_commit_syncer_with_mapping_change(fb).await?;
+    let reporter = Arc::new(RecordingSyncReporter::default());
+    let large_to_small_syncer = CommitSyncer::new_with_provider_and_reporter(
+        large_to_small_syncer.mapping.clone(),
+        large_to_small_syncer.repos.clone(),
+        large_to_small_syncer.commit_sync_data_provider.clone(),
+        reporter.clone(),
+    );
     let megarepo = large_to_small_syncer.get_source_repo();
 
     let new_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "new_mapping").await?;
-    // Create a new commit on top of commit with new mapping.
-    let new_mapping_cs_id =
-        CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
-            .add_file("tools/newtool", "1")
-            .commit()
-            .await?;
-
-    let tunables = MononokeTunables::default();
+    let parent_synced = large_to_small_syncer
+        .sync_commit(
+            &ctx,
+            new_mapping_large_cs_id,
+            CandidateSelectionHint::Only,
+            CommitSyncContext::Tests,
+            false,
+        )
+        .await?
+        .ok_or_else(|| anyhow!("new_mapping commit was not synced"))?;
+
+    // A commit that rewrites into the small repo is created by the first sync,
+    // and looked up by the second one.
+    let rewrites_large_cs_id =
+        CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
+            .add_file("tools/newtool", "1")
+            .commit()
+            .await?;
+    let outcome = large_to_small_syncer
+        .sync_commit_detailed(
+            &ctx,
+            rewrites_large_cs_id,
+            CandidateSelectionHint::Only,
+            CommitSyncContext::Tests,
+            false,
+        )
+        .await?;
+    let rewritten_small_cs_id = match outcome {
+        DetailedSyncOutcome::CreatedTarget(cs_id, ref version) => {
+            assert_eq!(version, &new_version);
+            cs_id
+        }
+        _ => {
+            return Err(anyhow!("unexpected outcome: {:?}", outcome));
+        }
+    };
+    assert_eq!(
+        large_to_small_syncer
+            .sync_commit_detailed(
+                &ctx,
+                rewrites_large_cs_id,
+                CandidateSelectionHint::Only,
+                CommitSyncContext::Tests,
+                false,
+            )
+            .await?,
+        DetailedSyncOutcome::AlreadySynced(rewritten_small_cs_id, new_version.clone())
+    );
+
+    // A commit that touches only files outside of the small repo is rewritten
+    // to nothing, and resolves to its parent's synced commit.
+    let does_not_rewrite_large_cs_id =
+        CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
+            .add_file("somerandomfile", "1")
+            .commit()
+            .await?;
+    let outcome = large_to_small_syncer
+        .sync_commit_detailed(
+            &ctx,
+            does_not_rewrite_large_cs_id,
+            CandidateSelectionHint::Only,
+            CommitSyncContext::Tests,
+            false,
+        )
+        .await?;
+    assert_eq!(
+        outcome,
+        DetailedSyncOutcome::RewrittenToNothing {
+            wc_equivalent: parent_synced,
+            version: new_version,
+        }
+    );
+    assert_eq!(outcome.target_cs_id(), Some(parent_synced));
+
+    // Every call is reported, with the outcome it resolved to.
+    let reports = reporter.reports();
+    for report in &reports {
+        assert_eq!(report.sync_fn, "sync_commit");
+        assert_eq!(report.commit_sync_context, CommitSyncContext::Tests);
+    }
+    assert_eq!(
+        reports
+            .into_iter()
+            .map(|report| (report.source_cs_id, report.result, report.sync_outcome))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                new_mapping_large_cs_id,
+                Ok(Some(parent_synced)),
+                Some("already_synced")
+            ),
+            (
+                rewrites_large_cs_id,
+                Ok(Some(rewritten_small_cs_id)),
+                Some("created_target")
+            ),
+            (
+                rewrites_large_cs_id,
+                Ok(Some(rewritten_small_cs_id)),
+                Some("already_synced")
+            ),
+            (
+                does_not_rewrite_large_cs_id,
+                Ok(Some(parent_synced)),
+                Some("rewritten_to_nothing")
+            ),
+        ]
+    );
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_plan_sync_matches_sync(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let (_old_version, new_version, large_to_small_syncer) =
+        prepare_commit_syncer_with_mapping_change(fb).await?;
+    // Paths longer than 20 bytes can't be synced.
+    let large_to_small_syncer =
+        large_to_small_syncer.with_target_path_policy(Arc::new(TargetPathLimits {
+            max_path_bytes: Some(20),
+            ..Default::default()
+        }));
+    let megarepo = large_to_small_syncer.get_source_repo();
+    let new_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "new_mapping").await?;
+
+    let rewrites = CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
+        .add_file("tools/newtool", "1")
+        .commit()
+        .await?;
+    let rewrites_to_nothing = CreateCommitContext::new(&ctx, &megarepo, vec![rewrites])
+        .add_file("somerandomfile", "1")
+        .commit()
+        .await?;
+    let rewrites_on_nothing = CreateCommitContext::new(&ctx, &megarepo, vec![rewrites_to_nothing])
+        .add_file("prefix/dir/other", "1")
+        .commit()
+        .await?;
+    let fails = CreateCommitContext::new(&ctx, &megarepo, vec![rewrites_on_nothing])
+        .add_file("prefix/a_long_directory/file", "1")
+        .commit()
+        .await?;
+    let descendant_of_failure = CreateCommitContext::new(&ctx, &megarepo, vec![fails])
+        .add_file("tools/newtool", "2")
+        .commit()
+        .await?;
+
+    let plan = large_to_small_syncer
+        .plan_sync(
+            &ctx,
+            vec![
+                new_mapping_large_cs_id,
+                rewrites_on_nothing,
+                descendant_of_failure,
+            ],
+            CandidateSelectionHint::Only,
+        )
+        .await?;
+    assert_eq!(
+        plan.iter()
+            .map(|entry| entry.source_cs_id)
+            .collect::<Vec<_>>(),
+        vec![
+            new_mapping_large_cs_id,
+            rewrites,
+            rewrites_to_nothing,
+            rewrites_on_nothing,
+            fails,
+            descendant_of_failure,
+        ]
+    );
+    assert_matches!(
+        &plan[1].decision,
+        SyncPlanDecision::WouldCreate { file_changes: 1, version, .. } if version == &new_version
+    );
+    // Nothing was synced by planning.
+    for entry in &plan[1..] {
+        assert!(
+            large_to_small_syncer
+                .get_commit_sync_outcome(&ctx, entry.source_cs_id)
+                .await?
+                .is_none()
+        );
+    }
+
+    // Syncing the same commits, in the same order, does what was planned.
+    for entry in plan {
+        let result = large_to_small_syncer
+            .sync_commit_detailed(
+                &ctx,
+                entry.source_cs_id,
+                CandidateSelectionHint::Only,
+                CommitSyncContext::Tests,
+                false,
+            )
+            .await;
+        match (entry.decision, result) {
+            (
+                SyncPlanDecision::AlreadySynced(CommitSyncOutcome::RewrittenAs(planned, _)),
+                Ok(DetailedSyncOutcome::AlreadySynced(synced, _)),
+            )
+            | (
+                SyncPlanDecision::WouldCreate {
+                    target_cs_id: planned,
+                    ..
+                },
+                Ok(DetailedSyncOutcome::CreatedTarget(synced, _)),
+            )
+            | (
+                SyncPlanDecision::WouldSkip {
+                    wc_equivalent_to: Some(planned),
+                    ..
+                },
+                Ok(DetailedSyncOutcome::RewrittenToNothing {
+                    wc_equivalent: synced,
+                    ..
+                }),
+            ) => assert_eq!(planned, synced),
+            (SyncPlanDecision::WouldFail { .. }, Err(_)) => {}
+            (decision, result) => {
+                return Err(anyhow!(
+                    "{} was planned as {:?}, but synced as {:?}",
+                    entry.source_cs_id,
+                    decision,
+                    result
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_sync_file_type_only_change(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let (_old_version, _new_version, large_to_small_syncer) =
+        prepare_commit_syncer_with_mapping_change(fb).await?;
+    let megarepo = large_to_small_syncer.get_source_repo();
+    let small_repo = large_to_small_syncer.get_target_repo();
+    let new_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "new_mapping").await?;
+
+    // Flipping the exec bit on a file that is synced to the small repo must
+    // produce a commit with the same flip.
+    let exec_large_cs_id = CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
+        .add_file_with_type("prefix/dir/file", "2", FileType::Executable)
+        .commit()
+        .await?;
+    let exec_small_cs_id = large_to_small_syncer
+        .sync_commit(
+            &ctx,
+            exec_large_cs_id,
+            CandidateSelectionHint::Only,
+            CommitSyncContext::Tests,
+            false,
+        )
+        .await?
+        .ok_or_else(|| anyhow!("exec bit change was not synced"))?;
+    let new_mapping_small_cs_id = large_to_small_syncer
+        .sync_commit(
+            &ctx,
+            new_mapping_large_cs_id,
+            CandidateSelectionHint::Only,
+            CommitSyncContext::Tests,
+            false,
+        )
+        .await?
+        .ok_or_else(|| anyhow!("new_mapping commit was not synced"))?;
+    assert_ne!(exec_small_cs_id, new_mapping_small_cs_id);
+
+    let exec_small_bcs = exec_small_cs_id
+        .load(&ctx, small_repo.repo_blobstore())
+        .await?;
+    let file_changes: Vec<_> = exec_small_bcs.file_changes().collect();
+    match file_changes.as_slice() {
+        [(path, FileChange::Change(tc))] => {
+            assert_eq!(*path, &MPath::new("dir/file")?);
+            assert_eq!(tc.file_type(), FileType::Executable);
+        }
+        _ => return Err(anyhow!("unexpected file changes: {:?}", file_changes)),
+    }
+    verify_working_copy(ctx.clone(), large_to_small_syncer.clone(), exec_large_cs_id).await?;
+
+    // Flipping the exec bit only on files outside of the small repo still
+    // rewrites the commit to nothing.
+    let outside_large_cs_id =
+        CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
+            .add_file("somerandomfile", "1")
+            .commit()
+            .await?;
+    let outside_exec_large_cs_id =
+        CreateCommitContext::new(&ctx, &megarepo, vec![outside_large_cs_id])
+            .add_file_with_type("somerandomfile", "1", FileType::Executable)
+            .commit()
+            .await?;
+    let synced = large_to_small_syncer
+        .sync_commit(
+            &ctx,
+            outside_exec_large_cs_id,
+            CandidateSelectionHint::Only,
+            CommitSyncContext::Tests,
+            false,
+        )
+        .await?;
+    assert_eq!(synced, Some(new_mapping_small_cs_id));
+    match large_to_small_syncer
+        .get_commit_sync_outcome(&ctx, outside_exec_large_cs_id)
+        .await?
+    {
+        Some(CommitSyncOutcome::EquivalentWorkingCopyAncestor(cs_id, _)) => {
+            assert_eq!(cs_id, new_mapping_small_cs_id);
+        }
+        outcome => return Err(anyhow!("unexpected outcome: {:?}", outcome)),
+    }
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_sync_equivalent_wc_with_mapping_change(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let (old_version, new_version, large_to_small_syncer) =
+        prepare_commit_syncer_with_mapping_change(fb).await?;
+    let megarepo = large_to_small_syncer.get_source_repo();
+    let small_repo = large_to_small_syncer.get_target_repo();
+
+    println!("create commits with new mapping");
+    let new_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "new_mapping").await?;
+    // Create a stack of commits on top of commit with new mapping.
+    // First commit should not rewrite into a small repo, but second should
+
+    let does_not_rewrite_large_cs_id =
+        CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
+            .add_file("somerandomfile", "1")
+            .commit()
+            .await?;
+
+    let synced = large_to_small_syncer
+        .sync_commit(
+            &ctx,
+            does_not_rewrite_large_cs_id,
+            CandidateSelectionHint::Only,
+            CommitSyncContext::Tests,
+            false,
+        )
+        .await?;
+    let parent_synced = large_to_small_syncer
+        .sync_commit(
+            &ctx,
+            new_mapping_large_cs_id,
+            CandidateSelectionHint::Only,
+            CommitSyncContext::Tests,
+            false,
+        )
+        .await?;
+    // does_not_rewrite_large_cs_id commit was rewritten out, so sync_commit
+    // should return the same changeset id as the parent
+    assert_eq!(synced, parent_synced);
+
+    let new_mapping_cs_id =
+        CreateCommitContext::new(&ctx, &megarepo, vec![does_not_rewrite_large_cs_id])
+            .add_file("tools/newtool", "1")
+            .delete_file("tools/1.txt")
+            .add_file("tools/somefile", "somefile1")
+            .add_file("prefix/dir/file", "3")
+            .add_file("prefix/dir/newfile", "3")
+            .commit()
+            .await?;
+
+    let synced = large_to_small_syncer
+        .sync_commit(
+            &ctx,
+            new_mapping_cs_id,
+            CandidateSelectionHint::Only,
+            CommitSyncContext::Tests,
+            false,
+        )
+        .await?;
+    assert!(synced.is_some());
+    let new_mapping_small_cs_id = synced.unwrap();
+
+    verify_working_copy(
+        ctx.clone(),
+        large_to_small_syncer.clone(),
+        new_mapping_cs_id,
+    )
+    .await?;
+    assert_working_copy(
+        &ctx,
+        small_repo,
+        new_mapping_small_cs_id,
+        vec!["tools/somefile", "tools/newtool", "dir/file", "dir/newfile"],
+    )
+    .await?;
+
+    let outcome = large_to_small_syncer
+        .get_commit_sync_outcome(&ctx, new_mapping_cs_id)
+        .await?;
+
+    match outcome {
+        Some(CommitSyncOutcome::RewrittenAs(_, version)) => {
+            assert_eq!(version, new_version);
+        }
+        _ => {
+            return Err(anyhow!("unexpected outcome: {:?}", outcome));
+        }
+    }
+
+    // Create a new commit on top of commit with old mapping.
+    println!("create commits with old mapping");
+
+    let old_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "old_mapping").await?;
+    let does_not_rewrite_large_cs_id =
+        CreateCommitContext::new(&ctx, &megarepo, vec![old_mapping_large_cs_id])
+            .add_file("somerandomfile", "1")
+            .commit()
+            .await?;
+
+    let old_mapping_cs_id =
+        CreateCommitContext::new(&ctx, &megarepo, vec![does_not_rewrite_large_cs_id])
+            .add_file("tools/3.txt", "2")
+            .add_file("prefix/file", "2")
+            .commit()
+            .await?;
+    let synced = large_to_small_syncer
+        .sync_commit(
+            &ctx,
+            old_mapping_cs_id,
+            CandidateSelectionHint::Only,
+            CommitSyncContext::Tests,
+            false,
+        )
+        .await?;
+    assert!(synced.is_some());
+    let old_mapping_small_cs_id = synced.unwrap();
+
+    verify_working_copy(
+        ctx.clone(),
+        large_to_small_syncer.clone(),
+        old_mapping_cs_id,
+    )
+    .await?;
+    assert_working_copy(
+        &ctx,
+        small_repo,
+        old_mapping_small_cs_id,
+        vec!["dir/file", "file", "tools/1.txt"],
+    )
+    .await?;
+
+    let outcome = large_to_small_syncer
+        .get_commit_sync_outcome(&ctx, old_mapping_cs_id)
+        .await?;
+
+    match outcome {
+        Some(CommitSyncOutcome::RewrittenAs(_, version)) => {
+            assert_eq!(version, old_version);
+        }
+        _ => {
+            return Err(anyhow!("unexpected outcome: {:?}", outcome));
+        }
+    }
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_disabled_sync(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let (_, _, large_to_small_syncer) = prepare_commit_syncer_with_mapping_change(fb).await?;
+    let megarepo = large_to_small_syncer.get_source_repo();
+
+    let new_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "new_mapping").await?;
+    // Create a new commit on top of commit with new mapping.
+    let new_mapping_cs_id =
+        CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
+            .add_file("tools/newtool", "1")
+            .commit()
+            .await?;
+
+    let tunables = MononokeTunables::default();
     tunables.update_bools(&hashmap! {"xrepo_sync_disable_all_syncs".to_string() => true});
 
-    // Disable sync - make sure it fails
-    let res = with_tunables_async(
+    // Disable sync - make sure it fails
+    let res = with_tunables_async(
+        tunables,
+        async {
+            large_to_small_syncer
+                .sync_commit(
+                    &ctx,
+                    new_mapping_cs_id,
+                    CandidateSelectionHint::Only,
+                    CommitSyncContext::Tests,
+                    false,
+                )
+                .await
+        }
+        .boxed(),
+    )
+    .await;
+
+    match res {
+        Ok(_) => Err(anyhow!("unexpected success")),
+        Err(err) => {
+            check_x_repo_sync_disabled(&err);
+            Ok(())
+        }
+    }
+}
+
+#[fbinit::test]
+async fn test_disabled_sync_pushrebase(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let (small_repo, megarepo, mapping) = prepare_repos_and_mapping(fb).await.unwrap();
+    Linear::initrepo(fb, &small_repo).await;
+    let small_to_large_syncer = create_small_to_large_commit_syncer(
+        &ctx,
+        small_repo.clone(),
+        megarepo.clone(),
+        "prefix",
+        mapping.clone(),
+    )?;
+
+    create_initial_commit(ctx.clone(), &megarepo).await;
+
+    let megarepo_master_cs_id = get_bookmark(&ctx, &megarepo, "master").await;
+    let small_repo_master_cs_id = get_bookmark(&ctx, &small_repo, "master").await;
+    // Masters map to each other before we even do any syncs
+    let version = version_name_with_small_repo();
+    mapping
+        .add(
+            &ctx,
+            SyncedCommitMappingEntry::new(
+                megarepo.repo_identity().id(),
+                megarepo_master_cs_id,
+                small_repo.repo_identity().id(),
+                small_repo_master_cs_id,
+                version.clone(),
+                small_to_large_syncer.get_source_repo_type(),
+            ),
+        )
+        .await?;
+
+    // 2. Create a small repo commit and sync it onto both branches
+    let small_repo_master_cs_id = create_commit_from_parent_and_changes(
+        &ctx,
+        &small_repo,
+        small_repo_master_cs_id,
+        btreemap! {"small_repo_file" => "content"},
+    )
+    .await;
+    move_bookmark(&ctx, &small_repo, "master", small_repo_master_cs_id).await;
+
+    let small_cs = small_repo_master_cs_id
+        .load(&ctx, small_repo.repo_blobstore())
+        .await?;
+
+    let tunables = MononokeTunables::default();
+    tunables.update_bools(&hashmap! {"xrepo_sync_disable_all_syncs".to_string() => true});
+
+    // Disable sync - make sure it fails
+    let res = with_tunables_async(
+        tunables,
+        async {
+            small_to_large_syncer
+                .unsafe_sync_commit_pushrebase(
+                    &ctx,
+                    small_cs.clone(),
+                    BookmarkKey::new("master").unwrap(),
+                    CommitSyncContext::Tests,
+                    PushrebaseRewriteDates::No,
+                )
+                .await
+        }
+        .boxed(),
+    )
+    .await;
+
+    match res {
+        Ok(_) => Err(anyhow!("unexpected success")),
+        Err(err) => match err.downcast_ref::<ErrorKind>() {
+            Some(error_kind) => match error_kind {
+                ErrorKind::PushrebaseFailure(error) => match error {
+                    PushrebaseError::Error(err) => {
+                        check_x_repo_sync_disabled(err);
+                        Ok(())
+                    }
+                    _ => Err(anyhow!("unexpected pushrebase error: {}", error)),
+                },
+                _ => Err(anyhow!("unexpected ErrorKind: {}", error_kind)),
+            },
+            None => Err(anyhow!("unexpected error - not ErrorKind")),
+        },
+    }
+}
+
+#[fbinit::test]
+async fn test_sync_unknown_mapping_change_version(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let (old_version, new_version, large_to_small_syncer) =
+        prepare_commit_syncer_with_mapping_change(fb).await?;
+    let megarepo = large_to_small_syncer.get_source_repo();
+
+    let new_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "new_mapping").await?;
+    let unsynced_cs_id = CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
+        .add_file("prefix/file", "1")
+        .commit()
+        .await?;
+    let bogus_version = CommitSyncConfigVersion("NONEXISTENT_VERSION".to_string());
+    let mapping_change_cs_id = CreateCommitContext::new(&ctx, &megarepo, vec![unsynced_cs_id])
+        .add_file("prefix/file", "2")
+        .add_extra(CHANGE_XREPO_MAPPING_EXTRA, bogus_version.0.clone())
+        .commit()
+        .await?;
+
+    let tunables = MononokeTunables::default();
+    tunables.update_bools(&hashmap! {"allow_change_xrepo_mapping_extra".to_string() => true});
+    let (sync_res, unsafe_sync_res) = with_tunables_async(
+        tunables,
+        async {
+            let sync_res = large_to_small_syncer
+                .sync_commit(
+                    &ctx,
+                    mapping_change_cs_id,
+                    CandidateSelectionHint::Only,
+                    CommitSyncContext::Tests,
+                    false,
+                )
+                .await;
+            let unsafe_sync_res = large_to_small_syncer
+                .unsafe_sync_commit_with_expected_version(
+                    &ctx,
+                    mapping_change_cs_id,
+                    CandidateSelectionHint::Only,
+                    new_version.clone(),
+                    CommitSyncContext::Tests,
+                )
+                .await;
+            (sync_res, unsafe_sync_res)
+        }
+        .boxed(),
+    )
+    .await;
+
+    for err in [sync_res.unwrap_err(), unsafe_sync_res.unwrap_err()] {
+        match err.downcast_ref::<ErrorKind>() {
+            Some(ErrorKind::UnknownMappingChangeVersion {
+                cs_id,
+                version,
+                known_versions,
+            }) => {
+                assert_eq!(*cs_id, mapping_change_cs_id);
+                assert_eq!(*version, bogus_version);
+                assert!(known_versions.contains(&old_version));
+                assert!(known_versions.contains(&new_version));
+                assert!(!known_versions.contains(&bogus_version));
+            }
+            _ => return Err(anyhow!("unexpected error: {:?}", err)),
+        }
+    }
+
+    // The sync failed before syncing anything, not even the ancestor that
+    // doesn't change the mapping.
+    for cs_id in [unsynced_cs_id, mapping_change_cs_id] {
+        assert!(
+            large_to_small_syncer
+                .get_commit_sync_outcome(&ctx, cs_id)
+                .await?
+                .is_none()
+        );
+    }
+    Ok(())
+}
+
+/// Sync a diamond of unsynced commits on top of the "new_mapping" commit,
+/// syncing up to `concurrency` independent commits at a time. Returns the
+/// sync outcome of each commit of the diamond.
+async fn sync_diamond(
+    fb: FacebookInit,
+    concurrency: i64,
+) -> Result<Vec<(ChangesetId, Option<CommitSyncOutcome>)>, Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let (_old_version, _new_version, large_to_small_syncer) =
+        prepare_commit_syncer_with_mapping_change(fb).await?;
+    let megarepo = large_to_small_syncer.get_source_repo();
+
+    // new_mapping -> left_1 -> left_2 -> merge
+    //            \-> right ------------/
+    let new_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "new_mapping").await?;
+    let left_1 = CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
+        .add_file("prefix/left", "1")
+        .commit()
+        .await?;
+    let left_2 = CreateCommitContext::new(&ctx, &megarepo, vec![left_1])
+        .add_file("prefix/left", "2")
+        .commit()
+        .await?;
+    let right = CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
+        .add_file("prefix/right", "1")
+        .commit()
+        .await?;
+    let merge = create_merge(&ctx, megarepo, vec![left_2, right]).await;
+
+    let tunables = MononokeTunables::default();
+    tunables.update_ints(&hashmap! {
+        "xrepo_sync_ancestors_concurrency".to_string() => concurrency,
+    });
+    with_tunables_async(
         tunables,
-        async {
-            large_to_small_syncer
-                .sync_commit(
-                    &ctx,
-                    new_mapping_cs_id,
-                    CandidateSelectionHint::Only,
-                    CommitSyncContext::Tests,
-                    false,
-                )
-                .await
+        large_to_small_syncer
+            .sync_commit(
+                &ctx,
+                merge,
+                CandidateSelectionHint::Only,
+                CommitSyncContext::Tests,
+                false,
+            )
+            .boxed(),
+    )
+    .await?;
+
+    let mut outcomes = Vec::new();
+    for cs_id in [left_1, left_2, right, merge] {
+        let outcome = large_to_small_syncer
+            .get_commit_sync_outcome(&ctx, cs_id)
+            .await?;
+        outcomes.push((cs_id, outcome));
+    }
+    Ok(outcomes)
+}
+
+#[fbinit::test]
+async fn test_sync_independent_ancestors_concurrently(fb: FacebookInit) -> Result<(), Error> {
+    let serial = sync_diamond(fb, 1).await?;
+    let parallel = sync_diamond(fb, 4).await?;
+
+    for (cs_id, outcome) in &serial {
+        assert_matches!(
+            outcome,
+            Some(CommitSyncOutcome::RewrittenAs(..)),
+            "{} was not synced",
+            cs_id
+        );
+    }
+    assert_eq!(serial, parallel);
+    Ok(())
+}
+
+/// A `SyncedCommitMapping` counting the entries added for each large repo
+/// commit, that is the uploads of each synced commit, and recording the
+/// order they were added in.
+#[derive(Clone)]
+struct CountingMapping {
+    inner: SqlSyncedCommitMapping,
+    added: Arc<Mutex<HashMap<ChangesetId, usize>>>,
+    written: Arc<Mutex<Vec<ChangesetId>>>,
+}
+
+impl CountingMapping {
+    fn count(&self, entries: &[SyncedCommitMappingEntry]) {
+        let mut added = self.added.lock().unwrap();
+        let mut written = self.written.lock().unwrap();
+        for entry in entries {
+            *added.entry(entry.large_bcs_id).or_default() += 1;
+            written.push(entry.large_bcs_id);
         }
-        .boxed(),
+    }
+}
+
+#[async_trait]
+impl SyncedCommitMapping for CountingMapping {
+    async fn add(&self, ctx: &CoreContext, entry: SyncedCommitMappingEntry) -> Result<bool, Error> {
+        self.count(std::slice::from_ref(&entry));
+        self.inner.add(ctx, entry).await
+    }
+
+    async fn add_bulk(
+        &self,
+        ctx: &CoreContext,
+        entries: Vec<SyncedCommitMappingEntry>,
+    ) -> Result<u64, Error> {
+        self.count(&entries);
+        self.inner.add_bulk(ctx, entries).await
+    }
+
+    async fn get(
+        &self,
+        ctx: &CoreContext,
+        source_repo_id: RepositoryId,
+        bcs_id: ChangesetId,
+        target_repo_id: RepositoryId,
+    ) -> Result<
+        Vec<(
+            ChangesetId,
+            Option<CommitSyncConfigVersion>,
+            Option<SyncedCommitSourceRepo>,
+        )>,
+        Error,
+    > {
+        self.inner
+            .get(ctx, source_repo_id, bcs_id, target_repo_id)
+            .await
+    }
+
+    async fn insert_equivalent_working_copy(
+        &self,
+        ctx: &CoreContext,
+        entry: EquivalentWorkingCopyEntry,
+    ) -> Result<bool, Error> {
+        self.inner.insert_equivalent_working_copy(ctx, entry).await
+    }
+
+    async fn overwrite_equivalent_working_copy(
+        &self,
+        ctx: &CoreContext,
+        entry: EquivalentWorkingCopyEntry,
+    ) -> Result<bool, Error> {
+        self.inner
+            .overwrite_equivalent_working_copy(ctx, entry)
+            .await
+    }
+
+    async fn get_equivalent_working_copy(
+        &self,
+        ctx: &CoreContext,
+        source_repo_id: RepositoryId,
+        source_bcs_id: ChangesetId,
+        target_repo_id: RepositoryId,
+    ) -> Result<Option<WorkingCopyEquivalence>, Error> {
+        self.inner
+            .get_equivalent_working_copy(ctx, source_repo_id, source_bcs_id, target_repo_id)
+            .await
+    }
+
+    async fn get_large_repo_commit_version(
+        &self,
+        ctx: &CoreContext,
+        large_repo_id: RepositoryId,
+        large_repo_cs_id: ChangesetId,
+    ) -> Result<Option<CommitSyncConfigVersion>, Error> {
+        self.inner
+            .get_large_repo_commit_version(ctx, large_repo_id, large_repo_cs_id)
+            .await
+    }
+
+    async fn delete(
+        &self,
+        ctx: &CoreContext,
+        source_repo_id: RepositoryId,
+        bcs_id: ChangesetId,
+        target_repo_id: RepositoryId,
+    ) -> Result<u64, Error> {
+        self.inner
+            .delete(ctx, source_repo_id, bcs_id, target_repo_id)
+            .await
+    }
+
+    async fn delete_equivalent_working_copy(
+        &self,
+        ctx: &CoreContext,
+        source_repo_id: RepositoryId,
+        source_bcs_id: ChangesetId,
+        target_repo_id: RepositoryId,
+    ) -> Result<u64, Error> {
+        self.inner
+            .delete_equivalent_working_copy(ctx, source_repo_id, source_bcs_id, target_repo_id)
+            .await
+    }
+}
+
+#[fbinit::test]
+async fn test_concurrent_syncs_share_ancestors(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let (_old_version, _new_version, large_to_small_syncer) =
+        prepare_commit_syncer_with_mapping_change(fb).await?;
+    let mapping = CountingMapping {
+        inner: large_to_small_syncer.mapping.clone(),
+        added: Arc::new(Mutex::new(HashMap::new())),
+        written: Arc::new(Mutex::new(Vec::new())),
+    };
+    // Both syncs share the in-process lease of the syncer.
+    let syncer = CommitSyncer::new_with_provider_and_reporter(
+        mapping.clone(),
+        large_to_small_syncer.repos.clone(),
+        large_to_small_syncer.commit_sync_data_provider.clone(),
+        large_to_small_syncer.reporter.clone(),
+    );
+    let megarepo = syncer.get_source_repo();
+
+    // new_mapping -> shared_1 -> shared_2 -> left
+    //                                     \-> right
+    let new_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "new_mapping").await?;
+    let shared_1 = CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
+        .add_file("prefix/shared", "1")
+        .commit()
+        .await?;
+    let shared_2 = CreateCommitContext::new(&ctx, &megarepo, vec![shared_1])
+        .add_file("prefix/shared", "2")
+        .commit()
+        .await?;
+    let left = CreateCommitContext::new(&ctx, &megarepo, vec![shared_2])
+        .add_file("prefix/left", "1")
+        .commit()
+        .await?;
+    let right = CreateCommitContext::new(&ctx, &megarepo, vec![shared_2])
+        .add_file("prefix/right", "1")
+        .commit()
+        .await?;
+
+    let sync = |cs_id| {
+        syncer.sync_commit(
+            &ctx,
+            cs_id,
+            CandidateSelectionHint::Only,
+            CommitSyncContext::Tests,
+            false,
+        )
+    };
+    let (left_synced, right_synced) = try_join(sync(left), sync(right)).await?;
+    assert!(left_synced.is_some());
+    assert!(right_synced.is_some());
+
+    let added = mapping.added.lock().unwrap().clone();
+    assert_eq!(
+        added,
+        hashmap! {shared_1 => 1, shared_2 => 1, left => 1, right => 1}
+    );
+
+    // Synced tips take the fast path, without uploading anything.
+    sync(left).await?;
+    assert_eq!(*mapping.added.lock().unwrap(), added);
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_sync_wide_graph_concurrently(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let (_old_version, _new_version, large_to_small_syncer) =
+        prepare_commit_syncer_with_mapping_change(fb).await?;
+    let mapping = CountingMapping {
+        inner: large_to_small_syncer.mapping.clone(),
+        added: Arc::new(Mutex::new(HashMap::new())),
+        written: Arc::new(Mutex::new(Vec::new())),
+    };
+    let syncer = CommitSyncer::new_with_provider_and_reporter(
+        mapping.clone(),
+        large_to_small_syncer.repos.clone(),
+        large_to_small_syncer.commit_sync_data_provider.clone(),
+        large_to_small_syncer.reporter.clone(),
+    );
+    let megarepo = syncer.get_source_repo();
+
+    // 50 independent stacks of 2 commits on top of new_mapping.
+    let new_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "new_mapping").await?;
+    let mut stacks = Vec::new();
+    for i in 0..50 {
+        let path = format!("prefix/stack_{}", i);
+        let bottom = CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
+            .add_file(path.as_str(), "1")
+            .commit()
+            .await?;
+        let top = CreateCommitContext::new(&ctx, &megarepo, vec![bottom])
+            .add_file(path.as_str(), "2")
+            .commit()
+            .await?;
+        stacks.push((bottom, top));
+    }
+
+    let tunables = MononokeTunables::default();
+    tunables.update_ints(&hashmap! {
+        "xrepo_sync_ancestors_concurrency".to_string() => 10,
+    });
+    let synced = with_tunables_async(
+        tunables,
+        syncer
+            .sync_commit_batch(
+                &ctx,
+                stacks.iter().map(|(_, top)| *top).collect(),
+                CandidateSelectionHint::Only,
+                CommitSyncContext::Tests,
+                false,
+            )
+            .boxed(),
+    )
+    .await?;
+    assert_eq!(synced.len(), stacks.len());
+
+    // Each commit was written once, after its parent.
+    let written = mapping.written.lock().unwrap().clone();
+    let positions: HashMap<_, _> = written
+        .iter()
+        .enumerate()
+        .map(|(position, cs_id)| (*cs_id, position))
+        .collect();
+    assert_eq!(written.len(), 2 * stacks.len());
+    assert_eq!(positions.len(), written.len());
+    for (bottom, top) in stacks {
+        assert!(positions[&bottom] < positions[&top]);
+        for cs_id in [bottom, top] {
+            assert_matches!(
+                syncer.get_commit_sync_outcome(&ctx, cs_id).await?,
+                Some(CommitSyncOutcome::RewrittenAs(..))
+            );
+        }
+        verify_working_copy(ctx.clone(), syncer.clone(), top).await?;
+    }
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_sync_independent_ancestors_failure(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let (_old_version, _new_version, large_to_small_syncer) =
+        prepare_commit_syncer_with_mapping_change(fb).await?;
+    let megarepo = large_to_small_syncer.get_source_repo();
+
+    // new_mapping -> left_1 -> left_2 ------------------> head
+    // old_mapping -> right ----------> right_merge ----/
+    //                      new_root --/
+    //
+    // new_root has no parent, and its unsynced descendant has synced
+    // ancestors with two different versions, so there is no way to pick a
+    // version to sync it with.
+    let new_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "new_mapping").await?;
+    let old_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "old_mapping").await?;
+    let left_1 = CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
+        .add_file("prefix/left", "1")
+        .commit()
+        .await?;
+    let left_2 = CreateCommitContext::new(&ctx, &megarepo, vec![left_1])
+        .add_file("prefix/left", "2")
+        .commit()
+        .await?;
+    let right = CreateCommitContext::new(&ctx, &megarepo, vec![old_mapping_large_cs_id])
+        .add_file("prefix/right", "1")
+        .commit()
+        .await?;
+    let new_root = CreateCommitContext::new_root(&ctx, &megarepo)
+        .add_file("prefix/new_root", "1")
+        .commit()
+        .await?;
+    let right_merge = create_merge(&ctx, megarepo, vec![right, new_root]).await;
+    let head = create_merge(&ctx, megarepo, vec![left_2, right_merge]).await;
+
+    let tunables = MononokeTunables::default();
+    tunables.update_ints(&hashmap! {
+        "xrepo_sync_ancestors_concurrency".to_string() => 4,
+    });
+    let res = with_tunables_async(
+        tunables,
+        large_to_small_syncer
+            .sync_commit(
+                &ctx,
+                head,
+                CandidateSelectionHint::Only,
+                CommitSyncContext::Tests,
+                false,
+            )
+            .boxed(),
     )
     .await;
+    assert!(res.is_err());
 
-    match res {
-        Ok(_) => Err(anyhow!("unexpected success")),
-        Err(err) => {
-            check_x_repo_sync_disabled(&err);
-            Ok(())
-        }
+    // The syncs of left_1 and right were started alongside the failing one,
+    // and were allowed to complete.
+    for cs_id in [left_1, right] {
+        assert!(
+            large_to_small_syncer
+                .get_commit_sync_outcome(&ctx, cs_id)
+                .await?
+                .is_some()
+        );
+    }
+    // Descendants of the failing commit were not synced.
+    for cs_id in [new_root, right_merge, head] {
+        assert!(
+            large_to_small_syncer
+                .get_commit_sync_outcome(&ctx, cs_id)
+                .await?
+                .is_none()
+        );
+    }
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_sync_commit_batch(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let (_old_version, _new_version, large_to_small_syncer) =
+        prepare_commit_syncer_with_mapping_change(fb).await?;
+    let mapping = CountingMapping {
+        inner: large_to_small_syncer.mapping.clone(),
+        added: Arc::new(Mutex::new(HashMap::new())),
+        written: Arc::new(Mutex::new(Vec::new())),
+    };
+    let syncer = CommitSyncer::new_with_provider_and_reporter(
+        mapping.clone(),
+        large_to_small_syncer.repos.clone(),
+        large_to_small_syncer.commit_sync_data_provider.clone(),
+        large_to_small_syncer.reporter.clone(),
+    );
+    let megarepo = syncer.get_source_repo();
+
+    // new_mapping -> shared_1 -> shared_2 -> left
+    //                                     \-> right
+    let new_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "new_mapping").await?;
+    let shared_1 = CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
+        .add_file("prefix/shared", "1")
+        .commit()
+        .await?;
+    let shared_2 = CreateCommitContext::new(&ctx, &megarepo, vec![shared_1])
+        .add_file("prefix/shared", "2")
+        .commit()
+        .await?;
+    let left = CreateCommitContext::new(&ctx, &megarepo, vec![shared_2])
+        .add_file("prefix/left", "1")
+        .commit()
+        .await?;
+    let right = CreateCommitContext::new(&ctx, &megarepo, vec![shared_2])
+        .add_file("prefix/right", "1")
+        .commit()
+        .await?;
+
+    let synced = syncer
+        .sync_commit_batch(
+            &ctx,
+            vec![left, right, new_mapping_large_cs_id],
+            CandidateSelectionHint::Only,
+            CommitSyncContext::Tests,
+            false,
+        )
+        .await?;
+    let added = mapping.added.lock().unwrap().clone();
+    assert_eq!(
+        added,
+        hashmap! {shared_1 => 1, shared_2 => 1, left => 1, right => 1}
+    );
+
+    // The outcomes are those `sync_commit` returns, for already synced
+    // commits too.
+    assert_eq!(synced.len(), 3);
+    for (cs_id, target_cs_id) in &synced {
+        let expected = syncer
+            .sync_commit(
+                &ctx,
+                *cs_id,
+                CandidateSelectionHint::Only,
+                CommitSyncContext::Tests,
+                false,
+            )
+            .await?;
+        assert!(target_cs_id.is_some());
+        assert_eq!(target_cs_id, &expected);
+    }
+    assert_eq!(*mapping.added.lock().unwrap(), added);
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_sync_commit_batch_partial_failure(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let (_old_version, _new_version, large_to_small_syncer) =
+        prepare_commit_syncer_with_mapping_change(fb).await?;
+    let megarepo = large_to_small_syncer.get_source_repo();
+
+    // new_mapping -> left_1 -> left_2
+    // old_mapping -> right ----------> right_merge
+    //                      new_root --/
+    //
+    // As in test_sync_independent_ancestors_failure, new_root can't be
+    // synced.
+    let new_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "new_mapping").await?;
+    let old_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "old_mapping").await?;
+    let left_1 = CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
+        .add_file("prefix/left", "1")
+        .commit()
+        .await?;
+    let left_2 = CreateCommitContext::new(&ctx, &megarepo, vec![left_1])
+        .add_file("prefix/left", "2")
+        .commit()
+        .await?;
+    let right = CreateCommitContext::new(&ctx, &megarepo, vec![old_mapping_large_cs_id])
+        .add_file("prefix/right", "1")
+        .commit()
+        .await?;
+    let new_root = CreateCommitContext::new_root(&ctx, &megarepo)
+        .add_file("prefix/new_root", "1")
+        .commit()
+        .await?;
+    let right_merge = create_merge(&ctx, megarepo, vec![right, new_root]).await;
+
+    // Even syncing one commit at a time, the commits that don't descend
+    // from new_root are synced after it fails.
+    let tunables = MononokeTunables::default();
+    tunables.update_ints(&hashmap! {
+        "xrepo_sync_ancestors_concurrency".to_string() => 1,
+    });
+    let err = with_tunables_async(
+        tunables,
+        large_to_small_syncer
+            .sync_commit_batch(
+                &ctx,
+                vec![right_merge, left_2, right],
+                CandidateSelectionHint::Only,
+                CommitSyncContext::Tests,
+                false,
+            )
+            .boxed(),
+    )
+    .await
+    .unwrap_err();
+    let (synced, failed) = match err.downcast_ref::<ErrorKind>() {
+        Some(ErrorKind::PartialBatchSync { synced, failed, .. }) => (synced, failed),
+        _ => panic!("unexpected error: {:?}", err),
+    };
+    assert_eq!(failed, &vec![right_merge]);
+    assert_eq!(
+        synced.keys().copied().collect::<HashSet<_>>(),
+        hashset! {left_2, right}
+    );
+    for cs_id in [left_1, left_2, right] {
+        assert!(
+            large_to_small_syncer
+                .get_commit_sync_outcome(&ctx, cs_id)
+                .await?
+                .is_some()
+        );
+    }
+    for cs_id in [new_root, right_merge] {
+        assert!(
+            large_to_small_syncer
+                .get_commit_sync_outcome(&ctx, cs_id)
+                .await?
+                .is_none()
+        );
+    }
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_sync_root_with_ambiguous_ancestor_versions(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let (_old_version, new_version, large_to_small_syncer) =
+        prepare_commit_syncer_with_mapping_change(fb).await?;
+    let megarepo = large_to_small_syncer.get_source_repo();
+
+    // new_mapping -> left --------------------------------> head
+    // old_mapping -> right -> right_merge (new mapping) --/
+    //            new_root --/
+    //
+    // new_root has no parent, and its unsynced descendant has synced
+    // ancestors with two different versions. right_merge switches to the new
+    // mapping, so that head can be synced once new_root is.
+    let new_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "new_mapping").await?;
+    let old_mapping_large_cs_id = resolve_cs_id(&ctx, &megarepo, "old_mapping").await?;
+    let left = CreateCommitContext::new(&ctx, &megarepo, vec![new_mapping_large_cs_id])
+        .add_file("prefix/left", "1")
+        .commit()
+        .await?;
+    let right = CreateCommitContext::new(&ctx, &megarepo, vec![old_mapping_large_cs_id])
+        .add_file("prefix/right", "1")
+        .commit()
+        .await?;
+    let new_root = CreateCommitContext::new_root(&ctx, &megarepo)
+        .add_file("prefix/new_root", "1")
+        .commit()
+        .await?;
+    let right_merge = CreateCommitContext::new(&ctx, &megarepo, vec![right, new_root])
+        .add_extra(CHANGE_XREPO_MAPPING_EXTRA, new_version.0.clone())
+        .commit()
+        .await?;
+    let head = create_merge(&ctx, megarepo, vec![left, right_merge]).await;
+
+    let sync = |commit_syncer: CommitSyncer<SqlSyncedCommitMapping, TestRepo>| {
+        let ctx = ctx.clone();
+        let tunables = MononokeTunables::default();
+        tunables.update_bools(&hashmap! {"allow_change_xrepo_mapping_extra".to_string() => true});
+        with_tunables_async(
+            tunables,
+            async move {
+                commit_syncer
+                    .sync_commit(
+                        &ctx,
+                        head,
+                        CandidateSelectionHint::Only,
+                        CommitSyncContext::Tests,
+                        false,
+                    )
+                    .await
+            }
+            .boxed(),
+        )
+    };
+
+    // By default there is no way to pick a version to sync new_root with.
+    let err = match sync(large_to_small_syncer.clone()).await {
+        Ok(_) => return Err(anyhow!("syncing {} should have failed", head)),
+        Err(err) => err,
+    };
+    assert!(
+        format!("{:#}", err).contains("cannot find single ancestor version"),
+        "unexpected error: {:#}",
+        err
+    );
+    for cs_id in [new_root, right_merge, head] {
+        assert!(
+            large_to_small_syncer
+                .get_commit_sync_outcome(&ctx, cs_id)
+                .await?
+                .is_none()
+        );
     }
+
+    // With the fallback allowed, new_root is synced with the current version.
+    let large_to_small_syncer =
+        large_to_small_syncer.with_ambiguous_ancestor_version_fallback(true);
+    sync(large_to_small_syncer.clone()).await?;
+    assert_matches!(
+        large_to_small_syncer
+            .get_commit_sync_outcome(&ctx, new_root)
+            .await?,
+        Some(CommitSyncOutcome::RewrittenAs(_, version)) if version == new_version
+    );
+    assert!(
+        large_to_small_syncer
+            .get_commit_sync_outcome(&ctx, head)
+            .await?
+            .is_some()
+    );
+    Ok(())
 }
 
 #[fbinit::test]
-async fn test_disabled_sync_pushrebase(fb: FacebookInit) -> Result<(), Error> {
+async fn test_sync_with_target_path_policy(fb: FacebookInit) -> Result<(), Error> {
     let ctx = CoreContext::test_mock(fb);
-    let (small_repo, megarepo, mapping) = prepare_repos_and_mapping(fb).await.unwrap();
-    Linear::initrepo(fb, &small_repo).await;
-    let small_to_large_syncer = create_small_to_large_commit_syncer(
+    let (small_repo, megarepo, mapping) = prepare_repos_and_mapping(fb).await?;
+    let root = CreateCommitContext::new_root(&ctx, &small_repo)
+        .add_file("dir/file", "1")
+        .add_file("dir/long_file_name", "2")
+        .commit()
+        .await?;
+    let commit_syncer =
+        create_small_to_large_commit_syncer(&ctx, small_repo, megarepo, "prefix", mapping)?;
+
+    // prefix/dir/long_file_name is 25 bytes long.
+    let policy = Arc::new(TargetPathLimits {
+        max_path_bytes: Some(20),
+        ..Default::default()
+    });
+    let limited_syncer = commit_syncer.clone().with_target_path_policy(policy);
+    let err = limited_syncer
+        .unsafe_sync_commit_with_expected_version(
+            &ctx,
+            root,
+            CandidateSelectionHint::Only,
+            version_name_with_small_repo(),
+            CommitSyncContext::Tests,
+        )
+        .await
+        .expect_err("the rewritten path is too long");
+    assert_matches!(
+        err.downcast_ref::<RewriteErrorKind>(),
+        Some(RewriteErrorKind::TargetPathPolicyViolation(violations))
+            if violations == &[TargetPathViolation {
+                source_path: mpath("dir/long_file_name"),
+                target_path: mpath("prefix/dir/long_file_name"),
+                rule: "path is 25 bytes long, more than 20".to_string(),
+            }]
+    );
+    assert!(
+        commit_syncer
+            .get_commit_sync_outcome(&ctx, root)
+            .await?
+            .is_none()
+    );
+
+    // The default policy allows all paths.
+    let synced = commit_syncer
+        .unsafe_sync_commit_with_expected_version(
+            &ctx,
+            root,
+            CandidateSelectionHint::Only,
+            version_name_with_small_repo(),
+            CommitSyncContext::Tests,
+        )
+        .await?;
+    assert!(synced.is_some());
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_unsafe_always_rewrite_sync_commit_validates_parent_overrides(
+    fb: FacebookInit,
+) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let (small_repo, megarepo, mapping) = prepare_repos_and_mapping(fb).await?;
+    let commit_syncer = create_large_to_small_commit_syncer(
         &ctx,
         small_repo.clone(),
         megarepo.clone(),
         "prefix",
-        mapping.clone(),
+        mapping,
     )?;
 
-    create_initial_commit(ctx.clone(), &megarepo).await;
-
-    let megarepo_master_cs_id = get_bookmark(&ctx, &megarepo, "master").await;
-    let small_repo_master_cs_id = get_bookmark(&ctx, &small_repo, "master").await;
-    // Masters map to each other before we even do any syncs
-    let version = version_name_with_small_repo();
-    mapping
-        .add(
+    let root = CreateCommitContext::new_root(&ctx, &megarepo)
+        .add_file("prefix/file", "1")
+        .commit()
+        .await?;
+    let small_root = commit_syncer
+        .unsafe_always_rewrite_sync_commit(
             &ctx,
-            SyncedCommitMappingEntry::new(
-                megarepo.repo_identity().id(),
-                megarepo_master_cs_id,
-                small_repo.repo_identity().id(),
-                small_repo_master_cs_id,
-                version.clone(),
-                small_to_large_syncer.get_source_repo_type(),
-            ),
+            root,
+            None,
+            ParentOverrideStrictness::Strict,
+            &version_name_with_small_repo(),
+            CommitSyncContext::Tests,
         )
+        .await?
+        .ok_or_else(|| anyhow!("root was not synced"))?;
+    let other = CreateCommitContext::new_root(&ctx, &megarepo)
+        .add_file("prefix/other", "1")
+        .commit()
         .await?;
-
-    // 2. Create a small repo commit and sync it onto both branches
-    let small_repo_master_cs_id = create_commit_from_parent_and_changes(
-        &ctx,
-        &small_repo,
-        small_repo_master_cs_id,
-        btreemap! {"small_repo_file" => "content"},
-    )
-    .await;
-    move_bookmark(&ctx, &small_repo, "master", small_repo_master_cs_id).await;
-
-    let small_cs = small_repo_master_cs_id
-        .load(&ctx, small_repo.repo_blobstore())
+    let child = CreateCommitContext::new(&ctx, &megarepo, vec![root])
+        .add_file("prefix/file", "2")
+        .commit()
         .await?;
 
-    let tunables = MononokeTunables::default();
-    tunables.update_bools(&hashmap! {"xrepo_sync_disable_all_syncs".to_string() => true});
+    // `root` is only in the large repo, and `other` is not a parent of
+    // `child`. Both are reported, and nothing is synced.
+    let err = commit_syncer
+        .unsafe_always_rewrite_sync_commit(
+            &ctx,
+            child,
+            Some(hashmap! { root => root, other => small_root }),
+            ParentOverrideStrictness::Strict,
+            &version_name_with_small_repo(),
+            CommitSyncContext::Tests,
+        )
+        .await
+        .unwrap_err();
+    assert_matches!(
+        err.downcast_ref::<ErrorKind>(),
+        Some(ErrorKind::InvalidParentOverrides { cs_id, missing_in_target, not_parents })
+            if *cs_id == child
+                && missing_in_target == &[(root, root)]
+                && not_parents == &[other]
+    );
+    assert!(
+        commit_syncer
+            .get_commit_sync_outcome(&ctx, child)
+            .await?
+            .is_none()
+    );
 
-    // Disable sync - make sure it fails
-    let res = with_tunables_async(
-        tunables,
-        async {
-            small_to_large_syncer
-                .unsafe_sync_commit_pushrebase(
-                    &ctx,
-                    small_cs.clone(),
-                    BookmarkKey::new("master").unwrap(),
-                    CommitSyncContext::Tests,
-                    PushrebaseRewriteDates::No,
-                )
-                .await
-        }
-        .boxed(),
-    )
-    .await;
+    // Extra overrides can be allowed, but a missing target parent still fails.
+    let err = commit_syncer
+        .unsafe_always_rewrite_sync_commit(
+            &ctx,
+            child,
+            Some(hashmap! { root => root, other => small_root }),
+            ParentOverrideStrictness::WarnOnExtraParents,
+            &version_name_with_small_repo(),
+            CommitSyncContext::Tests,
+        )
+        .await
+        .unwrap_err();
+    assert_matches!(
+        err.downcast_ref::<ErrorKind>(),
+        Some(ErrorKind::InvalidParentOverrides { missing_in_target, not_parents, .. })
+            if missing_in_target == &[(root, root)] && not_parents.is_empty()
+    );
 
-    match res {
-        Ok(_) => Err(anyhow!("unexpected success")),
-        Err(err) => match err.downcast_ref::<ErrorKind>() {
-            Some(error_kind) => match error_kind {
-                ErrorKind::PushrebaseFailure(error) => match error {
-                    PushrebaseError::Error(err) => {
-                        check_x_repo_sync_disabled(err);
-                        Ok(())
-                    }
-                    _ => Err(anyhow!("unexpected pushrebase error: {}", error)),
-                },
-                _ => Err(anyhow!("unexpected ErrorKind: {}", error_kind)),
-            },
-            None => Err(anyhow!("unexpected error - not ErrorKind")),
-        },
-    }
+    let small_child = commit_syncer
+        .unsafe_always_rewrite_sync_commit(
+            &ctx,
+            child,
+            Some(hashmap! { root => small_root }),
+            ParentOverrideStrictness::Strict,
+            &version_name_with_small_repo(),
+            CommitSyncContext::Tests,
+        )
+        .await?
+        .ok_or_else(|| anyhow!("child was not synced"))?;
+    let small_child = small_child.load(&ctx, small_repo.repo_blobstore()).await?;
+    assert_eq!(small_child.parents().collect::<Vec<_>>(), vec![small_root]);
+    Ok(())
 }
 
 fn check_x_repo_sync_disabled(err: &Error) {
@@ -1574,6 +3208,7 @@ async fn prepare_commit_syncer_with_mapping_change(
             &ctx,
             root_cs_id,
             None,
+            ParentOverrideStrictness::Strict,
             &version_name_with_small_repo(),
             CommitSyncContext::Tests,
         )
@@ -1623,6 +3258,8 @@ async fn prepare_commit_syncer_with_mapping_change(
         large_repo_id,
     });
     config_source.add_config(commit_sync_config);
+    config_source.set_current_version(large_repo_id, new_version.clone());
+    config_source.set_current_version(small_repo_id, new_version.clone());
 
     // Create manual commit to change mapping
     let new_mapping_large_cs_id = CreateCommitContext::new(&ctx, &megarepo, vec![root_cs_id])
@@ -1782,6 +3419,7 @@ async fn merge_test_setup(
             &ctx,
             c1,
             None, // parents override
+            ParentOverrideStrictness::Strict,
             &v1,
             CommitSyncContext::Tests,
         )
@@ -1791,6 +3429,7 @@ async fn merge_test_setup(
             &ctx,
             c2,
             None, // parents override
+            ParentOverrideStrictness::Strict,
             &v1,
             CommitSyncContext::Tests,
         )
@@ -1800,6 +3439,7 @@ async fn merge_test_setup(
             &ctx,
             c3,
             None, // parents override
+            ParentOverrideStrictness::Strict,
             &v2,
             CommitSyncContext::Tests,
         )
@@ -1809,6 +3449,7 @@ async fn merge_test_setup(
             &ctx,
             c4,
             None, // parents override
+            ParentOverrideStrictness::Strict,
             &v2,
             CommitSyncContext::Tests,
         )
@@ -1941,6 +3582,187 @@ async fn test_sync_merge_fails_when_parents_have_different_versions(
     Ok(())
 }
 
+#[fbinit::test]
+async fn test_mark_not_sync_candidate(fb: FacebookInit) -> Result<(), Error> {
+    let v1 = CommitSyncConfigVersion("v1".to_string());
+    let (ctx, mut lts_syncer, heads_with_versions) = merge_test_setup(fb).await?;
+    let reporter = Arc::new(RecordingSyncReporter::default());
+    lts_syncer.reporter = reporter.clone();
+    let (c1, c2) = match heads_with_versions[&Some(v1.clone())].as_slice() {
+        [c1, c2] => (*c1, *c2),
+        heads => return Err(anyhow!("unexpected heads: {:?}", heads)),
+    };
+    let large_repo = lts_syncer.get_source_repo().clone();
+
+    // `taken_down` is marked, so that only the other parent of the merge is
+    // synced.
+    let taken_down =
+        create_commit_from_parent_and_changes(&ctx, &large_repo, c1, btreemap! {"bad" => "1"})
+            .await;
+    let other =
+        create_commit_from_parent_and_changes(&ctx, &large_repo, c2, btreemap! {"good" => "1"})
+            .await;
+    let merge = CreateCommitContext::new(&ctx, &large_repo, vec![taken_down, other])
+        .add_file("merge", "1")
+        .commit()
+        .await?;
+
+    lts_syncer
+        .mark_not_sync_candidate(&ctx, taken_down, "takedown".to_string())
+        .await?;
+    assert_eq!(
+        lts_syncer.get_commit_sync_outcome(&ctx, taken_down).await?,
+        Some(CommitSyncOutcome::NotSyncCandidate(v1.clone()))
+    );
+    // Marking again does nothing.
+    lts_syncer
+        .mark_not_sync_candidate(&ctx, taken_down, "takedown".to_string())
+        .await?;
+
+    let (unsynced, _) = find_toposorted_unsynced_ancestors(&ctx, &lts_syncer, merge).await?;
+    assert_eq!(unsynced, vec![other, merge]);
+    lts_syncer
+        .sync_commit(
+            &ctx,
+            merge,
+            CandidateSelectionHint::Only,
+            CommitSyncContext::Tests,
+            false,
+        )
+        .await?;
+    let synced_other = match lts_syncer.get_commit_sync_outcome(&ctx, other).await? {
+        Some(CommitSyncOutcome::RewrittenAs(cs_id, _)) => cs_id,
+        outcome => return Err(anyhow!("unexpected outcome of other: {:?}", outcome)),
+    };
+    let synced_merge = match lts_syncer.get_commit_sync_outcome(&ctx, merge).await? {
+        Some(CommitSyncOutcome::RewrittenAs(cs_id, version)) => {
+            assert_eq!(version, v1);
+            cs_id
+        }
+        outcome => return Err(anyhow!("unexpected outcome of merge: {:?}", outcome)),
+    };
+    let small_repo = lts_syncer.get_target_repo();
+    assert_eq!(
+        small_repo
+            .changeset_fetcher()
+            .get_parents(&ctx, synced_merge)
+            .await?,
+        vec![synced_other]
+    );
+    assert_eq!(
+        lts_syncer.get_commit_sync_outcome(&ctx, taken_down).await?,
+        Some(CommitSyncOutcome::NotSyncCandidate(v1.clone()))
+    );
+
+    // Synced commits are only marked with force.
+    let synced_c1 = match lts_syncer.get_commit_sync_outcome(&ctx, c1).await? {
+        Some(CommitSyncOutcome::RewrittenAs(cs_id, _)) => cs_id,
+        outcome => return Err(anyhow!("unexpected outcome of c1: {:?}", outcome)),
+    };
+    let err = lts_syncer
+        .mark_not_sync_candidate(&ctx, c1, "bad import".to_string())
+        .await
+        .expect_err("marking a synced commit without force must fail");
+    assert_matches!(
+        err.downcast_ref::<ErrorKind>(),
+        Some(ErrorKind::SyncOutcomeExists { cs_id, outcome: "rewritten_as" }) if *cs_id == c1
+    );
+    assert_eq!(
+        lts_syncer.get_commit_sync_outcome(&ctx, c1).await?,
+        Some(CommitSyncOutcome::RewrittenAs(synced_c1, v1.clone()))
+    );
+    lts_syncer
+        .force_mark_not_sync_candidate(&ctx, c1, "bad import".to_string())
+        .await?;
+    assert_eq!(
+        lts_syncer.get_commit_sync_outcome(&ctx, c1).await?,
+        Some(CommitSyncOutcome::NotSyncCandidate(v1.clone()))
+    );
+
+    // Only marked commits are unmarked.
+    let err = lts_syncer
+        .unmark_not_sync_candidate(&ctx, c2, "mistake".to_string())
+        .await
+        .expect_err("unmarking a synced commit must fail");
+    assert_matches!(
+        err.downcast_ref::<ErrorKind>(),
+        Some(ErrorKind::NotMarkedNotSyncCandidate(cs_id)) if *cs_id == c2
+    );
+    lts_syncer
+        .unmark_not_sync_candidate(&ctx, c1, "mistake".to_string())
+        .await?;
+    assert_eq!(lts_syncer.get_commit_sync_outcome(&ctx, c1).await?, None);
+
+    let reports: Vec<_> = reporter
+        .mark_reports()
+        .into_iter()
+        .map(|report| {
+            (
+                report.source_cs_id,
+                report.mark_fn,
+                report.reason,
+                report.forced,
+                report.previous_outcome,
+                report.result.is_ok(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        reports,
+        vec![
+            (
+                taken_down,
+                "mark_not_sync_candidate",
+                "takedown".to_string(),
+                false,
+                None,
+                true
+            ),
+            (
+                taken_down,
+                "mark_not_sync_candidate",
+                "takedown".to_string(),
+                false,
+                Some("not_sync_candidate"),
+                true
+            ),
+            (
+                c1,
+                "mark_not_sync_candidate",
+                "bad import".to_string(),
+                false,
+                Some("rewritten_as"),
+                false
+            ),
+            (
+                c1,
+                "force_mark_not_sync_candidate",
+                "bad import".to_string(),
+                true,
+                Some("rewritten_as"),
+                true
+            ),
+            (
+                c2,
+                "unmark_not_sync_candidate",
+                "mistake".to_string(),
+                false,
+                Some("rewritten_as"),
+                false
+            ),
+            (
+                c1,
+                "unmark_not_sync_candidate",
+                "mistake".to_string(),
+                false,
+                Some("not_sync_candidate"),
+                true
+            ),
+        ]
+    );
+    Ok(())
+}
+
 async fn assert_working_copy(
     ctx: &CoreContext,
     repo: &TestRepo,
@@ -2143,6 +3965,7 @@ async fn test_not_sync_candidate_if_mapping_does_not_have_small_repo(
             &ctx,
             first_bcs_id,
             None, // parents override
+            ParentOverrideStrictness::Strict,
             &noop_version_first_small_repo,
             CommitSyncContext::Tests,
         )
@@ -2174,8 +3997,89 @@ async fn test_not_sync_candidate_if_mapping_does_not_have_small_repo(
             .get_commit_sync_outcome(&ctx, first_bcs_id)
             .await?,
         Some(CommitSyncOutcome::NotSyncCandidate(
-            noop_version_first_small_repo
+            noop_version_first_small_repo.clone()
         ))
     );
+    assert_eq!(
+        large_to_second_small_commit_syncer
+            .sync_commit_detailed(
+                &ctx,
+                first_bcs_id,
+                CandidateSelectionHint::Only,
+                CommitSyncContext::Tests,
+                false,
+            )
+            .await?,
+        DetailedSyncOutcome::NotSyncCandidate(noop_version_first_small_repo)
+    );
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_rename_bookmarks_and_bookmark_diff(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let (small_repo, large_repo, mapping) = prepare_repos_and_mapping(fb).await?;
+    let commit_syncer = create_small_to_large_commit_syncer_with_bookmark_prefix(
+        &ctx,
+        small_repo.clone(),
+        large_repo.clone(),
+        "prefix",
+        mapping,
+        AsciiString::from_ascii("prefix/")?,
+    )?;
+
+    let renamed = commit_syncer
+        .rename_bookmarks(vec![BookmarkKey::new("a")?, BookmarkKey::new("b")?])
+        .await?;
+    assert_eq!(
+        renamed,
+        hashmap! {
+            BookmarkKey::new("a")? => Some(BookmarkKey::new("prefix/a")?),
+            BookmarkKey::new("b")? => Some(BookmarkKey::new("prefix/b")?),
+        }
+    );
+
+    let small_synced = CreateCommitContext::new_root(&ctx, &small_repo)
+        .add_file("file", "synced")
+        .commit()
+        .await?;
+    let small_unsynced = CreateCommitContext::new(&ctx, &small_repo, vec![small_synced])
+        .add_file("file", "unsynced")
+        .commit()
+        .await?;
+    let large_synced = CreateCommitContext::new_root(&ctx, &large_repo)
+        .add_file("prefix/file", "synced")
+        .commit()
+        .await?;
+    update_mapping_with_version(
+        &ctx,
+        hashmap! { small_synced => large_synced },
+        &commit_syncer,
+        &version_name_with_small_repo(),
+    )
+    .await?;
+
+    move_bookmark(&ctx, &small_repo, "consistent", small_synced).await;
+    move_bookmark(&ctx, &small_repo, "inconsistent", small_unsynced).await;
+    move_bookmark(&ctx, &small_repo, "missing", small_synced).await;
+    move_bookmark(&ctx, &large_repo, "prefix/consistent", large_synced).await;
+    move_bookmark(&ctx, &large_repo, "prefix/inconsistent", large_synced).await;
+    move_bookmark(&ctx, &large_repo, "prefix/stale", large_synced).await;
+    // Not renamed from any small repo bookmark, so not reported.
+    move_bookmark(&ctx, &large_repo, "unrelated", large_synced).await;
+
+    assert_eq!(
+        commit_syncer.bookmark_diff(&ctx).await?,
+        BookmarkDiff {
+            missing_in_target: vec![(BookmarkKey::new("prefix/missing")?, Source(small_synced))],
+            only_in_target: vec![(BookmarkKey::new("prefix/stale")?, Target(large_synced))],
+            different_values: vec![(
+                BookmarkKey::new("prefix/inconsistent")?,
+                Source(small_unsynced),
+                Target(large_synced),
+            )],
+        }
+    );
+
     Ok(())
 }
@@ -8,6 +8,7 @@
 use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::sync::Mutex;
 
 use anyhow::format_err;
 use anyhow::Error;
@@ -33,7 +34,10 @@ use cross_repo_sync::CommitSyncContext;
 use cross_repo_sync::CommitSyncDataProvider;
 use cross_repo_sync::CommitSyncRepos;
 use cross_repo_sync::CommitSyncer;
+use cross_repo_sync::MarkReport;
 use cross_repo_sync::Repo;
+use cross_repo_sync::RewriteReport;
+use cross_repo_sync::SyncReporter;
 use cross_repo_sync::Syncers;
 use filenodes::Filenodes;
 use filestore::FilestoreConfig;
@@ -98,6 +102,35 @@ impl AsBlobRepo for TestRepo {
     }
 }
 
+/// A `SyncReporter` that keeps the reports, for tests to check them.
+#[derive(Default)]
+pub struct RecordingSyncReporter {
+    reports: Mutex<Vec<RewriteReport>>,
+    mark_reports: Mutex<Vec<MarkReport>>,
+}
+
+impl RecordingSyncReporter {
+    /// The reports received so far, in the order they were received.
+    pub fn reports(&self) -> Vec<RewriteReport> {
+        self.reports.lock().unwrap().clone()
+    }
+
+    /// The mark reports received so far, in the order they were received.
+    pub fn mark_reports(&self) -> Vec<MarkReport> {
+        self.mark_reports.lock().unwrap().clone()
+    }
+}
+
+impl SyncReporter for RecordingSyncReporter {
+    fn report_rewrite(&self, _ctx: &CoreContext, report: &RewriteReport) {
+        self.reports.lock().unwrap().push(report.clone());
+    }
+
+    fn report_mark(&self, _ctx: &CoreContext, report: &MarkReport) {
+        self.mark_reports.lock().unwrap().push(report.clone());
+    }
+}
+
 pub fn xrepo_mapping_version_with_small_repo() -> CommitSyncConfigVersion {
     CommitSyncConfigVersion("TEST_VERSION_NAME".to_string())
 }
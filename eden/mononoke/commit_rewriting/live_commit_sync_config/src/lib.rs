@@ -102,6 +102,16 @@ pub trait LiveCommitSyncConfig: Send + Sync {
         version_name: &CommitSyncConfigVersion,
     ) -> Result<Option<CommitSyncConfig>>;
 
+    /// Return the version of `CommitSyncConfig` that is currently
+    /// used by repo `repo_id`, if the config source specifies one
+    ///
+    /// NOTE: two subsequent calls may return different results
+    ///       as this queries config source
+    fn get_current_commit_sync_config_version(
+        &self,
+        repo_id: RepositoryId,
+    ) -> Result<Option<CommitSyncConfigVersion>>;
+
     /// Returns a config that applies to all config versions
     fn get_common_config(&self, repo_id: RepositoryId) -> Result<CommonCommitSyncConfig> {
         self.get_common_config_if_exists(repo_id)?
@@ -250,6 +260,37 @@ impl LiveCommitSyncConfig for CfgrLiveCommitSyncConfig {
         Ok(version)
     }
 
+    /// Return the version of `CommitSyncConfig` that is currently
+    /// used by repo `repo_id`, if the config source specifies one
+    ///
+    /// NOTE: two subsequent calls may return different results
+    ///       as this queries config source
+    fn get_current_commit_sync_config_version(
+        &self,
+        repo_id: RepositoryId,
+    ) -> Result<Option<CommitSyncConfigVersion>> {
+        let large_repo_config_version_sets = &self.config_handle_for_all_versions.get().repos;
+
+        let mut iter = large_repo_config_version_sets
+            .values()
+            .filter(|config_version_set| Self::related_to_repo(config_version_set, repo_id));
+        let config_version_set = match (iter.next(), iter.next()) {
+            (None, _) => return Ok(None),
+            (Some(config_version_set), None) => config_version_set,
+            (Some(_), Some(_)) => {
+                return Err(ErrorKind::PartOfMultipleCommitSyncConfigsVersionSets(repo_id).into());
+            }
+        };
+
+        if config_version_set.current_version.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(CommitSyncConfigVersion(
+                config_version_set.current_version.clone(),
+            )))
+        }
+    }
+
     fn get_common_config_if_exists(
         &self,
         repo_id: RepositoryId,
@@ -285,6 +326,7 @@ struct TestLiveCommitSyncConfigSourceInner {
     version_to_config: Mutex<HashMap<CommitSyncConfigVersion, CommitSyncConfig>>,
     push_redirection_for_draft: Mutex<HashMap<RepositoryId, bool>>,
     push_redirection_for_public: Mutex<HashMap<RepositoryId, bool>>,
+    current_versions: Mutex<HashMap<RepositoryId, CommitSyncConfigVersion>>,
     common_configs: Mutex<Vec<CommonCommitSyncConfig>>,
 }
 
@@ -306,6 +348,7 @@ impl TestLiveCommitSyncConfigSource {
             version_to_config: Mutex::new(HashMap::new()),
             push_redirection_for_draft: Mutex::new(HashMap::new()),
             push_redirection_for_public: Mutex::new(HashMap::new()),
+            current_versions: Mutex::new(HashMap::new()),
             common_configs: Mutex::new(vec![]),
         }))
     }
@@ -334,6 +377,14 @@ impl TestLiveCommitSyncConfigSource {
             .insert(repo_id, true);
     }
 
+    pub fn set_current_version(&self, repo_id: RepositoryId, version: CommitSyncConfigVersion) {
+        self.0
+            .current_versions
+            .lock()
+            .expect("poisoned lock")
+            .insert(repo_id, version);
+    }
+
     pub fn add_common_config(&self, config: CommonCommitSyncConfig) {
         self.0
             .common_configs
@@ -401,6 +452,19 @@ impl TestLiveCommitSyncConfigSource {
         }
     }
 
+    fn get_current_commit_sync_config_version(
+        &self,
+        repo_id: RepositoryId,
+    ) -> Result<Option<CommitSyncConfigVersion>> {
+        Ok(self
+            .0
+            .current_versions
+            .lock()
+            .expect("poisoned lock")
+            .get(&repo_id)
+            .cloned())
+    }
+
     pub fn get_common_config_if_exists(
         &self,
         repo_id: RepositoryId,
@@ -478,6 +542,13 @@ impl LiveCommitSyncConfig for TestLiveCommitSyncConfig {
             .get_commit_sync_config_by_version_if_exists(repo_id, version_name)
     }
 
+    fn get_current_commit_sync_config_version(
+        &self,
+        repo_id: RepositoryId,
+    ) -> Result<Option<CommitSyncConfigVersion>> {
+        self.source.get_current_commit_sync_config_version(repo_id)
+    }
+
     fn get_common_config_if_exists(
         &self,
         repo_id: RepositoryId,
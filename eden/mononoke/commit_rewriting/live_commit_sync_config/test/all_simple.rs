@@ -7,6 +7,7 @@
 
 use fbinit::FacebookInit;
 use live_commit_sync_config::LiveCommitSyncConfig;
+use metaconfig_types::CommitSyncConfigVersion;
 use mononoke_types::RepositoryId;
 use pretty_assertions::assert_eq;
 
@@ -168,3 +169,28 @@ async fn test_version_counts(fb: FacebookInit) {
     assert_eq!(av0.len(), 2);
     assert_eq!(av4.len(), 1);
 }
+
+#[fbinit::test]
+async fn test_current_version(fb: FacebookInit) {
+    let (_ctx, _test_source, _store, live_commit_sync_config) =
+        get_ctx_source_store_and_live_config(fb, EMPTY_PUSHREDIRECTOR, ALL_COMMIT_SYNC_CONFIG_V1);
+
+    let current_version = Some(CommitSyncConfigVersion(
+        "TEST_VERSION_NAME_LIVE_2".to_string(),
+    ));
+    for repo_id in [0, 1, 2] {
+        assert_eq!(
+            live_commit_sync_config
+                .get_current_commit_sync_config_version(RepositoryId::new(repo_id))
+                .unwrap(),
+            current_version,
+        );
+    }
+
+    assert_eq!(
+        live_commit_sync_config
+            .get_current_commit_sync_config_version(RepositoryId::new(5))
+            .unwrap(),
+        None,
+    );
+}
@@ -13,6 +13,7 @@ use blobstore::Loadable;
 use context::CoreContext;
 use cross_repo_sync::CommitSyncContext;
 use cross_repo_sync::CommitSyncer;
+use cross_repo_sync::ParentOverrideStrictness;
 use cross_repo_sync::Repo as CrossRepo;
 use metaconfig_types::CommitSyncConfigVersion;
 use mononoke_types::ChangesetId;
@@ -64,6 +65,7 @@ pub async fn manual_commit_sync<M: SyncedCommitMapping + Clone + 'static, R: Cro
                 ctx,
                 source_cs_id,
                 Some(remapped_parents),
+                ParentOverrideStrictness::Strict,
                 &mapping_version,
                 CommitSyncContext::ManualCommitSync,
             )
@@ -74,6 +76,7 @@ pub async fn manual_commit_sync<M: SyncedCommitMapping + Clone + 'static, R: Cro
                 ctx,
                 source_cs_id,
                 None,
+                ParentOverrideStrictness::Strict,
                 &mapping_version,
                 CommitSyncContext::ManualCommitSync,
             )
@@ -25,6 +25,7 @@ use cross_repo_sync::CandidateSelectionHint;
 use cross_repo_sync::CommitSyncContext;
 use cross_repo_sync::CommitSyncOutcome;
 use cross_repo_sync::CommitSyncer;
+use cross_repo_sync::ParentOverrideStrictness;
 use cross_repo_sync::PushrebaseRewriteDates;
 use futures::future::try_join_all;
 use futures::stream::TryStreamExt;
@@ -347,6 +348,7 @@ pub async fn sync_commit_without_pushrebase<M: SyncedCommitMapping + Clone + 'st
                     ctx,
                     cs_id,
                     None,
+                    ParentOverrideStrictness::Strict,
                     version,
                     CommitSyncContext::XRepoSyncJob,
                 )
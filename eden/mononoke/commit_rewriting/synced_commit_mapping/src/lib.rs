@@ -5,6 +5,8 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::HashMap;
+
 use anyhow::anyhow;
 use anyhow::Error;
 use async_trait::async_trait;
@@ -55,11 +57,15 @@ define_stats! {
     prefix = "mononoke.synced_commit_mapping";
     gets: timeseries(Rate, Sum),
     gets_master: timeseries(Rate, Sum),
+    get_manys: timeseries(Rate, Sum),
+    get_manys_master: timeseries(Rate, Sum),
     adds: timeseries(Rate, Sum),
     add_many_in_txn: timeseries(Rate, Sum),
     add_bulks: timeseries(Rate, Sum),
     insert_working_copy_eqivalence: timeseries(Rate, Sum),
     get_equivalent_working_copy: timeseries(Rate, Sum),
+    deletes: timeseries(Rate, Sum),
+    delete_working_copy_equivalence: timeseries(Rate, Sum),
 }
 
 // Repo that originally contained the synced commit
@@ -203,6 +209,38 @@ pub trait SyncedCommitMapping: Send + Sync {
         Error,
     >;
 
+    /// Find all the mapping entries for the given source commits and target
+    /// repo, keyed by source commit. Source commits without mapping entries
+    /// are absent from the result.
+    async fn get_many(
+        &self,
+        ctx: &CoreContext,
+        source_repo_id: RepositoryId,
+        bcs_ids: &[ChangesetId],
+        target_repo_id: RepositoryId,
+    ) -> Result<
+        HashMap<
+            ChangesetId,
+            Vec<(
+                ChangesetId,
+                Option<CommitSyncConfigVersion>,
+                Option<SyncedCommitSourceRepo>,
+            )>,
+        >,
+        Error,
+    > {
+        let mut res = HashMap::new();
+        for bcs_id in bcs_ids {
+            let targets = self
+                .get(ctx, source_repo_id, *bcs_id, target_repo_id)
+                .await?;
+            if !targets.is_empty() {
+                res.insert(*bcs_id, targets);
+            }
+        }
+        Ok(res)
+    }
+
     /// Inserts equivalent working copy of a large bcs id. It's similar to mapping entry,
     /// however there are a few differences:
     /// 1) For (large repo, small repo) pair, many large commits can map to the same small commit
@@ -240,6 +278,28 @@ pub trait SyncedCommitMapping: Send + Sync {
         large_repo_id: RepositoryId,
         large_repo_cs_id: ChangesetId,
     ) -> Result<Option<CommitSyncConfigVersion>, Error>;
+
+    /// Delete all the mapping entries for a given source commit and target repo,
+    /// the ones `get` finds. Returns the number of deleted entries.
+    /// This is not intended to be used by syncing, just to fix the mapping by hand
+    async fn delete(
+        &self,
+        ctx: &CoreContext,
+        source_repo_id: RepositoryId,
+        bcs_id: ChangesetId,
+        target_repo_id: RepositoryId,
+    ) -> Result<u64, Error>;
+
+    /// Delete all the equivalent working copies for a given source commit and
+    /// target repo. Returns the number of deleted entries.
+    /// This is not intended to be used by syncing, just to fix the mapping by hand
+    async fn delete_equivalent_working_copy(
+        &self,
+        ctx: &CoreContext,
+        source_repo_id: RepositoryId,
+        source_bcs_id: ChangesetId,
+        target_repo_id: RepositoryId,
+    ) -> Result<u64, Error>;
 }
 
 #[derive(Clone)]
@@ -273,6 +333,28 @@ mononoke_queries! {
           (small_repo_id = {source_repo_id} AND small_bcs_id = {bcs_id} AND large_repo_id = {target_repo_id})"
     }
 
+    read SelectManyMappings(
+        source_repo_id: RepositoryId,
+        target_repo_id: RepositoryId,
+        >list bcs_ids: ChangesetId
+    ) -> (RepositoryId, ChangesetId, RepositoryId, ChangesetId, Option<CommitSyncConfigVersion>, Option<SyncedCommitSourceRepo>) {
+        "SELECT large_repo_id, large_bcs_id, small_repo_id, small_bcs_id, sync_map_version_name, source_repo
+          FROM synced_commit_mapping
+          WHERE (large_repo_id = {source_repo_id} AND small_repo_id = {target_repo_id} AND large_bcs_id IN {bcs_ids}) OR
+          (small_repo_id = {source_repo_id} AND large_repo_id = {target_repo_id} AND small_bcs_id IN {bcs_ids})"
+    }
+
+    write DeleteMapping(
+        source_repo_id: RepositoryId,
+        bcs_id: ChangesetId,
+        target_repo_id: RepositoryId,
+    ) {
+        none,
+        "DELETE FROM synced_commit_mapping
+          WHERE (large_repo_id = {source_repo_id} AND large_bcs_id = {bcs_id} AND small_repo_id = {target_repo_id}) OR
+          (small_repo_id = {source_repo_id} AND small_bcs_id = {bcs_id} AND large_repo_id = {target_repo_id})"
+    }
+
     write InsertWorkingCopyEquivalence(values: (
         large_repo_id: RepositoryId,
         large_bcs_id: ChangesetId,
@@ -315,6 +397,17 @@ mononoke_queries! {
           "
     }
 
+    write DeleteWorkingCopyEquivalence(
+        source_repo_id: RepositoryId,
+        bcs_id: ChangesetId,
+        target_repo_id: RepositoryId,
+    ) {
+        none,
+        "DELETE FROM synced_working_copy_equivalence
+          WHERE (large_repo_id = {source_repo_id} AND small_repo_id = {target_repo_id} AND large_bcs_id = {bcs_id})
+          OR (large_repo_id = {target_repo_id} AND small_repo_id = {source_repo_id} AND small_bcs_id = {bcs_id})"
+    }
+
     write InsertVersionForLargeRepoCommit(values: (
         large_repo_id: RepositoryId,
         large_bcs_id: ChangesetId,
@@ -349,6 +442,47 @@ mononoke_queries! {
     }
 }
 
+/// Split a `synced_commit_mapping` row into the source commit and what it was
+/// synced as in `target_repo_id`
+fn split_mapping_row(
+    target_repo_id: RepositoryId,
+    row: (
+        RepositoryId,
+        ChangesetId,
+        RepositoryId,
+        ChangesetId,
+        Option<CommitSyncConfigVersion>,
+        Option<SyncedCommitSourceRepo>,
+    ),
+) -> (
+    ChangesetId,
+    (
+        ChangesetId,
+        Option<CommitSyncConfigVersion>,
+        Option<SyncedCommitSourceRepo>,
+    ),
+) {
+    let (
+        large_repo_id,
+        large_bcs_id,
+        _small_repo_id,
+        small_bcs_id,
+        maybe_version_name,
+        maybe_source_repo,
+    ) = row;
+    if target_repo_id == large_repo_id {
+        (
+            small_bcs_id,
+            (large_bcs_id, maybe_version_name, maybe_source_repo),
+        )
+    } else {
+        (
+            large_bcs_id,
+            (small_bcs_id, maybe_version_name, maybe_source_repo),
+        )
+    }
+}
+
 impl SqlConstruct for SqlSyncedCommitMapping {
     const LABEL: &'static str = "synced_commit_mapping";
 
@@ -579,24 +713,72 @@ impl SyncedCommitMapping for SqlSyncedCommitMapping {
 
         Ok(rows
             .into_iter()
-            .map(|row| {
-                let (
-                    large_repo_id,
-                    large_bcs_id,
-                    _small_repo_id,
-                    small_bcs_id,
-                    maybe_version_name,
-                    maybe_source_repo,
-                ) = row;
-                if target_repo_id == large_repo_id {
-                    (large_bcs_id, maybe_version_name, maybe_source_repo)
-                } else {
-                    (small_bcs_id, maybe_version_name, maybe_source_repo)
-                }
-            })
+            .map(|row| split_mapping_row(target_repo_id, row).1)
             .collect())
     }
 
+    async fn get_many(
+        &self,
+        ctx: &CoreContext,
+        source_repo_id: RepositoryId,
+        bcs_ids: &[ChangesetId],
+        target_repo_id: RepositoryId,
+    ) -> Result<
+        HashMap<
+            ChangesetId,
+            Vec<(
+                ChangesetId,
+                Option<CommitSyncConfigVersion>,
+                Option<SyncedCommitSourceRepo>,
+            )>,
+        >,
+        Error,
+    > {
+        if bcs_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        STATS::get_manys.add_value(1);
+
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlReadsReplica);
+        let mut res = HashMap::new();
+        let rows = SelectManyMappings::query(
+            &self.read_connection,
+            &source_repo_id,
+            &target_repo_id,
+            bcs_ids,
+        )
+        .await?;
+        for row in rows {
+            let (source_bcs_id, target) = split_mapping_row(target_repo_id, row);
+            res.entry(source_bcs_id).or_default().push(target);
+        }
+
+        let missing: Vec<_> = bcs_ids
+            .iter()
+            .filter(|bcs_id| !res.contains_key(*bcs_id))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            STATS::get_manys_master.add_value(1);
+            ctx.perf_counters()
+                .increment_counter(PerfCounterType::SqlReadsMaster);
+            let rows = SelectManyMappings::query(
+                &self.read_master_connection,
+                &source_repo_id,
+                &target_repo_id,
+                &missing,
+            )
+            .await?;
+            for row in rows {
+                let (source_bcs_id, target) = split_mapping_row(target_repo_id, row);
+                res.entry(source_bcs_id).or_default().push(target);
+            }
+        }
+
+        Ok(res)
+    }
+
     async fn insert_equivalent_working_copy(
         &self,
         ctx: &CoreContext,
@@ -718,6 +900,48 @@ impl SyncedCommitMapping for SqlSyncedCommitMapping {
         .pop()
         .map(|x| x.0))
     }
+
+    async fn delete(
+        &self,
+        ctx: &CoreContext,
+        source_repo_id: RepositoryId,
+        bcs_id: ChangesetId,
+        target_repo_id: RepositoryId,
+    ) -> Result<u64, Error> {
+        STATS::deletes.add_value(1);
+
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlWrites);
+        let result = DeleteMapping::query(
+            &self.write_connection,
+            &source_repo_id,
+            &bcs_id,
+            &target_repo_id,
+        )
+        .await?;
+        Ok(result.affected_rows())
+    }
+
+    async fn delete_equivalent_working_copy(
+        &self,
+        ctx: &CoreContext,
+        source_repo_id: RepositoryId,
+        source_bcs_id: ChangesetId,
+        target_repo_id: RepositoryId,
+    ) -> Result<u64, Error> {
+        STATS::delete_working_copy_equivalence.add_value(1);
+
+        ctx.perf_counters()
+            .increment_counter(PerfCounterType::SqlWrites);
+        let result = DeleteWorkingCopyEquivalence::query(
+            &self.write_connection,
+            &source_repo_id,
+            &source_bcs_id,
+            &target_repo_id,
+        )
+        .await?;
+        Ok(result.affected_rows())
+    }
 }
 
 pub async fn add_many_in_txn(
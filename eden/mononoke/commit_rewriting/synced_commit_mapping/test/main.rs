@@ -7,6 +7,8 @@
 
 //! Tests for the synced commits mapping.
 
+use std::collections::HashMap;
+
 use anyhow::Error;
 use context::CoreContext;
 use fbinit::FacebookInit;
@@ -333,3 +335,152 @@ async fn test_overwrite(fb: FacebookInit) -> Result<(), Error> {
 
     Ok(())
 }
+
+#[fbinit::test]
+async fn test_delete(fb: FacebookInit) -> Result<(), Error> {
+    let mapping = SqlSyncedCommitMapping::with_sqlite_in_memory()?;
+    let ctx = CoreContext::test_mock(fb);
+    let version_name = CommitSyncConfigVersion("TEST_VERSION_NAME".to_string());
+
+    let entry = SyncedCommitMappingEntry::new(
+        REPO_ZERO,
+        bonsai::ONES_CSID,
+        REPO_ONE,
+        bonsai::TWOS_CSID,
+        version_name.clone(),
+        SyncedCommitSourceRepo::Large,
+    );
+    assert!(mapping.add(&ctx, entry).await?);
+    let entry = EquivalentWorkingCopyEntry {
+        large_repo_id: REPO_ZERO,
+        large_bcs_id: bonsai::THREES_CSID,
+        small_repo_id: REPO_ONE,
+        small_bcs_id: None,
+        version_name: Some(version_name.clone()),
+    };
+    assert!(mapping.insert_equivalent_working_copy(&ctx, entry).await?);
+
+    // Entries are found from either side, and deleted from either side.
+    assert_eq!(
+        mapping
+            .delete(&ctx, REPO_ONE, bonsai::TWOS_CSID, REPO_ZERO)
+            .await?,
+        1
+    );
+    assert!(
+        mapping
+            .get(&ctx, REPO_ZERO, bonsai::ONES_CSID, REPO_ONE)
+            .await?
+            .is_empty()
+    );
+    assert_eq!(
+        mapping
+            .delete(&ctx, REPO_ZERO, bonsai::ONES_CSID, REPO_ONE)
+            .await?,
+        0
+    );
+    // Adding a mapping adds an equivalent working copy too.
+    assert_eq!(
+        mapping
+            .delete_equivalent_working_copy(&ctx, REPO_ZERO, bonsai::ONES_CSID, REPO_ONE)
+            .await?,
+        1
+    );
+
+    assert_eq!(
+        mapping
+            .get_equivalent_working_copy(&ctx, REPO_ZERO, bonsai::THREES_CSID, REPO_ONE)
+            .await?,
+        Some(WorkingCopyEquivalence::NoWorkingCopy(version_name.clone()))
+    );
+    assert_eq!(
+        mapping
+            .delete_equivalent_working_copy(&ctx, REPO_ZERO, bonsai::THREES_CSID, REPO_ONE)
+            .await?,
+        1
+    );
+    assert_eq!(
+        mapping
+            .get_equivalent_working_copy(&ctx, REPO_ZERO, bonsai::THREES_CSID, REPO_ONE)
+            .await?,
+        None
+    );
+    // The version of the large repo commit is kept.
+    assert_eq!(
+        mapping
+            .get_large_repo_commit_version(&ctx, REPO_ZERO, bonsai::THREES_CSID)
+            .await?,
+        Some(version_name)
+    );
+
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_get_many(fb: FacebookInit) -> Result<(), Error> {
+    let mapping = SqlSyncedCommitMapping::with_sqlite_in_memory()?;
+    let ctx = CoreContext::test_mock(fb);
+    let version_name = CommitSyncConfigVersion("TEST_VERSION_NAME".to_string());
+
+    for (large_bcs_id, small_bcs_id) in [
+        (bonsai::ONES_CSID, bonsai::TWOS_CSID),
+        (bonsai::THREES_CSID, bonsai::TWOS_CSID),
+        (bonsai::FIVES_CSID, bonsai::SIXES_CSID),
+    ] {
+        let entry = SyncedCommitMappingEntry::new(
+            REPO_ZERO,
+            large_bcs_id,
+            REPO_ONE,
+            small_bcs_id,
+            version_name.clone(),
+            SyncedCommitSourceRepo::Large,
+        );
+        assert!(mapping.add(&ctx, entry).await?);
+    }
+
+    let target = |bcs_id| {
+        (
+            bcs_id,
+            Some(version_name.clone()),
+            Some(SyncedCommitSourceRepo::Large),
+        )
+    };
+
+    // Commits without entries are absent from the result
+    let res = mapping
+        .get_many(
+            &ctx,
+            REPO_ZERO,
+            &[bonsai::ONES_CSID, bonsai::FIVES_CSID, bonsai::SEVENS_CSID],
+            REPO_ONE,
+        )
+        .await?;
+    assert_eq!(
+        res,
+        HashMap::from([
+            (bonsai::ONES_CSID, vec![target(bonsai::TWOS_CSID)]),
+            (bonsai::FIVES_CSID, vec![target(bonsai::SIXES_CSID)]),
+        ])
+    );
+
+    // All the entries of a commit are returned, from either side
+    let mut res = mapping
+        .get_many(&ctx, REPO_ONE, &[bonsai::TWOS_CSID], REPO_ZERO)
+        .await?;
+    let mut twos_targets = res.remove(&bonsai::TWOS_CSID).unwrap_or_default();
+    twos_targets.sort_by_key(|(bcs_id, _, _)| *bcs_id);
+    assert!(res.is_empty());
+    assert_eq!(
+        twos_targets,
+        vec![target(bonsai::ONES_CSID), target(bonsai::THREES_CSID)]
+    );
+
+    assert!(
+        mapping
+            .get_many(&ctx, REPO_ZERO, &[], REPO_ONE)
+            .await?
+            .is_empty()
+    );
+
+    Ok(())
+}
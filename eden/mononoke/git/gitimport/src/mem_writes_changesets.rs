@@ -14,6 +14,7 @@ use async_trait::async_trait;
 use changesets::ChangesetEntry;
 use changesets::ChangesetInsert;
 use changesets::Changesets;
+use changesets::EnumerationEntry;
 use changesets::SortOrder;
 use context::CoreContext;
 use futures::future;
@@ -50,7 +51,11 @@ impl<T: Changesets + Clone + 'static> Changesets for MemWritesChangesets<T> {
     }
 
     async fn add(&self, ctx: &CoreContext, ci: ChangesetInsert) -> Result<bool, Error> {
-        let ChangesetInsert { cs_id, parents } = ci;
+        let ChangesetInsert {
+            cs_id,
+            parents,
+            extra,
+        } = ci;
 
         let cs = self.get(ctx, cs_id);
         let parent_css = self.get_many(ctx, parents.clone());
@@ -66,6 +71,7 @@ impl<T: Changesets + Clone + 'static> Changesets for MemWritesChangesets<T> {
                 cs_id,
                 parents,
                 gen,
+                extra,
             };
 
             self.cache.with(|cache| cache.insert(cs_id, entry));
@@ -140,15 +146,22 @@ impl<T: Changesets + Clone + 'static> Changesets for MemWritesChangesets<T> {
             .await
     }
 
-    fn list_enumeration_range(
+    fn list_enumeration_range_detailed(
         &self,
         ctx: &CoreContext,
         min_id: u64,
         max_id: u64,
         sort_and_limit: Option<(SortOrder, u64)>,
         read_from_master: bool,
-    ) -> BoxStream<'_, Result<(ChangesetId, u64), Error>> {
-        self.inner
-            .list_enumeration_range(ctx, min_id, max_id, sort_and_limit, read_from_master)
+        include_parents: bool,
+    ) -> BoxStream<'_, Result<EnumerationEntry, Error>> {
+        self.inner.list_enumeration_range_detailed(
+            ctx,
+            min_id,
+            max_id,
+            sort_and_limit,
+            read_from_master,
+            include_parents,
+        )
     }
 }
@@ -7,20 +7,23 @@
 
 mod errors;
 mod memory;
+mod prefetched;
 mod repo;
 mod store;
 mod text_only;
 
 use bookmarks::BookmarksArc;
-use errors::ErrorKind;
+pub use errors::ErrorKind;
 use repo_blobstore::RepoBlobstoreArc;
 use repo_derived_data::RepoDerivedDataArc;
 pub use store::FileChange;
 pub use store::FileContentManager;
 pub use store::PathContent;
+pub use store::FILE_CONTENTS_CONCURRENCY;
 
 pub use crate::memory::InMemoryFileContentManager;
 pub use crate::memory::InMemoryFileText;
+pub use crate::prefetched::PrefetchedFileContentManager;
 pub use crate::repo::RepoFileContentManager;
 pub use crate::text_only::TextOnlyFileContentManager;
 
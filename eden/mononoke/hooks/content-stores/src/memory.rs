@@ -5,6 +5,7 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 
 use anyhow::format_err;
@@ -17,6 +18,7 @@ use mononoke_types::ChangesetId;
 use mononoke_types::ContentId;
 use mononoke_types::MPath;
 
+use crate::store::truncate;
 use crate::ErrorKind;
 use crate::FileChange;
 use crate::FileContentManager;
@@ -26,6 +28,13 @@ use crate::PathContent;
 pub enum InMemoryFileText {
     Present(Bytes),
     Elided(u64),
+    /// `size` copies of `byte`, generated when fetched, e.g. for files too
+    /// large to keep in memory. The text is elided like `Elided`, but prefixes
+    /// are served.
+    Repeated {
+        byte: u8,
+        size: u64,
+    },
 }
 
 impl From<Bytes> for InMemoryFileText {
@@ -50,6 +59,7 @@ impl From<u64> for InMemoryFileText {
 #[derive(Clone)]
 pub struct InMemoryFileContentManager {
     id_to_text: HashMap<ContentId, InMemoryFileText>,
+    changeset_files: HashMap<ChangesetId, BTreeMap<MPath, ContentId>>,
 }
 
 #[async_trait]
@@ -59,13 +69,7 @@ impl FileContentManager for InMemoryFileContentManager {
         _ctx: &'a CoreContext,
         id: ContentId,
     ) -> Result<u64, ErrorKind> {
-        self.id_to_text
-            .get(&id)
-            .ok_or(ErrorKind::ContentIdNotFound(id))
-            .map(|maybe_bytes| match maybe_bytes {
-                InMemoryFileText::Present(bytes) => bytes.len() as u64,
-                InMemoryFileText::Elided(size) => *size,
-            })
+        self.size(id)
     }
 
     async fn get_file_text<'a>(
@@ -73,13 +77,36 @@ impl FileContentManager for InMemoryFileContentManager {
         _ctx: &'a CoreContext,
         id: ContentId,
     ) -> Result<Option<Bytes>, ErrorKind> {
-        self.id_to_text
-            .get(&id)
-            .ok_or(ErrorKind::ContentIdNotFound(id))
-            .map(|maybe_bytes| match maybe_bytes {
-                InMemoryFileText::Present(bytes) => Some(bytes.clone()),
-                InMemoryFileText::Elided(_) => None,
-            })
+        self.text(id)
+    }
+
+    async fn get_file_prefix<'a>(
+        &'a self,
+        _ctx: &'a CoreContext,
+        id: ContentId,
+        len: u64,
+    ) -> Result<Option<Bytes>, ErrorKind> {
+        self.prefix(id, len)
+    }
+
+    async fn get_file_sizes<'a>(
+        &'a self,
+        _ctx: &'a CoreContext,
+        ids: Vec<ContentId>,
+    ) -> Result<HashMap<ContentId, u64>, ErrorKind> {
+        ids.into_iter()
+            .map(|id| self.size(id).map(|size| (id, size)))
+            .collect()
+    }
+
+    async fn get_file_texts<'a>(
+        &'a self,
+        _ctx: &'a CoreContext,
+        ids: Vec<ContentId>,
+    ) -> Result<HashMap<ContentId, Option<Bytes>>, ErrorKind> {
+        ids.into_iter()
+            .map(|id| self.text(id).map(|text| (id, text)))
+            .collect()
     }
 
     async fn find_content<'a>(
@@ -117,16 +144,99 @@ impl FileContentManager for InMemoryFileContentManager {
                 .into(),
         )
     }
+
+    async fn find_content_by_changeset_id<'a>(
+        &'a self,
+        _ctx: &'a CoreContext,
+        changeset_id: ChangesetId,
+        paths: Vec<MPath>,
+    ) -> Result<HashMap<MPath, PathContent>, ErrorKind> {
+        let files = match self.changeset_files.get(&changeset_id) {
+            Some(files) => files,
+            None => return Ok(HashMap::new()),
+        };
+        Ok(paths
+            .into_iter()
+            .filter_map(|path| {
+                if let Some(id) = files.get(&path) {
+                    Some((path, PathContent::File(*id)))
+                } else if files.keys().any(|file| path.is_prefix_of(file)) {
+                    Some((path, PathContent::Directory))
+                } else {
+                    None
+                }
+            })
+            .collect())
+    }
+
+    async fn list_dir<'a>(
+        &'a self,
+        _ctx: &'a CoreContext,
+        changeset_id: ChangesetId,
+        dir: MPath,
+    ) -> Result<Vec<MPath>, ErrorKind> {
+        Ok(self
+            .changeset_files
+            .get(&changeset_id)
+            .into_iter()
+            .flat_map(|files| files.keys())
+            .filter(|file| *file != &dir && dir.is_prefix_of(*file))
+            .cloned()
+            .collect())
+    }
 }
 
 impl InMemoryFileContentManager {
     pub fn new() -> InMemoryFileContentManager {
         InMemoryFileContentManager {
             id_to_text: HashMap::new(),
+            changeset_files: HashMap::new(),
         }
     }
 
+    fn size(&self, id: ContentId) -> Result<u64, ErrorKind> {
+        self.id_to_text
+            .get(&id)
+            .ok_or(ErrorKind::ContentIdNotFound(id))
+            .map(|maybe_bytes| match maybe_bytes {
+                InMemoryFileText::Present(bytes) => bytes.len() as u64,
+                InMemoryFileText::Elided(size) | InMemoryFileText::Repeated { size, .. } => *size,
+            })
+    }
+
+    fn text(&self, id: ContentId) -> Result<Option<Bytes>, ErrorKind> {
+        self.id_to_text
+            .get(&id)
+            .ok_or(ErrorKind::ContentIdNotFound(id))
+            .map(|maybe_bytes| match maybe_bytes {
+                InMemoryFileText::Present(bytes) => Some(bytes.clone()),
+                InMemoryFileText::Elided(_) | InMemoryFileText::Repeated { .. } => None,
+            })
+    }
+
+    fn prefix(&self, id: ContentId, len: u64) -> Result<Option<Bytes>, ErrorKind> {
+        self.id_to_text
+            .get(&id)
+            .ok_or(ErrorKind::ContentIdNotFound(id))
+            .map(|maybe_bytes| match maybe_bytes {
+                InMemoryFileText::Present(bytes) => Some(truncate(bytes.clone(), len)),
+                InMemoryFileText::Elided(_) => None,
+                InMemoryFileText::Repeated { byte, size } => {
+                    Some(Bytes::from(vec![*byte; len.min(*size) as usize]))
+                }
+            })
+    }
+
     pub fn insert(&mut self, key: ContentId, text: impl Into<InMemoryFileText>) {
         self.id_to_text.insert(key, text.into());
     }
+
+    /// Record that `path` has content `key` in changeset `cs_id`, for the
+    /// lookups that work on whole changesets (e.g. `file_contents`).
+    pub fn insert_file_at(&mut self, cs_id: ChangesetId, path: MPath, key: ContentId) {
+        self.changeset_files
+            .entry(cs_id)
+            .or_default()
+            .insert(path, key);
+    }
 }
@@ -0,0 +1,157 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use bookmarks::BookmarkKey;
+use bytes::Bytes;
+use changeset_info::ChangesetInfo;
+use context::CoreContext;
+use futures::try_join;
+use mononoke_types::ChangesetId;
+use mononoke_types::ContentId;
+use mononoke_types::MPath;
+
+use crate::store::truncate;
+use crate::ErrorKind;
+use crate::FileChange;
+use crate::FileContentManager;
+use crate::PathContent;
+
+/// Wraps a `FileContentManager` with the sizes, and optionally the texts, of
+/// some contents fetched up front in one batch, e.g. the contents of a
+/// changeset before running file hooks on it. Lookups of other contents, and
+/// all other methods, go to the inner manager.
+pub struct PrefetchedFileContentManager<'a> {
+    inner: &'a dyn FileContentManager,
+    sizes: HashMap<ContentId, u64>,
+    texts: HashMap<ContentId, Option<Bytes>>,
+}
+
+impl<'a> PrefetchedFileContentManager<'a> {
+    /// Wrap `inner` without prefetching anything.
+    pub fn empty(inner: &'a dyn FileContentManager) -> Self {
+        Self {
+            inner,
+            sizes: HashMap::new(),
+            texts: HashMap::new(),
+        }
+    }
+
+    /// Fetch the sizes of `ids`, and their texts if `texts` is set, with one
+    /// `get_file_sizes` and one `get_file_texts` call to `inner`.
+    pub async fn prefetch(
+        ctx: &CoreContext,
+        inner: &'a dyn FileContentManager,
+        ids: Vec<ContentId>,
+        texts: bool,
+    ) -> Result<Self, ErrorKind> {
+        if ids.is_empty() {
+            return Ok(Self::empty(inner));
+        }
+        let fetch_texts = async {
+            if texts {
+                inner.get_file_texts(ctx, ids.clone()).await
+            } else {
+                Ok(HashMap::new())
+            }
+        };
+        let (sizes, texts) = try_join!(inner.get_file_sizes(ctx, ids.clone()), fetch_texts)?;
+        Ok(Self {
+            inner,
+            sizes,
+            texts,
+        })
+    }
+}
+
+#[async_trait]
+impl<'m> FileContentManager for PrefetchedFileContentManager<'m> {
+    async fn get_file_size<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        id: ContentId,
+    ) -> Result<u64, ErrorKind> {
+        match self.sizes.get(&id) {
+            Some(size) => Ok(*size),
+            None => self.inner.get_file_size(ctx, id).await,
+        }
+    }
+
+    async fn get_file_text<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        id: ContentId,
+    ) -> Result<Option<Bytes>, ErrorKind> {
+        match self.texts.get(&id) {
+            Some(text) => Ok(text.clone()),
+            None => self.inner.get_file_text(ctx, id).await,
+        }
+    }
+
+    /// Prefixes of prefetched texts are served from memory.
+    async fn get_file_prefix<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        id: ContentId,
+        len: u64,
+    ) -> Result<Option<Bytes>, ErrorKind> {
+        match self.texts.get(&id) {
+            Some(Some(text)) => Ok(Some(truncate(text.clone(), len))),
+            // The text was elided or not prefetched.
+            _ => self.inner.get_file_prefix(ctx, id, len).await,
+        }
+    }
+
+    async fn find_content<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        bookmark: BookmarkKey,
+        paths: Vec<MPath>,
+    ) -> Result<HashMap<MPath, PathContent>, ErrorKind> {
+        self.inner.find_content(ctx, bookmark, paths).await
+    }
+
+    async fn file_changes<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        new_cs_id: ChangesetId,
+        old_cs_id: ChangesetId,
+    ) -> Result<Vec<(MPath, FileChange)>, ErrorKind> {
+        self.inner.file_changes(ctx, new_cs_id, old_cs_id).await
+    }
+
+    async fn latest_changes<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        bookmark: BookmarkKey,
+        paths: Vec<MPath>,
+    ) -> Result<HashMap<MPath, ChangesetInfo>, ErrorKind> {
+        self.inner.latest_changes(ctx, bookmark, paths).await
+    }
+
+    async fn find_content_by_changeset_id<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        changeset_id: ChangesetId,
+        paths: Vec<MPath>,
+    ) -> Result<HashMap<MPath, PathContent>, ErrorKind> {
+        self.inner
+            .find_content_by_changeset_id(ctx, changeset_id, paths)
+            .await
+    }
+
+    async fn list_dir<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        changeset_id: ChangesetId,
+        dir: MPath,
+    ) -> Result<Vec<MPath>, ErrorKind> {
+        self.inner.list_dir(ctx, changeset_id, dir).await
+    }
+}
@@ -39,6 +39,7 @@ use repo_derived_data::RepoDerivedData;
 use repo_derived_data::RepoDerivedDataArc;
 use unodes::RootUnodeManifestId;
 
+use crate::store::truncate;
 use crate::ErrorKind;
 use crate::FileChange;
 use crate::FileContentManager;
@@ -76,6 +77,20 @@ impl FileContentManager for RepoFileContentManager {
             .map(Option::Some)
     }
 
+    async fn get_file_prefix<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        id: ContentId,
+        len: u64,
+    ) -> Result<Option<Bytes>, ErrorKind> {
+        // `peek` buffers `size` bytes, which must not be 0.
+        let size = usize::try_from(len.max(1))?;
+        let prefix = filestore::peek(&self.repo_blobstore, ctx, &id.into(), size)
+            .await?
+            .ok_or(ErrorKind::ContentIdNotFound(id))?;
+        Ok(Some(truncate(prefix, len)))
+    }
+
     async fn find_content<'a>(
         &'a self,
         ctx: &'a CoreContext,
@@ -89,27 +104,7 @@ impl FileContentManager for RepoFileContentManager {
             .with_context(|| format!("Error fetching bookmark: {}", bookmark))?
             .ok_or_else(|| format_err!("Bookmark {} does not exist", bookmark))?;
 
-        let master_mf = derive_hg_manifest(
-            ctx,
-            &self.repo_derived_data,
-            &self.repo_blobstore,
-            changeset_id,
-        )
-        .await?;
-        master_mf
-            .find_entries(ctx.clone(), self.repo_blobstore.clone(), paths)
-            .map_ok(|(mb_path, entry)| async move {
-                if let Some(path) = mb_path {
-                    let content = resolve_content_id(ctx, &self.repo_blobstore, entry).await?;
-                    Ok(Some((path, content)))
-                } else {
-                    Ok(None)
-                }
-            })
-            .try_buffer_unordered(100)
-            .try_filter_map(future::ok)
-            .try_collect::<HashMap<_, _>>()
-            .map_err(ErrorKind::from)
+        self.find_content_by_changeset_id(ctx, changeset_id, paths)
             .await
     }
 
@@ -222,6 +217,55 @@ impl FileContentManager for RepoFileContentManager {
             .map_err(ErrorKind::from)
             .await
     }
+
+    async fn find_content_by_changeset_id<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        changeset_id: ChangesetId,
+        paths: Vec<MPath>,
+    ) -> Result<HashMap<MPath, PathContent>, ErrorKind> {
+        let mf = derive_hg_manifest(
+            ctx,
+            &self.repo_derived_data,
+            &self.repo_blobstore,
+            changeset_id,
+        )
+        .await?;
+        mf.find_entries(ctx.clone(), self.repo_blobstore.clone(), paths)
+            .map_ok(|(mb_path, entry)| async move {
+                if let Some(path) = mb_path {
+                    let content = resolve_content_id(ctx, &self.repo_blobstore, entry).await?;
+                    Ok(Some((path, content)))
+                } else {
+                    Ok(None)
+                }
+            })
+            .try_buffer_unordered(100)
+            .try_filter_map(future::ok)
+            .try_collect::<HashMap<_, _>>()
+            .map_err(ErrorKind::from)
+            .await
+    }
+
+    async fn list_dir<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        changeset_id: ChangesetId,
+        dir: MPath,
+    ) -> Result<Vec<MPath>, ErrorKind> {
+        let mf = derive_hg_manifest(
+            ctx,
+            &self.repo_derived_data,
+            &self.repo_blobstore,
+            changeset_id,
+        )
+        .await?;
+        mf.list_leaf_entries_under(ctx.clone(), self.repo_blobstore.clone(), vec![dir])
+            .map_ok(|(path, _leaf)| path)
+            .try_collect::<Vec<_>>()
+            .map_err(ErrorKind::from)
+            .await
+    }
 }
 
 impl RepoFileContentManager {
@@ -6,18 +6,26 @@
  */
 
 use std::collections::HashMap;
+use std::collections::HashSet;
 
+use anyhow::format_err;
 use async_trait::async_trait;
 use bookmarks::BookmarkKey;
 use bytes::Bytes;
 use changeset_info::ChangesetInfo;
 use context::CoreContext;
+use futures::stream;
+use futures::StreamExt;
+use futures::TryStreamExt;
 use mononoke_types::ChangesetId;
 use mononoke_types::ContentId;
 use mononoke_types::MPath;
 
 use crate::ErrorKind;
 
+/// Maximum number of file content lookups `file_contents` has in flight.
+pub const FILE_CONTENTS_CONCURRENCY: usize = 100;
+
 #[async_trait]
 pub trait FileContentManager: Send + Sync {
     async fn get_file_size<'a>(
@@ -32,6 +40,20 @@ pub trait FileContentManager: Send + Sync {
         id: ContentId,
     ) -> Result<Option<Bytes>, ErrorKind>;
 
+    /// Fetch at most the first `len` bytes of a content, e.g. to tell whether
+    /// it is binary without fetching all of it. Unlike `get_file_text`, this
+    /// should not be elided because the content is large or looks binary.
+    /// By default, this truncates the text returned by `get_file_text`.
+    async fn get_file_prefix<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        id: ContentId,
+        len: u64,
+    ) -> Result<Option<Bytes>, ErrorKind> {
+        let text = self.get_file_text(ctx, id).await?;
+        Ok(text.map(|text| truncate(text, len)))
+    }
+
     async fn find_content<'a>(
         &'a self,
         ctx: &'a CoreContext,
@@ -52,6 +74,128 @@ pub trait FileContentManager: Send + Sync {
         bookmark: BookmarkKey,
         paths: Vec<MPath>,
     ) -> Result<HashMap<MPath, ChangesetInfo>, ErrorKind>;
+
+    /// Like `find_content`, but looks the paths up in the given changeset
+    /// (e.g. the one being pushed) rather than at a bookmark.
+    async fn find_content_by_changeset_id<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        changeset_id: ChangesetId,
+        paths: Vec<MPath>,
+    ) -> Result<HashMap<MPath, PathContent>, ErrorKind>;
+
+    /// Fetch the sizes of many contents. By default, this calls
+    /// `get_file_size` for each id, with at most `FILE_CONTENTS_CONCURRENCY`
+    /// lookups in flight.
+    async fn get_file_sizes<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        ids: Vec<ContentId>,
+    ) -> Result<HashMap<ContentId, u64>, ErrorKind> {
+        stream::iter(ids)
+            .map(|id| async move { Ok::<_, ErrorKind>((id, self.get_file_size(ctx, id).await?)) })
+            .buffer_unordered(FILE_CONTENTS_CONCURRENCY)
+            .try_collect()
+            .await
+    }
+
+    /// Fetch the text of many contents. By default, this calls
+    /// `get_file_text` for each id, with at most `FILE_CONTENTS_CONCURRENCY`
+    /// lookups in flight.
+    async fn get_file_texts<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        ids: Vec<ContentId>,
+    ) -> Result<HashMap<ContentId, Option<Bytes>>, ErrorKind> {
+        stream::iter(ids)
+            .map(|id| async move { Ok::<_, ErrorKind>((id, self.get_file_text(ctx, id).await?)) })
+            .buffer_unordered(FILE_CONTENTS_CONCURRENCY)
+            .try_collect()
+            .await
+    }
+
+    /// Fetch the text of many files as of `changeset_id`. The paths are
+    /// resolved with a single `find_content_by_changeset_id` call, and the
+    /// texts fetched with `get_file_texts`. Every requested path is present in
+    /// the result: it maps to `None` if it is not a file in that changeset, or
+    /// if `get_file_text` elides its text.
+    async fn file_contents<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        changeset_id: ChangesetId,
+        paths: Vec<MPath>,
+    ) -> Result<HashMap<MPath, Option<Bytes>>, ErrorKind> {
+        let files = find_files(self, ctx, changeset_id, paths.clone()).await?;
+        let ids = files.values().copied().collect::<HashSet<_>>();
+        let texts = self.get_file_texts(ctx, ids.into_iter().collect()).await?;
+        Ok(paths
+            .into_iter()
+            .map(|path| {
+                let text = files
+                    .get(&path)
+                    .and_then(|id| texts.get(id).cloned().flatten());
+                (path, text)
+            })
+            .collect())
+    }
+
+    /// Like `file_contents`, for the sizes of the files.
+    async fn file_sizes<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        changeset_id: ChangesetId,
+        paths: Vec<MPath>,
+    ) -> Result<HashMap<MPath, Option<u64>>, ErrorKind> {
+        let files = find_files(self, ctx, changeset_id, paths.clone()).await?;
+        let ids = files.values().copied().collect::<HashSet<_>>();
+        let sizes = self.get_file_sizes(ctx, ids.into_iter().collect()).await?;
+        Ok(paths
+            .into_iter()
+            .map(|path| {
+                let size = files.get(&path).and_then(|id| sizes.get(id).copied());
+                (path, size)
+            })
+            .collect())
+    }
+
+    /// List all files under `dir` (recursively) as of `changeset_id`.
+    ///
+    /// Not every manager can enumerate directories, so by default this fails.
+    async fn list_dir<'a>(
+        &'a self,
+        _ctx: &'a CoreContext,
+        _changeset_id: ChangesetId,
+        _dir: MPath,
+    ) -> Result<Vec<MPath>, ErrorKind> {
+        Err(format_err!("`list_dir` is not implemented for this content manager").into())
+    }
+}
+
+/// The first `len` bytes of `bytes`.
+pub(crate) fn truncate(bytes: Bytes, len: u64) -> Bytes {
+    match usize::try_from(len) {
+        Ok(len) if len < bytes.len() => bytes.slice(..len),
+        _ => bytes,
+    }
+}
+
+/// The content ids of those of `paths` that are files in `changeset_id`.
+async fn find_files<M: FileContentManager + ?Sized>(
+    manager: &M,
+    ctx: &CoreContext,
+    changeset_id: ChangesetId,
+    paths: Vec<MPath>,
+) -> Result<HashMap<MPath, ContentId>, ErrorKind> {
+    let found = manager
+        .find_content_by_changeset_id(ctx, changeset_id, paths)
+        .await?;
+    Ok(found
+        .into_iter()
+        .filter_map(|(path, content)| match content {
+            PathContent::File(id) => Some((path, id)),
+            PathContent::Directory => None,
+        })
+        .collect())
 }
 
 #[derive(Clone, Debug)]
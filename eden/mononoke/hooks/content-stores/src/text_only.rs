@@ -72,6 +72,50 @@ impl<T: FileContentManager + 'static> FileContentManager for TextOnlyFileContent
         }))
     }
 
+    async fn get_file_prefix<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        id: ContentId,
+        len: u64,
+    ) -> Result<Option<Bytes>, ErrorKind> {
+        self.inner.get_file_prefix(ctx, id, len).await
+    }
+
+    async fn get_file_sizes<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        ids: Vec<ContentId>,
+    ) -> Result<HashMap<ContentId, u64>, ErrorKind> {
+        self.inner.get_file_sizes(ctx, ids).await
+    }
+
+    /// Like `get_file_text`, with one batch of size lookups and one batch
+    /// of text lookups for the files that are not too large.
+    async fn get_file_texts<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        ids: Vec<ContentId>,
+    ) -> Result<HashMap<ContentId, Option<Bytes>>, ErrorKind> {
+        let sizes = self.get_file_sizes(ctx, ids.clone()).await?;
+        let small_ids = ids
+            .iter()
+            .filter(|id| sizes.get(id).map_or(false, |size| *size <= self.max_size))
+            .copied()
+            .collect();
+        let mut texts = self.inner.get_file_texts(ctx, small_ids).await?;
+
+        Ok(ids
+            .into_iter()
+            .map(|id| {
+                let text = texts
+                    .remove(&id)
+                    .flatten()
+                    .filter(|bytes| !looks_like_binary(bytes));
+                (id, text)
+            })
+            .collect())
+    }
+
     async fn find_content<'a>(
         &'a self,
         ctx: &'a CoreContext,
@@ -98,6 +142,26 @@ impl<T: FileContentManager + 'static> FileContentManager for TextOnlyFileContent
     ) -> Result<HashMap<MPath, ChangesetInfo>, ErrorKind> {
         self.inner.latest_changes(ctx, bookmark, paths).await
     }
+
+    async fn find_content_by_changeset_id<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        changeset_id: ChangesetId,
+        paths: Vec<MPath>,
+    ) -> Result<HashMap<MPath, PathContent>, ErrorKind> {
+        self.inner
+            .find_content_by_changeset_id(ctx, changeset_id, paths)
+            .await
+    }
+
+    async fn list_dir<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        changeset_id: ChangesetId,
+        dir: MPath,
+    ) -> Result<Vec<MPath>, ErrorKind> {
+        self.inner.list_dir(ctx, changeset_id, dir).await
+    }
 }
 
 fn looks_like_binary(file_bytes: &[u8]) -> bool {
@@ -108,6 +172,8 @@ fn looks_like_binary(file_bytes: &[u8]) -> bool {
 mod test {
     use fbinit::FacebookInit;
     use mononoke_types_mocks::contentid::ONES_CTID;
+    use mononoke_types_mocks::contentid::THREES_CTID;
+    use mononoke_types_mocks::contentid::TWOS_CTID;
     use tokio::runtime::Runtime;
 
     use super::*;
@@ -144,6 +210,34 @@ mod test {
         assert_eq!(ret, 6);
     }
 
+    #[fbinit::test]
+    fn test_batched_texts(fb: FacebookInit) {
+        let rt = Runtime::new().unwrap();
+        let ctx = CoreContext::test_mock(fb);
+
+        let mut inner = InMemoryFileContentManager::new();
+        inner.insert(ONES_CTID, "foobar");
+        inner.insert(TWOS_CTID, "foobarbaz");
+        inner.insert(THREES_CTID, "foo\0");
+
+        let store = TextOnlyFileContentManager::new(inner, 6);
+        let ret = rt
+            .block_on(store.get_file_texts(&ctx, vec![ONES_CTID, TWOS_CTID, THREES_CTID]))
+            .unwrap();
+        assert_eq!(
+            ret,
+            HashMap::from([
+                (ONES_CTID, Some("foobar".into())),
+                (TWOS_CTID, None),
+                (THREES_CTID, None),
+            ])
+        );
+        let ret = rt
+            .block_on(store.get_file_sizes(&ctx, vec![ONES_CTID, TWOS_CTID]))
+            .unwrap();
+        assert_eq!(ret, HashMap::from([(ONES_CTID, 6), (TWOS_CTID, 9)]));
+    }
+
     #[fbinit::test]
     fn test_elide_binary_file(fb: FacebookInit) {
         let rt = Runtime::new().unwrap();
@@ -158,4 +252,26 @@ mod test {
         let ret = rt.block_on(store.get_file_size(&ctx, ONES_CTID)).unwrap();
         assert_eq!(ret, 4);
     }
+
+    #[fbinit::test]
+    fn test_prefix_of_binary_file(fb: FacebookInit) {
+        let rt = Runtime::new().unwrap();
+        let ctx = CoreContext::test_mock(fb);
+
+        let mut inner = InMemoryFileContentManager::new();
+        inner.insert(ONES_CTID, "foo\0bar");
+
+        // The text of the file is elided, but not its prefix.
+        let store = TextOnlyFileContentManager::new(inner, 2);
+        let ret = rt.block_on(store.get_file_text(&ctx, ONES_CTID)).unwrap();
+        assert_eq!(ret, None);
+        let ret = rt
+            .block_on(store.get_file_prefix(&ctx, ONES_CTID, 4))
+            .unwrap();
+        assert_eq!(ret, Some("foo\0".into()));
+        let ret = rt
+            .block_on(store.get_file_prefix(&ctx, ONES_CTID, 100))
+            .unwrap();
+        assert_eq!(ret, Some("foo\0bar".into()));
+    }
 }
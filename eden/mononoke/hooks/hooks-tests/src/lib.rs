@@ -8,29 +8,57 @@
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
 
+use anyhow::anyhow;
 use anyhow::Error;
 use async_trait::async_trait;
 use blobstore::Loadable;
 use bookmarks::BookmarkKey;
 use bookmarks::BookmarkUpdateReason;
 use bookmarks::BookmarksRef;
+use bytes::Bytes;
+use changeset_info::ChangesetInfo;
 use context::CoreContext;
+use context::PerfCounterType;
 use fbinit::FacebookInit;
 use fixtures::TestRepoFixture;
 use futures::future;
+use futures::stream;
 use futures::stream::futures_unordered;
 use futures::stream::TryStreamExt;
+use futures::StreamExt;
 use futures::TryFutureExt;
 use hooks::hook_loader::load_hooks;
+use hooks::testkit::HookTestKit;
+use hooks::testkit::ViolationKind;
+use hooks::BinaryHeuristic;
+use hooks::BookmarkHook;
+use hooks::BookmarkHookData;
+use hooks::BookmarkHookOutcome;
+use hooks::BookmarkOperationKind;
 use hooks::ChangesetHook;
+use hooks::ConfigProblem;
+use hooks::ContentInterest;
+use hooks::ContentPrefetch;
+use hooks::CopyFrom;
 use hooks::CrossRepoPushSource;
 use hooks::ErrorKind;
 use hooks::FileHook;
 use hooks::HookExecution;
+use hooks::HookExecutionScope;
 use hooks::HookManager;
+use hooks::HookOutcome;
 use hooks::HookRejectionInfo;
+use hooks::HookRunMode;
+use hooks::PreparedHookState;
 use hooks::PushAuthoredBy;
+use hooks::PusherGate;
+use hooks::PusherMatcher;
 use hooks_content_stores::FileChange as FileDiff;
 use hooks_content_stores::FileContentManager;
 use hooks_content_stores::InMemoryFileContentManager;
@@ -40,21 +68,34 @@ use maplit::btreemap;
 use maplit::hashmap;
 use maplit::hashset;
 use metaconfig_types::BookmarkParams;
+use metaconfig_types::HookBypass;
+use metaconfig_types::HookConfig;
+use metaconfig_types::HookKind;
 use metaconfig_types::HookManagerParams;
 use metaconfig_types::HookParams;
+use metaconfig_types::PartialHookConfig;
+use metaconfig_types::PusherGateParams;
+use metaconfig_types::PusherMatcherParams;
 use metaconfig_types::RepoConfig;
 use mononoke_types::BasicFileChange;
 use mononoke_types::BonsaiChangeset;
 use mononoke_types::BonsaiChangesetMut;
 use mononoke_types::ChangesetId;
+use mononoke_types::ContentId;
 use mononoke_types::DateTime;
 use mononoke_types::FileChange;
 use mononoke_types::FileType;
 use mononoke_types::MPath;
+use mononoke_types::MPathElement;
+use mononoke_types_mocks::changesetid::ONES_CSID;
+use mononoke_types_mocks::changesetid::TWOS_CSID;
 use mononoke_types_mocks::contentid::ONES_CTID;
 use mononoke_types_mocks::contentid::THREES_CTID;
 use mononoke_types_mocks::contentid::TWOS_CTID;
 use permission_checker::DefaultAclProvider;
+use permission_checker::MembershipChecker;
+use permission_checker::MononokeIdentity;
+use permission_checker::MononokeIdentitySet;
 use regex::Regex;
 use repo_blobstore::RepoBlobstoreRef;
 use scuba_ext::MononokeScubaSampleBuilder;
@@ -101,6 +142,24 @@ fn always_rejecting_changeset_hook() -> Box<dyn ChangesetHook> {
     Box::new(FnChangesetHook::new(f))
 }
 
+#[derive(Clone)]
+struct FailingChangesetHook;
+
+#[async_trait]
+impl ChangesetHook for FailingChangesetHook {
+    async fn run<'this: 'cs, 'ctx: 'this, 'cs, 'fetcher: 'cs>(
+        &'this self,
+        _ctx: &'ctx CoreContext,
+        _bookmark: &BookmarkKey,
+        _changeset: &'cs BonsaiChangeset,
+        _content_manager: &'fetcher dyn FileContentManager,
+        _cross_repo_push_source: CrossRepoPushSource,
+        _push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution, Error> {
+        Err(anyhow!("hook failed"))
+    }
+}
+
 #[derive(Clone)]
 struct FindFilesChangesetHook {
     pub filename: String,
@@ -186,6 +245,69 @@ impl ChangesetHook for FileChangesChangesetHook {
     }
 }
 
+/// Rejects changesets adding files under `proto/` that are not listed in the
+/// `BUILD` file of their directory, as of the changeset itself.
+#[derive(Clone)]
+struct ProtoBuildRegistrationChangesetHook;
+
+#[async_trait]
+impl ChangesetHook for ProtoBuildRegistrationChangesetHook {
+    async fn run<'this: 'cs, 'ctx: 'this, 'cs, 'fetcher: 'cs>(
+        &'this self,
+        ctx: &'ctx CoreContext,
+        _bookmark: &BookmarkKey,
+        changeset: &'cs BonsaiChangeset,
+        content_manager: &'fetcher dyn FileContentManager,
+        _cross_repo_push_source: CrossRepoPushSource,
+        _push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution, Error> {
+        let proto_dir = to_mpath("proto");
+        let build = build_element();
+        let new_protos: Vec<MPath> = changeset
+            .simplified_file_changes()
+            .filter(|(path, change)| {
+                change.is_some() && proto_dir.is_prefix_of(*path) && *path.basename() != build
+            })
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        let build_files: HashSet<MPath> = new_protos.iter().map(build_file_for).collect();
+        let build_contents = content_manager
+            .file_contents(
+                ctx,
+                changeset.get_changeset_id(),
+                build_files.into_iter().collect(),
+            )
+            .await?;
+
+        for path in new_protos {
+            let build_file = build_file_for(&path);
+            let registered = match build_contents.get(&build_file) {
+                Some(Some(build)) => {
+                    let name: &[u8] = path.basename().as_ref();
+                    build.split(|b| *b == b'\n').any(|line| line == name)
+                }
+                _ => false,
+            };
+            if !registered {
+                return Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                    "proto not registered in BUILD",
+                    format!("{} is not registered in {}", path, build_file),
+                )));
+            }
+        }
+        Ok(HookExecution::Accepted)
+    }
+}
+
+fn build_element() -> MPathElement {
+    MPathElement::new(b"BUILD".to_vec()).unwrap()
+}
+
+fn build_file_for(path: &MPath) -> MPath {
+    MPath::join_opt_element(path.split_dirname().0.as_ref(), &build_element())
+}
+
 #[derive(Clone, Debug)]
 struct FileContentMatchingChangesetHook {
     expected_content: HashMap<MPath, Option<String>>,
@@ -363,6 +485,7 @@ impl FileHook for FnFileHook {
         _content_manager: &'fetcher dyn FileContentManager,
         _change: Option<&'change BasicFileChange>,
         _path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
         _cross_repo_push_source: CrossRepoPushSource,
         _push_authored_by: PushAuthoredBy,
     ) -> Result<HookExecution, Error> {
@@ -380,6 +503,67 @@ fn always_rejecting_file_hook() -> Box<dyn FileHook> {
     Box::new(FnFileHook::new(f))
 }
 
+#[derive(Clone, Debug)]
+struct SleepingHook {
+    sleep: Duration,
+}
+
+#[async_trait]
+impl ChangesetHook for SleepingHook {
+    async fn run<'this: 'cs, 'ctx: 'this, 'cs, 'fetcher: 'cs>(
+        &'this self,
+        _ctx: &'ctx CoreContext,
+        _bookmark: &BookmarkKey,
+        _changeset: &'cs BonsaiChangeset,
+        _content_manager: &'fetcher dyn FileContentManager,
+        _cross_repo_push_source: CrossRepoPushSource,
+        _push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution, Error> {
+        tokio::time::sleep(self.sleep).await;
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[async_trait]
+impl FileHook for SleepingHook {
+    async fn run<'this: 'change, 'ctx: 'this, 'change, 'fetcher: 'change, 'path: 'change>(
+        &'this self,
+        _ctx: &'ctx CoreContext,
+        _content_manager: &'fetcher dyn FileContentManager,
+        _change: Option<&'change BasicFileChange>,
+        _path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
+        _cross_repo_push_source: CrossRepoPushSource,
+        _push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution, Error> {
+        tokio::time::sleep(self.sleep).await;
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct TextOnlyRejectingFileHook;
+
+#[async_trait]
+impl FileHook for TextOnlyRejectingFileHook {
+    fn content_interest(&self) -> ContentInterest {
+        ContentInterest::TextOnly
+    }
+
+    async fn run<'this: 'change, 'ctx: 'this, 'change, 'fetcher: 'change, 'path: 'change>(
+        &'this self,
+        _ctx: &'ctx CoreContext,
+        _content_manager: &'fetcher dyn FileContentManager,
+        _change: Option<&'change BasicFileChange>,
+        _path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
+        _cross_repo_push_source: CrossRepoPushSource,
+        _push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution, Error> {
+        Ok(default_rejection())
+    }
+}
+
 #[derive(Clone, Debug)]
 struct PathMatchingFileHook {
     paths: HashSet<MPath>,
@@ -393,6 +577,7 @@ impl FileHook for PathMatchingFileHook {
         _content_manager: &'fetcher dyn FileContentManager,
         _change: Option<&'change BasicFileChange>,
         path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
         _cross_repo_push_source: CrossRepoPushSource,
         _push_authored_by: PushAuthoredBy,
     ) -> Result<HookExecution, Error> {
@@ -408,6 +593,40 @@ fn path_matching_file_hook(paths: HashSet<MPath>) -> Box<dyn FileHook> {
     Box::new(PathMatchingFileHook { paths })
 }
 
+/// Rejects files moved out of `dir`. Copies out of it are fine.
+#[derive(Clone, Debug)]
+struct MovedOutOfDirFileHook {
+    dir: MPath,
+}
+
+#[async_trait]
+impl FileHook for MovedOutOfDirFileHook {
+    async fn run<'this: 'change, 'ctx: 'this, 'change, 'fetcher: 'change, 'path: 'change>(
+        &'this self,
+        _ctx: &'ctx CoreContext,
+        _content_manager: &'fetcher dyn FileContentManager,
+        _change: Option<&'change BasicFileChange>,
+        path: &'path MPath,
+        copy_from: Option<CopyFrom<'change>>,
+        _cross_repo_push_source: CrossRepoPushSource,
+        _push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution, Error> {
+        Ok(match copy_from {
+            Some(copy_from)
+                if copy_from.moved
+                    && self.dir.is_prefix_of(copy_from.path)
+                    && !self.dir.is_prefix_of(path) =>
+            {
+                HookExecution::Rejected(HookRejectionInfo::new_long(
+                    "file moved out of protected directory",
+                    format!("{} was moved from {}", path, copy_from.path),
+                ))
+            }
+            _ => HookExecution::Accepted,
+        })
+    }
+}
+
 #[derive(Clone, Debug)]
 struct FileContentMatchingFileHook {
     expected_content: Option<String>,
@@ -415,12 +634,17 @@ struct FileContentMatchingFileHook {
 
 #[async_trait]
 impl FileHook for FileContentMatchingFileHook {
+    fn content_prefetch(&self) -> ContentPrefetch {
+        ContentPrefetch::Text
+    }
+
     async fn run<'this: 'change, 'ctx: 'this, 'change, 'fetcher: 'change, 'path: 'change>(
         &'this self,
         ctx: &'ctx CoreContext,
         content_manager: &'fetcher dyn FileContentManager,
         change: Option<&'change BasicFileChange>,
         _path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
         _cross_repo_push_source: CrossRepoPushSource,
         _push_authored_by: PushAuthoredBy,
     ) -> Result<HookExecution, Error> {
@@ -465,6 +689,7 @@ impl FileHook for IsSymLinkMatchingFileHook {
         _content_manager: &'fetcher dyn FileContentManager,
         change: Option<&'change BasicFileChange>,
         _path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
         _cross_repo_push_source: CrossRepoPushSource,
         _push_authored_by: PushAuthoredBy,
     ) -> Result<HookExecution, Error> {
@@ -497,6 +722,7 @@ impl FileHook for LengthMatchingFileHook {
         content_manager: &'fetcher dyn FileContentManager,
         change: Option<&'change BasicFileChange>,
         _path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
         _cross_repo_push_source: CrossRepoPushSource,
         _push_authored_by: PushAuthoredBy,
     ) -> Result<HookExecution, Error> {
@@ -519,6 +745,173 @@ fn length_matching_file_hook(length: u64) -> Box<dyn FileHook> {
     Box::new(LengthMatchingFileHook { length })
 }
 
+/// Accepts files whose path matches the regex configured as `pattern`. The
+/// regex is compiled in `prepare`, which counts its invocations.
+struct PrepareCountingFileHook {
+    prepare_count: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl FileHook for PrepareCountingFileHook {
+    fn prepare(&self, config: &HookConfig) -> Result<PreparedHookState, Error> {
+        self.prepare_count.fetch_add(1, Ordering::SeqCst);
+        let pattern = config
+            .strings
+            .get("pattern")
+            .ok_or_else(|| anyhow!("missing pattern"))?;
+        Ok(Arc::new(Regex::new(pattern)?))
+    }
+
+    async fn run<'this: 'change, 'ctx: 'this, 'change, 'fetcher: 'change, 'path: 'change>(
+        &'this self,
+        _ctx: &'ctx CoreContext,
+        _content_manager: &'fetcher dyn FileContentManager,
+        _change: Option<&'change BasicFileChange>,
+        _path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
+        _cross_repo_push_source: CrossRepoPushSource,
+        _push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution, Error> {
+        Err(anyhow!("hook must be run with its prepared state"))
+    }
+
+    async fn run_prepared<
+        'this: 'change,
+        'ctx: 'this,
+        'change,
+        'fetcher: 'change,
+        'path: 'change,
+    >(
+        &'this self,
+        prepared: &'this PreparedHookState,
+        _ctx: &'ctx CoreContext,
+        _content_manager: &'fetcher dyn FileContentManager,
+        _change: Option<&'change BasicFileChange>,
+        path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
+        _cross_repo_push_source: CrossRepoPushSource,
+        _push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution, Error> {
+        let regex = prepared
+            .downcast_ref::<Regex>()
+            .ok_or_else(|| anyhow!("unexpected prepared state"))?;
+        Ok(if regex.is_match(&path.to_string()) {
+            HookExecution::Accepted
+        } else {
+            default_rejection()
+        })
+    }
+}
+
+fn prepare_counting_file_hook(prepare_count: &Arc<AtomicUsize>) -> Box<dyn FileHook> {
+    Box::new(PrepareCountingFileHook {
+        prepare_count: prepare_count.clone(),
+    })
+}
+
+/// Accepts every changeset or file, counting its executions.
+#[derive(Clone)]
+struct ExecutionCountingHook {
+    executions: Arc<AtomicUsize>,
+    bookmark_independent: bool,
+}
+
+impl ExecutionCountingHook {
+    fn new() -> (Self, Arc<AtomicUsize>) {
+        let executions = Arc::new(AtomicUsize::new(0));
+        let hook = ExecutionCountingHook {
+            executions: executions.clone(),
+            bookmark_independent: true,
+        };
+        (hook, executions)
+    }
+
+    fn bookmark_dependent() -> (Self, Arc<AtomicUsize>) {
+        let (hook, executions) = Self::new();
+        let hook = ExecutionCountingHook {
+            bookmark_independent: false,
+            ..hook
+        };
+        (hook, executions)
+    }
+}
+
+#[async_trait]
+impl ChangesetHook for ExecutionCountingHook {
+    fn bookmark_independent(&self) -> bool {
+        self.bookmark_independent
+    }
+
+    async fn run<'this: 'cs, 'ctx: 'this, 'cs, 'fetcher: 'cs>(
+        &'this self,
+        _ctx: &'ctx CoreContext,
+        _bookmark: &BookmarkKey,
+        _changeset: &'cs BonsaiChangeset,
+        _content_manager: &'fetcher dyn FileContentManager,
+        _cross_repo_push_source: CrossRepoPushSource,
+        _push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution, Error> {
+        self.executions.fetch_add(1, Ordering::SeqCst);
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[async_trait]
+impl FileHook for ExecutionCountingHook {
+    async fn run<'this: 'change, 'ctx: 'this, 'change, 'fetcher: 'change, 'path: 'change>(
+        &'this self,
+        _ctx: &'ctx CoreContext,
+        _content_manager: &'fetcher dyn FileContentManager,
+        _change: Option<&'change BasicFileChange>,
+        _path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
+        _cross_repo_push_source: CrossRepoPushSource,
+        _push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution, Error> {
+        self.executions.fetch_add(1, Ordering::SeqCst);
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct FnBookmarkHook {
+    f: fn(&BookmarkHookData) -> HookExecution,
+}
+
+#[async_trait]
+impl BookmarkHook for FnBookmarkHook {
+    async fn run(
+        &self,
+        _ctx: &CoreContext,
+        data: &BookmarkHookData,
+    ) -> Result<HookExecution, Error> {
+        Ok((self.f)(data))
+    }
+}
+
+fn always_rejecting_bookmark_hook() -> Box<dyn BookmarkHook> {
+    Box::new(FnBookmarkHook {
+        f: |_| default_rejection(),
+    })
+}
+
+fn bookmark_hook_data(bookmark: &str, operation: BookmarkOperationKind) -> BookmarkHookData {
+    BookmarkHookData {
+        bookmark: BookmarkKey::new(bookmark).unwrap(),
+        operation,
+        pusher: MononokeIdentitySet::new(),
+    }
+}
+
+fn pattern_config(pattern: &str) -> HookConfig {
+    HookConfig {
+        strings: hashmap! {
+            "pattern".to_string() => pattern.to_string(),
+        },
+        ..Default::default()
+    }
+}
+
 #[fbinit::test]
 async fn test_changeset_hook_accepted(fb: FacebookInit) {
     let ctx = CoreContext::test_mock(fb);
@@ -1232,65 +1625,1708 @@ async fn test_file_hooks_with_blob_store(fb: FacebookInit) {
     .await;
 }
 
-async fn run_changeset_hooks(
-    ctx: CoreContext,
-    bookmark_name: &str,
-    hooks: HashMap<String, Box<dyn ChangesetHook>>,
-    bookmarks: HashMap<String, Vec<String>>,
-    regexes: HashMap<String, Vec<String>>,
-    expected: HashMap<String, HookExecution>,
-) {
-    run_changeset_hooks_with_mgr(
-        ctx,
-        None,
-        bookmark_name,
-        hooks,
-        bookmarks,
-        regexes,
-        expected,
-        ContentFetcherType::InMemory,
-    )
-    .await
-}
-
-async fn run_changeset_hooks_with_mgr(
-    ctx: CoreContext,
-    changeset: Option<BonsaiChangeset>,
-    bookmark_name: &str,
-    hooks: HashMap<String, Box<dyn ChangesetHook>>,
-    bookmarks: HashMap<String, Vec<String>>,
-    regexes: HashMap<String, Vec<String>>,
-    expected: HashMap<String, HookExecution>,
-    content_manager_type: ContentFetcherType,
-) {
-    let mut hook_manager =
-        setup_hook_manager(ctx.fb, bookmarks, regexes, content_manager_type).await;
-    for (hook_name, hook) in hooks {
-        hook_manager.register_changeset_hook(&hook_name, hook, Default::default());
+#[fbinit::test]
+async fn test_changeset_hook_reads_other_files_in_batch(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let registered = changeset_with_files(&[("proto/a.proto", TWOS_CTID)]);
+    let unregistered = changeset_with_files(&[
+        ("proto/a.proto", TWOS_CTID),
+        ("proto/sub/b.proto", TWOS_CTID),
+    ]);
+
+    // proto/BUILD isn't touched by either changeset, so the hook has to read
+    // it from the changeset's snapshot. There is no proto/sub/BUILD at all.
+    let mut content_manager = InMemoryFileContentManager::new();
+    content_manager.insert(ONES_CTID, "a.proto\n");
+    content_manager.insert(TWOS_CTID, "syntax = \"proto3\";");
+    for cs in [&registered, &unregistered] {
+        let cs_id = cs.get_changeset_id();
+        content_manager.insert_file_at(cs_id, to_mpath("proto/BUILD"), ONES_CTID);
+        for (path, change) in cs.simplified_file_changes() {
+            let change = change.expect("no deletions in this test");
+            content_manager.insert_file_at(cs_id, path.clone(), change.content_id());
+        }
     }
 
-    let changeset = changeset.unwrap_or_else(default_changeset);
-    let res = hook_manager
+    let mut hook_manager = HookManager::new_test("zoo".to_string(), Box::new(content_manager));
+    hook_manager
+        .register_changeset_hook(
+            "proto_build",
+            Box::new(ProtoBuildRegistrationChangesetHook),
+            Default::default(),
+        )
+        .unwrap();
+    hook_manager.set_hooks_for_bookmark(
+        BookmarkKey::new("master").unwrap().into(),
+        vec!["proto_build".to_string()],
+    );
+
+    let outcomes = hook_manager
         .run_hooks_for_bookmark(
             &ctx,
-            vec![changeset].iter(),
-            &BookmarkKey::new(bookmark_name).unwrap(),
+            vec![registered.clone(), unregistered.clone()].iter(),
+            &BookmarkKey::new("master").unwrap(),
             None,
+            ctx.metadata().identities(),
             CrossRepoPushSource::NativeToThisRepo,
             PushAuthoredBy::User,
         )
         .await
         .unwrap();
-    let map: HashMap<String, HookExecution> = res
+    let results: HashMap<ChangesetId, HookExecution> = outcomes
         .into_iter()
-        .map(|outcome| (outcome.get_hook_name().to_string(), outcome.into()))
+        .map(|outcome| (outcome.get_changeset_id(), outcome.into()))
         .collect();
-    assert_eq!(expected, map);
+    assert_eq!(
+        results,
+        hashmap! {
+            registered.get_changeset_id() => HookExecution::Accepted,
+            unregistered.get_changeset_id() => HookExecution::Rejected(
+                HookRejectionInfo::new_long(
+                    "proto not registered in BUILD",
+                    "proto/sub/b.proto is not registered in proto/sub/BUILD".to_string(),
+                ),
+            ),
+        }
+    );
 }
 
-enum ContentFetcherType {
-    InMemory,
-    Blob(BasicTestRepo),
+/// Counts the calls to the lookups of an `InMemoryFileContentManager`.
+struct CountingFileContentManager {
+    inner: InMemoryFileContentManager,
+    calls: Arc<Mutex<HashMap<&'static str, usize>>>,
+}
+
+impl CountingFileContentManager {
+    fn count(&self, method: &'static str) {
+        *self.calls.lock().unwrap().entry(method).or_default() += 1;
+    }
+}
+
+#[async_trait]
+impl FileContentManager for CountingFileContentManager {
+    async fn get_file_size<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        id: ContentId,
+    ) -> Result<u64, hooks_content_stores::ErrorKind> {
+        self.count("get_file_size");
+        self.inner.get_file_size(ctx, id).await
+    }
+
+    async fn get_file_text<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        id: ContentId,
+    ) -> Result<Option<Bytes>, hooks_content_stores::ErrorKind> {
+        self.count("get_file_text");
+        self.inner.get_file_text(ctx, id).await
+    }
+
+    async fn get_file_sizes<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        ids: Vec<ContentId>,
+    ) -> Result<HashMap<ContentId, u64>, hooks_content_stores::ErrorKind> {
+        self.count("get_file_sizes");
+        self.inner.get_file_sizes(ctx, ids).await
+    }
+
+    async fn get_file_texts<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        ids: Vec<ContentId>,
+    ) -> Result<HashMap<ContentId, Option<Bytes>>, hooks_content_stores::ErrorKind> {
+        self.count("get_file_texts");
+        self.inner.get_file_texts(ctx, ids).await
+    }
+
+    async fn find_content<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        bookmark: BookmarkKey,
+        paths: Vec<MPath>,
+    ) -> Result<HashMap<MPath, PathContent>, hooks_content_stores::ErrorKind> {
+        self.inner.find_content(ctx, bookmark, paths).await
+    }
+
+    async fn file_changes<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        new_cs_id: ChangesetId,
+        old_cs_id: ChangesetId,
+    ) -> Result<Vec<(MPath, FileDiff)>, hooks_content_stores::ErrorKind> {
+        self.inner.file_changes(ctx, new_cs_id, old_cs_id).await
+    }
+
+    async fn latest_changes<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        bookmark: BookmarkKey,
+        paths: Vec<MPath>,
+    ) -> Result<HashMap<MPath, ChangesetInfo>, hooks_content_stores::ErrorKind> {
+        self.inner.latest_changes(ctx, bookmark, paths).await
+    }
+
+    async fn find_content_by_changeset_id<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        changeset_id: ChangesetId,
+        paths: Vec<MPath>,
+    ) -> Result<HashMap<MPath, PathContent>, hooks_content_stores::ErrorKind> {
+        self.count("find_content_by_changeset_id");
+        self.inner
+            .find_content_by_changeset_id(ctx, changeset_id, paths)
+            .await
+    }
+}
+
+#[fbinit::test]
+async fn test_file_contents_resolves_paths_once(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let cs_id = default_changeset().get_changeset_id();
+    let mut inner = InMemoryFileContentManager::new();
+    inner.insert(ONES_CTID, "elephants");
+    inner.insert(TWOS_CTID, "hippopatami");
+    inner.insert_file_at(cs_id, to_mpath("a"), ONES_CTID);
+    inner.insert_file_at(cs_id, to_mpath("b"), TWOS_CTID);
+    inner.insert_file_at(cs_id, to_mpath("c"), ONES_CTID);
+    let calls = Arc::new(Mutex::new(HashMap::new()));
+    let content_manager = CountingFileContentManager {
+        inner,
+        calls: calls.clone(),
+    };
+
+    let paths = vec![to_mpath("a"), to_mpath("b"), to_mpath("c"), to_mpath("d")];
+    let contents = content_manager
+        .file_contents(&ctx, cs_id, paths.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        contents[&to_mpath("b")],
+        Some(Bytes::from_static(b"hippopatami"))
+    );
+    assert_eq!(contents[&to_mpath("d")], None);
+    let sizes = content_manager
+        .file_sizes(&ctx, cs_id, paths)
+        .await
+        .unwrap();
+    assert_eq!(
+        sizes,
+        hashmap! {
+            to_mpath("a") => Some(9),
+            to_mpath("b") => Some(11),
+            to_mpath("c") => Some(9),
+            to_mpath("d") => None,
+        }
+    );
+    assert_eq!(
+        *calls.lock().unwrap(),
+        hashmap! {
+            "find_content_by_changeset_id" => 2,
+            "get_file_texts" => 1,
+            "get_file_sizes" => 1,
+        }
+    );
+}
+
+#[fbinit::test]
+async fn test_file_hooks_prefetch_content(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let mut inner = InMemoryFileContentManager::new();
+    inner.insert(ONES_CTID, "elephants");
+    inner.insert(TWOS_CTID, "hippopatami");
+    inner.insert(THREES_CTID, "eels");
+    let calls = Arc::new(Mutex::new(HashMap::new()));
+    let mut hook_manager = HookManager::new(
+        ctx.fb,
+        DefaultAclProvider::new(fb).as_ref(),
+        Box::new(CountingFileContentManager {
+            inner,
+            calls: calls.clone(),
+        }),
+        HookManagerParams {
+            disable_acl_checker: true,
+            ..Default::default()
+        },
+        MononokeScubaSampleBuilder::with_discard(),
+        "zoo".to_string(),
+    )
+    .await
+    .unwrap();
+    hook_manager
+        .register_file_hook(
+            "hook1",
+            file_text_matching_file_hook(Some("ele".to_string())),
+            Default::default(),
+        )
+        .unwrap();
+    hook_manager
+        .register_file_hook(
+            "hook2",
+            file_text_matching_file_hook(Some("e".to_string())),
+            Default::default(),
+        )
+        .unwrap();
+    hook_manager.set_hooks_for_bookmark(
+        BookmarkKey::new("bm1").unwrap().into(),
+        vec!["hook1".to_string(), "hook2".to_string()],
+    );
+
+    let changesets = vec![
+        changeset_with_files(&[("a", ONES_CTID), ("b", TWOS_CTID), ("c", THREES_CTID)]),
+        changeset_with_files(&[("a", THREES_CTID), ("b", THREES_CTID)]),
+    ];
+    let outcomes = hook_manager
+        .run_hooks_for_bookmark(
+            &ctx,
+            changesets.iter(),
+            &BookmarkKey::new("bm1").unwrap(),
+            None,
+            ctx.metadata().identities(),
+            CrossRepoPushSource::NativeToThisRepo,
+            PushAuthoredBy::User,
+        )
+        .await
+        .unwrap();
+    let accepted: Vec<_> = outcomes
+        .iter()
+        .map(|outcome| {
+            (
+                outcome.get_hook_name().to_string(),
+                outcome.get_file_path().unwrap().to_string(),
+                outcome.is_accept(),
+            )
+        })
+        .collect();
+    let expected =
+        |hook: &str, path: &str, accepted| (hook.to_string(), path.to_string(), accepted);
+    assert_eq!(
+        accepted,
+        vec![
+            expected("hook1", "a", true),
+            expected("hook2", "a", true),
+            expected("hook1", "b", false),
+            expected("hook2", "b", false),
+            expected("hook1", "c", false),
+            expected("hook2", "c", true),
+            expected("hook1", "a", false),
+            expected("hook2", "a", true),
+            expected("hook1", "b", false),
+            expected("hook2", "b", true),
+        ]
+    );
+
+    // One batch per changeset, instead of one lookup per file and hook.
+    assert_eq!(
+        *calls.lock().unwrap(),
+        hashmap! {
+            "get_file_texts" => 2,
+            "get_file_sizes" => 2,
+        }
+    );
+}
+
+/// An ACL group with fixed members, counting membership checks.
+struct FakeGroupChecker {
+    members: MononokeIdentitySet,
+    checks: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl MembershipChecker for FakeGroupChecker {
+    async fn is_member(&self, identities: &MononokeIdentitySet) -> bool {
+        self.checks.fetch_add(1, Ordering::SeqCst);
+        !self.members.is_disjoint(identities)
+    }
+}
+
+fn user_identities(user: &str) -> MononokeIdentitySet {
+    [MononokeIdentity::new("USER", user)].into_iter().collect()
+}
+
+/// A hook manager with a changeset hook counting its executions and a file
+/// hook reading file contents bound to "bm1", which `gate` is bound to.
+async fn gated_hook_manager(
+    fb: FacebookInit,
+    gate: PusherGate,
+) -> (
+    HookManager,
+    Arc<Mutex<HashMap<&'static str, usize>>>,
+    Arc<AtomicUsize>,
+) {
+    let mut inner = InMemoryFileContentManager::new();
+    inner.insert(ONES_CTID, "elephants");
+    let calls = Arc::new(Mutex::new(HashMap::new()));
+    let mut hook_manager = HookManager::new_test(
+        "zoo".to_string(),
+        Box::new(CountingFileContentManager {
+            inner,
+            calls: calls.clone(),
+        }),
+    );
+    let (counting, executions) = ExecutionCountingHook::new();
+    hook_manager
+        .register_changeset_hook("counting", Box::new(counting), Default::default())
+        .unwrap();
+    hook_manager
+        .register_file_hook(
+            "text",
+            file_text_matching_file_hook(Some("ele".to_string())),
+            Default::default(),
+        )
+        .unwrap();
+    hook_manager.set_hooks_for_bookmark(
+        BookmarkKey::new("bm1").unwrap().into(),
+        vec!["counting".to_string(), "text".to_string()],
+    );
+    hook_manager.set_pusher_gate_for_bookmark(BookmarkKey::new("bm1").unwrap().into(), gate);
+    (hook_manager, calls, executions)
+}
+
+#[fbinit::test]
+async fn test_pusher_gate_denies_before_running_hooks(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let gate = PusherGate {
+        name: "releasers".to_string(),
+        allowed_pushers: Some(vec![PusherMatcher::username_glob("ali?e*").unwrap()]),
+        denied_pushers: vec![PusherMatcher::Identity("USER:alice_bot".parse().unwrap())],
+        bypass: Some(HookBypass::new_with_pushvar(
+            "BYPASS_GATE".into(),
+            "true".into(),
+        )),
+    };
+    let (hook_manager, calls, executions) = gated_hook_manager(fb, gate).await;
+    let bookmark = BookmarkKey::new("bm1").unwrap();
+    assert_eq!(
+        hook_manager
+            .pusher_gates_for_bookmark(&bookmark)
+            .iter()
+            .map(|gate| gate.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["releasers"]
+    );
+
+    let changesets = vec![
+        changeset_with_files(&[("a", ONES_CTID)]),
+        changeset_with_files(&[("b", ONES_CTID)]),
+    ];
+    let run_hooks = |user: &str, pushvars: Option<HashMap<String, Bytes>>| {
+        let pusher = user_identities(user);
+        let hook_manager = &hook_manager;
+        let ctx = &ctx;
+        let changesets = &changesets;
+        let bookmark = &bookmark;
+        async move {
+            hook_manager
+                .run_hooks_for_bookmark(
+                    ctx,
+                    changesets.iter(),
+                    bookmark,
+                    pushvars.as_ref(),
+                    &pusher,
+                    CrossRepoPushSource::NativeToThisRepo,
+                    PushAuthoredBy::User,
+                )
+                .await
+                .unwrap()
+        }
+    };
+
+    // Denied pushers get a rejection per changeset, attributed to the gate,
+    // without hooks running or file contents being looked up.
+    for user in ["alice_bot", "bob"] {
+        let outcomes = run_hooks(user, None).await;
+        assert_eq!(outcomes.len(), 2);
+        for (outcome, cs) in outcomes.iter().zip(&changesets) {
+            assert!(outcome.is_rejection());
+            assert_eq!(outcome.get_hook_name(), "pusher_gate:releasers");
+            assert_eq!(outcome.get_changeset_id(), cs.get_changeset_id());
+        }
+    }
+    match run_hooks("bob", None).await[0].get_execution() {
+        HookExecution::Rejected(info) => assert_eq!(
+            info.long_description,
+            "Pushing to this bookmark is only allowed by policy releasers to: user ali?e*"
+        ),
+        HookExecution::Accepted => panic!("bob should be rejected"),
+    }
+    assert_eq!(executions.load(Ordering::SeqCst), 0);
+    assert!(calls.lock().unwrap().is_empty());
+
+    // The gate's pushvar bypasses it.
+    let pushvars = hashmap! {"BYPASS_GATE".to_string() => Bytes::from_static(b"true")};
+    let outcomes = run_hooks("alice_bot", Some(pushvars)).await;
+    assert!(outcomes.iter().all(|outcome| outcome.is_accept()));
+    assert_eq!(executions.load(Ordering::SeqCst), 2);
+}
+
+#[fbinit::test]
+async fn test_pusher_gate_denies_without_changesets(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let gate = PusherGate {
+        name: "releasers".to_string(),
+        allowed_pushers: Some(vec![PusherMatcher::username_glob("alice").unwrap()]),
+        denied_pushers: vec![],
+        bypass: None,
+    };
+    let (hook_manager, _calls, executions) = gated_hook_manager(fb, gate).await;
+    let bookmark = BookmarkKey::new("bm1").unwrap();
+    let run_hooks = |user: &str| {
+        let pusher = user_identities(user);
+        let hook_manager = &hook_manager;
+        let ctx = &ctx;
+        let bookmark = &bookmark;
+        async move {
+            hook_manager
+                .run_hooks_for_bookmark(
+                    ctx,
+                    std::iter::empty(),
+                    bookmark,
+                    None,
+                    &pusher,
+                    CrossRepoPushSource::NativeToThisRepo,
+                    PushAuthoredBy::User,
+                )
+                .await
+        }
+    };
+
+    // Moving the bookmark to existing changesets brings no new changeset to
+    // reject, so the denial is an error.
+    let err = run_hooks("bob").await.unwrap_err();
+    match err.downcast_ref::<ErrorKind>() {
+        Some(ErrorKind::PusherDenied(hook_name, denied_bookmark, _)) => {
+            assert_eq!(hook_name, "pusher_gate:releasers");
+            assert_eq!(denied_bookmark, &bookmark);
+        }
+        _ => panic!("unexpected error: {:#}", err),
+    }
+    assert!(run_hooks("alice").await.unwrap().is_empty());
+    assert_eq!(executions.load(Ordering::SeqCst), 0);
+}
+
+#[fbinit::test]
+async fn test_pusher_gate_allows_pusher(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let gate = PusherGate {
+        name: "releasers".to_string(),
+        allowed_pushers: Some(vec![PusherMatcher::username_glob("ali?e*").unwrap()]),
+        ..Default::default()
+    };
+    let (hook_manager, calls, executions) = gated_hook_manager(fb, gate).await;
+
+    let changesets = vec![changeset_with_files(&[("a", ONES_CTID)])];
+    let outcomes = hook_manager
+        .run_hooks_for_bookmark(
+            &ctx,
+            changesets.iter(),
+            &BookmarkKey::new("bm1").unwrap(),
+            None,
+            &user_identities("alice"),
+            CrossRepoPushSource::NativeToThisRepo,
+            PushAuthoredBy::User,
+        )
+        .await
+        .unwrap();
+    let hook_names: Vec<_> = outcomes
+        .iter()
+        .map(|outcome| (outcome.get_hook_name(), outcome.is_accept()))
+        .collect();
+    assert_eq!(hook_names, vec![("counting", true), ("text", true)]);
+    assert_eq!(executions.load(Ordering::SeqCst), 1);
+    assert!(!calls.lock().unwrap().is_empty());
+}
+
+#[fbinit::test]
+async fn test_pusher_gate_acl_group(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let checks = Arc::new(AtomicUsize::new(0));
+    let gate = PusherGate {
+        name: "release_team".to_string(),
+        allowed_pushers: Some(vec![PusherMatcher::Group {
+            name: "releasers".to_string(),
+            checker: Arc::new(FakeGroupChecker {
+                members: user_identities("carol"),
+                checks: checks.clone(),
+            }),
+        }]),
+        ..Default::default()
+    };
+    let (mut hook_manager, calls, executions) = gated_hook_manager(fb, gate.clone()).await;
+    hook_manager
+        .register_bookmark_hook(
+            "block_bookmark_deletion",
+            always_rejecting_bookmark_hook(),
+            Default::default(),
+        )
+        .unwrap();
+    hook_manager.set_hooks_for_bookmark(
+        Regex::new("^release/").unwrap().into(),
+        vec!["block_bookmark_deletion".to_string()],
+    );
+    hook_manager.set_pusher_gate_for_bookmark(Regex::new("^release/").unwrap().into(), gate);
+
+    let changesets = vec![changeset_with_files(&[("a", ONES_CTID)])];
+    let run_hooks = |user: &str| {
+        let pusher = user_identities(user);
+        let hook_manager = &hook_manager;
+        let ctx = &ctx;
+        let changesets = &changesets;
+        async move {
+            hook_manager
+                .run_hooks_for_bookmark(
+                    ctx,
+                    changesets.iter(),
+                    &BookmarkKey::new("bm1").unwrap(),
+                    None,
+                    &pusher,
+                    CrossRepoPushSource::NativeToThisRepo,
+                    PushAuthoredBy::User,
+                )
+                .await
+                .unwrap()
+        }
+    };
+    let outcomes = run_hooks("carol").await;
+    assert!(outcomes.iter().all(|outcome| outcome.is_accept()));
+    assert_eq!(executions.load(Ordering::SeqCst), 1);
+
+    let outcomes = run_hooks("dave").await;
+    assert_eq!(outcomes.len(), 1);
+    assert_eq!(outcomes[0].get_hook_name(), "pusher_gate:release_team");
+    assert_eq!(executions.load(Ordering::SeqCst), 1);
+    assert_eq!(checks.load(Ordering::SeqCst), 2);
+
+    // Gates bound to regexes apply to bookmark hooks too, with the pusher
+    // of the bookmark operation.
+    let mut data = bookmark_hook_data(
+        "release/1.0",
+        BookmarkOperationKind::Delete { from: ONES_CSID },
+    );
+    data.pusher = user_identities("dave");
+    let outcomes = hook_manager
+        .run_bookmark_hooks(&ctx, &data, None)
+        .await
+        .unwrap();
+    assert_eq!(outcomes.len(), 1);
+    assert!(outcomes[0].is_rejection());
+    assert_eq!(outcomes[0].get_hook_name(), "pusher_gate:release_team");
+    data.pusher = user_identities("carol");
+    let outcomes = hook_manager
+        .run_bookmark_hooks(&ctx, &data, None)
+        .await
+        .unwrap();
+    assert_eq!(outcomes.len(), 1);
+    assert_eq!(outcomes[0].get_hook_name(), "block_bookmark_deletion");
+    assert_eq!(calls.lock().unwrap().get("get_file_texts"), Some(&1));
+}
+
+#[fbinit::test]
+async fn test_text_only_file_hooks_skip_binary_files(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let mut content_manager = InMemoryFileContentManager::new();
+    content_manager.insert(ONES_CTID, "elephants\0");
+    content_manager.insert(TWOS_CTID, "hippopatami");
+    let mut hook_manager = HookManager::new_test("zoo".to_string(), Box::new(content_manager));
+    hook_manager
+        .register_file_hook(
+            "text_only",
+            Box::new(TextOnlyRejectingFileHook),
+            Default::default(),
+        )
+        .unwrap();
+    hook_manager
+        .register_file_hook("any", always_rejecting_file_hook(), Default::default())
+        .unwrap();
+    hook_manager.set_hooks_for_bookmark(
+        BookmarkKey::new("bm1").unwrap().into(),
+        vec!["text_only".to_string(), "any".to_string()],
+    );
+
+    let changesets = vec![
+        changeset_with_files(&[("a", ONES_CTID)]),
+        changeset_with_files(&[("a", TWOS_CTID)]),
+    ];
+    let binary_cs_id = changesets[0].get_changeset_id();
+    let text_cs_id = changesets[1].get_changeset_id();
+    let expected = |hook: &str, cs_id, accepted| (hook.to_string(), cs_id, accepted);
+
+    // The same path is skipped by the text-only hook when it is binary, but
+    // not when it is text. Other hooks run on both.
+    assert_eq!(
+        hook_outcomes(&ctx, &hook_manager, &changesets).await,
+        hashset! {
+            expected("text_only", binary_cs_id, true),
+            expected("text_only", text_cs_id, false),
+            expected("any", binary_cs_id, false),
+            expected("any", text_cs_id, false),
+        }
+    );
+
+    hook_manager.set_binary_heuristic(BinaryHeuristic::Disabled);
+    assert_eq!(
+        hook_outcomes(&ctx, &hook_manager, &changesets).await,
+        hashset! {
+            expected("text_only", binary_cs_id, false),
+            expected("text_only", text_cs_id, false),
+            expected("any", binary_cs_id, false),
+            expected("any", text_cs_id, false),
+        }
+    );
+}
+
+#[fbinit::test]
+async fn test_run_hooks_for_changesets(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let mut content_manager = InMemoryFileContentManager::new();
+    content_manager.insert(ONES_CTID, "elephants");
+    content_manager.insert(TWOS_CTID, "hippopatami");
+    let mut hook_manager = HookManager::new_test("zoo".to_string(), Box::new(content_manager));
+    hook_manager
+        .register_file_hook(
+            "elephants_only",
+            file_text_matching_file_hook(Some("elephants".to_string())),
+            HookConfig {
+                bypass: Some(HookBypass::new_with_commit_msg("@allow".into())),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    hook_manager
+        .register_changeset_hook(
+            "accepting",
+            always_accepting_changeset_hook(),
+            Default::default(),
+        )
+        .unwrap();
+    hook_manager
+        .register_changeset_hook(
+            "failing",
+            Box::new(FailingChangesetHook),
+            Default::default(),
+        )
+        .unwrap();
+    hook_manager
+        .register_bookmark_hook(
+            "bookmark",
+            always_rejecting_bookmark_hook(),
+            Default::default(),
+        )
+        .unwrap();
+
+    // The violating changeset has the bypass string, which audits ignore.
+    let mut violating = changeset_with_files(&[("b", TWOS_CTID)]).into_mut();
+    violating.message = "@allow".to_string();
+    let changesets = vec![
+        changeset_with_files(&[("a", ONES_CTID)]),
+        violating.freeze().unwrap(),
+        changeset_with_files(&[("c", ONES_CTID)]),
+    ];
+    let cs_ids: Vec<_> = changesets.iter().map(|cs| cs.get_changeset_id()).collect();
+    let bookmark = BookmarkKey::new("main").unwrap();
+    let audit = |hook_names: &[&str]| {
+        let hook_names: Vec<_> = hook_names.iter().map(|name| name.to_string()).collect();
+        hook_manager.run_hooks_for_changesets(
+            &ctx,
+            stream::iter(changesets.clone()),
+            &bookmark,
+            &hook_names,
+            2,
+        )
+    };
+
+    // Hooks run without being bound to any bookmark.
+    let verdicts: Vec<_> = audit(&["elephants_only", "accepting"])
+        .unwrap()
+        .map(|(cs_id, outcomes)| {
+            let outcomes = outcomes
+                .unwrap()
+                .iter()
+                .map(|outcome| (outcome.get_hook_name().to_string(), outcome.is_accept()))
+                .collect::<Vec<_>>();
+            (cs_id, outcomes)
+        })
+        .collect()
+        .await;
+    let expected = |elephants_only| {
+        vec![
+            ("accepting".to_string(), true),
+            ("elephants_only".to_string(), elephants_only),
+        ]
+    };
+    assert_eq!(
+        verdicts,
+        vec![
+            (cs_ids[0], expected(true)),
+            (cs_ids[1], expected(false)),
+            (cs_ids[2], expected(true)),
+        ]
+    );
+
+    // Failures are reported for each changeset.
+    let failures: Vec<_> = audit(&["failing"])
+        .unwrap()
+        .map(|(cs_id, outcomes)| (cs_id, format!("{:#}", outcomes.unwrap_err())))
+        .collect()
+        .await;
+    assert_eq!(
+        failures,
+        cs_ids
+            .iter()
+            .map(|cs_id| (
+                *cs_id,
+                format!(
+                    "while auditing changeset {}: while executing hook failing: hook failed",
+                    cs_id
+                )
+            ))
+            .collect::<Vec<_>>()
+    );
+
+    for (hook_name, expected) in [
+        ("missing", "No such hook 'missing'"),
+        (
+            "bookmark",
+            "Hook 'bookmark' is a bookmark hook, which does not run against changesets",
+        ),
+    ] {
+        match audit(&[hook_name]) {
+            Ok(_) => panic!("auditing with {} should fail", hook_name),
+            Err(e) => assert_eq!(e.to_string(), expected),
+        }
+    }
+}
+
+#[fbinit::test]
+async fn test_in_memory_file_contents_and_list_dir(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let cs_id = default_changeset().get_changeset_id();
+    let mut content_manager = InMemoryFileContentManager::new();
+    content_manager.insert(ONES_CTID, "elephants");
+    content_manager.insert_file_at(cs_id, to_mpath("dir/a"), ONES_CTID);
+    content_manager.insert_file_at(cs_id, to_mpath("dir/sub/b"), ONES_CTID);
+    content_manager.insert_file_at(cs_id, to_mpath("other"), ONES_CTID);
+
+    let contents = content_manager
+        .file_contents(
+            &ctx,
+            cs_id,
+            vec![to_mpath("dir/a"), to_mpath("dir"), to_mpath("missing")],
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        contents,
+        hashmap! {
+            to_mpath("dir/a") => Some(Bytes::from_static(b"elephants")),
+            to_mpath("dir") => None,
+            to_mpath("missing") => None,
+        }
+    );
+
+    let mut listed = content_manager
+        .list_dir(&ctx, cs_id, to_mpath("dir"))
+        .await
+        .unwrap();
+    listed.sort();
+    assert_eq!(listed, vec![to_mpath("dir/a"), to_mpath("dir/sub/b")]);
+}
+
+#[fbinit::test]
+async fn test_file_hook_prepared_once_per_registration(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let prepare_count = Arc::new(AtomicUsize::new(0));
+    let mut hook_manager = hook_manager_inmem(fb).await;
+    hook_manager.set_hooks_for_bookmark(
+        BookmarkKey::new("bm1").unwrap().into(),
+        vec!["hook1".to_string()],
+    );
+
+    hook_manager
+        .register_file_hook(
+            "hook1",
+            prepare_counting_file_hook(&prepare_count),
+            pattern_config("subsubdir1/"),
+        )
+        .unwrap();
+    for _ in 0..5 {
+        assert_eq!(
+            accepted_file_paths(&ctx, &hook_manager, "bm1").await,
+            hashset! {"dir1/subdir1/subsubdir1/file_1".to_string()}
+        );
+    }
+    assert_eq!(prepare_count.load(Ordering::SeqCst), 1);
+
+    // Registering the hook again with a different config prepares it again.
+    hook_manager
+        .register_file_hook(
+            "hook1",
+            prepare_counting_file_hook(&prepare_count),
+            pattern_config("file_2$"),
+        )
+        .unwrap();
+    assert_eq!(
+        accepted_file_paths(&ctx, &hook_manager, "bm1").await,
+        hashset! {"dir1/subdir1/subsubdir2/file_2".to_string()}
+    );
+    assert_eq!(prepare_count.load(Ordering::SeqCst), 2);
+}
+
+#[fbinit::test]
+async fn test_default_hook_config(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let prepare_count = Arc::new(AtomicUsize::new(0));
+    let mut hook_manager = hook_manager_inmem(fb).await;
+    hook_manager.set_hooks_for_bookmark(
+        BookmarkKey::new("bm1").unwrap().into(),
+        vec!["inherits".to_string()],
+    );
+    hook_manager.set_hooks_for_bookmark(
+        BookmarkKey::new("bm2").unwrap().into(),
+        vec!["inherits".to_string(), "overrides".to_string()],
+    );
+    hook_manager.set_default_hook_config(PartialHookConfig {
+        bypass: Some(HookBypass::new_with_pushvar(
+            "BYPASS_ALL_HOOKS".into(),
+            "true".into(),
+        )),
+        strings: Some(hashmap! {
+            "pattern".to_string() => "subsubdir1/".to_string(),
+            "other".to_string() => "default".to_string(),
+        }),
+        ..Default::default()
+    });
+
+    // A hook without its own pattern is prepared with the default one.
+    hook_manager
+        .register_file_hook(
+            "inherits",
+            prepare_counting_file_hook(&prepare_count),
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        accepted_file_paths(&ctx, &hook_manager, "bm1").await,
+        hashset! {"dir1/subdir1/subsubdir1/file_1".to_string()}
+    );
+
+    // A hook's own values override the defaults, and its own bypass is
+    // combined with the default bypass.
+    hook_manager
+        .register_file_hook(
+            "overrides",
+            prepare_counting_file_hook(&prepare_count),
+            HookConfig {
+                bypass: Some(HookBypass::new_with_commit_msg("@allow".into())),
+                ..pattern_config("file_2$")
+            },
+        )
+        .unwrap();
+    let effective: Vec<_> = hook_manager
+        .effective_hooks_for_bookmark(&BookmarkKey::new("bm2").unwrap())
+        .into_iter()
+        .map(|(name, config)| (name.to_string(), config.clone()))
+        .collect();
+    assert_eq!(
+        effective,
+        vec![
+            (
+                "inherits".to_string(),
+                HookConfig {
+                    bypass: Some(HookBypass::new_with_pushvar(
+                        "BYPASS_ALL_HOOKS".into(),
+                        "true".into(),
+                    )),
+                    strings: hashmap! {
+                        "pattern".to_string() => "subsubdir1/".to_string(),
+                        "other".to_string() => "default".to_string(),
+                    },
+                    ..Default::default()
+                },
+            ),
+            (
+                "overrides".to_string(),
+                HookConfig {
+                    bypass: Some(HookBypass::new_with_commit_msg_and_pushvar(
+                        "@allow".into(),
+                        "BYPASS_ALL_HOOKS".into(),
+                        "true".into(),
+                    )),
+                    strings: hashmap! {
+                        "pattern".to_string() => "file_2$".to_string(),
+                        "other".to_string() => "default".to_string(),
+                    },
+                    ..Default::default()
+                },
+            ),
+        ]
+    );
+
+    // New defaults apply to hooks registered again.
+    hook_manager.set_default_hook_config(PartialHookConfig {
+        strings: Some(hashmap! {
+            "pattern".to_string() => "file_2$".to_string(),
+        }),
+        ..Default::default()
+    });
+    hook_manager
+        .register_file_hook(
+            "inherits",
+            prepare_counting_file_hook(&prepare_count),
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        accepted_file_paths(&ctx, &hook_manager, "bm1").await,
+        hashset! {"dir1/subdir1/subsubdir2/file_2".to_string()}
+    );
+    assert_eq!(prepare_count.load(Ordering::SeqCst), 3);
+}
+
+#[fbinit::test]
+async fn test_file_hook_prepare_failure(fb: FacebookInit) {
+    let prepare_count = Arc::new(AtomicUsize::new(0));
+    let mut hook_manager = hook_manager_inmem(fb).await;
+    let err = hook_manager
+        .register_file_hook(
+            "hook1",
+            prepare_counting_file_hook(&prepare_count),
+            pattern_config("("),
+        )
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("while preparing hook hook1"));
+    assert_eq!(prepare_count.load(Ordering::SeqCst), 1);
+}
+
+#[fbinit::test]
+async fn test_validate_dangling_hook_references(fb: FacebookInit) {
+    let prepare_count = Arc::new(AtomicUsize::new(0));
+    let mut hook_manager = hook_manager_inmem(fb).await;
+    hook_manager
+        .register_file_hook(
+            "hook1",
+            prepare_counting_file_hook(&prepare_count),
+            pattern_config("file"),
+        )
+        .unwrap();
+    hook_manager.set_hooks_for_bookmark(
+        BookmarkKey::new("bm1").unwrap().into(),
+        vec!["hook1".to_string(), "missing1".to_string()],
+    );
+    hook_manager.set_hooks_for_bookmark(
+        Regex::new("bm.*").unwrap().into(),
+        vec!["missing2".to_string(), "hook1".to_string()],
+    );
+
+    assert_eq!(
+        hook_manager.validate(),
+        vec![
+            ConfigProblem::DanglingHookReference {
+                bookmark: BookmarkKey::new("bm1").unwrap().into(),
+                hook_name: "missing1".to_string(),
+            },
+            ConfigProblem::DanglingHookReference {
+                bookmark: Regex::new("bm.*").unwrap().into(),
+                hook_name: "missing2".to_string(),
+            },
+        ]
+    );
+}
+
+#[fbinit::test]
+async fn test_run_hooks_for_missing_hook_fails(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let mut hook_manager = hook_manager_inmem(fb).await;
+    hook_manager.set_hooks_for_bookmark(
+        BookmarkKey::new("bm1").unwrap().into(),
+        vec!["hook1".to_string()],
+    );
+    assert_eq!(hook_manager.validate().len(), 1);
+
+    let err = hook_manager
+        .run_hooks_for_bookmark(
+            &ctx,
+            vec![default_changeset()].iter(),
+            &BookmarkKey::new("bm1").unwrap(),
+            None,
+            ctx.metadata().identities(),
+            CrossRepoPushSource::NativeToThisRepo,
+            PushAuthoredBy::User,
+        )
+        .await
+        .unwrap_err();
+    match err.downcast::<ErrorKind>() {
+        Ok(ErrorKind::NoSuchHook(hook_name)) => assert_eq!(hook_name, "hook1"),
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[fbinit::test]
+async fn test_hook_execution_perf_counters(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let mut hook_manager = hook_manager_inmem(fb).await;
+    hook_manager
+        .register_changeset_hook(
+            "accept",
+            always_accepting_changeset_hook(),
+            Default::default(),
+        )
+        .unwrap();
+    hook_manager
+        .register_changeset_hook(
+            "reject",
+            always_rejecting_changeset_hook(),
+            Default::default(),
+        )
+        .unwrap();
+    hook_manager
+        .register_changeset_hook("fail", Box::new(FailingChangesetHook), Default::default())
+        .unwrap();
+    hook_manager.set_hooks_for_bookmark(
+        BookmarkKey::new("bm1").unwrap().into(),
+        vec!["accept".to_string(), "reject".to_string()],
+    );
+    hook_manager.set_hooks_for_bookmark(
+        BookmarkKey::new("bm2").unwrap().into(),
+        vec!["fail".to_string()],
+    );
+    let changesets = vec![default_changeset()];
+    let run_hooks = |bookmark: &'static str| {
+        hook_manager.run_hooks_for_bookmark(
+            &ctx,
+            changesets.iter(),
+            &BookmarkKey::new(bookmark).unwrap(),
+            None,
+            ctx.metadata().identities(),
+            CrossRepoPushSource::NativeToThisRepo,
+            PushAuthoredBy::User,
+        )
+    };
+    let counter = |counter| ctx.perf_counters().get_counter(counter);
+
+    assert_eq!(run_hooks("bm1").await.unwrap().len(), 2);
+    assert_eq!(counter(PerfCounterType::HooksRun), 2);
+    assert_eq!(counter(PerfCounterType::HooksRejected), 1);
+    assert_eq!(counter(PerfCounterType::HooksFailed), 0);
+
+    // Counters accumulate over the pushes of a request.
+    assert!(run_hooks("bm2").await.is_err());
+    assert_eq!(counter(PerfCounterType::HooksRun), 3);
+    assert_eq!(counter(PerfCounterType::HooksRejected), 1);
+    assert_eq!(counter(PerfCounterType::HooksFailed), 1);
+
+    assert!(run_hooks("bm3").await.unwrap().is_empty());
+    assert_eq!(counter(PerfCounterType::HooksRun), 3);
+}
+
+#[fbinit::test]
+async fn test_run_hooks_in_scope(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let mut hook_manager = hook_manager_inmem(fb).await;
+    let (shared_cs, shared_cs_runs) = ExecutionCountingHook::new();
+    let (bm2_cs, bm2_cs_runs) = ExecutionCountingHook::new();
+    let (shared_file, shared_file_runs) = ExecutionCountingHook::new();
+    let (bm1_file, bm1_file_runs) = ExecutionCountingHook::new();
+    hook_manager
+        .register_changeset_hook("shared_cs", Box::new(shared_cs), Default::default())
+        .unwrap();
+    hook_manager
+        .register_changeset_hook("bm2_cs", Box::new(bm2_cs), Default::default())
+        .unwrap();
+    hook_manager
+        .register_file_hook("shared_file", Box::new(shared_file), Default::default())
+        .unwrap();
+    hook_manager
+        .register_file_hook("bm1_file", Box::new(bm1_file), Default::default())
+        .unwrap();
+    hook_manager.set_hooks_for_bookmark(
+        BookmarkKey::new("bm1").unwrap().into(),
+        vec![
+            "shared_cs".to_string(),
+            "shared_file".to_string(),
+            "bm1_file".to_string(),
+        ],
+    );
+    hook_manager.set_hooks_for_bookmark(
+        BookmarkKey::new("bm2").unwrap().into(),
+        vec![
+            "bm2_cs".to_string(),
+            "shared_cs".to_string(),
+            "shared_file".to_string(),
+        ],
+    );
+    // The default changeset changes 3 files.
+    let changesets = vec![default_changeset()];
+    let scope = HookExecutionScope::new();
+    let unshared_scope = HookExecutionScope::new();
+    let run_hooks = |bookmark: &'static str, scope| {
+        hook_manager.run_hooks_for_bookmark_in_scope(
+            &ctx,
+            scope,
+            changesets.iter(),
+            &BookmarkKey::new(bookmark).unwrap(),
+            None,
+            ctx.metadata().identities(),
+            CrossRepoPushSource::NativeToThisRepo,
+            PushAuthoredBy::User,
+        )
+    };
+    let runs = || {
+        [
+            &shared_cs_runs,
+            &bm2_cs_runs,
+            &shared_file_runs,
+            &bm1_file_runs,
+        ]
+        .map(|runs| runs.load(Ordering::SeqCst))
+    };
+
+    assert_eq!(run_hooks("bm1", &scope).await.unwrap().len(), 7);
+    assert_eq!(runs(), [1, 0, 3, 3]);
+    assert_eq!(
+        ctx.perf_counters().get_counter(PerfCounterType::HooksRun),
+        7
+    );
+
+    // Only the hook bound to bm2 alone runs, but the outcomes are those of
+    // all the hooks of bm2.
+    let outcomes = run_hooks("bm2", &scope).await.unwrap();
+    assert_eq!(runs(), [1, 1, 3, 3]);
+    assert_eq!(
+        ctx.perf_counters().get_counter(PerfCounterType::HooksRun),
+        8
+    );
+    assert_eq!(outcomes, run_hooks("bm2", &unshared_scope).await.unwrap());
+    assert_eq!(runs(), [2, 2, 6, 3]);
+}
+
+#[fbinit::test]
+async fn test_run_hooks_in_scope_bookmark_dependent(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let mut hook_manager = hook_manager_inmem(fb).await;
+    let (independent, independent_runs) = ExecutionCountingHook::new();
+    let (dependent, dependent_runs) = ExecutionCountingHook::bookmark_dependent();
+    hook_manager
+        .register_changeset_hook("independent", Box::new(independent), Default::default())
+        .unwrap();
+    hook_manager
+        .register_changeset_hook("dependent", Box::new(dependent), Default::default())
+        .unwrap();
+    let hooks = vec!["independent".to_string(), "dependent".to_string()];
+    hook_manager.set_hooks_for_bookmark(BookmarkKey::new("bm1").unwrap().into(), hooks.clone());
+    hook_manager.set_hooks_for_bookmark(BookmarkKey::new("bm2").unwrap().into(), hooks);
+    let changesets = vec![default_changeset()];
+    let scope = HookExecutionScope::new();
+    let run_hooks = |bookmark: &'static str| {
+        hook_manager.run_hooks_for_bookmark_in_scope(
+            &ctx,
+            &scope,
+            changesets.iter(),
+            &BookmarkKey::new(bookmark).unwrap(),
+            None,
+            ctx.metadata().identities(),
+            CrossRepoPushSource::NativeToThisRepo,
+            PushAuthoredBy::User,
+        )
+    };
+    let runs = || [&independent_runs, &dependent_runs].map(|runs| runs.load(Ordering::SeqCst));
+
+    assert_eq!(run_hooks("bm1").await.unwrap().len(), 2);
+    assert_eq!(runs(), [1, 1]);
+
+    // Hooks that may depend on the bookmark run again for another one.
+    assert_eq!(run_hooks("bm2").await.unwrap().len(), 2);
+    assert_eq!(runs(), [1, 2]);
+
+    // Their outcomes are still reused for the same bookmark.
+    assert_eq!(run_hooks("bm1").await.unwrap().len(), 2);
+    assert_eq!(runs(), [1, 2]);
+}
+
+#[fbinit::test]
+async fn test_run_hooks_in_scope_after_reload(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let mut hook_manager = hook_manager_inmem(fb).await;
+    hook_manager
+        .register_file_hook("file", always_accepting_file_hook(), Default::default())
+        .unwrap();
+    let (counting, counting_runs) = ExecutionCountingHook::new();
+    hook_manager
+        .register_changeset_hook("counting", Box::new(counting), Default::default())
+        .unwrap();
+    hook_manager.set_hooks_for_bookmark(
+        BookmarkKey::new("bm1").unwrap().into(),
+        vec!["file".to_string(), "counting".to_string()],
+    );
+    let changesets = vec![default_changeset()];
+    let scope = HookExecutionScope::new();
+    let run_hooks = |hook_manager: &HookManager| {
+        let ctx = &ctx;
+        let scope = &scope;
+        let changesets = &changesets;
+        async move {
+            hook_manager
+                .run_hooks_for_bookmark_in_scope(
+                    ctx,
+                    scope,
+                    changesets.iter(),
+                    &BookmarkKey::new("bm1").unwrap(),
+                    None,
+                    ctx.metadata().identities(),
+                    CrossRepoPushSource::NativeToThisRepo,
+                    PushAuthoredBy::User,
+                )
+                .await
+                .unwrap()
+                .iter()
+                .filter(|outcome| outcome.is_rejection())
+                .count()
+        }
+    };
+
+    assert_eq!(run_hooks(&hook_manager).await, 0);
+    assert_eq!(counting_runs.load(Ordering::SeqCst), 1);
+    assert_eq!(run_hooks(&hook_manager).await, 0);
+    assert_eq!(counting_runs.load(Ordering::SeqCst), 1);
+
+    // The hook is reloaded with the same config but different code: its
+    // earlier outcomes are not reused.
+    hook_manager
+        .register_file_hook("file", always_rejecting_file_hook(), Default::default())
+        .unwrap();
+    assert_eq!(run_hooks(&hook_manager).await, 3);
+    assert_eq!(counting_runs.load(Ordering::SeqCst), 1);
+
+    hook_manager.purge_hook_cache("counting");
+    assert_eq!(run_hooks(&hook_manager).await, 3);
+    assert_eq!(counting_runs.load(Ordering::SeqCst), 2);
+}
+
+#[fbinit::test]
+async fn test_run_hooks_fail_fast(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let mut hook_manager = hook_manager_inmem(fb).await;
+    let (cheap, cheap_runs) = ExecutionCountingHook::new();
+    let (expensive_file, expensive_file_runs) = ExecutionCountingHook::new();
+    let (expensive_cs, expensive_cs_runs) = ExecutionCountingHook::new();
+    hook_manager
+        .register_changeset_hook("cheap", Box::new(cheap), Default::default())
+        .unwrap();
+    hook_manager
+        .register_file_hook("reject", always_rejecting_file_hook(), Default::default())
+        .unwrap();
+    hook_manager
+        .register_file_hook(
+            "expensive_file",
+            Box::new(expensive_file),
+            Default::default(),
+        )
+        .unwrap();
+    hook_manager
+        .register_changeset_hook("expensive_cs", Box::new(expensive_cs), Default::default())
+        .unwrap();
+    let hooks = vec![
+        "cheap".to_string(),
+        "reject".to_string(),
+        "expensive_file".to_string(),
+        "expensive_cs".to_string(),
+    ];
+    hook_manager.set_hooks_for_bookmark_with_mode(
+        BookmarkKey::new("bm1").unwrap().into(),
+        hooks.clone(),
+        HookRunMode::FailFast,
+    );
+    hook_manager.set_hooks_for_bookmark(BookmarkKey::new("bm2").unwrap().into(), hooks);
+    assert_eq!(
+        hook_manager.run_mode_for_bookmark(&BookmarkKey::new("bm1").unwrap()),
+        HookRunMode::FailFast
+    );
+    assert_eq!(
+        hook_manager.run_mode_for_bookmark(&BookmarkKey::new("bm2").unwrap()),
+        HookRunMode::RunAll
+    );
+
+    // The default changeset changes 3 files.
+    let changesets = vec![default_changeset()];
+    let cs_id = changesets[0].get_changeset_id();
+    let scope = HookExecutionScope::new();
+    let unshared_scope = HookExecutionScope::new();
+    let run_hooks = |bookmark: &'static str, scope| {
+        hook_manager.run_hooks_for_bookmark_in_scope(
+            &ctx,
+            scope,
+            changesets.iter(),
+            &BookmarkKey::new(bookmark).unwrap(),
+            None,
+            ctx.metadata().identities(),
+            CrossRepoPushSource::NativeToThisRepo,
+            PushAuthoredBy::User,
+        )
+    };
+    let runs = || {
+        [&cheap_runs, &expensive_file_runs, &expensive_cs_runs]
+            .map(|runs| runs.load(Ordering::SeqCst))
+    };
+
+    // The first file the rejecting hook runs on stops the run.
+    let outcomes: Vec<_> = run_hooks("bm1", &scope)
+        .await
+        .unwrap()
+        .iter()
+        .map(|o| {
+            (
+                o.get_changeset_id(),
+                o.get_hook_name().to_string(),
+                o.get_file_path().map(|p| p.to_string()),
+                o.is_rejection(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        outcomes,
+        vec![
+            (cs_id, "cheap".to_string(), None, false),
+            (
+                cs_id,
+                "reject".to_string(),
+                Some("dir1/subdir1/subsubdir1/file_1".to_string()),
+                true,
+            ),
+        ]
+    );
+    assert_eq!(runs(), [1, 0, 0]);
+    assert_eq!(
+        ctx.perf_counters().get_counter(PerfCounterType::HooksRun),
+        2
+    );
+
+    // The hooks that ran to completion are kept in the scope. The rejecting
+    // hook only ran on one file, so it runs again on all of them.
+    let outcomes = run_hooks("bm2", &scope).await.unwrap();
+    assert_eq!(runs(), [1, 3, 1]);
+    assert_eq!(outcomes.iter().filter(|o| o.is_rejection()).count(), 3);
+    assert_eq!(outcomes, run_hooks("bm2", &unshared_scope).await.unwrap());
+}
+
+#[fbinit::test]
+async fn test_file_hook_sees_copy_from(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let mut hook_manager = hook_manager_inmem(fb).await;
+    hook_manager
+        .register_file_hook(
+            "moved",
+            Box::new(MovedOutOfDirFileHook {
+                dir: to_mpath("protected"),
+            }),
+            Default::default(),
+        )
+        .unwrap();
+    hook_manager.set_hooks_for_bookmark(
+        BookmarkKey::new("bm1").unwrap().into(),
+        vec!["moved".to_string()],
+    );
+
+    let mut cs = default_changeset().into_mut();
+    cs.parents = vec![ONES_CSID];
+    let copied = |from: &str| {
+        FileChange::tracked(
+            ONES_CTID,
+            FileType::Regular,
+            10,
+            Some((to_mpath(from), ONES_CSID)),
+        )
+    };
+    cs.file_changes = sorted_vector_map! {
+        to_mpath("elsewhere/copied") => copied("protected/kept"),
+        to_mpath("elsewhere/moved") => copied("protected/moved"),
+        to_mpath("protected/moved") => FileChange::Deletion,
+        to_mpath("protected/renamed") => copied("protected/old"),
+        to_mpath("protected/old") => FileChange::Deletion,
+    };
+    let changesets = vec![cs.freeze().expect("Created changeset")];
+
+    let rejected: Vec<_> = hook_manager
+        .run_hooks_for_bookmark(
+            &ctx,
+            changesets.iter(),
+            &BookmarkKey::new("bm1").unwrap(),
+            None,
+            ctx.metadata().identities(),
+            CrossRepoPushSource::NativeToThisRepo,
+            PushAuthoredBy::User,
+        )
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(HookOutcome::is_rejection)
+        .map(|outcome| outcome.get_file_path().unwrap().to_string())
+        .collect();
+    assert_eq!(rejected, vec!["elsewhere/moved".to_string()]);
+}
+
+#[fbinit::test]
+async fn test_hook_timeout(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let mut hook_manager = hook_manager_inmem(fb).await;
+    hook_manager.set_default_hook_timeout(Duration::from_millis(100));
+    let slow = SleepingHook {
+        sleep: Duration::from_secs(600),
+    };
+    hook_manager
+        .register_changeset_hook("slow_cs", Box::new(slow.clone()), Default::default())
+        .unwrap();
+    hook_manager
+        .register_file_hook("slow_file", Box::new(slow), Default::default())
+        .unwrap();
+    // Its own timeout lets this hook outlast the default one.
+    let patient = SleepingHook {
+        sleep: Duration::from_millis(200),
+    };
+    hook_manager
+        .register_changeset_hook(
+            "patient_cs",
+            Box::new(patient),
+            HookConfig {
+                timeout_ms: Some(600_000),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    hook_manager.set_hooks_for_bookmark(
+        BookmarkKey::new("bm1").unwrap().into(),
+        vec![
+            "slow_cs".to_string(),
+            "slow_file".to_string(),
+            "patient_cs".to_string(),
+        ],
+    );
+
+    let changesets = vec![default_changeset()];
+    let outcomes = hook_manager
+        .run_hooks_for_bookmark(
+            &ctx,
+            changesets.iter(),
+            &BookmarkKey::new("bm1").unwrap(),
+            None,
+            ctx.metadata().identities(),
+            CrossRepoPushSource::NativeToThisRepo,
+            PushAuthoredBy::User,
+        )
+        .await
+        .unwrap();
+
+    assert!(!outcomes.is_empty());
+    for outcome in &outcomes {
+        match (outcome.get_hook_name(), outcome.get_execution()) {
+            ("patient_cs", execution) => assert_eq!(execution, &HookExecution::Accepted),
+            (name, HookExecution::Rejected(info)) => {
+                assert_eq!(info.description, "hook timed out");
+                assert!(info.long_description.contains(name));
+            }
+            (name, execution) => panic!("{} wasn't timed out: {:?}", name, execution),
+        }
+    }
+}
+
+#[fbinit::test]
+async fn test_run_hooks_output_order(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let mut hook_manager = hook_manager_inmem(fb).await;
+    for name in ["cs_a", "cs_b"] {
+        hook_manager
+            .register_changeset_hook(name, always_accepting_changeset_hook(), Default::default())
+            .unwrap();
+    }
+    for name in ["file_a", "file_b"] {
+        hook_manager
+            .register_file_hook(name, always_rejecting_file_hook(), Default::default())
+            .unwrap();
+    }
+    hook_manager.set_hooks_for_bookmark(
+        BookmarkKey::new("bm1").unwrap().into(),
+        vec!["file_b".to_string(), "cs_b".to_string()],
+    );
+    hook_manager.set_hooks_for_bookmark(
+        Regex::new("nomatch").unwrap().into(),
+        vec!["file_a".to_string()],
+    );
+    // Hooks already bound to the bookmark keep their first position.
+    hook_manager.set_hooks_for_bookmark(
+        Regex::new("bm.*").unwrap().into(),
+        vec![
+            "cs_a".to_string(),
+            "file_b".to_string(),
+            "file_a".to_string(),
+        ],
+    );
+
+    let changesets = vec![
+        default_changeset(),
+        changeset_with_files(&[("b", ONES_CTID), ("a", TWOS_CTID)]),
+    ];
+    let cs1 = changesets[0].get_changeset_id();
+    let cs2 = changesets[1].get_changeset_id();
+    let outcome = |cs_id, hook_name: &str, path: Option<&str>| {
+        (cs_id, hook_name.to_string(), path.map(|p| p.to_string()))
+    };
+    let mut expected = vec![outcome(cs1, "cs_b", None), outcome(cs1, "cs_a", None)];
+    for path in [
+        "dir1/subdir1/subsubdir1/file_1",
+        "dir1/subdir1/subsubdir2/file_1",
+        "dir1/subdir1/subsubdir2/file_2",
+    ] {
+        expected.push(outcome(cs1, "file_b", Some(path)));
+        expected.push(outcome(cs1, "file_a", Some(path)));
+    }
+    expected.extend([
+        outcome(cs2, "cs_b", None),
+        outcome(cs2, "cs_a", None),
+        outcome(cs2, "file_b", Some("a")),
+        outcome(cs2, "file_a", Some("a")),
+        outcome(cs2, "file_b", Some("b")),
+        outcome(cs2, "file_a", Some("b")),
+    ]);
+
+    for _ in 0..10 {
+        let outcomes: Vec<_> = hook_manager
+            .run_hooks_for_bookmark(
+                &ctx,
+                changesets.iter(),
+                &BookmarkKey::new("bm1").unwrap(),
+                None,
+                ctx.metadata().identities(),
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await
+            .unwrap()
+            .iter()
+            .map(|o| {
+                outcome(
+                    o.get_changeset_id(),
+                    o.get_hook_name(),
+                    o.get_file_path().map(|p| p.to_string()).as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(outcomes, expected);
+    }
+}
+
+async fn accepted_file_paths(
+    ctx: &CoreContext,
+    hook_manager: &HookManager,
+    bookmark_name: &str,
+) -> HashSet<String> {
+    hook_manager
+        .run_hooks_for_bookmark(
+            ctx,
+            vec![default_changeset()].iter(),
+            &BookmarkKey::new(bookmark_name).unwrap(),
+            None,
+            ctx.metadata().identities(),
+            CrossRepoPushSource::NativeToThisRepo,
+            PushAuthoredBy::User,
+        )
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(HookOutcome::is_accept)
+        .map(|outcome| outcome.get_file_path().expect("Changeset hook").to_string())
+        .collect()
+}
+
+async fn hook_outcomes(
+    ctx: &CoreContext,
+    hook_manager: &HookManager,
+    changesets: &[BonsaiChangeset],
+) -> HashSet<(String, ChangesetId, bool)> {
+    hook_manager
+        .run_hooks_for_bookmark(
+            ctx,
+            changesets.iter(),
+            &BookmarkKey::new("bm1").unwrap(),
+            None,
+            ctx.metadata().identities(),
+            CrossRepoPushSource::NativeToThisRepo,
+            PushAuthoredBy::User,
+        )
+        .await
+        .unwrap()
+        .iter()
+        .map(|outcome| {
+            (
+                outcome.get_hook_name().to_string(),
+                outcome.get_changeset_id(),
+                outcome.is_accept(),
+            )
+        })
+        .collect()
+}
+
+async fn run_changeset_hooks(
+    ctx: CoreContext,
+    bookmark_name: &str,
+    hooks: HashMap<String, Box<dyn ChangesetHook>>,
+    bookmarks: HashMap<String, Vec<String>>,
+    regexes: HashMap<String, Vec<String>>,
+    expected: HashMap<String, HookExecution>,
+) {
+    run_changeset_hooks_with_mgr(
+        ctx,
+        None,
+        bookmark_name,
+        hooks,
+        bookmarks,
+        regexes,
+        expected,
+        ContentFetcherType::InMemory,
+    )
+    .await
+}
+
+async fn run_changeset_hooks_with_mgr(
+    ctx: CoreContext,
+    changeset: Option<BonsaiChangeset>,
+    bookmark_name: &str,
+    hooks: HashMap<String, Box<dyn ChangesetHook>>,
+    bookmarks: HashMap<String, Vec<String>>,
+    regexes: HashMap<String, Vec<String>>,
+    expected: HashMap<String, HookExecution>,
+    content_manager_type: ContentFetcherType,
+) {
+    let mut hook_manager =
+        setup_hook_manager(ctx.fb, bookmarks, regexes, content_manager_type).await;
+    for (hook_name, hook) in hooks {
+        hook_manager
+            .register_changeset_hook(&hook_name, hook, Default::default())
+            .unwrap();
+    }
+
+    let changeset = changeset.unwrap_or_else(default_changeset);
+    let res = hook_manager
+        .run_hooks_for_bookmark(
+            &ctx,
+            vec![changeset].iter(),
+            &BookmarkKey::new(bookmark_name).unwrap(),
+            None,
+            ctx.metadata().identities(),
+            CrossRepoPushSource::NativeToThisRepo,
+            PushAuthoredBy::User,
+        )
+        .await
+        .unwrap();
+    let map: HashMap<String, HookExecution> = res
+        .into_iter()
+        .map(|outcome| (outcome.get_hook_name().to_string(), outcome.into()))
+        .collect();
+    assert_eq!(expected, map);
+}
+
+enum ContentFetcherType {
+    InMemory,
+    Blob(BasicTestRepo),
 }
 
 async fn run_file_hooks(
@@ -1351,7 +3387,9 @@ async fn run_file_hooks_with_mgr(
     let mut hook_manager =
         setup_hook_manager(ctx.fb, bookmarks, regexes, content_manager_type).await;
     for (hook_name, hook) in hooks {
-        hook_manager.register_file_hook(&hook_name, hook, Default::default());
+        hook_manager
+            .register_file_hook(&hook_name, hook, Default::default())
+            .unwrap();
     }
     let res = hook_manager
         .run_hooks_for_bookmark(
@@ -1359,6 +3397,7 @@ async fn run_file_hooks_with_mgr(
             vec![cs].iter(),
             &BookmarkKey::new(bookmark_name).unwrap(),
             None,
+            ctx.metadata().identities(),
             CrossRepoPushSource::NativeToThisRepo,
             PushAuthoredBy::User,
         )
@@ -1421,6 +3460,20 @@ fn default_changeset() -> BonsaiChangeset {
     }.freeze().expect("Created changeset")
 }
 
+fn changeset_with_files(files: &[(&str, ContentId)]) -> BonsaiChangeset {
+    let mut cs = default_changeset().into_mut();
+    cs.file_changes = files
+        .iter()
+        .map(|(path, id)| {
+            (
+                to_mpath(path),
+                FileChange::tracked(*id, FileType::Regular, 10, None),
+            )
+        })
+        .collect();
+    cs.freeze().expect("Created changeset")
+}
+
 async fn hook_manager_repo(fb: FacebookInit, repo: &BasicTestRepo) -> HookManager {
     let ctx = CoreContext::test_mock(fb);
 
@@ -1484,10 +3537,12 @@ async fn test_load_hooks_bad_rust_hook(fb: FacebookInit) {
         hooks_skip_ancestors_of: vec![],
         ensure_ancestor_of: None,
         allow_move_to_public_commits_without_hooks: false,
+        pusher_gate: None,
     }];
 
     config.hooks = vec![HookParams {
         name: "hook1".into(),
+        kind: None,
         config: Default::default(),
     }];
 
@@ -1517,6 +3572,7 @@ async fn test_load_disabled_hooks(fb: FacebookInit) {
 
     config.hooks = vec![HookParams {
         name: "hook1".into(),
+        kind: None,
         config: Default::default(),
     }];
 
@@ -1533,6 +3589,78 @@ async fn test_load_disabled_hooks(fb: FacebookInit) {
     .expect("disabling a broken hook should allow loading to succeed");
 }
 
+#[fbinit::test]
+async fn test_load_pusher_gate(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let mut config = RepoConfig::default();
+    config.bookmarks = vec![BookmarkParams {
+        bookmark: BookmarkKey::new("bm1").unwrap().into(),
+        hooks: vec![],
+        only_fast_forward: false,
+        allowed_users: None,
+        allowed_hipster_group: None,
+        rewrite_dates: None,
+        hooks_skip_ancestors_of: vec![],
+        ensure_ancestor_of: None,
+        allow_move_to_public_commits_without_hooks: false,
+        pusher_gate: Some(PusherGateParams {
+            name: "no_bots".to_string(),
+            allowed_pushers: None,
+            denied_pushers: vec![
+                PusherMatcherParams::Identity("USER:release_bot".to_string()),
+                PusherMatcherParams::Username("*_svc".to_string()),
+            ],
+            bypass: None,
+        }),
+    }];
+
+    let mut hm = hook_manager_inmem(fb).await;
+    load_hooks(
+        fb,
+        DefaultAclProvider::new(fb).as_ref(),
+        &mut hm,
+        &config,
+        &HashSet::new(),
+    )
+    .await
+    .unwrap();
+
+    let bookmark = BookmarkKey::new("bm1").unwrap();
+    // A bookmark with a gate but no hooks still needs them to be run.
+    assert!(hm.hooks_exist_for_bookmark(&bookmark));
+    let gates = hm.pusher_gates_for_bookmark(&bookmark);
+    assert_eq!(gates.len(), 1);
+    assert_eq!(
+        gates[0]
+            .denied_pushers
+            .iter()
+            .map(|matcher| matcher.to_string())
+            .collect::<Vec<_>>(),
+        vec!["identity USER:release_bot", "user *_svc"]
+    );
+
+    let changesets = vec![default_changeset()];
+    for (user, accepted) in [
+        ("release_bot", false),
+        ("deploy_svc", false),
+        ("alice", true),
+    ] {
+        let outcomes = hm
+            .run_hooks_for_bookmark(
+                &ctx,
+                changesets.iter(),
+                &bookmark,
+                None,
+                &user_identities(user),
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            )
+            .await
+            .unwrap();
+        assert_eq!(outcomes.iter().all(|o| o.is_accept()), accepted, "{}", user);
+    }
+}
+
 #[fbinit::test]
 async fn test_load_disabled_hooks_referenced_by_bookmark(fb: FacebookInit) {
     let mut config = RepoConfig::default();
@@ -1547,10 +3675,12 @@ async fn test_load_disabled_hooks_referenced_by_bookmark(fb: FacebookInit) {
         hooks_skip_ancestors_of: vec![],
         ensure_ancestor_of: None,
         allow_move_to_public_commits_without_hooks: false,
+        pusher_gate: None,
     }];
 
     config.hooks = vec![HookParams {
         name: "hook1".into(),
+        kind: None,
         config: Default::default(),
     }];
 
@@ -1567,6 +3697,49 @@ async fn test_load_disabled_hooks_referenced_by_bookmark(fb: FacebookInit) {
     .expect("disabling a broken hook should allow loading to succeed");
 }
 
+#[fbinit::test]
+async fn test_load_hooks_dangling_hook_reference(fb: FacebookInit) {
+    let mut config = RepoConfig::default();
+    config.bookmarks = vec![BookmarkParams {
+        bookmark: BookmarkKey::new("bm1").unwrap().into(),
+        hooks: vec!["hook1".into(), "hook2".into()],
+        only_fast_forward: false,
+        allowed_users: None,
+        allowed_hipster_group: None,
+        rewrite_dates: None,
+        hooks_skip_ancestors_of: vec![],
+        ensure_ancestor_of: None,
+        allow_move_to_public_commits_without_hooks: false,
+        pusher_gate: None,
+    }];
+    let mut hm = hook_manager_many_files_dirs_repo(fb).await;
+    // Hooks registered before loading count as existing.
+    hm.register_changeset_hook(
+        "hook1",
+        always_accepting_changeset_hook(),
+        Default::default(),
+    )
+    .unwrap();
+
+    match load_hooks(
+        fb,
+        DefaultAclProvider::new(fb).as_ref(),
+        &mut hm,
+        &config,
+        &hashset![],
+    )
+    .await
+    .unwrap_err()
+    .downcast::<ErrorKind>()
+    {
+        Ok(ErrorKind::NoSuchBookmarkHook(bookmark, hook_names)) => {
+            assert_eq!(bookmark, BookmarkKey::new("bm1").unwrap().into());
+            assert_eq!(hook_names, hashset!["hook2".to_string()]);
+        }
+        _ => panic!("Unexpected err type"),
+    };
+}
+
 #[fbinit::test]
 async fn test_load_disabled_hooks_hook_does_not_exist(fb: FacebookInit) {
     let config = RepoConfig::default();
@@ -1589,3 +3762,287 @@ async fn test_load_disabled_hooks_hook_does_not_exist(fb: FacebookInit) {
         _ => panic!("Unexpected err type"),
     };
 }
+
+#[fbinit::test]
+async fn test_bookmark_hook_blocks_deletion(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let mut config = RepoConfig::default();
+    config.bookmarks = vec![BookmarkParams {
+        bookmark: BookmarkKey::new("protected").unwrap().into(),
+        hooks: vec!["block_bookmark_deletion".into()],
+        only_fast_forward: false,
+        allowed_users: None,
+        allowed_hipster_group: None,
+        rewrite_dates: None,
+        hooks_skip_ancestors_of: vec![],
+        ensure_ancestor_of: None,
+        allow_move_to_public_commits_without_hooks: false,
+        pusher_gate: None,
+    }];
+    config.hooks = vec![HookParams {
+        name: "block_bookmark_deletion".into(),
+        kind: Some(HookKind::Bookmark),
+        config: Default::default(),
+    }];
+
+    let mut hm = hook_manager_many_files_dirs_repo(fb).await;
+    load_hooks(
+        fb,
+        DefaultAclProvider::new(fb).as_ref(),
+        &mut hm,
+        &config,
+        &hashset![],
+    )
+    .await
+    .unwrap();
+
+    let move_op = BookmarkOperationKind::Move {
+        from: ONES_CSID,
+        to: TWOS_CSID,
+        is_fast_forward: true,
+    };
+    let delete_op = BookmarkOperationKind::Delete { from: ONES_CSID };
+
+    let outcomes = hm
+        .run_bookmark_hooks(&ctx, &bookmark_hook_data("protected", move_op), None)
+        .await
+        .unwrap();
+    assert_eq!(outcomes.len(), 1);
+    assert!(outcomes[0].is_accept());
+
+    let outcomes = hm
+        .run_bookmark_hooks(&ctx, &bookmark_hook_data("protected", delete_op), None)
+        .await
+        .unwrap();
+    assert_eq!(outcomes.len(), 1);
+    assert!(outcomes[0].is_rejection());
+    assert_eq!(outcomes[0].get_hook_name(), "block_bookmark_deletion");
+
+    // Other bookmarks are not protected.
+    let outcomes = hm
+        .run_bookmark_hooks(&ctx, &bookmark_hook_data("other", delete_op), None)
+        .await
+        .unwrap();
+    assert!(outcomes.is_empty());
+}
+
+#[fbinit::test]
+async fn test_load_bookmark_hook_without_kind(fb: FacebookInit) {
+    let mut config = RepoConfig::default();
+    config.hooks = vec![HookParams {
+        name: "block_bookmark_deletion".into(),
+        kind: None,
+        config: Default::default(),
+    }];
+
+    let mut hm = hook_manager_many_files_dirs_repo(fb).await;
+
+    match load_hooks(
+        fb,
+        DefaultAclProvider::new(fb).as_ref(),
+        &mut hm,
+        &config,
+        &hashset![],
+    )
+    .await
+    .unwrap_err()
+    .downcast::<ErrorKind>()
+    {
+        Ok(ErrorKind::InvalidRustHook(hook_name)) => {
+            assert_eq!(hook_name, "block_bookmark_deletion".to_string());
+        }
+        _ => panic!("Unexpected err type"),
+    };
+}
+
+#[fbinit::test]
+async fn test_bookmark_hooks_regex(fb: FacebookInit) {
+    let ctx = CoreContext::test_mock(fb);
+    let mut hm = setup_hook_manager(
+        fb,
+        hashmap! {
+            "release/1.0".to_string() => vec!["exact".to_string()],
+        },
+        hashmap! {
+            "^release/.*".to_string() => vec!["regex".to_string(), "changeset".to_string()],
+        },
+        ContentFetcherType::InMemory,
+    )
+    .await;
+    hm.register_bookmark_hook(
+        "exact",
+        always_rejecting_bookmark_hook(),
+        Default::default(),
+    )
+    .unwrap();
+    hm.register_bookmark_hook(
+        "regex",
+        always_rejecting_bookmark_hook(),
+        Default::default(),
+    )
+    .unwrap();
+    // Changeset hooks bound to the same bookmarks are not run.
+    hm.register_changeset_hook(
+        "changeset",
+        always_rejecting_changeset_hook(),
+        Default::default(),
+    )
+    .unwrap();
+
+    let create_op = BookmarkOperationKind::Create { to: ONES_CSID };
+    let hook_names = |outcomes: Vec<BookmarkHookOutcome>| {
+        outcomes
+            .into_iter()
+            .map(|outcome| outcome.get_hook_name().to_string())
+            .collect::<HashSet<_>>()
+    };
+
+    let outcomes = hm
+        .run_bookmark_hooks(&ctx, &bookmark_hook_data("release/1.0", create_op), None)
+        .await
+        .unwrap();
+    assert_eq!(
+        hook_names(outcomes),
+        hashset! {"exact".to_string(), "regex".to_string()}
+    );
+
+    let outcomes = hm
+        .run_bookmark_hooks(&ctx, &bookmark_hook_data("release/2.0", create_op), None)
+        .await
+        .unwrap();
+    assert_eq!(hook_names(outcomes), hashset! {"regex".to_string()});
+
+    let outcomes = hm
+        .run_bookmark_hooks(&ctx, &bookmark_hook_data("master", create_op), None)
+        .await
+        .unwrap();
+    assert!(outcomes.is_empty());
+}
+
+/// A hook written like those of other crates, only using the public API:
+/// rejects text files with trailing whitespace.
+#[derive(Clone, Debug)]
+struct NoTrailingWhitespaceHook;
+
+#[async_trait]
+impl FileHook for NoTrailingWhitespaceHook {
+    fn content_interest(&self) -> ContentInterest {
+        ContentInterest::TextOnly
+    }
+
+    async fn run<'this: 'change, 'ctx: 'this, 'change, 'fetcher: 'change, 'path: 'change>(
+        &'this self,
+        ctx: &'ctx CoreContext,
+        content_manager: &'fetcher dyn FileContentManager,
+        change: Option<&'change BasicFileChange>,
+        path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
+        _cross_repo_push_source: CrossRepoPushSource,
+        _push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution, Error> {
+        // Deleted files have no content.
+        let change = match change {
+            Some(change) => change,
+            None => return Ok(HookExecution::Accepted),
+        };
+        // The text of large files is elided.
+        let text = match content_manager
+            .get_file_text(ctx, change.content_id())
+            .await?
+        {
+            Some(text) => text,
+            None => return Ok(HookExecution::Accepted),
+        };
+        let trailing = text
+            .split(|b| *b == b'\n')
+            .any(|line| line.ends_with(b" ") || line.ends_with(b"\t"));
+        Ok(if trailing {
+            HookExecution::Rejected(HookRejectionInfo::new_long(
+                "Trailing whitespace",
+                format!("{} has lines with trailing whitespace", path),
+            ))
+        } else {
+            HookExecution::Accepted
+        })
+    }
+}
+
+/// Panics on deleted files, and blocks the executor on others if `blocking`.
+#[derive(Clone, Debug)]
+struct CarelessFileHook {
+    blocking: bool,
+}
+
+#[async_trait]
+impl FileHook for CarelessFileHook {
+    async fn run<'this: 'change, 'ctx: 'this, 'change, 'fetcher: 'change, 'path: 'change>(
+        &'this self,
+        _ctx: &'ctx CoreContext,
+        _content_manager: &'fetcher dyn FileContentManager,
+        change: Option<&'change BasicFileChange>,
+        _path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
+        _cross_repo_push_source: CrossRepoPushSource,
+        _push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution, Error> {
+        change.expect("file is not deleted");
+        if self.blocking {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        Ok(HookExecution::Accepted)
+    }
+}
+
+#[fbinit::test]
+async fn test_testkit_third_party_hook(fb: FacebookInit) -> Result<(), Error> {
+    let kit = HookTestKit::new(fb)?;
+    let runs = kit.check_file_hook(&NoTrailingWhitespaceHook).await?;
+    let scenarios = runs.iter().map(|run| run.scenario).collect::<HashSet<_>>();
+    assert_eq!(
+        scenarios,
+        hashset! {
+            "empty file",
+            "huge file",
+            "non-UTF-8 file",
+            "deleted file",
+            "missing content",
+            "merge changeset",
+            "bypassed config",
+        }
+    );
+    for run in runs {
+        match run.scenario {
+            "missing content" => assert!(run.result.is_err()),
+            scenario => assert_eq!(run.result?, HookExecution::Accepted, "{}", scenario),
+        }
+    }
+    Ok(())
+}
+
+#[fbinit::test]
+async fn test_testkit_violations(fb: FacebookInit) -> Result<(), Error> {
+    let kit = HookTestKit::new(fb)?;
+    let violation = kit
+        .check_file_hook(&CarelessFileHook { blocking: false })
+        .await
+        .unwrap_err();
+    assert_eq!(violation.scenario, "deleted file");
+    assert!(
+        matches!(&violation.kind, ViolationKind::Panicked(message) if message == "file is not deleted"),
+        "{}",
+        violation
+    );
+
+    let kit = kit.with_max_poll(Duration::from_millis(10));
+    let violation = kit
+        .check_file_hook(&CarelessFileHook { blocking: true })
+        .await
+        .unwrap_err();
+    assert_eq!(violation.scenario, "empty file");
+    assert!(
+        matches!(violation.kind, ViolationKind::Blocked(_)),
+        "{}",
+        violation
+    );
+    Ok(())
+}
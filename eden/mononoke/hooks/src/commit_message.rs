@@ -0,0 +1,297 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Fields of commit messages, like `Summary:`, `Test Plan:` or
+//! `Differential Revision:`, so that hooks can check them without parsing
+//! messages themselves.
+
+/// Fields that start a section wherever they are in a message. Other
+/// `Key: value` lines are only fields in the trailers, which is the last
+/// paragraph of the message if it only contains such lines.
+const SECTION_FIELDS: &[&str] = &[
+    "Summary",
+    "Test Plan",
+    "Reviewers",
+    "Reviewed By",
+    "Subscribers",
+    "Tasks",
+    "Tags",
+    "Differential Revision",
+];
+
+/// Longest key of a `Key: value` line.
+const MAX_KEY_LEN: usize = 64;
+
+/// A commit message split into its title, description and fields.
+///
+/// A field runs from its `Key: value` line to the next field, so a field
+/// like `Test Plan:` can span several lines. A trailer continues on the
+/// following lines if they are indented, and these are joined with spaces.
+/// Keys are matched case-insensitively, and fields with an empty value are
+/// treated as missing.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParsedCommitMessage {
+    title: String,
+    description: String,
+    fields: Vec<(String, String)>,
+}
+
+impl ParsedCommitMessage {
+    pub fn parse(message: &str) -> Self {
+        let lines: Vec<&str> = message.lines().map(str::trim_end).collect();
+        let title = lines.first().map_or("", |line| line.trim()).to_string();
+        let body = lines.get(1..).unwrap_or_default();
+        let trailers_start = trailers_start(body);
+
+        let mut description = String::new();
+        let mut fields: Vec<(String, String)> = Vec::new();
+        for (index, line) in body.iter().enumerate() {
+            if matches!(trailers_start, Some(start) if index >= start) {
+                match split_field(line) {
+                    Some((key, value)) => fields.push((key.to_string(), value.to_string())),
+                    None => {
+                        // An indented continuation. The trailers start with
+                        // a field, so there is one to continue.
+                        if let Some((_, value)) = fields.last_mut() {
+                            value.push(' ');
+                            value.push_str(line.trim());
+                        }
+                    }
+                }
+                continue;
+            }
+            match split_field(line).filter(|(key, _)| is_section_field(key)) {
+                Some((key, value)) => fields.push((key.to_string(), value.to_string())),
+                None => {
+                    let text = match fields.last_mut() {
+                        Some((_, value)) => value,
+                        None => &mut description,
+                    };
+                    text.push('\n');
+                    text.push_str(line);
+                }
+            }
+        }
+
+        Self {
+            title,
+            description: trim_lines(&description).to_string(),
+            fields: fields
+                .into_iter()
+                .map(|(key, value)| (key, trim_lines(&value).to_string()))
+                .collect(),
+        }
+    }
+
+    /// The first line of the message.
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// The `Summary:` field, or else the text between the title and the
+    /// first field.
+    pub fn summary(&self) -> Option<&str> {
+        self.field("Summary")
+            .or_else(|| Some(self.description.as_str()).filter(|d| !d.is_empty()))
+    }
+
+    /// The `Test Plan:` field.
+    pub fn test_plan(&self) -> Option<&str> {
+        self.field("Test Plan")
+    }
+
+    /// The comma-separated names of the `Reviewed By:` field, or else of
+    /// the `Reviewers:` field.
+    pub fn reviewers(&self) -> Vec<&str> {
+        let reviewers = self
+            .field("Reviewed By")
+            .or_else(|| self.field("Reviewers"));
+        split_list(reviewers, |c| c == ',')
+    }
+
+    /// The tasks of the `Tasks:` field, separated by commas or spaces.
+    pub fn tasks(&self) -> Vec<&str> {
+        split_list(self.field("Tasks"), |c| c == ',' || c.is_whitespace())
+    }
+
+    /// The `Differential Revision:` field.
+    pub fn differential_revision(&self) -> Option<&str> {
+        self.field("Differential Revision")
+    }
+
+    /// The value of the first field named `name`, ignoring case.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(key, value)| key.eq_ignore_ascii_case(name) && !value.is_empty())
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// All fields as `(key, value)`, in the order of the message.
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+fn is_section_field(key: &str) -> bool {
+    SECTION_FIELDS
+        .iter()
+        .any(|field| field.eq_ignore_ascii_case(key))
+}
+
+/// Split a `Key: value` line. Keys start with a letter and contain letters,
+/// digits, spaces, `-` and `_`, so lines like `https://example.com` or
+/// `Note:this` are not fields.
+fn split_field(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.split_once(':')?;
+    let valid_key = key.len() <= MAX_KEY_LEN
+        && key.starts_with(|c: char| c.is_ascii_alphabetic())
+        && !key.ends_with(' ')
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '_'));
+    let valid_value = value.is_empty() || value.starts_with(char::is_whitespace);
+    (valid_key && valid_value).then_some((key, value.trim()))
+}
+
+/// Index of the first line of the trailers of `body`, if any. They are the
+/// last paragraph, if it starts with a field and its other lines are fields
+/// or indented continuations.
+fn trailers_start(body: &[&str]) -> Option<usize> {
+    let start = body.iter().rposition(|line| line.is_empty())? + 1;
+    let paragraph = &body[start..];
+    let (first, rest) = paragraph.split_first()?;
+    split_field(first)?;
+    rest.iter()
+        .all(|line| line.starts_with(char::is_whitespace) || split_field(line).is_some())
+        .then_some(start)
+}
+
+/// Remove blank lines at the start and whitespace at the end of `text`.
+fn trim_lines(text: &str) -> &str {
+    text.trim_start_matches('\n').trim_end()
+}
+
+fn split_list(list: Option<&str>, separator: impl Fn(char) -> bool) -> Vec<&str> {
+    list.map_or_else(Vec::new, |list| {
+        list.split(separator)
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_all_fields() {
+        let message = ParsedCommitMessage::parse(
+            "hooks: parse messages\n\
+             \n\
+             Summary: Add a parser.\n\
+             \n\
+             Test Plan: unit tests\n\
+             \n\
+             Reviewers: alice, bob\n\
+             \n\
+             Reviewed By: alice\n\
+             \n\
+             Tasks: T1, T2 T3\n\
+             \n\
+             Differential Revision: https://example.com/D42\n",
+        );
+        assert_eq!(message.title(), "hooks: parse messages");
+        assert_eq!(message.summary(), Some("Add a parser."));
+        assert_eq!(message.test_plan(), Some("unit tests"));
+        assert_eq!(message.reviewers(), ["alice"]);
+        assert_eq!(message.tasks(), ["T1", "T2", "T3"]);
+        assert_eq!(
+            message.differential_revision(),
+            Some("https://example.com/D42")
+        );
+        assert_eq!(message.field("test plan"), Some("unit tests"));
+        assert_eq!(message.fields().count(), 6);
+    }
+
+    #[test]
+    fn test_missing_fields() {
+        let message = ParsedCommitMessage::parse("fix typo\n\nIn the docs.\n\nTest Plan:\n");
+        assert_eq!(message.title(), "fix typo");
+        assert_eq!(message.summary(), Some("In the docs."));
+        assert_eq!(message.test_plan(), None);
+        assert!(message.reviewers().is_empty());
+        assert!(message.tasks().is_empty());
+        assert_eq!(message.differential_revision(), None);
+
+        let message = ParsedCommitMessage::parse("");
+        assert_eq!(message.title(), "");
+        assert_eq!(message.summary(), None);
+        assert_eq!(message.fields().count(), 0);
+    }
+
+    #[test]
+    fn test_multi_line_fields() {
+        let message = ParsedCommitMessage::parse(
+            "title\n\
+             \n\
+             Summary:\n\
+             First line.\n\
+             Note: not a field.\n\
+             \n\
+             Second paragraph.\n\
+             Test Plan:\n\
+             \x20 $ cargo test\n\
+             \x20 ok\n\
+             \n\
+             Signed-off-by: Alice\n\
+             \x20 <alice@example.com>\n\
+             Change-Id: I123\n",
+        );
+        assert_eq!(
+            message.summary(),
+            Some("First line.\nNote: not a field.\n\nSecond paragraph.")
+        );
+        assert_eq!(message.test_plan(), Some("  $ cargo test\n  ok"));
+        assert_eq!(
+            message.field("Signed-off-by"),
+            Some("Alice <alice@example.com>")
+        );
+        assert_eq!(message.field("Change-Id"), Some("I123"));
+        assert_eq!(message.field("Note"), None);
+    }
+
+    #[test]
+    fn test_malformed_trailers() {
+        let message = ParsedCommitMessage::parse(
+            "title\n\
+             \n\
+             See https://example.com/issue for details.\n\
+             \n\
+             Key: value\n\
+             this line is not a trailer\n",
+        );
+        // The last paragraph has a line that is not a field, so it is part
+        // of the description.
+        assert_eq!(message.field("Key"), None);
+        assert_eq!(
+            message.summary(),
+            Some(
+                "See https://example.com/issue for details.\n\n\
+                 Key: value\nthis line is not a trailer"
+            )
+        );
+
+        let message = ParsedCommitMessage::parse(
+            "title\n\nhttps://example.com\nNote:this\n: empty key\nBad key!: value\n",
+        );
+        assert_eq!(message.fields().count(), 0);
+    }
+}
@@ -7,6 +7,7 @@
 
 use std::collections::HashSet;
 
+use bookmarks::BookmarkKey;
 pub use mercurial_types::HgChangesetId;
 use metaconfig_types::BookmarkOrRegex;
 pub use mononoke_types::MPath;
@@ -16,6 +17,8 @@ use thiserror::Error;
 pub enum ErrorKind {
     #[error("No such hook '{0}'")]
     NoSuchHook(String),
+    #[error("Hook '{0}' is a bookmark hook, which does not run against changesets")]
+    NotAChangesetHook(String),
 
     #[error("Error while parsing hook '{0}'")]
     HookParseError(String),
@@ -38,4 +41,7 @@ pub enum ErrorKind {
 
     #[error("Disabled hook(s) do(es) not exist: {0:?}")]
     NoSuchHookToDisable(HashSet<String>),
+
+    #[error("{0} denied the push to bookmark {1}: {2}")]
+    PusherDenied(String, BookmarkKey, String),
 }
@@ -9,8 +9,10 @@
 
 use std::collections::HashSet;
 
+use anyhow::Context;
 use anyhow::Error;
 use fbinit::FacebookInit;
+use metaconfig_types::HookKind;
 use metaconfig_types::RepoConfig;
 use permission_checker::AclProvider;
 
@@ -19,17 +21,22 @@ use crate::errors::*;
 use crate::facebook::rust_hooks::hook_name_to_changeset_hook;
 #[cfg(fbcode_build)]
 use crate::facebook::rust_hooks::hook_name_to_file_hook;
+use crate::rust_hooks::hook_name_to_bookmark_hook;
 #[cfg(not(fbcode_build))]
 use crate::rust_hooks::hook_name_to_changeset_hook;
 #[cfg(not(fbcode_build))]
 use crate::rust_hooks::hook_name_to_file_hook;
+use crate::BookmarkHook;
 use crate::ChangesetHook;
+use crate::ConfigProblem;
 use crate::FileHook;
 use crate::HookManager;
+use crate::PusherGate;
 
 enum LoadedRustHook {
     ChangesetHook(Box<dyn ChangesetHook>),
     FileHook(Box<dyn FileHook>),
+    BookmarkHook(Box<dyn BookmarkHook>),
 }
 
 pub async fn load_hooks(
@@ -40,8 +47,8 @@ pub async fn load_hooks(
     disabled_hooks: &HashSet<String>,
 ) -> Result<(), Error> {
     let mut hooks_not_disabled = disabled_hooks.clone();
+    hook_manager.set_default_hook_config(config.hook_defaults.clone());
 
-    let mut hook_set = HashSet::new();
     for hook in config.hooks.clone() {
         use LoadedRustHook::*;
 
@@ -50,35 +57,43 @@ pub async fn load_hooks(
             continue;
         }
 
-        let rust_hook = {
-            if let Some(hook) = hook_name_to_changeset_hook(
+        // Hooks are created with the same config they are registered with.
+        let hook_config = hook_manager.effective_hook_config(hook.config);
+
+        // Hooks without a kind are looked up as changeset hooks, then as
+        // file hooks. Bookmark hooks must always be tagged as such.
+        let mut rust_hook = None;
+        if matches!(hook.kind, None | Some(HookKind::Changeset)) {
+            rust_hook = hook_name_to_changeset_hook(
                 fb,
                 &hook.name,
-                &hook.config,
+                &hook_config,
                 acl_provider,
                 hook_manager.get_reviewers_perm_checker(),
                 hook_manager.repo_name(),
             )
             .await?
-            {
-                ChangesetHook(hook)
-            } else if let Some(hook) = hook_name_to_file_hook(fb, &hook.name, &hook.config)? {
-                FileHook(hook)
-            } else {
-                return Err(ErrorKind::InvalidRustHook(hook.name.clone()).into());
-            }
-        };
+            .map(ChangesetHook);
+        }
+        if rust_hook.is_none() && matches!(hook.kind, None | Some(HookKind::File)) {
+            rust_hook = hook_name_to_file_hook(fb, &hook.name, &hook_config)?.map(FileHook);
+        }
+        if hook.kind == Some(HookKind::Bookmark) {
+            rust_hook = hook_name_to_bookmark_hook(fb, &hook.name, &hook_config)?.map(BookmarkHook);
+        }
+        let rust_hook = rust_hook.ok_or_else(|| ErrorKind::InvalidRustHook(hook.name.clone()))?;
 
         match rust_hook {
             FileHook(rust_hook) => {
-                hook_manager.register_file_hook(&hook.name, rust_hook, hook.config)
+                hook_manager.register_file_hook(&hook.name, rust_hook, hook_config)?
             }
             ChangesetHook(rust_hook) => {
-                hook_manager.register_changeset_hook(&hook.name, rust_hook, hook.config)
+                hook_manager.register_changeset_hook(&hook.name, rust_hook, hook_config)?
+            }
+            BookmarkHook(rust_hook) => {
+                hook_manager.register_bookmark_hook(&hook.name, rust_hook, hook_config)?
             }
         }
-
-        hook_set.insert(hook.name.clone());
     }
 
     if !hooks_not_disabled.is_empty() {
@@ -92,18 +107,31 @@ pub async fn load_hooks(
             .into_iter()
             .filter(|h| !disabled_hooks.contains(h))
             .collect();
-        let bm_hook_set: HashSet<String> = hooks.clone().into_iter().collect();
-        let diff: HashSet<_> = bm_hook_set.difference(&hook_set).collect();
-        if !diff.is_empty() {
-            return Err(ErrorKind::NoSuchBookmarkHook(
-                bookmark,
-                diff.into_iter().cloned().collect(),
-            )
-            .into());
-        } else {
-            hook_manager.set_hooks_for_bookmark(bookmark, hooks);
+        hook_manager.set_hooks_for_bookmark(bookmark.clone(), hooks);
+
+        if let Some(gate) = bookmark_hook.pusher_gate {
+            let gate = PusherGate::from_params(acl_provider, gate)
+                .await
+                .with_context(|| format!("while preparing pusher gate of {:?}", bookmark))?;
+            hook_manager.set_pusher_gate_for_bookmark(bookmark, gate);
         }
     }
 
+    // Report the hooks missing for the first bookmark bound to any.
+    let problems = hook_manager.validate();
+    if let Some(ConfigProblem::DanglingHookReference { bookmark, .. }) = problems.first() {
+        let hook_names = problems
+            .iter()
+            .filter_map(|problem| match problem {
+                ConfigProblem::DanglingHookReference {
+                    bookmark: problem_bookmark,
+                    hook_name,
+                } if problem_bookmark == bookmark => Some(hook_name.clone()),
+                _ => None,
+            })
+            .collect();
+        return Err(ErrorKind::NoSuchBookmarkHook(bookmark.clone(), hook_names).into());
+    }
+
     Ok(())
 }
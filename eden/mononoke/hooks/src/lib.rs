@@ -14,49 +14,92 @@
 
 #![cfg_attr(not(fbcode_build), allow(unused_crate_dependencies))]
 
+pub mod commit_message;
 pub mod errors;
 #[cfg(fbcode_build)]
 mod facebook;
 pub mod hook_loader;
+pub mod pusher_gate;
 mod rust_hooks;
+pub mod testkit;
 
+use std::any::Any;
 use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt;
 use std::hash::Hash;
+use std::hash::Hasher;
 use std::str;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
 
+use anyhow::Context;
 use anyhow::Error;
 use anyhow::Result;
 use async_trait::async_trait;
 use bookmarks::BookmarkKey;
 use bytes::Bytes;
 use context::CoreContext;
+use context::PerfCounterType;
+pub use commit_message::ParsedCommitMessage;
 pub use errors::*;
 use fbinit::FacebookInit;
+use futures::future;
+use futures::stream;
 use futures::stream::futures_unordered::FuturesUnordered;
+use futures::stream::BoxStream;
 use futures::stream::TryStreamExt;
 use futures::try_join;
 use futures::Future;
+use futures::Stream;
+use futures::StreamExt;
 use futures::TryFutureExt;
 use futures_stats::TimedFutureExt;
 pub use hooks_content_stores::FileContentManager;
 pub use hooks_content_stores::PathContent;
+pub use pusher_gate::PusherGate;
+pub use pusher_gate::PusherMatcher;
+use hooks_content_stores::PrefetchedFileContentManager;
+use hooks_content_stores::FILE_CONTENTS_CONCURRENCY;
+use itertools::Itertools;
 use metaconfig_types::BookmarkOrRegex;
+use metaconfig_types::ComparableRegex;
 use metaconfig_types::HookBypass;
 use metaconfig_types::HookConfig;
 use metaconfig_types::HookManagerParams;
+use metaconfig_types::PartialHookConfig;
 use mononoke_types::BasicFileChange;
 use mononoke_types::BonsaiChangeset;
 use mononoke_types::ChangesetId;
+use mononoke_types::ContentId;
 use mononoke_types::MPath;
 use permission_checker::AclProvider;
 use permission_checker::ArcMembershipChecker;
+use permission_checker::MononokeIdentitySet;
 use permission_checker::NeverMember;
 use regex::Regex;
 use scuba::builder::ServerData;
 use scuba_ext::MononokeScubaSampleBuilder;
 use slog::debug;
+use stats::prelude::*;
+
+define_stats! {
+    prefix = "mononoke.hooks";
+    executions: dynamic_timeseries("hook.{}.executions", (hook: String); Rate, Sum),
+    accepts: dynamic_timeseries("hook.{}.accepts", (hook: String); Rate, Sum),
+    rejects: dynamic_timeseries("hook.{}.rejects", (hook: String); Rate, Sum),
+    errors: dynamic_timeseries("hook.{}.errors", (hook: String); Rate, Sum),
+    skipped_binary: dynamic_timeseries("hook.{}.skipped_binary", (hook: String); Rate, Sum),
+    timeouts: dynamic_timeseries("hook.{}.timeouts", (hook: String); Rate, Sum),
+    duration_ms: dynamic_histogram("hook.{}.duration_ms", (hook: String); 10, 0, 1_000, Average, Sum, Count; P 50; P 90; P 99),
+    push_hooks_run: dynamic_timeseries("bookmark.{}.hooks_run", (bookmark: String); Rate, Sum),
+    push_duration_ms: dynamic_histogram("bookmark.{}.hooks_duration_ms", (bookmark: String); 100, 0, 10_000, Average, Sum, Count; P 50; P 90; P 99),
+}
 
 /// Manages hooks and allows them to be installed and uninstalled given a name
 /// Knows how to run hooks
@@ -65,16 +108,30 @@ use slog::debug;
 pub struct HookManager {
     repo_name: String,
     hooks: HashMap<String, Hook>,
+    /// Bumped whenever the outcomes of a hook kept in execution scopes must
+    /// not be reused anymore, see `purge_hook_cache`.
+    hook_generations: HashMap<String, u64>,
+    default_hook_config: PartialHookConfig,
     bookmark_hooks: HashMap<BookmarkKey, Vec<String>>,
     regex_hooks: Vec<(Regex, Vec<String>)>,
+    bookmark_run_modes: HashMap<BookmarkKey, HookRunMode>,
+    regex_run_modes: Vec<(Regex, HookRunMode)>,
+    bookmark_pusher_gates: HashMap<BookmarkKey, PusherGate>,
+    regex_pusher_gates: Vec<(Regex, PusherGate)>,
     content_manager: Box<dyn FileContentManager>,
     reviewers_membership: ArcMembershipChecker,
     admin_membership: ArcMembershipChecker,
     scuba: MononokeScubaSampleBuilder,
     all_hooks_bypassed: bool,
     scuba_bypassed_commits: MononokeScubaSampleBuilder,
+    binary_heuristic: BinaryHeuristic,
+    default_hook_timeout: Duration,
 }
 
+/// How long a hook may run on a changeset, a file or a bookmark move, unless
+/// configured otherwise.
+const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(600);
+
 impl HookManager {
     pub async fn new(
         fb: FacebookInit,
@@ -109,14 +166,24 @@ impl HookManager {
         Ok(HookManager {
             repo_name,
             hooks,
+            hook_generations: HashMap::new(),
+            default_hook_config: PartialHookConfig::default(),
             bookmark_hooks: HashMap::new(),
             regex_hooks: Vec::new(),
+            bookmark_run_modes: HashMap::new(),
+            regex_run_modes: Vec::new(),
+            bookmark_pusher_gates: HashMap::new(),
+            regex_pusher_gates: Vec::new(),
             content_manager,
             reviewers_membership: reviewers_membership.into(),
             admin_membership: admin_membership.into(),
             scuba,
             all_hooks_bypassed: hook_manager_params.all_hooks_bypassed,
             scuba_bypassed_commits,
+            binary_heuristic: BinaryHeuristic::default(),
+            default_hook_timeout: hook_manager_params
+                .default_hook_timeout_ms
+                .map_or(DEFAULT_HOOK_TIMEOUT, Duration::from_millis),
         })
     }
 
@@ -125,48 +192,188 @@ impl HookManager {
         Self {
             repo_name,
             hooks: HashMap::new(),
+            hook_generations: HashMap::new(),
+            default_hook_config: PartialHookConfig::default(),
             bookmark_hooks: HashMap::new(),
             regex_hooks: Vec::new(),
+            bookmark_run_modes: HashMap::new(),
+            regex_run_modes: Vec::new(),
+            bookmark_pusher_gates: HashMap::new(),
+            regex_pusher_gates: Vec::new(),
             content_manager,
             reviewers_membership: NeverMember::new().into(),
             admin_membership: NeverMember::new().into(),
             scuba: MononokeScubaSampleBuilder::with_discard(),
             all_hooks_bypassed: false,
             scuba_bypassed_commits: MononokeScubaSampleBuilder::with_discard(),
+            binary_heuristic: BinaryHeuristic::default(),
+            default_hook_timeout: DEFAULT_HOOK_TIMEOUT,
         }
     }
 
+    /// Set the defaults for the configs of the hooks registered from now on.
+    /// Hooks already registered keep their config until they are registered
+    /// again.
+    pub fn set_default_hook_config(&mut self, config: PartialHookConfig) {
+        self.default_hook_config = config;
+    }
+
+    /// Set how files are found to be binary, so that file hooks only
+    /// interested in text files are not run on them.
+    pub fn set_binary_heuristic(&mut self, heuristic: BinaryHeuristic) {
+        self.binary_heuristic = heuristic;
+    }
+
+    /// Set how long hooks without their own `HookConfig::timeout_ms` may run
+    /// on a changeset, a file or a bookmark move.
+    pub fn set_default_hook_timeout(&mut self, timeout: Duration) {
+        self.default_hook_timeout = timeout;
+    }
+
+    /// How long the hooks registered with `config` may run on a changeset, a
+    /// file or a bookmark move.
+    fn hook_timeout(&self, config: &HookConfig) -> Duration {
+        config
+            .timeout_ms
+            .map_or(self.default_hook_timeout, Duration::from_millis)
+    }
+
+    /// The config a hook registered with `config` gets: `config` with the
+    /// defaults set with `set_default_hook_config` applied.
+    pub fn effective_hook_config(&self, config: HookConfig) -> HookConfig {
+        self.default_hook_config.apply_to(config)
+    }
+
+    /// Register a changeset hook, preparing its state from `config` with the
+    /// default hook config applied.
+    /// Re-registering a hook under the same name replaces it and prepares its
+    /// state again.
     pub fn register_changeset_hook(
         &mut self,
         hook_name: &str,
         hook: Box<dyn ChangesetHook>,
         config: HookConfig,
-    ) {
-        self.hooks
-            .insert(hook_name.to_string(), Hook::from_changeset(hook, config));
+    ) -> Result<()> {
+        let hook = Hook::from_changeset(hook, self.effective_hook_config(config))
+            .with_context(|| format!("while preparing hook {}", hook_name))?;
+        self.hooks.insert(hook_name.to_string(), hook);
+        self.purge_hook_cache(hook_name);
+        Ok(())
     }
 
+    /// Register a file hook, preparing its state from `config` with the
+    /// default hook config applied.
+    /// Re-registering a hook under the same name replaces it and prepares its
+    /// state again.
     pub fn register_file_hook(
         &mut self,
         hook_name: &str,
         hook: Box<dyn FileHook>,
         config: HookConfig,
-    ) {
-        self.hooks
-            .insert(hook_name.to_string(), Hook::from_file(hook, config));
+    ) -> Result<()> {
+        let hook = Hook::from_file(hook, self.effective_hook_config(config))
+            .with_context(|| format!("while preparing hook {}", hook_name))?;
+        self.hooks.insert(hook_name.to_string(), hook);
+        self.purge_hook_cache(hook_name);
+        Ok(())
+    }
+
+    /// Register a bookmark hook, preparing its state from `config` with the
+    /// default hook config applied.
+    /// Re-registering a hook under the same name replaces it and prepares its
+    /// state again.
+    pub fn register_bookmark_hook(
+        &mut self,
+        hook_name: &str,
+        hook: Box<dyn BookmarkHook>,
+        config: HookConfig,
+    ) -> Result<()> {
+        let hook = Hook::from_bookmark(hook, self.effective_hook_config(config))
+            .with_context(|| format!("while preparing hook {}", hook_name))?;
+        self.hooks.insert(hook_name.to_string(), hook);
+        self.purge_hook_cache(hook_name);
+        Ok(())
+    }
+
+    /// Stop reusing the outcomes of `hook_name` kept in execution scopes, so
+    /// that it runs again even on changesets it already ran on in a scope.
+    /// Registering a hook does this too, as its code may have changed even if
+    /// its config didn't.
+    pub fn purge_hook_cache(&mut self, hook_name: &str) {
+        *self
+            .hook_generations
+            .entry(hook_name.to_string())
+            .or_default() += 1;
     }
 
     pub fn set_hooks_for_bookmark(&mut self, bookmark: BookmarkOrRegex, hooks: Vec<String>) {
+        self.set_hooks_for_bookmark_with_mode(bookmark, hooks, HookRunMode::RunAll)
+    }
+
+    /// Like `set_hooks_for_bookmark`, with the way the hooks bound to
+    /// `bookmark` run, see `HookRunMode`.
+    pub fn set_hooks_for_bookmark_with_mode(
+        &mut self,
+        bookmark: BookmarkOrRegex,
+        hooks: Vec<String>,
+        mode: HookRunMode,
+    ) {
         match bookmark {
             BookmarkOrRegex::Bookmark(bookmark) => {
+                self.bookmark_run_modes.insert(bookmark.clone(), mode);
                 self.bookmark_hooks.insert(bookmark, hooks);
             }
             BookmarkOrRegex::Regex(regex) => {
-                self.regex_hooks.push((regex.into_inner(), hooks));
+                let regex = regex.into_inner();
+                self.regex_run_modes.push((regex.clone(), mode));
+                self.regex_hooks.push((regex, hooks));
+            }
+        }
+    }
+
+    /// Bind `gate` to `bookmark`, replacing the gate bound to the same
+    /// bookmark. Gates bound to regexes are all kept.
+    pub fn set_pusher_gate_for_bookmark(&mut self, bookmark: BookmarkOrRegex, gate: PusherGate) {
+        match bookmark {
+            BookmarkOrRegex::Bookmark(bookmark) => {
+                self.bookmark_pusher_gates.insert(bookmark, gate);
+            }
+            BookmarkOrRegex::Regex(regex) => {
+                self.regex_pusher_gates.push((regex.into_inner(), gate));
             }
         }
     }
 
+    /// Check that every hook bound to a bookmark or bookmark regex is
+    /// registered. Running hooks for a bookmark bound to a missing hook fails,
+    /// so this allows rejecting such configuration up front.
+    pub fn validate(&self) -> Vec<ConfigProblem> {
+        let mut bookmark_hooks: Vec<_> = self.bookmark_hooks.iter().collect();
+        bookmark_hooks.sort_by_key(|(bookmark, _)| bookmark.as_str());
+        let bookmark_hooks = bookmark_hooks
+            .into_iter()
+            .map(|(bookmark, hooks)| (BookmarkOrRegex::Bookmark(bookmark.clone()), hooks));
+        let regex_hooks = self.regex_hooks.iter().map(|(regex, hooks)| {
+            (
+                BookmarkOrRegex::Regex(ComparableRegex::new(regex.clone())),
+                hooks,
+            )
+        });
+
+        let mut problems = Vec::new();
+        for (bookmark, hooks) in bookmark_hooks.chain(regex_hooks) {
+            for hook_name in hooks {
+                if !self.hooks.contains_key(hook_name) {
+                    problems.push(ConfigProblem::DanglingHookReference {
+                        bookmark: bookmark.clone(),
+                        hook_name: hook_name.clone(),
+                    });
+                }
+            }
+        }
+        problems
+    }
+
     pub(crate) fn get_reviewers_perm_checker(&self) -> ArcMembershipChecker {
         self.reviewers_membership.clone()
     }
@@ -175,8 +382,11 @@ impl HookManager {
         self.admin_membership.clone()
     }
 
+    /// Whether hooks or pusher gates are bound to `bookmark`.
     pub fn hooks_exist_for_bookmark(&self, bookmark: &BookmarkKey) -> bool {
-        if self.bookmark_hooks.contains_key(bookmark) {
+        if self.bookmark_hooks.contains_key(bookmark)
+            || self.bookmark_pusher_gates.contains_key(bookmark)
+        {
             return true;
         }
 
@@ -184,29 +394,114 @@ impl HookManager {
         self.regex_hooks
             .iter()
             .any(|(regex, _)| regex.is_match(bookmark))
+            || self
+                .regex_pusher_gates
+                .iter()
+                .any(|(regex, _)| regex.is_match(bookmark))
     }
 
     pub fn repo_name(&self) -> &String {
         &self.repo_name
     }
 
-    fn hooks_for_bookmark<'a>(
+    /// The hooks bound to `bookmark`: the hooks bound to the bookmark itself
+    /// in the order they were set, then the hooks of each matching regex, in
+    /// the order the regexes were set. A hook bound more than once is only
+    /// returned at its first position.
+    fn hooks_for_bookmark<'a>(&'a self, bookmark: &BookmarkKey) -> Vec<&'a str> {
+        let exact_hooks = self.bookmark_hooks.get(bookmark).into_iter().flatten();
+
+        let bookmark_str = bookmark.to_string();
+        let regex_hooks = self
+            .regex_hooks
+            .iter()
+            .filter(|(regex, _)| regex.is_match(&bookmark_str))
+            .flat_map(|(_, hooks)| hooks);
+
+        let mut seen = HashSet::new();
+        exact_hooks
+            .chain(regex_hooks)
+            .map(|hook| hook.as_str())
+            .filter(|hook| seen.insert(*hook))
+            .collect()
+    }
+
+    /// How the hooks bound to `bookmark` run: `HookRunMode::FailFast` if the
+    /// bookmark itself or any matching regex was bound with it.
+    pub fn run_mode_for_bookmark(&self, bookmark: &BookmarkKey) -> HookRunMode {
+        let bookmark_str = bookmark.as_str();
+        let fail_fast = self
+            .bookmark_run_modes
+            .get(bookmark)
+            .into_iter()
+            .chain(
+                self.regex_run_modes
+                    .iter()
+                    .filter(|(regex, _)| regex.is_match(bookmark_str))
+                    .map(|(_, mode)| mode),
+            )
+            .any(|mode| *mode == HookRunMode::FailFast);
+        if fail_fast {
+            HookRunMode::FailFast
+        } else {
+            HookRunMode::RunAll
+        }
+    }
+
+    /// The registered hooks bound to `bookmark`, in the order of
+    /// `hooks_for_bookmark`, with the config they run with.
+    pub fn effective_hooks_for_bookmark<'a>(
         &'a self,
         bookmark: &BookmarkKey,
-    ) -> impl Iterator<Item = &'a str> + Clone {
-        let mut hooks: Vec<&'a str> = match self.bookmark_hooks.get(bookmark) {
-            Some(hooks) => hooks.iter().map(|a| a.as_str()).collect(),
-            None => Vec::new(),
-        };
+    ) -> Vec<(&'a str, &'a HookConfig)> {
+        self.hooks_for_bookmark(bookmark)
+            .into_iter()
+            .filter_map(|name| Some((name, self.hooks.get(name)?.get_config())))
+            .collect()
+    }
 
-        let bookmark_str = bookmark.to_string();
-        for (regex, r_hooks) in &self.regex_hooks {
-            if regex.is_match(&bookmark_str) {
-                hooks.extend(r_hooks.iter().map(|a| a.as_str()));
+    /// The pusher gates bound to `bookmark`: the gate bound to the bookmark
+    /// itself, then the gates of each matching regex, in the order they were
+    /// set. All of them are checked before hooks run.
+    pub fn pusher_gates_for_bookmark<'a>(&'a self, bookmark: &BookmarkKey) -> Vec<&'a PusherGate> {
+        let bookmark_str = bookmark.as_str();
+        self.bookmark_pusher_gates
+            .get(bookmark)
+            .into_iter()
+            .chain(
+                self.regex_pusher_gates
+                    .iter()
+                    .filter(|(regex, _)| regex.is_match(bookmark_str))
+                    .map(|(_, gate)| gate),
+            )
+            .collect()
+    }
+
+    /// Check `pusher` against the pusher gates bound to `bookmark`, returning
+    /// the rejection of the first gate denying them, with the name of the
+    /// hook it is attributed to. Gates can only be bypassed with pushvars.
+    async fn check_pusher_gates(
+        &self,
+        ctx: &CoreContext,
+        bookmark: &BookmarkKey,
+        pusher: &MononokeIdentitySet,
+        maybe_pushvars: Option<&HashMap<String, Bytes>>,
+    ) -> Option<(String, HookRejectionInfo)> {
+        for gate in self.pusher_gates_for_bookmark(bookmark) {
+            if let Some(bypass_reason) =
+                get_bypass_reason(gate.bypass.as_ref(), "", maybe_pushvars)
+            {
+                debug!(
+                    ctx.logger(),
+                    "Pusher gate {} bypassed: {}", gate.name, bypass_reason
+                );
+                continue;
+            }
+            if let Some(rejection) = gate.check(pusher).await {
+                return Some((gate.hook_name(), rejection));
             }
         }
-
-        hooks.into_iter()
+        None
     }
 
     pub fn all_hooks_bypassed(&self) -> bool {
@@ -217,20 +512,175 @@ impl HookManager {
         &self.scuba_bypassed_commits
     }
 
+    /// Run the changeset and file hooks bound to `bookmark` against
+    /// `changesets`.
+    ///
+    /// The outcomes are in a stable order, whatever order the hooks complete
+    /// in: grouped by changeset, in the order of `changesets`, each group has
+    /// the changeset hook outcomes in hook order, followed by the file hook
+    /// outcomes ordered by path, then hook order. Hooks are in the order of
+    /// the bookmark's own hooks, followed by the hooks of matching regexes.
+    ///
+    /// The pusher gates bound to `bookmark` are checked against `pusher`
+    /// first. If one denies them, no hook runs and each changeset gets a
+    /// single rejection attributed to the gate. Without changesets, the
+    /// denial is returned as `ErrorKind::PusherDenied` instead.
+    ///
+    /// If `bookmark` runs its hooks with `HookRunMode::FailFast`, hooks run
+    /// one at a time, changeset by changeset in hook order, each file hook
+    /// on the files in path order, and stop at the first rejection. The
+    /// outcomes are then those of the hooks that ran, the rejection
+    /// included, in the same order as above.
     pub async fn run_hooks_for_bookmark(
         &self,
         ctx: &CoreContext,
         changesets: impl Iterator<Item = &BonsaiChangeset> + Clone + itertools::Itertools,
         bookmark: &BookmarkKey,
         maybe_pushvars: Option<&HashMap<String, Bytes>>,
+        pusher: &MononokeIdentitySet,
+        cross_repo_push_source: CrossRepoPushSource,
+        push_authored_by: PushAuthoredBy,
+    ) -> Result<Vec<HookOutcome>, Error> {
+        self.run_hooks_for_bookmark_in_scope(
+            ctx,
+            &HookExecutionScope::new(),
+            changesets,
+            bookmark,
+            maybe_pushvars,
+            pusher,
+            cross_repo_push_source,
+            push_authored_by,
+        )
+        .await
+    }
+
+    /// Like `run_hooks_for_bookmark`, but hooks that already ran against a
+    /// changeset in `scope` are not run again: their outcomes are reused.
+    ///
+    /// Requests moving several bookmarks to the same changesets should run
+    /// the hooks of every bookmark in one scope, so that hooks bound to
+    /// more than one of them run once per changeset. The outcomes are still
+    /// those of all the hooks bound to `bookmark`, in the same order.
+    pub async fn run_hooks_for_bookmark_in_scope(
+        &self,
+        ctx: &CoreContext,
+        scope: &HookExecutionScope,
+        changesets: impl Iterator<Item = &BonsaiChangeset> + Clone + itertools::Itertools,
+        bookmark: &BookmarkKey,
+        maybe_pushvars: Option<&HashMap<String, Bytes>>,
+        pusher: &MononokeIdentitySet,
         cross_repo_push_source: CrossRepoPushSource,
         push_authored_by: PushAuthoredBy,
     ) -> Result<Vec<HookOutcome>, Error> {
         debug!(ctx.logger(), "Running hooks for bookmark {:?}", bookmark);
 
+        if let Some((hook_name, rejection)) = self
+            .check_pusher_gates(ctx, bookmark, pusher, maybe_pushvars)
+            .await
+        {
+            let outcomes: Vec<_> = changesets
+                .map(|cs| {
+                    HookOutcome::ChangesetHook(
+                        ChangesetHookExecutionID {
+                            cs_id: cs.get_changeset_id(),
+                            hook_name: hook_name.clone(),
+                        },
+                        HookExecution::Rejected(rejection.clone()),
+                    )
+                })
+                .collect();
+            if outcomes.is_empty() {
+                // There is no changeset to attribute the rejection to, but
+                // the pusher must still be denied.
+                return Err(ErrorKind::PusherDenied(
+                    hook_name,
+                    bookmark.clone(),
+                    rejection.long_description,
+                )
+                .into());
+            }
+            return Ok(outcomes);
+        }
+
         let hooks = self.hooks_for_bookmark(bookmark);
+        let config_digests: Vec<_> = hooks
+            .iter()
+            .map(|hook_name| {
+                self.hooks
+                    .get(*hook_name)
+                    .map_or(0, |hook| hook_config_digest(hook.get_config()))
+            })
+            .collect();
+        let generations: Vec<_> = hooks
+            .iter()
+            .map(|hook_name| self.hook_generations.get(*hook_name).copied().unwrap_or(0))
+            .collect();
+        // File hooks don't see the bookmark, changeset hooks do unless they
+        // declare otherwise.
+        let bookmark_independent: Vec<_> = hooks
+            .iter()
+            .map(|hook_name| match self.hooks.get(*hook_name) {
+                Some(Hook::File(..)) => true,
+                Some(Hook::Changeset(hook, ..)) => hook.bookmark_independent(),
+                _ => false,
+            })
+            .collect();
+        let execution_key = |cs: &BonsaiChangeset, hook_index: usize| ExecutionKey {
+            cs_id: cs.get_changeset_id(),
+            hook_name: hooks[hook_index].to_string(),
+            generation: generations[hook_index],
+            config_digest: config_digests[hook_index],
+            bookmark: (!bookmark_independent[hook_index]).then(|| bookmark.clone()),
+            cross_repo_push_source,
+            push_authored_by,
+        };
+
+        // Fetch the file data needed by file hooks up front, one batch per
+        // changeset, rather than once per file and hook. Binary files are
+        // found once per changeset too, if any file hook only wants text.
+        // Hooks whose outcomes are reused from the scope need nothing.
+        let content_managers = future::join_all(changesets.clone().map(|cs| {
+            let file_hooks: Vec<_> = hooks
+                .iter()
+                .enumerate()
+                .filter_map(|(hook_index, hook_name)| match self.hooks.get(*hook_name) {
+                    Some(Hook::File(hook, config, _))
+                        if get_bypass_reason(
+                            config.bypass.as_ref(),
+                            cs.message(),
+                            maybe_pushvars,
+                        )
+                        .is_none()
+                            && !scope.contains(&execution_key(cs, hook_index)) =>
+                    {
+                        Some(hook)
+                    }
+                    _ => None,
+                })
+                .collect();
+            let prefetch = file_hooks
+                .iter()
+                .map(|hook| hook.content_prefetch())
+                .max()
+                .unwrap_or(ContentPrefetch::Nothing);
+            let text_only = file_hooks
+                .iter()
+                .any(|hook| hook.content_interest() == ContentInterest::TextOnly);
+            async move {
+                let content_manager = self.prefetch_file_contents(ctx, cs, prefetch).await;
+                let binary_contents = if text_only {
+                    self.find_binary_contents(ctx, &content_manager, cs).await
+                } else {
+                    HashSet::new()
+                };
+                (content_manager, binary_contents)
+            }
+        }))
+        .await;
 
-        let futs = FuturesUnordered::new();
+        let mut futs = Vec::new();
+        // Keys of the hook executions that are not in the scope yet.
+        let mut executed = Vec::new();
 
         let mut scuba = self.scuba.clone();
         let username = ctx.metadata().unix_name();
@@ -240,12 +690,20 @@ impl HookManager {
             scuba.add("user", user);
         }
 
-        for (cs, hook_name) in changesets.cartesian_product(hooks) {
+        for ((cs_index, cs), (hook_index, hook_name)) in changesets
+            .enumerate()
+            .cartesian_product(hooks.iter().copied().enumerate())
+        {
             let hook = self
                 .hooks
                 .get(hook_name)
                 .ok_or_else(|| ErrorKind::NoSuchHook(hook_name.to_string()))?;
 
+            if let Hook::Bookmark(..) = hook {
+                // Bookmark hooks are run by `run_bookmark_hooks`.
+                continue;
+            }
+
             let mut scuba = scuba.clone();
             scuba.add("hook", hook_name.to_string());
             scuba.add("hash", cs.get_changeset_id().to_string());
@@ -260,20 +718,513 @@ impl HookManager {
                 continue;
             }
 
-            for future in hook.get_futures(
+            let key = execution_key(cs, hook_index);
+            if let Some(outcomes) = scope.get(&key) {
+                for outcome in outcomes {
+                    let path = outcome.get_file_path().cloned().map(Cow::Owned);
+                    futs.push((
+                        (cs_index, path, hook_index),
+                        None,
+                        future::Either::Left(future::ok::<_, Error>(outcome)),
+                    ));
+                }
+                continue;
+            }
+            executed.push(key);
+
+            let (content_manager, binary_contents) = &content_managers[cs_index];
+            for (path, future) in hook.get_futures(
                 ctx,
                 bookmark,
-                &*self.content_manager,
+                content_manager,
+                binary_contents,
                 hook_name,
                 cs,
                 scuba,
                 cross_repo_push_source,
                 push_authored_by,
+                self.hook_timeout(hook.get_config()),
             ) {
-                futs.push(future);
+                // Changeset hooks have no path, so they sort before file hooks.
+                futs.push((
+                    (cs_index, path.map(Cow::Borrowed), hook_index),
+                    Some(executed.len() - 1),
+                    future::Either::Right(future),
+                ));
+            }
+        }
+        let run_mode = self.run_mode_for_bookmark(bookmark);
+        match run_mode {
+            HookRunMode::RunAll => futs.sort_by(|(a, ..), (b, ..)| a.cmp(b)),
+            // The order the hooks run in.
+            HookRunMode::FailFast => futs.sort_by(
+                |((a_cs, a_path, a_hook), ..), ((b_cs, b_path, b_hook), ..)| {
+                    (a_cs, a_hook, a_path).cmp(&(b_cs, b_hook, b_path))
+                },
+            ),
+        }
+        let mut order: Vec<_> = futs.iter().map(|(order, ..)| order.clone()).collect();
+        let executions: Vec<_> = futs.iter().map(|(_, execution, _)| *execution).collect();
+        let futs = futs.into_iter().map(|(.., fut)| fut);
+        let (stats, outcomes) = match run_mode {
+            HookRunMode::RunAll => try_collect_in_order(futs).timed().await,
+            HookRunMode::FailFast => {
+                try_collect_until_rejected(futs, HookOutcome::is_rejection)
+                    .timed()
+                    .await
+            }
+        };
+        let ran = outcomes.as_ref().map_or(executions.len(), Vec::len);
+        let hooks_run = executions[..ran].iter().flatten().count();
+        record_hooks_run(ctx, bookmark, hooks_run, stats.completion_time);
+        let mut outcomes = outcomes?;
+        let stopped = outcomes.len() < executions.len();
+
+        // Only executions that ran to completion are kept in the scope: a
+        // file hook that was stopped before running on all files, or that
+        // has no files and wasn't reached, will run again.
+        let mut executed_outcomes = vec![Vec::new(); executed.len()];
+        let mut pending = vec![0; executed.len()];
+        for execution in executions.iter().flatten() {
+            pending[*execution] += 1;
+        }
+        for (outcome, execution) in outcomes.iter().zip(&executions) {
+            if let Some(execution) = execution {
+                executed_outcomes[*execution].push(outcome.clone());
+                pending[*execution] -= 1;
+            }
+        }
+        scope.insert(
+            executed
+                .into_iter()
+                .zip(executed_outcomes)
+                .zip(pending)
+                .filter(|((_, outcomes), pending)| {
+                    *pending == 0 && !(stopped && outcomes.is_empty())
+                })
+                .map(|(execution, _)| execution),
+        );
+
+        if run_mode == HookRunMode::FailFast {
+            order.truncate(outcomes.len());
+            let mut ordered: Vec<_> = order.into_iter().zip(outcomes).collect();
+            ordered.sort_by(|(a, _), (b, _)| a.cmp(b));
+            outcomes = ordered.into_iter().map(|(_, outcome)| outcome).collect();
+        }
+        Ok(outcomes)
+    }
+
+    /// Run the changeset and file hooks named `hook_names` against
+    /// `changesets`, to audit commits that already landed.
+    ///
+    /// The hooks run as they would for a push to `bookmark`, whatever
+    /// bookmarks they are bound to, and can't be bypassed: audits want their
+    /// verdict. Nothing is logged to scuba, so audits don't look like pushes.
+    ///
+    /// Up to `concurrency` changesets are checked at a time. The outcomes of
+    /// each changeset are yielded as soon as they are ready, in the order of
+    /// `changesets`, and are ordered as in `run_hooks_for_bookmark`. If the
+    /// hooks fail to run on a changeset, its error is yielded and the audit
+    /// goes on.
+    pub fn run_hooks_for_changesets<'a>(
+        &'a self,
+        ctx: &'a CoreContext,
+        changesets: impl Stream<Item = BonsaiChangeset> + Send + 'a,
+        bookmark: &'a BookmarkKey,
+        hook_names: &[String],
+        concurrency: usize,
+    ) -> Result<BoxStream<'a, (ChangesetId, Result<Vec<HookOutcome>, Error>)>, Error> {
+        let mut hooks = Vec::new();
+        for hook_name in hook_names {
+            let (hook_name, hook) = self
+                .hooks
+                .get_key_value(hook_name)
+                .ok_or_else(|| ErrorKind::NoSuchHook(hook_name.clone()))?;
+            if let Hook::Bookmark(..) = hook {
+                return Err(ErrorKind::NotAChangesetHook(hook_name.clone()).into());
             }
+            hooks.push((hook_name.as_str(), hook));
+        }
+        let hooks = Arc::new(hooks);
+
+        Ok(changesets
+            .map(move |cs| {
+                let hooks = hooks.clone();
+                async move {
+                    let outcomes = self.audit_changeset(ctx, bookmark, &hooks, &cs).await;
+                    (cs.get_changeset_id(), outcomes)
+                }
+            })
+            .buffered(concurrency.max(1))
+            .boxed())
+    }
+
+    /// Run `hooks` against `cs`, without bypasses, for
+    /// `run_hooks_for_changesets`.
+    async fn audit_changeset(
+        &self,
+        ctx: &CoreContext,
+        bookmark: &BookmarkKey,
+        hooks: &[(&str, &Hook)],
+        cs: &BonsaiChangeset,
+    ) -> Result<Vec<HookOutcome>, Error> {
+        let file_hooks: Vec<_> = hooks
+            .iter()
+            .filter_map(|(_, hook)| match hook {
+                Hook::File(hook, ..) => Some(hook),
+                _ => None,
+            })
+            .collect();
+        let prefetch = file_hooks
+            .iter()
+            .map(|hook| hook.content_prefetch())
+            .max()
+            .unwrap_or(ContentPrefetch::Nothing);
+        let content_manager = self.prefetch_file_contents(ctx, cs, prefetch).await;
+        let binary_contents = if file_hooks
+            .iter()
+            .any(|hook| hook.content_interest() == ContentInterest::TextOnly)
+        {
+            self.find_binary_contents(ctx, &content_manager, cs).await
+        } else {
+            HashSet::new()
+        };
+
+        let mut futs = Vec::new();
+        for (hook_index, (hook_name, hook)) in hooks.iter().enumerate() {
+            for (path, future) in hook.get_futures(
+                ctx,
+                bookmark,
+                &content_manager,
+                &binary_contents,
+                hook_name,
+                cs,
+                MononokeScubaSampleBuilder::with_discard(),
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+                self.hook_timeout(hook.get_config()),
+            ) {
+                futs.push(((path, hook_index), future));
+            }
+        }
+        futs.sort_by(|(a, _), (b, _)| a.cmp(b));
+        try_collect_in_order(futs.into_iter().map(|(_, fut)| fut))
+            .await
+            .with_context(|| format!("while auditing changeset {}", cs.get_changeset_id()))
+    }
+
+    /// Wrap the content manager with the data `prefetch` of the files
+    /// changed by `cs`, fetched in one batch.
+    async fn prefetch_file_contents(
+        &self,
+        ctx: &CoreContext,
+        cs: &BonsaiChangeset,
+        prefetch: ContentPrefetch,
+    ) -> PrefetchedFileContentManager<'_> {
+        let content_manager = &*self.content_manager;
+        if prefetch == ContentPrefetch::Nothing {
+            return PrefetchedFileContentManager::empty(content_manager);
+        }
+        let ids: HashSet<_> = cs
+            .simplified_file_changes()
+            .filter_map(|(_path, change)| Some(change?.content_id()))
+            .collect();
+        let prefetched = PrefetchedFileContentManager::prefetch(
+            ctx,
+            content_manager,
+            ids.into_iter().collect(),
+            prefetch == ContentPrefetch::Text,
+        )
+        .await;
+        match prefetched {
+            Ok(prefetched) => prefetched,
+            Err(e) => {
+                // Hooks look up what they need on their own, and fail with
+                // an error attributed to them if it's really missing.
+                debug!(ctx.logger(), "Failed to prefetch file contents: {:?}", e);
+                PrefetchedFileContentManager::empty(content_manager)
+            }
+        }
+    }
+
+    /// The contents of the files changed by `cs` that are binary according
+    /// to the binary heuristic. Only the start of each content is fetched,
+    /// unless it was prefetched. Contents that fail to be fetched are not
+    /// considered binary, so that hooks still run on them.
+    async fn find_binary_contents(
+        &self,
+        ctx: &CoreContext,
+        content_manager: &dyn FileContentManager,
+        cs: &BonsaiChangeset,
+    ) -> HashSet<ContentId> {
+        let prefix_len = match self.binary_heuristic {
+            BinaryHeuristic::Disabled => return HashSet::new(),
+            BinaryHeuristic::NulByte { prefix_len } => prefix_len,
+        };
+        let ids: HashSet<_> = cs
+            .simplified_file_changes()
+            .filter_map(|(_path, change)| Some(change?.content_id()))
+            .collect();
+        stream::iter(ids)
+            .map(|id| async move {
+                match content_manager.get_file_prefix(ctx, id, prefix_len).await {
+                    Ok(prefix) => prefix.filter(|prefix| prefix.contains(&0)).map(|_| id),
+                    Err(e) => {
+                        debug!(ctx.logger(), "Failed to fetch the start of {}: {:?}", id, e);
+                        None
+                    }
+                }
+            })
+            .buffer_unordered(FILE_CONTENTS_CONCURRENCY)
+            .filter_map(future::ready)
+            .collect()
+            .await
+    }
+
+    /// Run the bookmark hooks bound to the bookmark in `data` against the
+    /// bookmark operation it describes. Changeset and file hooks bound to the
+    /// bookmark are not run; use `run_hooks_for_bookmark` for those.
+    ///
+    /// The outcomes are in hook order, as for `run_hooks_for_bookmark`,
+    /// whatever order the hooks complete in. If a pusher gate denies the
+    /// pusher of `data`, the only outcome is its rejection. With
+    /// `HookRunMode::FailFast`, the hooks run one at a time and stop at the
+    /// first rejection.
+    pub async fn run_bookmark_hooks(
+        &self,
+        ctx: &CoreContext,
+        data: &BookmarkHookData,
+        maybe_pushvars: Option<&HashMap<String, Bytes>>,
+    ) -> Result<Vec<BookmarkHookOutcome>, Error> {
+        debug!(
+            ctx.logger(),
+            "Running bookmark hooks for bookmark {:?}", data.bookmark
+        );
+
+        if let Some((hook_name, rejection)) = self
+            .check_pusher_gates(ctx, &data.bookmark, &data.pusher, maybe_pushvars)
+            .await
+        {
+            return Ok(vec![BookmarkHookOutcome {
+                id: BookmarkHookExecutionID {
+                    bookmark: data.bookmark.clone(),
+                    hook_name,
+                },
+                execution: HookExecution::Rejected(rejection),
+            }]);
+        }
+
+        let mut futs = Vec::new();
+
+        let mut scuba = self.scuba.clone();
+        let username = ctx.metadata().unix_name();
+        let user_option = ctx.metadata().client_hostname().or(username);
+
+        if let Some(user) = user_option {
+            scuba.add("user", user);
+        }
+
+        for hook_name in self.hooks_for_bookmark(&data.bookmark) {
+            let hook = self
+                .hooks
+                .get(hook_name)
+                .ok_or_else(|| ErrorKind::NoSuchHook(hook_name.to_string()))?;
+
+            let (hook, config, prepared) = match hook {
+                Hook::Bookmark(hook, config, prepared) => (hook, config, prepared),
+                Hook::Changeset(..) | Hook::File(..) => continue,
+            };
+
+            let mut scuba = scuba.clone();
+            scuba.add("hook", hook_name.to_string());
+            scuba.add("bookmark", data.bookmark.to_string());
+
+            // There is no commit message to look for a bypass string in, so
+            // only pushvars can bypass a bookmark hook.
+            if let Some(bypass_reason) =
+                get_bypass_reason(config.bypass.as_ref(), "", maybe_pushvars)
+            {
+                scuba.add("bypass_reason", bypass_reason);
+                scuba.log();
+                continue;
+            }
+
+            let timeout = self.hook_timeout(config);
+            futs.push(async move {
+                let run = hook.run_prepared(prepared, ctx, data);
+                let (stats, result) = with_timeout(timeout, hook_name, run).timed().await;
+                record_hook_execution(ctx, hook_name, stats.completion_time, result.as_ref());
+                log_hook_execution(scuba, stats.completion_time, result.as_ref());
+                let execution =
+                    result.map_err(|e| e.context(format!("while executing hook {}", hook_name)))?;
+                Ok::<_, Error>(BookmarkHookOutcome {
+                    id: BookmarkHookExecutionID {
+                        bookmark: data.bookmark.clone(),
+                        hook_name: hook_name.to_string(),
+                    },
+                    execution,
+                })
+            });
+        }
+        let hooks_bound = futs.len();
+        let (stats, outcomes) = match self.run_mode_for_bookmark(&data.bookmark) {
+            HookRunMode::RunAll => try_collect_in_order(futs).timed().await,
+            HookRunMode::FailFast => {
+                try_collect_until_rejected(futs, BookmarkHookOutcome::is_rejection)
+                    .timed()
+                    .await
+            }
+        };
+        let hooks_run = outcomes.as_ref().map_or(hooks_bound, Vec::len);
+        record_hooks_run(ctx, &data.bookmark, hooks_run, stats.completion_time);
+        outcomes
+    }
+}
+
+/// Run `futs` concurrently, returning their outputs in the order of `futs`
+/// rather than the order they complete in.
+async fn try_collect_in_order<T>(
+    futs: impl IntoIterator<Item = impl Future<Output = Result<T, Error>>>,
+) -> Result<Vec<T>, Error> {
+    let futs: FuturesUnordered<_> = futs
+        .into_iter()
+        .enumerate()
+        .map(|(index, fut)| fut.map_ok(move |output| (index, output)))
+        .collect();
+    let mut outputs: Vec<Option<T>> = std::iter::repeat_with(|| None).take(futs.len()).collect();
+    futs.try_for_each(|(index, output)| {
+        outputs[index] = Some(output);
+        future::ready(Ok(()))
+    })
+    .await?;
+    Ok(outputs.into_iter().flatten().collect())
+}
+
+/// Run `futs` one at a time, in order, until the output of one of them is a
+/// rejection according to `is_rejection`. Returns the outputs of the futures
+/// that ran, the rejection included.
+async fn try_collect_until_rejected<T>(
+    futs: impl IntoIterator<Item = impl Future<Output = Result<T, Error>>>,
+    is_rejection: impl Fn(&T) -> bool,
+) -> Result<Vec<T>, Error> {
+    let mut outputs = Vec::new();
+    for fut in futs {
+        let output = fut.await?;
+        let rejected = is_rejection(&output);
+        outputs.push(output);
+        if rejected {
+            break;
+        }
+    }
+    Ok(outputs)
+}
+
+/// The hook executions of one request, such as a push moving several
+/// bookmarks, for `HookManager::run_hooks_for_bookmark_in_scope`.
+///
+/// The outcomes of a hook against a changeset are kept for the lifetime of
+/// the scope, keyed by changeset, hook name, and the config of the hook,
+/// bypasses aside, as well as the push source and author. They are not
+/// reused once the hook is registered again or its cache purged with
+/// `HookManager::purge_hook_cache`. Outcomes of changeset hooks are only
+/// reused for other bookmarks if the hooks are
+/// `ChangesetHook::bookmark_independent`. Failed executions are not
+/// kept. Executions racing in concurrent runs of the same scope may both
+/// run.
+#[derive(Default)]
+pub struct HookExecutionScope {
+    executions: Mutex<HashMap<ExecutionKey, Vec<HookOutcome>>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct ExecutionKey {
+    cs_id: ChangesetId,
+    hook_name: String,
+    generation: u64,
+    config_digest: u64,
+    /// The bookmark, for hooks whose outcomes may depend on it.
+    bookmark: Option<BookmarkKey>,
+    cross_repo_push_source: CrossRepoPushSource,
+    push_authored_by: PushAuthoredBy,
+}
+
+impl HookExecutionScope {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn contains(&self, key: &ExecutionKey) -> bool {
+        self.executions
+            .lock()
+            .expect("lock poisoned")
+            .contains_key(key)
+    }
+
+    fn get(&self, key: &ExecutionKey) -> Option<Vec<HookOutcome>> {
+        self.executions
+            .lock()
+            .expect("lock poisoned")
+            .get(key)
+            .cloned()
+    }
+
+    fn insert(&self, executions: impl IntoIterator<Item = (ExecutionKey, Vec<HookOutcome>)>) {
+        self.executions
+            .lock()
+            .expect("lock poisoned")
+            .extend(executions);
+    }
+}
+
+/// Digest of the parts of `config` hooks read, which is the same for equal
+/// configs. Bypasses are left out: they are checked before hooks run.
+fn hook_config_digest(config: &HookConfig) -> u64 {
+    fn sorted<K: Ord, V>(map: &HashMap<K, V>) -> BTreeMap<&K, &V> {
+        map.iter().collect()
+    }
+    let mut hasher = DefaultHasher::new();
+    sorted(&config.strings).hash(&mut hasher);
+    sorted(&config.ints).hash(&mut hasher);
+    sorted(&config.ints_64).hash(&mut hasher);
+    sorted(&config.string_lists).hash(&mut hasher);
+    sorted(&config.int_lists).hash(&mut hasher);
+    sorted(&config.int_64_lists).hash(&mut hasher);
+    config.timeout_ms.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A problem with the hooks configured in a `HookManager`, as found by
+/// `HookManager::validate`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigProblem {
+    /// A bookmark or bookmark regex is bound to a hook that is not registered.
+    DanglingHookReference {
+        bookmark: BookmarkOrRegex,
+        hook_name: String,
+    },
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::DanglingHookReference {
+                bookmark: BookmarkOrRegex::Bookmark(bookmark),
+                hook_name,
+            } => write!(
+                f,
+                "bookmark '{}' uses hook '{}', which does not exist",
+                bookmark, hook_name
+            ),
+            Self::DanglingHookReference {
+                bookmark: BookmarkOrRegex::Regex(regex),
+                hook_name,
+            } => write!(
+                f,
+                "bookmark regex '{}' uses hook '{}', which does not exist",
+                regex.as_str(),
+                hook_name
+            ),
         }
-        futs.try_collect().await
     }
 }
 
@@ -312,7 +1263,7 @@ fn get_bypass_reason(
 /// hooks should just exit with a success because we trust
 /// service writes. However, some hooks like verify_integrity
 /// might still need to do some checks and/or logging.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum PushAuthoredBy {
     User,
     Service,
@@ -334,7 +1285,7 @@ impl PushAuthoredBy {
 /// Note: this functionality is rarely needed. You
 ///       should always strive to write hooks that
 ///       ignore this information.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum CrossRepoPushSource {
     /// Cahngeset pushed directly to the large repo
     NativeToThisRepo,
@@ -342,14 +1293,56 @@ pub enum CrossRepoPushSource {
     PushRedirected,
 }
 
+/// State a hook derives from its config in `prepare`. It is computed once
+/// when the hook is registered and handed back to the hook on every
+/// execution, as the first argument of `run_prepared`. Hooks that don't
+/// prepare anything keep implementing `run` only.
+pub type PreparedHookState = Arc<dyn Any + Send + Sync>;
+
+/// A bookmark operation, as seen by a bookmark hook.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BookmarkOperationKind {
+    /// The bookmark is being created, pointing at `to`.
+    Create { to: ChangesetId },
+    /// The bookmark is being moved from `from` to `to`.
+    Move {
+        from: ChangesetId,
+        to: ChangesetId,
+        is_fast_forward: bool,
+    },
+    /// The bookmark, currently pointing at `from`, is being deleted.
+    Delete { from: ChangesetId },
+}
+
+/// Everything a bookmark hook gets to see about a bookmark operation.
+#[derive(Clone, Debug)]
+pub struct BookmarkHookData {
+    /// The bookmark being created, moved or deleted.
+    pub bookmark: BookmarkKey,
+    /// What is happening to the bookmark.
+    pub operation: BookmarkOperationKind,
+    /// The identities of the user performing the operation.
+    pub pusher: MononokeIdentitySet,
+}
+
 enum Hook {
-    Changeset(Box<dyn ChangesetHook>, HookConfig),
-    File(Box<dyn FileHook>, HookConfig),
+    Changeset(Box<dyn ChangesetHook>, HookConfig, PreparedHookState),
+    File(Box<dyn FileHook>, HookConfig, PreparedHookState),
+    Bookmark(Box<dyn BookmarkHook>, HookConfig, PreparedHookState),
 }
 
 enum HookInstance<'a> {
-    Changeset(&'a dyn ChangesetHook),
-    File(&'a dyn FileHook, &'a MPath, Option<&'a BasicFileChange>),
+    Changeset(&'a dyn ChangesetHook, &'a PreparedHookState),
+    File(
+        &'a dyn FileHook,
+        &'a PreparedHookState,
+        &'a MPath,
+        Option<&'a BasicFileChange>,
+        Option<CopyFrom<'a>>,
+    ),
+    /// A file hook only interested in text, accepting a binary file without
+    /// running.
+    SkippedBinaryFile(&'a MPath),
 }
 
 impl<'a> HookInstance<'a> {
@@ -359,22 +1352,25 @@ impl<'a> HookInstance<'a> {
         bookmark: &BookmarkKey,
         content_manager: &dyn FileContentManager,
         hook_name: &str,
-        mut scuba: MononokeScubaSampleBuilder,
+        scuba: MononokeScubaSampleBuilder,
         cs: &BonsaiChangeset,
         cs_id: ChangesetId,
         cross_repo_push_source: CrossRepoPushSource,
         push_authored_by: PushAuthoredBy,
+        timeout: Duration,
     ) -> Result<HookOutcome, Error> {
         let (stats, result) = match self {
-            Self::Changeset(hook) => {
-                hook.run(
+            Self::Changeset(hook, prepared) => {
+                let run = hook.run_prepared(
+                    prepared,
                     ctx,
                     bookmark,
                     cs,
                     content_manager,
                     cross_repo_push_source,
                     push_authored_by,
-                )
+                );
+                with_timeout(timeout, hook_name, run)
                 .map_ok(|exec| {
                     HookOutcome::ChangesetHook(
                         ChangesetHookExecutionID {
@@ -387,15 +1383,18 @@ impl<'a> HookInstance<'a> {
                 .timed()
                 .await
             }
-            Self::File(hook, path, change) => {
-                hook.run(
+            Self::File(hook, prepared, path, change, copy_from) => {
+                let run = hook.run_prepared(
+                    prepared,
                     ctx,
                     content_manager,
                     change,
                     path,
+                    copy_from,
                     cross_repo_push_source,
                     push_authored_by,
-                )
+                );
+                with_timeout(timeout, hook_name, run)
                 .map_ok(|exec| {
                     HookOutcome::FileHook(
                         FileHookExecutionID {
@@ -409,55 +1408,157 @@ impl<'a> HookInstance<'a> {
                 .timed()
                 .await
             }
+            Self::SkippedBinaryFile(path) => {
+                STATS::skipped_binary.add_value(1, (hook_name.to_string(),));
+                let mut scuba = scuba;
+                scuba.add("skip_reason", "binary").log();
+                return Ok(HookOutcome::FileHook(
+                    FileHookExecutionID {
+                        cs_id,
+                        path: path.clone(),
+                        hook_name: hook_name.to_string(),
+                    },
+                    HookExecution::Accepted,
+                ));
+            }
         };
 
-        let mut errorcode = 0;
-        let mut failed_hooks = 0;
-        let mut stderr = None;
+        let execution = result.as_ref().map(HookOutcome::get_execution);
+        record_hook_execution(ctx, hook_name, stats.completion_time, execution);
+        log_hook_execution(scuba, stats.completion_time, execution);
 
-        match result.as_ref().map(HookOutcome::get_execution) {
-            Ok(HookExecution::Accepted) => {
-                // Nothing to do
-            }
-            Ok(HookExecution::Rejected(info)) => {
-                failed_hooks = 1;
-                stderr = Some(info.long_description.clone());
-            }
-            Err(e) => {
-                errorcode = 1;
-                stderr = Some(format!("{:?}", e));
-            }
-        };
+        result.map_err(|e| e.context(format!("while executing hook {}", hook_name)))
+    }
+}
 
-        if let Some(stderr) = stderr {
-            scuba.add("stderr", stderr);
+/// Await the execution of the hook `hook_name`, turning it into a rejection
+/// if it doesn't complete within `timeout`. A hook blocking its thread rather
+/// than yielding can't be interrupted.
+async fn with_timeout(
+    timeout: Duration,
+    hook_name: &str,
+    run: impl Future<Output = Result<HookExecution, Error>>,
+) -> Result<HookExecution, Error> {
+    let start = Instant::now();
+    match tokio::time::timeout(timeout, run).await {
+        Ok(result) => result,
+        Err(_) => {
+            STATS::timeouts.add_value(1, (hook_name.to_string(),));
+            Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                "hook timed out",
+                format!(
+                    "Hook {} timed out after {:.1?}, the limit is {:?}",
+                    hook_name,
+                    start.elapsed(),
+                    timeout
+                ),
+            )))
         }
+    }
+}
 
-        let elapsed = stats.completion_time.as_millis() as i64;
-        scuba
-            .add("elapsed", elapsed)
-            .add("total_time", elapsed)
-            .add("errorcode", errorcode)
-            .add("failed_hooks", failed_hooks)
-            .log();
+/// Record the outcome of a hook execution in the per-hook stats and the
+/// perf counters of `ctx`.
+fn record_hook_execution(
+    ctx: &CoreContext,
+    hook_name: &str,
+    completion_time: Duration,
+    result: Result<&HookExecution, &Error>,
+) {
+    let perf_counters = ctx.perf_counters();
+    perf_counters.increment_counter(PerfCounterType::HooksRun);
+    let hook = hook_name.to_string();
+    match result {
+        Ok(HookExecution::Accepted) => STATS::accepts.add_value(1, (hook.clone(),)),
+        Ok(HookExecution::Rejected(_)) => {
+            perf_counters.increment_counter(PerfCounterType::HooksRejected);
+            STATS::rejects.add_value(1, (hook.clone(),));
+        }
+        Err(_) => {
+            perf_counters.increment_counter(PerfCounterType::HooksFailed);
+            STATS::errors.add_value(1, (hook.clone(),));
+        }
+    }
+    STATS::executions.add_value(1, (hook.clone(),));
+    STATS::duration_ms.add_value(completion_time.as_millis() as i64, (hook,));
+}
 
-        result.map_err(|e| e.context(format!("while executing hook {}", hook_name)))
+/// Record that `hooks_run` hook executions for a push to `bookmark` took
+/// `completion_time` in total, as they are run concurrently.
+fn record_hooks_run(
+    ctx: &CoreContext,
+    bookmark: &BookmarkKey,
+    hooks_run: usize,
+    completion_time: Duration,
+) {
+    if hooks_run == 0 {
+        return;
+    }
+    let elapsed = completion_time.as_millis() as i64;
+    ctx.perf_counters()
+        .add_to_counter(PerfCounterType::HooksWallTimeMs, elapsed);
+    let bookmark = bookmark.to_string();
+    STATS::push_hooks_run.add_value(hooks_run as i64, (bookmark.clone(),));
+    STATS::push_duration_ms.add_value(elapsed, (bookmark,));
+}
+
+fn log_hook_execution(
+    mut scuba: MononokeScubaSampleBuilder,
+    completion_time: Duration,
+    result: Result<&HookExecution, &Error>,
+) {
+    let mut errorcode = 0;
+    let mut failed_hooks = 0;
+    let mut stderr = None;
+
+    match result {
+        Ok(HookExecution::Accepted) => {
+            // Nothing to do
+        }
+        Ok(HookExecution::Rejected(info)) => {
+            failed_hooks = 1;
+            stderr = Some(info.long_description.clone());
+        }
+        Err(e) => {
+            errorcode = 1;
+            stderr = Some(format!("{:?}", e));
+        }
+    };
+
+    if let Some(stderr) = stderr {
+        scuba.add("stderr", stderr);
     }
+
+    let elapsed = completion_time.as_millis() as i64;
+    scuba
+        .add("elapsed", elapsed)
+        .add("total_time", elapsed)
+        .add("errorcode", errorcode)
+        .add("failed_hooks", failed_hooks)
+        .log();
 }
 
 impl Hook {
-    pub fn from_changeset(hook: Box<dyn ChangesetHook>, config: HookConfig) -> Self {
-        Self::Changeset(hook, config)
+    pub fn from_changeset(hook: Box<dyn ChangesetHook>, config: HookConfig) -> Result<Self> {
+        let prepared = hook.prepare(&config)?;
+        Ok(Self::Changeset(hook, config, prepared))
     }
 
-    pub fn from_file(hook: Box<dyn FileHook>, config: HookConfig) -> Self {
-        Self::File(hook, config)
+    pub fn from_file(hook: Box<dyn FileHook>, config: HookConfig) -> Result<Self> {
+        let prepared = hook.prepare(&config)?;
+        Ok(Self::File(hook, config, prepared))
+    }
+
+    pub fn from_bookmark(hook: Box<dyn BookmarkHook>, config: HookConfig) -> Result<Self> {
+        let prepared = hook.prepare(&config)?;
+        Ok(Self::Bookmark(hook, config, prepared))
     }
 
     pub fn get_config(&self) -> &HookConfig {
         match self {
-            Self::Changeset(_, config) => config,
-            Self::File(_, config) => config,
+            Self::Changeset(_, config, _) => config,
+            Self::File(_, config, _) => config,
+            Self::Bookmark(_, config, _) => config,
         }
     }
 
@@ -466,31 +1567,54 @@ impl Hook {
         ctx: &'a CoreContext,
         bookmark: &'a BookmarkKey,
         content_manager: &'a dyn FileContentManager,
+        binary_contents: &'a HashSet<ContentId>,
         hook_name: &'cs str,
         cs: &'cs BonsaiChangeset,
         scuba: MononokeScubaSampleBuilder,
         cross_repo_push_source: CrossRepoPushSource,
         push_authored_by: PushAuthoredBy,
-    ) -> impl Iterator<Item = impl Future<Output = Result<HookOutcome, Error>> + 'cs> + 'cs {
+        timeout: Duration,
+    ) -> impl Iterator<
+        Item = (
+            Option<&'cs MPath>,
+            impl Future<Output = Result<HookOutcome, Error>> + 'cs,
+        ),
+    > + 'cs {
         let mut futures = Vec::new();
 
         let cs_id = cs.get_changeset_id();
 
         match self {
-            Self::Changeset(hook, _) => futures.push(HookInstance::Changeset(&**hook).run(
-                ctx,
-                bookmark,
-                content_manager,
-                hook_name,
-                scuba,
-                cs,
-                cs_id,
-                cross_repo_push_source,
-                push_authored_by,
+            Self::Changeset(hook, _, prepared) => futures.push((
+                None,
+                HookInstance::Changeset(&**hook, prepared).run(
+                    ctx,
+                    bookmark,
+                    content_manager,
+                    hook_name,
+                    scuba,
+                    cs,
+                    cs_id,
+                    cross_repo_push_source,
+                    push_authored_by,
+                    timeout,
+                ),
             )),
-            Self::File(hook, _) => {
-                futures.extend(cs.simplified_file_changes().map(move |(path, change)| {
-                    HookInstance::File(&**hook, path, change).run(
+            Self::File(hook, _, prepared) => {
+                let text_only = hook.content_interest() == ContentInterest::TextOnly;
+                futures.extend(cs.file_changes().map(move |(path, file_change)| {
+                    let change = file_change.simplify();
+                    let binary = change
+                        .map_or(false, |change| binary_contents.contains(&change.content_id()));
+                    let instance = if text_only && binary {
+                        HookInstance::SkippedBinaryFile(path)
+                    } else {
+                        let copy_from = file_change
+                            .copy_from()
+                            .map(|(from, from_cs_id)| CopyFrom::new(cs, from, *from_cs_id));
+                        HookInstance::File(&**hook, prepared, path, change, copy_from)
+                    };
+                    let future = instance.run(
                         ctx,
                         bookmark,
                         content_manager,
@@ -500,9 +1624,13 @@ impl Hook {
                         cs_id,
                         cross_repo_push_source,
                         push_authored_by,
-                    )
+                        timeout,
+                    );
+                    (Some(path), future)
                 }))
             }
+            // Bookmark hooks don't run against changesets.
+            Self::Bookmark(..) => {}
         };
         futures.into_iter()
     }
@@ -510,6 +1638,20 @@ impl Hook {
 
 #[async_trait]
 pub trait ChangesetHook: Send + Sync {
+    /// Derive state from the hook's config, such as compiled regexes, so that
+    /// it isn't rebuilt on every execution. Called when the hook is
+    /// registered with a `HookManager`.
+    fn prepare(&self, _config: &HookConfig) -> Result<PreparedHookState, Error> {
+        Ok(Arc::new(()))
+    }
+
+    /// Whether the outcome of the hook doesn't depend on the bookmark it
+    /// runs for, so that it can be reused for the other bookmarks of a
+    /// `HookExecutionScope`.
+    fn bookmark_independent(&self) -> bool {
+        false
+    }
+
     async fn run<'this: 'cs, 'ctx: 'this, 'cs, 'fetcher: 'cs>(
         &'this self,
         ctx: &'ctx CoreContext,
@@ -519,19 +1661,193 @@ pub trait ChangesetHook: Send + Sync {
         cross_repo_push_source: CrossRepoPushSource,
         push_authored_by: PushAuthoredBy,
     ) -> Result<HookExecution, Error>;
+
+    /// Run the hook with the state returned by `prepare`. This is what the
+    /// `HookManager` calls; by default it ignores the state and calls `run`.
+    async fn run_prepared<'this: 'cs, 'ctx: 'this, 'cs, 'fetcher: 'cs>(
+        &'this self,
+        _prepared: &'this PreparedHookState,
+        ctx: &'ctx CoreContext,
+        bookmark: &BookmarkKey,
+        changeset: &'cs BonsaiChangeset,
+        content_manager: &'fetcher dyn FileContentManager,
+        cross_repo_push_source: CrossRepoPushSource,
+        push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution, Error> {
+        self.run(
+            ctx,
+            bookmark,
+            changeset,
+            content_manager,
+            cross_repo_push_source,
+            push_authored_by,
+        )
+        .await
+    }
+}
+
+/// The file data a `FileHook` reads through the `FileContentManager`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ContentPrefetch {
+    /// Nothing, or nothing worth fetching for every file.
+    Nothing,
+    /// File sizes.
+    Size,
+    /// File sizes and texts.
+    Text,
+}
+
+/// The files a `FileHook` is interested in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentInterest {
+    /// All files.
+    Any,
+    /// Text files only. Binary files, as found by the `BinaryHeuristic` of
+    /// the `HookManager`, are accepted without running the hook.
+    TextOnly,
+}
+
+/// How the hooks bound to a bookmark run, see
+/// `HookManager::set_hooks_for_bookmark_with_mode`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HookRunMode {
+    /// All hooks run concurrently, and all their outcomes are returned.
+    #[default]
+    RunAll,
+    /// Hooks run one at a time, in the order they are bound, and stop at
+    /// the first rejection. Expensive hooks should be bound after cheap
+    /// ones, so they don't run on pushes the cheap ones reject.
+    FailFast,
+}
+
+/// How a `HookManager` finds binary files, for file hooks with
+/// `ContentInterest::TextOnly`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryHeuristic {
+    /// No file is binary: text-only hooks run on all files.
+    Disabled,
+    /// Files with a NUL byte in their first `prefix_len` bytes are binary.
+    NulByte { prefix_len: u64 },
+}
+
+impl Default for BinaryHeuristic {
+    fn default() -> Self {
+        Self::NulByte { prefix_len: 8000 }
+    }
 }
 
 #[async_trait]
 pub trait FileHook: Send + Sync {
+    /// Derive state from the hook's config, such as compiled regexes, so that
+    /// it isn't rebuilt for every file. Called when the hook is registered
+    /// with a `HookManager`.
+    fn prepare(&self, _config: &HookConfig) -> Result<PreparedHookState, Error> {
+        Ok(Arc::new(()))
+    }
+
+    /// The file data this hook reads. Before file hooks run on a changeset,
+    /// the data needed by any of them is fetched for all the files of the
+    /// changeset in one batch, and `content_manager` serves it from memory.
+    fn content_prefetch(&self) -> ContentPrefetch {
+        ContentPrefetch::Nothing
+    }
+
+    /// The files this hook is interested in. Hooks that only check text,
+    /// such as line lengths, can skip binary files without fetching them.
+    fn content_interest(&self) -> ContentInterest {
+        ContentInterest::Any
+    }
+
+    /// Run the hook on the file at `path`. `change` is `None` if the file is
+    /// deleted. `copy_from` is set if the changeset copied or moved the file,
+    /// for hooks about where files come from rather than what they contain.
     async fn run<'this: 'change, 'ctx: 'this, 'change, 'fetcher: 'change, 'path: 'change>(
         &'this self,
         ctx: &'ctx CoreContext,
         content_manager: &'fetcher dyn FileContentManager,
         change: Option<&'change BasicFileChange>,
         path: &'path MPath,
+        copy_from: Option<CopyFrom<'change>>,
         cross_repo_push_source: CrossRepoPushSource,
         push_authored_by: PushAuthoredBy,
     ) -> Result<HookExecution, Error>;
+
+    /// Run the hook with the state returned by `prepare`. This is what the
+    /// `HookManager` calls; by default it ignores the state and calls `run`.
+    async fn run_prepared<
+        'this: 'change,
+        'ctx: 'this,
+        'change,
+        'fetcher: 'change,
+        'path: 'change,
+    >(
+        &'this self,
+        _prepared: &'this PreparedHookState,
+        ctx: &'ctx CoreContext,
+        content_manager: &'fetcher dyn FileContentManager,
+        change: Option<&'change BasicFileChange>,
+        path: &'path MPath,
+        copy_from: Option<CopyFrom<'change>>,
+        cross_repo_push_source: CrossRepoPushSource,
+        push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution, Error> {
+        self.run(
+            ctx,
+            content_manager,
+            change,
+            path,
+            copy_from,
+            cross_repo_push_source,
+            push_authored_by,
+        )
+        .await
+    }
+}
+
+/// Where a file changed by a changeset was copied from, for `FileHook::run`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CopyFrom<'a> {
+    /// Path of the source in `cs_id`.
+    pub path: &'a MPath,
+    /// The parent changeset the file was copied from.
+    pub cs_id: ChangesetId,
+    /// Whether the changeset also deletes the source, that is whether the
+    /// file was moved or renamed rather than copied.
+    pub moved: bool,
+}
+
+impl<'a> CopyFrom<'a> {
+    fn new(cs: &'a BonsaiChangeset, path: &'a MPath, cs_id: ChangesetId) -> Self {
+        let moved = cs
+            .file_changes_map()
+            .get(path)
+            .map_or(false, |change| change.simplify().is_none());
+        Self { path, cs_id, moved }
+    }
+}
+
+#[async_trait]
+pub trait BookmarkHook: Send + Sync {
+    /// Derive state from the hook's config, such as compiled regexes, so that
+    /// it isn't rebuilt on every execution. Called when the hook is
+    /// registered with a `HookManager`.
+    fn prepare(&self, _config: &HookConfig) -> Result<PreparedHookState, Error> {
+        Ok(Arc::new(()))
+    }
+
+    async fn run(&self, ctx: &CoreContext, data: &BookmarkHookData)
+        -> Result<HookExecution, Error>;
+
+    /// Run the hook with the state returned by `prepare`. This is what the
+    /// `HookManager` calls; by default it ignores the state and calls `run`.
+    async fn run_prepared(
+        &self,
+        _prepared: &PreparedHookState,
+        ctx: &CoreContext,
+        data: &BookmarkHookData,
+    ) -> Result<HookExecution, Error> {
+        self.run(ctx, data).await
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -619,6 +1935,40 @@ impl HookOutcome {
     }
 }
 
+/// The result of running a bookmark hook against a bookmark operation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BookmarkHookOutcome {
+    pub id: BookmarkHookExecutionID,
+    pub execution: HookExecution,
+}
+
+impl fmt::Display for BookmarkHookOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} for bookmark {}: {}",
+            self.id.hook_name, self.id.bookmark, self.execution
+        )
+    }
+}
+
+impl BookmarkHookOutcome {
+    pub fn is_rejection(&self) -> bool {
+        match self.execution {
+            HookExecution::Accepted => false,
+            HookExecution::Rejected(_) => true,
+        }
+    }
+
+    pub fn is_accept(&self) -> bool {
+        !self.is_rejection()
+    }
+
+    pub fn get_hook_name(&self) -> &str {
+        &self.id.hook_name
+    }
+}
+
 /// Instance of a hook rejecting a changeset.
 #[derive(Clone, Debug, PartialEq)]
 pub struct HookRejection {
@@ -700,6 +2050,12 @@ pub struct ChangesetHookExecutionID {
     pub hook_name: String,
 }
 
+#[derive(Clone, Debug, PartialEq, Hash, Eq)]
+pub struct BookmarkHookExecutionID {
+    pub bookmark: BookmarkKey,
+    pub hook_name: String,
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -715,6 +2071,28 @@ mod test {
         assert!(r.is_some());
     }
 
+    #[test]
+    fn test_default_bypass() {
+        let default = HookBypass::new_with_pushvar("BYPASS_ALL_HOOKS".into(), "true".into());
+        let mut pushvars = HashMap::new();
+        pushvars.insert("BYPASS_ALL_HOOKS".into(), "true".as_bytes().into());
+
+        // A hook's own bypass of a kind replaces the default one.
+        let bypass = HookBypass::new_with_pushvar("myvar".into(), "myvalue".into())
+            .with_default(&default);
+        assert_eq!(
+            bypass,
+            HookBypass::new_with_pushvar("myvar".into(), "myvalue".into())
+        );
+        assert!(get_bypass_reason(Some(&bypass), "", Some(&pushvars)).is_none());
+
+        // Other kinds of bypass are combined: both bypass the hook.
+        let bypass = HookBypass::new_with_commit_msg("@mybypass".into()).with_default(&default);
+        assert!(get_bypass_reason(Some(&bypass), "@mybypass", None).is_some());
+        assert!(get_bypass_reason(Some(&bypass), "", Some(&pushvars)).is_some());
+        assert!(get_bypass_reason(Some(&bypass), "", None).is_none());
+    }
+
     #[test]
     fn test_pushvar_bypass() {
         let bypass = HookBypass::new_with_pushvar("myvar".into(), "myvalue".into());
@@ -0,0 +1,185 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Gates restricting who may push to a bookmark, checked before its hooks
+//! run, see `HookManager::set_pusher_gate_for_bookmark`.
+
+use std::fmt;
+
+use anyhow::Context;
+use anyhow::Result;
+use itertools::Itertools;
+use metaconfig_types::HookBypass;
+use metaconfig_types::PusherGateParams;
+use metaconfig_types::PusherMatcherParams;
+use permission_checker::AclProvider;
+use permission_checker::ArcMembershipChecker;
+use permission_checker::MononokeIdentity;
+use permission_checker::MononokeIdentitySet;
+use regex::Regex;
+
+use crate::HookRejectionInfo;
+
+/// Matches the identities of a pusher.
+#[derive(Clone)]
+pub enum PusherMatcher {
+    /// Matches pushers with this identity.
+    Identity(MononokeIdentity),
+    /// Matches pushers whose user name matches a glob, see `username_glob`.
+    Username { glob: String, regex: Regex },
+    /// Matches the members of the ACL group `name`, as checked by `checker`.
+    Group {
+        name: String,
+        checker: ArcMembershipChecker,
+    },
+}
+
+impl PusherMatcher {
+    /// The matcher described by `params`, looking up groups with
+    /// `acl_provider`.
+    pub async fn from_params(
+        acl_provider: &dyn AclProvider,
+        params: PusherMatcherParams,
+    ) -> Result<Self> {
+        Ok(match params {
+            PusherMatcherParams::Identity(identity) => Self::Identity(identity.parse()?),
+            PusherMatcherParams::Username(glob) => Self::username_glob(&glob)?,
+            PusherMatcherParams::Group(name) => Self::Group {
+                checker: acl_provider
+                    .group(&name)
+                    .await
+                    .with_context(|| format!("while looking up group {}", name))?
+                    .into(),
+                name,
+            },
+        })
+    }
+
+    /// Matches pushers whose `USER` identity matches `glob`, in which `*`
+    /// matches any characters and `?` matches one.
+    pub fn username_glob(glob: &str) -> Result<Self> {
+        let pattern = glob
+            .split('*')
+            .map(|part| {
+                part.split('?')
+                    .map(regex::escape)
+                    .collect::<Vec<_>>()
+                    .join(".")
+            })
+            .collect::<Vec<_>>()
+            .join(".*");
+        Ok(Self::Username {
+            glob: glob.to_string(),
+            regex: Regex::new(&format!("^{}$", pattern))?,
+        })
+    }
+
+    pub async fn matches(&self, pusher: &MononokeIdentitySet) -> bool {
+        match self {
+            Self::Identity(identity) => pusher.contains(identity),
+            Self::Username { regex, .. } => pusher
+                .iter()
+                .any(|identity| identity.id_type() == "USER" && regex.is_match(identity.id_data())),
+            Self::Group { checker, .. } => checker.is_member(pusher).await,
+        }
+    }
+}
+
+impl fmt::Display for PusherMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Identity(identity) => write!(f, "identity {}", identity),
+            Self::Username { glob, .. } => write!(f, "user {}", glob),
+            Self::Group { name, .. } => write!(f, "group {}", name),
+        }
+    }
+}
+
+impl fmt::Debug for PusherMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PusherMatcher({})", self)
+    }
+}
+
+/// A policy on who may push to the bookmarks it is bound to.
+///
+/// A pusher matching any of `denied_pushers` is denied. Otherwise, if
+/// `allowed_pushers` is set, a pusher matching none of them is denied.
+#[derive(Clone, Debug, Default)]
+pub struct PusherGate {
+    /// The name of the policy, shown in rejections.
+    pub name: String,
+    pub allowed_pushers: Option<Vec<PusherMatcher>>,
+    pub denied_pushers: Vec<PusherMatcher>,
+    /// Lets pushers bypass the gate. There is no commit message to look for
+    /// a bypass string in, so only pushvars apply.
+    pub bypass: Option<HookBypass>,
+}
+
+impl PusherGate {
+    /// The gate described by `params`, looking up groups with
+    /// `acl_provider`.
+    pub async fn from_params(
+        acl_provider: &dyn AclProvider,
+        params: PusherGateParams,
+    ) -> Result<Self> {
+        let mut allowed_pushers = None;
+        if let Some(matchers) = params.allowed_pushers {
+            let mut allowed = Vec::new();
+            for matcher in matchers {
+                allowed.push(PusherMatcher::from_params(acl_provider, matcher).await?);
+            }
+            allowed_pushers = Some(allowed);
+        }
+        let mut denied_pushers = Vec::new();
+        for matcher in params.denied_pushers {
+            denied_pushers.push(PusherMatcher::from_params(acl_provider, matcher).await?);
+        }
+        Ok(Self {
+            name: params.name,
+            allowed_pushers,
+            denied_pushers,
+            bypass: params.bypass,
+        })
+    }
+
+    /// The name of the hook that rejections by this gate are attributed to.
+    pub fn hook_name(&self) -> String {
+        format!("pusher_gate:{}", self.name)
+    }
+
+    /// Why `pusher` is denied by this gate, or `None` if they may push.
+    pub async fn check(&self, pusher: &MononokeIdentitySet) -> Option<HookRejectionInfo> {
+        for matcher in &self.denied_pushers {
+            if matcher.matches(pusher).await {
+                return Some(HookRejectionInfo::new_long(
+                    "Pusher denied by policy",
+                    format!(
+                        "Pushing to this bookmark is denied by policy {} to {}",
+                        self.name, matcher
+                    ),
+                ));
+            }
+        }
+        if let Some(allowed_pushers) = &self.allowed_pushers {
+            for matcher in allowed_pushers {
+                if matcher.matches(pusher).await {
+                    return None;
+                }
+            }
+            return Some(HookRejectionInfo::new_long(
+                "Pusher not allowed by policy",
+                format!(
+                    "Pushing to this bookmark is only allowed by policy {} to: {}",
+                    self.name,
+                    allowed_pushers.iter().join(", ")
+                ),
+            ));
+        }
+        None
+    }
+}
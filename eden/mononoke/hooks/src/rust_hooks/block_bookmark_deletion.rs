@@ -0,0 +1,49 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Error;
+use async_trait::async_trait;
+use context::CoreContext;
+
+use crate::BookmarkHook;
+use crate::BookmarkHookData;
+use crate::BookmarkOperationKind;
+use crate::HookExecution;
+use crate::HookRejectionInfo;
+
+#[derive(Clone, Debug)]
+pub struct BlockBookmarkDeletion;
+
+impl BlockBookmarkDeletion {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[async_trait]
+impl BookmarkHook for BlockBookmarkDeletion {
+    async fn run(
+        &self,
+        _ctx: &CoreContext,
+        data: &BookmarkHookData,
+    ) -> Result<HookExecution, Error> {
+        match data.operation {
+            BookmarkOperationKind::Delete { .. } => {
+                Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+                    "Deleting this bookmark is not allowed",
+                    format!(
+                        "Bookmark {} is protected and cannot be deleted",
+                        data.bookmark
+                    ),
+                )))
+            }
+            BookmarkOperationKind::Create { .. } | BookmarkOperationKind::Move { .. } => {
+                Ok(HookExecution::Accepted)
+            }
+        }
+    }
+}
@@ -16,6 +16,8 @@ use mononoke_types::MPath;
 use regex::Regex;
 
 use crate::ChangesetHook;
+use crate::ContentPrefetch;
+use crate::CopyFrom;
 use crate::CrossRepoPushSource;
 use crate::FileContentManager;
 use crate::FileHook;
@@ -51,12 +53,17 @@ fn has_nocommit(text: &[u8]) -> Option<&str> {
 
 #[async_trait]
 impl FileHook for CheckNocommitHook {
+    fn content_prefetch(&self) -> ContentPrefetch {
+        ContentPrefetch::Text
+    }
+
     async fn run<'this: 'change, 'ctx: 'this, 'change, 'fetcher: 'change, 'path: 'change>(
         &'this self,
         ctx: &'ctx CoreContext,
         content_manager: &'fetcher dyn FileContentManager,
         change: Option<&'change BasicFileChange>,
         path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
         _cross_repo_push_source: CrossRepoPushSource,
         push_authored_by: PushAuthoredBy,
     ) -> Result<HookExecution, Error> {
@@ -120,7 +127,10 @@ impl ChangesetHook for CheckNocommitHook {
 
 #[cfg(test)]
 mod test {
+    use fbinit::FacebookInit;
+
     use super::*;
+    use crate::testkit::HookTestKit;
 
     #[test]
     fn test_find_nocommit() {
@@ -132,6 +142,23 @@ mod test {
         assert_eq!(None, has_nocommit(b"foo nocommit"));
     }
 
+    #[fbinit::test]
+    async fn test_conformance(fb: FacebookInit) -> Result<(), Error> {
+        let kit = HookTestKit::new(fb)?;
+        let hook = CheckNocommitHook::new(&HookConfig::default())?;
+
+        for run in kit.check_file_hook(&hook).await? {
+            // Fetching the text fails when it's missing from the store.
+            if run.scenario != "missing content" {
+                assert_eq!(run.result?, HookExecution::Accepted, "{}", run.scenario);
+            }
+        }
+        for run in kit.check_changeset_hook(&hook).await? {
+            assert_eq!(run.result?, HookExecution::Accepted, "{}", run.scenario);
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_ignore_binary() {
         assert_eq!(None, has_nocommit(b"foo \x40nocommit \x80\x81"));
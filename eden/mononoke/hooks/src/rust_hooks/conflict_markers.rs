@@ -14,6 +14,8 @@ use maplit::hashset;
 use mononoke_types::BasicFileChange;
 use mononoke_types::MPath;
 
+use crate::ContentPrefetch;
+use crate::CopyFrom;
 use crate::CrossRepoPushSource;
 use crate::FileContentManager;
 use crate::FileHook;
@@ -35,12 +37,17 @@ impl ConflictMarkers {
 
 #[async_trait]
 impl FileHook for ConflictMarkers {
+    fn content_prefetch(&self) -> ContentPrefetch {
+        ContentPrefetch::Text
+    }
+
     async fn run<'this: 'change, 'ctx: 'this, 'change, 'fetcher: 'change, 'path: 'change>(
         &'this self,
         ctx: &'ctx CoreContext,
         content_manager: &'fetcher dyn FileContentManager,
         change: Option<&'change BasicFileChange>,
         path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
         _cross_repo_push_source: CrossRepoPushSource,
         push_authored_by: PushAuthoredBy,
     ) -> Result<HookExecution, Error> {
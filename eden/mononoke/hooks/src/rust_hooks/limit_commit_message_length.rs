@@ -99,7 +99,30 @@ fn extract_title<'a>(message: &'a str, max_length: usize) -> &'a str {
 
 #[cfg(test)]
 mod test {
+    use std::collections::HashMap;
+
+    use fbinit::FacebookInit;
+
     use super::*;
+    use crate::testkit::HookTestKit;
+
+    #[fbinit::test]
+    async fn test_conformance(fb: FacebookInit) -> Result<(), Error> {
+        let config = HookConfig {
+            strings: HashMap::from([("length_limit".to_string(), "40".to_string())]),
+            ..Default::default()
+        };
+        let hook = LimitCommitMessageLength::new(&config)?;
+        let kit = HookTestKit::new(fb)?.with_config(config);
+
+        for run in kit.check_changeset_hook(&hook).await? {
+            // Only the message of the bypassed scenario is long enough.
+            let expected_rejection = run.scenario == "bypassed config";
+            let rejected = matches!(run.result?, HookExecution::Rejected(_));
+            assert_eq!(rejected, expected_rejection, "{}", run.scenario);
+        }
+        Ok(())
+    }
 
     #[test]
     fn test_extract_title_short() {
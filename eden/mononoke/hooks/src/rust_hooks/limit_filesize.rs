@@ -14,6 +14,8 @@ use mononoke_types::BasicFileChange;
 use mononoke_types::MPath;
 use regex::Regex;
 
+use crate::ContentPrefetch;
+use crate::CopyFrom;
 use crate::CrossRepoPushSource;
 use crate::FileContentManager;
 use crate::FileHook;
@@ -86,12 +88,17 @@ impl LimitFilesize {
 
 #[async_trait]
 impl FileHook for LimitFilesize {
+    fn content_prefetch(&self) -> ContentPrefetch {
+        ContentPrefetch::Size
+    }
+
     async fn run<'this: 'change, 'ctx: 'this, 'change, 'fetcher: 'change, 'path: 'change>(
         &'this self,
         ctx: &'ctx CoreContext,
         content_manager: &'fetcher dyn FileContentManager,
         change: Option<&'change BasicFileChange>,
         path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
         cross_repo_push_source: CrossRepoPushSource,
         push_authored_by: PushAuthoredBy,
     ) -> Result<HookExecution> {
@@ -14,6 +14,7 @@ use mercurial_types::simple_fsencode;
 use mononoke_types::BasicFileChange;
 use mononoke_types::MPath;
 
+use crate::CopyFrom;
 use crate::CrossRepoPushSource;
 use crate::FileContentManager;
 use crate::FileHook;
@@ -51,6 +52,7 @@ impl FileHook for LimitPathLengthHook {
         _content_manager: &'fetcher dyn FileContentManager,
         change: Option<&'change BasicFileChange>,
         path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
         _cross_repo_push_source: CrossRepoPushSource,
         push_authored_by: PushAuthoredBy,
     ) -> Result<HookExecution, Error> {
@@ -8,6 +8,7 @@
 //! For Facebook hooks check the src/facebook/ folder
 
 mod always_fail_changeset;
+mod block_bookmark_deletion;
 mod block_empty_commit;
 mod check_nocommit;
 mod conflict_markers;
@@ -22,6 +23,7 @@ pub(crate) mod no_bad_filenames;
 mod no_insecure_filenames;
 pub(crate) mod no_questionable_filenames;
 pub(crate) mod no_windows_filenames;
+mod require_commit_message_fields;
 
 use anyhow::Result;
 use fbinit::FacebookInit;
@@ -31,6 +33,7 @@ use permission_checker::AclProvider;
 use permission_checker::ArcMembershipChecker;
 
 pub(crate) use self::lua_pattern::LuaPattern;
+use crate::BookmarkHook;
 use crate::ChangesetHook;
 use crate::FileHook;
 
@@ -60,6 +63,9 @@ pub fn hook_name_to_changeset_hook<'a>(
             "limit_commitsize" => Some(b(limit_commitsize::LimitCommitsize::builder()
                 .set_from_config(config)
                 .build()?)),
+            "require_commit_message_fields" => Some(b(
+                require_commit_message_fields::RequireCommitMessageFields::new(config)?,
+            )),
             _ => None,
         })
     }
@@ -112,3 +118,16 @@ pub fn hook_name_to_file_hook(
         _ => None,
     })
 }
+
+pub fn hook_name_to_bookmark_hook(
+    _fb: FacebookInit,
+    name: &str,
+    _config: &HookConfig,
+) -> Result<Option<Box<dyn BookmarkHook + 'static>>> {
+    Ok(match name {
+        "block_bookmark_deletion" => Some(Box::new(
+            block_bookmark_deletion::BlockBookmarkDeletion::new(),
+        )),
+        _ => None,
+    })
+}
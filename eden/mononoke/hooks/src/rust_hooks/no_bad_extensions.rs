@@ -13,6 +13,7 @@ use metaconfig_types::HookConfig;
 use mononoke_types::BasicFileChange;
 use mononoke_types::MPath;
 
+use crate::CopyFrom;
 use crate::CrossRepoPushSource;
 use crate::FileContentManager;
 use crate::FileHook;
@@ -66,6 +67,7 @@ impl FileHook for NoBadExtensions {
         _content_manager: &'fetcher dyn FileContentManager,
         change: Option<&'change BasicFileChange>,
         path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
         _cross_repo_push_source: CrossRepoPushSource,
         push_authored_by: PushAuthoredBy,
     ) -> Result<HookExecution> {
@@ -15,6 +15,7 @@ use mononoke_types::BasicFileChange;
 use mononoke_types::MPath;
 use regex::Regex;
 
+use crate::CopyFrom;
 use crate::CrossRepoPushSource;
 use crate::FileContentManager;
 use crate::FileHook;
@@ -84,6 +85,7 @@ impl FileHook for NoBadFilenames {
         _content_manager: &'fetcher dyn FileContentManager,
         change: Option<&'change BasicFileChange>,
         path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
         _cross_repo_push_source: CrossRepoPushSource,
         push_authored_by: PushAuthoredBy,
     ) -> Result<HookExecution> {
@@ -15,6 +15,7 @@ use mononoke_types::BasicFileChange;
 use mononoke_types::MPath;
 use regex::bytes::Regex;
 
+use crate::CopyFrom;
 use crate::CrossRepoPushSource;
 use crate::FileContentManager;
 use crate::FileHook;
@@ -99,6 +100,7 @@ impl FileHook for NoWindowsFilenames {
         _context_fetcher: &'fetcher dyn FileContentManager,
         change: Option<&'change BasicFileChange>,
         path: &'path MPath,
+        _copy_from: Option<CopyFrom<'change>>,
         cross_repo_push_source: CrossRepoPushSource,
         push_authored_by: PushAuthoredBy,
     ) -> Result<HookExecution> {
@@ -0,0 +1,146 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Error;
+use async_trait::async_trait;
+use bookmarks::BookmarkKey;
+use context::CoreContext;
+use mononoke_types::BonsaiChangeset;
+
+use crate::ChangesetHook;
+use crate::CrossRepoPushSource;
+use crate::FileContentManager;
+use crate::HookConfig;
+use crate::HookExecution;
+use crate::HookRejectionInfo;
+use crate::ParsedCommitMessage;
+use crate::PushAuthoredBy;
+
+/// Reject commits whose message lacks any of the configured fields, like
+/// `Test Plan` or `Tasks`.
+#[derive(Clone, Debug)]
+pub struct RequireCommitMessageFields {
+    fields: Vec<String>,
+}
+
+impl RequireCommitMessageFields {
+    pub fn new(config: &HookConfig) -> Result<Self, Error> {
+        let fields = config
+            .string_lists
+            .get("fields")
+            .filter(|fields| !fields.is_empty())
+            .ok_or_else(|| Error::msg("Required config fields is missing"))?
+            .clone();
+        Ok(Self { fields })
+    }
+}
+
+#[async_trait]
+impl ChangesetHook for RequireCommitMessageFields {
+    async fn run<'this: 'cs, 'ctx: 'this, 'cs, 'fetcher: 'cs>(
+        &'this self,
+        _ctx: &'ctx CoreContext,
+        _bookmark: &BookmarkKey,
+        changeset: &'cs BonsaiChangeset,
+        _content_manager: &'fetcher dyn FileContentManager,
+        _cross_repo_push_source: CrossRepoPushSource,
+        push_authored_by: PushAuthoredBy,
+    ) -> Result<HookExecution, Error> {
+        if push_authored_by.service() {
+            return Ok(HookExecution::Accepted);
+        }
+        let message = ParsedCommitMessage::parse(changeset.message());
+        let missing: Vec<&str> = self
+            .fields
+            .iter()
+            .filter(|field| message.field(field).is_none())
+            .map(String::as_str)
+            .collect();
+        if missing.is_empty() {
+            return Ok(HookExecution::Accepted);
+        }
+        Ok(HookExecution::Rejected(HookRejectionInfo::new_long(
+            "Missing commit message fields",
+            format!(
+                "Commit message of '{}' must have these fields: {}",
+                message.title(),
+                missing.join(", ")
+            ),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Error;
+    use blobstore::Loadable;
+    use borrowed::borrowed;
+    use fbinit::FacebookInit;
+    use hooks_content_stores::RepoFileContentManager;
+    use maplit::hashmap;
+    use tests_utils::BasicTestRepo;
+    use tests_utils::CreateCommitContext;
+
+    use super::*;
+
+    #[fbinit::test]
+    async fn test_require_commit_message_fields(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo: BasicTestRepo = test_repo_factory::build_empty(ctx.fb).await?;
+        borrowed!(ctx, repo);
+        let content_manager = RepoFileContentManager::new(&repo);
+
+        let config = HookConfig {
+            string_lists: hashmap! {
+                "fields".to_string() => vec!["Test Plan".to_string(), "Tasks".to_string()],
+            },
+            ..Default::default()
+        };
+        let hook = RequireCommitMessageFields::new(&config)?;
+
+        let cases = [
+            ("add a\n\nTest Plan:\n  cargo test\n\nTasks: T1\n", None),
+            ("add a\n\nTest Plan: cargo test\n", Some("Tasks")),
+            ("add a\n\nTest Plan:\n\nTasks:\n", Some("Test Plan, Tasks")),
+        ];
+        for (message, missing) in cases {
+            let bcs = CreateCommitContext::new_root(ctx, repo)
+                .add_file("a", "a")
+                .set_message(message)
+                .commit()
+                .await?
+                .load(ctx, &repo.repo_blobstore)
+                .await?;
+            let hook_execution = hook
+                .run(
+                    ctx,
+                    &BookmarkKey::new("book")?,
+                    &bcs,
+                    &content_manager,
+                    CrossRepoPushSource::NativeToThisRepo,
+                    PushAuthoredBy::User,
+                )
+                .await?;
+            match (hook_execution, missing) {
+                (HookExecution::Accepted, None) => {}
+                (HookExecution::Rejected(info), Some(missing)) => {
+                    assert_eq!(
+                        info.long_description,
+                        format!(
+                            "Commit message of 'add a' must have these fields: {}",
+                            missing
+                        )
+                    );
+                }
+                (hook_execution, _) => panic!("{:?}: unexpected {:?}", message, hook_execution),
+            }
+        }
+
+        assert!(RequireCommitMessageFields::new(&HookConfig::default()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,385 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Conformance checks for `FileHook` and `ChangesetHook` implementations,
+//! for the tests of hooks written outside of this crate.
+//!
+//! `HookTestKit` runs a hook against synthetic changesets covering the cases
+//! hooks tend to get wrong, and fails naming the scenario if the hook panics,
+//! fails, blocks the executor with synchronous work, or doesn't complete in
+//! time. It doesn't check whether the hook accepts or rejects: the runs are
+//! returned for the test to check.
+
+use std::any::Any;
+use std::fmt;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Error;
+use anyhow::Result;
+use bookmarks::BookmarkKey;
+use bytes::Bytes;
+use context::CoreContext;
+use fbinit::FacebookInit;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use hooks_content_stores::InMemoryFileContentManager;
+use hooks_content_stores::InMemoryFileText;
+use metaconfig_types::HookBypass;
+use metaconfig_types::HookConfig;
+use mononoke_types::BonsaiChangeset;
+use mononoke_types::BonsaiChangesetMut;
+use mononoke_types::ChangesetId;
+use mononoke_types::ContentId;
+use mononoke_types::DateTime;
+use mononoke_types::FileChange;
+use mononoke_types::FileType;
+use mononoke_types::MPath;
+use thiserror::Error;
+
+use crate::ChangesetHook;
+use crate::CopyFrom;
+use crate::CrossRepoPushSource;
+use crate::FileHook;
+use crate::HookExecution;
+use crate::PushAuthoredBy;
+
+/// Size of the file of the "huge file" scenario, whose content is generated
+/// when fetched.
+pub const HUGE_FILE_SIZE: u64 = 4 << 30;
+
+/// Commit message marker of the "bypassed config" scenario.
+pub const BYPASS_MARKER: &str = "@testkit-bypass";
+
+const BOOKMARK: &str = "master";
+
+/// What a hook did in a scenario.
+#[derive(Debug)]
+pub struct ScenarioRun {
+    pub scenario: &'static str,
+    /// The file the hook ran on, for file hooks.
+    pub path: Option<MPath>,
+    /// What the hook returned. Only scenarios with content missing from the
+    /// store can have errors, other errors are violations.
+    pub result: Result<HookExecution, Error>,
+}
+
+/// How a hook broke the contract of hooks in a scenario.
+#[derive(Debug, Error)]
+#[error("hook {kind} in scenario \"{scenario}\"")]
+pub struct Violation {
+    pub scenario: &'static str,
+    pub kind: ViolationKind,
+}
+
+#[derive(Debug)]
+pub enum ViolationKind {
+    /// `prepare` failed.
+    PrepareFailed(Error),
+    /// The hook returned an error although it had everything it needs.
+    Failed(Error),
+    /// The hook panicked, with this message.
+    Panicked(String),
+    /// A poll of the hook future took this long, so it ran synchronous work,
+    /// like IO, on the executor.
+    Blocked(Duration),
+    /// The hook didn't complete within the budget.
+    TimedOut(Duration),
+}
+
+impl fmt::Display for ViolationKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::PrepareFailed(e) => write!(f, "failed to prepare: {:#}", e),
+            Self::Failed(e) => write!(f, "failed: {:#}", e),
+            Self::Panicked(message) => write!(f, "panicked: {}", message),
+            Self::Blocked(duration) => write!(f, "blocked the executor for {:?}", duration),
+            Self::TimedOut(budget) => write!(f, "did not complete within {:?}", budget),
+        }
+    }
+}
+
+/// A synthetic changeset a hook is run against.
+struct Scenario {
+    name: &'static str,
+    changeset: BonsaiChangeset,
+    /// Whether the content of the files is missing from the store, so the
+    /// hook may fail.
+    missing_content: bool,
+    /// Whether the hook is prepared with a bypass that the changeset uses.
+    bypassed: bool,
+}
+
+/// Runs hooks against the scenarios, see the module documentation.
+pub struct HookTestKit {
+    ctx: CoreContext,
+    config: HookConfig,
+    budget: Duration,
+    max_poll: Duration,
+    store: InMemoryFileContentManager,
+    scenarios: Vec<Scenario>,
+}
+
+impl HookTestKit {
+    pub fn new(fb: FacebookInit) -> Result<Self> {
+        let mut store = InMemoryFileContentManager::new();
+        let scenarios = build_scenarios(&mut store)?;
+        Ok(Self {
+            ctx: CoreContext::test_mock(fb),
+            config: HookConfig::default(),
+            budget: Duration::from_secs(10),
+            max_poll: Duration::from_millis(100),
+            store,
+            scenarios,
+        })
+    }
+
+    /// The config hooks are prepared with, empty by default. The "bypassed
+    /// config" scenario adds a bypass to it.
+    pub fn with_config(mut self, config: HookConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// How long a hook may take on a scenario, 10s by default.
+    pub fn with_budget(mut self, budget: Duration) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// How long a single poll of a hook may take before it's considered
+    /// blocking, 100ms by default.
+    pub fn with_max_poll(mut self, max_poll: Duration) -> Self {
+        self.max_poll = max_poll;
+        self
+    }
+
+    /// Run `hook` on each file of each scenario.
+    pub async fn check_file_hook(
+        &self,
+        hook: &dyn FileHook,
+    ) -> Result<Vec<ScenarioRun>, Violation> {
+        let mut runs = vec![];
+        for scenario in &self.scenarios {
+            let prepared = self.prepare(scenario, |config| hook.prepare(config))?;
+            for (path, change) in scenario.changeset.file_changes() {
+                let copy_from = change
+                    .copy_from()
+                    .map(|(from, cs_id)| CopyFrom::new(&scenario.changeset, from, *cs_id));
+                let run = hook.run_prepared(
+                    &prepared,
+                    &self.ctx,
+                    &self.store,
+                    change.simplify(),
+                    path,
+                    copy_from,
+                    CrossRepoPushSource::NativeToThisRepo,
+                    PushAuthoredBy::User,
+                );
+                let result = self.run(scenario, run).await?;
+                runs.push(ScenarioRun {
+                    scenario: scenario.name,
+                    path: Some(path.clone()),
+                    result,
+                });
+            }
+        }
+        Ok(runs)
+    }
+
+    /// Run `hook` on each scenario.
+    pub async fn check_changeset_hook(
+        &self,
+        hook: &dyn ChangesetHook,
+    ) -> Result<Vec<ScenarioRun>, Violation> {
+        let bookmark = BookmarkKey::new(BOOKMARK).expect("valid bookmark name");
+        let mut runs = vec![];
+        for scenario in &self.scenarios {
+            let prepared = self.prepare(scenario, |config| hook.prepare(config))?;
+            let run = hook.run_prepared(
+                &prepared,
+                &self.ctx,
+                &bookmark,
+                &scenario.changeset,
+                &self.store,
+                CrossRepoPushSource::NativeToThisRepo,
+                PushAuthoredBy::User,
+            );
+            let result = self.run(scenario, run).await?;
+            runs.push(ScenarioRun {
+                scenario: scenario.name,
+                path: None,
+                result,
+            });
+        }
+        Ok(runs)
+    }
+
+    fn prepare<T>(
+        &self,
+        scenario: &Scenario,
+        prepare: impl FnOnce(&HookConfig) -> Result<T>,
+    ) -> Result<T, Violation> {
+        let mut config = self.config.clone();
+        if scenario.bypassed {
+            config.bypass = Some(HookBypass::new_with_commit_msg(BYPASS_MARKER.to_string()));
+        }
+        prepare(&config).map_err(|e| Violation {
+            scenario: scenario.name,
+            kind: ViolationKind::PrepareFailed(e),
+        })
+    }
+
+    async fn run(
+        &self,
+        scenario: &Scenario,
+        run: BoxFuture<'_, Result<HookExecution>>,
+    ) -> Result<Result<HookExecution>, Violation> {
+        let timed = TimedPolls {
+            inner: run,
+            longest: Duration::ZERO,
+        };
+        let outcome =
+            tokio::time::timeout(self.budget, AssertUnwindSafe(timed).catch_unwind()).await;
+        let kind = match outcome {
+            Err(_) => ViolationKind::TimedOut(self.budget),
+            Ok(Err(payload)) => ViolationKind::Panicked(panic_message(payload)),
+            Ok(Ok((_, longest))) if longest > self.max_poll => ViolationKind::Blocked(longest),
+            Ok(Ok((Err(e), _))) if !scenario.missing_content => ViolationKind::Failed(e),
+            Ok(Ok((result, _))) => return Ok(result),
+        };
+        Err(Violation {
+            scenario: scenario.name,
+            kind,
+        })
+    }
+}
+
+/// Polls `inner`, recording the longest poll.
+struct TimedPolls<'a, T> {
+    inner: BoxFuture<'a, T>,
+    longest: Duration,
+}
+
+impl<T> Future for TimedPolls<'_, T> {
+    type Output = (T, Duration);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let start = Instant::now();
+        let poll = self.inner.as_mut().poll(cx);
+        self.longest = self.longest.max(start.elapsed());
+        poll.map(|output| (output, self.longest))
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "<non-string panic payload>".to_string()
+    }
+}
+
+fn build_scenarios(store: &mut InMemoryFileContentManager) -> Result<Vec<Scenario>> {
+    let parent = ChangesetId::from_bytes([1; 32])?;
+    let other_parent = ChangesetId::from_bytes([2; 32])?;
+    let text = ContentId::from_bytes([1; 32])?;
+    let empty = ContentId::from_bytes([2; 32])?;
+    let huge = ContentId::from_bytes([3; 32])?;
+    let non_utf8 = ContentId::from_bytes([4; 32])?;
+    let missing = ContentId::from_bytes([5; 32])?;
+
+    let non_utf8_content = Bytes::from_static(b"\xff\xfe\x00not utf-8 \x80\n");
+    let non_utf8_size = non_utf8_content.len() as u64;
+    store.insert(text, "hello world\n");
+    store.insert(empty, "");
+    store.insert(
+        huge,
+        InMemoryFileText::Repeated {
+            byte: b'a',
+            size: HUGE_FILE_SIZE,
+        },
+    );
+    store.insert(non_utf8, non_utf8_content);
+
+    let file = |id, size| FileChange::tracked(id, FileType::Regular, size, None);
+    let scenarios = [
+        ("empty file", vec![parent], vec![("empty", file(empty, 0))]),
+        (
+            "huge file",
+            vec![parent],
+            vec![("huge", file(huge, HUGE_FILE_SIZE))],
+        ),
+        (
+            "non-UTF-8 file",
+            vec![parent],
+            vec![("non_utf8", file(non_utf8, non_utf8_size))],
+        ),
+        (
+            "deleted file",
+            vec![parent],
+            vec![("deleted", FileChange::Deletion), ("kept", file(text, 12))],
+        ),
+        (
+            "missing content",
+            vec![parent],
+            vec![("missing", file(missing, 12))],
+        ),
+        (
+            "merge changeset",
+            vec![parent, other_parent],
+            vec![("merged", file(text, 12))],
+        ),
+        (
+            "bypassed config",
+            vec![parent],
+            vec![("file", file(text, 12))],
+        ),
+    ];
+
+    let mut result = vec![];
+    for (name, parents, files) in scenarios {
+        let bypassed = name == "bypassed config";
+        let message = if bypassed {
+            format!("Test kit scenario: {}\n\n{}", name, BYPASS_MARKER)
+        } else {
+            format!("Test kit scenario: {}", name)
+        };
+        let changeset = BonsaiChangesetMut {
+            parents,
+            author: "Hook Test Kit <testkit@example.com>".to_string(),
+            author_date: DateTime::from_timestamp(1584887580, 0)?,
+            message,
+            file_changes: files
+                .into_iter()
+                .map(|(path, change)| Ok((MPath::new(path)?, change)))
+                .collect::<Result<_>>()?,
+            ..Default::default()
+        }
+        .freeze()?;
+        let cs_id = changeset.get_changeset_id();
+        for (path, change) in changeset.file_changes() {
+            if let Some(change) = change.simplify() {
+                store.insert_file_at(cs_id, path.clone(), change.content_id());
+            }
+        }
+        result.push(Scenario {
+            name,
+            changeset,
+            missing_content: name == "missing content",
+            bypassed,
+        });
+    }
+    Ok(result)
+}
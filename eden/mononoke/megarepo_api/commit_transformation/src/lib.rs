@@ -7,7 +7,9 @@
 
 #![feature(trait_alias)]
 
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
 
 use anyhow::anyhow;
@@ -43,6 +45,7 @@ use mononoke_types::FileChange;
 use mononoke_types::MPath;
 use mononoke_types::TrackedFileChange;
 use pushrebase::find_bonsai_diff;
+use regex::bytes::Regex;
 use repo_blobstore::RepoBlobstoreArc;
 use repo_blobstore::RepoBlobstoreRef;
 use repo_derived_data::RepoDerivedDataRef;
@@ -81,6 +84,38 @@ pub enum ErrorKind {
         "Can't reoder changesets parents to put {0} first because it's not a changeset's parent."
     )]
     MissingForcedParent(ChangesetId),
+    #[error("Source paths {sources:?} are all rewritten to {target} with conflicting changes")]
+    PathCollision { target: MPath, sources: Vec<MPath> },
+    #[error("Can't upload commit {child} in a batch before its parent {parent}")]
+    UploadParentAfterChild {
+        parent: ChangesetId,
+        child: ChangesetId,
+    },
+    #[error(
+        "Failed to upload batch {batch} of {batch_count} of rewritten commits, {} commits of previous batches were uploaded",
+        .uploaded.len()
+    )]
+    UploadBatchFailed {
+        /// Index of the failed batch, starting at 1.
+        batch: usize,
+        batch_count: usize,
+        /// Commits of the previous batches, which were fully uploaded.
+        uploaded: Vec<ChangesetId>,
+        /// Commits of the failed batch, some of which might be uploaded.
+        failed: Vec<ChangesetId>,
+        #[source]
+        source: Error,
+    },
+    #[error(
+        "Rewritten paths violate the target path policy: {}",
+        .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+    )]
+    TargetPathPolicyViolation(Vec<TargetPathViolation>),
+    #[error(
+        "File changes add files under paths they also add as files: {}",
+        .0.iter().map(|(file, under)| format!("{} (under {})", under, file)).collect::<Vec<_>>().join(", ")
+    )]
+    FileAddedUnderAddedFile(Vec<(MPath, MPath)>),
 }
 
 pub fn create_source_to_target_multi_mover(
@@ -178,14 +213,35 @@ async fn get_manifest_ids<'a, I: IntoIterator<Item = ChangesetId>>(
 /// Take an iterator of file changes, which may contain implicit deletes
 /// and produce a `SortedVectorMap` suitable to be used in the `BonsaiChangeset`,
 /// without any implicit deletes.
-fn minimize_file_change_set<I: IntoIterator<Item = (MPath, FileChange)>>(
+///
+/// A file replaced by a directory stays deleted explicitly, but deletes of
+/// files under a path that is added as a file are dropped. Adding both a
+/// file and files under its path is an error, as the result wouldn't be a
+/// valid changeset: `ErrorKind::FileAddedUnderAddedFile` lists such paths.
+pub fn minimize_file_change_set<I: IntoIterator<Item = (MPath, FileChange)>>(
     file_changes: I,
-) -> SortedVectorMap<MPath, FileChange> {
+) -> Result<SortedVectorMap<MPath, FileChange>, Error> {
     let (adds, removes): (Vec<_>, Vec<_>) = file_changes
         .into_iter()
         .partition(|(_, fc)| fc.is_changed());
     let adds: HashMap<MPath, FileChange> = adds.into_iter().collect();
 
+    let mut added_under_added: Vec<(MPath, MPath)> = adds
+        .keys()
+        .flat_map(|added_path| {
+            added_path
+                .clone()
+                .into_parent_dir_iter()
+                .skip(1)
+                .filter(|parent_dir| adds.contains_key(parent_dir))
+                .map(move |parent_dir| (parent_dir, added_path.clone()))
+        })
+        .collect();
+    if !added_under_added.is_empty() {
+        added_under_added.sort();
+        return Err(ErrorKind::FileAddedUnderAddedFile(added_under_added).into());
+    }
+
     let prefix_path_was_added = |removed_path: MPath| {
         removed_path
             .into_parent_dir_iter()
@@ -197,42 +253,214 @@ fn minimize_file_change_set<I: IntoIterator<Item = (MPath, FileChange)>>(
         .filter(|(ref mpath, _)| !prefix_path_was_added(mpath.clone()));
     let mut result: SortedVectorMap<_, _> = filtered_removes.collect();
     result.extend(adds.into_iter());
-    result
+    Ok(result)
+}
+
+/// Combine rewritten file changes, given as `(target path, source path, change)`
+/// triples, into a single change per target path.
+///
+/// If several source paths are rewritten onto the same target path and they
+/// don't all agree on the change, the collision is handled according to
+/// `resolution`. Changes are compared in full, so changes with the same
+/// content but a different file type collide. Entries for the same source path and target path (e.g. an
+/// explicit change and an implicit delete) are not a collision: the last one
+/// wins.
+fn resolve_path_collisions<I: IntoIterator<Item = (MPath, MPath, FileChange)>>(
+    file_changes: I,
+    resolution: PathCollisionResolution,
+) -> Result<Vec<(MPath, FileChange)>, Error> {
+    let mut changes_by_target: BTreeMap<MPath, BTreeMap<MPath, FileChange>> = BTreeMap::new();
+    for (target, source, change) in file_changes {
+        changes_by_target
+            .entry(target)
+            .or_default()
+            .insert(source, change);
+    }
+
+    changes_by_target
+        .into_iter()
+        .map(|(target, mut changes_by_source)| {
+            let mut changes = changes_by_source.values();
+            let first_change = changes.next();
+            let conflicting = changes.any(|change| Some(change) != first_change);
+            if conflicting && resolution == PathCollisionResolution::Error {
+                return Err(ErrorKind::PathCollision {
+                    target,
+                    sources: changes_by_source.into_keys().collect(),
+                }
+                .into());
+            }
+            // Sources are ordered, so this is the change from the smallest
+            // source path.
+            let (_, change) = changes_by_source
+                .pop_first()
+                .ok_or_else(|| anyhow!("no changes for {}", target))?;
+            Ok((target, change))
+        })
+        .collect()
 }
 
-/// Given a changeset and it's parents, get the list of file
-/// changes, which arise from "implicit deletes" as opposed
-/// to naive `MPath` rewriting in `cs.file_changes`. For
+/// Given a changeset and it's parents, get the "implicit deletes" of the
+/// changeset, as opposed to explicit deletions in `cs.file_changes`,
+/// rewritten with `mover`. Each delete is returned as a
+/// `(target path, source path, FileChange::Deletion)` triple. For
 /// more information about implicit deletes, please see
 /// `manifest/src/implici_deletes.rs`
+///
+/// A commit adding directories over large existing trees can implicitly
+/// delete a lot of paths, so they are rewritten as they are found, and only
+/// the ones that `mover` keeps are collected.
 async fn get_implicit_delete_file_changes<'a, I: IntoIterator<Item = ChangesetId>>(
     ctx: &'a CoreContext,
-    cs: BonsaiChangesetMut,
+    cs: &BonsaiChangesetMut,
     parent_changeset_ids: I,
     mover: MultiMover,
     source_repo: &'a impl Repo,
-) -> Result<Vec<(MPath, FileChange)>, Error> {
-    let parent_manifest_ids = get_manifest_ids(ctx, source_repo, parent_changeset_ids).await?;
+) -> Result<Vec<(MPath, MPath, FileChange)>, Error> {
     let file_adds: Vec<_> = cs
         .file_changes
         .iter()
         .filter_map(|(mpath, file_change)| file_change.is_changed().then(|| mpath.clone()))
         .collect();
+    // Only added paths can implicitly delete anything, so there's no need to
+    // look at the parents' manifests without them.
+    if file_adds.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let parent_manifest_ids = get_manifest_ids(ctx, source_repo, parent_changeset_ids).await?;
     let store = source_repo.repo_blobstore().clone();
-    let implicit_deletes: Vec<MPath> =
-        get_implicit_deletes(ctx, store, file_adds, parent_manifest_ids)
-            .try_collect()
-            .await?;
-    let maybe_renamed_implicit_deletes: Result<Vec<Vec<MPath>>, _> =
-        implicit_deletes.iter().map(|mpath| mover(mpath)).collect();
-    let maybe_renamed_implicit_deletes: Vec<Vec<MPath>> = maybe_renamed_implicit_deletes?;
-    let implicit_delete_file_changes: Vec<_> = maybe_renamed_implicit_deletes
+    get_implicit_deletes(ctx, store, file_adds, parent_manifest_ids)
+        .try_filter_map(|path| {
+            let changes = move_implicit_delete(&mover, path)
+                .map(|changes| (!changes.is_empty()).then_some(changes));
+            async move { changes }
+        })
+        .try_concat()
+        .await
+}
+
+/// Rewrite the implicitly deleted `path` with `mover`.
+fn move_implicit_delete(
+    mover: &MultiMover,
+    path: MPath,
+) -> Result<Vec<(MPath, MPath, FileChange)>, Error> {
+    let new_paths = mover(&path)?;
+    Ok(new_paths
         .into_iter()
-        .flatten()
-        .map(|implicit_delete_mpath| (implicit_delete_mpath, FileChange::Deletion))
-        .collect();
+        .map(|new_path| (new_path, path.clone(), FileChange::Deletion))
+        .collect())
+}
+
+/// Constraints on the paths commits are rewritten to, such as the limits of
+/// the target repo or of its clients. Checking them when rewriting makes
+/// commits with bad paths fail to sync, instead of failing later in derived
+/// data or checkouts.
+pub trait TargetPathPolicy: fmt::Debug + Send + Sync {
+    /// Return a description of the rule violated by `target_path`, which
+    /// `source_path` is rewritten to, or `None` if it is allowed.
+    fn check(&self, source_path: &MPath, target_path: &MPath) -> Option<String>;
+}
+
+/// A `TargetPathPolicy` limiting the length of paths and their components,
+/// and forbidding some components. The default allows all paths.
+#[derive(Clone, Debug, Default)]
+pub struct TargetPathLimits {
+    /// Maximum length of a path in bytes, including slashes.
+    pub max_path_bytes: Option<usize>,
+    /// Maximum length of a path component in bytes.
+    pub max_component_bytes: Option<usize>,
+    /// Forbid components that are not valid file names on Windows, such as
+    /// `CON`, `aux.txt` or names ending with a dot.
+    pub forbid_invalid_windows_names: bool,
+    /// Forbid components matching any of these regexes.
+    pub forbidden_components: Vec<Regex>,
+}
+
+impl TargetPathPolicy for TargetPathLimits {
+    fn check(&self, _source_path: &MPath, target_path: &MPath) -> Option<String> {
+        if let Some(max) = self.max_path_bytes {
+            if target_path.len() > max {
+                return Some(format!(
+                    "path is {} bytes long, more than {}",
+                    target_path.len(),
+                    max
+                ));
+            }
+        }
+        for element in target_path {
+            let name = String::from_utf8_lossy(element.as_ref());
+            if let Some(max) = self.max_component_bytes {
+                if element.len() > max {
+                    return Some(format!(
+                        "component '{}' is {} bytes long, more than {}",
+                        name,
+                        element.len(),
+                        max
+                    ));
+                }
+            }
+            if self.forbid_invalid_windows_names && !element.is_valid_windows_filename() {
+                return Some(format!(
+                    "component '{}' is not a valid file name on Windows",
+                    name
+                ));
+            }
+            if let Some(re) = self
+                .forbidden_components
+                .iter()
+                .find(|re| re.is_match(element.as_ref()))
+            {
+                return Some(format!("component '{}' matches '{}'", name, re));
+            }
+        }
+        None
+    }
+}
+
+/// A rewritten path violating a `TargetPathPolicy`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TargetPathViolation {
+    pub source_path: MPath,
+    pub target_path: MPath,
+    /// Description of the violated rule.
+    pub rule: String,
+}
+
+impl fmt::Display for TargetPathViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is rewritten to {}, but {}",
+            self.source_path, self.target_path, self.rule
+        )
+    }
+}
 
-    Ok(implicit_delete_file_changes)
+/// Check rewritten file changes, given as `(target path, source path, change)`
+/// triples, against `policy`, failing with all the violations found.
+fn check_target_paths<'a>(
+    policy: &dyn TargetPathPolicy,
+    file_changes: impl IntoIterator<Item = &'a (MPath, MPath, FileChange)>,
+) -> Result<(), ErrorKind> {
+    let mut violations: Vec<_> = file_changes
+        .into_iter()
+        .filter_map(|(target_path, source_path, _)| {
+            let rule = policy.check(source_path, target_path)?;
+            Some(TargetPathViolation {
+                source_path: source_path.clone(),
+                target_path: target_path.clone(),
+                rule,
+            })
+        })
+        .collect();
+    if violations.is_empty() {
+        return Ok(());
+    }
+    // A path can be both explicitly and implicitly deleted.
+    violations.sort();
+    violations.dedup();
+    Err(ErrorKind::TargetPathPolicyViolation(violations))
 }
 
 /// Determines what to do in commits rewriting to empty commit in small repo.
@@ -257,10 +485,59 @@ pub enum EmptyCommitFromLargeRepo {
     Discard,
 }
 
+/// Determines what to do when the mover rewrites several source paths of a
+/// commit onto the same target path, and their changes differ.
 #[derive(PartialEq, Debug, Copy, Clone, Default)]
+pub enum PathCollisionResolution {
+    /// Fail the rewrite with `ErrorKind::PathCollision`.
+    #[default]
+    Error,
+    /// Use the change from the smallest source path (in `MPath` order). Only
+    /// suitable for configs where such collisions are known to be benign.
+    PreferSmallestSourcePath,
+}
+
+/// Extra recording the id of the commit a rewritten commit was rewritten
+/// from, see `ExtrasPolicy::add_synced_from`.
+pub const SYNCED_FROM_EXTRA: &str = "synced-from";
+
+/// Determines what to do with the extras of rewritten commits. By default
+/// they are all kept as they are.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct ExtrasPolicy {
+    /// Keys of the extras to drop.
+    pub strip_keys: Vec<String>,
+    /// Record the id of the source commit in a `SYNCED_FROM_EXTRA` extra,
+    /// replacing any such extra of the source commit.
+    pub add_synced_from: bool,
+}
+
+impl ExtrasPolicy {
+    fn apply(&self, source_cs_id: Option<ChangesetId>, cs: &mut BonsaiChangesetMut) {
+        if !self.strip_keys.is_empty() {
+            cs.hg_extra = std::mem::take(&mut cs.hg_extra)
+                .into_iter()
+                .filter(|(key, _)| !self.strip_keys.contains(key))
+                .collect();
+        }
+        if let Some(source_cs_id) = source_cs_id {
+            cs.hg_extra.insert(
+                SYNCED_FROM_EXTRA.to_string(),
+                source_cs_id.to_string().into_bytes(),
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct RewriteOpts {
     pub commit_rewritten_to_empty: CommitRewrittenToEmpty,
     pub empty_commit_from_large_repo: EmptyCommitFromLargeRepo,
+    pub path_collision_resolution: PathCollisionResolution,
+    /// Policy all rewritten paths, including implicitly deleted ones, must
+    /// follow. `None` allows all paths.
+    pub target_path_policy: Option<Arc<dyn TargetPathPolicy>>,
+    pub extras_policy: ExtrasPolicy,
 }
 
 /// Create a version of `cs` with `Mover` applied to all changes
@@ -288,25 +565,21 @@ pub async fn rewrite_commit<'a>(
     force_first_parent: Option<ChangesetId>,
     rewrite_opts: RewriteOpts,
 ) -> Result<Option<BonsaiChangesetMut>, Error> {
-    let delete_file_changes = if !cs.file_changes.is_empty() {
-        get_implicit_delete_file_changes(
-            ctx,
-            cs.clone(),
-            remapped_parents.keys().cloned(),
-            mover.clone(),
-            source_repo,
-        )
-        .await?
-    } else {
-        vec![]
-    };
+    let implicit_delete_changes = get_implicit_delete_file_changes(
+        ctx,
+        &cs,
+        remapped_parents.keys().cloned(),
+        mover.clone(),
+        source_repo,
+    )
+    .await?;
 
-    internal_rewrite_commit_with_implicit_deletes(
+    rewrite_commit_with_implicit_delete_changes(
         cs,
         remapped_parents,
         mover,
         force_first_parent,
-        delete_file_changes,
+        implicit_delete_changes,
         rewrite_opts,
     )
 }
@@ -383,21 +656,16 @@ pub async fn rewrite_stack_no_merges<'a>(
     let css = stream::iter(css)
         .map({
             |cs| async move {
-                let deleted_file_changes = if cs.file_changes().next().is_some() {
-                    let parents = cs.parents();
-                    get_implicit_delete_file_changes(
-                        ctx,
-                        cs.clone().into_mut(),
-                        parents,
-                        mover.clone(),
-                        source_repo,
-                    )
-                    .await?
-                } else {
-                    vec![]
-                };
-
-                anyhow::Ok((cs, deleted_file_changes))
+                let implicit_delete_changes = get_implicit_delete_file_changes(
+                    ctx,
+                    &cs.clone().into_mut(),
+                    cs.parents(),
+                    mover.clone(),
+                    source_repo,
+                )
+                .await?;
+
+                anyhow::Ok((cs, implicit_delete_changes))
             }
         })
         .buffered(100)
@@ -405,7 +673,7 @@ pub async fn rewrite_stack_no_merges<'a>(
         .await?;
 
     let mut res = vec![];
-    for (from_cs, implicit_deletes_file_changes) in css {
+    for (from_cs, implicit_delete_changes) in css {
         let from_cs_id = from_cs.get_changeset_id();
         let from_cs = from_cs.into_mut();
 
@@ -414,12 +682,12 @@ pub async fn rewrite_stack_no_merges<'a>(
             remapped_parents.insert(*parent, rewritten_parent);
         }
 
-        let maybe_cs = internal_rewrite_commit_with_implicit_deletes(
+        let maybe_cs = rewrite_commit_with_implicit_delete_changes(
             from_cs,
             &remapped_parents,
             mover.clone(),
             force_first_parent,
-            implicit_deletes_file_changes,
+            implicit_delete_changes,
             Default::default(),
         )?;
 
@@ -439,18 +707,48 @@ pub async fn rewrite_stack_no_merges<'a>(
 }
 
 pub fn internal_rewrite_commit_with_implicit_deletes<'a>(
+    cs: BonsaiChangesetMut,
+    remapped_parents: &'a HashMap<ChangesetId, ChangesetId>,
+    mover: MultiMover,
+    force_first_parent: Option<ChangesetId>,
+    implicit_deletes: Vec<MPath>,
+    rewrite_opts: RewriteOpts,
+) -> Result<Option<BonsaiChangesetMut>, Error> {
+    let implicit_delete_changes = implicit_deletes
+        .into_iter()
+        .map(|path| move_implicit_delete(&mover, path))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    rewrite_commit_with_implicit_delete_changes(
+        cs,
+        remapped_parents,
+        mover,
+        force_first_parent,
+        implicit_delete_changes.into_iter().flatten().collect(),
+        rewrite_opts,
+    )
+}
+
+/// Like `internal_rewrite_commit_with_implicit_deletes`, with implicit
+/// deletes already rewritten by `get_implicit_delete_file_changes`.
+fn rewrite_commit_with_implicit_delete_changes<'a>(
     mut cs: BonsaiChangesetMut,
     remapped_parents: &'a HashMap<ChangesetId, ChangesetId>,
     mover: MultiMover,
     force_first_parent: Option<ChangesetId>,
-    implicit_delete_file_changes: Vec<(MPath, FileChange)>,
+    implicit_delete_changes: Vec<(MPath, MPath, FileChange)>,
     rewrite_opts: RewriteOpts,
 ) -> Result<Option<BonsaiChangesetMut>, Error> {
+    let source_cs_id = if rewrite_opts.extras_policy.add_synced_from {
+        Some(cs.clone().freeze()?.get_changeset_id())
+    } else {
+        None
+    };
     let empty_commit = cs.file_changes.is_empty();
     if !empty_commit
         || rewrite_opts.empty_commit_from_large_repo == EmptyCommitFromLargeRepo::Discard
     {
-        let path_rewritten_changes: Result<Vec<Vec<_>>, _> = cs
+        let path_rewritten_changes: Result<Vec<Vec<_>>, Error> = cs
             .file_changes
             .into_iter()
             .map(|(path, change)| {
@@ -518,17 +816,29 @@ pub fn internal_rewrite_commit_with_implicit_deletes<'a>(
                         .map(|new_path| (new_path, change.clone()))
                         .collect())
                 }
-                do_rewrite(path, change, remapped_parents, mover.clone())
+                let rewritten = do_rewrite(path.clone(), change, remapped_parents, mover.clone())?;
+                Ok(rewritten
+                    .into_iter()
+                    .map(|(new_path, change)| (new_path, path.clone(), change))
+                    .collect())
             })
             .collect();
 
-        let mut path_rewritten_changes: SortedVectorMap<_, _> = path_rewritten_changes?
+        // Implicit deletes come after the explicit changes, so for the same
+        // source path they take precedence.
+        let path_rewritten_changes: Vec<_> = path_rewritten_changes?
             .into_iter()
-            .flat_map(|changes| changes.into_iter())
+            .flatten()
+            .chain(implicit_delete_changes)
             .collect();
-
-        path_rewritten_changes.extend(implicit_delete_file_changes.into_iter());
-        let path_rewritten_changes = minimize_file_change_set(path_rewritten_changes.into_iter());
+        if let Some(policy) = &rewrite_opts.target_path_policy {
+            check_target_paths(policy.as_ref(), &path_rewritten_changes)?;
+        }
+        let path_rewritten_changes = resolve_path_collisions(
+            path_rewritten_changes,
+            rewrite_opts.path_collision_resolution,
+        )?;
+        let path_rewritten_changes = minimize_file_change_set(path_rewritten_changes)?;
         let is_merge = cs.parents.len() >= 2;
 
         // If all parent has < 2 commits then it's not a merge, and it was completely rewritten
@@ -566,32 +876,131 @@ pub fn internal_rewrite_commit_with_implicit_deletes<'a>(
         new_parents.extend(cs.parents.into_iter().filter(|cs| *cs != first_parent));
         cs.parents = new_parents
     }
+    rewrite_opts.extras_policy.apply(source_cs_id, &mut cs);
 
     Ok(Some(cs))
 }
 
+/// Options of `upload_commits_with_options`.
+#[derive(Clone, Copy, Debug)]
+pub struct UploadCommitsOptions {
+    /// Lists of more commits than this are uploaded in batches.
+    pub batch_threshold: usize,
+    /// Number of commits per batch when uploading in batches.
+    pub batch_size: usize,
+}
+
+impl Default for UploadCommitsOptions {
+    fn default() -> Self {
+        Self {
+            batch_threshold: 100,
+            batch_size: 100,
+        }
+    }
+}
+
 pub async fn upload_commits<'a>(
     ctx: &'a CoreContext,
     rewritten_list: Vec<BonsaiChangeset>,
     source_repo: &'a (impl RepoBlobstoreRef + ChangesetsRef),
     target_repo: &'a (impl RepoBlobstoreRef + ChangesetsRef + FilestoreConfigRef),
 ) -> Result<(), Error> {
-    let mut files_to_sync = vec![];
-    for rewritten in &rewritten_list {
-        let rewritten_mut = rewritten.clone().into_mut();
-        let new_files_to_sync =
-            rewritten_mut
-                .file_changes
-                .values()
-                .filter_map(|change| match change {
-                    FileChange::Change(tc) => Some(tc.content_id()),
-                    FileChange::UntrackedChange(uc) => Some(uc.content_id()),
-                    FileChange::Deletion | FileChange::UntrackedDeletion => None,
-                });
-        files_to_sync.extend(new_files_to_sync);
+    upload_commits_with_options(
+        ctx,
+        rewritten_list,
+        source_repo,
+        target_repo,
+        UploadCommitsOptions::default(),
+    )
+    .await
+}
+
+/// Copy the contents of the rewritten commits from `source_repo`, and save
+/// the commits in `target_repo`.
+///
+/// Lists above `options.batch_threshold` are uploaded in batches, each saved
+/// before copying the contents of the next one, so a failure leaves the
+/// previous batches fully uploaded and is reported as
+/// `ErrorKind::UploadBatchFailed`. In that case, parents must come before
+/// their children in `rewritten_list`.
+pub async fn upload_commits_with_options<'a>(
+    ctx: &'a CoreContext,
+    rewritten_list: Vec<BonsaiChangeset>,
+    source_repo: &'a (impl RepoBlobstoreRef + ChangesetsRef),
+    target_repo: &'a (impl RepoBlobstoreRef + ChangesetsRef + FilestoreConfigRef),
+    options: UploadCommitsOptions,
+) -> Result<(), Error> {
+    if rewritten_list.len() <= options.batch_threshold {
+        return upload_batch(ctx, rewritten_list, source_repo, target_repo).await;
+    }
+
+    check_parents_first(&rewritten_list)?;
+    let batch_size = options.batch_size.max(1);
+    let batch_count = rewritten_list.len().div_ceil(batch_size);
+    let mut uploaded = Vec::with_capacity(rewritten_list.len());
+    let mut remaining = rewritten_list.into_iter().peekable();
+    let mut batch = 0;
+    while remaining.peek().is_some() {
+        batch += 1;
+        let batch_list: Vec<_> = remaining.by_ref().take(batch_size).collect();
+        let batch_ids: Vec<_> = batch_list
+            .iter()
+            .map(|bcs| bcs.get_changeset_id())
+            .collect();
+        if let Err(source) = upload_batch(ctx, batch_list, source_repo, target_repo).await {
+            return Err(ErrorKind::UploadBatchFailed {
+                batch,
+                batch_count,
+                uploaded,
+                failed: batch_ids,
+                source,
+            }
+            .into());
+        }
+        uploaded.extend(batch_ids);
+    }
+    Ok(())
+}
+
+/// Check that the parents of each commit of `rewritten_list` that are in the
+/// list come before it.
+fn check_parents_first(rewritten_list: &[BonsaiChangeset]) -> Result<(), Error> {
+    let positions: HashMap<ChangesetId, usize> = rewritten_list
+        .iter()
+        .enumerate()
+        .map(|(i, bcs)| (bcs.get_changeset_id(), i))
+        .collect();
+    for (i, bcs) in rewritten_list.iter().enumerate() {
+        for parent in bcs.parents() {
+            if matches!(positions.get(&parent), Some(position) if *position > i) {
+                return Err(ErrorKind::UploadParentAfterChild {
+                    parent,
+                    child: bcs.get_changeset_id(),
+                }
+                .into());
+            }
+        }
     }
+    Ok(())
+}
+
+async fn upload_batch<'a>(
+    ctx: &'a CoreContext,
+    rewritten_list: Vec<BonsaiChangeset>,
+    source_repo: &'a (impl RepoBlobstoreRef + ChangesetsRef),
+    target_repo: &'a (impl RepoBlobstoreRef + ChangesetsRef + FilestoreConfigRef),
+) -> Result<(), Error> {
+    let files_to_sync: Vec<_> = rewritten_list
+        .iter()
+        .flat_map(|rewritten| rewritten.file_changes())
+        .filter_map(|(_, change)| match change {
+            FileChange::Change(tc) => Some(tc.content_id()),
+            FileChange::UntrackedChange(uc) => Some(uc.content_id()),
+            FileChange::Deletion | FileChange::UntrackedDeletion => None,
+        })
+        .collect();
     copy_file_contents(ctx, source_repo, target_repo, files_to_sync, |_| {}).await?;
-    save_bonsai_changesets(rewritten_list.clone(), ctx.clone(), target_repo).await?;
+    save_bonsai_changesets(rewritten_list, ctx.clone(), target_repo).await?;
     Ok(())
 }
 
@@ -630,12 +1039,20 @@ pub async fn copy_file_contents<'a>(
 #[cfg(test)]
 mod test {
     use std::collections::BTreeMap;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
 
     use anyhow::bail;
+    use async_trait::async_trait;
     use blobrepo::save_bonsai_changesets;
+    use blobstore::Blobstore;
+    use blobstore::BlobstoreBytes;
+    use blobstore::BlobstoreGetData;
     use fbinit::FacebookInit;
     use maplit::btreemap;
     use maplit::hashmap;
+    use memblob::Memblob;
+    use mononoke_types::BlobstoreKey;
     use mononoke_types::ContentId;
     use mononoke_types::FileType;
     use test_repo_factory::TestRepoFactory;
@@ -732,6 +1149,42 @@ mod test {
         MPath::new(p).unwrap()
     }
 
+    #[test]
+    fn test_target_path_limits() -> Result<(), Error> {
+        let check = |limits: &TargetPathLimits, p: &str| limits.check(&path("src"), &path(p));
+
+        let permissive = TargetPathLimits::default();
+        assert_eq!(
+            check(&permissive, &format!("{}/aux.txt", "a".repeat(300))),
+            None
+        );
+
+        let limits = TargetPathLimits {
+            max_path_bytes: Some(12),
+            max_component_bytes: Some(4),
+            forbid_invalid_windows_names: true,
+            forbidden_components: vec![Regex::new("^~")?],
+        };
+        assert_eq!(check(&limits, "dir/file"), None);
+        assert_eq!(
+            check(&limits, "dir/file/file"),
+            Some("path is 13 bytes long, more than 12".to_string())
+        );
+        assert_eq!(
+            check(&limits, "dir/files"),
+            Some("component 'files' is 5 bytes long, more than 4".to_string())
+        );
+        assert_eq!(
+            check(&limits, "dir/CON"),
+            Some("component 'CON' is not a valid file name on Windows".to_string())
+        );
+        assert_eq!(
+            check(&limits, "~dir/file"),
+            Some("component '~dir' matches '^~'".to_string())
+        );
+        Ok(())
+    }
+
     fn verify_minimized(changes: Vec<(&str, Option<()>)>, expected: BTreeMap<&str, Option<()>>) {
         fn to_file_change(o: Option<()>) -> FileChange {
             match o {
@@ -748,7 +1201,7 @@ mod test {
             .into_iter()
             .map(|(p, c)| (path(p), to_file_change(c)))
             .collect();
-        let minimized = minimize_file_change_set(changes);
+        let minimized = minimize_file_change_set(changes).unwrap();
         let expected: SortedVectorMap<MPath, FileChange> = expected
             .into_iter()
             .map(|(p, c)| (path(p), to_file_change(c)))
@@ -756,6 +1209,29 @@ mod test {
         assert_eq!(expected, minimized);
     }
 
+    fn verify_added_under_added(added: Vec<&str>, expected: Vec<(&str, &str)>) {
+        let changes = added.into_iter().map(|p| {
+            (
+                path(p),
+                FileChange::tracked(
+                    ContentId::from_bytes([1; 32]).unwrap(),
+                    FileType::Regular,
+                    0,
+                    None,
+                ),
+            )
+        });
+        let err = minimize_file_change_set(changes).unwrap_err();
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(file, under)| (path(file), path(under)))
+            .collect();
+        match err.downcast_ref::<ErrorKind>() {
+            Some(ErrorKind::FileAddedUnderAddedFile(paths)) => assert_eq!(paths, &expected),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
     #[fbinit::test]
     fn test_minimize_file_change_set(_fb: FacebookInit) {
         verify_minimized(
@@ -778,6 +1254,55 @@ mod test {
         );
     }
 
+    #[fbinit::test]
+    fn test_minimize_file_change_set_nested_swaps(_fb: FacebookInit) {
+        // a file three levels deep replaced with a directory
+        verify_minimized(
+            vec![
+                ("a/b/c", None),
+                ("a/b/c/d", Some(())),
+                ("a/b/c/e", Some(())),
+            ],
+            btreemap! { "a/b/c" => None, "a/b/c/d" => Some(()), "a/b/c/e" => Some(()) },
+        );
+        // a directory three levels deep replaced with a file
+        verify_minimized(
+            vec![
+                ("a/b/c", Some(())),
+                ("a/b/c/d", None),
+                ("a/b/c/e/f", None),
+                ("a/b/g", None),
+            ],
+            btreemap! { "a/b/c" => Some(()), "a/b/g" => None },
+        );
+        // both at once: "a/b" becomes a directory, and "a/b/c" in it a file
+        // replacing the former "a/b/c" directory of another path
+        verify_minimized(
+            vec![
+                ("a/b", None),
+                ("a/b/c", Some(())),
+                ("a/b/c/d", None),
+                ("x/y/z", Some(())),
+                ("x/y/z/w", None),
+            ],
+            btreemap! { "a/b" => None, "a/b/c" => Some(()), "x/y/z" => Some(()) },
+        );
+    }
+
+    #[fbinit::test]
+    fn test_minimize_file_change_set_added_under_added(_fb: FacebookInit) {
+        verify_added_under_added(vec!["a", "a/b"], vec![("a", "a/b")]);
+        verify_added_under_added(
+            vec!["a/b", "a/b/c/d", "a/b/c/e", "f"],
+            vec![("a/b", "a/b/c/d"), ("a/b", "a/b/c/e")],
+        );
+        // every added ancestor is reported
+        verify_added_under_added(
+            vec!["a", "a/b", "a/b/c"],
+            vec![("a", "a/b"), ("a", "a/b/c"), ("a/b", "a/b/c")],
+        );
+    }
+
     #[fbinit::test]
     async fn test_rewrite_commit(fb: FacebookInit) -> Result<(), Error> {
         let repo: blobrepo::BlobRepo = TestRepoFactory::new(fb)?.build().await?;
@@ -910,6 +1435,497 @@ mod test {
         Ok(())
     }
 
+    fn basename_mover() -> MultiMover {
+        Arc::new(|path: &MPath| Ok(vec![MPath::new(path.basename())?]))
+    }
+
+    async fn rewrite_with_collision_resolution<'a>(
+        ctx: &'a CoreContext,
+        repo: &'a impl Repo,
+        bcs_id: ChangesetId,
+        parents: HashMap<ChangesetId, ChangesetId>,
+        path_collision_resolution: PathCollisionResolution,
+    ) -> Result<Option<BonsaiChangesetMut>, Error> {
+        let bcs = bcs_id.load(ctx, &repo.repo_blobstore()).await?;
+        rewrite_commit(
+            ctx,
+            bcs.into_mut(),
+            &parents,
+            basename_mover(),
+            repo,
+            None,
+            RewriteOpts {
+                path_collision_resolution,
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    fn assert_path_collision(err: Error, expected_target: &str, expected_sources: &[&str]) {
+        match err.downcast_ref::<ErrorKind>() {
+            Some(ErrorKind::PathCollision { target, sources }) => {
+                assert_eq!(target, &path(expected_target));
+                let expected_sources: Vec<_> = expected_sources.iter().copied().map(path).collect();
+                assert_eq!(sources, &expected_sources);
+            }
+            _ => panic!("expected PathCollision, got {:?}", err),
+        }
+    }
+
+    #[fbinit::test]
+    async fn test_rewrite_commit_path_collision(fb: FacebookInit) -> Result<(), Error> {
+        let repo: blobrepo::BlobRepo = TestRepoFactory::new(fb)?.build().await?;
+        let ctx = CoreContext::test_mock(fb);
+        let root = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("a/x", "a")
+            .add_file("b/x", "b")
+            .commit()
+            .await?;
+
+        let err = rewrite_with_collision_resolution(
+            &ctx,
+            &repo,
+            root,
+            HashMap::new(),
+            PathCollisionResolution::Error,
+        )
+        .await
+        .unwrap_err();
+        assert_path_collision(err, "x", &["a/x", "b/x"]);
+
+        let rewritten = rewrite_with_collision_resolution(
+            &ctx,
+            &repo,
+            root,
+            HashMap::new(),
+            PathCollisionResolution::PreferSmallestSourcePath,
+        )
+        .await?
+        .ok_or_else(|| anyhow!("commit was rewritten out"))?;
+        let root_bcs = root.load(&ctx, &repo.repo_blobstore()).await?;
+        assert_eq!(
+            rewritten.file_changes.keys().collect::<Vec<_>>(),
+            vec![&path("x")]
+        );
+        assert_eq!(
+            rewritten.file_changes.get(&path("x")),
+            root_bcs.file_changes_map().get(&path("a/x"))
+        );
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_rewrite_commit_path_collision_with_implicit_delete(
+        fb: FacebookInit,
+    ) -> Result<(), Error> {
+        let repo: blobrepo::BlobRepo = TestRepoFactory::new(fb)?.build().await?;
+        let ctx = CoreContext::test_mock(fb);
+        let root = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("a/x", "a")
+            .add_file("b/x", "b")
+            .commit()
+            .await?;
+        // Replacing directory "b" with a file implicitly deletes "b/x", which
+        // collides with the change to "a/x".
+        let child = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("a/x", "a2")
+            .add_file("b", "b")
+            .commit()
+            .await?;
+        let parents = hashmap! { root => root };
+
+        let err = rewrite_with_collision_resolution(
+            &ctx,
+            &repo,
+            child,
+            parents.clone(),
+            PathCollisionResolution::Error,
+        )
+        .await
+        .unwrap_err();
+        assert_path_collision(err, "x", &["a/x", "b/x"]);
+
+        let rewritten = rewrite_with_collision_resolution(
+            &ctx,
+            &repo,
+            child,
+            parents,
+            PathCollisionResolution::PreferSmallestSourcePath,
+        )
+        .await?
+        .ok_or_else(|| anyhow!("commit was rewritten out"))?;
+        let child_bcs = child.load(&ctx, &repo.repo_blobstore()).await?;
+        assert_eq!(
+            rewritten.file_changes.get(&path("x")),
+            child_bcs.file_changes_map().get(&path("a/x"))
+        );
+        assert_eq!(
+            rewritten.file_changes.get(&path("b")),
+            child_bcs.file_changes_map().get(&path("b"))
+        );
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_rewrite_commit_file_type_only_change(fb: FacebookInit) -> Result<(), Error> {
+        let repo: blobrepo::BlobRepo = TestRepoFactory::new(fb)?.build().await?;
+        let ctx = CoreContext::test_mock(fb);
+        let root = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("a/x", "x")
+            .add_file("b/y", "y")
+            .commit()
+            .await?;
+        let parents = hashmap! { root => root };
+
+        // Only the exec bit changes, which is still a change to rewrite.
+        let exec = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file_with_type("a/x", "x", FileType::Executable)
+            .commit()
+            .await?;
+        let rewritten = rewrite_with_collision_resolution(
+            &ctx,
+            &repo,
+            exec,
+            parents.clone(),
+            PathCollisionResolution::Error,
+        )
+        .await?
+        .ok_or_else(|| anyhow!("commit was rewritten out"))?;
+        let exec_bcs = exec.load(&ctx, &repo.repo_blobstore()).await?;
+        assert_eq!(
+            rewritten.file_changes.keys().collect::<Vec<_>>(),
+            vec![&path("x")]
+        );
+        assert_eq!(
+            rewritten.file_changes.get(&path("x")),
+            exec_bcs.file_changes_map().get(&path("a/x"))
+        );
+
+        // Changes with the same content but different file types collide.
+        let collision = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file_with_type("a/z", "z", FileType::Executable)
+            .add_file("b/z", "z")
+            .commit()
+            .await?;
+        let err = rewrite_with_collision_resolution(
+            &ctx,
+            &repo,
+            collision,
+            parents,
+            PathCollisionResolution::Error,
+        )
+        .await
+        .unwrap_err();
+        assert_path_collision(err, "z", &["a/z", "b/z"]);
+
+        Ok(())
+    }
+
+    #[derive(Debug)]
+    struct CountingBlobstore {
+        gets: AtomicUsize,
+        inner: Memblob,
+    }
+
+    impl std::fmt::Display for CountingBlobstore {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "CountingBlobstore")
+        }
+    }
+
+    impl CountingBlobstore {
+        fn new() -> Self {
+            Self {
+                gets: AtomicUsize::new(0),
+                inner: Memblob::default(),
+            }
+        }
+
+        fn gets_count(&self) -> usize {
+            self.gets.load(Ordering::Relaxed)
+        }
+    }
+
+    #[async_trait]
+    impl Blobstore for CountingBlobstore {
+        async fn put<'a>(
+            &'a self,
+            ctx: &'a CoreContext,
+            key: String,
+            value: BlobstoreBytes,
+        ) -> Result<(), Error> {
+            self.inner.put(ctx, key, value).await
+        }
+
+        async fn get<'a>(
+            &'a self,
+            ctx: &'a CoreContext,
+            key: &'a str,
+        ) -> Result<Option<BlobstoreGetData>, Error> {
+            self.gets.fetch_add(1, Ordering::Relaxed);
+            self.inner.get(ctx, key).await
+        }
+    }
+
+    #[fbinit::test]
+    async fn test_rewrite_commit_without_file_adds(fb: FacebookInit) -> Result<(), Error> {
+        let blobstore = Arc::new(CountingBlobstore::new());
+        let repo: blobrepo::BlobRepo = TestRepoFactory::new(fb)?
+            .with_blobstore(blobstore.clone())
+            .build()
+            .await?;
+        let ctx = CoreContext::test_mock(fb);
+        let root = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("dir/a", "a")
+            .add_file("dir/b", "b")
+            .commit()
+            .await?;
+        let child = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .delete_file("dir/a")
+            .commit()
+            .await?;
+        let child_bcs = child.load(&ctx, &repo.repo_blobstore()).await?;
+
+        // Only deletions can't implicitly delete anything, so the parent's
+        // manifest must not be looked up (or derived).
+        let gets_count = blobstore.gets_count();
+        let rewritten = rewrite_commit(
+            &ctx,
+            child_bcs.into_mut(),
+            &hashmap! { root => root },
+            basename_mover(),
+            &repo,
+            None,
+            Default::default(),
+        )
+        .await?
+        .ok_or_else(|| anyhow!("commit was rewritten out"))?;
+        assert_eq!(blobstore.gets_count(), gets_count);
+        assert_eq!(
+            rewritten.file_changes.into_iter().collect::<Vec<_>>(),
+            vec![(path("a"), FileChange::Deletion)]
+        );
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_rewrite_commit_directory_overwrite(fb: FacebookInit) -> Result<(), Error> {
+        let repo: blobrepo::BlobRepo = TestRepoFactory::new(fb)?.build().await?;
+        let ctx = CoreContext::test_mock(fb);
+        let root = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("dir/a", "a")
+            .add_file("dir/sub/b", "b")
+            .add_file("dir/dropped", "dropped")
+            .add_file("other", "other")
+            .commit()
+            .await?;
+        // Replacing directory "dir" with a file implicitly deletes everything
+        // under it.
+        let child = CreateCommitContext::new(&ctx, &repo, vec![root])
+            .add_file("dir", "dir")
+            .commit()
+            .await?;
+        let child_bcs = child.load(&ctx, &repo.repo_blobstore()).await?;
+
+        // Move the contents of "dir" elsewhere, so that the implicit deletes
+        // are not minimized away by the addition of "dir", and drop
+        // "dir/dropped".
+        let mover: MultiMover = Arc::new(|mpath: &MPath| {
+            let dir = path("dir");
+            if mpath == &path("dir/dropped") {
+                Ok(vec![])
+            } else if mpath != &dir && dir.is_prefix_of(mpath) {
+                Ok(vec![MPath::new("moved")?.join(mpath.into_iter().skip(1))])
+            } else {
+                Ok(vec![mpath.clone()])
+            }
+        });
+        let rewritten = rewrite_commit(
+            &ctx,
+            child_bcs.clone().into_mut(),
+            &hashmap! { root => root },
+            mover,
+            &repo,
+            None,
+            Default::default(),
+        )
+        .await?
+        .ok_or_else(|| anyhow!("commit was rewritten out"))?;
+
+        assert_eq!(
+            rewritten.file_changes.keys().collect::<Vec<_>>(),
+            vec![&path("dir"), &path("moved/a"), &path("moved/sub/b")]
+        );
+        assert_eq!(
+            rewritten.file_changes.get(&path("dir")),
+            child_bcs.file_changes_map().get(&path("dir"))
+        );
+        assert_eq!(
+            rewritten.file_changes.get(&path("moved/a")),
+            Some(&FileChange::Deletion)
+        );
+        assert_eq!(
+            rewritten.file_changes.get(&path("moved/sub/b")),
+            Some(&FileChange::Deletion)
+        );
+
+        Ok(())
+    }
+
+    /// Memblob failing to put the blob of a changeset.
+    #[derive(Debug)]
+    struct FailingBlobstore {
+        fail_changeset: ChangesetId,
+        inner: Memblob,
+    }
+
+    impl std::fmt::Display for FailingBlobstore {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "FailingBlobstore")
+        }
+    }
+
+    #[async_trait]
+    impl Blobstore for FailingBlobstore {
+        async fn put<'a>(
+            &'a self,
+            ctx: &'a CoreContext,
+            key: String,
+            value: BlobstoreBytes,
+        ) -> Result<(), Error> {
+            if key.ends_with(&self.fail_changeset.blobstore_key()) {
+                bail!("injected failure for {}", key);
+            }
+            self.inner.put(ctx, key, value).await
+        }
+
+        async fn get<'a>(
+            &'a self,
+            ctx: &'a CoreContext,
+            key: &'a str,
+        ) -> Result<Option<BlobstoreGetData>, Error> {
+            self.inner.get(ctx, key).await
+        }
+    }
+
+    /// Create a chain of `len` commits, each adding a file.
+    async fn create_chain(
+        ctx: &CoreContext,
+        repo: &blobrepo::BlobRepo,
+        len: usize,
+    ) -> Result<Vec<BonsaiChangeset>, Error> {
+        let mut chain: Vec<BonsaiChangeset> = Vec::with_capacity(len);
+        for i in 0..len {
+            let parents: Vec<_> = chain
+                .last()
+                .map(|p| p.get_changeset_id())
+                .into_iter()
+                .collect();
+            let cs_id = CreateCommitContext::new(ctx, repo, parents)
+                .add_file(format!("file{}", i).as_str(), format!("content{}", i))
+                .commit()
+                .await?;
+            chain.push(cs_id.load(ctx, &repo.repo_blobstore()).await?);
+        }
+        Ok(chain)
+    }
+
+    async fn exists(
+        ctx: &CoreContext,
+        repo: &blobrepo::BlobRepo,
+        chain: &[BonsaiChangeset],
+    ) -> Result<Vec<bool>, Error> {
+        try_join_all(
+            chain
+                .iter()
+                .map(|bcs| repo.changesets().exists(ctx, bcs.get_changeset_id())),
+        )
+        .await
+    }
+
+    #[fbinit::test]
+    async fn test_upload_commits_in_batches(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let source: blobrepo::BlobRepo = TestRepoFactory::new(fb)?.build().await?;
+        let target: blobrepo::BlobRepo = TestRepoFactory::new(fb)?.build().await?;
+        let chain = create_chain(&ctx, &source, 500).await?;
+        let options = UploadCommitsOptions {
+            batch_threshold: 10,
+            batch_size: 7,
+        };
+
+        // Children can't be uploaded in batches before their parents.
+        let mut reversed = chain.clone();
+        reversed.reverse();
+        let err = upload_commits_with_options(&ctx, reversed, &source, &target, options)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ErrorKind>(),
+            Some(ErrorKind::UploadParentAfterChild { .. })
+        ));
+
+        upload_commits_with_options(&ctx, chain.clone(), &source, &target, options).await?;
+        assert!(exists(&ctx, &target, &chain).await?.into_iter().all(|e| e));
+        let head = chain.last().unwrap().get_changeset_id();
+        let working_copy = list_working_copy_utf8(&ctx, &target, head).await?;
+        assert_eq!(working_copy.len(), 500);
+        assert_eq!(
+            working_copy.get(&path("file123")),
+            Some(&"content123".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_upload_commits_batch_failure(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let source: blobrepo::BlobRepo = TestRepoFactory::new(fb)?.build().await?;
+        let chain = create_chain(&ctx, &source, 30).await?;
+        let ids: Vec<_> = chain.iter().map(|bcs| bcs.get_changeset_id()).collect();
+        let target: blobrepo::BlobRepo = TestRepoFactory::new(fb)?
+            .with_blobstore(Arc::new(FailingBlobstore {
+                fail_changeset: ids[12],
+                inner: Memblob::default(),
+            }))
+            .build()
+            .await?;
+        let options = UploadCommitsOptions {
+            batch_threshold: 10,
+            batch_size: 5,
+        };
+
+        let err = upload_commits_with_options(&ctx, chain.clone(), &source, &target, options)
+            .await
+            .unwrap_err();
+        match err.downcast_ref::<ErrorKind>() {
+            Some(ErrorKind::UploadBatchFailed {
+                batch,
+                batch_count,
+                uploaded,
+                failed,
+                ..
+            }) => {
+                assert_eq!((*batch, *batch_count), (3, 6));
+                assert_eq!(uploaded, &ids[..10]);
+                assert_eq!(failed, &ids[10..15]);
+            }
+            _ => panic!("expected UploadBatchFailed, got {:?}", err),
+        }
+
+        // The first two batches were saved, the later ones weren't attempted.
+        let exists = exists(&ctx, &target, &chain).await?;
+        assert!(exists[..10].iter().all(|e| *e));
+        assert!(!exists[15..].iter().any(|e| *e));
+
+        Ok(())
+    }
+
     async fn test_rewrite_commit_cs_id<'a>(
         ctx: &'a CoreContext,
         repo: &'a impl Repo,
@@ -194,6 +194,7 @@ fn parse_with_repo_definition(
         bookmarks,
         hook_manager_params,
         hooks,
+        hook_defaults,
         redaction,
         generation_cache_size,
         scuba_table_hooks,
@@ -233,6 +234,7 @@ fn parse_with_repo_definition(
     let enabled = enabled.unwrap_or(true);
 
     let hooks: Vec<_> = hooks.unwrap_or_default().convert()?;
+    let hook_defaults = hook_defaults.convert()?.unwrap_or_default();
 
     let get_storage = move |name: &str| -> Result<StorageConfig> {
         let raw_storage_config = storage
@@ -350,6 +352,7 @@ fn parse_with_repo_definition(
         hook_manager_params,
         bookmarks,
         hooks,
+        hook_defaults,
         push,
         pushrebase,
         lfs,
@@ -523,6 +526,7 @@ mod test {
     use metaconfig_types::MetadataDatabaseConfig;
     use metaconfig_types::MultiplexId;
     use metaconfig_types::MultiplexedStoreType;
+    use metaconfig_types::PartialHookConfig;
     use metaconfig_types::PushParams;
     use metaconfig_types::PushrebaseFlags;
     use metaconfig_types::PushrebaseParams;
@@ -794,6 +798,7 @@ mod test {
             disable_acl_checker=false
             all_hooks_bypassed=false
             bypassed_commits_scuba_table="commits_bypassed_hooks"
+            default_hook_timeout_ms=60000
 
             [derived_data_config]
             enabled_config_name = "default"
@@ -821,6 +826,7 @@ mod test {
             [[hooks]]
             name="hook1"
             bypass_commit_string="@allow_hook1"
+            timeout_ms=5000
 
             [[hooks]]
             name="rust:rusthook"
@@ -829,6 +835,11 @@ mod test {
             [hooks.config_string_lists]
                 list1 = ["val1", "val2"]
 
+            [hook_defaults]
+            bypass_pushvar="BYPASS_ALL_HOOKS=true"
+            config_strings={ log_level = "info" }
+            timeout_ms=30000
+
             [push]
             pure_push_allowed = false
 
@@ -1090,6 +1101,7 @@ mod test {
                     disable_acl_checker: false,
                     all_hooks_bypassed: false,
                     bypassed_commits_scuba_table: Some("commits_bypassed_hooks".to_string()),
+                    default_hook_timeout_ms: Some(60000),
                 }),
                 bookmarks: vec![
                     BookmarkParams {
@@ -1102,6 +1114,7 @@ mod test {
                         hooks_skip_ancestors_of: vec![],
                         ensure_ancestor_of: None,
                         allow_move_to_public_commits_without_hooks: false,
+                        pusher_gate: None,
                     },
                     BookmarkParams {
                         bookmark: Regex::new("[^/]*/stable").unwrap().into(),
@@ -1113,11 +1126,13 @@ mod test {
                         hooks_skip_ancestors_of: vec![],
                         ensure_ancestor_of: Some(BookmarkKey::new("master").unwrap()),
                         allow_move_to_public_commits_without_hooks: true,
+                        pusher_gate: None,
                     },
                 ],
                 hooks: vec![
                     HookParams {
                         name: "hook1".to_string(),
+                        kind: None,
                         config: HookConfig {
                             bypass: Some(HookBypass::new_with_commit_msg("@allow_hook1".into())),
                             strings: hashmap! {},
@@ -1126,10 +1141,12 @@ mod test {
                             string_lists: hashmap! {},
                             int_lists: hashmap! {},
                             int_64_lists: hashmap! {},
+                            timeout_ms: Some(5000),
                         },
                     },
                     HookParams {
                         name: "rust:rusthook".to_string(),
+                        kind: None,
                         config: HookConfig {
                             bypass: None,
                             strings: hashmap! {},
@@ -1144,9 +1161,21 @@ mod test {
                             },
                             int_lists: hashmap! {},
                             int_64_lists: hashmap! {},
+                            timeout_ms: None,
                         },
                     },
                 ],
+                hook_defaults: PartialHookConfig {
+                    bypass: Some(HookBypass::new_with_pushvar(
+                        "BYPASS_ALL_HOOKS".into(),
+                        "true".into(),
+                    )),
+                    strings: Some(hashmap! {
+                        "log_level".into() => "info".into(),
+                    }),
+                    timeout_ms: Some(30000),
+                    ..Default::default()
+                },
                 push: PushParams {
                     pure_push_allowed: false,
                 },
@@ -1322,6 +1351,7 @@ mod test {
                 hook_manager_params: None,
                 bookmarks: vec![],
                 hooks: vec![],
+                hook_defaults: Default::default(),
                 push: Default::default(),
                 pushrebase: Default::default(),
                 lfs: Default::default(),
@@ -1457,6 +1487,63 @@ mod test {
         assert!(msg.contains("InvalidPushvar"));
     }
 
+    #[test]
+    fn test_hook_defaults_timeout() {
+        let content = r#"
+            storage_config = "sqlite"
+
+            [storage.sqlite.metadata.local]
+            local_db_path = "/tmp/fbsource"
+
+            [storage.sqlite.blobstore.blob_files]
+            path = "/tmp/fbsource"
+
+            [[hooks]]
+            name="inherits"
+
+            [[hooks]]
+            name="overrides"
+            timeout_ms=5000
+
+            [hook_defaults]
+            timeout_ms=30000
+        "#;
+
+        let content_def = r#"
+            repo_id = 0
+            repo_name = "fbsource"
+            repo_config = "fbsource"
+        "#;
+
+        let paths = btreemap! {
+            "common/commitsyncmap.toml" => "",
+            "repos/fbsource/server.toml" => content,
+            "repo_definitions/fbsource/server.toml" => content_def,
+        };
+
+        let config_store = ConfigStore::new(Arc::new(TestSource::new()), None, None);
+        let tmp_dir = write_files(&paths);
+        let repoconfig =
+            load_repo_configs(tmp_dir.path(), &config_store).expect("failed to load configs");
+        let repo = &repoconfig.repos["fbsource"];
+
+        let timeouts: HashMap<_, _> = repo
+            .hooks
+            .iter()
+            .map(|hook| {
+                let config = repo.hook_defaults.apply_to(hook.config.clone());
+                (hook.name.as_str(), config.timeout_ms)
+            })
+            .collect();
+        assert_eq!(
+            timeouts,
+            hashmap! {
+                "inherits" => Some(30000),
+                "overrides" => Some(5000),
+            }
+        );
+    }
+
     #[test]
     fn test_broken_common_config() {
         fn check_fails(common: &str, expect: &str) {
@@ -28,12 +28,14 @@ use metaconfig_types::GlobalrevConfig;
 use metaconfig_types::HgSyncConfig;
 use metaconfig_types::HookBypass;
 use metaconfig_types::HookConfig;
+use metaconfig_types::HookKind;
 use metaconfig_types::HookManagerParams;
 use metaconfig_types::HookParams;
 use metaconfig_types::InfinitepushNamespace;
 use metaconfig_types::InfinitepushParams;
 use metaconfig_types::LfsParams;
 use metaconfig_types::LoggingDestination;
+use metaconfig_types::PartialHookConfig;
 use metaconfig_types::PushParams;
 use metaconfig_types::PushrebaseFlags;
 use metaconfig_types::PushrebaseParams;
@@ -66,6 +68,8 @@ use repos::RawDerivedDataConfig;
 use repos::RawDerivedDataTypesConfig;
 use repos::RawHgSyncConfig;
 use repos::RawHookConfig;
+use repos::RawHookDefaults;
+use repos::RawHookKind;
 use repos::RawHookManagerParams;
 use repos::RawInfinitepushParams;
 use repos::RawLfsParams;
@@ -116,6 +120,10 @@ impl Convert for RawHookManagerParams {
             disable_acl_checker: self.disable_acl_checker,
             all_hooks_bypassed: self.all_hooks_bypassed,
             bypassed_commits_scuba_table: self.bypassed_commits_scuba_table,
+            default_hook_timeout_ms: self
+                .default_hook_timeout_ms
+                .map(|v| v.try_into())
+                .transpose()?,
         })
     }
 }
@@ -124,27 +132,7 @@ impl Convert for RawHookConfig {
     type Output = HookParams;
 
     fn convert(self) -> Result<Self::Output> {
-        let bypass_commit_message = self.bypass_commit_string;
-
-        let bypass_pushvar = self
-            .bypass_pushvar
-            .map(|s| {
-                let parts: Vec<_> = s.split('=').collect();
-                match parts.as_slice() {
-                    [name, value] => Ok((name.to_string(), value.to_string())),
-                    _ => Err(ConfigurationError::InvalidPushvar(s)),
-                }
-            })
-            .transpose()?;
-
-        let bypass = match (bypass_commit_message, bypass_pushvar) {
-            (Some(msg), None) => Some(HookBypass::new_with_commit_msg(msg)),
-            (None, Some((name, value))) => Some(HookBypass::new_with_pushvar(name, value)),
-            (Some(msg), Some((name, value))) => Some(HookBypass::new_with_commit_msg_and_pushvar(
-                msg, name, value,
-            )),
-            (None, None) => None,
-        };
+        let bypass = convert_hook_bypass(self.bypass_commit_string, self.bypass_pushvar)?;
 
         let config = HookConfig {
             bypass,
@@ -154,15 +142,75 @@ impl Convert for RawHookConfig {
             string_lists: self.config_string_lists.unwrap_or_default(),
             int_lists: self.config_int_lists.unwrap_or_default(),
             int_64_lists: self.config_int_64_lists.unwrap_or_default(),
+            timeout_ms: self.timeout_ms.map(|v| v.try_into()).transpose()?,
         };
 
         Ok(HookParams {
             name: self.name,
+            kind: self.kind.convert()?,
             config,
         })
     }
 }
 
+impl Convert for RawHookDefaults {
+    type Output = PartialHookConfig;
+
+    fn convert(self) -> Result<Self::Output> {
+        let bypass = convert_hook_bypass(self.bypass_commit_string, self.bypass_pushvar)?;
+
+        Ok(PartialHookConfig {
+            bypass,
+            strings: self.config_strings,
+            ints: self.config_ints,
+            ints_64: self.config_ints_64,
+            string_lists: self.config_string_lists,
+            int_lists: self.config_int_lists,
+            int_64_lists: self.config_int_64_lists,
+            timeout_ms: self.timeout_ms.map(|v| v.try_into()).transpose()?,
+        })
+    }
+}
+
+fn convert_hook_bypass(
+    bypass_commit_message: Option<String>,
+    bypass_pushvar: Option<String>,
+) -> Result<Option<HookBypass>> {
+    let bypass_pushvar = bypass_pushvar
+        .map(|s| {
+            let parts: Vec<_> = s.split('=').collect();
+            match parts.as_slice() {
+                [name, value] => Ok((name.to_string(), value.to_string())),
+                _ => Err(ConfigurationError::InvalidPushvar(s)),
+            }
+        })
+        .transpose()?;
+
+    let bypass = match (bypass_commit_message, bypass_pushvar) {
+        (Some(msg), None) => Some(HookBypass::new_with_commit_msg(msg)),
+        (None, Some((name, value))) => Some(HookBypass::new_with_pushvar(name, value)),
+        (Some(msg), Some((name, value))) => Some(HookBypass::new_with_commit_msg_and_pushvar(
+            msg, name, value,
+        )),
+        (None, None) => None,
+    };
+    Ok(bypass)
+}
+
+impl Convert for RawHookKind {
+    type Output = HookKind;
+
+    fn convert(self) -> Result<Self::Output> {
+        let converted = match self {
+            RawHookKind::CHANGESET => HookKind::Changeset,
+            RawHookKind::FILE => HookKind::File,
+            RawHookKind::BOOKMARK => HookKind::Bookmark,
+            v => return Err(anyhow!("Invalid value {} for enum HookKind", v)),
+        };
+        Ok(converted)
+    }
+}
+
 impl Convert for RawBookmarkConfig {
     type Output = BookmarkParams;
 
@@ -217,6 +265,8 @@ impl Convert for RawBookmarkConfig {
             hooks_skip_ancestors_of,
             ensure_ancestor_of,
             allow_move_to_public_commits_without_hooks,
+            // Pusher gates are not part of the raw config yet.
+            pusher_gate: None,
         })
     }
 }
@@ -162,6 +162,8 @@ pub struct RepoConfig {
     pub infinitepush: InfinitepushParams,
     /// Configuration for hooks
     pub hooks: Vec<HookParams>,
+    /// Defaults for the configuration of all hooks
+    pub hook_defaults: PartialHookConfig,
     /// Push configuration options
     pub push: PushParams,
     /// Pushrebase configuration options
@@ -435,6 +437,10 @@ pub struct HookManagerParams {
     pub all_hooks_bypassed: bool,
     /// Scuba table for bypassed commits logging.
     pub bypassed_commits_scuba_table: Option<String>,
+    /// How long a hook may run on a changeset, a file or a bookmark move,
+    /// in milliseconds, unless it sets `HookConfig::timeout_ms`. The hook
+    /// manager picks a default if unset.
+    pub default_hook_timeout_ms: Option<u64>,
 }
 
 /// Configuration might be done for a single bookmark or for all bookmarks matching a regex
@@ -497,6 +503,35 @@ pub struct BookmarkParams {
     /// because commit is already public, meaning that hooks already
     /// should have been run when the commit was first made public.
     pub allow_move_to_public_commits_without_hooks: bool,
+    /// Who may push to this bookmark, checked before its hooks run
+    pub pusher_gate: Option<PusherGateParams>,
+}
+
+/// A policy on who may push to a bookmark. A pusher matching any of
+/// `denied_pushers` is denied. Otherwise, if `allowed_pushers` is set, a
+/// pusher matching none of them is denied.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct PusherGateParams {
+    /// The name of the policy, shown in rejections
+    pub name: String,
+    /// If set, only pushers matching one of these may push
+    pub allowed_pushers: Option<Vec<PusherMatcherParams>>,
+    /// Pushers matching one of these may not push
+    pub denied_pushers: Vec<PusherMatcherParams>,
+    /// Bypass for the gate. Only its pushvar applies.
+    pub bypass: Option<HookBypass>,
+}
+
+/// Matcher of the identities of a pusher
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum PusherMatcherParams {
+    /// An identity, written as `TYPE:data`
+    Identity(String),
+    /// A glob on the user name, where `*` matches any characters and `?`
+    /// matches one
+    Username(String),
+    /// The name of an ACL group
+    Group(String),
 }
 
 /// The type of the hook
@@ -569,6 +604,21 @@ impl HookBypass {
             .as_ref()
             .map(|name_and_value| (&name_and_value.0, &name_and_value.1))
     }
+
+    /// Combine with a `default` bypass. Each kind of bypass (commit message
+    /// or pushvar) is kept if set, and taken from `default` otherwise, so
+    /// the hook can be bypassed with its own bypasses, or with the default
+    /// bypass of a kind it doesn't set.
+    pub fn with_default(self, default: &HookBypass) -> Self {
+        Self {
+            commit_message_bypass: self
+                .commit_message_bypass
+                .or_else(|| default.commit_message_bypass.clone()),
+            pushvar_name_and_value: self
+                .pushvar_name_and_value
+                .or_else(|| default.pushvar_name_and_value.clone()),
+        }
+    }
 }
 
 /// Configs that are being passed to the hook during runtime
@@ -588,6 +638,76 @@ pub struct HookConfig {
     pub int_lists: HashMap<String, Vec<i32>>,
     /// Map of config to it's value. Values here are lists of 64bit integers
     pub int_64_lists: HashMap<String, Vec<i64>>,
+    /// How long the hook may run on a changeset, a file or a bookmark move,
+    /// in milliseconds. Executions running longer are rejections. If unset,
+    /// `PartialHookConfig::timeout_ms` or else
+    /// `HookManagerParams::default_hook_timeout_ms` applies.
+    pub timeout_ms: Option<u64>,
+}
+
+/// Defaults for the configs of all hooks of a repo. Unset fields leave the
+/// configs of hooks unchanged.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct PartialHookConfig {
+    /// Default bypass, combined with the bypass of each hook as described in
+    /// `HookBypass::with_default`
+    pub bypass: Option<HookBypass>,
+    /// Default string configs
+    pub strings: Option<HashMap<String, String>>,
+    /// Default 32bit integer configs
+    pub ints: Option<HashMap<String, i32>>,
+    /// Default 64bit integer configs
+    pub ints_64: Option<HashMap<String, i64>>,
+    /// Default string list configs
+    pub string_lists: Option<HashMap<String, Vec<String>>>,
+    /// Default 32bit integer list configs
+    pub int_lists: Option<HashMap<String, Vec<i32>>>,
+    /// Default 64bit integer list configs
+    pub int_64_lists: Option<HashMap<String, Vec<i64>>>,
+    /// Default timeout of hooks, in milliseconds
+    pub timeout_ms: Option<u64>,
+}
+
+impl PartialHookConfig {
+    /// Apply these defaults to the config of a hook. Values set in `config`
+    /// take precedence over the defaults with the same name.
+    pub fn apply_to(&self, config: HookConfig) -> HookConfig {
+        let bypass = match (config.bypass, &self.bypass) {
+            (Some(bypass), Some(default)) => Some(bypass.with_default(default)),
+            (bypass, default) => bypass.or_else(|| default.clone()),
+        };
+        HookConfig {
+            bypass,
+            strings: with_defaults(config.strings, &self.strings),
+            ints: with_defaults(config.ints, &self.ints),
+            ints_64: with_defaults(config.ints_64, &self.ints_64),
+            string_lists: with_defaults(config.string_lists, &self.string_lists),
+            int_lists: with_defaults(config.int_lists, &self.int_lists),
+            int_64_lists: with_defaults(config.int_64_lists, &self.int_64_lists),
+            timeout_ms: config.timeout_ms.or(self.timeout_ms),
+        }
+    }
+}
+
+fn with_defaults<V: Clone>(
+    mut values: HashMap<String, V>,
+    defaults: &Option<HashMap<String, V>>,
+) -> HashMap<String, V> {
+    for (name, value) in defaults.iter().flatten() {
+        values.entry(name.clone()).or_insert_with(|| value.clone());
+    }
+    values
+}
+
+/// The category of a hook, which determines what it is run against
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HookKind {
+    /// Hook run once for every changeset
+    Changeset,
+    /// Hook run once for every file changed by a changeset
+    File,
+    /// Hook run once for every bookmark creation, move or deletion
+    Bookmark,
 }
 
 /// Configuration for a hook
@@ -595,6 +715,9 @@ pub struct HookConfig {
 pub struct HookParams {
     /// The name of the hook
     pub name: String,
+    /// The category of the hook. If not set, the hook is looked up as a
+    /// changeset hook and then as a file hook.
+    pub kind: Option<HookKind>,
     /// Configs that should be passed to hook
     pub config: HookConfig,
 }
@@ -12,6 +12,7 @@ use async_trait::async_trait;
 use changesets::ChangesetEntry;
 use changesets::ChangesetInsert;
 use changesets::Changesets;
+use changesets::EnumerationEntry;
 use changesets::SortOrder;
 use cloned::cloned;
 use context::CoreContext;
@@ -124,15 +125,22 @@ impl Changesets for MicrowaveChangesets {
             .await
     }
 
-    fn list_enumeration_range(
+    fn list_enumeration_range_detailed(
         &self,
         ctx: &CoreContext,
         min_id: u64,
         max_id: u64,
         sort_and_limit: Option<(SortOrder, u64)>,
         read_from_master: bool,
-    ) -> BoxStream<'_, Result<(ChangesetId, u64), Error>> {
-        self.inner
-            .list_enumeration_range(ctx, min_id, max_id, sort_and_limit, read_from_master)
+        include_parents: bool,
+    ) -> BoxStream<'_, Result<EnumerationEntry, Error>> {
+        self.inner.list_enumeration_range_detailed(
+            ctx,
+            min_id,
+            max_id,
+            sort_and_limit,
+            read_from_master,
+            include_parents,
+        )
     }
 }
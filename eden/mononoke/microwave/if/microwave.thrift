@@ -31,6 +31,7 @@ struct ChangesetSnapshot {
   1: optional mononoke_types_thrift.ChangesetId cs_id;
   2: optional list<mononoke_types_thrift.ChangesetId> parents;
   3: optional i64 gen;
+  4: optional mononoke_types_thrift.binary_bytes extra;
 } (rust.exhaustive)
 
 struct RepoSnapshot {
@@ -89,6 +89,7 @@ impl Snapshot {
                 cs_id,
                 parents,
                 gen,
+                extra,
             } = c;
 
             if seen_changesets.insert(cs_id) {
@@ -99,6 +100,7 @@ impl Snapshot {
                     // succeed because the generation number is >= 0, but also not so large that it
                     // cannot fit in a i64.
                     gen: Some(gen.try_into().unwrap()),
+                    extra,
                 };
 
                 v.push(t);
@@ -267,6 +269,7 @@ fn reheat_changesets(
                 cs_id,
                 parents,
                 gen,
+                extra,
             } = c;
 
             let cs_id = cs_id.ok_or_else(|| Error::msg("cs_id missing"))?;
@@ -283,6 +286,7 @@ fn reheat_changesets(
                 cs_id: ChangesetId::from_thrift(cs_id)?,
                 parents,
                 gen: gen.try_into().unwrap(), // See above
+                extra,
             })
         })
         .collect()
@@ -1428,6 +1428,7 @@ impl ChangesetContext {
                 vec![self.bonsai_changeset().await?].iter(),
                 &BookmarkKey::new(bookmark.as_ref())?,
                 pushvars,
+                self.ctx().metadata().identities(),
                 CrossRepoPushSource::NativeToThisRepo,
                 PushAuthoredBy::User,
             )
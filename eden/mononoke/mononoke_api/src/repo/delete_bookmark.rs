@@ -68,13 +68,19 @@ impl RepoContext {
                     self.ctx(),
                     self.authorization_context(),
                     redirector.repo.inner_repo(),
+                    redirector.repo.hook_manager(),
                 )
                 .await?;
             // Wait for bookmark to catch up on small repo
             redirector.backsync_latest(ctx).await?;
         } else {
             make_delete_op(bookmark, old_target, pushvars)
-                .run(self.ctx(), self.authorization_context(), self.inner_repo())
+                .run(
+                    self.ctx(),
+                    self.authorization_context(),
+                    self.inner_repo(),
+                    self.hook_manager().as_ref(),
+                )
                 .await?;
         }
 
@@ -463,6 +463,7 @@ impl HgRepoContext {
         let insert = ChangesetInsert {
             cs_id,
             parents: bonsai_cs.parents().collect(),
+            extra: None,
         };
         match save_bonsai_changeset_object(self.ctx(), blobstore, bonsai_cs).await {
             Ok(_) => self.blob_repo().changesets().add(self.ctx(), insert).await,
@@ -27,10 +27,12 @@ async fn setup_changesets(ctx: &CoreContext) -> Result<SqlChangesets, Error> {
     let ones = ChangesetInsert {
         cs_id: ONES_CSID,
         parents: vec![],
+        extra: None,
     };
     let twos = ChangesetInsert {
         cs_id: TWOS_CSID,
         parents: vec![ONES_CSID],
+        extra: None,
     };
 
     changesets.add(ctx, ones).await?;
@@ -16,6 +16,7 @@ use changesets::ArcChangesets;
 use changesets::ChangesetEntry;
 use changesets::ChangesetInsert;
 use changesets::Changesets;
+use changesets::EnumerationEntry;
 use changesets::SortOrder;
 use commit_graph::ArcCommitGraph;
 use commit_graph_types::edges::ChangesetParents;
@@ -187,20 +188,22 @@ impl Changesets for ChangesetsCommitGraphCompat {
             .await
     }
 
-    fn list_enumeration_range(
+    fn list_enumeration_range_detailed(
         &self,
         ctx: &CoreContext,
         min_id: u64,
         max_id: u64,
         sort_and_limit: Option<(SortOrder, u64)>,
         read_from_master: bool,
-    ) -> BoxStream<'_, Result<(ChangesetId, u64)>> {
-        self.changesets.list_enumeration_range(
+        include_parents: bool,
+    ) -> BoxStream<'_, Result<EnumerationEntry>> {
+        self.changesets.list_enumeration_range_detailed(
             ctx,
             min_id,
             max_id,
             sort_and_limit,
             read_from_master,
+            include_parents,
         )
     }
 }
@@ -697,13 +697,28 @@ async fn plain_push_bookmark(
         }
 
         (Some(old_target), None) => {
-            bookmarks_movement::DeleteBookmarkOp::new(&bookmark_push.name, old_target, reason)
-                .only_if_public()
-                .with_pushvars(maybe_pushvars)
-                .only_log_acl_checks(only_log_acl_checks)
-                .run(ctx, &authz, repo)
-                .await
-                .context("Failed to delete bookmark")?;
+            let res =
+                bookmarks_movement::DeleteBookmarkOp::new(&bookmark_push.name, old_target, reason)
+                    .only_if_public()
+                    .with_pushvars(maybe_pushvars)
+                    .only_log_acl_checks(only_log_acl_checks)
+                    .run(ctx, &authz, repo, hook_manager)
+                    .await;
+            match res {
+                Ok(()) => {}
+                Err(err) => match err {
+                    BookmarkMovementError::HookFailure(rejections) => {
+                        let rejections =
+                            map_hook_rejections(rejections, hook_rejection_remapper).await?;
+                        return Err(BundleResolverError::HookError(rejections));
+                    }
+                    _ => {
+                        return Err(BundleResolverError::Error(
+                            Error::from(err).context("Failed to delete bookmark"),
+                        ));
+                    }
+                },
+            }
         }
 
         (None, None) => {}
@@ -87,6 +87,7 @@ use environment::WarmBookmarksCacheDerivedData;
 use ephemeral_blobstore::ArcRepoEphemeralStore;
 use ephemeral_blobstore::RepoEphemeralStore;
 use ephemeral_blobstore::RepoEphemeralStoreBuilder;
+use fallbackblob::FallbackReadBlobstore;
 use fbinit::FacebookInit;
 use filenodes::ArcFilenodes;
 use filestore::ArcFilestoreConfig;
@@ -187,6 +188,19 @@ use wireproto_handler::PushRedirectorMode::Enabled;
 use wireproto_handler::RepoHandlerBase;
 use wireproto_handler::TargetRepoDbs;
 
+mod readonly;
+
+pub use crate::readonly::ReadOnlyBonsaiGitMapping;
+pub use crate::readonly::ReadOnlyBonsaiGlobalrevMapping;
+pub use crate::readonly::ReadOnlyBonsaiHgMapping;
+pub use crate::readonly::ReadOnlyBonsaiSvnrevMapping;
+pub use crate::readonly::ReadOnlyBookmarks;
+pub use crate::readonly::ReadOnlyChangesets;
+pub use crate::readonly::ReadOnlyHgMutationStore;
+pub use crate::readonly::ReadOnlyLongRunningRequestsQueue;
+pub use crate::readonly::ReadOnlyModeError;
+pub use crate::readonly::ReadOnlyPhases;
+
 const DERIVED_DATA_LEASE: &str = "derived-data-lease";
 
 #[derive(Clone)]
@@ -239,6 +253,7 @@ pub struct RepoFactory {
     scrub_handler: Arc<dyn ScrubHandler>,
     blobstore_component_sampler: Option<Arc<dyn ComponentSamplingHandler>>,
     bonsai_hg_mapping_overwrite: bool,
+    fallback_blobstore: Option<BlobConfig>,
 }
 
 impl RepoFactory {
@@ -251,6 +266,7 @@ impl RepoFactory {
             scrub_handler: default_scrub_handler(),
             blobstore_component_sampler: None,
             bonsai_hg_mapping_overwrite: false,
+            fallback_blobstore: None,
             env,
         }
     }
@@ -281,6 +297,27 @@ impl RepoFactory {
         self
     }
 
+    /// Read blobs missing from the repo blobstore from the blobstore with
+    /// this config instead, e.g. while migrating between blobstores.
+    ///
+    /// Writes only ever go to the repo blobstore.
+    pub fn with_fallback_blobstore(&mut self, config: BlobConfig) -> &mut Self {
+        self.fallback_blobstore = Some(config);
+        self
+    }
+
+    /// Whether repos built by this factory are forced into read-only mode.
+    ///
+    /// When set, metadata facets are wrapped in guards that reject writes
+    /// with `ReadOnlyModeError`, in addition to read-only storage.
+    pub fn force_readonly(&self) -> bool {
+        self.env.force_readonly
+    }
+
+    fn readonly_storage(&self) -> ReadOnlyStorage {
+        ReadOnlyStorage(self.env.readonly_storage.0 || self.env.force_readonly)
+    }
+
     pub async fn sql_factory(
         &self,
         config: &MetadataDatabaseConfig,
@@ -291,7 +328,7 @@ impl RepoFactory {
                     self.env.fb,
                     config.clone(),
                     self.env.mysql_options.clone(),
-                    self.env.readonly_storage,
+                    self.readonly_storage(),
                 )
                 .watched(&self.env.logger)
                 .await?;
@@ -313,7 +350,7 @@ impl RepoFactory {
             self.env.fb,
             config.clone(),
             &self.env.mysql_options,
-            self.env.readonly_storage,
+            self.readonly_storage(),
             &self.env.blobstore_options,
             &self.env.logger,
             &self.env.config_store,
@@ -332,7 +369,11 @@ impl RepoFactory {
         common_config: &ArcCommonConfig,
     ) -> Result<RepoBlobstore> {
         let mut blobstore = blobstore.clone();
-        if self.env.readonly_storage.0 {
+        if let Some(fallback_config) = &self.fallback_blobstore {
+            let fallback = self.blobstore_no_cache(fallback_config).await?;
+            blobstore = Arc::new(FallbackReadBlobstore::new(blobstore, fallback));
+        }
+        if self.readonly_storage().0 {
             blobstore = Arc::new(ReadOnlyBlobstore::new(blobstore));
         }
 
@@ -370,7 +411,7 @@ impl RepoFactory {
         common_config: &ArcCommonConfig,
     ) -> Result<RepoBlobstoreUnlinkOps> {
         let mut blobstore = blobstore.clone();
-        if self.env.readonly_storage.0 {
+        if self.readonly_storage().0 {
             blobstore = Arc::new(ReadOnlyBlobstore::new(blobstore));
         }
 
@@ -459,7 +500,7 @@ impl RepoFactory {
             self.env.fb,
             config.clone(),
             &self.env.mysql_options,
-            self.env.readonly_storage,
+            self.readonly_storage(),
             &self.env.blobstore_options,
             &self.env.logger,
             &self.env.config_store,
@@ -720,13 +761,19 @@ impl RepoFactory {
                 Arc::new(changesets)
             };
 
-        Ok(Arc::new(ChangesetsCommitGraphCompat::new(
+        let changesets: ArcChangesets = Arc::new(ChangesetsCommitGraphCompat::new(
             self.env.fb,
             possibly_cached_changesets,
             commit_graph.clone(),
             repo_identity.name().to_string(),
             repo_config.commit_graph_config.scuba_table.as_deref(),
-        )?))
+        )?);
+
+        if self.force_readonly() {
+            Ok(Arc::new(ReadOnlyChangesets::new(changesets)))
+        } else {
+            Ok(changesets)
+        }
     }
 
     pub fn changeset_fetcher(
@@ -759,10 +806,16 @@ impl RepoFactory {
         sql_bookmarks: &ArcSqlBookmarks,
         repo_identity: &ArcRepoIdentity,
     ) -> ArcBookmarks {
-        Arc::new(CachedBookmarks::new(
+        let bookmarks: ArcBookmarks = Arc::new(CachedBookmarks::new(
             sql_bookmarks.clone(),
             repo_identity.id(),
-        ))
+        ));
+
+        if self.force_readonly() {
+            Arc::new(ReadOnlyBookmarks::new(bookmarks))
+        } else {
+            bookmarks
+        }
     }
 
     pub fn bookmark_update_log(&self, sql_bookmarks: &ArcSqlBookmarks) -> ArcBookmarkUpdateLog {
@@ -784,7 +837,14 @@ impl RepoFactory {
             sql_phases_builder.enable_caching(cache_handler_factory);
         }
         let heads_fetcher = bookmark_heads_fetcher(bookmarks.clone());
-        Ok(sql_phases_builder.build(repo_identity.id(), changeset_fetcher.clone(), heads_fetcher))
+        let phases =
+            sql_phases_builder.build(repo_identity.id(), changeset_fetcher.clone(), heads_fetcher);
+
+        if self.force_readonly() {
+            Ok(Arc::new(ReadOnlyPhases::new(phases)))
+        } else {
+            Ok(phases)
+        }
     }
 
     pub async fn bonsai_hg_mapping(
@@ -803,13 +863,20 @@ impl RepoFactory {
 
         let bonsai_hg_mapping = builder.build(repo_identity.id(), self.env.rendezvous_options);
 
-        if let Some(cache_handler_factory) = self.cache_handler_factory("bonsai_hg_mapping")? {
-            Ok(Arc::new(CachingBonsaiHgMapping::new(
-                Arc::new(bonsai_hg_mapping),
-                cache_handler_factory,
-            )))
+        let bonsai_hg_mapping: ArcBonsaiHgMapping =
+            if let Some(cache_handler_factory) = self.cache_handler_factory("bonsai_hg_mapping")? {
+                Arc::new(CachingBonsaiHgMapping::new(
+                    Arc::new(bonsai_hg_mapping),
+                    cache_handler_factory,
+                ))
+            } else {
+                Arc::new(bonsai_hg_mapping)
+            };
+
+        if self.force_readonly() {
+            Ok(Arc::new(ReadOnlyBonsaiHgMapping::new(bonsai_hg_mapping)))
         } else {
-            Ok(Arc::new(bonsai_hg_mapping))
+            Ok(bonsai_hg_mapping)
         }
     }
 
@@ -823,7 +890,14 @@ impl RepoFactory {
             .await
             .context(RepoFactoryError::BonsaiGitMapping)?
             .build(repo_identity.id());
-        Ok(Arc::new(bonsai_git_mapping))
+
+        if self.force_readonly() {
+            Ok(Arc::new(ReadOnlyBonsaiGitMapping::new(Arc::new(
+                bonsai_git_mapping,
+            ))))
+        } else {
+            Ok(Arc::new(bonsai_git_mapping))
+        }
     }
 
     pub async fn long_running_requests_queue(
@@ -834,7 +908,14 @@ impl RepoFactory {
             .open_sql::<SqlLongRunningRequestsQueue>(repo_config)
             .await
             .context(RepoFactoryError::LongRunningRequestsQueue)?;
-        Ok(Arc::new(long_running_requests_queue))
+
+        if self.force_readonly() {
+            Ok(Arc::new(ReadOnlyLongRunningRequestsQueue::new(Arc::new(
+                long_running_requests_queue,
+            ))))
+        } else {
+            Ok(Arc::new(long_running_requests_queue))
+        }
     }
 
     pub async fn bonsai_globalrev_mapping(
@@ -847,15 +928,24 @@ impl RepoFactory {
             .await
             .context(RepoFactoryError::BonsaiGlobalrevMapping)?
             .build(repo_identity.id());
-        if let Some(cache_handler_factory) =
-            self.cache_handler_factory("bonsai_globalrev_mapping")?
-        {
-            Ok(Arc::new(CachingBonsaiGlobalrevMapping::new(
-                Arc::new(bonsai_globalrev_mapping),
-                cache_handler_factory,
+        let bonsai_globalrev_mapping: ArcBonsaiGlobalrevMapping =
+            if let Some(cache_handler_factory) =
+                self.cache_handler_factory("bonsai_globalrev_mapping")?
+            {
+                Arc::new(CachingBonsaiGlobalrevMapping::new(
+                    Arc::new(bonsai_globalrev_mapping),
+                    cache_handler_factory,
+                ))
+            } else {
+                Arc::new(bonsai_globalrev_mapping)
+            };
+
+        if self.force_readonly() {
+            Ok(Arc::new(ReadOnlyBonsaiGlobalrevMapping::new(
+                bonsai_globalrev_mapping,
             )))
         } else {
-            Ok(Arc::new(bonsai_globalrev_mapping))
+            Ok(bonsai_globalrev_mapping)
         }
     }
 
@@ -869,13 +959,23 @@ impl RepoFactory {
             .await
             .context(RepoFactoryError::BonsaiSvnrevMapping)?
             .build(repo_identity.id());
-        if let Some(cache_handler_factory) = self.cache_handler_factory("bonsai_svnrev_mapping")? {
-            Ok(Arc::new(CachingBonsaiSvnrevMapping::new(
+        let bonsai_svnrev_mapping: ArcBonsaiSvnrevMapping = if let Some(cache_handler_factory) =
+            self.cache_handler_factory("bonsai_svnrev_mapping")?
+        {
+            Arc::new(CachingBonsaiSvnrevMapping::new(
                 Arc::new(bonsai_svnrev_mapping),
                 cache_handler_factory,
+            ))
+        } else {
+            Arc::new(bonsai_svnrev_mapping)
+        };
+
+        if self.force_readonly() {
+            Ok(Arc::new(ReadOnlyBonsaiSvnrevMapping::new(
+                bonsai_svnrev_mapping,
             )))
         } else {
-            Ok(Arc::new(bonsai_svnrev_mapping))
+            Ok(bonsai_svnrev_mapping)
         }
     }
 
@@ -989,13 +1089,20 @@ impl RepoFactory {
             .context(RepoFactoryError::HgMutationStore)?
             .with_repo_id(repo_identity.id());
 
-        if let Some(cache_handler_factory) = self.cache_handler_factory("hg_mutation_store")? {
-            Ok(Arc::new(CachedHgMutationStore::new(
-                Arc::new(hg_mutation_store),
-                cache_handler_factory,
-            )))
+        let hg_mutation_store: ArcHgMutationStore =
+            if let Some(cache_handler_factory) = self.cache_handler_factory("hg_mutation_store")? {
+                Arc::new(CachedHgMutationStore::new(
+                    Arc::new(hg_mutation_store),
+                    cache_handler_factory,
+                ))
+            } else {
+                Arc::new(hg_mutation_store)
+            };
+
+        if self.force_readonly() {
+            Ok(Arc::new(ReadOnlyHgMutationStore::new(hg_mutation_store)))
         } else {
-            Ok(Arc::new(hg_mutation_store))
+            Ok(hg_mutation_store)
         }
     }
 
@@ -1164,7 +1271,7 @@ impl RepoFactory {
                 self.env.fb,
                 &ephemeral_config.metadata,
                 &self.env.mysql_options,
-                self.env.readonly_storage.0,
+                self.readonly_storage().0,
             )?
             .build(
                 repo_identity.id(),
@@ -1372,6 +1479,12 @@ impl RepoFactory {
         repo_config: &ArcRepoConfig,
         repo_identity: &ArcRepoIdentity,
     ) -> Result<ArcRepoLock> {
+        if self.force_readonly() {
+            return Ok(Arc::new(AlwaysLockedRepoLock::new(
+                repo_identity.id(),
+                "Service is in read-only mode".to_string(),
+            )));
+        }
         match repo_config.readonly {
             RepoReadOnly::ReadOnly(ref reason) => Ok(Arc::new(AlwaysLockedRepoLock::new(
                 repo_identity.id(),
@@ -1382,7 +1495,7 @@ impl RepoFactory {
                     self.env.fb,
                     &repo_config.storage_config.metadata,
                     &self.env.mysql_options,
-                    self.env.readonly_storage.0,
+                    self.readonly_storage().0,
                 )?;
 
                 Ok(Arc::new(MutableRepoLock::new(sql, repo_identity.id())))
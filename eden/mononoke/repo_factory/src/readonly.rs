@@ -0,0 +1,944 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Write-rejecting guards for repo facets.
+//!
+//! When the environment forces the service into read-only mode, the
+//! factory wraps the metadata facets in these guards.  Reads are passed
+//! through to the underlying facet, while any attempt to write fails with
+//! [`ReadOnlyModeError`] naming the operation that was attempted.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::Error;
+use anyhow::Result;
+use async_trait::async_trait;
+use bonsai_git_mapping::AddGitMappingErrorKind;
+use bonsai_git_mapping::ArcBonsaiGitMapping;
+use bonsai_git_mapping::BonsaiGitMapping;
+use bonsai_git_mapping::BonsaiGitMappingEntry;
+use bonsai_git_mapping::BonsaisOrGitShas;
+use bonsai_git_mapping::GitSha1Prefix;
+use bonsai_git_mapping::GitSha1sResolvedFromPrefix;
+use bonsai_globalrev_mapping::ArcBonsaiGlobalrevMapping;
+use bonsai_globalrev_mapping::BonsaiGlobalrevMapping;
+use bonsai_globalrev_mapping::BonsaiGlobalrevMappingEntry;
+use bonsai_globalrev_mapping::BonsaisOrGlobalrevs;
+use bonsai_hg_mapping::ArcBonsaiHgMapping;
+use bonsai_hg_mapping::BonsaiHgMapping;
+use bonsai_hg_mapping::BonsaiHgMappingEntry;
+use bonsai_hg_mapping::BonsaiOrHgChangesetIds;
+use bonsai_svnrev_mapping::ArcBonsaiSvnrevMapping;
+use bonsai_svnrev_mapping::BonsaiSvnrevMapping;
+use bonsai_svnrev_mapping::BonsaiSvnrevMappingEntry;
+use bonsai_svnrev_mapping::BonsaisOrSvnrevs;
+use bookmarks::ArcBookmarks;
+use bookmarks::Bookmark;
+use bookmarks::BookmarkCategory;
+use bookmarks::BookmarkKey;
+use bookmarks::BookmarkKind;
+use bookmarks::BookmarkPagination;
+use bookmarks::BookmarkPrefix;
+use bookmarks::BookmarkTransaction;
+use bookmarks::BookmarkTransactionHook;
+use bookmarks::BookmarkUpdateReason;
+use bookmarks::Bookmarks;
+use bookmarks::BookmarksSubscription;
+use bookmarks::Freshness;
+use changesets::ArcChangesets;
+use changesets::ChangesetEntry;
+use changesets::ChangesetInsert;
+use changesets::Changesets;
+use changesets::EnumerationEntry;
+use changesets::SortOrder;
+use context::CoreContext;
+use futures::future;
+use futures::future::BoxFuture;
+use futures::future::FutureExt;
+use futures::stream::BoxStream;
+use mercurial_mutation::ArcHgMutationStore;
+use mercurial_mutation::HgMutationEntry;
+use mercurial_mutation::HgMutationStore;
+use mercurial_types::HgChangesetId;
+use mercurial_types::HgChangesetIdPrefix;
+use mercurial_types::HgChangesetIdsResolvedFromPrefix;
+use mononoke_types::hash::GitSha1;
+use mononoke_types::BonsaiChangeset;
+use mononoke_types::ChangesetId;
+use mononoke_types::ChangesetIdPrefix;
+use mononoke_types::ChangesetIdsResolvedFromPrefix;
+use mononoke_types::Generation;
+use mononoke_types::Globalrev;
+use mononoke_types::RepositoryId;
+use mononoke_types::Svnrev;
+use mononoke_types::Timestamp;
+use phases::ArcPhases;
+use phases::Phases;
+use requests_table::ArcLongRunningRequestsQueue;
+use requests_table::BlobstoreKey;
+use requests_table::ClaimedBy;
+use requests_table::LongRunningRequestEntry;
+use requests_table::LongRunningRequestsQueue;
+use requests_table::RequestId;
+use requests_table::RequestStatus;
+use requests_table::RequestType;
+use requests_table::RowId;
+use sql::Transaction;
+use thiserror::Error;
+use vec1::Vec1;
+
+/// Error returned by the read-only guards for every attempted write.
+#[derive(Debug, Error)]
+#[error("Service is in read-only mode: {operation} is not permitted")]
+pub struct ReadOnlyModeError {
+    /// The operation that was attempted, e.g. `"bookmarks update"`.
+    pub operation: &'static str,
+}
+
+impl ReadOnlyModeError {
+    pub fn new(operation: &'static str) -> Self {
+        Self { operation }
+    }
+}
+
+fn rejected<T>(operation: &'static str) -> Result<T> {
+    Err(ReadOnlyModeError::new(operation).into())
+}
+
+/// Bookmarks that can be read, but whose transactions always fail.
+pub struct ReadOnlyBookmarks {
+    inner: ArcBookmarks,
+}
+
+impl ReadOnlyBookmarks {
+    pub fn new(inner: ArcBookmarks) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl Bookmarks for ReadOnlyBookmarks {
+    fn get(
+        &self,
+        ctx: CoreContext,
+        name: &BookmarkKey,
+    ) -> BoxFuture<'static, Result<Option<ChangesetId>>> {
+        self.inner.get(ctx, name)
+    }
+
+    fn list(
+        &self,
+        ctx: CoreContext,
+        freshness: Freshness,
+        prefix: &BookmarkPrefix,
+        categories: &[BookmarkCategory],
+        kinds: &[BookmarkKind],
+        pagination: &BookmarkPagination,
+        limit: u64,
+    ) -> BoxStream<'static, Result<(Bookmark, ChangesetId)>> {
+        self.inner
+            .list(ctx, freshness, prefix, categories, kinds, pagination, limit)
+    }
+
+    fn create_transaction(&self, _ctx: CoreContext) -> Box<dyn BookmarkTransaction> {
+        Box::new(ReadOnlyBookmarkTransaction)
+    }
+
+    async fn create_subscription(
+        &self,
+        ctx: &CoreContext,
+        freshness: Freshness,
+    ) -> Result<Box<dyn BookmarksSubscription>> {
+        self.inner.create_subscription(ctx, freshness).await
+    }
+
+    fn drop_caches(&self) {
+        self.inner.drop_caches()
+    }
+}
+
+/// A bookmark transaction that rejects every change, and fails to commit.
+struct ReadOnlyBookmarkTransaction;
+
+impl BookmarkTransaction for ReadOnlyBookmarkTransaction {
+    fn update(
+        &mut self,
+        _bookmark: &BookmarkKey,
+        _new_cs: ChangesetId,
+        _old_cs: ChangesetId,
+        _reason: BookmarkUpdateReason,
+    ) -> Result<()> {
+        rejected("bookmarks update")
+    }
+
+    fn create(
+        &mut self,
+        _bookmark: &BookmarkKey,
+        _new_cs: ChangesetId,
+        _reason: BookmarkUpdateReason,
+    ) -> Result<()> {
+        rejected("bookmarks create")
+    }
+
+    fn force_set(
+        &mut self,
+        _bookmark: &BookmarkKey,
+        _new_cs: ChangesetId,
+        _reason: BookmarkUpdateReason,
+    ) -> Result<()> {
+        rejected("bookmarks force_set")
+    }
+
+    fn delete(
+        &mut self,
+        _bookmark: &BookmarkKey,
+        _old_cs: ChangesetId,
+        _reason: BookmarkUpdateReason,
+    ) -> Result<()> {
+        rejected("bookmarks delete")
+    }
+
+    fn force_delete(
+        &mut self,
+        _bookmark: &BookmarkKey,
+        _reason: BookmarkUpdateReason,
+    ) -> Result<()> {
+        rejected("bookmarks force_delete")
+    }
+
+    fn update_scratch(
+        &mut self,
+        _bookmark: &BookmarkKey,
+        _new_cs: ChangesetId,
+        _old_cs: ChangesetId,
+    ) -> Result<()> {
+        rejected("bookmarks update_scratch")
+    }
+
+    fn create_scratch(&mut self, _bookmark: &BookmarkKey, _new_cs: ChangesetId) -> Result<()> {
+        rejected("bookmarks create_scratch")
+    }
+
+    fn delete_scratch(&mut self, _bookmark: &BookmarkKey, _old_cs: ChangesetId) -> Result<()> {
+        rejected("bookmarks delete_scratch")
+    }
+
+    fn create_publishing(
+        &mut self,
+        _bookmark: &BookmarkKey,
+        _new_cs: ChangesetId,
+        _reason: BookmarkUpdateReason,
+    ) -> Result<()> {
+        rejected("bookmarks create_publishing")
+    }
+
+    fn commit(self: Box<Self>) -> BoxFuture<'static, Result<bool>> {
+        future::ready(rejected("bookmarks commit")).boxed()
+    }
+
+    fn commit_with_hook(
+        self: Box<Self>,
+        _txn_hook: BookmarkTransactionHook,
+    ) -> BoxFuture<'static, Result<bool>> {
+        future::ready(rejected("bookmarks commit")).boxed()
+    }
+}
+
+/// Changesets that can be read, but not added to.
+pub struct ReadOnlyChangesets {
+    inner: ArcChangesets,
+}
+
+impl ReadOnlyChangesets {
+    pub fn new(inner: ArcChangesets) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl Changesets for ReadOnlyChangesets {
+    fn repo_id(&self) -> RepositoryId {
+        self.inner.repo_id()
+    }
+
+    async fn add(&self, _ctx: &CoreContext, _cs: ChangesetInsert) -> Result<bool, Error> {
+        rejected("changesets add")
+    }
+
+    async fn add_many(
+        &self,
+        _ctx: &CoreContext,
+        _css: Vec1<(ChangesetInsert, Generation)>,
+    ) -> Result<(), Error> {
+        rejected("changesets add_many")
+    }
+
+    async fn get(
+        &self,
+        ctx: &CoreContext,
+        cs_id: ChangesetId,
+    ) -> Result<Option<ChangesetEntry>, Error> {
+        self.inner.get(ctx, cs_id).await
+    }
+
+    async fn exists(&self, ctx: &CoreContext, cs_id: ChangesetId) -> Result<bool, Error> {
+        self.inner.exists(ctx, cs_id).await
+    }
+
+    async fn get_many(
+        &self,
+        ctx: &CoreContext,
+        cs_ids: Vec<ChangesetId>,
+    ) -> Result<Vec<ChangesetEntry>, Error> {
+        self.inner.get_many(ctx, cs_ids).await
+    }
+
+    async fn get_many_by_prefix(
+        &self,
+        ctx: &CoreContext,
+        cs_prefix: ChangesetIdPrefix,
+        limit: usize,
+    ) -> Result<ChangesetIdsResolvedFromPrefix, Error> {
+        self.inner.get_many_by_prefix(ctx, cs_prefix, limit).await
+    }
+
+    fn prime_cache(&self, ctx: &CoreContext, changesets: &[ChangesetEntry]) {
+        self.inner.prime_cache(ctx, changesets)
+    }
+
+    async fn enumeration_bounds(
+        &self,
+        ctx: &CoreContext,
+        read_from_master: bool,
+        known_heads: Vec<ChangesetId>,
+    ) -> Result<Option<(u64, u64)>> {
+        self.inner
+            .enumeration_bounds(ctx, read_from_master, known_heads)
+            .await
+    }
+
+    fn list_enumeration_range_detailed(
+        &self,
+        ctx: &CoreContext,
+        min_id: u64,
+        max_id: u64,
+        sort_and_limit: Option<(SortOrder, u64)>,
+        read_from_master: bool,
+        include_parents: bool,
+    ) -> BoxStream<'_, Result<EnumerationEntry, Error>> {
+        self.inner.list_enumeration_range_detailed(
+            ctx,
+            min_id,
+            max_id,
+            sort_and_limit,
+            read_from_master,
+            include_parents,
+        )
+    }
+}
+
+/// Bonsai-Hg mapping that can be read, but not added to.
+pub struct ReadOnlyBonsaiHgMapping {
+    inner: ArcBonsaiHgMapping,
+}
+
+impl ReadOnlyBonsaiHgMapping {
+    pub fn new(inner: ArcBonsaiHgMapping) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl BonsaiHgMapping for ReadOnlyBonsaiHgMapping {
+    fn repo_id(&self) -> RepositoryId {
+        self.inner.repo_id()
+    }
+
+    async fn add(&self, _ctx: &CoreContext, _entry: BonsaiHgMappingEntry) -> Result<bool, Error> {
+        rejected("bonsai_hg_mapping add")
+    }
+
+    async fn get(
+        &self,
+        ctx: &CoreContext,
+        cs_id: BonsaiOrHgChangesetIds,
+    ) -> Result<Vec<BonsaiHgMappingEntry>, Error> {
+        self.inner.get(ctx, cs_id).await
+    }
+
+    async fn get_hg_from_bonsai(
+        &self,
+        ctx: &CoreContext,
+        cs_id: ChangesetId,
+    ) -> Result<Option<HgChangesetId>, Error> {
+        self.inner.get_hg_from_bonsai(ctx, cs_id).await
+    }
+
+    async fn get_bonsai_from_hg(
+        &self,
+        ctx: &CoreContext,
+        cs_id: HgChangesetId,
+    ) -> Result<Option<ChangesetId>, Error> {
+        self.inner.get_bonsai_from_hg(ctx, cs_id).await
+    }
+
+    async fn get_many_hg_by_prefix(
+        &self,
+        ctx: &CoreContext,
+        cs_prefix: HgChangesetIdPrefix,
+        limit: usize,
+    ) -> Result<HgChangesetIdsResolvedFromPrefix, Error> {
+        self.inner
+            .get_many_hg_by_prefix(ctx, cs_prefix, limit)
+            .await
+    }
+
+    async fn get_hg_in_range(
+        &self,
+        ctx: &CoreContext,
+        low: HgChangesetId,
+        high: HgChangesetId,
+        limit: usize,
+    ) -> Result<Vec<HgChangesetId>, Error> {
+        self.inner.get_hg_in_range(ctx, low, high, limit).await
+    }
+}
+
+/// Bonsai-Git mapping that can be read, but not added to.
+pub struct ReadOnlyBonsaiGitMapping {
+    inner: ArcBonsaiGitMapping,
+}
+
+impl ReadOnlyBonsaiGitMapping {
+    pub fn new(inner: ArcBonsaiGitMapping) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl BonsaiGitMapping for ReadOnlyBonsaiGitMapping {
+    async fn add(
+        &self,
+        _ctx: &CoreContext,
+        _entry: BonsaiGitMappingEntry,
+    ) -> Result<(), AddGitMappingErrorKind> {
+        rejected("bonsai_git_mapping add").map_err(AddGitMappingErrorKind::from)
+    }
+
+    async fn bulk_add(
+        &self,
+        _ctx: &CoreContext,
+        _entries: &[BonsaiGitMappingEntry],
+    ) -> Result<(), AddGitMappingErrorKind> {
+        rejected("bonsai_git_mapping bulk_add").map_err(AddGitMappingErrorKind::from)
+    }
+
+    async fn bulk_add_git_mapping_in_transaction(
+        &self,
+        _ctx: &CoreContext,
+        _entries: &[BonsaiGitMappingEntry],
+        _transaction: Transaction,
+    ) -> Result<Transaction, AddGitMappingErrorKind> {
+        rejected("bonsai_git_mapping bulk_add_git_mapping_in_transaction")
+            .map_err(AddGitMappingErrorKind::from)
+    }
+
+    async fn get(
+        &self,
+        ctx: &CoreContext,
+        field: BonsaisOrGitShas,
+    ) -> Result<Vec<BonsaiGitMappingEntry>> {
+        self.inner.get(ctx, field).await
+    }
+
+    async fn get_git_sha1_from_bonsai(
+        &self,
+        ctx: &CoreContext,
+        bcs_id: ChangesetId,
+    ) -> Result<Option<GitSha1>> {
+        self.inner.get_git_sha1_from_bonsai(ctx, bcs_id).await
+    }
+
+    async fn get_bonsai_from_git_sha1(
+        &self,
+        ctx: &CoreContext,
+        git_sha1: GitSha1,
+    ) -> Result<Option<ChangesetId>> {
+        self.inner.get_bonsai_from_git_sha1(ctx, git_sha1).await
+    }
+
+    async fn get_many_git_sha1_by_prefix(
+        &self,
+        ctx: &CoreContext,
+        cs_prefix: GitSha1Prefix,
+        limit: usize,
+    ) -> Result<GitSha1sResolvedFromPrefix> {
+        self.inner
+            .get_many_git_sha1_by_prefix(ctx, cs_prefix, limit)
+            .await
+    }
+
+    async fn bulk_import_from_bonsai(
+        &self,
+        _ctx: &CoreContext,
+        _changesets: &[BonsaiChangeset],
+    ) -> Result<()> {
+        rejected("bonsai_git_mapping bulk_import_from_bonsai")
+    }
+
+    async fn get_in_range(
+        &self,
+        ctx: &CoreContext,
+        low: GitSha1,
+        high: GitSha1,
+        limit: usize,
+    ) -> Result<Vec<GitSha1>> {
+        self.inner.get_in_range(ctx, low, high, limit).await
+    }
+}
+
+/// Bonsai-Globalrev mapping that can be read, but not imported into.
+pub struct ReadOnlyBonsaiGlobalrevMapping {
+    inner: ArcBonsaiGlobalrevMapping,
+}
+
+impl ReadOnlyBonsaiGlobalrevMapping {
+    pub fn new(inner: ArcBonsaiGlobalrevMapping) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl BonsaiGlobalrevMapping for ReadOnlyBonsaiGlobalrevMapping {
+    fn repo_id(&self) -> RepositoryId {
+        self.inner.repo_id()
+    }
+
+    async fn bulk_import(
+        &self,
+        _ctx: &CoreContext,
+        _entries: &[BonsaiGlobalrevMappingEntry],
+    ) -> Result<(), Error> {
+        rejected("bonsai_globalrev_mapping bulk_import")
+    }
+
+    async fn get(
+        &self,
+        ctx: &CoreContext,
+        field: BonsaisOrGlobalrevs,
+    ) -> Result<Vec<BonsaiGlobalrevMappingEntry>, Error> {
+        self.inner.get(ctx, field).await
+    }
+
+    async fn get_globalrev_from_bonsai(
+        &self,
+        ctx: &CoreContext,
+        bcs_id: ChangesetId,
+    ) -> Result<Option<Globalrev>, Error> {
+        self.inner.get_globalrev_from_bonsai(ctx, bcs_id).await
+    }
+
+    async fn get_bonsai_from_globalrev(
+        &self,
+        ctx: &CoreContext,
+        globalrev: Globalrev,
+    ) -> Result<Option<ChangesetId>, Error> {
+        self.inner.get_bonsai_from_globalrev(ctx, globalrev).await
+    }
+
+    async fn get_closest_globalrev(
+        &self,
+        ctx: &CoreContext,
+        globalrev: Globalrev,
+    ) -> Result<Option<Globalrev>, Error> {
+        self.inner.get_closest_globalrev(ctx, globalrev).await
+    }
+
+    async fn get_max(&self, ctx: &CoreContext) -> Result<Option<Globalrev>, Error> {
+        self.inner.get_max(ctx).await
+    }
+
+    async fn get_max_custom_repo(
+        &self,
+        ctx: &CoreContext,
+        repo_id: &RepositoryId,
+    ) -> Result<Option<Globalrev>, Error> {
+        self.inner.get_max_custom_repo(ctx, repo_id).await
+    }
+}
+
+/// Bonsai-Svnrev mapping that can be read, but not imported into.
+pub struct ReadOnlyBonsaiSvnrevMapping {
+    inner: ArcBonsaiSvnrevMapping,
+}
+
+impl ReadOnlyBonsaiSvnrevMapping {
+    pub fn new(inner: ArcBonsaiSvnrevMapping) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl BonsaiSvnrevMapping for ReadOnlyBonsaiSvnrevMapping {
+    fn repo_id(&self) -> RepositoryId {
+        self.inner.repo_id()
+    }
+
+    async fn bulk_import(
+        &self,
+        _ctx: &CoreContext,
+        _entries: &[BonsaiSvnrevMappingEntry],
+    ) -> Result<(), Error> {
+        rejected("bonsai_svnrev_mapping bulk_import")
+    }
+
+    async fn get(
+        &self,
+        ctx: &CoreContext,
+        field: BonsaisOrSvnrevs,
+    ) -> Result<Vec<BonsaiSvnrevMappingEntry>, Error> {
+        self.inner.get(ctx, field).await
+    }
+
+    async fn get_svnrev_from_bonsai(
+        &self,
+        ctx: &CoreContext,
+        bcs_id: ChangesetId,
+    ) -> Result<Option<Svnrev>, Error> {
+        self.inner.get_svnrev_from_bonsai(ctx, bcs_id).await
+    }
+
+    async fn get_bonsai_from_svnrev(
+        &self,
+        ctx: &CoreContext,
+        svnrev: Svnrev,
+    ) -> Result<Option<ChangesetId>, Error> {
+        self.inner.get_bonsai_from_svnrev(ctx, svnrev).await
+    }
+
+    async fn bulk_import_from_bonsai(
+        &self,
+        _ctx: &CoreContext,
+        _changesets: &[BonsaiChangeset],
+    ) -> Result<()> {
+        rejected("bonsai_svnrev_mapping bulk_import_from_bonsai")
+    }
+}
+
+/// Mutation store that can be read, but not added to.
+pub struct ReadOnlyHgMutationStore {
+    inner: ArcHgMutationStore,
+}
+
+impl ReadOnlyHgMutationStore {
+    pub fn new(inner: ArcHgMutationStore) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl HgMutationStore for ReadOnlyHgMutationStore {
+    async fn add_entries(
+        &self,
+        _ctx: &CoreContext,
+        _new_changeset_ids: HashSet<HgChangesetId>,
+        _entries: Vec<HgMutationEntry>,
+    ) -> Result<()> {
+        rejected("hg_mutation_store add_entries")
+    }
+
+    async fn all_predecessors(
+        &self,
+        ctx: &CoreContext,
+        changeset_ids: HashSet<HgChangesetId>,
+    ) -> Result<Vec<HgMutationEntry>> {
+        self.inner.all_predecessors(ctx, changeset_ids).await
+    }
+
+    async fn all_predecessors_by_changeset(
+        &self,
+        ctx: &CoreContext,
+        changeset_ids: HashSet<HgChangesetId>,
+    ) -> Result<HashMap<HgChangesetId, Vec<HgMutationEntry>>> {
+        self.inner
+            .all_predecessors_by_changeset(ctx, changeset_ids)
+            .await
+    }
+
+    fn repo_id(&self) -> RepositoryId {
+        self.inner.repo_id()
+    }
+}
+
+/// Phases that can be queried, but never persisted.
+pub struct ReadOnlyPhases {
+    inner: ArcPhases,
+}
+
+impl ReadOnlyPhases {
+    pub fn new(inner: ArcPhases) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl Phases for ReadOnlyPhases {
+    async fn add_reachable_as_public(
+        &self,
+        _ctx: &CoreContext,
+        _heads: Vec<ChangesetId>,
+    ) -> Result<Vec<ChangesetId>> {
+        rejected("phases add_reachable_as_public")
+    }
+
+    async fn add_public_with_known_public_ancestors(
+        &self,
+        _ctx: &CoreContext,
+        _csids: Vec<ChangesetId>,
+    ) -> Result<()> {
+        rejected("phases add_public_with_known_public_ancestors")
+    }
+
+    async fn get_public(
+        &self,
+        ctx: &CoreContext,
+        csids: Vec<ChangesetId>,
+        _ephemeral_derive: bool,
+    ) -> Result<HashSet<ChangesetId>> {
+        // Deriving phases normally persists the newly public commits, so
+        // always derive ephemerally.
+        self.inner.get_public(ctx, csids, true).await
+    }
+
+    async fn get_cached_public(
+        &self,
+        ctx: &CoreContext,
+        csids: Vec<ChangesetId>,
+    ) -> Result<HashSet<ChangesetId>> {
+        self.inner.get_cached_public(ctx, csids).await
+    }
+
+    async fn list_all_public(&self, ctx: &CoreContext) -> Result<Vec<ChangesetId>> {
+        self.inner.list_all_public(ctx).await
+    }
+
+    fn with_frozen_public_heads(&self, heads: Vec<ChangesetId>) -> ArcPhases {
+        Arc::new(ReadOnlyPhases::new(
+            self.inner.with_frozen_public_heads(heads),
+        ))
+    }
+}
+
+/// Long-running requests queue that can be listed, but not modified.
+///
+/// Note that polling a request marks it as polled, so `poll` is rejected
+/// too.
+pub struct ReadOnlyLongRunningRequestsQueue {
+    inner: ArcLongRunningRequestsQueue,
+}
+
+impl ReadOnlyLongRunningRequestsQueue {
+    pub fn new(inner: ArcLongRunningRequestsQueue) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl LongRunningRequestsQueue for ReadOnlyLongRunningRequestsQueue {
+    async fn add_request(
+        &self,
+        _ctx: &CoreContext,
+        _request_type: &RequestType,
+        _repo_id: &RepositoryId,
+        _bookmark: &BookmarkKey,
+        _args_blobstore_key: &BlobstoreKey,
+    ) -> Result<RowId> {
+        rejected("long_running_requests_queue add_request")
+    }
+
+    async fn claim_and_get_new_request(
+        &self,
+        _ctx: &CoreContext,
+        _claimed_by: &ClaimedBy,
+        _supported_repos: &[RepositoryId],
+    ) -> Result<Option<LongRunningRequestEntry>> {
+        rejected("long_running_requests_queue claim_and_get_new_request")
+    }
+
+    async fn test_get_request_entry_by_id(
+        &self,
+        ctx: &CoreContext,
+        id: &RowId,
+    ) -> Result<Option<LongRunningRequestEntry>> {
+        self.inner.test_get_request_entry_by_id(ctx, id).await
+    }
+
+    async fn mark_in_progress(
+        &self,
+        _ctx: &CoreContext,
+        _req_id: &RequestId,
+        _claimed_by: &ClaimedBy,
+    ) -> Result<bool> {
+        rejected("long_running_requests_queue mark_in_progress")
+    }
+
+    async fn update_in_progress_timestamp(
+        &self,
+        _ctx: &CoreContext,
+        _req_id: &RequestId,
+    ) -> Result<bool> {
+        rejected("long_running_requests_queue update_in_progress_timestamp")
+    }
+
+    async fn find_abandoned_requests(
+        &self,
+        ctx: &CoreContext,
+        repo_ids: &[RepositoryId],
+        abandoned_timestamp: Timestamp,
+    ) -> Result<Vec<RequestId>> {
+        self.inner
+            .find_abandoned_requests(ctx, repo_ids, abandoned_timestamp)
+            .await
+    }
+
+    async fn mark_abandoned_request_as_new(
+        &self,
+        _ctx: &CoreContext,
+        _request_id: RequestId,
+        _abandoned_timestamp: Timestamp,
+    ) -> Result<bool> {
+        rejected("long_running_requests_queue mark_abandoned_request_as_new")
+    }
+
+    async fn mark_ready(
+        &self,
+        _ctx: &CoreContext,
+        _req_id: &RequestId,
+        _blobstore_result_key: BlobstoreKey,
+    ) -> Result<bool> {
+        rejected("long_running_requests_queue mark_ready")
+    }
+
+    async fn mark_new(&self, _ctx: &CoreContext, _req_id: &RequestId) -> Result<bool> {
+        rejected("long_running_requests_queue mark_new")
+    }
+
+    async fn test_mark(
+        &self,
+        _ctx: &CoreContext,
+        _row_id: &RowId,
+        _status: RequestStatus,
+    ) -> Result<bool> {
+        rejected("long_running_requests_queue test_mark")
+    }
+
+    async fn poll(
+        &self,
+        _ctx: &CoreContext,
+        _req_id: &RequestId,
+    ) -> Result<Option<(bool, LongRunningRequestEntry)>> {
+        rejected("long_running_requests_queue poll")
+    }
+
+    async fn list_requests(
+        &self,
+        ctx: &CoreContext,
+        repo_ids: &[RepositoryId],
+        last_update_newer_than: Option<&Timestamp>,
+    ) -> Result<Vec<LongRunningRequestEntry>> {
+        self.inner
+            .list_requests(ctx, repo_ids, last_update_newer_than)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use changesets_impl::SqlChangesetsBuilder;
+    use dbbookmarks::SqlBookmarksBuilder;
+    use fbinit::FacebookInit;
+    use mononoke_types_mocks::changesetid::ONES_CSID;
+    use mononoke_types_mocks::changesetid::TWOS_CSID;
+    use mononoke_types_mocks::repo::REPO_ZERO;
+    use rendezvous::RendezVousOptions;
+    use sql_construct::SqlConstruct;
+
+    use super::*;
+
+    fn assert_read_only_error(err: Error, operation: &str) {
+        match err.downcast_ref::<ReadOnlyModeError>() {
+            Some(err) => assert_eq!(err.operation, operation),
+            None => panic!("expected ReadOnlyModeError, got {:?}", err),
+        }
+    }
+
+    #[fbinit::test]
+    async fn test_readonly_bookmarks(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let sql_bookmarks =
+            Arc::new(SqlBookmarksBuilder::with_sqlite_in_memory()?.with_repo_id(REPO_ZERO));
+        let key = BookmarkKey::new("main")?;
+
+        let mut txn = sql_bookmarks.create_transaction(ctx.clone());
+        txn.create(&key, ONES_CSID, BookmarkUpdateReason::TestMove)?;
+        assert!(txn.commit().await?);
+
+        let bookmarks = ReadOnlyBookmarks::new(sql_bookmarks);
+        assert_eq!(bookmarks.get(ctx.clone(), &key).await?, Some(ONES_CSID));
+
+        let mut txn = bookmarks.create_transaction(ctx.clone());
+        let err = txn
+            .update(&key, TWOS_CSID, ONES_CSID, BookmarkUpdateReason::TestMove)
+            .unwrap_err();
+        assert_read_only_error(err, "bookmarks update");
+        let err = txn.commit().await.unwrap_err();
+        assert_read_only_error(err, "bookmarks commit");
+
+        assert_eq!(bookmarks.get(ctx.clone(), &key).await?, Some(ONES_CSID));
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_readonly_changesets(fb: FacebookInit) -> Result<()> {
+        let ctx = CoreContext::test_mock(fb);
+        let sql_changesets = Arc::new(
+            SqlChangesetsBuilder::with_sqlite_in_memory()?
+                .build(RendezVousOptions::for_test(), REPO_ZERO),
+        );
+        sql_changesets
+            .add(
+                &ctx,
+                ChangesetInsert {
+                    cs_id: ONES_CSID,
+                    parents: vec![],
+                    extra: None,
+                },
+            )
+            .await?;
+
+        let changesets = ReadOnlyChangesets::new(sql_changesets);
+        assert_eq!(changesets.repo_id(), REPO_ZERO);
+        assert!(changesets.exists(&ctx, ONES_CSID).await?);
+
+        let err = changesets
+            .add(
+                &ctx,
+                ChangesetInsert {
+                    cs_id: TWOS_CSID,
+                    parents: vec![ONES_CSID],
+                    extra: None,
+                },
+            )
+            .await
+            .unwrap_err();
+        assert_read_only_error(err, "changesets add");
+        assert!(!changesets.exists(&ctx, TWOS_CSID).await?);
+        Ok(())
+    }
+}
@@ -28,6 +28,7 @@ mod tests {
     use context::CoreContext;
     use cross_repo_sync::create_commit_syncers;
     use cross_repo_sync::CommitSyncContext;
+    use cross_repo_sync::ParentOverrideStrictness;
     use derived_data_manager::BonsaiDerivable;
     use derived_data_utils::derived_data_utils;
     use fbinit::FacebookInit;
@@ -1001,6 +1002,7 @@ mod tests {
                 &ctx,
                 change_mapping_cs_id,
                 None,
+                ParentOverrideStrictness::Strict,
                 &CommitSyncConfigVersion("TEST_VERSION2".to_string()),
                 CommitSyncContext::Tests,
             )
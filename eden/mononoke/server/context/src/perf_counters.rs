@@ -93,6 +93,10 @@ define_perf_counters! {
         GettreepackResponseSize,
         HgMutationStoreNumAdded,
         HgMutationStoreNumFetched,
+        HooksFailed,
+        HooksRejected,
+        HooksRun,
+        HooksWallTimeMs,
         MemcacheHits,
         MemcacheMisses,
         NullLinknode,
@@ -195,6 +199,10 @@ impl PerfCounterType {
             | GettreepackResponseSize
             | HgMutationStoreNumAdded
             | HgMutationStoreNumFetched
+            | HooksFailed
+            | HooksRejected
+            | HooksRun
+            | HooksWallTimeMs
             | MemcacheHits
             | MemcacheMisses
             | NullLinknode
@@ -144,6 +144,9 @@ pub struct MononokeTunables {
     disable_commit_scribe_logging_scs: TunableBool,
     xrepo_sync_disable_all_syncs: TunableBool,
     xrepo_disable_commit_sync_lease: TunableBool,
+    // How many independent unsynced ancestors `sync_commit` syncs
+    // concurrently. 0 means the default, 1 syncs them one by one.
+    xrepo_sync_ancestors_concurrency: TunableI64,
 
     // Use Background session class while deriving data. This makes derived data not write
     // data to blobstore sync queue if a write was successful to the main blobstore.
@@ -88,7 +88,7 @@ py_class!(class checkoutplan |py| {
         }
         let vfs = VFS::new(root.to_path_buf()).map_pyerr(py)?;
         let checkout = Checkout::from_config(vfs, &config).map_pyerr(py)?;
-        let mut plan = checkout.plan_action_map(actions);
+        let mut plan = checkout.plan_action_map(actions).map_pyerr(py)?;
         if let Some(progress_path) = progress_path {
             plan.add_progress(progress_path.as_path()).map_pyerr(py)?;
         }
@@ -127,17 +127,23 @@ py_class!(class checkoutplan |py| {
     def apply(&self, store: ImplInto<ArcReadFileContents>) -> PyResult<PyNone> {
         let plan = self.plan(py);
         let store = store.into();
-        py.allow_threads(|| try_block_unless_interrupted(
-            plan.apply_store(store.as_ref())
-        )).map_pyerr(py)?;
+        // Convert to anyhow::Error before blocking so that interruption
+        // surfaces as a plain io::Error, which Python maps to IOError.
+        py.allow_threads(|| try_block_unless_interrupted(async {
+            Ok::<_, anyhow::Error>(plan.apply_store(store.as_ref()).await?)
+        })).map_pyerr(py)?;
         Ok(PyNone)
     }
 
-    def apply_dry_run(&self, store: ImplInto<ArcReadFileContents>) -> PyResult<(usize, u64)> {
+    def apply_dry_run(
+        &self,
+        store: ImplInto<ArcReadFileContents>,
+        include_already_written: bool = false
+    ) -> PyResult<(usize, u64)> {
         let plan = self.plan(py);
         let store = store.into();
         py.allow_threads(|| try_block_unless_interrupted(
-            plan.apply_store_dry_run(store.as_ref())
+            plan.apply_store_dry_run(store.as_ref(), include_already_written)
         )).map_pyerr(py)
     }
 
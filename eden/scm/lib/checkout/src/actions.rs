@@ -7,6 +7,7 @@
 
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt;
 use std::ops::Deref;
 use std::ops::DerefMut;
@@ -22,14 +23,22 @@ use pathmatcher::Matcher;
 use pathmatcher::XorMatcher;
 use progress_model::ProgressBar;
 use tracing::instrument;
+use types::RepoPath;
 use types::RepoPathBuf;
 
 /// Map of simple actions that needs to be performed to move between revisions without conflicts.
 #[derive(Debug, Default, Clone, PartialEq)]
 pub struct ActionMap {
     map: HashMap<RepoPathBuf, Action>,
+    /// Set by `from_diff_for_paths`.
+    scope: Option<PathScope>,
 }
 
+/// Paths a scoped checkout is limited to. A file is in scope if it is one of
+/// the paths, or is in a directory that is.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PathScope(HashSet<RepoPathBuf>);
+
 /// Basic update action.
 /// Diff between regular(no conflict checkin) commit generates list of such actions.
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -82,7 +91,49 @@ impl ActionMap {
                 }
             }
         }
-        Ok(Self { map })
+        Ok(Self { map, scope: None })
+    }
+
+    /// Like `from_diff`, but only keeps the actions for `paths` and the files
+    /// in directories of `paths`, so that a subset of the working copy can be
+    /// moved to another revision, e.g. by `revert`. Other files are left
+    /// alone even if they differ.
+    ///
+    /// Files of `paths` in a directory that replaced a file outside of
+    /// `paths` get no action either, since writing them would remove that
+    /// file. `diff` must not be filtered to `paths` for them to be found.
+    ///
+    /// The map is scoped: see `CheckoutPlan::scoped`.
+    pub fn from_diff_for_paths<D: Iterator<Item = Result<DiffEntry>>>(
+        diff: D,
+        paths: &[RepoPathBuf],
+    ) -> Result<Self> {
+        let scope = PathScope(paths.iter().cloned().collect());
+        let mut map = HashMap::new();
+        let mut removed_out_of_scope = HashSet::new();
+        for (path, action) in Self::from_diff(diff)? {
+            if scope.contains(&path) {
+                map.insert(path, action);
+            } else if action == Action::Remove {
+                removed_out_of_scope.insert(path);
+            }
+        }
+        if !removed_out_of_scope.is_empty() {
+            map.retain(|path, action| {
+                *action == Action::Remove
+                    || !path
+                        .parents()
+                        .any(|parent| removed_out_of_scope.contains(parent))
+            });
+        }
+        Ok(Self {
+            map,
+            scope: Some(scope),
+        })
+    }
+
+    pub(crate) fn scope(&self) -> Option<&PathScope> {
+        self.scope.as_ref()
     }
 
     pub fn with_sparse_profile_change<
@@ -149,10 +200,24 @@ impl ActionMap {
     pub fn empty() -> Self {
         Self {
             map: Default::default(),
+            scope: None,
         }
     }
 }
 
+impl PathScope {
+    pub(crate) fn contains(&self, path: &RepoPath) -> bool {
+        self.0.contains(path) || path.parents().any(|parent| self.0.contains(parent))
+    }
+
+    /// The paths of the scope, sorted.
+    pub(crate) fn paths(&self) -> Vec<&RepoPathBuf> {
+        let mut paths: Vec<_> = self.0.iter().collect();
+        paths.sort();
+        paths
+    }
+}
+
 impl Deref for ActionMap {
     type Target = HashMap<RepoPathBuf, Action>;
 
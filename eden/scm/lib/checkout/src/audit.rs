@@ -0,0 +1,287 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::HashSet;
+use std::fs::Metadata;
+use std::io;
+use std::sync::Arc;
+
+use anyhow::Result;
+use futures::stream;
+use futures::StreamExt;
+use manifest::FileMetadata;
+use manifest::FileType;
+use manifest::Manifest;
+use pathmatcher::DynMatcher;
+use pathmatcher::Matcher;
+use progress_model::ProgressBar;
+use storemodel::ReadFileContents;
+use tokio::runtime::Handle;
+use tracing::warn;
+use types::Key;
+use types::PathComponent;
+use types::RepoPathBuf;
+use vfs::VFS;
+
+use crate::transform::ContentTransform;
+use crate::CheckoutPlan;
+use crate::VFS_BATCH_SIZE;
+
+/// Differences between the working copy and a manifest, found by
+/// [`audit_working_copy`]. Each list is sorted.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct AuditReport {
+    /// Files of the manifest that don't exist on disk.
+    pub missing: Vec<RepoPathBuf>,
+    /// Files that are not a regular file, executable or symlink on disk like
+    /// in the manifest.
+    pub type_mismatches: Vec<RepoPathBuf>,
+    /// Files whose content on disk differs from the manifest.
+    pub content_mismatches: Vec<RepoPathBuf>,
+    /// Files on disk that are not in the manifest, in directories of the
+    /// manifest.
+    pub extra: Vec<RepoPathBuf>,
+}
+
+impl AuditReport {
+    /// Whether the working copy matches the manifest.
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty()
+            && self.type_mismatches.is_empty()
+            && self.content_mismatches.is_empty()
+            && self.extra.is_empty()
+    }
+}
+
+/// Which files [`audit_working_copy`] compares the content of. Content is
+/// only compared for files that exist on disk with the expected type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentCheck {
+    /// Don't compare content, only existence and file type.
+    Skip,
+    /// Compare the content of all files.
+    All,
+    /// Compare the content of one of every N files, in manifest order.
+    /// `EveryNth(0)` only compares the first file.
+    EveryNth(usize),
+    /// Compare the content of files of at most this many bytes on disk.
+    MaxSize(u64),
+}
+
+impl ContentCheck {
+    fn selects(&self, index: usize, size: u64) -> bool {
+        match *self {
+            ContentCheck::Skip => false,
+            ContentCheck::All => true,
+            ContentCheck::EveryNth(n) => index.is_multiple_of(n),
+            ContentCheck::MaxSize(max) => size <= max,
+        }
+    }
+}
+
+/// State on disk of a file of the manifest.
+enum DiskState {
+    Missing,
+    TypeMismatch,
+    Matching { size: u64 },
+}
+
+/// Compares the working copy in `vfs` with the files of `manifest` matched
+/// by `matcher`.
+///
+/// Every file is checked to exist with the file type of the manifest, and
+/// files selected by `content_check` have their content fetched from `store`
+/// and compared, without the content transforms of a `Checkout`.
+/// Directories containing files of the manifest are listed to find extra
+/// files, but unknown directories are not descended into.
+///
+/// Like `CheckoutPlan::apply_store`, filesystem operations run in batches on
+/// the tokio blocking thread pool, with at most `concurrency` batches in
+/// flight. Dropping the returned future cancels the audit: batches already
+/// running finish, but no new ones are started.
+pub async fn audit_working_copy(
+    vfs: &VFS,
+    manifest: &impl Manifest,
+    store: &dyn ReadFileContents<Error = anyhow::Error>,
+    matcher: DynMatcher,
+    concurrency: usize,
+    content_check: ContentCheck,
+) -> Result<AuditReport> {
+    let mut files = vec![];
+    let mut expected = HashSet::new();
+    let mut dirs = HashSet::new();
+    for file in manifest.files(matcher.clone()) {
+        let file = file?;
+        dirs.extend(file.path.parents().map(|dir| dir.to_owned()));
+        expected.insert(file.path.clone());
+        files.push((file.path, file.meta));
+    }
+    let expected = Arc::new(expected);
+
+    let bar = &ProgressBar::register_new("Auditing", files.len() as u64, "files");
+    let states = stream::iter(files.into_iter().enumerate())
+        .chunks(VFS_BATCH_SIZE)
+        .map(|files| {
+            let vfs = vfs.clone();
+            let bar = bar.clone();
+            Handle::current().spawn_blocking(move || {
+                let count = files.len() as u64;
+                let states = stat_files(&vfs, files);
+                bar.increase_position(count);
+                states
+            })
+        })
+        .buffer_unordered(concurrency)
+        .map(|r| r?);
+    let states = CheckoutPlan::process_vec_work_stream(states).await?;
+
+    let mut report = AuditReport::default();
+    let mut check_content = vec![];
+    for (index, path, meta, state) in states {
+        match state {
+            DiskState::Missing => report.missing.push(path),
+            DiskState::TypeMismatch => report.type_mismatches.push(path),
+            DiskState::Matching { size } => {
+                if meta.file_type != FileType::GitSubmodule && content_check.selects(index, size) {
+                    check_content.push(Key::new(path, meta.hgid));
+                }
+            }
+        }
+    }
+
+    let content_mismatches = store
+        .read_file_contents(check_content)
+        .await
+        .chunks(VFS_BATCH_SIZE)
+        .map(|v| {
+            let vfs = vfs.clone();
+            Handle::current().spawn_blocking(move || -> Result<Vec<RepoPathBuf>> {
+                let v: std::result::Result<Vec<_>, _> = v.into_iter().collect();
+                let v = v?
+                    .into_iter()
+                    .map(|(data, key)| (data, key, None))
+                    .collect();
+                CheckoutPlan::check_content(&vfs, &ContentTransform::default(), v)
+            })
+        })
+        .buffer_unordered(concurrency)
+        .map(|r| r?);
+    report.content_mismatches = CheckoutPlan::process_vec_work_stream(content_mismatches).await?;
+
+    let extra = stream::iter(dirs)
+        .chunks(VFS_BATCH_SIZE)
+        .map(|dirs| {
+            let vfs = vfs.clone();
+            let expected = expected.clone();
+            let matcher = matcher.clone();
+            Handle::current()
+                .spawn_blocking(move || find_extra_files(&vfs, dirs, &expected, &matcher))
+        })
+        .buffer_unordered(concurrency)
+        .map(|r| r?);
+    report.extra = CheckoutPlan::process_vec_work_stream(extra).await?;
+
+    report.missing.sort();
+    report.type_mismatches.sort();
+    report.content_mismatches.sort();
+    report.extra.sort();
+    Ok(report)
+}
+
+fn stat_files(
+    vfs: &VFS,
+    files: Vec<(usize, (RepoPathBuf, FileMetadata))>,
+) -> Result<Vec<(usize, RepoPathBuf, FileMetadata, DiskState)>> {
+    let mut result = Vec::with_capacity(files.len());
+    for (index, (path, meta)) in files {
+        let state = match vfs.metadata(&path) {
+            Ok(metadata) if matches_file_type(vfs, meta.file_type, &metadata) => {
+                DiskState::Matching {
+                    size: metadata.len(),
+                }
+            }
+            Ok(_) => DiskState::TypeMismatch,
+            Err(err) if is_not_found(&err) => DiskState::Missing,
+            Err(err) => return Err(err.context(format!("Can not stat {}", path))),
+        };
+        result.push((index, path, meta, state));
+    }
+    Ok(result)
+}
+
+pub(crate) fn is_not_found(err: &anyhow::Error) -> bool {
+    matches!(err.downcast_ref::<io::Error>(), Some(e) if e.kind() == io::ErrorKind::NotFound)
+}
+
+/// Whether `metadata` is of the file type `expected`, taking into account
+/// that checkout writes symlinks as regular files, and doesn't set the exec
+/// bit, on filesystems that don't support them.
+pub(crate) fn matches_file_type(vfs: &VFS, expected: FileType, metadata: &Metadata) -> bool {
+    let file_type = metadata.file_type();
+    match expected {
+        FileType::Symlink if vfs.supports_symlinks() => file_type.is_symlink(),
+        FileType::Symlink => file_type.is_file(),
+        FileType::Regular | FileType::Executable => {
+            file_type.is_file()
+                && (!vfs.supports_executables()
+                    || is_executable(metadata) == (expected == FileType::Executable))
+        }
+        // Submodules are not written by checkout.
+        FileType::GitSubmodule => true,
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &Metadata) -> bool {
+    false
+}
+
+/// Files directly in `dirs` on disk that are matched by `matcher` but not
+/// `expected`.
+fn find_extra_files(
+    vfs: &VFS,
+    dirs: Vec<RepoPathBuf>,
+    expected: &HashSet<RepoPathBuf>,
+    matcher: &dyn Matcher,
+) -> Result<Vec<RepoPathBuf>> {
+    let mut result = vec![];
+    for dir in dirs {
+        let full_path = vfs.join(&dir);
+        let entries = match std::fs::read_dir(&full_path) {
+            Ok(entries) => entries,
+            // Files of a directory that is missing, or replaced by a file,
+            // are reported as missing already.
+            Err(_) if !full_path.is_dir() => continue,
+            Err(err) => return Err(err.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                continue;
+            }
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(name) => {
+                    warn!("Ignoring non UTF-8 file name {:?} in {}", name, dir);
+                    continue;
+                }
+            };
+            let mut path = dir.clone();
+            path.push(PathComponent::from_str(&name)?);
+            if !expected.contains(&path) && matcher.matches_file(&path)? {
+                result.push(path);
+            }
+        }
+    }
+    Ok(result)
+}
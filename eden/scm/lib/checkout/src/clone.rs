@@ -37,6 +37,7 @@ use vfs::VFS;
 use workingcopy::sparse;
 
 use crate::file_state;
+use crate::verify::verify_applied_if_enabled;
 use crate::ActionMap;
 use crate::Checkout;
 use crate::CheckoutPlan;
@@ -153,7 +154,7 @@ impl CheckoutState {
         let actions = ActionMap::from_diff(diff).context("error creating checkout action map")?;
 
         let checkout = Checkout::from_config(vfs.clone(), config)?;
-        let mut plan = checkout.plan_action_map(actions);
+        let mut plan = checkout.plan_action_map(actions)?;
 
         // Write out overrides first so they don't change when resuming
         // this checkout.
@@ -184,6 +185,7 @@ impl CheckoutState {
         ts.set_metadata(BTreeMap::from([("p1".to_string(), target.to_hex())]))?;
 
         update_dirstate(&plan, ts, &vfs)?;
+        verify_applied_if_enabled(&plan, &vfs, ts)?;
         flush_dirstate(config, ts, dot_path, target)?;
 
         remove_file(dot_path.join("updatestate"))?;
@@ -0,0 +1,515 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::time::Duration;
+
+use anyhow::bail;
+use anyhow::format_err;
+use anyhow::Result;
+use configmodel::convert::FromConfigValue;
+use configmodel::Config;
+use configmodel::ConfigExt;
+use minibytes::Text;
+use tracing::warn;
+use vfs::RetryPolicy;
+
+use crate::transform::EolPolicy;
+use crate::tune::AutoTune;
+use crate::tune::Bounds;
+use crate::ProgressSync;
+use crate::VFS_BATCH_SIZE;
+
+const SECTION: &str = "nativecheckout";
+
+const DEFAULT_CONCURRENCY: usize = 16;
+const DEFAULT_MIN_BATCH_SIZE: usize = 10;
+const DEFAULT_MAX_BATCH_SIZE: usize = 1000;
+const DEFAULT_MIN_CONCURRENCY: usize = 1;
+const DEFAULT_MAX_CONCURRENCY: usize = 64;
+const DEFAULT_AUTOTUNE_BATCHES: usize = 64;
+const DEFAULT_VFS_WORKERS: usize = 16;
+const DEFAULT_RETRY_BACKOFF_MS: u64 = 10;
+/// The retry delay is capped at one second, so a larger initial backoff
+/// would not be honored.
+const MAX_RETRY_BACKOFF_MS: u64 = 1000;
+/// Delay before the first retry round of failed content fetches, doubled
+/// for each following round.
+const DEFAULT_FETCH_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Keys of the section that are read elsewhere, and must not be reported
+/// as unknown.
+const OTHER_KEYS: &[&str] = &["rebaseonenative", "usescmstore"];
+
+/// Keys of the section read by `CheckoutConfig::from_config`.
+const KEYS: &[&str] = &[
+    "concurrency",
+    "batchsize",
+    "autotune",
+    "minbatchsize",
+    "maxbatchsize",
+    "minconcurrency",
+    "maxconcurrency",
+    "autotunebatches",
+    "vfsworkers",
+    "progress-sync",
+    "retries",
+    "retrybackoffms",
+    "fixdirpermissions",
+    "max-retries",
+    "checkdiskspace",
+    "allowlongpaths",
+    "eol",
+    "warnunknown",
+    "verifyapplied",
+];
+
+/// All `nativecheckout.*` options used by `Checkout`, parsed and validated
+/// once.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckoutConfig {
+    /// Number of concurrent batches of filesystem operations.
+    /// `nativecheckout.concurrency`.
+    pub(crate) concurrency: usize,
+    /// Number of files per batch of filesystem operations.
+    /// `nativecheckout.batchsize`.
+    pub(crate) batch_size: usize,
+    /// Bounds of the batch size and concurrency, adjusted from the observed
+    /// latency of the first batches if set, see the `tune` module.
+    /// Otherwise they are fixed, so checkouts are deterministic.
+    /// `nativecheckout.autotune`.
+    pub(crate) autotune: Option<AutoTune>,
+    /// Number of threads writing to the working copy, unless the `Checkout`
+    /// was given a writer. `nativecheckout.vfsworkers`.
+    pub(crate) vfs_workers: usize,
+    /// `nativecheckout.progress-sync`.
+    pub(crate) progress_sync: ProgressSync,
+    /// `nativecheckout.retries`, `nativecheckout.retrybackoffms` and
+    /// `nativecheckout.fixdirpermissions`.
+    pub(crate) retry_policy: RetryPolicy,
+    /// Number of rounds retrying the keys whose content fetch failed with a
+    /// retryable error, after all other files are written.
+    /// `nativecheckout.max-retries`.
+    pub(crate) fetch_retries: u32,
+    /// Delay before the first fetch retry round.
+    pub(crate) fetch_retry_backoff: Duration,
+    /// `nativecheckout.checkdiskspace`.
+    pub(crate) check_disk_space: bool,
+    /// Skip the path length check, for filesystems known to support paths
+    /// longer than the platform limit. `nativecheckout.allowlongpaths`.
+    pub(crate) allow_long_paths: bool,
+    /// Line endings of text files written. `nativecheckout.eol`.
+    pub(crate) eol: EolPolicy,
+    /// Check the working copy and the treestate against the plan after
+    /// checkout, see `verify_applied`. `nativecheckout.verifyapplied`.
+    pub(crate) verify_applied: bool,
+}
+
+impl Default for CheckoutConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: DEFAULT_CONCURRENCY,
+            batch_size: VFS_BATCH_SIZE,
+            autotune: None,
+            vfs_workers: DEFAULT_VFS_WORKERS,
+            progress_sync: ProgressSync::default(),
+            retry_policy: RetryPolicy {
+                retries: 0,
+                backoff: Duration::from_millis(DEFAULT_RETRY_BACKOFF_MS),
+                fix_parent_dir_permissions: false,
+            },
+            fetch_retries: 0,
+            fetch_retry_backoff: DEFAULT_FETCH_RETRY_BACKOFF,
+            check_disk_space: false,
+            allow_long_paths: false,
+            eol: EolPolicy::default(),
+            verify_applied: false,
+        }
+    }
+}
+
+impl CheckoutConfig {
+    /// Parse and validate the `nativecheckout` section. Errors name the
+    /// offending config key.
+    ///
+    /// If `nativecheckout.warnunknown` is set, keys of the section that are
+    /// not known are logged as warnings, to catch typos.
+    pub fn from_config(config: &dyn Config) -> Result<Self> {
+        let concurrency = get(config, "concurrency")?.unwrap_or(DEFAULT_CONCURRENCY);
+        if concurrency == 0 {
+            bail!("{}.concurrency must be at least 1", SECTION);
+        }
+        let batch_size = get(config, "batchsize")?.unwrap_or(VFS_BATCH_SIZE);
+        if batch_size == 0 {
+            bail!("{}.batchsize must be at least 1", SECTION);
+        }
+        let autotune = autotune(config, batch_size, concurrency)?;
+        let vfs_workers = get(config, "vfsworkers")?.unwrap_or(DEFAULT_VFS_WORKERS);
+        if vfs_workers == 0 {
+            bail!("{}.vfsworkers must be at least 1", SECTION);
+        }
+
+        let progress_sync = match config.get(SECTION, "progress-sync") {
+            Some(value) => value
+                .parse()
+                .map_err(|e| format_err!("Failed to parse {}.progress-sync: {}", SECTION, e))?,
+            None => ProgressSync::default(),
+        };
+
+        let retries: Option<u32> = get(config, "retries")?;
+        let backoff_ms: Option<u64> = get(config, "retrybackoffms")?;
+        if backoff_ms.is_some() && retries.unwrap_or_default() == 0 {
+            bail!(
+                "{}.retrybackoffms has no effect unless {}.retries is set",
+                SECTION,
+                SECTION
+            );
+        }
+        let backoff_ms = backoff_ms.unwrap_or(DEFAULT_RETRY_BACKOFF_MS);
+        if backoff_ms > MAX_RETRY_BACKOFF_MS {
+            bail!(
+                "{}.retrybackoffms must be at most {}, got {}",
+                SECTION,
+                MAX_RETRY_BACKOFF_MS,
+                backoff_ms
+            );
+        }
+        let retry_policy = RetryPolicy {
+            retries: retries.unwrap_or_default(),
+            backoff: Duration::from_millis(backoff_ms),
+            fix_parent_dir_permissions: get(config, "fixdirpermissions")?.unwrap_or_default(),
+        };
+        let fetch_retries: u32 = get(config, "max-retries")?.unwrap_or_default();
+
+        let check_disk_space: bool = get(config, "checkdiskspace")?.unwrap_or_default();
+        let allow_long_paths: bool = get(config, "allowlongpaths")?.unwrap_or_default();
+        let eol = match config.get(SECTION, "eol") {
+            Some(value) => value
+                .parse()
+                .map_err(|e| format_err!("Failed to parse {}.eol: {}", SECTION, e))?,
+            None => EolPolicy::default(),
+        };
+        let verify_applied: bool = get(config, "verifyapplied")?.unwrap_or_default();
+
+        if get::<bool>(config, "warnunknown")?.unwrap_or_default() {
+            for key in Self::unknown_keys(config) {
+                warn!("Unknown config {}.{}", SECTION, key);
+            }
+        }
+
+        Ok(Self {
+            concurrency,
+            batch_size,
+            autotune,
+            vfs_workers,
+            progress_sync,
+            retry_policy,
+            fetch_retries,
+            fetch_retry_backoff: DEFAULT_FETCH_RETRY_BACKOFF,
+            check_disk_space,
+            allow_long_paths,
+            eol,
+            verify_applied,
+        })
+    }
+
+    /// Keys of the `nativecheckout` section that are not used.
+    pub fn unknown_keys(config: &dyn Config) -> Vec<Text> {
+        config
+            .keys(SECTION)
+            .into_iter()
+            .filter(|key| {
+                let key: &str = key;
+                !KEYS.contains(&key) && !OTHER_KEYS.contains(&key)
+            })
+            .collect()
+    }
+}
+
+/// Parse the options of `nativecheckout.autotune`. The initial `batch_size`
+/// and `concurrency` must be within their bounds.
+fn autotune(
+    config: &dyn Config,
+    batch_size: usize,
+    concurrency: usize,
+) -> Result<Option<AutoTune>> {
+    const OPTIONS: &[&str] = &[
+        "minbatchsize",
+        "maxbatchsize",
+        "minconcurrency",
+        "maxconcurrency",
+        "autotunebatches",
+    ];
+    if !get::<bool>(config, "autotune")?.unwrap_or_default() {
+        for name in OPTIONS {
+            if config.get(SECTION, name).is_some() {
+                bail!(
+                    "{}.{} has no effect unless {}.autotune is set",
+                    SECTION,
+                    name,
+                    SECTION
+                );
+            }
+        }
+        return Ok(None);
+    }
+
+    let bounds = |name: &str, value: usize, min: usize, max: usize| -> Result<Bounds> {
+        let min = get(config, &format!("min{}", name))?.unwrap_or(min);
+        let max = get(config, &format!("max{}", name))?.unwrap_or(max);
+        if min == 0 || min > value || value > max {
+            bail!(
+                "{}.{} must be between {}.min{} and {}.max{}, and at least 1, got {} <= {} <= {}",
+                SECTION,
+                name,
+                SECTION,
+                name,
+                SECTION,
+                name,
+                min,
+                value,
+                max
+            );
+        }
+        Ok(Bounds { min, max })
+    };
+    let batch_size_bounds = bounds(
+        "batchsize",
+        batch_size,
+        DEFAULT_MIN_BATCH_SIZE.min(batch_size),
+        DEFAULT_MAX_BATCH_SIZE.max(batch_size),
+    )?;
+    let concurrency_bounds = bounds(
+        "concurrency",
+        concurrency,
+        DEFAULT_MIN_CONCURRENCY,
+        DEFAULT_MAX_CONCURRENCY.max(concurrency),
+    )?;
+    Ok(Some(AutoTune {
+        batch_size: batch_size_bounds,
+        concurrency: concurrency_bounds,
+        sampled_batches: get(config, "autotunebatches")?.unwrap_or(DEFAULT_AUTOTUNE_BATCHES),
+    }))
+}
+
+fn get<T: FromConfigValue>(config: &dyn Config, name: &str) -> Result<Option<T>> {
+    config
+        .get_opt(SECTION, name)
+        .map_err(|e| format_err!("Failed to parse {}.{}: {}", SECTION, name, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    /// Config items, and the parsed config or the start of the error.
+    type Case = (
+        &'static [(&'static str, &'static str)],
+        Result<CheckoutConfig, &'static str>,
+    );
+
+    #[test]
+    fn test_from_config() {
+        let default = CheckoutConfig::default();
+        let cases: &[Case] = &[
+            (&[], Ok(default.clone())),
+            (
+                &[
+                    ("nativecheckout.concurrency", "4"),
+                    ("nativecheckout.batchsize", "50"),
+                    ("nativecheckout.vfsworkers", "2"),
+                    ("nativecheckout.progress-sync", "end"),
+                    ("nativecheckout.retries", "3"),
+                    ("nativecheckout.retrybackoffms", "50"),
+                    ("nativecheckout.fixdirpermissions", "true"),
+                    ("nativecheckout.max-retries", "5"),
+                    ("nativecheckout.checkdiskspace", "true"),
+                    ("nativecheckout.allowlongpaths", "true"),
+                    ("nativecheckout.eol", "crlf"),
+                    ("nativecheckout.verifyapplied", "true"),
+                ],
+                Ok(CheckoutConfig {
+                    concurrency: 4,
+                    batch_size: 50,
+                    autotune: None,
+                    vfs_workers: 2,
+                    progress_sync: ProgressSync::End,
+                    retry_policy: RetryPolicy {
+                        retries: 3,
+                        backoff: Duration::from_millis(50),
+                        fix_parent_dir_permissions: true,
+                    },
+                    fetch_retries: 5,
+                    fetch_retry_backoff: DEFAULT_FETCH_RETRY_BACKOFF,
+                    check_disk_space: true,
+                    allow_long_paths: true,
+                    eol: EolPolicy::Crlf,
+                    verify_applied: true,
+                }),
+            ),
+            (
+                &[("nativecheckout.retries", "2")],
+                Ok(CheckoutConfig {
+                    retry_policy: RetryPolicy {
+                        retries: 2,
+                        backoff: Duration::from_millis(DEFAULT_RETRY_BACKOFF_MS),
+                        fix_parent_dir_permissions: false,
+                    },
+                    ..default.clone()
+                }),
+            ),
+            (
+                &[("nativecheckout.max-retries", "-1")],
+                Err("Failed to parse nativecheckout.max-retries: "),
+            ),
+            (
+                &[("nativecheckout.concurrency", "many")],
+                Err("Failed to parse nativecheckout.concurrency: "),
+            ),
+            (
+                &[("nativecheckout.concurrency", "0")],
+                Err("nativecheckout.concurrency must be at least 1"),
+            ),
+            (
+                &[("nativecheckout.batchsize", "0")],
+                Err("nativecheckout.batchsize must be at least 1"),
+            ),
+            (
+                &[("nativecheckout.autotune", "true")],
+                Ok(CheckoutConfig {
+                    autotune: Some(AutoTune {
+                        batch_size: Bounds { min: 10, max: 1000 },
+                        concurrency: Bounds { min: 1, max: 64 },
+                        sampled_batches: 64,
+                    }),
+                    ..default.clone()
+                }),
+            ),
+            (
+                &[
+                    ("nativecheckout.autotune", "true"),
+                    ("nativecheckout.batchsize", "2000"),
+                    ("nativecheckout.minconcurrency", "4"),
+                    ("nativecheckout.maxconcurrency", "32"),
+                    ("nativecheckout.autotunebatches", "16"),
+                ],
+                Ok(CheckoutConfig {
+                    batch_size: 2000,
+                    autotune: Some(AutoTune {
+                        batch_size: Bounds { min: 10, max: 2000 },
+                        concurrency: Bounds { min: 4, max: 32 },
+                        sampled_batches: 16,
+                    }),
+                    ..default.clone()
+                }),
+            ),
+            (
+                &[("nativecheckout.maxbatchsize", "500")],
+                Err("nativecheckout.maxbatchsize has no effect unless nativecheckout.autotune is set"),
+            ),
+            (
+                &[
+                    ("nativecheckout.autotune", "true"),
+                    ("nativecheckout.maxconcurrency", "8"),
+                ],
+                Err("nativecheckout.concurrency must be between nativecheckout.minconcurrency and nativecheckout.maxconcurrency, and at least 1, got 1 <= 16 <= 8"),
+            ),
+            (
+                &[
+                    ("nativecheckout.autotune", "true"),
+                    ("nativecheckout.minbatchsize", "0"),
+                ],
+                Err("nativecheckout.batchsize must be between"),
+            ),
+            (
+                &[("nativecheckout.vfsworkers", "0")],
+                Err("nativecheckout.vfsworkers must be at least 1"),
+            ),
+            (
+                &[("nativecheckout.progress-sync", "never")],
+                Err("Failed to parse nativecheckout.progress-sync: expected 'batch' or 'end', got 'never'"),
+            ),
+            (
+                &[("nativecheckout.retries", "-1")],
+                Err("Failed to parse nativecheckout.retries: "),
+            ),
+            (
+                &[("nativecheckout.retrybackoffms", "50")],
+                Err("nativecheckout.retrybackoffms has no effect unless nativecheckout.retries is set"),
+            ),
+            (
+                &[
+                    ("nativecheckout.retries", "0"),
+                    ("nativecheckout.retrybackoffms", "50"),
+                ],
+                Err("nativecheckout.retrybackoffms has no effect unless nativecheckout.retries is set"),
+            ),
+            (
+                &[
+                    ("nativecheckout.retries", "1"),
+                    ("nativecheckout.retrybackoffms", "5000"),
+                ],
+                Err("nativecheckout.retrybackoffms must be at most 1000, got 5000"),
+            ),
+            (
+                &[("nativecheckout.checkdiskspace", "maybe")],
+                Err("Failed to parse nativecheckout.checkdiskspace: "),
+            ),
+            (
+                &[("nativecheckout.fixdirpermissions", "maybe")],
+                Err("Failed to parse nativecheckout.fixdirpermissions: "),
+            ),
+            (
+                &[("nativecheckout.allowlongpaths", "maybe")],
+                Err("Failed to parse nativecheckout.allowlongpaths: "),
+            ),
+            (
+                &[("nativecheckout.eol", "cr")],
+                Err("Failed to parse nativecheckout.eol: expected 'asis', 'lf', 'crlf' or 'native', got 'cr'"),
+            ),
+            (
+                &[("nativecheckout.verifyapplied", "maybe")],
+                Err("Failed to parse nativecheckout.verifyapplied: "),
+            ),
+        ];
+
+        for (items, expected) in cases {
+            let config: BTreeMap<&str, &str> = items.iter().copied().collect();
+            let result = CheckoutConfig::from_config(&config);
+            match (result, expected) {
+                (Ok(actual), Ok(expected)) => assert_eq!(&actual, expected, "{:?}", items),
+                (Err(err), Err(expected)) => {
+                    let message = err.to_string();
+                    assert!(
+                        message.starts_with(expected),
+                        "{:?}: {:?} does not start with {:?}",
+                        items,
+                        message,
+                        expected
+                    );
+                }
+                (result, expected) => {
+                    panic!("{:?}: got {:?}, expected {:?}", items, result, expected)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let config: BTreeMap<&str, &str> = [
+            ("nativecheckout.concurrency", "4"),
+            ("nativecheckout.concurency", "4"),
+            ("nativecheckout.usescmstore", "true"),
+            ("checkout.resumable", "true"),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(CheckoutConfig::unknown_keys(&config), vec!["concurency"]);
+    }
+}
@@ -0,0 +1,204 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Journal of the operations performed when applying a `CheckoutPlan`, see
+//! `CheckoutPlan::set_journal`.
+
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::bail;
+use anyhow::Result;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde::Serialize;
+use types::HgId;
+use types::RepoPathBuf;
+
+use crate::CheckoutError;
+use crate::ProgressSync;
+
+/// Receives the operations performed when applying a `CheckoutPlan`.
+///
+/// `record` is called once per completed batch of removed files, written
+/// files or exec flag updates, with the entries of the batch. Calls are
+/// serialized, in the order of the sequence numbers of their entries. Only
+/// operations that succeeded are recorded.
+pub trait CheckoutJournal: Send + Sync {
+    /// Records the entries of a completed batch. Failing makes the checkout
+    /// fail with `CheckoutError::Journal`.
+    fn record(&self, entries: &[JournalEntry]) -> Result<()>;
+
+    /// Syncs recorded entries to storage. Called once applying ends, even if
+    /// it failed.
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Sequence number of the last entry already in the journal, if any.
+    /// Entries of a checkout resumed with this journal are numbered after it.
+    fn last_sequence(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// An operation performed on a file of the working copy.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Increases by one for each entry of the journal.
+    pub seq: u64,
+    pub path: RepoPathBuf,
+    pub kind: JournalEntryKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JournalEntryKind {
+    /// The file was written with the content of `hgid`. `size` is the size
+    /// of the written content, after content transforms.
+    Write {
+        #[serde(with = "types::serde_with::hgid::hex")]
+        hgid: HgId,
+        size: u64,
+    },
+    /// The file was removed.
+    Remove,
+    /// The exec flag of the file was set or cleared.
+    SetExec(bool),
+}
+
+/// Numbers the entries of a plan being applied and hands them to its
+/// journal.
+pub(crate) struct JournalRecorder {
+    journal: Arc<dyn CheckoutJournal>,
+    next_seq: Mutex<u64>,
+}
+
+impl JournalRecorder {
+    pub(crate) fn new(journal: Arc<dyn CheckoutJournal>) -> Self {
+        let next_seq = journal.last_sequence().map_or(0, |seq| seq + 1);
+        Self {
+            journal,
+            next_seq: Mutex::new(next_seq),
+        }
+    }
+
+    /// Records a completed batch. The lock is held while recording, so that
+    /// the journal gets entries in sequence order.
+    pub(crate) fn record(
+        &self,
+        batch: impl IntoIterator<Item = (RepoPathBuf, JournalEntryKind)>,
+    ) -> Result<(), CheckoutError> {
+        let mut next_seq = self.next_seq.lock();
+        let entries: Vec<_> = batch
+            .into_iter()
+            .enumerate()
+            .map(|(i, (path, kind))| JournalEntry {
+                seq: *next_seq + i as u64,
+                path,
+                kind,
+            })
+            .collect();
+        self.journal
+            .record(&entries)
+            .map_err(|source| CheckoutError::Journal { source })?;
+        *next_seq += entries.len() as u64;
+        Ok(())
+    }
+
+    pub(crate) fn sync(&self) -> Result<()> {
+        self.journal.sync()
+    }
+}
+
+/// A `CheckoutJournal` appending entries to a file.
+///
+/// Each entry is a record made of its length, as a little endian `u32`,
+/// followed by the entry serialized as JSON. Each batch is written at once,
+/// and synced like the progress file with the same `ProgressSync`.
+pub struct FileCheckoutJournal {
+    file: Mutex<File>,
+    sync: ProgressSync,
+    last_seq: Option<u64>,
+}
+
+impl FileCheckoutJournal {
+    /// Opens the journal at `path`, creating it if it doesn't exist. New
+    /// entries are appended after the existing ones. A last record torn by
+    /// an interrupted write is truncated away.
+    pub fn open(path: &Path, sync: ProgressSync) -> Result<Self> {
+        let (entries, complete_len) = match util::file::exists(path)? {
+            Some(_) => read_records(util::file::open(path, "r")?)?,
+            None => (vec![], 0),
+        };
+        let file = util::file::open(path, "ca")?;
+        if file.metadata()?.len() > complete_len {
+            file.set_len(complete_len)?;
+        }
+        Ok(Self {
+            file: Mutex::new(file),
+            sync,
+            last_seq: entries.last().map(|entry| entry.seq),
+        })
+    }
+
+    /// Reads the entries of the journal at `path`, in the order they were
+    /// recorded. A torn last record is skipped.
+    pub fn read(path: &Path) -> Result<Vec<JournalEntry>> {
+        Ok(read_records(util::file::open(path, "r")?)?.0)
+    }
+}
+
+impl CheckoutJournal for FileCheckoutJournal {
+    fn record(&self, entries: &[JournalEntry]) -> Result<()> {
+        let mut records = vec![];
+        for entry in entries {
+            let record = serde_json::to_vec(entry)?;
+            records.extend_from_slice(&(record.len() as u32).to_le_bytes());
+            records.extend_from_slice(&record);
+        }
+        let mut file = self.file.lock();
+        file.write_all(&records)?;
+        if self.sync == ProgressSync::Batch {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(self.file.lock().sync_data()?)
+    }
+
+    fn last_sequence(&self) -> Option<u64> {
+        self.last_seq
+    }
+}
+
+/// Returns the entries of complete records, and the length of these
+/// records.
+fn read_records(mut file: File) -> Result<(Vec<JournalEntry>, u64)> {
+    let mut data = vec![];
+    file.read_to_end(&mut data)?;
+    let mut entries = vec![];
+    let mut offset = 0;
+    while let Some(len) = data.get(offset..offset + 4) {
+        let len = u32::from_le_bytes(len.try_into()?) as usize;
+        let record = match data.get(offset + 4..offset + 4 + len) {
+            Some(record) => record,
+            None => break,
+        };
+        let entry: JournalEntry = match serde_json::from_slice(record) {
+            Ok(entry) => entry,
+            Err(e) => bail!("invalid checkout journal record at {}: {}", offset, e),
+        };
+        entries.push(entry);
+        offset += 4 + len;
+    }
+    Ok((entries, offset as u64))
+}
@@ -6,23 +6,28 @@
  */
 
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt;
 use std::fs::File;
+use std::future::Future;
 use std::io::BufRead;
 use std::io::BufReader;
 use std::io::Write;
 use std::path::Path;
+use std::sync::atomic::AtomicU64;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 use std::time::SystemTime;
 
 use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::format_err;
-use anyhow::Context;
 use anyhow::Result;
 use async_runtime::try_block_unless_interrupted as block_on;
+use futures::future;
 use futures::stream;
 use futures::try_join;
 use futures::Stream;
@@ -56,6 +61,8 @@ use types::Key;
 use types::RepoPath;
 use types::RepoPathBuf;
 use vfs::AsyncVfsWriter;
+use vfs::RetryPolicy;
+use vfs::RetryStats;
 use vfs::UpdateFlag;
 use vfs::VFS;
 use workingcopy::sparse;
@@ -63,22 +70,52 @@ use workingcopy::workingcopy::WorkingCopy;
 
 #[allow(dead_code)]
 mod actions;
+mod audit;
 pub mod clone;
+mod config;
 #[allow(dead_code)]
 mod conflict;
+pub mod journal;
 #[allow(dead_code)]
 mod merge;
+pub mod reporter;
+mod transform;
+mod tune;
+mod verify;
 
 pub use actions::Action;
 pub use actions::ActionMap;
+use actions::PathScope;
+pub use audit::audit_working_copy;
+pub use audit::AuditReport;
+pub use audit::ContentCheck;
+pub use config::CheckoutConfig;
 use configmodel::Config;
-use configmodel::ConfigExt;
 pub use conflict::Conflict;
+pub use journal::CheckoutJournal;
+pub use journal::FileCheckoutJournal;
+pub use journal::JournalEntry;
+pub use journal::JournalEntryKind;
+use journal::JournalRecorder;
 pub use merge::Merge;
 pub use merge::MergeResult;
+pub use reporter::CheckoutEvent;
+pub use reporter::CheckoutStatsSnapshot;
+pub use reporter::ProgressReporter;
 use status::FileStatus;
 use status::Status;
 use tokio::runtime::Handle;
+use transform::ContentTransform;
+pub use transform::ContentTransformer;
+pub use transform::EolPolicy;
+pub use transform::SecondaryStore;
+pub use transform::TransformOutcome;
+pub use tune::Tuned;
+pub use tune::TunedValues;
+use tune::Tuner;
+pub use verify::verify_applied;
+pub use verify::InvariantViolation;
+pub use verify::ViolationKind;
 
 const VFS_BATCH_SIZE: usize = 100;
 
@@ -94,16 +131,82 @@ pub struct CheckoutPlan {
     /// Files that only need X flag updated.
     update_meta: Vec<UpdateMetaAction>,
     progress: Option<Mutex<CheckoutProgress>>,
+    /// Journal of the operations performed, see `set_journal`.
+    journal: Option<JournalRecorder>,
+    /// Called with the events of `apply_store`, see `with_progress_reporter`.
+    reporter: Option<ProgressReporter>,
     checkout: Checkout,
+    /// Paths the plan is limited to, if planned from a scoped `ActionMap`.
+    scope: Option<PathScope>,
+    /// Removals dropped because the file is written anyway, see
+    /// `coalesced_removes`.
+    coalesced: Vec<CoalescedRemove>,
+    /// Paths changing between file and directory, see `type_changes`.
+    type_changes: Vec<TypeChange>,
+    /// Number of removals at the start of `remove` that make room for
+    /// `type_changes`. They are applied in a phase of their own.
+    type_change_removes: usize,
+}
+
+/// A path of a plan that changes from a file, or symlink, to a directory,
+/// or back.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TypeChange {
+    pub path: RepoPathBuf,
+    /// Whether `path` becomes a directory, rather than a file.
+    pub to_dir: bool,
+}
+
+/// A removal dropped from a plan because the same file is also updated.
+///
+/// The write of `updated` replaces the file, so removing it first is
+/// redundant. `removed` and `updated` only differ on a case-insensitive
+/// working copy, for a case-only rename.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoalescedRemove {
+    pub removed: RepoPathBuf,
+    pub updated: RepoPathBuf,
 }
 
 struct CheckoutProgress {
     file: File,
     vfs: VFS,
+    sync: ProgressSync,
     /// Recording of the file time and size that have already been written.
     state: HashMap<RepoPathBuf, (HgId, u128, u64)>,
 }
 
+/// Content updates of a `CheckoutPlan`, with or without the files already
+/// written according to the progress file. Every enumeration of the files
+/// written by a plan goes through this, so that estimates and checks cover
+/// exactly what `apply_store` writes.
+struct PlanKeys<'a> {
+    actions: &'a [UpdateContentAction],
+}
+
+impl<'a> PlanKeys<'a> {
+    fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    fn actions(&self) -> impl Iterator<Item = &'a UpdateContentAction> + 'a {
+        self.actions.iter()
+    }
+
+    fn new_files(&self) -> impl Iterator<Item = &'a UpdateContentAction> + 'a {
+        // todo - index new files so that this function don't need to be O(total_files_changed)
+        self.actions().filter(|u| u.new_file)
+    }
+
+    fn keys(&self) -> Vec<Key> {
+        self.actions().map(UpdateContentAction::make_key).collect()
+    }
+
+    fn actions_by_key(&self) -> HashMap<Key, UpdateContentAction> {
+        self.actions().map(|u| (u.make_key(), u.clone())).collect()
+    }
+}
+
 /// Update content and (possibly) metadata on the file
 #[derive(Clone, Debug)]
 struct UpdateContentAction {
@@ -132,44 +235,490 @@ pub struct CheckoutStats {
     updated: AtomicUsize,
     meta_updated: AtomicUsize,
     written_bytes: AtomicUsize,
+    /// Files that were written but could not be recorded in the progress
+    /// file, so a resumed checkout will write them again.
+    unrecorded_progress: AtomicUsize,
+    /// Filesystem operations retried after a transient error.
+    retries: Arc<RetryStats>,
+    /// Batches of file contents received from the store.
+    fetch_batches: AtomicUsize,
+    /// Wall-clock durations of the phases, in nanoseconds. Content and
+    /// metadata are updated concurrently, so their durations overlap.
+    remove_nanos: AtomicU64,
+    write_nanos: AtomicU64,
+    meta_nanos: AtomicU64,
+    /// Batch sizes and concurrencies at the end of each phase.
+    tuned: Mutex<TunedValues>,
+}
+
+impl CheckoutStats {
+    pub fn removed(&self) -> usize {
+        self.removed.load(Ordering::Relaxed)
+    }
+
+    pub fn updated(&self) -> usize {
+        self.updated.load(Ordering::Relaxed)
+    }
+
+    pub fn meta_updated(&self) -> usize {
+        self.meta_updated.load(Ordering::Relaxed)
+    }
+
+    pub fn written_bytes(&self) -> usize {
+        self.written_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Number of batches of file contents received from the store, of up to
+    /// `nativecheckout.batchsize` files each. That's the number of
+    /// round-trips for a store fetching in batches of the same size.
+    pub fn fetch_batches(&self) -> usize {
+        self.fetch_batches.load(Ordering::Relaxed)
+    }
+
+    /// Time spent removing files, before any content is written.
+    pub fn remove_duration(&self) -> Duration {
+        Duration::from_nanos(self.remove_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Time spent fetching and writing file contents.
+    pub fn write_duration(&self) -> Duration {
+        Duration::from_nanos(self.write_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Time spent updating exec flags, concurrently with writing contents.
+    pub fn meta_duration(&self) -> Duration {
+        Duration::from_nanos(self.meta_nanos.load(Ordering::Relaxed))
+    }
+
+    /// The batch size and concurrency each phase ended with. They are the
+    /// configured ones, unless `nativecheckout.autotune` is set.
+    pub fn tuned(&self) -> TunedValues {
+        *self.tuned.lock()
+    }
+
+    pub fn unrecorded_progress(&self) -> usize {
+        self.unrecorded_progress.load(Ordering::Relaxed)
+    }
+
+    /// Number of filesystem operations that succeeded after being retried.
+    pub fn retried(&self) -> usize {
+        self.retries.retried()
+    }
+
+    /// Number of filesystem operations that still failed after being retried.
+    pub fn failed_after_retries(&self) -> usize {
+        self.retries.failed()
+    }
+
+    /// Number of files whose permissions, or their parent directory's, were
+    /// changed so they could be removed.
+    pub fn permission_fixups(&self) -> usize {
+        self.retries.permission_fixups()
+    }
+
+    fn add_elapsed(nanos: &AtomicU64, start: Instant) {
+        nanos.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Runs `phase`, adding its duration to `nanos`.
+    async fn timed<T>(nanos: &AtomicU64, phase: impl Future<Output = T>) -> T {
+        let start = Instant::now();
+        let result = phase.await;
+        Self::add_elapsed(nanos, start);
+        result
+    }
+}
+
+/// One line, like `3 removed, 10 updated (4096 bytes), 1 meta updated in
+/// remove 1.2ms, write 35.0ms, meta 0.3ms, 2 fetch batches`, for `--time`.
+impl fmt::Display for CheckoutStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} removed, {} updated ({} bytes), {} meta updated in remove {:.1?}, write {:.1?}, meta {:.1?}, {} fetch batches",
+            self.removed(),
+            self.updated(),
+            self.written_bytes(),
+            self.meta_updated(),
+            self.remove_duration(),
+            self.write_duration(),
+            self.meta_duration(),
+            self.fetch_batches(),
+        )
+    }
+}
+
+/// Error returned when applying a [`CheckoutPlan`], identifying the operation
+/// that failed.
+///
+/// Converts into `anyhow::Error` (and back), so callers working with
+/// `anyhow::Result` can keep using `?`.
+#[derive(Debug, thiserror::Error)]
+pub enum CheckoutError {
+    /// Fetching file content from the store failed. `key` is set when the
+    /// failure can be attributed to a key, either because the store attached
+    /// the `Key` as error context or because it returned a key that was never
+    /// requested.
+    #[error("failed to fetch file content{}: {source}", .key.as_ref().map(|k| format!(" for {}", k)).unwrap_or_default())]
+    StoreFetch {
+        key: Option<Key>,
+        source: anyhow::Error,
+    },
+    /// Writing file content failed. Writes are batched, so unless the exact
+    /// file is known this is the first file of the failed batch.
+    #[error("failed to write {path}: {source}")]
+    Write {
+        path: RepoPathBuf,
+        source: anyhow::Error,
+    },
+    /// Removing files failed. Like `Write`, this is the first file of the
+    /// failed batch.
+    #[error("failed to remove {path}: {source}")]
+    Remove {
+        path: RepoPathBuf,
+        source: anyhow::Error,
+    },
+    /// Transforming the content of a file before writing it failed, see
+    /// [`ContentTransformer`].
+    #[error("failed to transform content of {path}: {source}")]
+    Transform {
+        path: RepoPathBuf,
+        source: anyhow::Error,
+    },
+    /// Updating the exec flag of a file failed.
+    #[error("failed to update exec flag on {path}: {source}")]
+    Meta {
+        path: RepoPathBuf,
+        source: anyhow::Error,
+    },
+    /// Recording completed operations in the journal failed, see
+    /// [`CheckoutPlan::set_journal`].
+    #[error("failed to record checkout journal: {source}")]
+    Journal { source: anyhow::Error },
+    /// Checkout stopped after recording progress.
+    #[error("checkout interrupted after recording progress: {source}")]
+    Progress { source: anyhow::Error },
+    /// The disk space check failed before anything was changed. Sizes are
+    /// in bytes, `needed` including the safety margin.
+    #[error(
+        "not enough disk space for checkout: need ~{} GB, have {} GB",
+        format_gb(.needed),
+        format_gb(.available)
+    )]
+    InsufficientDiskSpace { needed: u64, available: u64 },
+    /// Files of the plan have on-disk paths longer than the platform limit,
+    /// found before anything was changed. See
+    /// [`CheckoutPlan::check_path_lengths`].
+    #[error(
+        "{} path(s) too long for checkout, first: {} (set nativecheckout.allowlongpaths if the filesystem supports long paths)",
+        .0.len(),
+        .0[0]
+    )]
+    PathsTooLong(Vec<LongPath>),
+    /// The action map both removes a file and updates its exec flag, which
+    /// only keeps the file. `updated` differs from `removed` on a
+    /// case-insensitive working copy.
+    #[error("cannot both remove {removed} and update the exec flag of {updated}")]
+    RemoveAndUpdateMeta {
+        removed: RepoPathBuf,
+        updated: RepoPathBuf,
+    },
+    /// Fetching the content of `keys` kept failing with retryable errors
+    /// after `nativecheckout.max-retries` rounds of retries. `source` is the
+    /// last of the errors.
+    #[error(
+        "failed to fetch file content for {} file(s) after {retries} retries, including {}: {source}",
+        .keys.len(),
+        .keys.iter().take(MAX_REPORTED_KEYS).map(|k| k.to_string()).collect::<Vec<_>>().join(", ")
+    )]
+    StoreFetchRetries {
+        keys: Vec<Key>,
+        retries: u32,
+        source: anyhow::Error,
+    },
+    /// Checkout was interrupted, e.g. by Ctrl-C, while blocking on
+    /// [`CheckoutPlan::apply_store`].
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Number of keys listed in the message of
+/// [`CheckoutError::StoreFetchRetries`].
+const MAX_REPORTED_KEYS: usize = 10;
+
+/// A file whose path on disk is longer than the platform allows.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LongPath {
+    pub path: RepoPathBuf,
+    /// Length of the path on disk, see `VFS::path_len`.
+    pub len: usize,
+    /// Longest path allowed, see `VFS::max_path_len`.
+    pub limit: usize,
+}
+
+impl fmt::Display for LongPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is {} long on disk, the limit is {}",
+            self.path, self.len, self.limit
+        )
+    }
+}
+
+/// Why a file of a [`CheckoutPlan`] would block it, see
+/// [`CheckoutPlan::conflict_report`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConflictKind {
+    /// An untracked file would be overwritten with different content.
+    UnknownBlocking,
+    /// An untracked file already has the content it would be written with,
+    /// so overwriting it is safe.
+    UnknownSameContent,
+    /// A tracked file has local changes, or was added.
+    ModifiedTracked,
+    /// A tracked file was removed or deleted locally.
+    RemovedLocally,
 }
 
-const DEFAULT_CONCURRENCY: usize = 16;
+/// Files of a [`CheckoutPlan`] with local state that would block it, see
+/// [`CheckoutPlan::conflict_report`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConflictReport {
+    /// Files and why they would block, sorted by path.
+    pub conflicts: Vec<(RepoPathBuf, ConflictKind)>,
+    /// Whether untracked files were compared by size rather than content,
+    /// because there were too many of them.
+    pub compared_by_size: bool,
+}
+
+impl ConflictReport {
+    /// Whether checkout would fail without `--clean`, that is whether any
+    /// file is not safe to overwrite.
+    pub fn is_blocking(&self) -> bool {
+        self.conflicts
+            .iter()
+            .any(|(_, kind)| *kind != ConflictKind::UnknownSameContent)
+    }
+
+    /// Files of the report of `kind`.
+    pub fn paths(&self, kind: ConflictKind) -> impl Iterator<Item = &RepoPathBuf> {
+        self.conflicts
+            .iter()
+            .filter(move |(_, k)| *k == kind)
+            .map(|(path, _)| path)
+    }
+}
+
+/// Counts of the actions of a [`CheckoutPlan`] under a path prefix, see
+/// [`CheckoutPlan::summarize_by_prefix`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PrefixSummary {
+    /// Prefix of the files, empty for the tail bucket.
+    pub prefix: RepoPathBuf,
+    /// Files to be removed.
+    pub removed: usize,
+    /// Files that need their content updated.
+    pub updated: usize,
+    /// Files that only need X flag updated.
+    pub meta: usize,
+}
+
+impl PrefixSummary {
+    pub fn total(&self) -> usize {
+        self.removed + self.updated + self.meta
+    }
+
+    fn add(&mut self, other: &PrefixSummary) {
+        self.removed += other.removed;
+        self.updated += other.updated;
+        self.meta += other.meta;
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "prefix": self.prefix.as_str(),
+            "removed": self.removed,
+            "updated": self.updated,
+            "meta": self.meta,
+        })
+    }
+}
+
+/// Prefixes with less than this percentage of the files of a plan are
+/// summarized in the tail bucket.
+const PREFIX_SUMMARY_TAIL_PERCENT: usize = 1;
+
+/// Returns the first `depth` components of `path`, or `path` itself if it
+/// has fewer components.
+fn path_prefix(path: &RepoPath, depth: usize) -> &RepoPath {
+    path.parents()
+        .chain(std::iter::once(path))
+        .nth(depth)
+        .unwrap_or(path)
+}
+
+fn format_gb(bytes: &u64) -> String {
+    format!("{:.1}", *bytes as f64 / (1024 * 1024 * 1024) as f64)
+}
+
+impl CheckoutError {
+    fn store_fetch(source: anyhow::Error) -> Self {
+        let key = source.downcast_ref::<Key>().cloned();
+        CheckoutError::StoreFetch { key, source }
+    }
+}
+
+/// Whether the content fetch failing with `err` may succeed if tried again:
+/// network errors, and content not found yet, like when a remote store
+/// races with the upload of a commit.
+fn is_retryable_fetch(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|e| e.downcast_ref::<std::io::Error>())
+        .any(|e| {
+            matches!(
+                e.kind(),
+                std::io::ErrorKind::ConnectionRefused
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::NotConnected
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::Interrupted
+                    | std::io::ErrorKind::UnexpectedEof
+                    | std::io::ErrorKind::NotFound
+            )
+        })
+}
+
+/// Longest delay between two fetch retry rounds.
+const MAX_FETCH_RETRY_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Space required on top of the estimated write size when checking disk
+/// space, in addition to 10% of the estimate.
+const DISK_SPACE_MARGIN: u64 = 100 * 1024 * 1024;
 const MAX_CHECK_UNKNOWN: usize = 5000;
 
+/// When records in the checkout progress file are synced to disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProgressSync {
+    /// Sync after each batch of written files.
+    #[default]
+    Batch,
+    /// Only sync once all files are written. Faster, but the progress of
+    /// an interrupted checkout may be partially lost.
+    End,
+}
+
+impl std::str::FromStr for ProgressSync {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "batch" => Ok(ProgressSync::Batch),
+            "end" => Ok(ProgressSync::End),
+            _ => bail!("expected 'batch' or 'end', got '{}'", s),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Checkout {
     vfs: VFS,
-    concurrency: usize,
+    config: CheckoutConfig,
+    /// Writer shared by all plans applied through this `Checkout`, if any.
+    vfs_writer: Option<Arc<AsyncVfsWriter>>,
+    /// Spawns the writer of a plan if there is no shared writer. Replaced
+    /// in tests.
+    spawn_vfs_writer: fn(VFS, usize, RetryPolicy, Arc<RetryStats>) -> AsyncVfsWriter,
+    /// Returns the bytes available on the filesystem of the given path.
+    /// Replaced in tests.
+    available_space: fn(&Path) -> Result<u64>,
+    /// Longest path on disk allowed for files. Replaced in tests.
+    max_path_len: usize,
+    /// Transformers added with `with_content_transformer`.
+    transformers: Vec<Arc<dyn ContentTransformer>>,
 }
 
 impl Checkout {
     pub fn default_config(vfs: VFS) -> Self {
+        Self::new(vfs, CheckoutConfig::default())
+    }
+
+    pub fn from_config(vfs: VFS, config: &dyn Config) -> Result<Self> {
+        Ok(Self::new(vfs, CheckoutConfig::from_config(config)?))
+    }
+
+    pub fn new(vfs: VFS, config: CheckoutConfig) -> Self {
+        let max_path_len = vfs.max_path_len();
         Self {
             vfs,
-            concurrency: DEFAULT_CONCURRENCY,
+            config,
+            vfs_writer: None,
+            spawn_vfs_writer: AsyncVfsWriter::spawn_with_retry,
+            available_space: |path| fsinfo::available_space(path),
+            max_path_len,
+            transformers: Vec::new(),
         }
     }
 
-    pub fn from_config(vfs: VFS, config: &dyn Config) -> Result<Self> {
-        let concurrency = config
-            .get_opt("nativecheckout", "concurrency")
-            .map_err(|e| format_err!("Failed to parse nativecheckout.concurrency: {}", e))?;
-        let concurrency = concurrency.unwrap_or(DEFAULT_CONCURRENCY);
-        Ok(Self { vfs, concurrency })
+    /// Write the working copy of all plans applied through this `Checkout`
+    /// with `writer`, instead of spawning `nativecheckout.vfsworkers`
+    /// threads for each plan. This bounds the filesystem parallelism of
+    /// concurrent checkouts, like in a server.
+    ///
+    /// `writer` must write to the same `VFS` as this `Checkout`. Its retry
+    /// policy is used instead of `nativecheckout.retries`, and retries are
+    /// counted in its `RetryStats` instead of in `CheckoutStats`.
+    ///
+    /// The writer threads stop when the last reference to `writer` is
+    /// dropped, which blocks until queued operations are done. The embedder
+    /// owns it, and should drop it once no plan is being applied.
+    pub fn with_vfs_writer(mut self, writer: Arc<AsyncVfsWriter>) -> Self {
+        self.vfs_writer = Some(writer);
+        self
+    }
+
+    /// Transform file contents with `transformer` before writing them, and
+    /// before comparing them with files on disk. Transformers run in the
+    /// order they are added, and before the line ending conversion of
+    /// `nativecheckout.eol`.
+    ///
+    /// Files are recorded in the progress file with their size on disk,
+    /// after transforms. Resuming a checkout with different transformers
+    /// may therefore keep files written with the previous ones.
+    pub fn with_content_transformer(mut self, transformer: Arc<dyn ContentTransformer>) -> Self {
+        self.transformers.push(transformer);
+        self
+    }
+
+    fn content_transform(&self) -> ContentTransform {
+        ContentTransform::new(&self.transformers, self.config.eol)
     }
 
-    pub fn plan_action_map(&self, map: ActionMap) -> CheckoutPlan {
+    /// Plan the actions of `map`.
+    ///
+    /// Removals of files that are also updated are dropped, see
+    /// `CheckoutPlan::coalesced_removes`. Fails if a file is both removed
+    /// and has its exec flag updated.
+    pub fn plan_action_map(&self, map: ActionMap) -> Result<CheckoutPlan, CheckoutError> {
         CheckoutPlan::from_action_map(self.clone(), map)
     }
 }
 
 impl CheckoutPlan {
-    fn from_action_map(checkout: Checkout, map: ActionMap) -> Self {
+    fn from_action_map(checkout: Checkout, map: ActionMap) -> Result<Self, CheckoutError> {
+        let scope = map.scope().cloned();
         let mut remove = vec![];
         let mut update_content = vec![];
         let mut update_meta = vec![];
         for (path, action) in map.into_iter() {
+            // Actions added to a scoped map for other paths are ignored.
+            if scope.as_ref().is_some_and(|scope| !scope.contains(&path)) {
+                continue;
+            }
             match action {
                 Action::Remove => remove.push(path),
                 Action::UpdateExec(set_x_flag) => {
@@ -180,35 +729,104 @@ impl CheckoutPlan {
                 }
             }
         }
+        let coalesced = coalesce_removes(
+            &mut remove,
+            &update_content,
+            &update_meta,
+            checkout.vfs.case_sensitive(),
+        )?;
+        let type_changes = find_type_changes(&remove, &update_content);
+        let type_change_removes = order_type_change_removes(&mut remove, &type_changes);
         let filtered_update_content = update_content.clone();
-        Self {
+        Ok(Self {
             remove,
             update_content,
             filtered_update_content,
             update_meta,
             progress: None,
+            journal: None,
+            reporter: None,
             checkout,
-        }
+            scope,
+            coalesced,
+            type_changes,
+            type_change_removes,
+        })
+    }
+
+    /// Paths changing between file and directory, sorted by path.
+    ///
+    /// The old entries of these paths, the file or the files under the
+    /// directory, are removed in a first phase, before the other removals.
+    /// All removals complete before any file is written.
+    pub fn type_changes(&self) -> &[TypeChange] {
+        &self.type_changes
+    }
+
+    /// Removals dropped from the plan because the same file is also
+    /// updated, sorted by removed path.
+    pub fn coalesced_removes(&self) -> &[CoalescedRemove] {
+        &self.coalesced
+    }
+
+    /// Whether the plan only touches some paths of the working copy,
+    /// because it was created from `ActionMap::from_diff_for_paths`.
+    ///
+    /// A scoped plan has no actions for files outside of its paths, so
+    /// `check_conflicts` and `check_unknown_files` only report files in
+    /// these paths, and local changes to other files are kept as is.
+    pub fn scoped(&self) -> bool {
+        self.scope.is_some()
     }
 
     pub fn add_progress(&mut self, path: &Path) -> Result<()> {
         let vfs = &self.checkout.vfs;
+        let sync = self.checkout.config.progress_sync;
         let progress = if path.exists() {
-            match CheckoutProgress::load(path, vfs.clone()) {
+            match CheckoutProgress::load(path, vfs.clone(), sync) {
                 Ok(p) => p,
                 Err(e) => {
                     debug!("Failed to load CheckoutProgress with {:?}", e);
-                    CheckoutProgress::new(path, vfs.clone())?
+                    CheckoutProgress::new(path, vfs.clone(), sync)?
                 }
             }
         } else {
-            CheckoutProgress::new(path, vfs.clone())?
+            CheckoutProgress::new(path, vfs.clone(), sync)?
         };
         self.filtered_update_content = progress.filter_already_written(&self.update_content);
         self.progress = Some(Mutex::new(progress));
         Ok(())
     }
 
+    /// Records the operations performed by `apply_store` in `journal`, once
+    /// per completed batch. Entries are numbered after
+    /// `CheckoutJournal::last_sequence`, so that a resumed checkout can use
+    /// the journal of the interrupted one.
+    pub fn set_journal(&mut self, journal: Arc<dyn CheckoutJournal>) {
+        self.journal = Some(JournalRecorder::new(journal));
+    }
+
+    /// Calls `reporter` with the events of `apply_store`: each file written
+    /// or removed and each exec flag update, as it completes, and the totals
+    /// so far after each batch of files. This works alongside the progress
+    /// file and the journal.
+    pub fn with_progress_reporter(mut self, reporter: ProgressReporter) -> Self {
+        self.reporter = Some(reporter);
+        self
+    }
+
+    /// Content updates of this plan. Unless `include_already_written` is
+    /// set, files already written according to the progress file are
+    /// skipped, like `apply_store` does.
+    fn plan_keys(&self, include_already_written: bool) -> PlanKeys<'_> {
+        let actions = if include_already_written {
+            &self.update_content
+        } else {
+            &self.filtered_update_content
+        };
+        PlanKeys { actions }
+    }
+
     /// Applies plan to the root using store to fetch data.
     /// This async function offloads file system operation to tokio blocking thread pool.
     /// It limits number of concurrent fs operations to Checkout::concurrency.
@@ -222,89 +840,246 @@ impl CheckoutPlan {
     /// stop polling storage stream, until one of pending fs operations complete
     ///
     /// This function fails fast and returns error when first checkout operation fails.
-    /// Pending storage futures are dropped when error is returned
+    /// Pending storage futures are dropped when error is returned. The returned
+    /// [`CheckoutError`] describes which operation failed.
     pub async fn apply_store(
         &self,
         store: &dyn ReadFileContents<Error = anyhow::Error>,
-    ) -> Result<CheckoutStats> {
+    ) -> Result<CheckoutStats, CheckoutError> {
+        let stats = CheckoutStats::default();
+        self.apply_store_with_stats(store, &stats).await?;
+        Ok(stats)
+    }
+
+    /// Same as `apply_store`, but accumulates into the given `stats`, which
+    /// then reflect the work completed before a failure.
+    async fn apply_store_with_stats(
+        &self,
+        store: &dyn ReadFileContents<Error = anyhow::Error>,
+        stats: &CheckoutStats,
+    ) -> Result<(), CheckoutError> {
         let vfs = &self.checkout.vfs;
+        let plan_keys = self.plan_keys(false);
         debug!(
             "Skipping checking out {} files since they're already written",
-            self.update_content.len() - self.filtered_update_content.len()
+            self.update_content.len() - plan_keys.len()
         );
-        let total = self.filtered_update_content.len() + self.remove.len() + self.update_meta.len();
+        if !self.checkout.config.allow_long_paths {
+            let long_paths = self.check_path_lengths();
+            if !long_paths.is_empty() {
+                return Err(CheckoutError::PathsTooLong(long_paths));
+            }
+        }
+        if self.checkout.config.check_disk_space {
+            self.check_disk_space(store).await?;
+        }
+        let total = plan_keys.len() + self.remove.len() + self.update_meta.len();
         let bar = &ProgressBar::new("Updating", total as u64, "files");
         Registry::main().register_progress_bar(bar);
-        let async_vfs = &AsyncVfsWriter::spawn_new(vfs.clone(), 16);
-        let stats = CheckoutStats::default();
-        let stats_ref = &stats;
-
-        let remove_files = stream::iter(self.remove.clone().into_iter())
-            .chunks(VFS_BATCH_SIZE)
-            .map(|paths| Self::remove_files(async_vfs, stats_ref, paths, bar));
-        let remove_files = remove_files.buffer_unordered(self.checkout.concurrency);
+        let spawned;
+        let async_vfs = match &self.checkout.vfs_writer {
+            Some(writer) => writer.as_ref(),
+            None => {
+                spawned = (self.checkout.spawn_vfs_writer)(
+                    vfs.clone(),
+                    self.checkout.config.vfs_workers,
+                    self.checkout.config.retry_policy,
+                    stats.retries.clone(),
+                );
+                &spawned
+            }
+        };
 
-        Self::process_work_stream(remove_files).await?;
+        let journal = self.journal.as_ref();
+        let reporter = self.reporter.as_ref();
+        let config = &self.checkout.config;
+        let remove_tuner = &Tuner::new(config.batch_size, config.concurrency, config.autotune);
+        let write_tuner = &Tuner::new(config.batch_size, config.concurrency, config.autotune);
+        let meta_tuner = &Tuner::unbatched(config.concurrency, config.autotune);
+        // Entries in the way of type changes are gone, their directories
+        // included, before the other removals start.
+        let (type_change_removes, other_removes) = self.remove.split_at(self.type_change_removes);
+        let remove_start = Instant::now();
+        for removes in [type_change_removes, other_removes] {
+            let remove_files =
+                tune::chunks(stream::iter(removes.to_vec()), remove_tuner).map(|paths| {
+                    let items = paths.len();
+                    let remove =
+                        Self::remove_files(async_vfs, stats, paths, journal, reporter, bar);
+                    remove_tuner.run(items, remove)
+                });
+            let remove_files = remove_files.buffer_unordered(remove_tuner.max_concurrency());
+
+            Self::process_work_stream(remove_files).await?;
+            // Concurrent batches removing files of the same directory can
+            // leave it behind, empty.
+            vfs.remove_empty_parents_of(removes.iter().map(|path| path.as_repo_path()));
+        }
+        CheckoutStats::add_elapsed(&stats.remove_nanos, remove_start);
 
-        let actions: HashMap<_, _> = self
-            .filtered_update_content
-            .iter()
-            .map(|u| (u.make_key(), u.clone()))
-            .collect();
+        let actions = &plan_keys.actions_by_key();
         let keys: Vec<_> = actions.keys().cloned().collect();
 
-        let data_stream = store.read_file_contents(keys).await;
-
-        let update_content = data_stream.map(|result| -> Result<_> {
-            let (data, key) = result?;
-            let action = actions
-                .get(&key)
-                .ok_or_else(|| format_err!("Storage returned unknown key {}", key))?;
-            let path = action.path.clone();
-            let flag = type_to_flag(&action.file_type);
-            Ok((path, action.content_hgid, data, flag))
-        });
-
+        // Keys whose fetch failed with a retryable error, fetched again once
+        // the other files are written.
+        let deferred = &Mutex::new(Vec::new());
+        let fetch_retries = self.checkout.config.fetch_retries;
         let progress_ref = self.progress.as_ref();
-        let update_content = update_content
-            .chunks(VFS_BATCH_SIZE)
-            .map(|actions| async move {
-                let actions: Result<Vec<_>, _> = actions.into_iter().collect();
-                Self::write_files(async_vfs, stats_ref, actions?, progress_ref, bar).await
+        let transform = &self.checkout.content_transform();
+        let write_content = move |keys: Vec<Key>| async move {
+            let data_stream = store.read_file_contents(keys).await;
+            let update_content = data_stream.filter_map(move |result| {
+                let result = match result {
+                    Ok((data, key)) => match actions.get(&key) {
+                        Some(action) => {
+                            let path = action.path.clone();
+                            let flag = type_to_flag(&action.file_type);
+                            Some(Ok((path, action.content_hgid, data, flag)))
+                        }
+                        None => Some(Err(CheckoutError::StoreFetch {
+                            source: format_err!("Storage returned unknown key {}", key),
+                            key: Some(key),
+                        })),
+                    },
+                    Err(err) => match err.downcast_ref::<Key>() {
+                        Some(key)
+                            if fetch_retries > 0
+                                && is_retryable_fetch(&err)
+                                && actions.contains_key(key) =>
+                        {
+                            debug!("Deferring fetch of {} after error: {:?}", key, err);
+                            let key = key.clone();
+                            deferred.lock().push((key, err));
+                            None
+                        }
+                        _ => Some(Err(CheckoutError::store_fetch(err))),
+                    },
+                };
+                future::ready(result)
+            });
+            let update_content = tune::chunks(update_content, write_tuner).map(|actions| {
+                stats.fetch_batches.fetch_add(1, Ordering::Relaxed);
+                let items = actions.len();
+                write_tuner.run(items, async move {
+                    let actions: Result<Vec<_>, _> = actions.into_iter().collect();
+                    Self::write_files(
+                        async_vfs,
+                        stats,
+                        transform,
+                        actions?,
+                        progress_ref,
+                        journal,
+                        reporter,
+                        bar,
+                    )
+                    .await
+                })
             });
+            let update_content = update_content.buffer_unordered(write_tuner.max_concurrency());
+            Self::process_work_stream(update_content).await
+        };
 
-        let update_content = update_content.buffer_unordered(self.checkout.concurrency);
+        let update_content = async {
+            write_content(keys).await?;
+            let mut backoff = self.checkout.config.fetch_retry_backoff;
+            for round in 1..=fetch_retries {
+                let failed = std::mem::take(&mut *deferred.lock());
+                if failed.is_empty() {
+                    break;
+                }
+                debug!(
+                    "Retrying fetch of {} files, round {}/{}",
+                    failed.len(),
+                    round,
+                    fetch_retries
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_FETCH_RETRY_BACKOFF);
+                let keys = failed.into_iter().map(|(key, _)| key).collect();
+                write_content(keys).await?;
+            }
+            let mut failed = std::mem::take(&mut *deferred.lock());
+            match failed.pop() {
+                None => Ok(()),
+                Some((key, source)) => {
+                    let mut keys: Vec<_> = failed.into_iter().map(|(key, _)| key).collect();
+                    keys.push(key);
+                    keys.sort();
+                    Err(CheckoutError::StoreFetchRetries {
+                        keys,
+                        retries: fetch_retries,
+                        source,
+                    })
+                }
+            }
+        };
 
         let update_meta = stream::iter(self.update_meta.iter()).map(|action| {
-            Self::set_exec_on_file(async_vfs, stats_ref, &action.path, action.set_x_flag, bar)
+            let update = Self::set_exec_on_file(
+                async_vfs,
+                stats,
+                &action.path,
+                action.set_x_flag,
+                journal,
+                reporter,
+                bar,
+            );
+            meta_tuner.run(1, update)
         });
-        let update_meta = update_meta.buffer_unordered(self.checkout.concurrency);
+        let update_meta = update_meta.buffer_unordered(meta_tuner.max_concurrency());
 
-        let update_content = Self::process_work_stream(update_content);
-        let update_meta = Self::process_work_stream(update_meta);
+        let update_content = CheckoutStats::timed(&stats.write_nanos, update_content);
+        let update_meta =
+            CheckoutStats::timed(&stats.meta_nanos, Self::process_work_stream(update_meta));
 
-        try_join!(update_content, update_meta)?;
+        let result = try_join!(update_content, update_meta);
 
-        Ok(stats)
+        let tuned = TunedValues {
+            remove: remove_tuner.tuned(),
+            write: write_tuner.tuned(),
+            meta: meta_tuner.tuned(),
+        };
+        debug!("Checkout batch sizes and concurrencies: {:?}", tuned);
+        *stats.tuned.lock() = tuned;
+
+        // Also sync progress when interrupted, that is when it's needed.
+        if let Some(progress) = &self.progress {
+            if let Err(e) = progress.lock().sync() {
+                warn!("Failed to sync checkout progress: {:?}", e);
+            }
+        }
+        if let Some(journal) = journal {
+            if let Err(e) = journal.sync() {
+                warn!("Failed to sync checkout journal: {:?}", e);
+            }
+        }
+        reporter::report_stats(reporter, stats);
+
+        result?;
+        Ok(())
     }
 
     #[instrument(skip_all, err)]
     pub fn blocking_apply_store(
         &self,
         store: &dyn ReadFileContents<Error = anyhow::Error>,
-    ) -> Result<CheckoutStats> {
+    ) -> Result<CheckoutStats, CheckoutError> {
         block_on(self.apply_store(store))
     }
 
+    /// Fetches the contents `apply_store` would write, without writing them,
+    /// and returns their count and total size. Sizes are those of the store,
+    /// before content transforms.
+    ///
+    /// Like `apply_store`, files already written according to the progress
+    /// file are skipped, unless `include_already_written` is set.
     pub async fn apply_store_dry_run(
         &self,
         store: &dyn ReadFileContents<Error = anyhow::Error>,
+        include_already_written: bool,
     ) -> Result<(usize, u64)> {
-        let keys = self
-            .filtered_update_content
-            .iter()
-            .map(UpdateContentAction::make_key);
-        let mut stream = store.read_file_contents(keys.collect()).await;
+        let keys = self.plan_keys(include_already_written).keys();
+        let mut stream = store.read_file_contents(keys).await;
         let (mut count, mut size) = (0, 0);
         while let Some(result) = stream.next().await {
             let (bytes, _) = result?;
@@ -314,6 +1089,76 @@ impl CheckoutPlan {
         Ok((count, size))
     }
 
+    /// Approximate number of bytes written when applying this plan. Files
+    /// already written according to the progress file are not counted.
+    ///
+    /// Uses size metadata if the store has it, and otherwise fetches the
+    /// contents like `apply_store_dry_run`.
+    pub async fn estimated_write_size(
+        &self,
+        store: &dyn ReadFileContents<Error = anyhow::Error>,
+    ) -> Result<u64> {
+        let keys = self.plan_keys(false).keys();
+        match store.read_file_sizes(keys) {
+            Some(mut sizes) => {
+                let mut total = 0;
+                while let Some(result) = sizes.next().await {
+                    total += result?.0;
+                }
+                Ok(total)
+            }
+            None => Ok(self.apply_store_dry_run(store, false).await?.1),
+        }
+    }
+
+    /// Fail if the filesystem of the working copy doesn't have room for the
+    /// files written by this plan, plus a safety margin. Space freed by
+    /// removed or overwritten files is not taken into account.
+    async fn check_disk_space(
+        &self,
+        store: &dyn ReadFileContents<Error = anyhow::Error>,
+    ) -> Result<(), CheckoutError> {
+        let estimate = self
+            .estimated_write_size(store)
+            .await
+            .map_err(CheckoutError::store_fetch)?;
+        let needed = estimate + estimate / 10 + DISK_SPACE_MARGIN;
+        let available = (self.checkout.available_space)(self.checkout.vfs.root())?;
+        debug!(
+            "Checkout needs ~{} bytes, {} bytes available",
+            needed, available
+        );
+        if available < needed {
+            return Err(CheckoutError::InsufficientDiskSpace { needed, available });
+        }
+        Ok(())
+    }
+
+    /// Returns files written by this plan whose path on disk is longer than
+    /// the platform limit, sorted by path. Applying the plan fails with
+    /// `CheckoutError::PathsTooLong` if any, unless
+    /// `nativecheckout.allowlongpaths` is set.
+    pub fn check_path_lengths(&self) -> Vec<LongPath> {
+        let vfs = &self.checkout.vfs;
+        let limit = self.checkout.max_path_len;
+        let mut long_paths: Vec<_> = self
+            .plan_keys(false)
+            .actions()
+            .filter_map(|action| {
+                let len = vfs.path_len(&action.path);
+                (len > limit).then(|| LongPath {
+                    path: action.path.clone(),
+                    len,
+                    limit,
+                })
+            })
+            .collect();
+        long_paths.sort_by(|a, b| a.path.cmp(&b.path));
+        long_paths
+    }
+
+    /// Returns files of the plan with local changes in `status`. For a
+    /// scoped plan, these are only files in its paths.
     pub fn check_conflicts(&self, status: &Status) -> Vec<&RepoPath> {
         let mut conflicts = vec![];
         for file in self.all_files() {
@@ -325,6 +1170,13 @@ impl CheckoutPlan {
         conflicts
     }
 
+    /// Returns untracked files in the working copy that would be overwritten
+    /// by this plan with different content.
+    ///
+    /// Expected content is read through `ReadFileContents`, so this works with
+    /// any store the plan can be applied with: revisionstore implements it
+    /// for scmstore (`ArcFileStore`) and for legacy `RemoteDataStore`s
+    /// (`ArcRemoteDataStore`) alike.
     pub async fn check_unknown_files(
         &self,
         manifest: &impl Manifest,
@@ -332,10 +1184,86 @@ impl CheckoutPlan {
         tree_state: &mut TreeState,
         status: &Status,
     ) -> Result<Vec<RepoPathBuf>> {
+        let (check_content, flags) = self.find_unknown_files(manifest, tree_state, status)?;
+
+        if check_content.len() > MAX_CHECK_UNKNOWN {
+            warn!(
+                "Working directory has {} untracked files, not going to check their content. Use --clean to overwrite files without checking",
+                check_content.len()
+            );
+            let unknowns = check_content.into_iter().map(|k| k.path).collect();
+            return Ok(unknowns);
+        }
+
+        self.changed_unknown_files(store, check_content, flags)
+            .await
+    }
+
+    /// Returns what would block this plan in the working copy, without
+    /// changing anything.
+    ///
+    /// Untracked files the plan writes are compared with the content they
+    /// would be written with, like `check_unknown_files`. If there are more
+    /// than `MAX_CHECK_UNKNOWN` of them, they are compared by size instead,
+    /// using the sizes of `store` if it has them, and are all blocking
+    /// otherwise. Ignored files are overwritten by checkout and are not
+    /// reported.
+    pub async fn conflict_report(
+        &self,
+        status: &Status,
+        manifest: &impl Manifest,
+        store: &dyn ReadFileContents<Error = anyhow::Error>,
+        tree_state: &mut TreeState,
+    ) -> Result<ConflictReport> {
+        let mut conflicts = vec![];
+        for file in self.all_files() {
+            let kind = match status.status(file) {
+                Some(FileStatus::Modified | FileStatus::Added) => ConflictKind::ModifiedTracked,
+                Some(FileStatus::Removed | FileStatus::Deleted) => ConflictKind::RemovedLocally,
+                _ => continue,
+            };
+            conflicts.push((file.clone(), kind));
+        }
+
+        let (unknowns, flags) = self.find_unknown_files(manifest, tree_state, status)?;
+        let compared_by_size = unknowns.len() > MAX_CHECK_UNKNOWN;
+        let blocking: HashSet<RepoPathBuf> = if compared_by_size {
+            self.changed_unknown_file_sizes(store, &unknowns).await?
+        } else {
+            self.changed_unknown_files(store, unknowns.clone(), flags)
+                .await?
+        }
+        .into_iter()
+        .collect();
+        for key in unknowns {
+            let kind = if blocking.contains(&key.path) {
+                ConflictKind::UnknownBlocking
+            } else {
+                ConflictKind::UnknownSameContent
+            };
+            conflicts.push((key.path, kind));
+        }
+
+        conflicts.sort();
+        Ok(ConflictReport {
+            conflicts,
+            compared_by_size,
+        })
+    }
+
+    /// Returns keys of the untracked files in the working copy that would be
+    /// written by this plan, and the flags they would be written with.
+    fn find_unknown_files(
+        &self,
+        manifest: &impl Manifest,
+        tree_state: &mut TreeState,
+        status: &Status,
+    ) -> Result<(Vec<Key>, HashMap<RepoPathBuf, UpdateFlag>)> {
         let vfs = &self.checkout.vfs;
         let mut check_content = vec![];
+        let mut flags = HashMap::new();
 
-        let new_files: Vec<_> = self.new_file_actions().collect();
+        let new_files: Vec<_> = self.plan_keys(false).new_files().collect();
 
         let bar = ProgressBar::register_new("Checking untracked", new_files.len() as u64, "files");
         for file_action in new_files {
@@ -383,41 +1311,78 @@ impl CheckoutPlan {
                 };
                 let key = Key::new(file.clone(), hgid);
                 check_content.push(key);
+                flags.insert(file.clone(), type_to_flag(&file_action.file_type));
             }
         }
 
-        if check_content.len() > MAX_CHECK_UNKNOWN {
-            warn!(
-                "Working directory has {} untracked files, not going to check their content. Use --clean to overwrite files without checking",
-                check_content.len()
-            );
-            let unknowns = check_content.into_iter().map(|k| k.path).collect();
-            return Ok(unknowns);
-        }
+        Ok((check_content, flags))
+    }
 
+    /// Returns the files of `keys` whose content on disk differs from the
+    /// content they would be written with.
+    async fn changed_unknown_files(
+        &self,
+        store: &dyn ReadFileContents<Error = anyhow::Error>,
+        keys: Vec<Key>,
+        flags: HashMap<RepoPathBuf, UpdateFlag>,
+    ) -> Result<Vec<RepoPathBuf>> {
+        let vfs = &self.checkout.vfs;
+        // Compare with the content as it would be written.
+        let transform = self.checkout.content_transform();
+        let flags = Arc::new(flags);
         let check_content = store
-            .read_file_contents(check_content)
+            .read_file_contents(keys)
             .await
-            .chunks(VFS_BATCH_SIZE)
+            .chunks(self.checkout.config.batch_size)
             .map(|v| {
                 let vfs = vfs.clone();
+                let transform = transform.clone();
+                let flags = flags.clone();
                 Handle::current().spawn_blocking(move || -> Result<Vec<RepoPathBuf>> {
                     let v: std::result::Result<Vec<_>, _> = v.into_iter().collect();
-                    Self::check_content(&vfs, v?)
+                    let v = v?
+                        .into_iter()
+                        .map(|(data, key)| {
+                            let flag = flags.get(&key.path).copied();
+                            (data, key, flag)
+                        })
+                        .collect();
+                    Self::check_content(&vfs, &transform, v)
                 })
             })
-            .buffer_unordered(self.checkout.concurrency)
+            .buffer_unordered(self.checkout.config.concurrency)
             .map(|r| r?);
 
-        let unknowns = Self::process_vec_work_stream(check_content).await?;
+        Self::process_vec_work_stream(check_content).await
+    }
 
-        Ok(unknowns)
+    /// Returns the files of `keys` whose size on disk differs from the size
+    /// in `store`, or all of them if `store` doesn't have sizes. Content
+    /// transforms are not applied, so transformed files differ.
+    async fn changed_unknown_file_sizes(
+        &self,
+        store: &dyn ReadFileContents<Error = anyhow::Error>,
+        keys: &[Key],
+    ) -> Result<Vec<RepoPathBuf>> {
+        let mut sizes = match store.read_file_sizes(keys.to_vec()) {
+            Some(sizes) => sizes,
+            None => return Ok(keys.iter().map(|k| k.path.clone()).collect()),
+        };
+        let mut changed = vec![];
+        while let Some(result) = sizes.next().await {
+            let (size, key) = result?;
+            match self.checkout.vfs.metadata(&key.path) {
+                Ok(meta) if meta.len() == size => {}
+                _ => changed.push(key.path),
+            }
+        }
+        Ok(changed)
     }
 
     /// Drains stream returning error if one of futures fail
-    async fn process_work_stream<S: Stream<Item = Result<()>> + Unpin>(
+    async fn process_work_stream<E, S: Stream<Item = Result<(), E>> + Unpin>(
         mut stream: S,
-    ) -> Result<()> {
+    ) -> Result<(), E> {
         while let Some(result) = stream.next().await {
             result?;
         }
@@ -434,11 +1399,17 @@ impl CheckoutPlan {
         Ok(r)
     }
 
-    fn check_content(vfs: &VFS, files: Vec<(Bytes, Key)>) -> Result<Vec<RepoPathBuf>> {
+    /// Returns files whose content on disk differs from the store content,
+    /// after `transform`.
+    fn check_content(
+        vfs: &VFS,
+        transform: &ContentTransform,
+        files: Vec<(Bytes, Key, Option<UpdateFlag>)>,
+    ) -> Result<Vec<RepoPathBuf>> {
         let mut result = vec![];
-        for file in files {
-            let path = &file.1.path;
-            match Self::check_file(vfs, file.0, path) {
+        for (content, key, flag) in files {
+            let path = &key.path;
+            match Self::check_file(vfs, transform, content, path, flag) {
                 Err(err) => {
                     warn!("Can not check {}: {}", path, err);
                     result.push(path.clone())
@@ -450,7 +1421,14 @@ impl CheckoutPlan {
         Ok(result)
     }
 
-    fn check_file(vfs: &VFS, expected_content: Bytes, path: &RepoPath) -> Result<bool> {
+    fn check_file(
+        vfs: &VFS,
+        transform: &ContentTransform,
+        expected_content: Bytes,
+        path: &RepoPath,
+        flag: Option<UpdateFlag>,
+    ) -> Result<bool> {
+        let expected_content = transform.apply(path, expected_content, flag)?;
         let actual_content = vfs.read(path)?;
         Ok(actual_content.eq(&expected_content))
     }
@@ -459,51 +1437,164 @@ impl CheckoutPlan {
     // As of today tokio::fs operations do the same.
     // Since we do multiple fs calls inside, it is beneficial to 'pack'
     // all of them into single spawn_blocking.
+    #[allow(clippy::too_many_arguments)]
     async fn write_files(
         async_vfs: &AsyncVfsWriter,
         stats: &CheckoutStats,
+        transform: &ContentTransform,
         actions: Vec<(RepoPathBuf, HgId, Bytes, UpdateFlag)>,
         progress: Option<&Mutex<CheckoutProgress>>,
+        journal: Option<&JournalRecorder>,
+        reporter: Option<&ProgressReporter>,
         bar: &Arc<ProgressBar>,
-    ) -> Result<()> {
+    ) -> Result<(), CheckoutError> {
         let count = actions.len();
 
         let first_file = actions
             .get(0)
             .expect("Cant have empty actions in write_files")
             .0
-            .to_string();
-        bar.set_message(first_file);
+            .clone();
+        bar.set_message(first_file.to_string());
 
         let paths: Vec<_> = actions
             .iter()
             .map(|(path, hgid, _, _)| (hgid.clone(), path.as_repo_path().to_owned()))
             .collect();
+
+        Self::inject_write_fault(&paths)?;
+
+        let actions = if transform.is_identity() {
+            actions
+        } else {
+            let transform = transform.clone();
+            Handle::current()
+                .spawn_blocking(move || Self::transform_contents(&transform, actions))
+                .await
+                .map_err(|e| CheckoutError::Other(e.into()))??
+        };
+        // Sizes of the written content, after transforms.
+        let written: Option<Vec<_>> = (journal.is_some() || reporter.is_some()).then(|| {
+            actions
+                .iter()
+                .map(|(path, hgid, content, _)| (path.clone(), *hgid, content.len() as u64))
+                .collect()
+        });
         let actions = actions
             .into_iter()
             .map(|(path, _, content, flag)| (path, content, flag));
-        let w = async_vfs.write_batch(actions).await?;
+        let w = async_vfs
+            .write_batch(actions)
+            .await
+            .map_err(|source| CheckoutError::Write {
+                path: first_file,
+                source,
+            })?;
         stats.updated.fetch_add(count, Ordering::Relaxed);
         stats.written_bytes.fetch_add(w, Ordering::Relaxed);
+        let written = written.unwrap_or_default();
+        if let Some(journal) = journal {
+            journal.record(written.iter().map(|(path, hgid, size)| {
+                let kind = JournalEntryKind::Write {
+                    hgid: *hgid,
+                    size: *size,
+                };
+                (path.clone(), kind)
+            }))?;
+        }
+        if let Some(reporter) = reporter {
+            for (path, _, bytes) in written {
+                reporter(CheckoutEvent::FileWritten { path, bytes });
+            }
+            reporter::report_stats(Some(reporter), stats);
+        }
 
         if let Some(progress) = progress {
-            progress.lock().record_writes(paths);
-            fail::fail_point!("checkout-post-progress", |_| { bail!("oh no!") });
+            let unrecorded = progress.lock().record_writes(paths);
+            stats
+                .unrecorded_progress
+                .fetch_add(unrecorded, Ordering::Relaxed);
+            fail::fail_point!("checkout-post-progress", |_| {
+                Err(CheckoutError::Progress {
+                    source: format_err!("oh no!"),
+                })
+            });
         }
         bar.increase_position(count as u64);
 
         Ok(())
     }
 
+    /// Transforms may fetch content, so this blocks.
+    fn transform_contents(
+        transform: &ContentTransform,
+        actions: Vec<(RepoPathBuf, HgId, Bytes, UpdateFlag)>,
+    ) -> Result<Vec<(RepoPathBuf, HgId, Bytes, UpdateFlag)>, CheckoutError> {
+        actions
+            .into_iter()
+            .map(
+                |(path, hgid, content, flag)| match transform.apply(&path, content, Some(flag)) {
+                    Ok(content) => Ok((path, hgid, content, flag)),
+                    Err(source) => Err(CheckoutError::Transform { path, source }),
+                },
+            )
+            .collect()
+    }
+
+    /// Fails the batch if it contains the file named by the
+    /// "checkout-write-file" failpoint, e.g. `checkout-write-file=return(a/b)`.
+    fn inject_write_fault(paths: &[(HgId, RepoPathBuf)]) -> Result<(), CheckoutError> {
+        fail::fail_point!("checkout-write-file", |arg: Option<String>| {
+            match paths
+                .iter()
+                .find(|(_, p)| Some(p.as_str()) == arg.as_deref())
+            {
+                Some((_, path)) => Err(CheckoutError::Write {
+                    path: path.clone(),
+                    source: format_err!("injected write failure"),
+                }),
+                None => Ok(()),
+            }
+        });
+        Ok(())
+    }
+
     async fn remove_files(
         async_vfs: &AsyncVfsWriter,
         stats: &CheckoutStats,
         paths: Vec<RepoPathBuf>,
+        journal: Option<&JournalRecorder>,
+        reporter: Option<&ProgressReporter>,
         bar: &Arc<ProgressBar>,
-    ) -> Result<()> {
+    ) -> Result<(), CheckoutError> {
         let count = paths.len();
-        async_vfs.remove_batch(paths).await?;
+        let first_path = paths
+            .get(0)
+            .expect("Cant have empty paths in remove_files")
+            .clone();
+        let removed = (journal.is_some() || reporter.is_some()).then(|| paths.clone());
+        async_vfs
+            .remove_batch(paths)
+            .await
+            .map_err(|source| CheckoutError::Remove {
+                path: first_path,
+                source,
+            })?;
         stats.removed.fetch_add(count, Ordering::Relaxed);
+        let removed = removed.unwrap_or_default();
+        if let Some(journal) = journal {
+            journal.record(
+                removed
+                    .iter()
+                    .map(|path| (path.clone(), JournalEntryKind::Remove)),
+            )?;
+        }
+        if let Some(reporter) = reporter {
+            for path in removed {
+                reporter(CheckoutEvent::FileRemoved { path });
+            }
+            reporter::report_stats(Some(reporter), stats);
+        }
         bar.increase_position(count as u64);
         Ok(())
     }
@@ -513,13 +1604,27 @@ impl CheckoutPlan {
         stats: &CheckoutStats,
         path: &RepoPath,
         flag: bool,
+        journal: Option<&JournalRecorder>,
+        reporter: Option<&ProgressReporter>,
         bar: &Arc<ProgressBar>,
-    ) -> Result<()> {
+    ) -> Result<(), CheckoutError> {
         async_vfs
             .set_executable(path.to_owned(), flag)
             .await
-            .context(format!("Updating exec on {}", path))?;
+            .map_err(|source| CheckoutError::Meta {
+                path: path.to_owned(),
+                source,
+            })?;
         stats.meta_updated.fetch_add(1, Ordering::Relaxed);
+        if let Some(journal) = journal {
+            journal.record([(path.to_owned(), JournalEntryKind::SetExec(flag))])?;
+        }
+        if let Some(reporter) = reporter {
+            reporter(CheckoutEvent::ExecBitSet {
+                path: path.to_owned(),
+                executable: flag,
+            });
+        }
         bar.increase_position(1);
         Ok(())
     }
@@ -536,11 +1641,6 @@ impl CheckoutPlan {
         self.update_meta.iter().map(|u| &u.path)
     }
 
-    fn new_file_actions(&self) -> impl Iterator<Item = &UpdateContentAction> {
-        // todo - index new files so that this function don't need to be O(total_files_changed)test-update-names.t.err
-        self.filtered_update_content.iter().filter(|u| u.new_file)
-    }
-
     pub fn all_files(&self) -> impl Iterator<Item = &RepoPathBuf> {
         self.update_content
             .iter()
@@ -549,7 +1649,8 @@ impl CheckoutPlan {
             .chain(self.update_meta.iter().map(|u| &u.path))
     }
 
-    /// Returns (updated, removed)
+    /// Returns (updated, removed), only counting files in the paths of a
+    /// scoped plan.
     pub fn stats(&self) -> (usize, usize) {
         (
             self.update_meta.len() + self.update_content.len(),
@@ -557,6 +1658,62 @@ impl CheckoutPlan {
         )
     }
 
+    /// Returns counts of the actions of the plan grouped by the first `depth`
+    /// components of their paths, sorted by descending total and then by
+    /// prefix. Files with fewer components are counted under their own path.
+    ///
+    /// Prefixes with less than 1% of the files of the plan are merged in a
+    /// last entry with an empty prefix.
+    pub fn summarize_by_prefix(&self, depth: usize) -> Vec<PrefixSummary> {
+        // Counts of removed, updated and meta files by prefix.
+        let mut counts: HashMap<&RepoPath, [usize; 3]> = HashMap::new();
+        let actions = self
+            .remove
+            .iter()
+            .map(|path| (path, 0))
+            .chain(self.update_content.iter().map(|u| (&u.path, 1)))
+            .chain(self.update_meta.iter().map(|u| (&u.path, 2)));
+        let mut total = 0;
+        for (path, kind) in actions {
+            counts.entry(path_prefix(path, depth)).or_default()[kind] += 1;
+            total += 1;
+        }
+
+        let mut tail = PrefixSummary::default();
+        let mut summaries = vec![];
+        for (prefix, [removed, updated, meta]) in counts {
+            let summary = PrefixSummary {
+                prefix: prefix.to_owned(),
+                removed,
+                updated,
+                meta,
+            };
+            if summary.total() * 100 < total * PREFIX_SUMMARY_TAIL_PERCENT {
+                tail.add(&summary);
+            } else {
+                summaries.push(summary);
+            }
+        }
+        summaries.sort_by(|a, b| {
+            b.total()
+                .cmp(&a.total())
+                .then_with(|| a.prefix.cmp(&b.prefix))
+        });
+        if tail.total() > 0 {
+            summaries.push(tail);
+        }
+        summaries
+    }
+
+    /// Same as `summarize_by_prefix`, as a JSON list of objects with the
+    /// fields of `PrefixSummary`.
+    pub fn summarize_by_prefix_json(&self, depth: usize) -> serde_json::Value {
+        self.summarize_by_prefix(depth)
+            .iter()
+            .map(PrefixSummary::to_json)
+            .collect()
+    }
+
     pub fn vfs(&self) -> &VFS {
         &self.checkout.vfs
     }
@@ -569,16 +1726,133 @@ impl CheckoutPlan {
             filtered_update_content: vec![],
             update_meta: vec![],
             progress: None,
+            journal: None,
+            reporter: None,
             checkout: Checkout::default_config(vfs),
+            scope: None,
+            coalesced: vec![],
+            type_changes: vec![],
+            type_change_removes: 0,
+        }
+    }
+}
+
+/// Returns the paths that are removed files with files written under them,
+/// or written files with files removed under them.
+fn find_type_changes(
+    remove: &[RepoPathBuf],
+    update_content: &[UpdateContentAction],
+) -> Vec<TypeChange> {
+    let removed: HashSet<&RepoPath> = remove.iter().map(|path| path.as_repo_path()).collect();
+    let updated: HashSet<&RepoPath> = update_content
+        .iter()
+        .map(|u| u.path.as_repo_path())
+        .collect();
+    let mut type_changes = vec![];
+    for (paths, dirs, to_dir) in [(&updated, &removed, true), (&removed, &updated, false)] {
+        for path in paths {
+            for parent in path.parents().skip(1) {
+                if dirs.contains(parent) {
+                    type_changes.push(TypeChange {
+                        path: parent.to_owned(),
+                        to_dir,
+                    });
+                }
+            }
+        }
+    }
+    type_changes.sort();
+    type_changes.dedup();
+    type_changes
+}
+
+/// Move the removals that make room for `type_changes` to the start of
+/// `remove`, and return how many there are.
+fn order_type_change_removes(remove: &mut Vec<RepoPathBuf>, type_changes: &[TypeChange]) -> usize {
+    if type_changes.is_empty() {
+        return 0;
+    }
+    let files: HashSet<&RepoPath> = type_changes
+        .iter()
+        .filter(|c| c.to_dir)
+        .map(|c| c.path.as_repo_path())
+        .collect();
+    let dirs: HashSet<&RepoPath> = type_changes
+        .iter()
+        .filter(|c| !c.to_dir)
+        .map(|c| c.path.as_repo_path())
+        .collect();
+    let (mut first, rest): (Vec<_>, Vec<_>) = remove.drain(..).partition(|path| {
+        files.contains(path.as_repo_path())
+            || path.parents().skip(1).any(|parent| dirs.contains(parent))
+    });
+    first.sort();
+    let count = first.len();
+    first.extend(rest);
+    *remove = first;
+    count
+}
+
+/// Drop from `remove` the files that are also in `update_content`, and
+/// return what was dropped. Paths are compared ignoring case unless
+/// `case_sensitive`.
+fn coalesce_removes(
+    remove: &mut Vec<RepoPathBuf>,
+    update_content: &[UpdateContentAction],
+    update_meta: &[UpdateMetaAction],
+    case_sensitive: bool,
+) -> Result<Vec<CoalescedRemove>, CheckoutError> {
+    let key = |path: &RepoPathBuf| {
+        if case_sensitive {
+            path.as_str().to_string()
+        } else {
+            path.as_str().to_lowercase()
+        }
+    };
+    let updated: HashMap<String, &RepoPathBuf> = update_content
+        .iter()
+        .map(|u| (key(&u.path), &u.path))
+        .collect();
+    let meta: HashMap<String, &RepoPathBuf> = update_meta
+        .iter()
+        .map(|u| (key(&u.path), &u.path))
+        .collect();
+
+    let mut coalesced = vec![];
+    let mut kept = Vec::with_capacity(remove.len());
+    for path in remove.drain(..) {
+        let path_key = key(&path);
+        if let Some(updated) = meta.get(&path_key) {
+            return Err(CheckoutError::RemoveAndUpdateMeta {
+                removed: path,
+                updated: (*updated).clone(),
+            });
         }
+        match updated.get(&path_key) {
+            Some(updated) => coalesced.push(CoalescedRemove {
+                removed: path,
+                updated: (*updated).clone(),
+            }),
+            None => kept.push(path),
+        }
+    }
+    *remove = kept;
+    coalesced.sort_by(|a, b| a.removed.cmp(&b.removed));
+    for c in &coalesced {
+        debug!(
+            "coalesced removal of {} into update of {}",
+            c.removed, c.updated
+        );
     }
+    Ok(coalesced)
 }
 
 impl CheckoutProgress {
-    pub fn new(path: &Path, vfs: VFS) -> Result<Self> {
+    pub fn new(path: &Path, vfs: VFS, sync: ProgressSync) -> Result<Self> {
         Ok(CheckoutProgress {
             file: util::file::create(path)?,
             vfs,
+            sync,
             state: HashMap::new(),
         })
     }
@@ -589,17 +1863,22 @@ impl CheckoutProgress {
     ///
     ///   <40_char_hg_hash> <mtime_in_millis> <written_file_length> <file_path>\0
     ///
-    pub fn load(path: &Path, vfs: VFS) -> Result<Self> {
+    /// Malformed rows are skipped. A final row without the trailing \0 was
+    /// torn by an interrupted write, and is truncated away so that new rows
+    /// are not appended to it.
+    pub fn load(path: &Path, vfs: VFS, sync: ProgressSync) -> Result<Self> {
         let mut state: HashMap<RepoPathBuf, (HgId, u128, u64)> = HashMap::new();
 
         let file = util::file::open(path, "r")?;
         let mut reader = BufReader::new(file);
         let mut buffer = vec![];
+        let mut complete_len = 0;
         loop {
             reader.read_until(0, &mut buffer)?;
-            if buffer.is_empty() {
+            if buffer.last() != Some(&0) {
                 break;
             }
+            complete_len += buffer.len() as u64;
             let (path, (hgid, time, size)) = match (|| -> Result<_> {
                 let mut split = buffer.splitn(4, |c| *c == b' ');
                 let hgid = HgId::from_hex(
@@ -641,32 +1920,67 @@ impl CheckoutProgress {
             buffer.clear();
         }
 
+        let file = util::file::open(path, "ca")?;
+        if file.metadata()?.len() > complete_len {
+            file.set_len(complete_len)?;
+        }
+
         Ok(CheckoutProgress {
-            file: util::file::open(path, "ca")?,
+            file,
             vfs,
+            sync,
             state,
         })
     }
 
-    fn record_writes(&mut self, paths: Vec<(HgId, RepoPathBuf)>) {
+    /// Records a batch of written files with a single write to the progress
+    /// file. Returns how many of the files could not be recorded.
+    fn record_writes(&mut self, paths: Vec<(HgId, RepoPathBuf)>) -> usize {
+        let count = paths.len();
+        let mut unrecorded = 0;
+        let mut records = String::new();
         for (hgid, path) in paths.into_iter() {
-            // Don't report write failures, just let the checkout continue.
-            let _ = (|| -> Result<()> {
+            let time_and_len = (|| -> Result<_> {
                 let stat = self.vfs.metadata(&path)?;
                 let time = stat
                     .modified()?
                     .duration_since(SystemTime::UNIX_EPOCH)?
                     .as_millis();
-
-                self.file
-                    .write_all(
-                        format!("{} {} {} {}\0", hgid.to_hex(), time, stat.len(), path).as_bytes(),
-                    )
-                    .map_err(|e| e.into())
+                Ok((time, stat.len()))
             })();
+            match time_and_len {
+                Ok((time, len)) => {
+                    records.push_str(&format!("{} {} {} {}\0", hgid.to_hex(), time, len, path));
+                }
+                Err(e) => {
+                    debug!("Failed to record checkout progress for {}: {:?}", path, e);
+                    unrecorded += 1;
+                }
+            }
+        }
+
+        // Don't report write failures, just let the checkout continue.
+        let written = self.file.write_all(records.as_bytes()).and_then(|()| {
+            if self.sync == ProgressSync::Batch {
+                self.file.sync_data()
+            } else {
+                Ok(())
+            }
+        });
+        match written {
+            Ok(()) => unrecorded,
+            Err(e) => {
+                debug!("Failed to write checkout progress: {:?}", e);
+                count
+            }
         }
     }
 
+    /// Syncs recorded progress to disk.
+    fn sync(&mut self) -> Result<()> {
+        Ok(self.file.sync_data()?)
+    }
+
     fn filter_already_written<'a>(
         &self,
         actions: &[UpdateContentAction],
@@ -704,132 +2018,1647 @@ impl CheckoutProgress {
             .map(|a| a.clone())
             .collect()
     }
-}
+}
+
+fn type_to_flag(ft: &FileType) -> UpdateFlag {
+    match ft {
+        FileType::Regular => UpdateFlag::Regular,
+        FileType::Executable => UpdateFlag::Executable,
+        FileType::Symlink => UpdateFlag::Symlink,
+        FileType::GitSubmodule => {
+            panic!("bug: GitSubmodule should be filtered out earlier by ActionMap")
+        }
+    }
+}
+
+impl UpdateContentAction {
+    pub fn new(path: RepoPathBuf, meta: FileMetadata, new_file: bool) -> Self {
+        Self {
+            path,
+            content_hgid: meta.hgid,
+            file_type: meta.file_type,
+            new_file,
+        }
+    }
+
+    pub fn make_key(&self) -> Key {
+        Key::new(self.path.clone(), self.content_hgid)
+    }
+}
+
+impl AsRef<RepoPath> for UpdateContentAction {
+    fn as_ref(&self) -> &RepoPath {
+        &self.path
+    }
+}
+
+impl AsRef<RepoPath> for UpdateMetaAction {
+    fn as_ref(&self) -> &RepoPath {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+// todo - consider moving some of this code to vfs / separate test create
+// todo parallel execution for the test
+mod test {
+    use std::collections::HashMap;
+    use std::collections::HashSet;
+    use std::fs::create_dir;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use anyhow::ensure;
+    use anyhow::Context;
+    use futures::stream::BoxStream;
+    use manifest_tree::testutil::make_tree_manifest_from_meta;
+    use manifest_tree::testutil::TestStore;
+    use manifest_tree::Diff;
+    use pathmatcher::AlwaysMatcher;
+    use pathmatcher::DynMatcher;
+    use quickcheck::Arbitrary;
+    use quickcheck::Gen;
+    use status::StatusBuilder;
+    use tempfile::TempDir;
+    use types::testutil::generate_repo_paths;
+    use vfs::RetryPolicy;
+    use walkdir::DirEntry;
+    use walkdir::WalkDir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_basic_checkout() -> Result<()> {
+        // Pattern - lowercase_path_[hgid!=1]_[flags!=normal]
+        let a = (rp("A"), FileMetadata::regular(hgid(1)));
+        let a_2 = (rp("A"), FileMetadata::regular(hgid(2)));
+        let a_e = (rp("A"), FileMetadata::executable(hgid(1)));
+        let a_s = (rp("A"), FileMetadata::symlink(hgid(1)));
+        let b = (rp("B"), FileMetadata::regular(hgid(1)));
+        let ab = (rp("A/B"), FileMetadata::regular(hgid(1)));
+        let cd = (rp("C/D"), FileMetadata::regular(hgid(1)));
+
+        // update file
+        assert_checkout(&[a.clone()], &[a_2.clone()]).await?;
+        // mv file
+        assert_checkout(&[a.clone()], &[b.clone()]).await?;
+        // add / rm file
+        assert_checkout_symmetrical(&[a.clone()], &[a.clone(), b.clone()]).await?;
+        // regular<->exec
+        assert_checkout_symmetrical(&[a.clone()], &[a_e.clone()]).await?;
+        // regular<->symlink
+        assert_checkout_symmetrical(&[a.clone()], &[a_s.clone()]).await?;
+        // dir <-> file with the same name
+        assert_checkout_symmetrical(&[ab.clone()], &[a.clone()]).await?;
+        // create / rm dir
+        assert_checkout_symmetrical(&[ab.clone()], &[b.clone()]).await?;
+        // mv file between dirs
+        assert_checkout(&[ab.clone()], &[cd.clone()]).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_type_change_checkout() -> Result<()> {
+        // dir <-> file with the same name, with nested children on both
+        // sides.
+        let dirs = [
+            (rp("A/0"), FileMetadata::regular(hgid(1))),
+            (rp("A/B/C"), FileMetadata::regular(hgid(1))),
+            (rp("A/B/D/E"), FileMetadata::executable(hgid(2))),
+            (rp("A/F"), FileMetadata::regular(hgid(3))),
+        ];
+        let files = [
+            (rp("A/B"), FileMetadata::regular(hgid(4))),
+            (rp("A/F/G/H"), FileMetadata::regular(hgid(5))),
+            (rp("A/F/I"), FileMetadata::symlink(hgid(6))),
+        ];
+        let symlinks = [
+            (rp("A/B"), FileMetadata::symlink(hgid(4))),
+            (rp("A/F/G/H"), FileMetadata::regular(hgid(5))),
+        ];
+        assert_checkout_symmetrical(&dirs, &files).await?;
+        assert_checkout_symmetrical(&dirs, &symlinks).await?;
+
+        let tempdir = tempfile::tempdir()?;
+        let vfs = VFS::new(tempdir.path().to_path_buf())?;
+        let plan = make_plan(&vfs, &dirs, &files)?;
+        assert_eq!(
+            plan.type_changes(),
+            &[
+                TypeChange {
+                    path: rp("A/B"),
+                    to_dir: false,
+                },
+                TypeChange {
+                    path: rp("A/F"),
+                    to_dir: true,
+                },
+            ]
+        );
+        // Removals making room for the type changes come first.
+        assert!(plan.to_string().starts_with(
+            "retype A/B => file\nretype A/F => dir\nrm A/B/C\nrm A/B/D/E\nrm A/F\nrm A/0\nup "
+        ));
+        assert_eq!(plan.stats(), (3, 4));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_checkout_generated() -> Result<()> {
+        let trees = generate_trees(6, 50);
+        for a in trees.iter() {
+            for b in trees.iter() {
+                if a == b {
+                    continue;
+                }
+                assert_checkout(a, b).await?;
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_audit_working_copy() -> Result<()> {
+        let trees = generate_trees(6, 2);
+        let (from, to) = (&trees[0], &trees[1]);
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().to_path_buf().join("workingdir");
+        create_dir(working_path.as_path())?;
+        let vfs = VFS::new(working_path)?;
+        roll_out_fs(&vfs, from)?;
+        make_plan(&vfs, from, to)?
+            .apply_store(&DummyFileContentStore)
+            .await?;
+
+        let manifest = make_tree_manifest_from_meta(Arc::new(TestStore::new()), to.iter().cloned());
+        let matcher: DynMatcher = Arc::new(AlwaysMatcher::new());
+        let report = audit_working_copy(
+            &vfs,
+            &manifest,
+            &DummyFileContentStore,
+            matcher.clone(),
+            4,
+            ContentCheck::All,
+        )
+        .await?;
+        assert_eq!(report, AuditReport::default());
+
+        let (corrupted, corrupted_meta) = &to[0];
+        vfs.write(
+            corrupted,
+            b"corrupted",
+            type_to_flag(&corrupted_meta.file_type),
+        )?;
+        let (deleted, _) = &to[1];
+        vfs.remove(deleted)?;
+
+        let report = audit_working_copy(
+            &vfs,
+            &manifest,
+            &DummyFileContentStore,
+            matcher,
+            4,
+            ContentCheck::All,
+        )
+        .await?;
+        assert_eq!(
+            report,
+            AuditReport {
+                missing: vec![deleted.clone()],
+                content_mismatches: vec![corrupted.clone()],
+                ..Default::default()
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_progress_parsing() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().to_path_buf().join("workingdir");
+        create_dir(working_path.as_path()).unwrap();
+        let vfs = VFS::new(working_path.clone())?;
+        let path = tempdir.path().to_path_buf().join("updateprogress");
+        let mut progress = CheckoutProgress::new(&path, vfs.clone(), ProgressSync::Batch)?;
+        let file_path = RepoPathBuf::from_string("file".to_string())?;
+        vfs.write(file_path.as_repo_path(), &[0b0, 0b01], UpdateFlag::Regular)?;
+        let id = hgid(1);
+        progress.record_writes(vec![(id, file_path.clone())]);
+
+        let progress = CheckoutProgress::load(&path, vfs.clone(), ProgressSync::Batch)?;
+        assert_eq!(progress.state.len(), 1);
+        assert_eq!(progress.state.get(&file_path).unwrap().0, id);
+        Ok(())
+    }
+
+    #[test]
+    fn test_progress_torn_record() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path)?;
+        let path = tempdir.path().join("updateprogress");
+        let files: Vec<_> = (0..3)
+            .map(|i| (hgid(i + 1), rp(&format!("file{}", i))))
+            .collect();
+        for (_, file) in &files {
+            vfs.write(file, b"content", UpdateFlag::Regular)?;
+        }
+
+        let mut progress = CheckoutProgress::new(&path, vfs.clone(), ProgressSync::End)?;
+        // Metadata failures are counted rather than recorded.
+        let mut batch = files.clone();
+        batch.push((hgid(4), rp("missing")));
+        assert_eq!(progress.record_writes(batch), 1);
+        progress.sync()?;
+        drop(progress);
+
+        // Tear the last record in the middle of its path.
+        let len = std::fs::metadata(&path)?.len();
+        File::options().write(true).open(&path)?.set_len(len - 3)?;
+
+        let mut progress = CheckoutProgress::load(&path, vfs.clone(), ProgressSync::Batch)?;
+        assert_eq!(progress.state.len(), 2);
+        for (id, file) in &files[..2] {
+            assert_eq!(progress.state.get(file).unwrap().0, *id);
+        }
+
+        // The torn record is dropped, so records appended later still load.
+        assert_eq!(progress.record_writes(files[2..].to_vec()), 0);
+        let progress = CheckoutProgress::load(&path, vfs, ProgressSync::Batch)?;
+        assert_eq!(progress.state.len(), 3);
+        assert_eq!(progress.state.get(&files[2].1).unwrap().0, files[2].0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_store_fetch_fault() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path.clone())?;
+        let from = [
+            (rp("A"), FileMetadata::regular(hgid(1))),
+            (rp("B"), FileMetadata::regular(hgid(1))),
+        ];
+        let to = [
+            (rp("A"), FileMetadata::regular(hgid(2))),
+            (rp("C"), FileMetadata::regular(hgid(3))),
+            (rp("D"), FileMetadata::regular(hgid(4))),
+        ];
+        roll_out_fs(&vfs, &from)?;
+        let plan = make_plan(&vfs, &from, &to)?;
+
+        let store = FaultyFileContentStore::fail_nth_fetch(1);
+        let stats = CheckoutStats::default();
+        let err = plan
+            .apply_store_with_stats(&store, &stats)
+            .await
+            .unwrap_err();
+        let failed_key = store.failed_key().expect("fetch fault was not injected");
+        match err {
+            CheckoutError::StoreFetch { key, .. } => assert_eq!(key, Some(failed_key)),
+            err => panic!("unexpected error: {:?}", err),
+        }
+
+        // Removal completes before any content is fetched, while the failed
+        // fetch aborts the only write batch.
+        assert_eq!(stats.removed.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.updated.load(Ordering::Relaxed), 0);
+        assert_eq!(stats.written_bytes.load(Ordering::Relaxed), 0);
+        assert!(!working_path.join("B").exists());
+        assert_eq!(vfs.read(&rp("A"))?, Bytes::from(hgid_file(&hgid(1))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_store_fetch_retries() -> Result<()> {
+        let to = [
+            (rp("A"), FileMetadata::regular(hgid(2))),
+            (rp("C"), FileMetadata::regular(hgid(3))),
+            (rp("D"), FileMetadata::regular(hgid(4))),
+        ];
+        let make_plan_with_retries = |vfs: &VFS, retries| -> Result<CheckoutPlan> {
+            let mut plan = make_plan(vfs, &[], &to)?;
+            plan.checkout.config.fetch_retries = retries;
+            plan.checkout.config.fetch_retry_backoff = Duration::from_millis(1);
+            Ok(plan)
+        };
+
+        // Each failing key succeeds on its last retry.
+        let tempdir = tempfile::tempdir()?;
+        let vfs = VFS::new(tempdir.path().to_path_buf())?;
+        let store = FlakyFileContentStore::new(&[("C", 2), ("D", 1)]);
+        make_plan_with_retries(&vfs, 2)?.apply_store(&store).await?;
+        assert_fs(tempdir.path(), &to)?;
+
+        // Keys still failing after the retries are all reported, once the
+        // other files are written.
+        let tempdir = tempfile::tempdir()?;
+        let vfs = VFS::new(tempdir.path().to_path_buf())?;
+        let store = FlakyFileContentStore::new(&[("C", 3), ("D", 3)]);
+        let err = make_plan_with_retries(&vfs, 2)?
+            .apply_store_with_stats(&store, &CheckoutStats::default())
+            .await
+            .unwrap_err();
+        match err {
+            CheckoutError::StoreFetchRetries { keys, retries, .. } => {
+                let paths: Vec<_> = keys.into_iter().map(|key| key.path).collect();
+                assert_eq!(paths, vec![rp("C"), rp("D")]);
+                assert_eq!(retries, 2);
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+        assert_eq!(vfs.read(&rp("A"))?, Bytes::from(hgid_file(&hgid(2))));
+        assert!(!tempdir.path().join("C").exists());
+
+        // Without retries, the first network error fails the checkout.
+        let tempdir = tempfile::tempdir()?;
+        let vfs = VFS::new(tempdir.path().to_path_buf())?;
+        let store = FlakyFileContentStore::new(&[("C", 1)]);
+        let err = make_plan_with_retries(&vfs, 0)?
+            .apply_store_with_stats(&store, &CheckoutStats::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CheckoutError::StoreFetch { .. }), "{:?}", err);
+
+        // Other errors are not retried.
+        let tempdir = tempfile::tempdir()?;
+        let vfs = VFS::new(tempdir.path().to_path_buf())?;
+        let store = FaultyFileContentStore::fail_nth_fetch(1);
+        let err = make_plan_with_retries(&vfs, 2)?
+            .apply_store_with_stats(&store, &CheckoutStats::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CheckoutError::StoreFetch { .. }), "{:?}", err);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_apply_store_meta_fault() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path)?;
+        let from = [(rp("A"), FileMetadata::regular(hgid(1)))];
+        let to = [(rp("A"), FileMetadata::executable(hgid(1)))];
+        // "A" is never written, so updating its exec flag fails.
+        let plan = make_plan(&vfs, &from, &to)?;
+
+        let stats = CheckoutStats::default();
+        let err = plan
+            .apply_store_with_stats(&DummyFileContentStore, &stats)
+            .await
+            .unwrap_err();
+        match err {
+            CheckoutError::Meta { path, .. } => assert_eq!(path, rp("A")),
+            err => panic!("unexpected error: {:?}", err),
+        }
+        assert_eq!(stats.meta_updated.load(Ordering::Relaxed), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_store_write_fault_and_resume() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path.clone())?;
+        let progress_path = tempdir.path().join("updateprogress");
+        // Enough files to span several write batches.
+        let mut to: Vec<_> = (0..VFS_BATCH_SIZE * 2)
+            .map(|i| {
+                (
+                    rp(&format!("dir/file{}", i)),
+                    FileMetadata::regular(hgid(1)),
+                )
+            })
+            .collect();
+        to.push((rp("fault/target"), FileMetadata::regular(hgid(2))));
+        let total = to.len();
+
+        let mut plan = make_plan(&vfs, &[], &to)?;
+        plan.add_progress(&progress_path)?;
+        fail::cfg("checkout-write-file", "return(fault/target)").map_err(|e| anyhow!(e))?;
+        let stats = CheckoutStats::default();
+        let result = plan
+            .apply_store_with_stats(&DummyFileContentStore, &stats)
+            .await;
+        fail::remove("checkout-write-file");
+        match result {
+            Err(CheckoutError::Write { path, .. }) => assert_eq!(path, rp("fault/target")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Only completed batches are counted, and those are exactly the files
+        // recorded in the progress file.
+        let updated = stats.updated.load(Ordering::Relaxed);
+        assert!(updated < total);
+        let progress = CheckoutProgress::load(&progress_path, vfs.clone(), ProgressSync::Batch)?;
+        assert_eq!(progress.state.len(), updated);
+        assert!(!progress.state.contains_key(&rp("fault/target")));
+
+        // Resuming skips the files already written and completes the checkout.
+        let mut plan = make_plan(&vfs, &[], &to)?;
+        plan.add_progress(&progress_path)?;
+        assert_eq!(plan.filtered_update_content.len(), total - updated);
+        let stats = plan.apply_store(&DummyFileContentStore).await?;
+        assert_eq!(stats.updated.load(Ordering::Relaxed), total - updated);
+        assert_fs(&working_path, &to)
+    }
+
+    #[tokio::test]
+    async fn test_journal_replays_plan() -> Result<()> {
+        let trees = generate_trees(6, 10);
+        for (i, from) in trees.iter().enumerate() {
+            for to in trees.iter().skip(i + 1) {
+                let tempdir = tempfile::tempdir()?;
+                let working_path = tempdir.path().join("workingdir");
+                create_dir(&working_path)?;
+                let vfs = VFS::new(working_path.clone())?;
+                roll_out_fs(&vfs, from)?;
+                let journal_path = tempdir.path().join("journal");
+
+                let mut plan = make_plan(&vfs, from, to)?;
+                let journal = FileCheckoutJournal::open(&journal_path, ProgressSync::Batch)?;
+                plan.set_journal(Arc::new(journal));
+                plan.apply_store(&DummyFileContentStore).await?;
+                assert_fs(&working_path, to)?;
+
+                let entries = FileCheckoutJournal::read(&journal_path)?;
+                let seqs: Vec<_> = entries.iter().map(|entry| entry.seq).collect();
+                assert_eq!(seqs, (0..entries.len() as u64).collect::<Vec<_>>());
+
+                // Each action of the plan is recorded exactly once.
+                let mut expected: Vec<_> = plan
+                    .remove
+                    .iter()
+                    .map(|path| (path.clone(), JournalEntryKind::Remove))
+                    .chain(plan.update_content.iter().map(|action| {
+                        let hgid = action.content_hgid;
+                        let size = hgid_file(&hgid).len() as u64;
+                        (action.path.clone(), JournalEntryKind::Write { hgid, size })
+                    }))
+                    .chain(plan.update_meta.iter().map(|action| {
+                        let kind = JournalEntryKind::SetExec(action.set_x_flag);
+                        (action.path.clone(), kind)
+                    }))
+                    .collect();
+                let mut replayed: Vec<_> = entries
+                    .into_iter()
+                    .map(|entry| (entry.path, entry.kind))
+                    .collect();
+                let key = |(path, kind): &(RepoPathBuf, JournalEntryKind)| {
+                    (path.clone(), format!("{:?}", kind))
+                };
+                expected.sort_by_key(key);
+                replayed.sort_by_key(key);
+                assert_eq!(replayed, expected);
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_journal_write_fault_and_resume() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path.clone())?;
+        let progress_path = tempdir.path().join("updateprogress");
+        let journal_path = tempdir.path().join("journal");
+        let mut to: Vec<_> = (0..VFS_BATCH_SIZE * 2)
+            .map(|i| {
+                (
+                    rp(&format!("dir/file{}", i)),
+                    FileMetadata::regular(hgid(1)),
+                )
+            })
+            .collect();
+        to.push((rp("fault/target"), FileMetadata::regular(hgid(2))));
+        let total = to.len();
+
+        let mut plan = make_plan(&vfs, &[], &to)?;
+        plan.add_progress(&progress_path)?;
+        let journal = FileCheckoutJournal::open(&journal_path, ProgressSync::Batch)?;
+        plan.set_journal(Arc::new(journal));
+        fail::cfg("checkout-write-file", "return(fault/target)").map_err(|e| anyhow!(e))?;
+        let result = plan.apply_store(&DummyFileContentStore).await;
+        fail::remove("checkout-write-file");
+        assert!(result.is_err());
+
+        // The journal has exactly the files of the completed batches, as
+        // they are on disk.
+        let entries = FileCheckoutJournal::read(&journal_path)?;
+        let progress = CheckoutProgress::load(&progress_path, vfs.clone(), ProgressSync::Batch)?;
+        assert!(entries.len() < total);
+        assert_eq!(entries.len(), progress.state.len());
+        for (seq, entry) in entries.iter().enumerate() {
+            assert_eq!(entry.seq, seq as u64);
+            assert!(progress.state.contains_key(&entry.path));
+            let size = vfs.metadata(&entry.path)?.len();
+            let kind = JournalEntryKind::Write {
+                hgid: hgid(1),
+                size,
+            };
+            assert_eq!(entry.kind, kind);
+        }
+
+        // Resuming appends the remaining files after the last entry.
+        let last = entries.last().map(|entry| entry.seq);
+        let mut plan = make_plan(&vfs, &[], &to)?;
+        plan.add_progress(&progress_path)?;
+        let journal = FileCheckoutJournal::open(&journal_path, ProgressSync::Batch)?;
+        assert_eq!(journal.last_sequence(), last);
+        plan.set_journal(Arc::new(journal));
+        plan.apply_store(&DummyFileContentStore).await?;
+        assert_fs(&working_path, &to)?;
+
+        let entries = FileCheckoutJournal::read(&journal_path)?;
+        assert_eq!(entries.len(), total);
+        let paths: HashSet<_> = entries.iter().map(|entry| &entry.path).collect();
+        assert_eq!(paths.len(), total);
+        let seqs: Vec<_> = entries.iter().map(|entry| entry.seq).collect();
+        assert_eq!(seqs, (0..total as u64).collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_progress_reporter_matches_stats() -> Result<()> {
+        let trees = generate_trees(6, 10);
+        for (i, from) in trees.iter().enumerate() {
+            for to in trees.iter().skip(i + 1) {
+                let tempdir = tempfile::tempdir()?;
+                let working_path = tempdir.path().join("workingdir");
+                create_dir(&working_path)?;
+                let vfs = VFS::new(working_path.clone())?;
+                roll_out_fs(&vfs, from)?;
+                let journal_path = tempdir.path().join("journal");
+
+                let events = Arc::new(Mutex::new(Vec::new()));
+                let reporter_events = events.clone();
+                let mut plan = make_plan(&vfs, from, to)?.with_progress_reporter(Box::new(
+                    move |event: CheckoutEvent| reporter_events.lock().push(event),
+                ));
+                // The progress file and the journal are still kept.
+                plan.add_progress(&tempdir.path().join("updateprogress"))?;
+                let journal = FileCheckoutJournal::open(&journal_path, ProgressSync::Batch)?;
+                plan.set_journal(Arc::new(journal));
+                let stats = plan.apply_store(&DummyFileContentStore).await?;
+                assert_fs(&working_path, to)?;
+
+                let events = events.lock();
+                // `written_bytes` doesn't count symlinks by the size of their
+                // content, so only the counts are compared.
+                let mut counted = CheckoutStatsSnapshot {
+                    written_bytes: stats.snapshot().written_bytes,
+                    ..Default::default()
+                };
+                for event in events.iter() {
+                    match event {
+                        CheckoutEvent::FileWritten { .. } => counted.updated += 1,
+                        CheckoutEvent::FileRemoved { .. } => counted.removed += 1,
+                        CheckoutEvent::ExecBitSet { .. } => counted.meta_updated += 1,
+                        CheckoutEvent::Stats(_) => {}
+                    }
+                }
+                assert_eq!(counted, stats.snapshot());
+                assert_eq!(events.last(), Some(&CheckoutEvent::Stats(stats.snapshot())));
+                assert_eq!(
+                    FileCheckoutJournal::read(&journal_path)?.len(),
+                    counted.updated + counted.removed + counted.meta_updated
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_journal_torn_record() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("journal");
+        let journal = FileCheckoutJournal::open(&path, ProgressSync::End)?;
+        let entries: Vec<_> = (0..2)
+            .map(|seq| JournalEntry {
+                seq,
+                path: rp(&format!("file{}", seq)),
+                kind: JournalEntryKind::Remove,
+            })
+            .collect();
+        journal.record(&entries)?;
+        drop(journal);
+
+        // Tear the last record.
+        let len = std::fs::metadata(&path)?.len();
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)?
+            .set_len(len - 2)?;
+        assert_eq!(FileCheckoutJournal::read(&path)?, entries[..1]);
+
+        let journal = FileCheckoutJournal::open(&path, ProgressSync::End)?;
+        assert_eq!(journal.last_sequence(), Some(0));
+        journal.record(&entries[1..])?;
+        assert_eq!(FileCheckoutJournal::read(&path)?, entries);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_store_stats() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let vfs = VFS::new(tempdir.path().to_path_buf())?;
+        let mut from: Vec<_> = (0..10)
+            .map(|i| (rp(&format!("old{}", i)), FileMetadata::regular(hgid(1))))
+            .collect();
+        from.push((rp("x"), FileMetadata::regular(hgid(1))));
+        let mut to: Vec<_> = (0..VFS_BATCH_SIZE + 2)
+            .map(|i| (rp(&format!("new{}", i)), FileMetadata::regular(hgid(2))))
+            .collect();
+        to.push((rp("x"), FileMetadata::executable(hgid(1))));
+        roll_out_fs(&vfs, &from)?;
+
+        let plan = make_plan(&vfs, &from, &to)?;
+        let stats = plan.apply_store(&DummyFileContentStore).await?;
+        assert_fs(tempdir.path(), &to)?;
+
+        assert_eq!(stats.removed(), plan.removed_files().count());
+        assert_eq!(stats.updated(), plan.updated_content_files().count());
+        assert_eq!(stats.meta_updated(), plan.updated_meta_files().count());
+        assert_eq!(stats.removed(), 10);
+        assert_eq!(stats.updated(), VFS_BATCH_SIZE + 2);
+        assert_eq!(stats.meta_updated(), 1);
+        assert_eq!(stats.fetch_batches(), 2);
+        assert!(stats.written_bytes() > 0);
+        assert!(stats.remove_duration() > Duration::ZERO);
+        assert!(stats.write_duration() > Duration::ZERO);
+        assert!(stats.meta_duration() > Duration::ZERO);
+        let summary = stats.to_string();
+        assert!(
+            summary.starts_with(&format!(
+                "10 removed, {} updated ({} bytes), 1 meta updated in remove ",
+                VFS_BATCH_SIZE + 2,
+                stats.written_bytes()
+            )),
+            "{}",
+            summary
+        );
+        assert!(summary.ends_with(", 2 fetch batches"), "{}", summary);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_store_autotune() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let vfs = VFS::new(tempdir.path().to_path_buf())?;
+        let from: Vec<_> = (0..50)
+            .map(|i| (rp(&format!("old{}", i)), FileMetadata::regular(hgid(1))))
+            .collect();
+        let to: Vec<_> = (0..500)
+            .map(|i| {
+                (
+                    rp(&format!("dir{}/new{}", i % 7, i)),
+                    FileMetadata::regular(hgid(2)),
+                )
+            })
+            .collect();
+        roll_out_fs(&vfs, &from)?;
+
+        let mut plan = make_plan(&vfs, &from, &to)?;
+        let bounds = tune::AutoTune {
+            batch_size: tune::Bounds { min: 4, max: 64 },
+            concurrency: tune::Bounds { min: 1, max: 8 },
+            sampled_batches: 8,
+        };
+        plan.checkout.config.batch_size = 16;
+        plan.checkout.config.concurrency = 2;
+        plan.checkout.config.autotune = Some(bounds);
+        let stats = plan.apply_store(&DummyFileContentStore).await?;
+        assert_fs(tempdir.path(), &to)?;
+        assert_eq!(stats.removed(), 50);
+        assert_eq!(stats.updated(), 500);
+
+        let tuned = stats.tuned();
+        for phase in [tuned.remove, tuned.write] {
+            assert!((4..=64).contains(&phase.batch_size), "{:?}", tuned);
+            assert!((1..=8).contains(&phase.concurrency), "{:?}", tuned);
+        }
+        assert_eq!(tuned.meta.batch_size, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_store_fixed_tuning() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let vfs = VFS::new(tempdir.path().to_path_buf())?;
+        let to: Vec<_> = (0..VFS_BATCH_SIZE * 3)
+            .map(|i| (rp(&format!("new{}", i)), FileMetadata::regular(hgid(2))))
+            .collect();
+
+        let mut plan = make_plan(&vfs, &[], &to)?;
+        plan.checkout.config.batch_size = 30;
+        let stats = plan.apply_store(&DummyFileContentStore).await?;
+        assert_fs(tempdir.path(), &to)?;
+        assert_eq!(stats.fetch_batches(), VFS_BATCH_SIZE * 3 / 30);
+        let write = Tuned {
+            batch_size: 30,
+            concurrency: plan.checkout.config.concurrency,
+        };
+        assert_eq!(stats.tuned().write, write);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_matches_resumed_apply() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path.clone())?;
+        let progress_path = tempdir.path().join("updateprogress");
+        let mut to: Vec<_> = (0..VFS_BATCH_SIZE * 2)
+            .map(|i| {
+                (
+                    rp(&format!("dir/file{}", i)),
+                    FileMetadata::regular(hgid(1)),
+                )
+            })
+            .collect();
+        to.push((rp("fault/target"), FileMetadata::regular(hgid(2))));
+
+        // Interrupt a checkout after some files were written.
+        let mut plan = make_plan(&vfs, &[], &to)?;
+        plan.add_progress(&progress_path)?;
+        fail::cfg("checkout-write-file", "return(fault/target)").map_err(|e| anyhow!(e))?;
+        let result = plan.apply_store(&DummyFileContentStore).await;
+        fail::remove("checkout-write-file");
+        assert!(result.is_err());
+        let written = CheckoutProgress::load(&progress_path, vfs.clone(), ProgressSync::Batch)?
+            .state
+            .len();
+
+        // The dry run only counts the files left to write, unless asked for
+        // all of them, and these are exactly the files fetched when resuming.
+        let mut plan = make_plan(&vfs, &[], &to)?;
+        plan.add_progress(&progress_path)?;
+        let (count, size) = plan
+            .apply_store_dry_run(&DummyFileContentStore, false)
+            .await?;
+        let all = plan
+            .apply_store_dry_run(&DummyFileContentStore, true)
+            .await?;
+        assert_eq!(all.0, to.len());
+        assert_eq!(count, to.len() - written);
+
+        let store = RecordingFileContentStore::default();
+        plan.apply_store(&store).await?;
+        assert_eq!(store.fetched(), (count, size));
+        assert_fs(&working_path, &to)
+    }
+
+    #[tokio::test]
+    async fn test_apply_store_retries_transient_errors() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path.clone())?;
+        let to = [(rp("flaky/file"), FileMetadata::regular(hgid(1)))];
+        let retry_policy = |retries| RetryPolicy {
+            retries,
+            backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+
+        // Fails twice, then succeeds on the second retry.
+        let mut plan = make_plan(&vfs, &[], &to)?;
+        plan.checkout.config.retry_policy = retry_policy(3);
+        fail::cfg("async-vfs-write-transient", "return(flaky/file:2)").map_err(|e| anyhow!(e))?;
+        let result = plan.apply_store(&DummyFileContentStore).await;
+        fail::remove("async-vfs-write-transient");
+        let stats = result?;
+        assert_eq!(stats.updated.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.retried(), 1);
+        assert_eq!(stats.failed_after_retries(), 0);
+        assert_fs(&working_path, &to)?;
+
+        // Not enough retries, the checkout fails. Failures are counted per
+        // path, so use another one.
+        let to = [(rp("flaky/other"), FileMetadata::regular(hgid(1)))];
+        let tempdir = tempfile::tempdir()?;
+        let vfs = VFS::new(tempdir.path().to_path_buf())?;
+        let mut plan = make_plan(&vfs, &[], &to)?;
+        plan.checkout.config.retry_policy = retry_policy(1);
+        fail::cfg("async-vfs-write-transient", "return(flaky/other:2)").map_err(|e| anyhow!(e))?;
+        let stats = CheckoutStats::default();
+        let result = plan
+            .apply_store_with_stats(&DummyFileContentStore, &stats)
+            .await;
+        fail::remove("async-vfs-write-transient");
+        match result {
+            Err(CheckoutError::Write { path, .. }) => assert_eq!(path, rp("flaky/other")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(stats.retried(), 0);
+        assert_eq!(stats.failed_after_retries(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_store_shared_vfs_writer() -> Result<()> {
+        static SPAWNED: AtomicUsize = AtomicUsize::new(0);
+        let counting_spawn = |vfs, workers, policy, stats| {
+            SPAWNED.fetch_add(1, Ordering::Relaxed);
+            AsyncVfsWriter::spawn_with_retry(vfs, workers, policy, stats)
+        };
+
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path.clone())?;
+        let a = [(rp("a"), FileMetadata::regular(hgid(1)))];
+        let b = [
+            (rp("b"), FileMetadata::regular(hgid(2))),
+            (rp("dir/c"), FileMetadata::executable(hgid(1))),
+        ];
+
+        // Plans applied through a Checkout with a writer all use it.
+        let writer = Arc::new(AsyncVfsWriter::spawn_new(vfs.clone(), 2));
+        let mut checkout = Checkout::default_config(vfs.clone()).with_vfs_writer(writer);
+        checkout.spawn_vfs_writer = counting_spawn;
+        make_plan_with(&checkout, &[], &a)?
+            .apply_store(&DummyFileContentStore)
+            .await?;
+        assert_fs(&working_path, &a)?;
+        make_plan_with(&checkout, &a, &b)?
+            .apply_store(&DummyFileContentStore)
+            .await?;
+        assert_fs(&working_path, &b)?;
+        assert_eq!(SPAWNED.load(Ordering::Relaxed), 0);
+
+        // Otherwise each plan spawns its own.
+        let mut checkout = Checkout::default_config(vfs.clone());
+        checkout.spawn_vfs_writer = counting_spawn;
+        for (from, to) in [(&b[..], &a[..]), (&a[..], &b[..])] {
+            make_plan_with(&checkout, from, to)?
+                .apply_store(&DummyFileContentStore)
+                .await?;
+            assert_fs(&working_path, to)?;
+        }
+        assert_eq!(SPAWNED.load(Ordering::Relaxed), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_store_content_transformer() -> Result<()> {
+        /// Uppercases contents, and appends `!` so sizes change too.
+        struct Shout;
+        impl ContentTransformer for Shout {
+            fn transform(
+                &self,
+                _path: &RepoPath,
+                data: Bytes,
+                _flag: Option<UpdateFlag>,
+            ) -> Result<TransformOutcome> {
+                let mut data = data.to_ascii_uppercase();
+                data.push(b'!');
+                Ok(TransformOutcome::Inline(data.into()))
+            }
+        }
+        let shout = |hgid: &HgId| {
+            let mut data = hgid_file(hgid).to_ascii_uppercase();
+            data.push(b'!');
+            data
+        };
+
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path.clone())?;
+        let checkout =
+            Checkout::default_config(vfs.clone()).with_content_transformer(Arc::new(Shout));
+        let progress_path = tempdir.path().join("updateprogress");
+
+        // Written files and the progress file have the transformed content.
+        let to = [
+            (rp("a"), FileMetadata::regular(hgid(1))),
+            (rp("dir/b"), FileMetadata::executable(hgid(2))),
+        ];
+        let mut plan = make_plan_with(&checkout, &[], &to)?;
+        plan.add_progress(&progress_path)?;
+        let stats = plan.apply_store(&DummyFileContentStore).await?;
+        let mut written = 0;
+        for (path, meta) in &to {
+            let expected = shout(&meta.hgid);
+            assert_eq!(vfs.read(path)?.as_ref(), &expected[..]);
+            written += expected.len();
+        }
+        assert_eq!(stats.written_bytes.load(Ordering::Relaxed), written);
+        let progress = CheckoutProgress::load(&progress_path, vfs.clone(), ProgressSync::Batch)?;
+        for (path, meta) in &to {
+            let (_, _, size) = progress.state[path];
+            assert_eq!(size, shout(&meta.hgid).len() as u64);
+        }
+
+        // So resuming skips them.
+        let mut plan = make_plan_with(&checkout, &[], &to)?;
+        plan.add_progress(&progress_path)?;
+        assert!(plan.filtered_update_content.is_empty());
+
+        // Untracked files are compared with the transformed content.
+        let new = [
+            (rp("same"), FileMetadata::regular(hgid(3))),
+            (rp("untransformed"), FileMetadata::regular(hgid(4))),
+        ];
+        vfs.write(&rp("same"), &shout(&hgid(3)), UpdateFlag::Regular)?;
+        vfs.write(
+            &rp("untransformed"),
+            &hgid_file(&hgid(4)),
+            UpdateFlag::Regular,
+        )?;
+        let status = StatusBuilder::new()
+            .unknown(vec![rp("same"), rp("untransformed")])
+            .build();
+        let (mut tree_state, _) = TreeState::new(tempdir.path(), vfs.case_sensitive())?;
+        let all: Vec<_> = to.iter().chain(new.iter()).cloned().collect();
+        let manifest =
+            make_tree_manifest_from_meta(Arc::new(TestStore::new()), all.iter().cloned());
+        let plan = make_plan_with(&checkout, &to, &all)?;
+        let unknowns = plan
+            .check_unknown_files(&manifest, &DummyFileContentStore, &mut tree_state, &status)
+            .await?;
+        assert_eq!(unknowns, vec![rp("untransformed")]);
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    #[tokio::test]
+    async fn test_apply_store_removes_read_only_files() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path.clone())?;
+        let from = [
+            (rp("dir/generated"), FileMetadata::regular(hgid(1))),
+            (rp("dir/kept"), FileMetadata::regular(hgid(1))),
+        ];
+        let to = [(rp("dir/kept"), FileMetadata::regular(hgid(1)))];
+        for (path, _) in &from {
+            vfs.write(path, &hgid_file(&hgid(1)), UpdateFlag::Regular)?;
+        }
+        let generated = working_path.join("dir").join("generated");
+        let mut permissions = std::fs::metadata(&generated)?.permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&generated, permissions)?;
+
+        let plan = make_plan(&vfs, &from, &to)?;
+        let stats = plan.apply_store(&DummyFileContentStore).await?;
+        assert_eq!(stats.removed.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.permission_fixups(), 1);
+        assert_fs(&working_path, &to)
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_apply_store_fixes_parent_dir_permissions() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path.clone())?;
+        let from = [
+            (rp("dir/generated"), FileMetadata::regular(hgid(1))),
+            (rp("dir/kept"), FileMetadata::regular(hgid(1))),
+        ];
+        let to = [(rp("dir/kept"), FileMetadata::regular(hgid(1)))];
+        for (path, _) in &from {
+            vfs.write(path, &hgid_file(&hgid(1)), UpdateFlag::Regular)?;
+        }
+        let dir = working_path.join("dir");
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o555))?;
+        if File::create(dir.join("probe")).is_ok() {
+            // Permissions are not enforced, for example for root.
+            return Ok(());
+        }
+
+        // Permissions are left alone unless the flag is set.
+        let plan = make_plan(&vfs, &from, &to)?;
+        let stats = CheckoutStats::default();
+        let result = plan
+            .apply_store_with_stats(&DummyFileContentStore, &stats)
+            .await;
+        match result {
+            Err(CheckoutError::Remove { path, .. }) => assert_eq!(path, rp("dir/generated")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(stats.permission_fixups(), 0);
+
+        let mut plan = make_plan(&vfs, &from, &to)?;
+        plan.checkout.config.retry_policy.fix_parent_dir_permissions = true;
+        let stats = plan.apply_store(&DummyFileContentStore).await?;
+        assert_eq!(stats.removed.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.permission_fixups(), 1);
+        assert_eq!(std::fs::metadata(&dir)?.permissions().mode() & 0o777, 0o755);
+        assert_fs(&working_path, &to)
+    }
+
+    #[tokio::test]
+    async fn test_apply_store_prunes_empty_dirs() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path.clone())?;
+        let from: Vec<_> = [
+            "a/b/c/1", "a/b/c/2", "a/b/d/1", "a/e/1", "a/2", "keep/f/1", "keep/2", "top",
+        ]
+        .iter()
+        .map(|path| (rp(path), FileMetadata::regular(hgid(1))))
+        .collect();
+        let to = [(rp("top"), FileMetadata::regular(hgid(1)))];
+        for (path, _) in &from {
+            vfs.write(path, &hgid_file(&hgid(1)), UpdateFlag::Regular)?;
+        }
+        let untracked = working_path.join("keep").join("untracked");
+        std::fs::write(&untracked, b"untracked")?;
+
+        let plan = make_plan(&vfs, &from, &to)?;
+        let stats = plan.apply_store(&DummyFileContentStore).await?;
+        assert_eq!(stats.removed.load(Ordering::Relaxed), 7);
+
+        // Directories emptied by the checkout are removed, nested ones too,
+        // but the untracked file keeps its directory alive.
+        assert!(!working_path.join("a").exists());
+        assert!(!working_path.join("keep").join("f").exists());
+        assert!(untracked.exists());
+        std::fs::remove_file(&untracked)?;
+        std::fs::remove_dir(working_path.join("keep"))?;
+        assert_fs(&working_path, &to)
+    }
+
+    #[tokio::test]
+    async fn test_estimated_write_size() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let vfs = VFS::new(tempdir.path().to_path_buf())?;
+        let progress_path = tempdir.path().join("updateprogress");
+        let to = [
+            (rp("a"), FileMetadata::regular(hgid(1))),
+            (rp("b"), FileMetadata::regular(hgid(2))),
+            (rp("c"), FileMetadata::regular(hgid(3))),
+        ];
+        let file_size = hgid_file(&hgid(1)).len() as u64;
+
+        // Content lengths are summed up if the store has no size metadata.
+        let plan = make_plan(&vfs, &[], &to)?;
+        assert_eq!(
+            plan.estimated_write_size(&DummyFileContentStore).await?,
+            3 * file_size
+        );
+        assert_eq!(
+            plan.estimated_write_size(&SizeOnlyFileContentStore).await?,
+            3 * SIZE_ONLY_FILE_SIZE
+        );
+
+        // Files already written according to the progress file don't count.
+        let mut plan = make_plan(&vfs, &[], &to[..1])?;
+        plan.add_progress(&progress_path)?;
+        plan.apply_store(&DummyFileContentStore).await?;
+        let mut plan = make_plan(&vfs, &[], &to)?;
+        plan.add_progress(&progress_path)?;
+        assert_eq!(
+            plan.estimated_write_size(&DummyFileContentStore).await?,
+            2 * file_size
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_store_check_disk_space() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path.clone())?;
+        let from = [(rp("old"), FileMetadata::regular(hgid(1)))];
+        let to = [
+            (rp("a"), FileMetadata::regular(hgid(2))),
+            (rp("b"), FileMetadata::regular(hgid(3))),
+        ];
+        let plan = make_plan(&vfs, &[], &from)?;
+        plan.apply_store(&DummyFileContentStore).await?;
+
+        // Not enough space: nothing is removed or written.
+        let mut plan = make_plan(&vfs, &from, &to)?;
+        plan.checkout.config.check_disk_space = true;
+        plan.checkout.available_space = |_| Ok(DISK_SPACE_MARGIN);
+        match plan.apply_store(&SizeOnlyFileContentStore).await {
+            Err(CheckoutError::InsufficientDiskSpace { needed, available }) => {
+                assert_eq!(
+                    needed,
+                    2 * SIZE_ONLY_FILE_SIZE + 2 * SIZE_ONLY_FILE_SIZE / 10 + DISK_SPACE_MARGIN
+                );
+                assert_eq!(available, DISK_SPACE_MARGIN);
+            }
+            Err(e) => return Err(e.into()),
+            Ok(_) => panic!("checkout should fail"),
+        }
+        assert_fs(&working_path, &from)?;
+
+        // Enough space.
+        plan.checkout.available_space = |_| Ok(u64::MAX);
+        plan.apply_store(&DummyFileContentStore).await?;
+        assert_fs(&working_path, &to)
+    }
+
+    #[tokio::test]
+    async fn test_apply_store_check_path_lengths() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path.clone())?;
+        let from = [(rp("old"), FileMetadata::regular(hgid(1)))];
+        let to = [
+            (rp("abcde"), FileMetadata::regular(hgid(2))),
+            (rp("d/abc"), FileMetadata::regular(hgid(3))),
+            (rp("d/abcd"), FileMetadata::regular(hgid(4))),
+        ];
+        let plan = make_plan(&vfs, &[], &from)?;
+        plan.apply_store(&DummyFileContentStore).await?;
+
+        // Paths of up to 5 bytes in the working copy fit.
+        let limit = vfs.path_len(&rp("abcde"));
+        let mut plan = make_plan(&vfs, &from, &to)?;
+        plan.checkout.max_path_len = limit;
+        let expected = vec![LongPath {
+            path: rp("d/abcd"),
+            len: limit + 1,
+            limit,
+        }];
+        assert_eq!(plan.check_path_lengths(), expected);
+
+        // Nothing is removed or written.
+        match plan.apply_store(&DummyFileContentStore).await {
+            Err(CheckoutError::PathsTooLong(long_paths)) => assert_eq!(long_paths, expected),
+            Err(e) => return Err(e.into()),
+            Ok(_) => panic!("checkout should fail"),
+        }
+        assert_fs(&working_path, &from)?;
+
+        plan.checkout.config.allow_long_paths = true;
+        plan.apply_store(&DummyFileContentStore).await?;
+        assert_fs(&working_path, &to)
+    }
+
+    #[tokio::test]
+    async fn test_check_unknown_files_store_parity() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path)?;
+        let to = [
+            (rp("same"), FileMetadata::regular(hgid(1))),
+            (rp("differs"), FileMetadata::regular(hgid(2))),
+            (rp("missing"), FileMetadata::regular(hgid(3))),
+            (rp("dir/same"), FileMetadata::regular(hgid(4))),
+            (rp("dir/differs"), FileMetadata::regular(hgid(5))),
+        ];
+        // Untracked files that the checkout would overwrite.
+        vfs.write(&rp("same"), &hgid_file(&hgid(1)), UpdateFlag::Regular)?;
+        vfs.write(&rp("differs"), b"local changes", UpdateFlag::Regular)?;
+        vfs.write(&rp("dir/same"), &hgid_file(&hgid(4)), UpdateFlag::Regular)?;
+        vfs.write(&rp("dir/differs"), b"local changes", UpdateFlag::Regular)?;
+        let status = StatusBuilder::new()
+            .unknown(vec![
+                rp("same"),
+                rp("differs"),
+                rp("dir/same"),
+                rp("dir/differs"),
+            ])
+            .build();
+        let (mut tree_state, _) = TreeState::new(tempdir.path(), vfs.case_sensitive())?;
+        let manifest = make_tree_manifest_from_meta(Arc::new(TestStore::new()), to.iter().cloned());
+        let plan = make_plan(&vfs, &[], &to)?;
+
+        let batched = BatchedFileContentStore { batch_size: 2 };
+        let stores: [&dyn ReadFileContents<Error = anyhow::Error>; 2] =
+            [&DummyFileContentStore, &batched];
+        let mut results = vec![];
+        for store in stores {
+            let mut unknowns = plan
+                .check_unknown_files(&manifest, store, &mut tree_state, &status)
+                .await?;
+            unknowns.sort();
+            results.push(unknowns);
+        }
+
+        assert_eq!(results[0], vec![rp("differs"), rp("dir/differs")]);
+        assert_eq!(results[0], results[1]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_conflict_report() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path)?;
+        let from = [
+            (rp("modified"), FileMetadata::regular(hgid(1))),
+            (rp("removed"), FileMetadata::regular(hgid(2))),
+            (rp("deleted"), FileMetadata::regular(hgid(3))),
+            (rp("clean"), FileMetadata::regular(hgid(4))),
+        ];
+        let to = [
+            (rp("modified"), FileMetadata::regular(hgid(5))),
+            (rp("removed"), FileMetadata::regular(hgid(6))),
+            (rp("deleted"), FileMetadata::regular(hgid(7))),
+            (rp("clean"), FileMetadata::regular(hgid(8))),
+            (rp("same"), FileMetadata::regular(hgid(9))),
+            (rp("differs"), FileMetadata::regular(hgid(10))),
+        ];
+        roll_out_fs(&vfs, &from)?;
+        vfs.write(&rp("modified"), b"local changes", UpdateFlag::Regular)?;
+        vfs.remove(&rp("removed"))?;
+        vfs.remove(&rp("deleted"))?;
+        vfs.write(&rp("same"), &hgid_file(&hgid(9)), UpdateFlag::Regular)?;
+        vfs.write(&rp("differs"), b"local changes", UpdateFlag::Regular)?;
+        let status = StatusBuilder::new()
+            .modified(vec![rp("modified")])
+            .removed(vec![rp("removed")])
+            .deleted(vec![rp("deleted")])
+            .unknown(vec![rp("same"), rp("differs")])
+            .build();
+        let (mut tree_state, _) = TreeState::new(tempdir.path(), vfs.case_sensitive())?;
+        let manifest = make_tree_manifest_from_meta(Arc::new(TestStore::new()), to.iter().cloned());
+        let plan = make_plan(&vfs, &from, &to)?;
+
+        let report = plan
+            .conflict_report(&status, &manifest, &DummyFileContentStore, &mut tree_state)
+            .await?;
+        assert_eq!(
+            report,
+            ConflictReport {
+                conflicts: vec![
+                    (rp("deleted"), ConflictKind::RemovedLocally),
+                    (rp("differs"), ConflictKind::UnknownBlocking),
+                    (rp("modified"), ConflictKind::ModifiedTracked),
+                    (rp("removed"), ConflictKind::RemovedLocally),
+                    (rp("same"), ConflictKind::UnknownSameContent),
+                ],
+                compared_by_size: false,
+            }
+        );
+        assert!(report.is_blocking());
+        assert_eq!(
+            report
+                .paths(ConflictKind::RemovedLocally)
+                .collect::<Vec<_>>(),
+            vec![&rp("deleted"), &rp("removed")]
+        );
+
+        // Only untracked files with the same content don't block.
+        let status = StatusBuilder::new().unknown(vec![rp("same")]).build();
+        let report = plan
+            .conflict_report(&status, &manifest, &DummyFileContentStore, &mut tree_state)
+            .await?;
+        assert_eq!(
+            report.conflicts,
+            vec![(rp("same"), ConflictKind::UnknownSameContent)]
+        );
+        assert!(!report.is_blocking());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_changed_unknown_file_sizes() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let vfs = VFS::new(tempdir.path().to_path_buf())?;
+        let plan = CheckoutPlan::empty(vfs.clone());
+        let same_size = vec![b'x'; SIZE_ONLY_FILE_SIZE as usize];
+        vfs.write(&rp("same_size"), &same_size, UpdateFlag::Regular)?;
+        vfs.write(&rp("other_size"), b"x", UpdateFlag::Regular)?;
+        let keys = vec![
+            Key::new(rp("same_size"), hgid(1)),
+            Key::new(rp("other_size"), hgid(2)),
+        ];
+
+        let changed = plan
+            .changed_unknown_file_sizes(&SizeOnlyFileContentStore, &keys)
+            .await?;
+        assert_eq!(changed, vec![rp("other_size")]);
+
+        // Without sizes in the store, all files are assumed to differ.
+        let changed = plan
+            .changed_unknown_file_sizes(&DummyFileContentStore, &keys)
+            .await?;
+        assert_eq!(changed, vec![rp("same_size"), rp("other_size")]);
+        Ok(())
+    }
 
-fn type_to_flag(ft: &FileType) -> UpdateFlag {
-    match ft {
-        FileType::Regular => UpdateFlag::Regular,
-        FileType::Executable => UpdateFlag::Executable,
-        FileType::Symlink => UpdateFlag::Symlink,
-        FileType::GitSubmodule => {
-            panic!("bug: GitSubmodule should be filtered out earlier by ActionMap")
-        }
-    }
-}
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_verify_applied() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
 
-impl UpdateContentAction {
-    pub fn new(path: RepoPathBuf, meta: FileMetadata, new_file: bool) -> Self {
-        Self {
-            path,
-            content_hgid: meta.hgid,
-            file_type: meta.file_type,
-            new_file,
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path.clone())?;
+        let from = [
+            (rp("a"), FileMetadata::regular(hgid(1))),
+            (rp("removed"), FileMetadata::regular(hgid(2))),
+            (rp("x"), FileMetadata::regular(hgid(3))),
+        ];
+        let to = [
+            (rp("a"), FileMetadata::regular(hgid(4))),
+            (rp("added"), FileMetadata::regular(hgid(5))),
+            (rp("x"), FileMetadata::executable(hgid(3))),
+        ];
+        roll_out_fs(&vfs, &from)?;
+        let plan = make_plan(&vfs, &from, &to)?;
+        plan.apply_store(&DummyFileContentStore).await?;
+        let (mut tree_state, _) = TreeState::new(tempdir.path(), vfs.case_sensitive())?;
+        record_updates(&plan, &vfs, &mut tree_state)?;
+        assert_eq!(verify_applied(&plan, &vfs, &mut tree_state)?, vec![]);
+
+        // Break the state of every file of the plan after it was recorded.
+        vfs.write(&rp("removed"), b"back", UpdateFlag::Regular)?;
+        let mut data = hgid_file(&hgid(4));
+        data.push(b'\n');
+        vfs.write(&rp("a"), &data, UpdateFlag::Regular)?;
+        tree_state.remove(rp("added"))?;
+        std::fs::set_permissions(
+            working_path.join("x"),
+            std::fs::Permissions::from_mode(0o644),
+        )?;
+
+        let mut violations = verify_applied(&plan, &vfs, &mut tree_state)?;
+        violations.sort_by(|a, b| a.path.cmp(&b.path));
+        let violation = |path: &str, kind| InvariantViolation {
+            path: rp(path),
+            kind,
+        };
+        assert_eq!(
+            violations[..4],
+            [
+                violation(
+                    "a",
+                    ViolationKind::SizeMismatch {
+                        recorded: 40,
+                        actual: 41
+                    }
+                ),
+                violation("added", ViolationKind::NotRecorded),
+                violation("removed", ViolationKind::NotRemoved),
+                violation(
+                    "x",
+                    ViolationKind::TypeMismatch {
+                        expected: FileType::Executable
+                    }
+                ),
+            ]
+        );
+        match &violations[4..] {
+            [InvariantViolation {
+                path,
+                kind: ViolationKind::ModeMismatch { recorded, actual },
+            }] => {
+                assert_eq!(path, &rp("x"));
+                assert_ne!(recorded & 0o111, 0);
+                assert_eq!(actual & 0o777, 0o644);
+            }
+            violations => panic!("unexpected violations: {:?}", violations),
         }
+        Ok(())
     }
 
-    pub fn make_key(&self) -> Key {
-        Key::new(self.path.clone(), self.content_hgid)
-    }
-}
+    #[tokio::test]
+    async fn test_scoped_revert() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path)?;
+        let parent = [
+            (rp("reverted"), FileMetadata::regular(hgid(1))),
+            (rp("modified"), FileMetadata::regular(hgid(2))),
+            (rp("dir/changed"), FileMetadata::regular(hgid(3))),
+            (rp("file"), FileMetadata::regular(hgid(4))),
+        ];
+        let old = [
+            (rp("reverted"), FileMetadata::regular(hgid(5))),
+            (rp("modified"), FileMetadata::regular(hgid(6))),
+            (rp("dir/changed"), FileMetadata::regular(hgid(7))),
+            (rp("file/became_dir"), FileMetadata::regular(hgid(8))),
+        ];
+        roll_out_fs(&vfs, &parent)?;
+        vfs.write(&rp("modified"), b"local changes", UpdateFlag::Regular)?;
+        vfs.write(&rp("dir/changed"), b"local changes", UpdateFlag::Regular)?;
 
-impl AsRef<RepoPath> for UpdateContentAction {
-    fn as_ref(&self) -> &RepoPath {
-        &self.path
+        let store = Arc::new(TestStore::new());
+        let matcher = AlwaysMatcher::new();
+        let parent_tree = make_tree_manifest_from_meta(store.clone(), parent.iter().cloned());
+        let old_tree = make_tree_manifest_from_meta(store, old.iter().cloned());
+        let diff = Diff::new(&parent_tree, &old_tree, &matcher)?;
+        let actions =
+            ActionMap::from_diff_for_paths(diff, &[rp("reverted"), rp("file/became_dir")])?;
+        let plan = Checkout::default_config(vfs.clone()).plan_action_map(actions)?;
+        assert!(plan.scoped());
+        // "file" is outside of the paths, so "file/became_dir" can't be
+        // written.
+        assert_eq!(
+            plan.to_string(),
+            format!(
+                "scope file/became_dir\nscope reverted\nup reverted=>{}\n",
+                hgid(5)
+            )
+        );
+        assert_eq!(plan.stats(), (1, 0));
+
+        // Local changes outside of the paths are not conflicts.
+        let status = StatusBuilder::new()
+            .modified(vec![rp("modified"), rp("dir/changed")])
+            .build();
+        assert!(plan.check_conflicts(&status).is_empty());
+
+        plan.apply_store(&DummyFileContentStore).await?;
+        assert_eq!(vfs.read(&rp("reverted"))?, hgid_file(&hgid(5)));
+        assert_eq!(vfs.read(&rp("modified"))?, b"local changes");
+        assert_eq!(vfs.read(&rp("dir/changed"))?, b"local changes");
+        assert_eq!(vfs.read(&rp("file"))?, hgid_file(&hgid(4)));
+        Ok(())
     }
-}
 
-impl AsRef<RepoPath> for UpdateMetaAction {
-    fn as_ref(&self) -> &RepoPath {
-        &self.path
-    }
-}
+    #[test]
+    fn test_summarize_by_prefix() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let vfs = VFS::new(tempdir.path().to_path_buf())?;
+        let mut plan = CheckoutPlan::empty(vfs);
+        plan.remove = vec![rp("third_party/a/x"), rp("third_party/b/y"), rp("src/z")];
+        for path in ["third_party/a/w", "src/main.rs", "README"] {
+            let meta = FileMetadata::regular(hgid(1));
+            plan.update_content
+                .push(UpdateContentAction::new(rp(path), meta, false));
+        }
+        plan.update_meta.push(UpdateMetaAction {
+            path: rp("third_party/b/bin"),
+            set_x_flag: true,
+        });
 
-#[cfg(test)]
-// todo - consider moving some of this code to vfs / separate test create
-// todo parallel execution for the test
-mod test {
-    use std::collections::HashMap;
-    use std::fs::create_dir;
-    use std::path::Path;
+        let summary = |prefix: &str, removed, updated, meta| PrefixSummary {
+            prefix: rp(prefix),
+            removed,
+            updated,
+            meta,
+        };
 
-    use anyhow::ensure;
-    use anyhow::Context;
-    use futures::stream::BoxStream;
-    use manifest_tree::testutil::make_tree_manifest_from_meta;
-    use manifest_tree::testutil::TestStore;
-    use manifest_tree::Diff;
-    use pathmatcher::AlwaysMatcher;
-    use quickcheck::Arbitrary;
-    use quickcheck::Gen;
-    use tempfile::TempDir;
-    use types::testutil::generate_repo_paths;
-    use walkdir::DirEntry;
-    use walkdir::WalkDir;
+        let expected = vec![
+            summary("third_party", 2, 1, 1),
+            summary("src", 1, 1, 0),
+            summary("README", 0, 1, 0),
+        ];
+        assert_eq!(plan.summarize_by_prefix(1), expected);
+
+        // Ties are sorted by prefix.
+        let expected = vec![
+            summary("third_party/a", 1, 1, 0),
+            summary("third_party/b", 1, 0, 1),
+            summary("README", 0, 1, 0),
+            summary("src/main.rs", 0, 1, 0),
+            summary("src/z", 1, 0, 0),
+        ];
+        assert_eq!(plan.summarize_by_prefix(2), expected);
+        assert_eq!(plan.summarize_by_prefix(2), expected);
+
+        assert_eq!(plan.summarize_by_prefix(0), vec![summary("", 3, 3, 1)]);
+
+        assert_eq!(
+            plan.summarize_by_prefix_json(1)[0],
+            serde_json::json!({"prefix": "third_party", "removed": 2, "updated": 1, "meta": 1})
+        );
 
-    use super::*;
+        // Prefixes with less than 1% of the files are merged in the tail.
+        plan.remove
+            .extend((0..200).map(|i| rp(&format!("third_party/c/{}", i))));
+        let expected = vec![summary("third_party", 202, 1, 1), summary("", 1, 2, 0)];
+        assert_eq!(plan.summarize_by_prefix(1), expected);
+        Ok(())
+    }
 
-    #[tokio::test]
-    async fn test_basic_checkout() -> Result<()> {
-        // Pattern - lowercase_path_[hgid!=1]_[flags!=normal]
-        let a = (rp("A"), FileMetadata::regular(hgid(1)));
-        let a_2 = (rp("A"), FileMetadata::regular(hgid(2)));
-        let a_e = (rp("A"), FileMetadata::executable(hgid(1)));
-        let a_s = (rp("A"), FileMetadata::symlink(hgid(1)));
-        let b = (rp("B"), FileMetadata::regular(hgid(1)));
-        let ab = (rp("A/B"), FileMetadata::regular(hgid(1)));
-        let cd = (rp("C/D"), FileMetadata::regular(hgid(1)));
+    #[test]
+    fn test_coalesce_removes() -> Result<()> {
+        let update =
+            |path: &str| UpdateContentAction::new(rp(path), FileMetadata::regular(hgid(1)), false);
+        let update_content = vec![update("a"), update("dir/Foo")];
+
+        let mut remove = vec![rp("dir/foo"), rp("b"), rp("a")];
+        let coalesced = coalesce_removes(&mut remove, &update_content, &[], true)?;
+        assert_eq!(remove, vec![rp("dir/foo"), rp("b")]);
+        assert_eq!(
+            coalesced,
+            vec![CoalescedRemove {
+                removed: rp("a"),
+                updated: rp("a"),
+            }]
+        );
 
-        // update file
-        assert_checkout(&[a.clone()], &[a_2.clone()]).await?;
-        // mv file
-        assert_checkout(&[a.clone()], &[b.clone()]).await?;
-        // add / rm file
-        assert_checkout_symmetrical(&[a.clone()], &[a.clone(), b.clone()]).await?;
-        // regular<->exec
-        assert_checkout_symmetrical(&[a.clone()], &[a_e.clone()]).await?;
-        // regular<->symlink
-        assert_checkout_symmetrical(&[a.clone()], &[a_s.clone()]).await?;
-        // dir <-> file with the same name
-        assert_checkout_symmetrical(&[ab.clone()], &[a.clone()]).await?;
-        // create / rm dir
-        assert_checkout_symmetrical(&[ab.clone()], &[b.clone()]).await?;
-        // mv file between dirs
-        assert_checkout(&[ab.clone()], &[cd.clone()]).await?;
+        // A case-only rename on a case-insensitive working copy.
+        let mut remove = vec![rp("dir/foo"), rp("b"), rp("a")];
+        let coalesced = coalesce_removes(&mut remove, &update_content, &[], false)?;
+        assert_eq!(remove, vec![rp("b")]);
+        assert_eq!(
+            coalesced,
+            vec![
+                CoalescedRemove {
+                    removed: rp("a"),
+                    updated: rp("a"),
+                },
+                CoalescedRemove {
+                    removed: rp("dir/foo"),
+                    updated: rp("dir/Foo"),
+                },
+            ]
+        );
 
+        let update_meta = vec![UpdateMetaAction {
+            path: rp("B"),
+            set_x_flag: true,
+        }];
+        let mut remove = vec![rp("b")];
+        assert!(coalesce_removes(&mut remove, &update_content, &update_meta, true).is_ok());
+        match coalesce_removes(&mut remove, &update_content, &update_meta, false) {
+            Err(CheckoutError::RemoveAndUpdateMeta { removed, updated }) => {
+                assert_eq!((removed, updated), (rp("b"), rp("B")));
+            }
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_checkout_generated() -> Result<()> {
-        let trees = generate_trees(6, 50);
-        for a in trees.iter() {
-            for b in trees.iter() {
-                if a == b {
-                    continue;
-                }
-                assert_checkout(a, b).await?;
+    async fn test_coalesced_plan_applies_like_naive() -> Result<()> {
+        let from = [
+            (rp("a"), FileMetadata::regular(hgid(1))),
+            (rp("b"), FileMetadata::regular(hgid(2))),
+            (rp("c"), FileMetadata::regular(hgid(3))),
+        ];
+        let to = [
+            (rp("a"), FileMetadata::symlink(hgid(4))),
+            (rp("b"), FileMetadata::executable(hgid(5))),
+        ];
+
+        for normalize in [false, true] {
+            let tempdir = tempfile::tempdir()?;
+            let vfs = VFS::new(tempdir.path().to_path_buf())?;
+            roll_out_fs(&vfs, &from)?;
+            let mut plan = make_plan(&vfs, &from, &to)?;
+            // Like a plan removing "a" and "b" before writing them.
+            plan.remove.extend([rp("a"), rp("b")]);
+            if normalize {
+                plan.coalesced = coalesce_removes(
+                    &mut plan.remove,
+                    &plan.update_content,
+                    &plan.update_meta,
+                    vfs.case_sensitive(),
+                )?;
+                let coalesced: Vec<_> = plan
+                    .coalesced_removes()
+                    .iter()
+                    .map(|c| &c.removed)
+                    .collect();
+                assert_eq!(coalesced, vec![&rp("a"), &rp("b")]);
+                assert_eq!(plan.removed_files().collect::<Vec<_>>(), vec![&rp("c")]);
+                assert!(plan.to_string().starts_with(
+                    "rm c
+up "
+                ));
+                assert_eq!(plan.stats(), (2, 1));
+            } else {
+                assert_eq!(plan.stats(), (2, 3));
             }
+            plan.apply_store(&DummyFileContentStore).await?;
+            assert_fs(tempdir.path(), &to)?;
         }
         Ok(())
     }
 
-    #[test]
-    fn test_progress_parsing() -> Result<()> {
-        let tempdir = tempfile::tempdir()?;
-        let working_path = tempdir.path().to_path_buf().join("workingdir");
-        create_dir(working_path.as_path()).unwrap();
-        let vfs = VFS::new(working_path.clone())?;
-        let path = tempdir.path().to_path_buf().join("updateprogress");
-        let mut progress = CheckoutProgress::new(&path, vfs.clone())?;
-        let file_path = RepoPathBuf::from_string("file".to_string())?;
-        vfs.write(file_path.as_repo_path(), &[0b0, 0b01], UpdateFlag::Regular)?;
-        let id = hgid(1);
-        progress.record_writes(vec![(id, file_path.clone())]);
+    fn make_plan(
+        vfs: &VFS,
+        from: &[(RepoPathBuf, FileMetadata)],
+        to: &[(RepoPathBuf, FileMetadata)],
+    ) -> Result<CheckoutPlan> {
+        make_plan_with(&Checkout::default_config(vfs.clone()), from, to)
+    }
 
-        let progress = CheckoutProgress::load(&path, vfs.clone())?;
-        assert_eq!(progress.state.len(), 1);
-        assert_eq!(progress.state.get(&file_path).unwrap().0, id);
-        Ok(())
+    fn make_plan_with(
+        checkout: &Checkout,
+        from: &[(RepoPathBuf, FileMetadata)],
+        to: &[(RepoPathBuf, FileMetadata)],
+    ) -> Result<CheckoutPlan> {
+        let store = Arc::new(TestStore::new());
+        let matcher = AlwaysMatcher::new();
+        let left_tree = make_tree_manifest_from_meta(store.clone(), from.iter().cloned());
+        let right_tree = make_tree_manifest_from_meta(store, to.iter().cloned());
+        let diff = Diff::new(&left_tree, &right_tree, &matcher)?;
+        Ok(checkout.plan_action_map(ActionMap::from_diff(diff)?)?)
     }
 
     fn generate_trees(tree_size: usize, count: usize) -> Vec<Vec<(RepoPathBuf, FileMetadata)>> {
@@ -904,16 +3733,26 @@ mod test {
         let right_tree = make_tree_manifest_from_meta(store, to.iter().cloned());
         let diff = Diff::new(&left_tree, &right_tree, &matcher).unwrap();
         let vfs = VFS::new(working_path.clone())?;
-        let checkout = Checkout::default_config(vfs);
+        let checkout = Checkout::default_config(vfs.clone());
         let plan = checkout
-            .plan_action_map(ActionMap::from_diff(diff).context("Plan construction failed")?);
+            .plan_action_map(ActionMap::from_diff(diff).context("Plan construction failed")?)?;
 
         // Use clean vfs for test
         plan.apply_store(&DummyFileContentStore)
             .await
             .context("Plan execution failed")?;
 
-        assert_fs(&working_path, to)
+        assert_fs(&working_path, to)?;
+
+        let (mut tree_state, _) = TreeState::new(tempdir.path(), vfs.case_sensitive())?;
+        record_updates(&plan, &vfs, &mut tree_state)?;
+        let violations = verify_applied(&plan, &vfs, &mut tree_state)?;
+        ensure!(
+            violations.is_empty(),
+            "Checkout invariants violated: {:?}",
+            violations
+        );
+        Ok(())
     }
 
     fn print_tree(t: &[(RepoPathBuf, FileMetadata)]) {
@@ -1058,6 +3897,202 @@ mod test {
         }
     }
 
+    /// Serves the same content as `DummyFileContentStore`, and counts the
+    /// fetched files and bytes.
+    #[derive(Default)]
+    struct RecordingFileContentStore {
+        fetched: Mutex<(usize, u64)>,
+    }
+
+    impl RecordingFileContentStore {
+        fn fetched(&self) -> (usize, u64) {
+            *self.fetched.lock()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl ReadFileContents for RecordingFileContentStore {
+        type Error = anyhow::Error;
+
+        async fn read_file_contents(&self, keys: Vec<Key>) -> BoxStream<Result<(Bytes, Key)>> {
+            let items: Vec<_> = keys
+                .into_iter()
+                .map(|key| -> Result<(Bytes, Key)> {
+                    let data: Bytes = hgid_file(&key.hgid).into();
+                    let mut fetched = self.fetched.lock();
+                    fetched.0 += 1;
+                    fetched.1 += data.len() as u64;
+                    Ok((data, key))
+                })
+                .collect();
+            stream::iter(items).boxed()
+        }
+
+        async fn read_rename_metadata(
+            &self,
+            _keys: Vec<Key>,
+        ) -> BoxStream<Result<(Key, Option<Key>), Self::Error>> {
+            stream::empty().boxed()
+        }
+    }
+
+    /// Serves the same content as `DummyFileContentStore`, but fetches keys in
+    /// batches and returns each batch out of request order, the way remote
+    /// stores do.
+    struct BatchedFileContentStore {
+        batch_size: usize,
+    }
+
+    #[async_trait::async_trait]
+    impl ReadFileContents for BatchedFileContentStore {
+        type Error = anyhow::Error;
+
+        async fn read_file_contents(&self, keys: Vec<Key>) -> BoxStream<Result<(Bytes, Key)>> {
+            let batches: Vec<Vec<Key>> = keys.chunks(self.batch_size).map(<[_]>::to_vec).collect();
+            stream::iter(batches)
+                .map(|batch| {
+                    stream::iter(batch.into_iter().rev())
+                        .map(|key| Ok((hgid_file(&key.hgid).into(), key)))
+                })
+                .flatten()
+                .boxed()
+        }
+
+        async fn read_rename_metadata(
+            &self,
+            _keys: Vec<Key>,
+        ) -> BoxStream<Result<(Key, Option<Key>), Self::Error>> {
+            stream::empty().boxed()
+        }
+    }
+
+    /// Serves the same content as `DummyFileContentStore`, except that the
+    /// fetch of the Nth requested key fails. The failing key is attached to
+    /// the error as context, the way stores identify failed keys.
+    struct FaultyFileContentStore {
+        fail_nth: usize,
+        failed_key: Mutex<Option<Key>>,
+    }
+
+    impl FaultyFileContentStore {
+        fn fail_nth_fetch(n: usize) -> Self {
+            Self {
+                fail_nth: n,
+                failed_key: Mutex::new(None),
+            }
+        }
+
+        fn failed_key(&self) -> Option<Key> {
+            self.failed_key.lock().clone()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl ReadFileContents for FaultyFileContentStore {
+        type Error = anyhow::Error;
+
+        async fn read_file_contents(&self, keys: Vec<Key>) -> BoxStream<Result<(Bytes, Key)>> {
+            let items: Vec<_> = keys
+                .into_iter()
+                .enumerate()
+                .map(|(idx, key)| -> Result<(Bytes, Key)> {
+                    if idx == self.fail_nth {
+                        *self.failed_key.lock() = Some(key.clone());
+                        Err(anyhow!("injected fetch failure").context(key))
+                    } else {
+                        Ok((hgid_file(&key.hgid).into(), key))
+                    }
+                })
+                .collect();
+            stream::iter(items).boxed()
+        }
+
+        async fn read_rename_metadata(
+            &self,
+            _keys: Vec<Key>,
+        ) -> BoxStream<Result<(Key, Option<Key>), Self::Error>> {
+            stream::empty().boxed()
+        }
+    }
+
+    /// Fails the fetches of the files in `failures` with a network error,
+    /// the given number of times each.
+    struct FlakyFileContentStore {
+        failures: Mutex<HashMap<RepoPathBuf, usize>>,
+    }
+
+    impl FlakyFileContentStore {
+        fn new(failures: &[(&str, usize)]) -> Self {
+            let failures = failures
+                .iter()
+                .map(|(path, count)| (rp(path), *count))
+                .collect();
+            Self {
+                failures: Mutex::new(failures),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl ReadFileContents for FlakyFileContentStore {
+        type Error = anyhow::Error;
+
+        async fn read_file_contents(&self, keys: Vec<Key>) -> BoxStream<Result<(Bytes, Key)>> {
+            let items: Vec<_> = keys
+                .into_iter()
+                .map(|key| -> Result<(Bytes, Key)> {
+                    match self.failures.lock().get_mut(&key.path) {
+                        Some(count) if *count > 0 => {
+                            *count -= 1;
+                            let err = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+                            Err(anyhow::Error::from(err).context(key))
+                        }
+                        _ => Ok((hgid_file(&key.hgid).into(), key)),
+                    }
+                })
+                .collect();
+            stream::iter(items).boxed()
+        }
+
+        async fn read_rename_metadata(
+            &self,
+            _keys: Vec<Key>,
+        ) -> BoxStream<Result<(Key, Option<Key>), Self::Error>> {
+            stream::empty().boxed()
+        }
+    }
+
+    /// Only serves file sizes from metadata, all of them `SIZE_ONLY_FILE_SIZE`.
+    struct SizeOnlyFileContentStore;
+
+    const SIZE_ONLY_FILE_SIZE: u64 = 1000;
+
+    #[async_trait::async_trait]
+    impl ReadFileContents for SizeOnlyFileContentStore {
+        type Error = anyhow::Error;
+
+        async fn read_file_contents(&self, keys: Vec<Key>) -> BoxStream<Result<(Bytes, Key)>> {
+            stream::iter(keys)
+                .map(|key| Err(anyhow!("unexpected content fetch").context(key)))
+                .boxed()
+        }
+
+        async fn read_rename_metadata(
+            &self,
+            _keys: Vec<Key>,
+        ) -> BoxStream<Result<(Key, Option<Key>), Self::Error>> {
+            stream::empty().boxed()
+        }
+
+        fn read_file_sizes(&self, keys: Vec<Key>) -> Option<BoxStream<Result<(u64, Key)>>> {
+            Some(
+                stream::iter(keys)
+                    .map(|key| Ok((SIZE_ONLY_FILE_SIZE, key)))
+                    .boxed(),
+            )
+        }
+    }
+
     fn hgid_file(hgid: &HgId) -> Vec<u8> {
         hgid.to_string().into_bytes()
     }
@@ -1065,6 +4100,15 @@ mod test {
 
 impl fmt::Display for CheckoutPlan {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(scope) = &self.scope {
+            for path in scope.paths() {
+                writeln!(f, "scope {}", path)?;
+            }
+        }
+        for c in &self.type_changes {
+            let to = if c.to_dir { "dir" } else { "file" };
+            writeln!(f, "retype {} => {}", c.path, to)?;
+        }
         for r in &self.remove {
             writeln!(f, "rm {}", r)?;
         }
@@ -1173,6 +4217,7 @@ pub fn checkout(
     // 4. Update the treestate parents, dirstate
     wc.set_parents(&mut [target_commit].iter())?;
     record_updates(&plan, &wc.vfs(), &mut wc.treestate().lock())?;
+    verify::verify_applied_if_enabled(&plan, &wc.vfs(), &mut wc.treestate().lock())?;
     dirstate::flush(
         wc.vfs().root(),
         &mut wc.treestate().lock(),
@@ -1250,7 +4295,7 @@ fn create_plan(
             actions.with_sparse_profile_change(old_sparse, new_sparse, current_mf, target_mf)?;
     }
     let checkout = Checkout::from_config(vfs.clone(), &config)?;
-    let plan = checkout.plan_action_map(actions);
+    let plan = checkout.plan_action_map(actions)?;
     // if let Some(progress_path) = progress_path {
     //     plan.add_progress(progress_path.as_path()).map_pyerr(py)?;
     // }
@@ -0,0 +1,60 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Events reported while applying a `CheckoutPlan`, see
+//! `CheckoutPlan::with_progress_reporter`.
+
+use std::sync::atomic::Ordering;
+
+use types::RepoPathBuf;
+
+use crate::CheckoutStats;
+
+/// Called with the events of a checkout as they happen, concurrently from
+/// the tasks applying the plan.
+pub type ProgressReporter = Box<dyn Fn(CheckoutEvent) + Send + Sync>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CheckoutEvent {
+    /// The file was written. `bytes` is the size of the written content,
+    /// after content transforms.
+    FileWritten { path: RepoPathBuf, bytes: u64 },
+    /// The file was removed.
+    FileRemoved { path: RepoPathBuf },
+    /// The exec flag of the file was set, or cleared.
+    ExecBitSet { path: RepoPathBuf, executable: bool },
+    /// Totals so far, reported after each batch of written or removed files,
+    /// and once applying ends, even if it failed.
+    Stats(CheckoutStatsSnapshot),
+}
+
+/// The totals of a `CheckoutStats` at some point.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CheckoutStatsSnapshot {
+    pub removed: usize,
+    pub updated: usize,
+    pub meta_updated: usize,
+    pub written_bytes: usize,
+}
+
+impl CheckoutStats {
+    pub fn snapshot(&self) -> CheckoutStatsSnapshot {
+        CheckoutStatsSnapshot {
+            removed: self.removed.load(Ordering::Relaxed),
+            updated: self.updated.load(Ordering::Relaxed),
+            meta_updated: self.meta_updated.load(Ordering::Relaxed),
+            written_bytes: self.written_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Reports the totals of `stats`, if there is a reporter.
+pub(crate) fn report_stats(reporter: Option<&ProgressReporter>, stats: &CheckoutStats) {
+    if let Some(reporter) = reporter {
+        reporter(CheckoutEvent::Stats(stats.snapshot()));
+    }
+}
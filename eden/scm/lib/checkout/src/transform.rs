@@ -0,0 +1,258 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Transforms of file contents between the store and the working copy, like
+//! converting line endings or materializing LFS pointers.
+
+use std::sync::Arc;
+
+use anyhow::bail;
+use anyhow::Result;
+use minibytes::Bytes;
+use types::RepoPath;
+use vfs::UpdateFlag;
+
+/// Transforms the content of files returned by the store into what is
+/// written to the working copy.
+///
+/// Transformers also apply when comparing files on disk with the store,
+/// so they must be deterministic.
+pub trait ContentTransformer: Send + Sync {
+    /// Transform `data` of `path`. `flag` is the type of the file, if known.
+    fn transform(
+        &self,
+        path: &RepoPath,
+        data: Bytes,
+        flag: Option<UpdateFlag>,
+    ) -> Result<TransformOutcome>;
+}
+
+/// Result of [`ContentTransformer::transform`].
+pub enum TransformOutcome {
+    /// The content to write.
+    Inline(Bytes),
+    /// The content must be fetched from another store, like LFS content
+    /// referred to by a pointer.
+    Fetch {
+        store: Arc<dyn SecondaryStore>,
+        reference: Bytes,
+    },
+}
+
+/// A store of contents that transformers refer to, like the LFS store.
+pub trait SecondaryStore: Send + Sync {
+    /// Fetch the content of `path` referred to by `reference`.
+    fn fetch(&self, path: &RepoPath, reference: &Bytes) -> Result<Bytes>;
+}
+
+/// Line endings of text files written to the working copy.
+/// `nativecheckout.eol`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EolPolicy {
+    /// Write files as they are in the store.
+    #[default]
+    AsIs,
+    /// Convert CRLF line endings to LF.
+    Lf,
+    /// Convert LF line endings to CRLF.
+    Crlf,
+    /// CRLF on Windows, and as is elsewhere.
+    Native,
+}
+
+impl std::str::FromStr for EolPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "asis" => Ok(EolPolicy::AsIs),
+            "lf" => Ok(EolPolicy::Lf),
+            "crlf" => Ok(EolPolicy::Crlf),
+            "native" => Ok(EolPolicy::Native),
+            _ => bail!("expected 'asis', 'lf', 'crlf' or 'native', got '{}'", s),
+        }
+    }
+}
+
+impl EolPolicy {
+    fn transformer(self) -> Option<Arc<dyn ContentTransformer>> {
+        let crlf = match self {
+            EolPolicy::AsIs => return None,
+            EolPolicy::Lf => false,
+            EolPolicy::Crlf => true,
+            EolPolicy::Native if cfg!(windows) => true,
+            EolPolicy::Native => return None,
+        };
+        Some(Arc::new(EolTransformer { crlf }))
+    }
+}
+
+/// Converts line endings of regular files. Symlinks and binary files,
+/// which contain a NUL byte, are not changed.
+struct EolTransformer {
+    crlf: bool,
+}
+
+impl ContentTransformer for EolTransformer {
+    fn transform(
+        &self,
+        _path: &RepoPath,
+        data: Bytes,
+        flag: Option<UpdateFlag>,
+    ) -> Result<TransformOutcome> {
+        if matches!(flag, Some(UpdateFlag::Symlink)) || data.contains(&0) {
+            return Ok(TransformOutcome::Inline(data));
+        }
+        let data = if self.crlf {
+            to_crlf(data)
+        } else {
+            to_lf(data)
+        };
+        Ok(TransformOutcome::Inline(data))
+    }
+}
+
+fn to_crlf(data: Bytes) -> Bytes {
+    let lone_lf = |(i, c): (usize, &u8)| *c == b'\n' && (i == 0 || data[i - 1] != b'\r');
+    if !data.iter().enumerate().any(lone_lf) {
+        return data;
+    }
+    let mut result = Vec::with_capacity(data.len() + data.len() / 16);
+    for (i, c) in data.iter().enumerate() {
+        if lone_lf((i, c)) {
+            result.push(b'\r');
+        }
+        result.push(*c);
+    }
+    result.into()
+}
+
+fn to_lf(data: Bytes) -> Bytes {
+    if !data.windows(2).any(|w| w == b"\r\n") {
+        return data;
+    }
+    let mut result = Vec::with_capacity(data.len());
+    for (i, c) in data.iter().enumerate() {
+        if *c != b'\r' || data.get(i + 1) != Some(&b'\n') {
+            result.push(*c);
+        }
+    }
+    result.into()
+}
+
+/// The transformers of a `Checkout`: injected ones in the order they were
+/// added, then the line ending conversion. Without transformers, contents
+/// are used as they are, without any copy.
+#[derive(Clone, Default)]
+pub(crate) struct ContentTransform {
+    transformers: Vec<Arc<dyn ContentTransformer>>,
+}
+
+impl ContentTransform {
+    pub(crate) fn new(injected: &[Arc<dyn ContentTransformer>], eol: EolPolicy) -> Self {
+        let mut transformers = injected.to_vec();
+        transformers.extend(eol.transformer());
+        Self { transformers }
+    }
+
+    pub(crate) fn is_identity(&self) -> bool {
+        self.transformers.is_empty()
+    }
+
+    /// Apply the transformers to `data` of `path`, fetching contents from
+    /// secondary stores as instructed. May block.
+    pub(crate) fn apply(
+        &self,
+        path: &RepoPath,
+        mut data: Bytes,
+        flag: Option<UpdateFlag>,
+    ) -> Result<Bytes> {
+        for transformer in &self.transformers {
+            data = match transformer.transform(path, data, flag)? {
+                TransformOutcome::Inline(data) => data,
+                TransformOutcome::Fetch { store, reference } => store.fetch(path, &reference)?,
+            };
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(transform: &ContentTransform, data: &str, flag: UpdateFlag) -> String {
+        let path = RepoPath::from_str("a").unwrap();
+        let data = transform
+            .apply(path, Bytes::copy_from_slice(data.as_bytes()), Some(flag))
+            .unwrap();
+        String::from_utf8(data.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_eol() {
+        let crlf = ContentTransform::new(&[], EolPolicy::Crlf);
+        assert_eq!(
+            apply(&crlf, "a\nb\r\nc\n", UpdateFlag::Regular),
+            "a\r\nb\r\nc\r\n"
+        );
+        assert_eq!(apply(&crlf, "\n", UpdateFlag::Executable), "\r\n");
+        assert_eq!(apply(&crlf, "a\nb", UpdateFlag::Symlink), "a\nb");
+        assert_eq!(apply(&crlf, "a\0\nb", UpdateFlag::Regular), "a\0\nb");
+
+        let lf = ContentTransform::new(&[], EolPolicy::Lf);
+        assert_eq!(
+            apply(&lf, "a\r\nb\r\r\nc\r", UpdateFlag::Regular),
+            "a\nb\r\nc\r"
+        );
+
+        assert!(ContentTransform::new(&[], EolPolicy::AsIs).is_identity());
+        assert_eq!(
+            ContentTransform::new(&[], EolPolicy::Native).is_identity(),
+            !cfg!(windows)
+        );
+    }
+
+    #[test]
+    fn test_fetch_from_secondary_store() {
+        struct Pointers;
+        impl ContentTransformer for Pointers {
+            fn transform(
+                &self,
+                _path: &RepoPath,
+                data: Bytes,
+                _flag: Option<UpdateFlag>,
+            ) -> Result<TransformOutcome> {
+                match data.strip_prefix(b"pointer ") {
+                    Some(reference) => Ok(TransformOutcome::Fetch {
+                        store: Arc::new(Store),
+                        reference: Bytes::copy_from_slice(reference),
+                    }),
+                    None => Ok(TransformOutcome::Inline(data)),
+                }
+            }
+        }
+        struct Store;
+        impl SecondaryStore for Store {
+            fn fetch(&self, path: &RepoPath, reference: &Bytes) -> Result<Bytes> {
+                Ok(
+                    format!("{} of {}\n", reference.as_ref().escape_ascii(), path)
+                        .into_bytes()
+                        .into(),
+                )
+            }
+        }
+
+        // Fetched contents go through the following transformers.
+        let transform = ContentTransform::new(&[Arc::new(Pointers)], EolPolicy::Crlf);
+        assert_eq!(
+            apply(&transform, "pointer x", UpdateFlag::Regular),
+            "x of a\r\n"
+        );
+        assert_eq!(apply(&transform, "y\n", UpdateFlag::Regular), "y\r\n");
+    }
+}
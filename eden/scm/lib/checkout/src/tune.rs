@@ -0,0 +1,423 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Tuning of the batch size and the concurrency of the filesystem operations
+//! of `CheckoutPlan::apply_store`, see `nativecheckout.autotune`.
+//!
+//! Each phase has a `Tuner` sampling the latency of its first batches, in
+//! windows of `WINDOW` batches. After each window, the batch size and the
+//! concurrency grow by a quarter of their initial values while the latency
+//! per item stays within `DEGRADED` times the best seen, and are halved
+//! when the p95 latency per item of the window degrades beyond that. Values
+//! stay within the configured bounds, and are fixed after the sampled
+//! batches, or from the start when tuning is off.
+
+use std::future::Future;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::stream;
+use futures::Stream;
+use futures::StreamExt;
+use parking_lot::Mutex;
+use tokio::sync::Semaphore;
+use tokio::sync::SemaphorePermit;
+
+/// Number of batches sampled before each adjustment.
+const WINDOW: usize = 4;
+
+/// Latency per item more than this times the best seen is degraded.
+const DEGRADED: f64 = 1.25;
+
+/// Inclusive bounds of a tuned value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bounds {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl Bounds {
+    fn clamp(&self, value: usize) -> usize {
+        value.clamp(self.min, self.max)
+    }
+}
+
+/// Bounds of the tuned values. `nativecheckout.autotune` and related
+/// options.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AutoTune {
+    /// `nativecheckout.minbatchsize` and `nativecheckout.maxbatchsize`.
+    pub(crate) batch_size: Bounds,
+    /// `nativecheckout.minconcurrency` and `nativecheckout.maxconcurrency`.
+    pub(crate) concurrency: Bounds,
+    /// Number of batches of each phase sampled, values are fixed after.
+    /// `nativecheckout.autotunebatches`.
+    pub(crate) sampled_batches: usize,
+}
+
+/// The batch size and concurrency of a phase, at its end.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Tuned {
+    pub batch_size: usize,
+    pub concurrency: usize,
+}
+
+/// The values used by each phase of applying a plan.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TunedValues {
+    pub remove: Tuned,
+    pub write: Tuned,
+    /// Exec flags are updated one file at a time, so the batch size is 1.
+    pub meta: Tuned,
+}
+
+/// Whether to grow or shrink the values after a window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Adjustment {
+    Grow,
+    Shrink,
+    Keep,
+}
+
+/// Latencies per item, in seconds.
+#[derive(Default)]
+struct State {
+    /// Items and latency of the batches of the current window.
+    window: Vec<(usize, Duration)>,
+    sampled: usize,
+    best_mean: Option<f64>,
+    best_p95: Option<f64>,
+}
+
+impl State {
+    /// Adds a batch to the window, and returns the adjustment once it's
+    /// full.
+    fn sample(&mut self, items: usize, latency: Duration) -> Option<Adjustment> {
+        self.sampled += 1;
+        self.window.push((items.max(1), latency));
+        if self.window.len() < WINDOW {
+            return None;
+        }
+        let window = std::mem::take(&mut self.window);
+        let total_items: usize = window.iter().map(|(items, _)| items).sum();
+        let total_latency: Duration = window.iter().map(|(_, latency)| *latency).sum();
+        let mean = total_latency.as_secs_f64() / total_items as f64;
+        let mut per_item: Vec<f64> = window
+            .iter()
+            .map(|(items, latency)| latency.as_secs_f64() / *items as f64)
+            .collect();
+        per_item.sort_by(f64::total_cmp);
+        let p95 = per_item[(per_item.len() * 95).div_ceil(100) - 1];
+
+        let best_mean = *self.best_mean.get_or_insert(mean);
+        let best_p95 = *self.best_p95.get_or_insert(p95);
+        let adjustment = if p95 > best_p95 * DEGRADED {
+            Adjustment::Shrink
+        } else if mean <= best_mean * DEGRADED {
+            Adjustment::Grow
+        } else {
+            Adjustment::Keep
+        };
+        self.best_mean = Some(best_mean.min(mean));
+        self.best_p95 = Some(best_p95.min(p95));
+        Some(adjustment)
+    }
+}
+
+/// The batch size and concurrency of a phase, see the module documentation.
+pub(crate) struct Tuner {
+    batch_size: AtomicUsize,
+    concurrency: AtomicUsize,
+    /// Additive increments of the values.
+    batch_size_step: usize,
+    concurrency_step: usize,
+    autotune: Option<AutoTune>,
+    /// Limits the batches in flight to `concurrency`.
+    permits: Semaphore,
+    /// Permits to drop instead of releasing, after lowering `concurrency`
+    /// while they were held.
+    debt: AtomicUsize,
+    state: Mutex<State>,
+}
+
+impl Tuner {
+    /// `batch_size` and `concurrency` are the initial values. Without
+    /// `autotune`, they are used as is.
+    pub(crate) fn new(batch_size: usize, concurrency: usize, autotune: Option<AutoTune>) -> Self {
+        Self {
+            batch_size: AtomicUsize::new(batch_size),
+            concurrency: AtomicUsize::new(concurrency),
+            batch_size_step: (batch_size / 4).max(1),
+            concurrency_step: (concurrency / 4).max(1),
+            autotune,
+            permits: Semaphore::new(concurrency),
+            debt: AtomicUsize::new(0),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Same as `new`, for a phase whose operations are not batched.
+    pub(crate) fn unbatched(concurrency: usize, autotune: Option<AutoTune>) -> Self {
+        let autotune = autotune.map(|autotune| AutoTune {
+            batch_size: Bounds { min: 1, max: 1 },
+            ..autotune
+        });
+        Self::new(1, concurrency, autotune)
+    }
+
+    pub(crate) fn batch_size(&self) -> usize {
+        self.batch_size.load(Ordering::Relaxed)
+    }
+
+    /// Limit of batches in flight for `StreamExt::buffer_unordered`, which
+    /// `run` lowers to the current concurrency.
+    pub(crate) fn max_concurrency(&self) -> usize {
+        match &self.autotune {
+            Some(autotune) => autotune.concurrency.max,
+            None => self.concurrency.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn tuned(&self) -> Tuned {
+        Tuned {
+            batch_size: self.batch_size(),
+            concurrency: self.concurrency.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Runs `batch` of `items` items once fewer than the current
+    /// concurrency of batches are running, and samples its latency.
+    pub(crate) async fn run<T>(&self, items: usize, batch: impl Future<Output = T>) -> T {
+        let permit = self
+            .permits
+            .acquire()
+            .await
+            .expect("semaphore is never closed");
+        let start = Instant::now();
+        let result = batch.await;
+        self.sample(items, start.elapsed());
+        self.release(permit);
+        result
+    }
+
+    /// Records the latency of a batch of `items` items, and adjusts the
+    /// values after each window.
+    pub(crate) fn sample(&self, items: usize, latency: Duration) {
+        let autotune = match &self.autotune {
+            Some(autotune) => autotune,
+            None => return,
+        };
+        let mut state = self.state.lock();
+        if state.sampled >= autotune.sampled_batches {
+            return;
+        }
+        let adjustment = match state.sample(items, latency) {
+            Some(adjustment) => adjustment,
+            None => return,
+        };
+        let batch_size = self.batch_size();
+        let concurrency = self.concurrency.load(Ordering::Relaxed);
+        let (batch_size, concurrency) = match adjustment {
+            Adjustment::Grow => (
+                batch_size + self.batch_size_step,
+                concurrency + self.concurrency_step,
+            ),
+            Adjustment::Shrink => (batch_size / 2, concurrency / 2),
+            Adjustment::Keep => (batch_size, concurrency),
+        };
+        self.batch_size
+            .store(autotune.batch_size.clamp(batch_size), Ordering::Relaxed);
+        self.set_concurrency(autotune.concurrency.clamp(concurrency));
+    }
+
+    fn set_concurrency(&self, concurrency: usize) {
+        let previous = self.concurrency.swap(concurrency, Ordering::Relaxed);
+        if concurrency > previous {
+            // Cancel the debt before adding permits.
+            let mut added = concurrency - previous;
+            let _ = self
+                .debt
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |debt| {
+                    let paid = debt.min(added);
+                    added -= paid;
+                    Some(debt - paid)
+                });
+            self.permits.add_permits(added);
+        } else {
+            self.debt
+                .fetch_add(previous - concurrency, Ordering::Relaxed);
+        }
+    }
+
+    fn release(&self, permit: SemaphorePermit) {
+        let in_debt = self
+            .debt
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |debt| {
+                debt.checked_sub(1)
+            })
+            .is_ok();
+        if in_debt {
+            permit.forget();
+        }
+    }
+}
+
+/// Like `StreamExt::chunks`, with the batch size of `tuner` when each chunk
+/// starts. The stream is boxed so that it is `Unpin`, like `chunks`.
+pub(crate) fn chunks<'a, S>(
+    stream: S,
+    tuner: &'a Tuner,
+) -> impl Stream<Item = Vec<S::Item>> + Unpin + 'a
+where
+    S: Stream + Unpin + 'a,
+{
+    Box::pin(stream::unfold(stream, move |mut stream| async move {
+        let size = tuner.batch_size();
+        let mut chunk = Vec::with_capacity(size);
+        while chunk.len() < size {
+            match stream.next().await {
+                Some(item) => chunk.push(item),
+                None => break,
+            }
+        }
+        if chunk.is_empty() {
+            None
+        } else {
+            Some((chunk, stream))
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AUTOTUNE: AutoTune = AutoTune {
+        batch_size: Bounds { min: 10, max: 1000 },
+        concurrency: Bounds { min: 1, max: 64 },
+        sampled_batches: 64,
+    };
+
+    /// Feeds `tuner` with the latencies of `profile`, given the batch size
+    /// and concurrency, for `batches` batches.
+    fn simulate(tuner: &Tuner, batches: usize, profile: impl Fn(usize, usize) -> Duration) {
+        for _ in 0..batches {
+            let Tuned {
+                batch_size,
+                concurrency,
+            } = tuner.tuned();
+            tuner.sample(batch_size, profile(batch_size, concurrency));
+        }
+    }
+
+    /// Local disk: a fixed dispatch cost per batch dominates small batches.
+    fn fast(batch_size: usize, _concurrency: usize) -> Duration {
+        Duration::from_micros(1000 + 2 * batch_size as u64)
+    }
+
+    /// Network filesystem: a server handling 8 concurrent batches, slowing
+    /// down beyond that.
+    fn slow(batch_size: usize, concurrency: usize) -> Duration {
+        let per_item = 50 * concurrency.saturating_sub(7).max(1) as u64;
+        Duration::from_micros(per_item * batch_size as u64)
+    }
+
+    #[test]
+    fn test_fast_profile_grows_batches() {
+        let tuner = Tuner::new(100, 16, Some(AUTOTUNE));
+        simulate(&tuner, 200, fast);
+        let tuned = tuner.tuned();
+        assert!(tuned.batch_size > 100, "{:?}", tuned);
+        assert!(tuned.concurrency >= 16, "{:?}", tuned);
+    }
+
+    #[test]
+    fn test_slow_profile_lowers_concurrency() {
+        let tuner = Tuner::new(100, 16, Some(AUTOTUNE));
+        simulate(&tuner, 200, slow);
+        let tuned = tuner.tuned();
+        assert!(tuned.concurrency < 16, "{:?}", tuned);
+        assert!(tuned.concurrency >= AUTOTUNE.concurrency.min, "{:?}", tuned);
+    }
+
+    #[test]
+    fn test_bounds_and_sampled_batches() {
+        let autotune = AutoTune {
+            batch_size: Bounds { min: 50, max: 150 },
+            concurrency: Bounds { min: 8, max: 20 },
+            sampled_batches: 64,
+        };
+        let tuner = Tuner::new(100, 16, Some(autotune));
+        simulate(&tuner, 64, fast);
+        let tuned = tuner.tuned();
+        assert_eq!(
+            tuned,
+            Tuned {
+                batch_size: 150,
+                concurrency: 20
+            }
+        );
+        // Values are fixed after the sampled batches.
+        simulate(&tuner, 64, slow);
+        assert_eq!(tuner.tuned(), tuned);
+
+        let tuner = Tuner::new(100, 16, Some(autotune));
+        simulate(&tuner, 64, slow);
+        assert!((8..16).contains(&tuner.tuned().concurrency));
+    }
+
+    #[test]
+    fn test_fixed_without_autotune() {
+        for profile in [fast, slow] {
+            let tuner = Tuner::new(100, 16, None);
+            simulate(&tuner, 200, profile);
+            assert_eq!(
+                tuner.tuned(),
+                Tuned {
+                    batch_size: 100,
+                    concurrency: 16
+                }
+            );
+            assert_eq!(tuner.max_concurrency(), 16);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_limits_concurrency() {
+        let tuner = Tuner::new(1, 4, None);
+        tuner.set_concurrency(2);
+        let running = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+        stream::iter(0..20)
+            .map(|_| {
+                tuner.run(1, async {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .buffer_unordered(4)
+            .collect::<Vec<_>>()
+            .await;
+        assert!(most.load(Ordering::SeqCst) <= 4);
+        // Permits released while over the lowered concurrency were dropped.
+        assert_eq!(tuner.permits.available_permits(), 2);
+
+        tuner.set_concurrency(3);
+        assert_eq!(tuner.permits.available_permits(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_chunks_follow_batch_size() {
+        let tuner = Tuner::new(3, 1, None);
+        let chunks: Vec<Vec<i32>> = chunks(stream::iter(0..7), &tuner).collect().await;
+        assert_eq!(chunks, vec![vec![0, 1, 2], vec![3, 4, 5], vec![6]]);
+    }
+}
@@ -0,0 +1,205 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Checks that applying a `CheckoutPlan` left the working copy, and what is
+//! recorded about it in the treestate, as the actions of the plan demanded.
+
+use std::fmt;
+
+use anyhow::Result;
+use manifest::FileType;
+use tracing::warn;
+use treestate::filestate::StateFlags;
+use treestate::treestate::TreeState;
+use types::RepoPathBuf;
+use vfs::VFS;
+
+use crate::audit::is_not_found;
+use crate::audit::matches_file_type;
+use crate::file_state;
+use crate::CheckoutPlan;
+
+/// Violations logged by [`verify_applied_if_enabled`], the others are
+/// only counted.
+const MAX_LOGGED_VIOLATIONS: usize = 100;
+
+/// A file of a `CheckoutPlan` that is not in the state its action demanded,
+/// found by [`verify_applied`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvariantViolation {
+    pub path: RepoPathBuf,
+    pub kind: ViolationKind,
+}
+
+/// How a file differs from the state its action demanded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// A removed file still exists on disk.
+    NotRemoved,
+    /// A removed file is still recorded as existing in the treestate.
+    StillRecorded,
+    /// An updated file doesn't exist on disk.
+    Missing,
+    /// An updated file is not of the type of the plan on disk, including
+    /// its exec bit.
+    TypeMismatch { expected: FileType },
+    /// An updated file is not recorded as existing in the treestate.
+    NotRecorded,
+    /// The treestate records another mode than the one of the file on
+    /// disk, so `status` would check the file again.
+    ModeMismatch { recorded: u32, actual: u32 },
+    /// The treestate records another size than the one of the file on disk,
+    /// so `status` would report it as modified.
+    SizeMismatch { recorded: i32, actual: i32 },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.path)?;
+        match &self.kind {
+            ViolationKind::NotRemoved => write!(f, "removed file exists on disk"),
+            ViolationKind::StillRecorded => write!(f, "removed file is recorded in treestate"),
+            ViolationKind::Missing => write!(f, "updated file is missing on disk"),
+            ViolationKind::TypeMismatch { expected } => {
+                write!(f, "expected a {:?} file on disk", expected)
+            }
+            ViolationKind::NotRecorded => write!(f, "updated file is not recorded in treestate"),
+            ViolationKind::ModeMismatch { recorded, actual } => write!(
+                f,
+                "treestate records mode {:#o}, but it is {:#o} on disk",
+                recorded, actual
+            ),
+            ViolationKind::SizeMismatch { recorded, actual } => write!(
+                f,
+                "treestate records size {}, but it is {} on disk",
+                recorded, actual
+            ),
+        }
+    }
+}
+
+/// Checks the state of every file of `plan` after it was applied to `vfs`
+/// and recorded in `tree_state`.
+///
+/// Removed files must not exist on disk, nor be recorded in `tree_state`.
+/// Files whose content or exec bit was updated must exist on disk with the
+/// type of the plan, and be recorded in `tree_state` with their mode and
+/// size on disk. Files skipped because the progress file says they were
+/// already written are checked too.
+///
+/// All violations are returned, in the order of the plan. Errors are only
+/// returned if the working copy or the treestate can't be read.
+pub fn verify_applied(
+    plan: &CheckoutPlan,
+    vfs: &VFS,
+    tree_state: &mut TreeState,
+) -> Result<Vec<InvariantViolation>> {
+    let mut violations = vec![];
+    let mut violation = |path: &RepoPathBuf, kind| {
+        violations.push(InvariantViolation {
+            path: path.clone(),
+            kind,
+        })
+    };
+
+    for path in &plan.remove {
+        match vfs.metadata(path) {
+            // A directory of files added by the plan may replace the file.
+            Ok(metadata) if !metadata.is_dir() => violation(path, ViolationKind::NotRemoved),
+            Ok(_) => {}
+            Err(err) if is_not_found(&err) => {}
+            Err(err) => return Err(err.context(format!("Can not stat {}", path))),
+        }
+        if let Some(state) = tree_state.get(path)? {
+            if state.state.contains(StateFlags::EXIST_NEXT) {
+                violation(path, ViolationKind::StillRecorded);
+            }
+        }
+    }
+
+    let updated = plan
+        .update_content
+        .iter()
+        .map(|action| (&action.path, action.file_type))
+        .chain(plan.update_meta.iter().map(|action| {
+            let file_type = if action.set_x_flag {
+                FileType::Executable
+            } else {
+                FileType::Regular
+            };
+            (&action.path, file_type)
+        }));
+    for (path, expected) in updated {
+        let exists = match vfs.metadata(path) {
+            Ok(metadata) => {
+                if !matches_file_type(vfs, expected, &metadata) {
+                    violation(path, ViolationKind::TypeMismatch { expected });
+                }
+                true
+            }
+            Err(err) if is_not_found(&err) => {
+                violation(path, ViolationKind::Missing);
+                false
+            }
+            Err(err) => return Err(err.context(format!("Can not stat {}", path))),
+        };
+        let recorded = match tree_state.get(path)? {
+            Some(state) if state.state.contains(StateFlags::EXIST_NEXT) => state.clone(),
+            _ => {
+                violation(path, ViolationKind::NotRecorded);
+                continue;
+            }
+        };
+        if !exists {
+            continue;
+        }
+        let actual = file_state(vfs, path)?;
+        if recorded.mode != actual.mode {
+            violation(
+                path,
+                ViolationKind::ModeMismatch {
+                    recorded: recorded.mode,
+                    actual: actual.mode,
+                },
+            );
+        }
+        if recorded.size != actual.size {
+            violation(
+                path,
+                ViolationKind::SizeMismatch {
+                    recorded: recorded.size,
+                    actual: actual.size,
+                },
+            );
+        }
+    }
+
+    Ok(violations)
+}
+
+/// Runs [`verify_applied`] if `nativecheckout.verifyapplied` is set, and
+/// logs the violations found as warnings.
+pub(crate) fn verify_applied_if_enabled(
+    plan: &CheckoutPlan,
+    vfs: &VFS,
+    tree_state: &mut TreeState,
+) -> Result<()> {
+    if !plan.checkout.config.verify_applied {
+        return Ok(());
+    }
+    let violations = verify_applied(plan, vfs, tree_state)?;
+    for violation in violations.iter().take(MAX_LOGGED_VIOLATIONS) {
+        warn!("Checkout invariant violated: {}", violation);
+    }
+    if violations.len() > MAX_LOGGED_VIOLATIONS {
+        warn!(
+            "{} more checkout invariant violations",
+            violations.len() - MAX_LOGGED_VIOLATIONS
+        );
+    }
+    Ok(())
+}
@@ -55,6 +55,18 @@ impl fmt::Display for Error {
 
 impl std::error::Error for Error {}
 
+impl Error {
+    /// 1-based line number of the syntax error.
+    pub fn line(&self) -> usize {
+        self.line_no + 1
+    }
+
+    /// Description of the syntax error, without the line number.
+    pub fn message(&self) -> &'static str {
+        self.message
+    }
+}
+
 impl<'a> Context<'a> {
     fn parse(&self) -> Result<ParseOutput<'a>, Error> {
         let mut output = Vec::with_capacity(self.instruction_size_hint());
@@ -66,6 +78,11 @@ impl<'a> Context<'a> {
         let mut value_lines: Vec<&'a str> = Vec::with_capacity(1);
 
         for (line_no, line) in self.buf.lines().enumerate().chain(std::iter::once((0, ""))) {
+            // Like hg, ignore a UTF-8 BOM.
+            let line = match line_no {
+                0 => line.strip_prefix('\u{feff}').unwrap_or(line),
+                _ => line,
+            };
             let first_char = line.chars().next().unwrap_or('#');
             let value_empty: bool = value_lines.is_empty();
             // Multi-line config.
@@ -73,6 +90,10 @@ impl<'a> Context<'a> {
                 value_lines.push(line.trim());
                 continue;
             }
+            // Comments do not end a multi-line config.
+            if !value_empty && line.starts_with(['#', ';']) {
+                continue;
+            }
             // Push parsed config.
             if !value_empty {
                 let span = get_range(
@@ -113,7 +134,7 @@ impl<'a> Context<'a> {
                         }
                         Some((section, rest)) => (section.trim(), rest.trim()),
                     };
-                    if !rest.is_empty() {
+                    if !rest.is_empty() && !rest.starts_with(['#', ';']) {
                         return Err(Error {
                             line_no,
                             message: "extra content after section header",
@@ -142,14 +163,26 @@ impl<'a> Context<'a> {
                 }
                 // %include or %unset
                 '%' => {
-                    if let Some(rest) = line.strip_prefix("%include ") {
+                    if let Some(rest) = strip_directive(line, "%include") {
                         let path = rest.trim();
+                        if path.is_empty() {
+                            return Err(Error {
+                                line_no,
+                                message: "empty path for %include",
+                            });
+                        }
                         let span = get_range(self.buf, path, path);
                         let inst = Instruction::Include { path, span };
                         output.push(inst);
-                    } else if let Some(rest) = line.strip_prefix("%unset ") {
+                    } else if let Some(rest) = strip_directive(line, "%unset") {
                         let name = rest.trim();
                         let span = get_range(self.buf, name, name);
+                        if name.is_empty() {
+                            return Err(Error {
+                                line_no,
+                                message: "empty config name",
+                            });
+                        }
                         if name.contains('=') {
                             return Err(Error {
                                 line_no,
@@ -203,6 +236,12 @@ impl<'a> Context<'a> {
     }
 }
 
+/// Strip `directive` and the whitespace separating it from its argument.
+fn strip_directive<'a>(line: &'a str, directive: &str) -> Option<&'a str> {
+    let rest = line.strip_prefix(directive)?;
+    rest.starts_with([' ', '\t']).then_some(rest)
+}
+
 /// Figure out a range in `text` so `text[range]` starts with the first byte
 /// of `start` and ends with the last byte of `end`.
 /// Assumes `start` and `end` are derived from (sub-strings of) `text`.
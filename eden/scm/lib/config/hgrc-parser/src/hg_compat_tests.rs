@@ -0,0 +1,434 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Differential tests against the config parser of Python hg
+//! (`config.parse` in `mercurial/config.py`).
+//!
+//! `FIXTURES` record how hg parses edge cases. Where this crate differs on
+//! purpose, its outcome is listed in `DIVERGENCES`, so that any other
+//! difference fails. Generated configs check the same semantics on more
+//! inputs, and that parsing is stable across a round trip through
+//! `serialize`.
+
+use quickcheck::quickcheck;
+use quickcheck::Arbitrary;
+use quickcheck::Gen;
+
+use crate::parse;
+use crate::Instruction;
+
+/// Fixture name, config text, and outcome of hg's parser as reported by
+/// `outcome`.
+const FIXTURES: &[(&str, &str, &[&str])] = &[
+    ("basic", "[s]\nx = a\n", &[r#"set s.x="a""#]),
+    (
+        "item before any section",
+        "x = a\n[s]\ny = b\n",
+        &[r#"set .x="a""#, r#"set s.y="b""#],
+    ),
+    (
+        "trailing whitespace",
+        "[s]\nx = a  \t\ny =   \n",
+        &[r#"set s.x="a""#, r#"set s.y="""#],
+    ),
+    ("empty value", "[s]\nx =\n", &[r#"set s.x="""#]),
+    (
+        "equal sign in value",
+        "[s]\nx = a=b\n",
+        &[r#"set s.x="a=b""#],
+    ),
+    ("space in name", "[s]\nx y = a\n", &[r#"set s.x y="a""#]),
+    (
+        "comment character in value",
+        "[s]\nx = a # b\ny = c ; d\n",
+        &[r#"set s.x="a # b""#, r#"set s.y="c ; d""#],
+    ),
+    (
+        "comment lines",
+        "# c\n; c\n[s]\n#x = a\nx = b\n",
+        &[r#"set s.x="b""#],
+    ),
+    (
+        "multi-line value",
+        "[s]\nx = a\n  b\n\tc  \n",
+        &[r#"set s.x="a\nb\nc""#],
+    ),
+    (
+        "multi-line value after empty value",
+        "[s]\nx =\n  a\n",
+        &[r#"set s.x="\na""#],
+    ),
+    (
+        "comment in multi-line value",
+        "[s]\nx = a\n# c\n  b\n; d\n  c\n",
+        &[r#"set s.x="a\nb\nc""#],
+    ),
+    (
+        "indented comment in multi-line value",
+        "[s]\nx = a\n  # b\n",
+        &[r##"set s.x="a\n# b""##],
+    ),
+    (
+        "empty line ends multi-line value",
+        "[s]\nx = a\n\ny = b\n",
+        &[r#"set s.x="a""#, r#"set s.y="b""#],
+    ),
+    (
+        "whitespace line ends multi-line value",
+        "[s]\nx = a\n  \ny = b\n",
+        &[r#"set s.x="a""#, r#"set s.y="b""#],
+    ),
+    (
+        "indented line after empty line",
+        "[s]\nx = a\n\n  b\n",
+        &["error line 4"],
+    ),
+    (
+        "indented line after section",
+        "[s]\nx = a\n# c\n[t]\n  b\n",
+        &["error line 5"],
+    ),
+    (
+        "crlf",
+        "[s]\r\nx = a\r\n  b\r\ny = c\r\n",
+        &[r#"set s.x="a\nb""#, r#"set s.y="c""#],
+    ),
+    ("no final newline", "[s]\nx = a", &[r#"set s.x="a""#]),
+    ("bom", "\u{feff}[s]\nx = a\n", &[r#"set s.x="a""#]),
+    (
+        "comment after section header",
+        "[s] # c\nx = a\n",
+        &[r#"set s.x="a""#],
+    ),
+    (
+        "unset",
+        "[s]\nx = a\n%unset x\n",
+        &[r#"set s.x="a""#, "unset s.x"],
+    ),
+    ("unset with tab", "[s]\n%unset\tx\n", &["unset s.x"]),
+    ("unset without name", "[s]\n%unset \n", &["error line 2"]),
+    ("include", "%include a b.rc  \n", &["include a b.rc"]),
+    ("include with tab", "%include\t/p\n", &["include /p"]),
+    ("include without path", "%include \n", &["error line 1"]),
+    ("unknown directive", "%set a b\n", &["error line 1"]),
+    ("missing equal sign", "[s]\nx\n", &["error line 2"]),
+    ("empty name", "[s]\n= a\n", &["error line 2"]),
+    ("indented item", "[s]\n  x = a\n", &["error line 2"]),
+    ("unclosed section", "[s\n", &["error line 1"]),
+    ("empty section name", "[]\n", &["error line 1"]),
+    ("text after section header", "[s] x\n", &[]),
+    (
+        "space in section header",
+        "[ s ]\nx = a\n",
+        &[r#"set  s .x="a""#],
+    ),
+    ("bracket in section name", "[a[b]\n", &["error line 1"]),
+    (
+        "whitespace line in multi-line value",
+        "[s]\nx = a\n  \n  b\n",
+        &["error line 4"],
+    ),
+    (
+        "unset with extra words",
+        "[s]\n%unset x y\n",
+        &["unset s.x"],
+    ),
+    (
+        "unset with equal sign",
+        "[s]\n%unset x=y\n",
+        &[r#"set s.%unset x="y""#],
+    ),
+    (
+        "percent sign in name",
+        "[s]\n%x = a\n",
+        &[r#"set s.%x="a""#],
+    ),
+    ("lone carriage return", "[s]\rx = a\n", &[r#"set s.x="a""#]),
+    (
+        "non-ascii whitespace",
+        "[s]\nx = a\u{a0}\n",
+        &["set s.x=\"a\u{a0}\""],
+    ),
+];
+
+/// Fixtures this crate intentionally parses differently from hg, and the
+/// outcome it has instead.
+const DIVERGENCES: &[(&str, &[&str])] = &[
+    // hg ignores anything after the closing bracket. Only comments are
+    // accepted, so typos don't go unnoticed.
+    ("text after section header", &["error line 1"]),
+    // Section names are trimmed, like config names.
+    ("space in section header", &[r#"set s.x="a""#]),
+    // Only the closing bracket is special in section names.
+    ("bracket in section name", &[]),
+    // Lines of whitespace are empty lines of the value, so values can be
+    // split into paragraphs.
+    (
+        "whitespace line in multi-line value",
+        &[r#"set s.x="a\n\nb""#],
+    ),
+    // Config names can't be split, the whole rest of the line is the name.
+    ("unset with extra words", &["unset s.x y"]),
+    // hg parses the line as setting "%unset x"; report the bad name instead.
+    ("unset with equal sign", &["error line 2"]),
+    // '%' starts a directive, names can't start with it.
+    ("percent sign in name", &["error line 2"]),
+    // Lines end with "\n" or "\r\n" only.
+    ("lone carriage return", &["error line 1"]),
+    // Values are trimmed of all Unicode whitespace, not only ASCII.
+    ("non-ascii whitespace", &[r#"set s.x="a""#]),
+];
+
+/// Outcome of parsing `text`: one line per instruction, or the error.
+fn outcome(text: &str) -> Vec<String> {
+    match parse(text) {
+        Ok(instructions) => instructions.iter().map(describe).collect(),
+        Err(e) => vec![format!("error line {}", e.line())],
+    }
+}
+
+fn describe(instruction: &Instruction) -> String {
+    match instruction {
+        Instruction::SetConfig {
+            section,
+            name,
+            value,
+            ..
+        } => describe_set(section, name, value),
+        Instruction::UnsetConfig { section, name, .. } => format!("unset {}.{}", section, name),
+        Instruction::Include { path, .. } => format!("include {}", path),
+    }
+}
+
+fn describe_set(section: &str, name: &str, value: &str) -> String {
+    format!("set {}.{}={:?}", section, name, value)
+}
+
+/// Render `instructions` as config text, which parses to the same
+/// instructions.
+fn serialize(instructions: &[Instruction]) -> String {
+    let mut text = String::new();
+    let mut current_section = "";
+    for instruction in instructions {
+        match instruction {
+            Instruction::SetConfig { section, .. } | Instruction::UnsetConfig { section, .. }
+                if *section != current_section =>
+            {
+                text.push_str(&format!("[{}]\n", section));
+                current_section = section;
+            }
+            _ => {}
+        }
+        match instruction {
+            Instruction::SetConfig { name, value, .. } => {
+                let mut lines = value.split('\n');
+                text.push_str(&format!("{} = {}\n", name, lines.next().unwrap_or("")));
+                for line in lines {
+                    text.push_str(&format!("  {}\n", line));
+                }
+            }
+            Instruction::UnsetConfig { name, .. } => text.push_str(&format!("%unset {}\n", name)),
+            Instruction::Include { path, .. } => text.push_str(&format!("%include {}\n", path)),
+        }
+    }
+    text
+}
+
+#[test]
+fn test_hg_fixtures() {
+    for (name, text, hg) in FIXTURES {
+        let expected = match DIVERGENCES.iter().find(|(n, _)| n == name) {
+            Some((_, ours)) => ours,
+            None => hg,
+        };
+        assert_eq!(&outcome(text), expected, "{}: {:?}", name, text);
+    }
+}
+
+#[test]
+fn test_divergences_are_fixtures() {
+    for (name, ours) in DIVERGENCES {
+        let (_, _, hg) = FIXTURES
+            .iter()
+            .find(|(n, ..)| n == name)
+            .unwrap_or_else(|| panic!("no fixture for divergence {:?}", name));
+        assert_ne!(ours, hg, "{:?} no longer diverges from hg", name);
+    }
+}
+
+#[test]
+fn test_fixtures_round_trip() {
+    for (_, text, _) in FIXTURES {
+        if let Ok(instructions) = parse(text) {
+            assert_eq!(outcome(&serialize(&instructions)), outcome(text));
+        }
+    }
+}
+
+/// A config text generated from a grammar avoiding `DIVERGENCES`, and the
+/// outcome of hg's parser for it.
+#[derive(Clone, Debug)]
+struct GeneratedConfig {
+    text: String,
+    hg: Vec<String>,
+}
+
+/// Characters of generated values, names and comments.
+const CHARS: &[char] = &[
+    'a', 'b', 'z', 'A', '0', '9', '.', '-', '_', '/', ' ', '=', '#', ';', '[', ']', '%', '"', '\\',
+    'é',
+];
+
+/// A string of up to `max_len` characters of `CHARS` for which `allowed`
+/// returns true, without leading or trailing spaces.
+fn gen_text(g: &mut Gen, max_len: usize, allowed: impl Fn(char) -> bool) -> String {
+    let chars: Vec<char> = CHARS.iter().copied().filter(|c| allowed(*c)).collect();
+    let len = usize::arbitrary(g) % (max_len + 1);
+    let text: String = (0..len).map(|_| *g.choose(&chars).unwrap()).collect();
+    text.trim().to_string()
+}
+
+/// Like `gen_text`, but never empty, and with a first character for which
+/// `allowed_first` returns true.
+fn gen_word(
+    g: &mut Gen,
+    max_len: usize,
+    allowed_first: impl Fn(char) -> bool,
+    allowed: impl Fn(char) -> bool,
+) -> String {
+    let first = gen_text(g, 1, |c| {
+        c.is_ascii_alphanumeric() || allowed_first(c) && allowed(c)
+    });
+    let first = if first.is_empty() {
+        "a".to_string()
+    } else {
+        first
+    };
+    format!("{}{}", first, gen_text(g, max_len, allowed))
+        .trim_end()
+        .to_string()
+}
+
+fn gen_whitespace(g: &mut Gen, allow_empty: bool) -> &'static str {
+    let choices: &[&str] = if allow_empty {
+        &["", " ", "\t", "  \t "]
+    } else {
+        &[" ", "\t", "  \t "]
+    };
+    g.choose(choices).unwrap()
+}
+
+impl Arbitrary for GeneratedConfig {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let newline = *g.choose(&["\n", "\r\n"]).unwrap();
+        let mut lines: Vec<String> = Vec::new();
+        let mut hg = Vec::new();
+        let mut section = String::new();
+
+        for _ in 0..usize::arbitrary(g) % 16 {
+            match u8::arbitrary(g) % 6 {
+                0 => {
+                    section = gen_word(g, 8, |_| true, |c| !"[]=#;%\"\\".contains(c));
+                    let comment = if bool::arbitrary(g) {
+                        format!(
+                            " {} {}",
+                            g.choose(&['#', ';']).unwrap(),
+                            gen_text(g, 8, |_| true)
+                        )
+                    } else {
+                        String::new()
+                    };
+                    lines.push(format!(
+                        "[{}]{}{}",
+                        section,
+                        comment,
+                        gen_whitespace(g, true)
+                    ));
+                }
+                1 => {
+                    let name = gen_word(g, 8, |c| !"[]#;%".contains(c), |c| c != '=');
+                    let first = gen_text(g, 12, |_| true);
+                    lines.push(format!(
+                        "{}{}={}{}{}",
+                        name,
+                        gen_whitespace(g, true),
+                        gen_whitespace(g, true),
+                        first,
+                        gen_whitespace(g, true)
+                    ));
+                    let mut value = first;
+                    for _ in 0..usize::arbitrary(g) % 4 {
+                        if u8::arbitrary(g) % 4 == 0 {
+                            lines.push(format!(
+                                "{}{}",
+                                g.choose(&['#', ';']).unwrap(),
+                                gen_text(g, 8, |_| true)
+                            ));
+                        }
+                        let line = gen_word(g, 12, |_| true, |_| true);
+                        lines.push(format!(
+                            "{}{}{}",
+                            gen_whitespace(g, false),
+                            line,
+                            gen_whitespace(g, true)
+                        ));
+                        value = format!("{}\n{}", value, line);
+                    }
+                    hg.push(describe_set(&section, &name, &value));
+                }
+                2 => {
+                    let name = gen_word(g, 8, |_| true, |c| c != ' ' && c != '=');
+                    lines.push(format!(
+                        "%unset{}{}{}",
+                        gen_whitespace(g, false),
+                        name,
+                        gen_whitespace(g, true)
+                    ));
+                    hg.push(format!("unset {}.{}", section, name));
+                }
+                3 => {
+                    let path = gen_word(g, 12, |_| true, |_| true);
+                    lines.push(format!(
+                        "%include{}{}{}",
+                        gen_whitespace(g, false),
+                        path,
+                        gen_whitespace(g, true)
+                    ));
+                    hg.push(format!("include {}", path));
+                }
+                4 => lines.push(format!(
+                    "{}{}",
+                    g.choose(&['#', ';']).unwrap(),
+                    gen_text(g, 12, |_| true)
+                )),
+                _ => lines.push(gen_whitespace(g, true).to_string()),
+            }
+        }
+
+        let mut text = lines.join(newline);
+        if bool::arbitrary(g) {
+            text.push_str(newline);
+        }
+        if bool::arbitrary(g) {
+            text.insert(0, '\u{feff}');
+        }
+        Self { text, hg }
+    }
+}
+
+quickcheck! {
+    fn test_generated_configs_match_hg(config: GeneratedConfig) -> bool {
+        outcome(&config.text) == config.hg
+    }
+
+    fn test_generated_configs_round_trip(config: GeneratedConfig) -> bool {
+        match parse(&config.text) {
+            Ok(instructions) => outcome(&serialize(&instructions)) == outcome(&config.text),
+            Err(_) => false,
+        }
+    }
+}
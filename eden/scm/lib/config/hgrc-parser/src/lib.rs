@@ -12,11 +12,23 @@
 //! - UnsetConfig(section, name)
 //! - Include(path)
 //!
+//! Also provides a three-way merge of config files (`merge_configs`).
+//!
 //! Pure. Do not depend on a filesystem.
 
 pub(crate) mod config;
 #[cfg(test)]
+mod hg_compat_tests;
+pub(crate) mod merge;
+#[cfg(test)]
 mod tests;
 
 pub use config::parse;
+pub use config::Error;
 pub use config::Instruction;
+pub use merge::merge_configs;
+pub use merge::ConflictKind;
+pub use merge::MergeConflict;
+pub use merge::MergeError;
+pub use merge::MergeOutcome;
+pub use merge::MergeSide;
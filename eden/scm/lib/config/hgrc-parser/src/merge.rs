@@ -0,0 +1,414 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Three-way merge of config files.
+//!
+//! Configs are merged per `(section, name)` rather than per line, so changes
+//! to different configs never conflict even if they are on adjacent lines.
+//! The local version is used as the starting point, which keeps its comments
+//! and ordering. Changes made only by the other side are applied on top of it.
+
+use std::borrow::Cow;
+use std::collections::hash_map::Entry as HashMapEntry;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+
+use crate::config::parse;
+use crate::config::Error;
+use crate::config::Instruction;
+
+/// Result of [`merge_configs`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergeOutcome {
+    /// The merged config. Each conflict is surrounded by conflict markers
+    /// that only cover the lines of the conflicting config.
+    pub text: String,
+    /// Configs that could not be merged, in the order they appear in `text`.
+    pub conflicts: Vec<MergeConflict>,
+}
+
+impl MergeOutcome {
+    /// Whether the merge completed without conflicts.
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+/// A config changed by both sides in incompatible ways.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergeConflict {
+    pub section: String,
+    pub name: String,
+    pub kind: ConflictKind,
+    /// Byte range of the conflict, including the conflict markers, in
+    /// [`MergeOutcome::text`].
+    pub span: Range<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// Both sides changed the config in different ways.
+    BothModified,
+    /// One side removed the config or used `%unset` on it, while the other
+    /// side set it to a new value.
+    RemovedAndModified,
+}
+
+/// One of the versions of a config file taking part in a merge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeSide {
+    Base,
+    Local,
+    Other,
+}
+
+impl fmt::Display for MergeSide {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            MergeSide::Base => "base",
+            MergeSide::Local => "local",
+            MergeSide::Other => "other",
+        };
+        f.write_str(name)
+    }
+}
+
+/// One of the versions passed to [`merge_configs`] could not be parsed.
+#[derive(Debug)]
+pub struct MergeError {
+    side: MergeSide,
+    error: Error,
+}
+
+impl MergeError {
+    /// The version that could not be parsed.
+    pub fn side(&self) -> MergeSide {
+        self.side
+    }
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} config: {}", self.side, self.error)
+    }
+}
+
+impl std::error::Error for MergeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Three-way merge `local` and `other` config text, given their common
+/// ancestor `base`.
+///
+/// A config is merged cleanly if only one side changed it, or both changed it
+/// the same way. It conflicts if both sides set it to different values, or
+/// one side removed it (or used `%unset`) while the other modified it.
+pub fn merge_configs(base: &str, local: &str, other: &str) -> Result<MergeOutcome, MergeError> {
+    let base = Version::parse(base, MergeSide::Base)?;
+    let local = Version::parse(local, MergeSide::Local)?;
+    let other = Version::parse(other, MergeSide::Other)?;
+
+    let mut edits: Vec<Edit> = Vec::new();
+    // Entries whose section does not exist in `local`, grouped by section.
+    let mut appended: Vec<(Option<&str>, Vec<Block>)> = Vec::new();
+
+    let mut seen = HashSet::new();
+    let keys = local.order.iter().chain(&other.order).chain(&base.order);
+    for key in keys.filter(|key| seen.insert(**key)) {
+        let (base_state, local_state, other_state) =
+            (base.state(key), local.state(key), other.state(key));
+        if local_state == other_state || other_state == base_state {
+            // `local` already has the merged result.
+            continue;
+        }
+
+        let block = if local_state == base_state {
+            match other.last_lines(key) {
+                Some(text) => Block {
+                    text,
+                    conflict: None,
+                },
+                None => {
+                    // Removed by `other`.
+                    for range in local.lines(key) {
+                        edits.push(Edit {
+                            range: range.clone(),
+                            block: None,
+                        });
+                    }
+                    continue;
+                }
+            }
+        } else {
+            let kind = match (local_state, other_state) {
+                (Some(EntryState::Set(_)), Some(EntryState::Set(_))) => ConflictKind::BothModified,
+                (Some(EntryState::Set(_)), _) | (_, Some(EntryState::Set(_))) => {
+                    ConflictKind::RemovedAndModified
+                }
+                _ => ConflictKind::BothModified,
+            };
+            let text = format!(
+                "<<<<<<< local\n{}=======\n{}>>>>>>> other\n",
+                local.last_lines(key).unwrap_or_default(),
+                other.last_lines(key).unwrap_or_default(),
+            );
+            Block {
+                text,
+                conflict: Some((*key, kind)),
+            }
+        };
+
+        if let Some(range) = local.lines(key).last() {
+            edits.push(Edit {
+                range: range.clone(),
+                block: Some(block),
+            });
+        } else if let Some(pos) = local.insert_position(key) {
+            edits.push(Edit {
+                range: pos..pos,
+                block: Some(block),
+            });
+        } else {
+            let section = key.section();
+            match appended.iter_mut().find(|(s, _)| *s == section) {
+                Some((_, blocks)) => blocks.push(block),
+                None => appended.push((section, vec![block])),
+            }
+        }
+    }
+
+    // Insertions sort before a replacement starting at the same position.
+    edits.sort_by_key(|edit| (edit.range.start, edit.range.end));
+
+    let mut text = String::with_capacity(local.text.len());
+    let mut conflicts = Vec::new();
+    let mut pos = 0;
+    for edit in edits {
+        text.push_str(&local.text[pos..edit.range.start]);
+        if let Some(block) = edit.block {
+            block.write(&mut text, &mut conflicts);
+        }
+        pos = edit.range.end;
+    }
+    text.push_str(&local.text[pos..]);
+
+    for (section, blocks) in appended {
+        if let Some(section) = section {
+            ensure_newline(&mut text);
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text.push_str(&format!("[{}]\n", section));
+        }
+        for block in blocks {
+            block.write(&mut text, &mut conflicts);
+        }
+    }
+
+    Ok(MergeOutcome { text, conflicts })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum EntryKey<'a> {
+    Config { section: &'a str, name: &'a str },
+    Include { path: &'a str },
+}
+
+impl<'a> EntryKey<'a> {
+    /// The section header an entry needs to be placed under, if any.
+    fn section(&self) -> Option<&'a str> {
+        match self {
+            EntryKey::Config { section, .. } if !section.is_empty() => Some(*section),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum EntryState<'a> {
+    Set(Cow<'a, str>),
+    Unset,
+    Included,
+}
+
+struct Entry<'a> {
+    /// State after the last occurrence of the entry.
+    state: EntryState<'a>,
+    /// Lines of each occurrence of the entry.
+    lines: Vec<Range<usize>>,
+}
+
+/// A parsed version of a config file.
+struct Version<'a> {
+    text: &'a str,
+    /// Entries in the order they first appear.
+    order: Vec<EntryKey<'a>>,
+    entries: HashMap<EntryKey<'a>, Entry<'a>>,
+}
+
+impl<'a> Version<'a> {
+    fn parse(text: &'a str, side: MergeSide) -> Result<Self, MergeError> {
+        let instructions = parse(text).map_err(|error| MergeError { side, error })?;
+        let mut order = Vec::new();
+        let mut entries: HashMap<EntryKey<'a>, Entry<'a>> = HashMap::new();
+        for inst in instructions {
+            let (key, state, span) = match inst {
+                Instruction::SetConfig {
+                    section,
+                    name,
+                    value,
+                    span,
+                } => (
+                    EntryKey::Config { section, name },
+                    EntryState::Set(value),
+                    span,
+                ),
+                Instruction::UnsetConfig {
+                    section,
+                    name,
+                    span,
+                } => (EntryKey::Config { section, name }, EntryState::Unset, span),
+                Instruction::Include { path, span } => {
+                    (EntryKey::Include { path }, EntryState::Included, span)
+                }
+            };
+            let lines = line_range(text, span);
+            match entries.entry(key) {
+                HashMapEntry::Occupied(mut e) => {
+                    let entry = e.get_mut();
+                    entry.state = state;
+                    entry.lines.push(lines);
+                }
+                HashMapEntry::Vacant(e) => {
+                    order.push(key);
+                    e.insert(Entry {
+                        state,
+                        lines: vec![lines],
+                    });
+                }
+            }
+        }
+        Ok(Self {
+            text,
+            order,
+            entries,
+        })
+    }
+
+    fn state(&self, key: &EntryKey<'a>) -> Option<&EntryState<'a>> {
+        self.entries.get(key).map(|entry| &entry.state)
+    }
+
+    fn lines(&self, key: &EntryKey<'a>) -> &[Range<usize>] {
+        self.entries
+            .get(key)
+            .map_or(&[], |entry| entry.lines.as_slice())
+    }
+
+    /// Text of the last occurrence of `key`, ending with a newline.
+    fn last_lines(&self, key: &EntryKey<'a>) -> Option<String> {
+        let range = self.lines(key).last()?;
+        let mut text = self.text[range.clone()].to_string();
+        ensure_newline(&mut text);
+        Some(text)
+    }
+
+    /// Where a new entry for `key` can be inserted: after the last entry in
+    /// the same section, or after the section header if the section is
+    /// empty. `None` if the section does not exist.
+    fn insert_position(&self, key: &EntryKey) -> Option<usize> {
+        let same_section = |other: &EntryKey| match (key, other) {
+            (EntryKey::Config { section, .. }, EntryKey::Config { section: s, .. }) => section == s,
+            (EntryKey::Include { .. }, EntryKey::Include { .. }) => true,
+            _ => false,
+        };
+        let after_entries = self
+            .entries
+            .iter()
+            .filter(|(other, _)| same_section(other))
+            .filter_map(|(_, entry)| entry.lines.last().map(|range| range.end))
+            .max();
+        match key {
+            EntryKey::Config { section, .. } => {
+                after_entries.or_else(|| self.section_header_end(section))
+            }
+            EntryKey::Include { .. } => after_entries,
+        }
+    }
+
+    fn section_header_end(&self, section: &str) -> Option<usize> {
+        if section.is_empty() {
+            return Some(0);
+        }
+        let mut end = None;
+        let mut offset = 0;
+        for line in self.text.split_inclusive('\n') {
+            offset += line.len();
+            let name = line
+                .strip_prefix('[')
+                .and_then(|rest| rest.split_once(']'))
+                .map(|(name, _)| name.trim());
+            if name == Some(section) {
+                end = Some(offset);
+            }
+        }
+        end
+    }
+}
+
+/// Replace `range` of the local text with `block`, or remove it if `block`
+/// is `None`. An empty `range` inserts `block`.
+struct Edit<'a> {
+    range: Range<usize>,
+    block: Option<Block<'a>>,
+}
+
+struct Block<'a> {
+    text: String,
+    conflict: Option<(EntryKey<'a>, ConflictKind)>,
+}
+
+impl<'a> Block<'a> {
+    fn write(self, out: &mut String, conflicts: &mut Vec<MergeConflict>) {
+        ensure_newline(out);
+        let start = out.len();
+        out.push_str(&self.text);
+        if let Some((key, kind)) = self.conflict {
+            let (section, name) = match key {
+                EntryKey::Config { section, name } => (section, name),
+                // Includes are either present or not, so they cannot
+                // conflict.
+                EntryKey::Include { path } => ("", path),
+            };
+            conflicts.push(MergeConflict {
+                section: section.to_string(),
+                name: name.to_string(),
+                kind,
+                span: start..out.len(),
+            });
+        }
+    }
+}
+
+/// Extend `span` to whole lines, including the trailing newline.
+fn line_range(text: &str, span: Range<usize>) -> Range<usize> {
+    let start = text[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let end = text[span.end..]
+        .find('\n')
+        .map_or(text.len(), |i| span.end + i + 1);
+    start..end
+}
+
+fn ensure_newline(text: &mut String) {
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
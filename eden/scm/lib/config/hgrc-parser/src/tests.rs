@@ -5,7 +5,10 @@
  * GNU General Public License version 2.
  */
 
+use crate::merge_configs;
 use crate::parse;
+use crate::ConflictKind;
+use crate::MergeSide;
 
 #[test]
 fn test_parse_basic() {
@@ -66,3 +69,179 @@ fn test_parse_error() {
         "line 1: unknown directive (expect '%include' or '%unset')"
     );
 }
+
+/// Merge `base`, `local` and `other`, returning the merged text followed by
+/// the conflicts (kind, config and conflict text), if any.
+fn merge(base: &str, local: &str, other: &str) -> String {
+    let outcome = merge_configs(base, local, other).unwrap();
+    let mut out = outcome.text.clone();
+    for conflict in &outcome.conflicts {
+        out.push_str(&format!(
+            "-- {:?} {}.{}\n{}",
+            conflict.kind,
+            conflict.section,
+            conflict.name,
+            &outcome.text[conflict.span.clone()]
+        ));
+    }
+    out
+}
+
+#[test]
+fn test_merge_different_keys() {
+    let base = "[a]\nx = 1\ny = 1\n";
+    let local = "[a]\n# changed x\nx = 2\ny = 1\n";
+    let other = "[a]\nx = 1\ny = 2\n";
+    assert_eq!(
+        merge(base, local, other),
+        "[a]\n# changed x\nx = 2\ny = 2\n"
+    );
+    // Merging is symmetric apart from which side's formatting is kept.
+    assert_eq!(merge(base, other, local), "[a]\nx = 2\ny = 2\n");
+}
+
+#[test]
+fn test_merge_same_change() {
+    let base = "[a]\nx = 1\n";
+    let local = "[a]\nx = 2\n";
+    let other = "[a]\nx=2\n";
+    assert_eq!(merge(base, local, other), "[a]\nx = 2\n");
+}
+
+#[test]
+fn test_merge_section_additions() {
+    let base = "[a]\nx = 1\n";
+    let local = "[a]\nx = 1\nl = 1\n\n# local section\n[l]\nk = 1\n";
+    let other = "[o]\nk = 1\nm = multi\n  line\n\n[a]\nx = 1\no = 1\n";
+    assert_eq!(
+        merge(base, local, other),
+        r#"[a]
+x = 1
+l = 1
+o = 1
+
+# local section
+[l]
+k = 1
+
+[o]
+k = 1
+m = multi
+  line
+"#
+    );
+}
+
+#[test]
+fn test_merge_removal() {
+    let base = "[a]\nx = 1\ny = 1\n";
+    let local = "[a]\n# comment\nx = 1\ny = 1\n";
+    let other = "[a]\ny = 1\n";
+    assert_eq!(merge(base, local, other), "[a]\n# comment\ny = 1\n");
+}
+
+#[test]
+fn test_merge_multiline_value() {
+    let base = "[a]\nx = 1\n  2\ny = 1\n";
+    let local = "[a]\nx = 1\n  2\ny = 2\n";
+    let other = "[a]\nx = 1\n  3\n  4\ny = 1\n";
+    assert_eq!(merge(base, local, other), "[a]\nx = 1\n  3\n  4\ny = 2\n");
+}
+
+#[test]
+fn test_merge_same_key_conflict() {
+    let base = "[a]\nx = 1\ny = 1\n";
+    let local = "[a]\nx = 2\ny = 1\n";
+    let other = "[a]\nx = 3\ny = 2";
+    assert_eq!(
+        merge(base, local, other),
+        r#"[a]
+<<<<<<< local
+x = 2
+=======
+x = 3
+>>>>>>> other
+y = 2
+-- BothModified a.x
+<<<<<<< local
+x = 2
+=======
+x = 3
+>>>>>>> other
+"#
+    );
+}
+
+#[test]
+fn test_merge_unset_conflict() {
+    let base = "[a]\nx = 1\n";
+    let local = "[a]\n%unset x\n";
+    let other = "[a]\nx = 2\n";
+    assert_eq!(
+        merge(base, local, other),
+        r#"[a]
+<<<<<<< local
+%unset x
+=======
+x = 2
+>>>>>>> other
+-- RemovedAndModified a.x
+<<<<<<< local
+%unset x
+=======
+x = 2
+>>>>>>> other
+"#
+    );
+
+    // Unsetting a config nobody else changed merges cleanly.
+    let other = "[a]\nx = 1\ny = 1\n";
+    assert_eq!(merge(base, local, other), "[a]\n%unset x\ny = 1\n");
+}
+
+#[test]
+fn test_merge_removed_conflict() {
+    let base = "[a]\nx = 1\ny = 1\n";
+    let local = "[a]\nx = 2\ny = 1\n";
+    let other = "[a]\ny = 1\n";
+    assert_eq!(
+        merge(base, other, local),
+        r#"[a]
+y = 1
+<<<<<<< local
+=======
+x = 2
+>>>>>>> other
+-- RemovedAndModified a.x
+<<<<<<< local
+=======
+x = 2
+>>>>>>> other
+"#
+    );
+}
+
+#[test]
+fn test_merge_includes() {
+    let base = "%include a\n[s]\nx = 1\n";
+    let local = "[s]\nx = 1\n";
+    let other = "%include a\n%include b\n[s]\nx = 1\n";
+    assert_eq!(merge(base, local, other), "[s]\nx = 1\n%include b\n");
+}
+
+#[test]
+fn test_merge_parse_error() {
+    let err = merge_configs("", "[a]\nx = 1\n", "[a\n").unwrap_err();
+    assert_eq!(err.side(), MergeSide::Other);
+    assert_eq!(
+        err.to_string(),
+        "other config: line 1: missing ']' for section header"
+    );
+    assert!(matches!(
+        merge_configs("[a]\nx = 1\n", "[a]\nx = 2\n", "[a]\nx = 3\n")
+            .unwrap()
+            .conflicts[0]
+            .kind,
+        ConflictKind::BothModified
+    ));
+}
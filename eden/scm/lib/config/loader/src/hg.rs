@@ -17,9 +17,11 @@ use std::hash::Hash;
 use std::io;
 use std::io::Error as IOError;
 use std::io::ErrorKind;
+use std::io::Read;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::bail;
 use anyhow::Context;
@@ -94,26 +96,64 @@ pub trait ConfigSetHgExt {
     fn validate_dynamic(&mut self) -> Result<(), Error>;
 }
 
+/// The name config read from stdin by `--configfile -` is attributed to.
+pub const STDIN_CONFIG_NAME: &str = "<stdin>";
+
 /// Load config from specified repo root path, or global config if no path specified.
 /// `extra_values` contains config overrides (i.e. "--config" CLI values).
 /// `extra_files` contains additional config files (i.e. "--configfile" CLI values).
+/// An `extra_files` entry of `-` reads config from stdin, named
+/// [`STDIN_CONFIG_NAME`], in which `%include` is not allowed.
 pub fn load(
     repo_path: Option<&Path>,
     extra_values: &[String],
     extra_files: &[String],
+) -> Result<ConfigSet> {
+    load_internal(repo_path, extra_values, extra_files, None)
+}
+
+/// Like [`load`], but stop loading config files once `deadline` has passed,
+/// for callers that can't wait for a hung file system, like interactive
+/// prompts. See `ConfigSet::load_path_with_deadline`.
+///
+/// If the deadline passes, the returned errors include an `Error::Timeout`
+/// naming the file that was being loaded. No partially loaded config is
+/// returned.
+pub fn load_with_deadline(
+    repo_path: Option<&Path>,
+    extra_values: &[String],
+    extra_files: &[String],
+    deadline: Instant,
+) -> Result<ConfigSet> {
+    load_internal(repo_path, extra_values, extra_files, Some(deadline))
+}
+
+fn load_internal(
+    repo_path: Option<&Path>,
+    extra_values: &[String],
+    extra_files: &[String],
+    deadline: Option<Instant>,
 ) -> Result<ConfigSet> {
     let mut cfg = ConfigSet::new();
 
-    let mut errors = Vec::new();
-    for path in extra_files {
-        errors.extend(cfg.load_path(&path, &"--configfile".into()));
+    // Config files are loaded twice, but stdin can only be read once.
+    let mut stdin = Vec::new();
+    if extra_files.iter().any(|path| path == "-") {
+        io::stdin()
+            .read_to_end(&mut stdin)
+            .map_err(|source| Error::Io {
+                path: STDIN_CONFIG_NAME.into(),
+                source,
+            })?;
     }
 
+    let mut errors = load_config_files(&mut cfg, extra_files, &stdin, deadline);
+
     if let Err(err) = set_overrides(&mut cfg, extra_values) {
         errors.push(err);
     }
 
-    match cfg.load::<Text, Text>(repo_path, None) {
+    match load_layers::<Text, Text>(&mut cfg, repo_path, None, true, deadline) {
         Ok(_) => {
             if !errors.is_empty() {
                 return Err(Errors(errors).into());
@@ -128,8 +168,9 @@ pub fn load(
     // Load the CLI configs again to make sure they take precedence.
     // The "readonly" facility can't be used to pin the configs
     // because it doesn't interact with the config verification properly.
-    for path in extra_files {
-        cfg.load_path(&path, &"--configfile".into());
+    let errors = load_config_files(&mut cfg, extra_files, &stdin, deadline);
+    if has_timeout(&errors) {
+        return Err(Errors(errors).into());
     }
 
     let _ = set_overrides(&mut cfg, extra_values);
@@ -137,6 +178,37 @@ pub fn load(
     Ok(cfg)
 }
 
+/// Load "--configfile" values. `-` loads `stdin`, the content read from stdin.
+fn load_config_files(
+    cfg: &mut ConfigSet,
+    paths: &[String],
+    stdin: &[u8],
+    deadline: Option<Instant>,
+) -> Vec<Error> {
+    let mut opts: Options = "--configfile".into();
+    if let Some(deadline) = deadline {
+        opts = opts.deadline(deadline);
+    }
+    let mut errors = Vec::new();
+    for path in paths {
+        if path == "-" {
+            errors.extend(cfg.load_reader(stdin, STDIN_CONFIG_NAME, &opts));
+        } else {
+            errors.extend(cfg.load_path(path, &opts));
+        }
+    }
+    errors
+}
+
+/// Load the config a user gets without any user or repo config files, that
+/// is, the builtin, dynamic and system configs. This is the baseline for
+/// `ConfigSet::non_default_items` when looking for what a user changed.
+pub fn load_defaults(repo_path: Option<&Path>) -> Result<ConfigSet> {
+    let mut cfg = ConfigSet::new();
+    load_layers::<Text, Text>(&mut cfg, repo_path, None, false, None)?;
+    Ok(cfg)
+}
+
 impl OptionsHgExt for Options {
     fn process_hgplain(self) -> Self {
         if hgplain::is_plain(None) {
@@ -281,74 +353,7 @@ impl ConfigSetHgExt for ConfigSet {
         repo_path: Option<&Path>,
         readonly_items: Option<Vec<(S, N)>>,
     ) -> Result<(), Errors> {
-        tracing::info!(
-            repo_path = %repo_path.and_then(|p| p.to_str()).unwrap_or("<none>"),
-            "loading config"
-        );
-
-        let ident = repo_path
-            .map(|p| identity::must_sniff_dir(p).map_err(|e| Errors(vec![Error::Other(e)])))
-            .transpose()?;
-        let ident = ident.unwrap_or_else(identity::default);
-
-        let repo_path = repo_path.map(|p| p.join(ident.dot_dir()));
-
-        let mut errors = vec![];
-
-        let mut opts = Options::new();
-        if let Some(readonly_items) = readonly_items {
-            opts = opts.readonly_items(readonly_items);
-        }
-
-        // The config priority from low to high is:
-        //
-        //   builtin
-        //   dynamic
-        //   system
-        //   user
-        //   repo
-        //
-        // We load things out of order a bit since the dynamic config can depend
-        // on system config (namely, auth_proxy.unix_socket_path).
-
-        // Clone rather than Self::new() so we include any --config overrides
-        // already inside self.
-        let mut dynamic = self.clone();
-
-        errors.append(&mut self.load_system(opts.clone(), &ident));
-        errors.append(&mut self.load_user(opts.clone(), &ident));
-
-        // This is the out-of-orderness. We load the dynamic config on a
-        // detached ConfigSet then combine it into our "secondary" config
-        // sources to maintain the correct priority.
-        errors.append(
-            &mut dynamic
-                .load_dynamic(
-                    repo_path.as_deref(),
-                    opts.clone(),
-                    &ident,
-                    self.get_opt("auth_proxy", "unix_socket_path")
-                        .unwrap_or_default(),
-                )
-                .map_err(|e| Errors(vec![Error::Other(e)]))?,
-        );
-
-        let mut low_prio_configs = crate::builtin_static::builtin_system(opts.clone(), &ident);
-        low_prio_configs.push(Arc::new(dynamic));
-        self.secondary(Arc::new(low_prio_configs));
-
-        if let Some(repo_path) = repo_path.as_deref() {
-            errors.append(&mut self.load_repo(repo_path, opts, &ident));
-            if let Err(e) = read_set_repo_name(self, repo_path) {
-                errors.push(e);
-            }
-        }
-
-        if !errors.is_empty() {
-            return Err(Errors(errors));
-        }
-
-        self.validate_dynamic().map_err(|err| Errors(vec![err]))
+        load_layers(self, repo_path, readonly_items, true, None)
     }
 
     fn load_system(&mut self, opts: Options, ident: &Identity) -> Vec<Error> {
@@ -590,6 +595,100 @@ impl ConfigSetHgExt for ConfigSet {
     }
 }
 
+/// Whether loading config was stopped by a deadline.
+fn has_timeout(errors: &[Error]) -> bool {
+    errors.iter().any(|e| matches!(e, Error::Timeout { .. }))
+}
+
+/// Load config layers in priority order. User and repo configs are only
+/// loaded if `user_and_repo` is set. Config files are only loaded before
+/// `deadline`, if any.
+fn load_layers<S: Into<Text>, N: Into<Text>>(
+    config: &mut ConfigSet,
+    repo_path: Option<&Path>,
+    readonly_items: Option<Vec<(S, N)>>,
+    user_and_repo: bool,
+    deadline: Option<Instant>,
+) -> Result<(), Errors> {
+    tracing::info!(
+        repo_path = %repo_path.and_then(|p| p.to_str()).unwrap_or("<none>"),
+        "loading config"
+    );
+
+    let ident = repo_path
+        .map(|p| identity::must_sniff_dir(p).map_err(|e| Errors(vec![Error::Other(e)])))
+        .transpose()?;
+    let ident = ident.unwrap_or_else(identity::default);
+
+    let repo_path = repo_path.map(|p| p.join(ident.dot_dir()));
+
+    let mut errors = vec![];
+
+    let mut opts = Options::new();
+    if let Some(readonly_items) = readonly_items {
+        opts = opts.readonly_items(readonly_items);
+    }
+    if let Some(deadline) = deadline {
+        opts = opts.deadline(deadline);
+    }
+
+    // The config priority from low to high is:
+    //
+    //   builtin
+    //   dynamic
+    //   system
+    //   user
+    //   repo
+    //
+    // We load things out of order a bit since the dynamic config can depend
+    // on system config (namely, auth_proxy.unix_socket_path).
+
+    // Clone rather than ConfigSet::new() so we include any --config overrides
+    // already inside config.
+    let mut dynamic = config.clone();
+
+    errors.append(&mut config.load_system(opts.clone(), &ident));
+    if user_and_repo {
+        errors.append(&mut config.load_user(opts.clone(), &ident));
+    }
+    if has_timeout(&errors) {
+        return Err(Errors(errors));
+    }
+
+    // This is the out-of-orderness. We load the dynamic config on a
+    // detached ConfigSet then combine it into our "secondary" config
+    // sources to maintain the correct priority.
+    errors.append(
+        &mut dynamic
+            .load_dynamic(
+                repo_path.as_deref(),
+                opts.clone(),
+                &ident,
+                config
+                    .get_opt("auth_proxy", "unix_socket_path")
+                    .unwrap_or_default(),
+            )
+            .map_err(|e| Errors(vec![Error::Other(e)]))?,
+    );
+
+    let mut low_prio_configs = crate::builtin_static::builtin_system(opts.clone(), &ident);
+    low_prio_configs.push(Arc::new(dynamic));
+    config.secondary(Arc::new(low_prio_configs));
+
+    if let (Some(repo_path), true) = (repo_path.as_deref(), user_and_repo) {
+        errors.append(&mut config.load_repo(repo_path, opts, &ident));
+        if let Err(e) = read_set_repo_name(config, repo_path) {
+            errors.push(e);
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(Errors(errors));
+    }
+
+    config.validate_dynamic().map_err(|err| Errors(vec![err]))
+}
+
 /// Read repo name from various places (remotefilelog.reponame, paths.default, .hg/reponame).
 ///
 /// Try to write the reponame back to `.hg/reponame`, and set `remotefilelog.reponame`
@@ -799,7 +898,10 @@ fn get_config_dir(repo_path: Option<&Path>) -> Result<PathBuf, Error> {
         Some(repo_path) => {
             let shared_path = repo_path.join("sharedpath");
             if shared_path.exists() {
-                let raw = read_to_string(&shared_path).map_err(|e| Error::Io(shared_path, e))?;
+                let raw = read_to_string(&shared_path).map_err(|source| Error::Io {
+                    path: shared_path,
+                    source,
+                })?;
                 let trimmed = raw.trim_end_matches("\n");
                 // sharedpath can be relative, so join it with repo_path.
                 repo_path.join(trimmed)
@@ -1273,6 +1375,85 @@ mod tests {
         assert_eq!(cfg.get("s", "c"), Some("orig".into()));
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_load_with_deadline() {
+        let mut env = lock_env();
+
+        // Skip real dynamic config.
+        env.set("TESTTMP", Some("1"));
+
+        let dir = TempDir::new("test_load_with_deadline").unwrap();
+        let repo_rc = dir.path().join(".hg/hgrc");
+        write_file(repo_rc, "[s]\na=orig\n%include stuck.rc\n");
+
+        let cfg = load_with_deadline(
+            Some(dir.path()),
+            &["s.b=flag".to_string()],
+            &[],
+            Instant::now() + std::time::Duration::from_secs(60),
+        )
+        .unwrap();
+        assert_eq!(cfg.get("s", "a"), Some("orig".into()));
+        assert_eq!(cfg.get("s", "b"), Some("flag".into()));
+
+        // Opening a FIFO without a writer blocks, like a hung network mount.
+        let fifo = dir.path().join(".hg/stuck.rc");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let err = match load_with_deadline(
+            Some(dir.path()),
+            &[],
+            &[],
+            Instant::now() + std::time::Duration::from_millis(200),
+        ) {
+            Ok(_) => panic!("loading should time out"),
+            Err(err) => err,
+        };
+        let errors = err.downcast_ref::<Errors>().unwrap();
+        assert!(
+            errors.0.iter().any(|e| matches!(
+                e,
+                Error::Timeout { path, .. } if path == &fifo.canonicalize().unwrap()
+            )),
+            "{:?}",
+            errors
+        );
+    }
+
+    #[test]
+    fn test_load_config_files_stdin() {
+        let dir = TempDir::new("test_load_config_files_stdin").unwrap();
+        let other_rc = dir.path().join("other.rc");
+        write_file(other_rc.clone(), "[s]\na=other\nb=other");
+
+        let mut cfg = ConfigSet::new();
+        let errors = load_config_files(
+            &mut cfg,
+            &[format!("{}", other_rc.display()), "-".to_string()],
+            b"[s]\nb=stdin\n",
+            None,
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        assert_eq!(cfg.get("s", "a"), Some("other".into()));
+        assert_eq!(cfg.get("s", "b"), Some("stdin".into()));
+        let sources = cfg.get_sources("s", "b");
+        assert_eq!(sources[1].source(), &"--configfile");
+        assert_eq!(
+            sources[1].location().unwrap().0,
+            PathBuf::from(STDIN_CONFIG_NAME)
+        );
+
+        let errors = load_config_files(&mut cfg, &["-".to_string()], b"%include other.rc\n", None);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], Error::UnresolvedInclude { .. }));
+    }
+
     #[test]
     fn test_repo_name_from_url() {
         let config = BTreeMap::<&str, &str>::from([("schemes.fb", "mononoke://example.com/{1}")]);
@@ -44,6 +44,19 @@ pub trait Config: Send + Sync {
     /// - `Some(Some(value))`: set.
     fn get_considering_unset(&self, section: &str, name: &str) -> Option<Option<Text>>;
 
+    /// Similar to `get_considering_unset`, but report errors computing the
+    /// value, like a secret that cannot be resolved, instead of treating the
+    /// config as not set.
+    fn try_get_considering_unset(&self, section: &str, name: &str) -> Result<Option<Option<Text>>> {
+        Ok(self.get_considering_unset(section, name))
+    }
+
+    /// Similar to `get_considering_unset`, but return values referring to a
+    /// secret as they are written, without resolving them.
+    fn get_unresolved(&self, section: &str, name: &str) -> Option<Option<Text>> {
+        self.get_considering_unset(section, name)
+    }
+
     /// Get a nonempty config value for a given config.
     /// Return `None` if the config item does not exist, is unset or is empty str.
     fn get_nonempty(&self, section: &str, name: &str) -> Option<Text> {
@@ -53,7 +66,8 @@ pub trait Config: Send + Sync {
     /// Get config sections.
     fn sections(&self) -> Cow<[Text]>;
 
-    /// Get the sources of a config.
+    /// Get the sources of a config, in the order they were set. The last
+    /// one is the effective value, or an unset.
     fn get_sources(&self, section: &str, name: &str) -> Cow<[ValueSource]>;
 
     /// Get on-disk files loaded for this `Config`.
@@ -80,16 +94,22 @@ pub trait Config: Send + Sync {
 
 /// Extra APIs (incompatible with trait objects) around reading config.
 pub trait ConfigExt: Config {
+    /// Similar to `get`, but report errors computing the value.
+    fn try_get(&self, section: &str, name: &str) -> Result<Option<Text>> {
+        Ok(self.try_get_considering_unset(section, name)?.flatten())
+    }
+
     /// Get a config item. Convert to type `T`.
     fn get_opt<T: FromConfigValue>(&self, section: &str, name: &str) -> Result<Option<T>> {
-        self.get(section, name)
+        self.try_get(section, name)?
             .map(|bytes| T::try_from_str(&bytes))
             .transpose()
     }
 
     /// Get a nonempty config item. Convert to type `T`.
     fn get_nonempty_opt<T: FromConfigValue>(&self, section: &str, name: &str) -> Result<Option<T>> {
-        self.get_nonempty(section, name)
+        self.try_get(section, name)?
+            .filter(|v| !v.is_empty())
             .map(|bytes| T::try_from_str(&bytes))
             .transpose()
     }
@@ -217,11 +237,18 @@ pub struct ValueSource {
 
 /// The on-disk file name and byte offsets that provide the config value.
 /// Useful if applications want to edit config values in-place.
+///
+/// For config not read from a file, like config read from stdin, `path` is
+/// a virtual name such as `<stdin>`. `content` and `location` are still the
+/// content that was read and the byte offsets in it.
 #[derive(Clone, Debug)]
 pub struct ValueLocation {
     pub path: Arc<PathBuf>,
     pub content: Text,
     pub location: Range<usize>,
+    /// Whether `path` was loaded by a `%include`, directly or not, instead
+    /// of being loaded by itself.
+    pub included: bool,
 }
 
 impl ValueSource {
@@ -246,6 +273,12 @@ impl ValueSource {
             .map(|src| (src.path.as_ref().to_path_buf(), src.location.clone()))
     }
 
+    /// Return whether the value comes from a file loaded by `%include`.
+    /// `false` if there is no location information.
+    pub fn is_included(&self) -> bool {
+        self.location.as_ref().is_some_and(|src| src.included)
+    }
+
     /// Return the file content. Or `None` if there is no such information.
     pub fn file_content(&self) -> Option<Text> {
         self.location.as_ref().map(|src| src.content.clone())
@@ -8,33 +8,128 @@
 use std::ffi::CString;
 use std::fmt;
 use std::io;
+use std::path::Path;
 use std::path::PathBuf;
 use std::str;
+use std::time::Duration;
 
 use thiserror::Error;
 
 /// The error type for parsing config files.
+///
+/// Errors about a config file keep the underlying error available through
+/// [`std::error::Error::source`], so callers can find out what went wrong by
+/// downcasting instead of matching on messages. The `Display` format of each
+/// variant is documented and considered stable.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// Unable to convert to a type.
     #[error("{0}")]
     Convert(String),
 
     /// Unable to parse a file due to syntax.
-    #[error("{0:?}:\n{1}")]
-    ParseFile(PathBuf, String),
+    ///
+    /// Displayed as `"<path>":` followed by a newline and the parser error,
+    /// which reads `line <line>: <message>`.
+    #[error("{path:?}:\n{source}")]
+    Parse {
+        path: PathBuf,
+        /// 1-based line number of the syntax error.
+        line: usize,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
 
     /// Unable to parse a flag due to syntax.
     #[error("malformed --config option: '{0}' (use --config section.name=value)")]
     ParseFlag(String),
 
     /// Unable to read a file due to IO errors.
-    #[error("{0:?}: {1}")]
-    Io(PathBuf, #[source] io::Error),
+    ///
+    /// Displayed as `"<path>": <io error>`.
+    #[error("{path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
 
     /// Config file contains invalid UTF-8.
-    #[error("{0:?}: {1}")]
-    Utf8(PathBuf, #[source] str::Utf8Error),
+    ///
+    /// Displayed as `"<path>": <utf-8 error>`.
+    #[error("{path:?}: {source}")]
+    Utf8 {
+        path: PathBuf,
+        source: str::Utf8Error,
+    },
+
+    /// `%include` in content that has no directory to resolve it against,
+    /// like config read from stdin.
+    ///
+    /// Displayed as `"<path>": cannot resolve %include <include> without a base directory`.
+    #[error("{path:?}: cannot resolve %include {include} without a base directory")]
+    UnresolvedInclude { path: PathBuf, include: String },
+
+    /// `%include` of a Windows absolute path, like `C:\x` or
+    /// `\\server\share\x`, on a platform other than Windows.
+    ///
+    /// Displayed as `"<path>": %include <include> is a Windows path, which cannot be included on this platform`.
+    #[error(
+        "{path:?}: %include {include} is a Windows path, which cannot be included on this platform"
+    )]
+    WindowsInclude { path: PathBuf, include: String },
+
+    /// A `%include` glob pattern of `path` matched a file that is being
+    /// loaded. `chain` lists the files including each other, from the
+    /// first loaded one to the matched one. The matched file is not loaded
+    /// again.
+    ///
+    /// Displayed as `"<path>": %include <include> is a cycle: <chain>`,
+    /// with the files of the chain separated by ` -> `.
+    #[error("{path:?}: %include {include} is a cycle: {}", format_chain(.chain))]
+    IncludeCycle {
+        path: PathBuf,
+        include: String,
+        chain: Vec<PathBuf>,
+    },
+
+    /// `%include` of a URL without a fetcher set by
+    /// `Options::remote_fetcher`.
+    ///
+    /// Displayed as `"<path>": cannot fetch %include <url>: remote includes are disabled`.
+    #[error("{path:?}: cannot fetch %include {url}: remote includes are disabled")]
+    RemoteIncludeDisabled { path: PathBuf, url: String },
+
+    /// `%include` of a URL that starts with none of the allowed prefixes.
+    ///
+    /// Displayed as `"<path>": %include <url> is not an allowed remote source`.
+    #[error("{path:?}: %include {url} is not an allowed remote source")]
+    RemoteIncludeNotAllowed { path: PathBuf, url: String },
+
+    /// Fetching the remote include `url` of `path` failed.
+    ///
+    /// Displayed as `"<path>": cannot fetch %include <url>: <error>`.
+    #[error("{path:?}: cannot fetch %include {url}: {source}")]
+    RemoteInclude {
+        path: PathBuf,
+        url: String,
+        source: anyhow::Error,
+    },
+
+    /// The content of the remote include `url` of `path`, of `size` bytes,
+    /// exceeds the limit of `max` bytes. It is not loaded.
+    ///
+    /// Displayed as `"<path>": %include <url> is too large (<size> bytes, limit <max>)`.
+    #[error("{path:?}: %include {url} is too large ({size} bytes, limit {max})")]
+    RemoteIncludeTooLarge {
+        path: PathBuf,
+        url: String,
+        size: usize,
+        max: usize,
+    },
+
+    /// Loading config did not finish before a deadline. `path` is the file
+    /// that was being loaded, and `elapsed` the time spent loading.
+    ///
+    /// Displayed as `"<path>": timed out after <elapsed>`.
+    #[error("{path:?}: timed out after {elapsed:?}")]
+    Timeout { path: PathBuf, elapsed: Duration },
 
     #[error("{0:?}: {1}")]
     Utf8Path(CString, #[source] str::Utf8Error),
@@ -51,10 +146,70 @@ pub enum Error {
     #[error("config {0}.{1} is not set")]
     NotSet(String, String),
 
+    /// Different default values were registered for the same config.
+    #[error(
+        "conflicting defaults registered for {section}.{name}: {registered:?} and {conflicting:?}"
+    )]
+    DefaultConflict {
+        section: String,
+        name: String,
+        /// The default registered first, which is kept.
+        registered: String,
+        conflicting: String,
+    },
+
+    /// A config value refers to a secret that cannot be resolved. Only the
+    /// reference is kept, never the secret.
+    ///
+    /// Displayed as `cannot resolve secret <reference> of <section>.<name>: <error>`.
+    #[error("cannot resolve secret {reference} of {section}.{name}: {source}")]
+    Secret {
+        section: String,
+        name: String,
+        reference: String,
+        source: anyhow::Error,
+    },
+
     #[error("{0}")]
     Other(#[source] anyhow::Error),
 }
 
+impl Error {
+    /// The config file this error is about, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::Parse { path, .. }
+            | Error::Io { path, .. }
+            | Error::Utf8 { path, .. }
+            | Error::UnresolvedInclude { path, .. }
+            | Error::WindowsInclude { path, .. }
+            | Error::IncludeCycle { path, .. }
+            | Error::RemoteIncludeDisabled { path, .. }
+            | Error::RemoteIncludeNotAllowed { path, .. }
+            | Error::RemoteInclude { path, .. }
+            | Error::RemoteIncludeTooLarge { path, .. }
+            | Error::Timeout { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// The 1-based line of a syntax error.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Error::Parse { line, .. } => Some(*line),
+            _ => None,
+        }
+    }
+}
+
+fn format_chain(chain: &[PathBuf]) -> String {
+    let chain: Vec<_> = chain
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    chain.join(" -> ")
+}
+
 impl From<String> for Error {
     fn from(s: String) -> Self {
         Self::General(s)
@@ -72,3 +227,34 @@ impl fmt::Display for Errors {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+
+    use super::*;
+
+    #[test]
+    fn test_io_error_source_chain() {
+        let err = Error::Io {
+            path: PathBuf::from("missing.rc"),
+            source: io::Error::new(io::ErrorKind::NotFound, "not found"),
+        };
+        assert_eq!(err.path(), Some(Path::new("missing.rc")));
+        assert_eq!(err.line(), None);
+        assert_eq!(format!("{}", err), "\"missing.rc\": not found");
+
+        let io_err = err
+            .source()
+            .and_then(|e| e.downcast_ref::<io::Error>())
+            .expect("source should be the io::Error");
+        assert_eq!(io_err.kind(), io::ErrorKind::NotFound);
+
+        // The chain survives conversion into anyhow.
+        let err = anyhow::Error::from(err).context("loading config");
+        assert!(err.chain().any(|e| matches!(
+            e.downcast_ref::<io::Error>(),
+            Some(e) if e.kind() == io::ErrorKind::NotFound
+        )));
+    }
+}
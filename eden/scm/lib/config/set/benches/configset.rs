@@ -0,0 +1,129 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use configset::Config;
+use configset::ConfigExt;
+use configset::ConfigSet;
+use configset::KeyHandle;
+use configset::Options;
+use minibench::bench;
+use minibench::elapsed;
+use tempdir::TempDir;
+
+/// Number of reads measured by each lookup benchmark.
+const READS: usize = 100_000;
+
+/// Items read over and over by the hg CLI.
+const HOT_KEYS: &[(&str, &str)] = &[
+    ("ui", "quiet"),
+    ("ui", "verbose"),
+    ("ui", "debug"),
+    ("ui", "interactive"),
+    ("experimental", "narrow-heads"),
+    ("experimental", "evolution"),
+    ("devel", "all-warnings"),
+    ("tracing", "threshold"),
+];
+
+/// Content of the config file at `level`, like system, user or repo config:
+/// 40 sections of 50 items, a few overriding each other between levels,
+/// along with the hot keys.
+fn config_file(level: usize) -> String {
+    let mut content = String::new();
+    for section in 0..40 {
+        content += &format!("[section{}]\n", section);
+        for item in 0..50 {
+            content += &format!("item{} = value {} of level {}\n", item, item, level);
+        }
+    }
+    content += "[ui]\nquiet = false\nverbose = false\ndebug = false\ninteractive = true\n";
+    content += "[experimental]\nnarrow-heads = true\nevolution = obsolete\n";
+    content += "[devel]\nall-warnings = false\n";
+    content += "[tracing]\nthreshold = 10\n";
+    content
+}
+
+fn main() {
+    let dir = TempDir::new("configset").unwrap();
+    let paths: Vec<_> = (0..3)
+        .map(|level| {
+            let path = dir.path().join(format!("{}.rc", level));
+            std::fs::write(&path, config_file(level)).unwrap();
+            path
+        })
+        .collect();
+    let load = || {
+        let mut config = ConfigSet::new();
+        for path in &paths {
+            let errors = config.load_path(path, &Options::new());
+            assert!(errors.is_empty());
+        }
+        config
+    };
+
+    bench("load 3 files of 2000 items", || {
+        elapsed(|| {
+            load();
+        })
+    });
+
+    let config = load();
+    let keys = HOT_KEYS.iter().cycle().take(READS);
+
+    bench("get (100K hot keys)", || {
+        elapsed(|| {
+            for (section, name) in keys.clone() {
+                assert!(config.get(section, name).is_some());
+            }
+        })
+    });
+
+    bench("get (100K missing keys)", || {
+        elapsed(|| {
+            for (_, name) in keys.clone() {
+                assert!(config.get("missing", name).is_none());
+            }
+        })
+    });
+
+    bench("get_sources (100K hot keys)", || {
+        elapsed(|| {
+            for (section, name) in keys.clone() {
+                assert_eq!(config.get_sources(section, name).len(), 3);
+            }
+        })
+    });
+
+    bench("get_opt::<bool> (100K reads)", || {
+        elapsed(|| {
+            for _ in 0..READS {
+                assert_eq!(config.get_opt::<bool>("ui", "quiet").unwrap(), Some(false));
+            }
+        })
+    });
+
+    let handles: Vec<_> = HOT_KEYS
+        .iter()
+        .map(|(section, name)| config.key_handle(section, name))
+        .collect();
+    bench("KeyHandle::get (100K hot keys)", || {
+        elapsed(|| {
+            for handle in handles.iter().cycle().take(READS) {
+                assert!(handle.get(&config).is_some());
+            }
+        })
+    });
+
+    let quiet = KeyHandle::new("ui", "quiet");
+    bench("KeyHandle::get_opt::<bool> (100K reads)", || {
+        elapsed(|| {
+            for _ in 0..READS {
+                assert_eq!(quiet.get_opt::<bool>(&config).unwrap(), Some(false));
+            }
+        })
+    });
+}
@@ -8,11 +8,21 @@
 use std::borrow::Cow;
 use std::collections::HashSet;
 use std::fs;
+use std::hash::BuildHasherDefault;
 use std::hash::Hash;
+use std::hash::Hasher;
+use std::io::Read;
+use std::ops::Range;
 use std::path::Path;
 use std::path::PathBuf;
+use std::path::MAIN_SEPARATOR;
 use std::str;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
 use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
 
 use configmodel::Config;
 pub use configmodel::ValueLocation;
@@ -24,24 +34,83 @@ use indexmap::IndexSet;
 use minibytes::Text;
 use util::path::expand_path;
 
+use crate::defaults::RegisteredDefaults;
 use crate::error::Error;
+use crate::glob;
+use crate::handle::KeyHandle;
+use crate::intern::Interner;
+use crate::remote;
+use crate::remote::CacheValidity;
+use crate::remote::RemoteFetcher;
+use crate::remote::RemoteIncludes;
+use crate::remote::Warning;
+use crate::secret::SecretResolver;
+use crate::secret::Secrets;
+use crate::winpath;
 
 /// Collection of config sections loaded from various sources.
 #[derive(Clone, Default)]
 pub struct ConfigSet {
     name: Text,
-    sections: IndexMap<Text, Section>,
+    sections: NameMap<Section>,
     // Canonicalized files that were loaded, including files with errors
     files: Vec<PathBuf>,
+    // Canonicalized directories that were included, with the sorted list of
+    // files each of them expanded to.
+    dir_includes: Vec<(PathBuf, Vec<PathBuf>)>,
     // Secondary, immutable config to try out if `sections` does not
     // contain the requested config.
     secondary: Option<Arc<dyn Config>>,
+    // Shared by clones until one of them is changed.
+    interner: Arc<Interner>,
+    // Defaults registered by `register_defaults`. Shared by clones until
+    // one of them registers more.
+    defaults: Arc<RegisteredDefaults>,
+    // Resolver of values referring to secrets, and the resolved secrets.
+    secrets: Secrets,
+    // Changed whenever `sections` is, to invalidate `KeyHandle`s. Shared by
+    // clones until one of them is changed.
+    generation: u64,
+    // Reported while loading, without stopping it.
+    warnings: Vec<Warning>,
 }
 
+/// Source of `ConfigSet` generations. Generations are unique across all
+/// `ConfigSet`s, so a `KeyHandle` resolved for one `ConfigSet` is never
+/// reused for another with different items. Only empty `ConfigSet`s have
+/// generation 0.
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
 /// Internal representation of a config section.
 #[derive(Clone, Default, Debug)]
 struct Section {
-    items: IndexMap<Text, Vec<ValueSource>>,
+    items: NameMap<Vec<ValueSource>>,
+}
+
+/// Map of section or item names, looked up on every read.
+type NameMap<V> = IndexMap<Text, V, BuildHasherDefault<NameHasher>>;
+
+/// FNV-1a, which hashes short names like section and item names several
+/// times faster than the default hasher. Names come from config files, not
+/// from untrusted input, so collision attacks are not a concern.
+struct NameHasher(u64);
+
+impl Default for NameHasher {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for NameHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
 }
 
 /// Options that affects config setting functions like `load_path`, `parse`,
@@ -49,6 +118,12 @@ struct Section {
 #[derive(Clone, Default)]
 pub struct Options {
     source: Text,
+    include_base: Option<PathBuf>,
+    // Files whose `%include`s led to the content being loaded, outermost
+    // first. Empty unless the content is loaded by `%include`.
+    include_chain: Vec<PathBuf>,
+    deadline: Option<Deadline>,
+    remote: RemoteIncludes,
     filters: Vec<Arc<Box<dyn Fn(Text, Text, Option<Text>) -> Option<(Text, Text, Option<Text>)>>>>,
 }
 
@@ -74,17 +149,29 @@ impl Config for ConfigSet {
     /// Get config value for a given config.
     /// Return `None` if the config item does not exist.
     /// Return `Some(None)` if the config is is unset.
+    ///
+    /// A secret that cannot be resolved is logged and treated as not set.
     fn get_considering_unset(&self, section: &str, name: &str) -> Option<Option<Text>> {
-        let self_value = (|| -> Option<Option<Text>> {
-            let section = self.sections.get(section)?;
-            let value_sources: &Vec<ValueSource> = section.items.get(name)?;
-            let value = value_sources.last()?.value.clone();
-            Some(value)
-        })();
-        if let (None, Some(secondary)) = (&self_value, &self.secondary) {
-            return secondary.get_considering_unset(section, name);
+        self.get_at(self.item_index(section, name), section, name)
+    }
+
+    /// Values referring to a secret are resolved if a resolver is set.
+    /// Values from the secondary config are resolved by the secondary config.
+    fn try_get_considering_unset(
+        &self,
+        section: &str,
+        name: &str,
+    ) -> crate::Result<Option<Option<Text>>> {
+        self.try_get_at(self.item_index(section, name), section, name)
+    }
+
+    /// Values from the secondary config are looked up unresolved too.
+    fn get_unresolved(&self, section: &str, name: &str) -> Option<Option<Text>> {
+        let self_value = self.own_value_at(self.item_index(section, name));
+        match (self_value, &self.secondary) {
+            (None, Some(secondary)) => secondary.get_unresolved(section, name),
+            (self_value, _) => self_value,
         }
-        self_value
     }
 
     /// Get config sections.
@@ -160,9 +247,101 @@ impl Config for ConfigSet {
     }
 }
 
+/// List config files that an include of directory `dir` expands to.
+///
+/// Only regular files (or symlinks to regular files) directly inside `dir`
+/// are considered. Subdirectories, including symlinked ones, are not
+/// recursed into. A file is selected if its name ends with `.rc`, does not
+/// start with `.`, and does not end with `~` or `.bak`. The result is sorted
+/// by file name, compared byte-wise, so the load order does not depend on
+/// the order the filesystem returns directory entries in.
+pub(crate) fn include_dir_entries(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if !is_included_file_name(&name.to_string_lossy()) {
+            continue;
+        }
+        // `fs::metadata` follows symlinks. A dangling symlink is skipped.
+        match fs::metadata(entry.path()) {
+            Ok(metadata) if metadata.is_file() => names.push(name),
+            _ => continue,
+        }
+    }
+    names.sort();
+    Ok(names.into_iter().map(|name| dir.join(name)).collect())
+}
+
+fn is_included_file_name(name: &str) -> bool {
+    name.ends_with(".rc")
+        && !name.starts_with('.')
+        && !name.ends_with('~')
+        && !name.ends_with(".bak")
+}
+
+/// How `%include` is handled in loaded content.
+#[derive(Clone, Copy)]
+enum Includes<'a> {
+    /// Resolve includes relative to a directory.
+    RelativeTo(&'a Path),
+    /// Skip includes.
+    Ignore,
+    /// Report includes, other than of builtin configs and URLs, as errors.
+    Reject,
+    /// Resolve includes relative to the URL of a remote include.
+    Remote(&'a str),
+}
+
+/// When loading config files has to stop, set by `Options::deadline`.
+#[derive(Clone, Copy)]
+struct Deadline {
+    started: Instant,
+    at: Instant,
+}
+
+impl Deadline {
+    fn timeout(&self, path: &Path) -> Error {
+        Error::Timeout {
+            path: path.to_path_buf(),
+            elapsed: self.started.elapsed(),
+        }
+    }
+}
+
+/// Run `f`, which does file system IO, before `deadline` if there is one.
+///
+/// With a deadline, `f` runs on a helper thread so that a hung `open()`, for
+/// example on an unresponsive network mount, can't block past the deadline.
+/// Return `None` if `f` didn't finish in time, which can only happen with a
+/// deadline. The helper thread is then left behind, since blocking IO can't
+/// be interrupted.
+fn run_until<T: Send + 'static>(
+    deadline: Option<&Deadline>,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Option<T> {
+    let deadline = match deadline {
+        Some(deadline) => deadline.at,
+        None => return Some(f()),
+    };
+    let (sender, receiver) = mpsc::sync_channel(1);
+    let spawned = thread::Builder::new()
+        .name("config-load".to_string())
+        .spawn(move || {
+            let _ = sender.send(f());
+        });
+    if let Err(error) = spawned {
+        tracing::warn!("cannot spawn thread to load config: {}", error);
+        return None;
+    }
+    receiver
+        .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        .ok()
+}
+
 /// Merge two lists. Preserve order (a is before b). Remove duplicated items.
 /// Assumes `a` and `b` do not have duplicated items respectively.
-fn merge_cow_list<'a, T: Clone + Hash + Eq>(a: Cow<'a, [T]>, b: Cow<'a, [T]>) -> Cow<'a, [T]> {
+pub(crate) fn merge_cow_list<'a, T: Clone + Hash + Eq>(a: Cow<'a, [T]>, b: Cow<'a, [T]>) -> Cow<'a, [T]> {
     if a.is_empty() {
         b
     } else if b.is_empty() {
@@ -174,6 +353,54 @@ fn merge_cow_list<'a, T: Clone + Hash + Eq>(a: Cow<'a, [T]>, b: Cow<'a, [T]>) ->
     }
 }
 
+/// Return the text of the item setting `section.name` to the value of
+/// `source` in the file it was loaded from, with the comment lines right
+/// above it. Return `None` if the value was not loaded from a file, or if
+/// the text doesn't parse back to the same value, like for values changed
+/// by filters.
+fn original_item_text(section: &str, name: &str, source: &ValueSource) -> Option<String> {
+    let content = source.file_content()?;
+    let (_, range) = source.location()?;
+    let text: &str = content.as_ref();
+    let line_start = |pos: usize| text[..pos].rfind('\n').map_or(0, |i| i + 1);
+
+    // The span covers the value, which starts on the line with the name.
+    let start = line_start(range.start);
+    let end = text[range.end..]
+        .find('\n')
+        .map_or(text.len(), |i| range.end + i);
+    let item = &text[start..end];
+
+    let reparsed = format!("[{}]\n{}\n", section, item);
+    let matches = match parse(&reparsed).ok()?.as_slice() {
+        [Instruction::SetConfig {
+            section: s,
+            name: n,
+            value,
+            ..
+        }] => *s == section && *n == name && Some(value.as_ref()) == source.value().as_deref(),
+        [Instruction::UnsetConfig {
+            section: s,
+            name: n,
+            ..
+        }] => *s == section && *n == name && source.value().is_none(),
+        _ => false,
+    };
+    if !matches {
+        return None;
+    }
+
+    let mut comment_start = start;
+    while comment_start > 0 {
+        let prev = line_start(comment_start - 1);
+        if !text[prev..].starts_with(['#', ';']) {
+            break;
+        }
+        comment_start = prev;
+    }
+    Some(text[comment_start..end].to_string())
+}
+
 impl ConfigSet {
     /// Return an empty `ConfigSet`.
     pub fn new() -> Self {
@@ -193,15 +420,112 @@ impl ConfigSet {
         self
     }
 
+    /// Resolve config values referring to a secret, which start with the
+    /// secret prefix (`secret:` by default), with `resolver` when they are
+    /// read. The resolver gets the value without the prefix.
+    ///
+    /// Resolved secrets are cached until `purge_secrets`. `get_sources`,
+    /// serialization and export still show the references.
+    ///
+    /// A value starting with `\` followed by the prefix is not a reference.
+    /// It is read without the `\`.
+    ///
+    /// Without a resolver, values are read as they are.
+    pub fn set_secret_resolver(&mut self, resolver: SecretResolver) -> &mut Self {
+        self.secrets.set_resolver(resolver);
+        self
+    }
+
+    /// Change the prefix of config values referring to a secret.
+    pub fn set_secret_prefix(&mut self, prefix: &str) -> &mut Self {
+        self.secrets.set_prefix(prefix);
+        self
+    }
+
+    /// Forget resolved secrets, so they are resolved again when read.
+    ///
+    /// Clones of this `ConfigSet` share the resolved secrets, so this purges
+    /// them too.
+    pub fn purge_secrets(&self) {
+        self.secrets.purge();
+    }
+
+    /// Return a handle to read `section.name` repeatedly, faster than with
+    /// `get`. See `KeyHandle`.
+    pub fn key_handle(&self, section: &str, name: &str) -> KeyHandle {
+        let handle = KeyHandle::new(section, name);
+        handle.get_considering_unset(self);
+        handle
+    }
+
+    /// Changed whenever items are changed.
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Index of the section and of the item of `section.name`, if set.
+    pub(crate) fn item_index(&self, section: &str, name: &str) -> Option<(usize, usize)> {
+        let (section_index, _, section) = self.sections.get_full(section)?;
+        let (item_index, _, _) = section.items.get_full(name)?;
+        Some((section_index, item_index))
+    }
+
+    /// `get_considering_unset` of the item at `index`, as returned by
+    /// `item_index` for `section.name`.
+    pub(crate) fn get_at(
+        &self,
+        index: Option<(usize, usize)>,
+        section: &str,
+        name: &str,
+    ) -> Option<Option<Text>> {
+        match self.try_get_at(index, section, name) {
+            Ok(value) => value,
+            Err(err) => {
+                tracing::warn!("{}", err);
+                None
+            }
+        }
+    }
+
+    /// `try_get_considering_unset` of the item at `index`, as returned by
+    /// `item_index` for `section.name`.
+    fn try_get_at(
+        &self,
+        index: Option<(usize, usize)>,
+        section: &str,
+        name: &str,
+    ) -> crate::Result<Option<Option<Text>>> {
+        let self_value = self.own_value_at(index);
+        match (self_value, &self.secondary) {
+            (None, Some(secondary)) => secondary.try_get_considering_unset(section, name),
+            (Some(Some(value)), _) => Ok(Some(Some(self.secrets.resolve(section, name, value)?))),
+            (self_value, _) => Ok(self_value),
+        }
+    }
+
+    /// Value of the item at `index`, ignoring the secondary config and
+    /// without resolving secrets.
+    fn own_value_at(&self, index: Option<(usize, usize)>) -> Option<Option<Text>> {
+        let (section_index, item_index) = index?;
+        let (_, section) = self.sections.get_index(section_index)?;
+        let (_, value_sources) = section.items.get_index(item_index)?;
+        Some(value_sources.last()?.value.clone())
+    }
+
+    fn changed(&mut self) {
+        self.generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+    }
+
     /// Update the name of the `ConfigSet`.
     pub fn named(&mut self, name: &str) -> &mut Self {
         self.name = Text::copy_from_slice(name);
         self
     }
 
-    /// Load config files at given path. The path is a file.
+    /// Load config files at given path.
     ///
-    /// If `path` is a directory, it is ignored.
+    /// If `path` is a directory, files directly inside it are loaded in the
+    /// order described in the crate documentation.
     /// If `path` is a file, it will be loaded directly.
     ///
     /// A config file can use `%include` to load other paths (directories or files). They will
@@ -236,6 +560,63 @@ impl ConfigSet {
         errors
     }
 
+    /// Like `load_path`, but stop loading once `deadline` has passed.
+    ///
+    /// Files, including the ones loaded by `%include`, are only loaded before
+    /// the deadline, and each file is read on a helper thread, so that this
+    /// returns soon after the deadline even if reading a file hangs.
+    ///
+    /// If the deadline passes, the returned errors include an
+    /// `Error::Timeout` naming the file that was being loaded, and this
+    /// `ConfigSet` is rolled back to its state before the call: files loaded
+    /// before the timeout are not kept.
+    pub fn load_path_with_deadline<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        opts: &Options,
+        deadline: Instant,
+    ) -> Vec<Error> {
+        let backup = self.clone();
+        let opts = opts.clone().deadline(deadline);
+        let errors = self.load_path(path, &opts);
+        if errors.iter().any(|e| matches!(e, Error::Timeout { .. })) {
+            *self = backup;
+        }
+        errors
+    }
+
+    /// Load config from `reader`, such as stdin, read until EOF.
+    ///
+    /// Loaded config items are attributed to `name`, a virtual path like
+    /// `<stdin>`: it is the `path` of their `ValueLocation`s, with byte ranges
+    /// into the read content, but no file is read or listed in `files`.
+    ///
+    /// `%include` of a relative path is resolved against the directory set by
+    /// `Options::include_base`. Without it, `%include` other than of builtin
+    /// configs and URLs is reported as `Error::UnresolvedInclude`.
+    ///
+    /// Return a list of errors.
+    pub fn load_reader(&mut self, mut reader: impl Read, name: &str, opts: &Options) -> Vec<Error> {
+        let path = Path::new(name);
+        let mut text = String::new();
+        if let Err(error) = reader.read_to_string(&mut text) {
+            return vec![Error::Io {
+                path: path.to_path_buf(),
+                source: error,
+            }];
+        }
+        text.push('\n');
+
+        let includes = match &opts.include_base {
+            Some(dir) => Includes::RelativeTo(dir),
+            None => Includes::Reject,
+        };
+        let mut visited = HashSet::new();
+        let mut errors = Vec::new();
+        self.load_content(path, text.into(), includes, opts, &mut visited, &mut errors);
+        errors
+    }
+
     /// Load content of an unnamed config file. The `ValueLocation`s of loaded config items will
     /// have an empty `path`.
     ///
@@ -259,10 +640,22 @@ impl ConfigSet {
     ) {
         let section = Text::copy_from_slice(section.as_ref());
         let name = Text::copy_from_slice(name.as_ref());
-        let value = value.map(|v| Text::copy_from_slice(v.as_ref()));
+        let value = value.map(|v| self.interner().intern_str(v.as_ref()));
         self.set_internal(section, name, value, None, &opts)
     }
 
+    fn interner(&mut self) -> &mut Interner {
+        Arc::make_mut(&mut self.interner)
+    }
+
+    pub(crate) fn defaults(&self) -> &RegisteredDefaults {
+        &self.defaults
+    }
+
+    pub(crate) fn defaults_mut(&mut self) -> &mut RegisteredDefaults {
+        Arc::make_mut(&mut self.defaults)
+    }
+
     fn set_internal(
         &mut self,
         section: Text,
@@ -272,6 +665,10 @@ impl ConfigSet {
         opts: &Options,
     ) {
         if let Some((section, name, value)) = opts.filter(section, name, value) {
+            self.changed();
+            let interner = self.interner();
+            let value = value.map(|value| interner.intern(value));
+            let source = interner.intern(opts.source.clone());
             self.sections
                 .entry(section)
                 .or_insert_with(Default::default)
@@ -281,7 +678,7 @@ impl ConfigSet {
                 .push(ValueSource {
                     value,
                     location,
-                    source: opts.source.clone(),
+                    source,
                 })
         }
     }
@@ -293,24 +690,84 @@ impl ConfigSet {
         visited: &mut HashSet<PathBuf>,
         errors: &mut Vec<Error>,
     ) {
-        if let Ok(path) = path.canonicalize() {
+        let deadline = opts.deadline.as_ref();
+        if let Some(deadline) = deadline {
+            // After a timeout, don't load any more files.
+            if errors.iter().any(|e| matches!(e, Error::Timeout { .. })) {
+                return;
+            }
+            if Instant::now() >= deadline.at {
+                return errors.push(deadline.timeout(path));
+            }
+        }
+
+        let canonicalized = {
+            let path = path.to_path_buf();
+            run_until(deadline, move || {
+                let path = path.canonicalize()?;
+                let is_dir = path.is_dir();
+                Ok::<_, std::io::Error>((path, is_dir))
+            })
+        };
+        let canonicalized = match canonicalized {
+            Some(canonicalized) => canonicalized,
+            None => return errors.push(deadline.unwrap().timeout(path)),
+        };
+
+        if let Ok((path, is_dir)) = canonicalized {
             let path = &path;
             debug_assert!(path.is_absolute());
 
-            if !visited.insert(path.to_path_buf()) {
+            // `path` keeps its spelling for locations and `files`.
+            if !visited.insert(winpath::visited_key(path, cfg!(windows))) {
                 // skip - visited before
                 return;
             }
 
+            if is_dir {
+                let entries = {
+                    let path = path.to_path_buf();
+                    run_until(deadline, move || include_dir_entries(&path))
+                };
+                match entries {
+                    Some(Ok(entries)) => {
+                        tracing::debug!(
+                            "include directory {} expanded to {:?}",
+                            path.display(),
+                            &entries
+                        );
+                        self.dir_includes
+                            .push((path.to_path_buf(), entries.clone()));
+                        for entry in entries {
+                            self.load_file(&entry, opts, visited, errors);
+                        }
+                    }
+                    Some(Err(error)) => errors.push(Error::Io {
+                        path: path.to_path_buf(),
+                        source: error,
+                    }),
+                    None => errors.push(deadline.unwrap().timeout(path)),
+                }
+                return;
+            }
+
             self.files.push(path.to_path_buf());
 
-            match fs::read_to_string(path) {
-                Ok(mut text) => {
+            let content = {
+                let path = path.to_path_buf();
+                run_until(deadline, move || fs::read_to_string(path))
+            };
+            match content {
+                Some(Ok(mut text)) => {
                     text.push('\n');
                     let text = Text::from(text);
                     self.load_file_content(path, text, opts, visited, errors);
                 }
-                Err(error) => errors.push(Error::Io(path.to_path_buf(), error)),
+                Some(Err(error)) => errors.push(Error::Io {
+                    path: path.to_path_buf(),
+                    source: error,
+                }),
+                None => errors.push(deadline.unwrap().timeout(path)),
             }
         } else {
             // On Windows, a UNC path `\\?\C:\foo\.\x` will fail to canonicalize
@@ -321,11 +778,8 @@ impl ConfigSet {
             // `canonicalize`. `C:\foo\.\x` would be canonicalized without errors.
             #[cfg(windows)]
             {
-                if let Some(path_str) = path.to_str() {
-                    if path_str.starts_with(r"\\?\") {
-                        let path = Path::new(&path_str[4..]);
-                        self.load_file(&path, opts, visited, errors);
-                    }
+                if let Some(path) = path.to_str().and_then(winpath::strip_verbatim) {
+                    self.load_file(Path::new(path.as_ref()), opts, visited, errors);
                 }
             }
         }
@@ -342,6 +796,23 @@ impl ConfigSet {
         opts: &Options,
         visited: &mut HashSet<PathBuf>,
         errors: &mut Vec<Error>,
+    ) {
+        let includes = match path.parent() {
+            Some(dir) => Includes::RelativeTo(dir),
+            // skip handling %include if path is empty
+            None => Includes::Ignore,
+        };
+        self.load_content(path, buf, includes, opts, visited, errors)
+    }
+
+    fn load_content(
+        &mut self,
+        path: &Path,
+        buf: Text,
+        includes: Includes,
+        opts: &Options,
+        visited: &mut HashSet<PathBuf>,
+        errors: &mut Vec<Error>,
     ) {
         tracing::debug!(
             "load {} from path '{}' ({} bytes)",
@@ -350,13 +821,19 @@ impl ConfigSet {
             buf.len()
         );
 
-        let shared_path = Arc::new(path.to_path_buf()); // use Arc to do shallow copy
-        let skip_include = path.parent().is_none(); // skip handling %include if path is empty
+        let shared_path = self.interner().intern_path(path); // use Arc to do shallow copy
+        // Values are slices of `buf`, which locations keep alive. Identical
+        // files, like machine-generated fragments, share one buffer.
+        let buf = self.interner().intern(buf);
 
         let insts = match parse(&buf) {
             Ok(insts) => insts,
             Err(error) => {
-                return errors.push(Error::ParseFile(path.to_path_buf(), format!("{}", error)));
+                return errors.push(Error::Parse {
+                    path: path.to_path_buf(),
+                    line: error.line(),
+                    source: Box::new(error),
+                });
             }
         };
 
@@ -375,6 +852,7 @@ impl ConfigSet {
                         path: shared_path.clone(),
                         content: buf.clone(),
                         location: span,
+                        included: !opts.include_chain.is_empty(),
                     };
                     self.set_internal(section, name, value, location.into(), opts);
                 }
@@ -389,6 +867,7 @@ impl ConfigSet {
                         path: shared_path.clone(),
                         content: buf.clone(),
                         location: span,
+                        included: !opts.include_chain.is_empty(),
                     };
                     self.set_internal(section.clone(), name, None, location.into(), opts);
                 }
@@ -396,26 +875,269 @@ impl ConfigSet {
                     path: include_path,
                     span: _,
                 } => {
-                    if !skip_include {
-                        if let Some(content) = crate::builtin::get(include_path) {
-                            let text = Text::from(content);
-                            let path = Path::new(include_path);
-                            self.load_file_content(path, text, opts, visited, errors);
+                    if let Includes::Ignore = includes {
+                        continue;
+                    }
+                    let mut include_chain = opts.include_chain.clone();
+                    include_chain.push(path.to_path_buf());
+                    let opts = &Options {
+                        include_chain,
+                        ..opts.clone()
+                    };
+                    if let Some(content) = crate::builtin::get(include_path) {
+                        let text = Text::from(content);
+                        let path = Path::new(include_path);
+                        self.load_file_content(path, text, opts, visited, errors);
+                    } else if remote::is_remote(include_path)
+                        || matches!(includes, Includes::Remote(_))
+                    {
+                        let url = match includes {
+                            Includes::Remote(base) => remote::resolve_url(base, include_path),
+                            _ => include_path.to_string(),
+                        };
+                        self.load_remote(path, &url, opts, visited, errors);
+                    } else if let Includes::RelativeTo(dir) = includes {
+                        let expanded = expand_path(include_path);
+                        let expanded = expanded.to_string_lossy();
+                        if !cfg!(windows) && winpath::is_windows_absolute(&expanded) {
+                            errors.push(Error::WindowsInclude {
+                                path: path.to_path_buf(),
+                                include: include_path.to_string(),
+                            });
+                            continue;
+                        }
+                        // Relative paths of configs written on Windows use
+                        // `\`, others `/`.
+                        let full_include_path = if Path::new(expanded.as_ref()).is_absolute() {
+                            dir.join(expanded.as_ref())
+                        } else {
+                            let relative = winpath::normalize_separators(&expanded, MAIN_SEPARATOR);
+                            dir.join(relative.as_ref())
+                        };
+                        // A file whose name only looks like a glob is
+                        // still included as is.
+                        if glob::is_glob(include_path) && !full_include_path.exists() {
+                            self.load_glob(
+                                path,
+                                include_path,
+                                &full_include_path,
+                                opts,
+                                visited,
+                                errors,
+                            );
                         } else {
-                            let full_include_path =
-                                path.parent().unwrap().join(expand_path(include_path));
                             self.load_file(&full_include_path, opts, visited, errors);
                         }
+                    } else {
+                        errors.push(Error::UnresolvedInclude {
+                            path: path.to_path_buf(),
+                            include: include_path.to_string(),
+                        });
                     }
                 }
             }
         }
     }
 
+    /// Load the remote include `url` of `path`, fetched by the fetcher of
+    /// `opts`. A URL loaded before is skipped, like files.
+    fn load_remote(
+        &mut self,
+        path: &Path,
+        url: &str,
+        opts: &Options,
+        visited: &mut HashSet<PathBuf>,
+        errors: &mut Vec<Error>,
+    ) {
+        let remote = &opts.remote;
+        let fetcher = match &remote.fetcher {
+            Some(fetcher) => fetcher.clone(),
+            None => {
+                return errors.push(Error::RemoteIncludeDisabled {
+                    path: path.to_path_buf(),
+                    url: url.to_string(),
+                });
+            }
+        };
+        if !remote.is_allowed(url) {
+            return errors.push(Error::RemoteIncludeNotAllowed {
+                path: path.to_path_buf(),
+                url: url.to_string(),
+            });
+        }
+        if !visited.insert(PathBuf::from(url)) {
+            return;
+        }
+
+        let deadline = opts.deadline.as_ref();
+        if let Some(deadline) = deadline {
+            if errors.iter().any(|e| matches!(e, Error::Timeout { .. })) {
+                return;
+            }
+            if Instant::now() >= deadline.at {
+                return errors.push(deadline.timeout(Path::new(url)));
+            }
+        }
+        let fetched = {
+            let url = url.to_string();
+            run_until(deadline, move || fetcher.fetch(&url))
+        };
+        let (content, validity) = match fetched {
+            Some(Ok(fetched)) => fetched,
+            Some(Err(source)) => {
+                return errors.push(Error::RemoteInclude {
+                    path: path.to_path_buf(),
+                    url: url.to_string(),
+                    source,
+                });
+            }
+            None => return errors.push(deadline.unwrap().timeout(Path::new(url))),
+        };
+        if content.len() > remote.max_size {
+            return errors.push(Error::RemoteIncludeTooLarge {
+                path: path.to_path_buf(),
+                url: url.to_string(),
+                size: content.len(),
+                max: remote.max_size,
+            });
+        }
+        let mut text = match str::from_utf8(&content) {
+            Ok(text) => text.to_string(),
+            Err(source) => {
+                return errors.push(Error::Utf8 {
+                    path: PathBuf::from(url),
+                    source,
+                });
+            }
+        };
+        text.push('\n');
+
+        if validity == CacheValidity::Stale {
+            tracing::warn!("remote config {} was served from a stale cache", url);
+            self.warnings.push(Warning::StaleRemoteInclude {
+                path: path.to_path_buf(),
+                url: url.to_string(),
+            });
+        }
+        let includes = Includes::Remote(url);
+        self.load_content(Path::new(url), text.into(), includes, opts, visited, errors);
+    }
+
+    /// Load the files matching `pattern`, the glob of `%include include` in
+    /// `path`, in path order. Matching no file is not an error, like
+    /// including a missing file.
+    ///
+    /// A matched file that is being loaded, `path` or a file including it,
+    /// is reported as `Error::IncludeCycle`. Other files loaded before are
+    /// skipped silently, like for any include.
+    fn load_glob(
+        &mut self,
+        path: &Path,
+        include: &str,
+        pattern: &Path,
+        opts: &Options,
+        visited: &mut HashSet<PathBuf>,
+        errors: &mut Vec<Error>,
+    ) {
+        let deadline = opts.deadline.as_ref();
+        let matches = {
+            let pattern = pattern.to_path_buf();
+            run_until(deadline, move || {
+                let matches = glob::expand_glob(&pattern)?;
+                matches
+                    .into_iter()
+                    .map(|path| path.canonicalize())
+                    .collect::<std::io::Result<Vec<_>>>()
+            })
+        };
+        let matches = match matches {
+            Some(Ok(matches)) => matches,
+            Some(Err(error)) => {
+                return errors.push(Error::Io {
+                    path: pattern.to_path_buf(),
+                    source: error,
+                });
+            }
+            None => return errors.push(deadline.unwrap().timeout(pattern)),
+        };
+        tracing::debug!(
+            "include glob {} expanded to {:?}",
+            pattern.display(),
+            &matches
+        );
+        for matched in matches {
+            if opts.include_chain.contains(&matched) {
+                let mut chain = opts.include_chain.clone();
+                chain.push(matched);
+                errors.push(Error::IncludeCycle {
+                    path: path.to_path_buf(),
+                    include: include.to_string(),
+                    chain,
+                });
+                continue;
+            }
+            self.load_file(&matched, opts, visited, errors);
+        }
+    }
+
     pub fn files(&self) -> &[PathBuf] {
         &self.files
     }
 
+    /// Warnings reported while loading, in load order, like remote includes
+    /// served from a stale cache. Unlike errors, they don't stop loading.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Directories that were included, in load order, together with the
+    /// files each of them expanded to. Useful for tooling that wants to
+    /// show what a `%include` of a directory resolved to.
+    pub fn dir_includes(&self) -> &[(PathBuf, Vec<PathBuf>)] {
+        &self.dir_includes
+    }
+
+    /// Sections in the order they were first defined.
+    ///
+    /// Included files are loaded at their `%include`, so a section first
+    /// defined by an included file comes before the sections defined after
+    /// the `%include`. Defining a section again, in any file, doesn't move
+    /// it. Sections of the secondary config come first.
+    pub fn sections_ordered(&self) -> Vec<Text> {
+        self.sections().into_owned()
+    }
+
+    /// Names of `section` in the order they were first set, with the same
+    /// guarantees as `sections_ordered`. Overriding a value, in any file,
+    /// doesn't move its name.
+    pub fn keys_ordered(&self, section: &str) -> Vec<Text> {
+        self.keys(section)
+    }
+
+    /// Values set by the file at `path`, as listed by `files`, in the order
+    /// they are set in that file: the section, the name, and the byte range
+    /// of the value, or of the `%unset`, in the file.
+    ///
+    /// A name set several times by the file is listed once per value,
+    /// including the overridden ones. Values dropped by
+    /// `ensure_location_supersets` are not listed.
+    pub fn items_in_file(&self, path: &Path) -> Vec<(Text, Text, Range<usize>)> {
+        let mut items = Vec::new();
+        for section in self.sections().iter() {
+            for name in self.keys(section) {
+                for source in self.get_sources(section, &name).iter() {
+                    if let Some((source_path, range)) = source.location() {
+                        if source_path == path {
+                            items.push((section.clone(), name.clone(), range));
+                        }
+                    }
+                }
+            }
+        }
+        items.sort_by_key(|(_, _, range)| range.start);
+        items
+    }
+
     pub fn to_string(&self) -> String {
         let mut result = String::new();
 
@@ -425,7 +1147,8 @@ impl ConfigSet {
             result.push_str("]\n");
 
             for key in self.keys(section).iter() {
-                let value = self.get_considering_unset(section, key);
+                // Serialize references to secrets, not the secrets.
+                let value = self.get_unresolved(section, key);
                 #[cfg(test)]
                 {
                     let values = self.get_sources(section, key);
@@ -457,6 +1180,53 @@ impl ConfigSet {
         result
     }
 
+    /// Serialize the config, like `to_string`, but keep the text of items
+    /// as it was loaded when possible.
+    ///
+    /// Sections and names are written in order, one `[section]` block per
+    /// section. An item whose effective value comes from a loaded file is
+    /// written as it appears in the file, with its indentation, the comment
+    /// lines between its value lines, and the comment lines right above it.
+    /// Other items, like the ones changed by `set`, are written like
+    /// `to_string` does. `%include`s are flattened.
+    ///
+    /// Loading the result gives the same sections, names and values.
+    pub fn serialize(&self) -> String {
+        let mut result = String::new();
+
+        for section in self.sections().iter() {
+            result.push('[');
+            result.push_str(section.as_ref());
+            result.push_str("]\n");
+
+            for key in self.keys(section).iter() {
+                let sources = self.get_sources(section, key);
+                let source = match sources.last() {
+                    Some(source) => source,
+                    None => continue,
+                };
+                if let Some(text) = original_item_text(section, key, source) {
+                    result.push_str(&text);
+                    result.push('\n');
+                } else if let Some(value) = source.value() {
+                    result.push_str(key);
+                    result.push('=');
+                    // See `to_string`.
+                    result.push_str(&value.replace("\n", "\n "));
+                    result.push('\n');
+                } else {
+                    result.push_str("%unset ");
+                    result.push_str(key);
+                    result.push('\n');
+                }
+            }
+
+            result.push('\n');
+        }
+
+        result
+    }
+
     /// Drop configs from sources that are outside `allowed_locations` or
     /// `allowed_configs`.
     ///
@@ -467,6 +1237,7 @@ impl ConfigSet {
         allowed_locations: Option<HashSet<&str>>,
         allowed_configs: Option<HashSet<(&str, &str)>>,
     ) {
+        self.changed();
         for (sname, section) in self.sections.iter_mut() {
             for (kname, values) in section.items.iter_mut() {
                 let values_copy = values.clone();
@@ -569,6 +1340,52 @@ impl Options {
         self
     }
 
+    /// Set the directory `%include` in content loaded by `ConfigSet::load_reader` is resolved
+    /// against. Has no effect on files, whose includes are relative to their own directory.
+    pub fn include_base<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.include_base = Some(dir.into());
+        self
+    }
+
+    /// Stop loading config files once `deadline` has passed, reporting the
+    /// file being loaded as `Error::Timeout`. Files are read on a helper
+    /// thread, so a hung read can't block past the deadline.
+    ///
+    /// Time reported by `Error::Timeout` is counted from this call.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(Deadline {
+            started: Instant::now(),
+            at: deadline,
+        });
+        self
+    }
+
+    /// Fetch `%include`s of `http://` and `https://` URLs with `fetcher`.
+    /// Without a fetcher, they are reported as `Error::RemoteIncludeDisabled`.
+    ///
+    /// Only URLs starting with a prefix added by `allow_remote_prefix` are
+    /// fetched. Fetched content is loaded with the URL as its path, and its
+    /// relative includes are resolved against the URL.
+    pub fn remote_fetcher(mut self, fetcher: Arc<dyn RemoteFetcher>) -> Self {
+        self.remote.fetcher = Some(fetcher);
+        self
+    }
+
+    /// Allow remote includes of URLs starting with `prefix`, like
+    /// `https://config.example.com/`.
+    pub fn allow_remote_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.remote.allowed_prefixes.push(prefix.into());
+        self
+    }
+
+    /// Report remote includes larger than `max_size` bytes as
+    /// `Error::RemoteIncludeTooLarge` instead of loading them. Defaults to
+    /// `DEFAULT_MAX_REMOTE_SIZE`.
+    pub fn max_remote_size(mut self, max_size: usize) -> Self {
+        self.remote.max_size = max_size;
+        self
+    }
+
     /// Pass `(section, name, value)` through chain of filters, yielding mutated
     /// result or `None`, if any filter returned `None`.
     pub fn filter(
@@ -594,9 +1411,15 @@ impl<S: Into<Text>> From<S> for Options {
 
 #[cfg(test)]
 pub(crate) mod tests {
+    use std::collections::HashMap;
     use std::io::Write;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Mutex;
+    use std::time::Duration;
 
     use configmodel::ConfigExt;
+    use minibytes::Bytes;
     use tempdir::TempDir;
 
     use super::*;
@@ -636,6 +1459,55 @@ pub(crate) mod tests {
         assert_eq!(sources[1].file_content(), None);
     }
 
+    #[test]
+    fn test_interned_values() {
+        let dir = TempDir::new("test_interned_values").unwrap();
+        let value = "x".repeat(10000);
+        let mut cfg = ConfigSet::new();
+        for i in 0..10 {
+            let path = dir.path().join(format!("{}.rc", i));
+            write_file(path.clone(), &format!("[x]\na = {}\nb = {}\n", value, i));
+            cfg.load_path(&path, &"fragment".into());
+        }
+        for i in 0..10 {
+            cfg.set("x", "a", Some(&value), &format!("set{}", i % 2).into());
+        }
+
+        // All 20 assignments share the buffer of the first one.
+        let sources = cfg.get_sources("x", "a");
+        assert_eq!(sources.len(), 20);
+        let first = sources[0].value().as_ref().unwrap();
+        for source in sources.iter() {
+            let interned = source.value().as_ref().unwrap();
+            assert_eq!(interned.as_ptr(), first.as_ptr());
+        }
+        assert_eq!(cfg.get("x", "a").unwrap().as_ptr(), first.as_ptr());
+        assert_eq!(sources[10].source().as_ptr(), sources[12].source().as_ptr());
+        assert_eq!(cfg.get("x", "b"), Some(Text::from("9")));
+
+        // Identical fragments retain a single copy of their content.
+        let mut cfg = ConfigSet::new();
+        for i in 0..10 {
+            let path = dir.path().join(format!("same{}.rc", i));
+            write_file(path.clone(), &format!("[x]\na = {}\n", value));
+            cfg.load_path(&path, &"fragment".into());
+        }
+        let sources = cfg.get_sources("x", "a");
+        assert_eq!(sources.len(), 10);
+        let first = sources[0].value().as_ref().unwrap();
+        let content = sources[0].file_content().unwrap();
+        for source in sources.iter() {
+            assert_eq!(source.value().as_ref().unwrap().as_ptr(), first.as_ptr());
+            assert_eq!(source.file_content().unwrap().as_ptr(), content.as_ptr());
+        }
+
+        // Clones can be changed independently.
+        let mut cloned = cfg.clone();
+        cloned.set("x", "a", Some("1"), &"".into());
+        assert_eq!(cloned.get("x", "a"), Some(Text::from("1")));
+        assert_eq!(cfg.get("x", "a").unwrap().as_ptr(), first.as_ptr());
+    }
+
     #[test]
     fn test_keys() {
         let mut cfg = ConfigSet::new();
@@ -897,16 +1769,80 @@ pub(crate) mod tests {
         assert_eq!(cfg.get("z", "c"), Some(Text::from("b")));
     }
 
-    pub(crate) fn write_file(path: PathBuf, content: &str) {
-        fs::create_dir_all(path.parent().unwrap()).unwrap();
-        let mut f = fs::File::create(path).unwrap();
-        f.write_all(content.as_bytes()).unwrap();
-    }
-
     #[test]
-    fn test_parse_include() {
-        let dir = TempDir::new("test_parse_include").unwrap();
-        write_file(
+    fn test_parse_error_in_include() {
+        let dir = TempDir::new("test_parse_error_in_include").unwrap();
+        write_file(dir.path().join("rootrc"), "[x]\na=1\n%include bad.rc\n");
+        write_file(dir.path().join("bad.rc"), "[x]\nb=2\n[y\n");
+
+        let mut cfg = ConfigSet::new();
+        let errors = cfg.load_path(dir.path().join("rootrc"), &"test".into());
+        assert_eq!(errors.len(), 1);
+
+        let bad_path = dir.path().join("bad.rc").canonicalize().unwrap();
+        match &errors[0] {
+            Error::Parse { path, line, .. } => {
+                assert_eq!(path, &bad_path);
+                assert_eq!(*line, 3);
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+        assert_eq!(errors[0].path(), Some(bad_path.as_path()));
+        assert_eq!(errors[0].line(), Some(3));
+        assert_eq!(
+            format!("{}", errors[0]),
+            format!("{:?}:\nline 3: missing ']' for section header", bad_path)
+        );
+
+        let source = std::error::Error::source(&errors[0])
+            .and_then(|e| e.downcast_ref::<hgrc_parser::Error>())
+            .expect("source should be the parser error");
+        assert_eq!(source.line(), 3);
+        assert_eq!(source.message(), "missing ']' for section header");
+
+        // A file with a syntax error is skipped as a whole; the including
+        // file is still loaded.
+        assert_eq!(cfg.get("x", "a"), Some("1".into()));
+        assert_eq!(cfg.get("x", "b"), None);
+    }
+
+    #[test]
+    fn test_io_error_in_include() {
+        let dir = TempDir::new("test_io_error_in_include").unwrap();
+        write_file(dir.path().join("rootrc"), "[x]\na=1\n%include bad.rc\n");
+        // Not valid UTF-8, so reading the file fails.
+        fs::write(dir.path().join("bad.rc"), b"[x]\nb=\xff\n").unwrap();
+
+        let mut cfg = ConfigSet::new();
+        let errors = cfg.load_path(dir.path().join("rootrc"), &"test".into());
+        assert_eq!(errors.len(), 1);
+
+        let bad_path = dir.path().join("bad.rc").canonicalize().unwrap();
+        match &errors[0] {
+            Error::Io { path, .. } => assert_eq!(path, &bad_path),
+            e => panic!("unexpected error: {:?}", e),
+        }
+        assert_eq!(errors[0].path(), Some(bad_path.as_path()));
+
+        let source = std::error::Error::source(&errors[0])
+            .and_then(|e| e.downcast_ref::<std::io::Error>())
+            .expect("source should be the io error");
+        assert_eq!(source.kind(), std::io::ErrorKind::InvalidData);
+
+        assert_eq!(cfg.get("x", "a"), Some("1".into()));
+        assert_eq!(cfg.get("x", "b"), None);
+    }
+
+    pub(crate) fn write_file(path: PathBuf, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut f = fs::File::create(path).unwrap();
+        f.write_all(content.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_parse_include() {
+        let dir = TempDir::new("test_parse_include").unwrap();
+        write_file(
             dir.path().join("rootrc"),
             "[x]\n\
              b=1\n\
@@ -968,6 +1904,211 @@ pub(crate) mod tests {
         assert_eq!(cfg.get("y", "b"), Some(Text::from("1")));
     }
 
+    #[test]
+    fn test_ordered_across_includes() {
+        let dir = TempDir::new("test_ordered_across_includes").unwrap();
+        write_file(
+            dir.path().join("a.rc"),
+            "[x]\n\
+             a=1\n\
+             %include b.rc\n\
+             [x]\n\
+             c=1\n\
+             a=3\n\
+             [z]\n\
+             z=1",
+        );
+        write_file(dir.path().join("b.rc"), "[x]\na=2\nb=2\n[y]\ny=2");
+
+        let mut cfg = ConfigSet::new();
+        let errors = cfg.load_path(dir.path().join("a.rc"), &"test".into());
+        assert!(errors.is_empty());
+
+        fn texts(names: &[&'static str]) -> Vec<Text> {
+            names.iter().map(|n| Text::from(*n)).collect()
+        }
+        assert_eq!(cfg.sections_ordered(), texts(&["x", "y", "z"]));
+        // Overriding "a", in b.rc and again in a.rc, doesn't move it.
+        assert_eq!(cfg.keys_ordered("x"), texts(&["a", "b", "c"]));
+        assert_eq!(cfg.get("x", "a"), Some(Text::from("3")));
+
+        let names_in_file = |name: &str| -> Vec<String> {
+            let path = dir.path().join(name).canonicalize().unwrap();
+            cfg.items_in_file(&path)
+                .into_iter()
+                .map(|(section, name, _)| format!("{}.{}", section, name))
+                .collect()
+        };
+        assert_eq!(names_in_file("a.rc"), ["x.a", "x.c", "x.a", "z.z"]);
+        assert_eq!(names_in_file("b.rc"), ["x.a", "x.b", "y.y"]);
+
+        // The secondary config's first definitions come first.
+        let mut secondary = ConfigSet::new();
+        secondary.set("w", "w", Some("1"), &"secondary".into());
+        secondary.set("x", "c", Some("1"), &"secondary".into());
+        cfg.secondary(Arc::new(secondary));
+        assert_eq!(cfg.sections_ordered(), texts(&["w", "x", "y", "z"]));
+        assert_eq!(cfg.keys_ordered("x"), texts(&["c", "a", "b"]));
+    }
+
+    #[test]
+    fn test_source_spans_across_includes() {
+        let dir = TempDir::new("test_source_spans_across_includes").unwrap();
+        write_file(
+            dir.path().join("a.rc"),
+            "[x]\n\
+             a = first\n  second\n  third\n\
+             %include b.rc\n\
+             [x]\n\
+             b = 2",
+        );
+        write_file(dir.path().join("b.rc"), "[x]\n%unset a\nb = 1\n");
+
+        let mut cfg = ConfigSet::new();
+        let errors = cfg.load_path(dir.path().join("a.rc"), &"test".into());
+        assert!(errors.is_empty());
+
+        // Each source's span is the value, or the name of an unset, in its
+        // own file.
+        let spans = |name: &str| -> Vec<(String, String, bool)> {
+            cfg.get_sources("x", name)
+                .iter()
+                .map(|source| {
+                    let (path, range) = source.location().unwrap();
+                    let content = source.file_content().unwrap();
+                    let file = path.file_name().unwrap().to_string_lossy().into_owned();
+                    (file, content[range].to_string(), source.is_included())
+                })
+                .collect()
+        };
+        assert_eq!(
+            spans("a"),
+            [
+                (
+                    "a.rc".to_string(),
+                    "first\n  second\n  third".to_string(),
+                    false
+                ),
+                ("b.rc".to_string(), "a".to_string(), true),
+            ]
+        );
+        assert_eq!(
+            spans("b"),
+            [
+                ("b.rc".to_string(), "1".to_string(), true),
+                ("a.rc".to_string(), "2".to_string(), false),
+            ]
+        );
+        // The last source is the effective value.
+        assert_eq!(cfg.get_sources("x", "a").last().unwrap().value(), &None);
+        assert_eq!(cfg.get("x", "b"), Some(Text::from("2")));
+    }
+
+    #[test]
+    fn test_parse_include_glob() {
+        let dir = TempDir::new("test_parse_include_glob").unwrap();
+        write_file(
+            dir.path().join("rootrc"),
+            "[x]\n\
+             a=0\n\
+             %include conf.d/*.rc\n\
+             %include missing/*.rc\n\
+             %include $CONFIGSET_TEST_GLOB_DIR/**/*.rc",
+        );
+        write_file(dir.path().join("conf.d/b.rc"), "[x]\na=2");
+        write_file(dir.path().join("conf.d/a.rc"), "[x]\na=1\nb=1");
+        write_file(dir.path().join("conf.d/c.txt"), "[x]\nc=1");
+        write_file(dir.path().join("sub/deep/y.rc"), "[y]\ny=1");
+        write_file(dir.path().join("sub/z.rc"), "[y]\nz=1\n%include ../*rc");
+        std::env::set_var("CONFIGSET_TEST_GLOB_DIR", dir.path().join("sub"));
+
+        let mut cfg = ConfigSet::new();
+        let errors = cfg.load_path(dir.path().join("rootrc"), &"test".into());
+
+        // Matches are loaded in path order, and a pattern matching nothing
+        // is not an error.
+        assert_eq!(cfg.get("x", "a"), Some(Text::from("2")));
+        assert_eq!(cfg.get("x", "b"), Some(Text::from("1")));
+        assert_eq!(cfg.get("x", "c"), None);
+        assert_eq!(cfg.keys("y"), vec![Text::from("y"), Text::from("z")]);
+
+        // "../*rc" of sub/z.rc matches rootrc, which is being loaded.
+        let canonical = |name: &str| dir.path().join(name).canonicalize().unwrap();
+        assert_eq!(errors.len(), 1);
+        match &errors[0] {
+            Error::IncludeCycle {
+                path,
+                include,
+                chain,
+            } => {
+                assert_eq!(path, &canonical("sub/z.rc"));
+                assert_eq!(include, "../*rc");
+                assert_eq!(
+                    chain,
+                    &[
+                        canonical("rootrc"),
+                        canonical("sub/z.rc"),
+                        canonical("rootrc")
+                    ]
+                );
+            }
+            error => panic!("unexpected error: {}", error),
+        }
+        assert!(errors[0].to_string().contains("is a cycle: "));
+    }
+
+    #[test]
+    fn test_parse_include_dir() {
+        let dir = TempDir::new("test_parse_include_dir").unwrap();
+        write_file(dir.path().join("rootrc"), "[x]\na=0\n%include conf.d\n");
+
+        let conf = dir.path().join("conf.d");
+        write_file(conf.join("b.rc"), "[x]\na=b\nb=b\n");
+        write_file(conf.join("a.rc"), "[x]\na=a\nb=a\nc=a\n");
+        write_file(conf.join("B.rc"), "[x]\nc=B\n");
+        // Skipped: hidden, backups, and names without the ".rc" suffix.
+        write_file(conf.join(".hidden.rc"), "[x]\na=hidden\n");
+        write_file(conf.join("a.rc~"), "[x]\na=backup\n");
+        write_file(conf.join("a.rc.bak"), "[x]\na=backup\n");
+        write_file(conf.join("notrc"), "[x]\na=notrc\n");
+        // Skipped: subdirectories are not recursed into.
+        write_file(conf.join("sub.rc/c.rc"), "[x]\na=sub\n");
+
+        #[cfg(unix)]
+        {
+            write_file(dir.path().join("other/c.rc"), "[x]\nd=c\n");
+            // Followed: symlink to a file.
+            std::os::unix::fs::symlink(dir.path().join("other/c.rc"), conf.join("c.rc")).unwrap();
+            // Skipped: symlink to a directory.
+            std::os::unix::fs::symlink(dir.path().join("other"), conf.join("link.rc")).unwrap();
+        }
+
+        let mut cfg = ConfigSet::new();
+        let errors = cfg.load_path(dir.path().join("rootrc"), &"test_parse_include_dir".into());
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let conf = conf.canonicalize().unwrap();
+        let mut expected = vec![conf.join("B.rc"), conf.join("a.rc"), conf.join("b.rc")];
+        if cfg!(unix) {
+            expected.push(conf.join("c.rc"));
+        }
+        assert_eq!(cfg.dir_includes(), &[(conf, expected)]);
+
+        assert_eq!(cfg.get("x", "a"), Some(Text::from("b")));
+        assert_eq!(cfg.get("x", "b"), Some(Text::from("b")));
+        assert_eq!(cfg.get("x", "c"), Some(Text::from("a")));
+        if cfg!(unix) {
+            assert_eq!(cfg.get("x", "d"), Some(Text::from("c")));
+        }
+        assert_eq!(
+            cfg.get_sources("x", "a")
+                .iter()
+                .map(|s| s.value().clone().unwrap())
+                .collect::<Vec<_>>(),
+            ["0", "a", "b"]
+        );
+    }
+
     #[test]
     fn test_parse_include_builtin() {
         let dir = TempDir::new("test_parse_include").unwrap();
@@ -1006,6 +2147,386 @@ pub(crate) mod tests {
         assert_eq!(cfg.get("y", "b"), Some(Text::from("2")));
     }
 
+    #[test]
+    fn test_parse_include_windows_separators() {
+        let dir = TempDir::new("test_parse_include_windows_separators").unwrap();
+        write_file(
+            dir.path().join("rootrc"),
+            "%include sub\\a.rc\n\
+             %include C:\\rc\\hg.rc\n",
+        );
+        write_file(dir.path().join("sub/a.rc"), "[x]\na=1\n%include ..\\b.rc\n");
+        write_file(dir.path().join("b.rc"), "[x]\nb=2\n");
+
+        let mut cfg = ConfigSet::new();
+        let errors = cfg.load_path(dir.path().join("rootrc"), &"test".into());
+        assert_eq!(cfg.get("x", "a"), Some(Text::from("1")));
+        assert_eq!(cfg.get("x", "b"), Some(Text::from("2")));
+        if cfg!(windows) {
+            // A missing file is not an error.
+            assert!(errors.is_empty(), "{:?}", errors);
+        } else {
+            assert_eq!(errors.len(), 1);
+            assert!(matches!(
+                &errors[0],
+                Error::WindowsInclude { include, .. } if include == "C:\\rc\\hg.rc"
+            ));
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_parse_include_windows_absolute() {
+        let dir = TempDir::new("test_parse_include_windows_absolute").unwrap();
+        let abs = dir.path().canonicalize().unwrap();
+        let abs = winpath::strip_verbatim(abs.to_str().unwrap())
+            .unwrap()
+            .into_owned();
+        // `C:\x` as `\\localhost\C$\x`.
+        let (drive, rest) = abs.split_once(":\\").unwrap();
+        let unc = format!(r"\\localhost\{}$\{}", drive, rest);
+        write_file(
+            dir.path().join("rootrc"),
+            &format!(
+                "%include {}\\drive.rc\n%include {}\\unc.rc\n%include {}\\DRIVE.RC\n",
+                abs,
+                unc,
+                abs.to_uppercase(),
+            ),
+        );
+        write_file(dir.path().join("drive.rc"), "[x]\na=1\n");
+        write_file(dir.path().join("unc.rc"), "[x]\nb=2\n");
+
+        let mut cfg = ConfigSet::new();
+        let errors = cfg.load_path(dir.path().join("rootrc"), &"test".into());
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(cfg.get("x", "a"), Some(Text::from("1")));
+        assert_eq!(cfg.get("x", "b"), Some(Text::from("2")));
+        // `DRIVE.RC` is `drive.rc`, loaded once.
+        let drive_files = cfg
+            .files()
+            .iter()
+            .filter(|f| f.to_string_lossy().to_lowercase().ends_with("drive.rc"))
+            .count();
+        assert_eq!(drive_files, 1);
+    }
+
+    #[test]
+    fn test_load_reader() {
+        let content = "[x]\na = 1\nb = 2\n%unset a\n";
+        let mut cfg = ConfigSet::new();
+        let errors = cfg.load_reader(content.as_bytes(), "<stdin>", &"--configfile".into());
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        assert_eq!(cfg.get("x", "a"), None);
+        assert_eq!(cfg.get("x", "b"), Some(Text::from("2")));
+        assert!(cfg.files().is_empty());
+
+        let sources = cfg.get_sources("x", "b");
+        assert_eq!(sources[0].source(), &"--configfile");
+        let (path, range) = sources[0].location().unwrap();
+        assert_eq!(path, PathBuf::from("<stdin>"));
+        assert_eq!(&content[range], "2");
+        let (_, range) = cfg.get_sources("x", "a")[1].location().unwrap();
+        assert_eq!(
+            range,
+            content.find("%unset a").unwrap() + 7..content.len() - 1
+        );
+    }
+
+    #[test]
+    fn test_load_reader_include() {
+        let dir = TempDir::new("test_load_reader_include").unwrap();
+        write_file(dir.path().join("a.rc"), "[x]\na=2\n");
+        let content = "[x]\na=1\n%include a.rc\n%include builtin:git.rc\nb=1\n";
+
+        // Without a base directory, only builtin configs can be included.
+        let mut cfg = ConfigSet::new();
+        let errors = cfg.load_reader(content.as_bytes(), "<stdin>", &"stdin".into());
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            &errors[0],
+            Error::UnresolvedInclude { path, include }
+                if path == Path::new("<stdin>") && include == "a.rc"
+        ));
+        assert_eq!(
+            errors[0].to_string(),
+            "\"<stdin>\": cannot resolve %include a.rc without a base directory"
+        );
+        assert_eq!(cfg.get("x", "a"), Some(Text::from("1")));
+        assert_eq!(cfg.get("x", "b"), Some(Text::from("1")));
+        assert_eq!(cfg.get("remotenames", "hoist"), Some(Text::from("remote")));
+
+        let mut cfg = ConfigSet::new();
+        let opts = Options::from("stdin").include_base(dir.path());
+        let errors = cfg.load_reader(content.as_bytes(), "<stdin>", &opts);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(cfg.get("x", "a"), Some(Text::from("2")));
+        assert_eq!(
+            cfg.files(),
+            [dir.path().join("a.rc").canonicalize().unwrap()]
+        );
+    }
+
+    /// Serves `pages` by URL, counting fetches.
+    #[derive(Default)]
+    struct FakeFetcher {
+        pages: HashMap<String, (&'static str, CacheValidity)>,
+        fetched: Mutex<Vec<String>>,
+    }
+
+    impl FakeFetcher {
+        fn page(mut self, url: &str, content: &'static str) -> Self {
+            self.pages
+                .insert(url.to_string(), (content, CacheValidity::Fresh));
+            self
+        }
+
+        fn stale_page(mut self, url: &str, content: &'static str) -> Self {
+            self.pages
+                .insert(url.to_string(), (content, CacheValidity::Stale));
+            self
+        }
+    }
+
+    impl RemoteFetcher for FakeFetcher {
+        fn fetch(&self, url: &str) -> anyhow::Result<(Bytes, CacheValidity)> {
+            self.fetched.lock().unwrap().push(url.to_string());
+            match self.pages.get(url) {
+                Some((content, validity)) => Ok((Bytes::from(*content), *validity)),
+                None => anyhow::bail!("404 not found"),
+            }
+        }
+    }
+
+    fn remote_opts(fetcher: Arc<FakeFetcher>) -> Options {
+        Options::from("remote")
+            .remote_fetcher(fetcher)
+            .allow_remote_prefix("https://config.example.com/")
+    }
+
+    #[test]
+    fn test_remote_include() {
+        let fetcher = Arc::new(
+            FakeFetcher::default().page("https://config.example.com/team.rc", "[x]\na=2\nb=2\n"),
+        );
+        let content = "[x]\na=1\n%include https://config.example.com/team.rc\nb=3\n";
+        let mut cfg = ConfigSet::new();
+        let errors = cfg.parse(content, &remote_opts(fetcher.clone()));
+        // `parse` has no path to resolve includes against, so they are
+        // ignored, remote ones too.
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(cfg.get("x", "a"), Some(Text::from("1")));
+
+        let errors = cfg.load_reader(content.as_bytes(), "<stdin>", &remote_opts(fetcher.clone()));
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(cfg.get("x", "a"), Some(Text::from("2")));
+        assert_eq!(cfg.get("x", "b"), Some(Text::from("3")));
+        assert!(cfg.warnings().is_empty());
+
+        let (path, range) = cfg
+            .get_sources("x", "a")
+            .last()
+            .unwrap()
+            .location()
+            .unwrap();
+        assert_eq!(path, PathBuf::from("https://config.example.com/team.rc"));
+        assert_eq!(range, 6..7);
+        assert_eq!(
+            *fetcher.fetched.lock().unwrap(),
+            ["https://config.example.com/team.rc"]
+        );
+    }
+
+    #[test]
+    fn test_remote_include_not_allowed() {
+        let fetcher =
+            Arc::new(FakeFetcher::default().page("https://evil.example.com/team.rc", "[x]\na=2\n"));
+        let content = "[x]\na=1\n%include https://evil.example.com/team.rc\n";
+        let mut cfg = ConfigSet::new();
+        let errors = cfg.load_reader(content.as_bytes(), "<stdin>", &remote_opts(fetcher.clone()));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "\"<stdin>\": %include https://evil.example.com/team.rc is not an allowed remote source"
+        );
+        assert_eq!(cfg.get("x", "a"), Some(Text::from("1")));
+        assert!(fetcher.fetched.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_remote_include_without_fetcher() {
+        let dir = TempDir::new("test_remote_include_without_fetcher").unwrap();
+        let path = dir.path().join("rootrc");
+        write_file(
+            path.clone(),
+            "[x]\na=1\n%include https://config.example.com/team.rc\nb=1\n",
+        );
+
+        let mut cfg = ConfigSet::new();
+        let errors = cfg.load_path(&path, &"test".into());
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            &errors[0],
+            Error::RemoteIncludeDisabled { url, .. } if url == "https://config.example.com/team.rc"
+        ));
+        assert!(
+            errors[0]
+                .to_string()
+                .ends_with(": cannot fetch %include https://config.example.com/team.rc: remote includes are disabled")
+        );
+        assert_eq!(cfg.get("x", "b"), Some(Text::from("1")));
+    }
+
+    #[test]
+    fn test_remote_include_nested_relative() {
+        let fetcher = Arc::new(
+            FakeFetcher::default()
+                .page(
+                    "https://config.example.com/team/main.rc",
+                    "[x]\na=2\n%include extra.rc\n%include ../common.rc\n",
+                )
+                .page("https://config.example.com/team/extra.rc", "[x]\nb=2\n")
+                .page(
+                    "https://config.example.com/common.rc",
+                    "[x]\nc=2\n%include team/main.rc\n",
+                ),
+        );
+        let content = "%include https://config.example.com/team/main.rc\n";
+        let mut cfg = ConfigSet::new();
+        let errors = cfg.load_reader(content.as_bytes(), "<stdin>", &remote_opts(fetcher.clone()));
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(cfg.get("x", "a"), Some(Text::from("2")));
+        assert_eq!(cfg.get("x", "b"), Some(Text::from("2")));
+        assert_eq!(cfg.get("x", "c"), Some(Text::from("2")));
+        // The include of main.rc by common.rc is skipped, like a file loaded
+        // before.
+        assert_eq!(
+            *fetcher.fetched.lock().unwrap(),
+            [
+                "https://config.example.com/team/main.rc",
+                "https://config.example.com/team/extra.rc",
+                "https://config.example.com/common.rc",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_remote_include_errors_and_warnings() {
+        let fetcher = Arc::new(
+            FakeFetcher::default()
+                .stale_page("https://config.example.com/stale.rc", "[x]\na=2\n")
+                .page("https://config.example.com/large.rc", "[x]\nbb=2\n"),
+        );
+        let content = "%include https://config.example.com/stale.rc\n\
+                       %include https://config.example.com/large.rc\n\
+                       %include https://config.example.com/missing.rc\n";
+        let mut cfg = ConfigSet::new();
+        let opts = remote_opts(fetcher).max_remote_size(8);
+        let errors = cfg.load_reader(content.as_bytes(), "<stdin>", &opts);
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert_eq!(
+            errors[0].to_string(),
+            "\"<stdin>\": %include https://config.example.com/large.rc is too large (9 bytes, limit 8)"
+        );
+        assert_eq!(
+            errors[1].to_string(),
+            "\"<stdin>\": cannot fetch %include https://config.example.com/missing.rc: 404 not found"
+        );
+
+        // Stale content is loaded, with a warning.
+        assert_eq!(cfg.get("x", "a"), Some(Text::from("2")));
+        assert_eq!(cfg.get("x", "bb"), None);
+        assert_eq!(
+            cfg.warnings(),
+            [Warning::StaleRemoteInclude {
+                path: PathBuf::from("<stdin>"),
+                url: "https://config.example.com/stale.rc".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_load_path_with_deadline() {
+        let dir = TempDir::new("test_load_path_with_deadline").unwrap();
+        write_file(dir.path().join("rootrc"), "[x]\na=1\n%include a.rc\n");
+        write_file(dir.path().join("a.rc"), "[x]\nb=2\n");
+
+        let mut cfg = ConfigSet::new();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let errors =
+            cfg.load_path_with_deadline(dir.path().join("rootrc"), &"test".into(), deadline);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(cfg.get("x", "a"), Some(Text::from("1")));
+        assert_eq!(cfg.get("x", "b"), Some(Text::from("2")));
+
+        // An expired deadline loads nothing and leaves the config unchanged.
+        let mut cfg = ConfigSet::new();
+        cfg.set("x", "a", Some("0"), &"set".into());
+        let errors =
+            cfg.load_path_with_deadline(dir.path().join("rootrc"), &"test".into(), Instant::now());
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            &errors[0],
+            Error::Timeout { path, .. } if path == &dir.path().join("rootrc")
+        ));
+        assert_eq!(cfg.get("x", "a"), Some(Text::from("0")));
+        assert!(cfg.files().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_load_path_with_deadline_hung_read() {
+        let dir = TempDir::new("test_load_path_with_deadline_hung_read").unwrap();
+        write_file(
+            dir.path().join("rootrc"),
+            "[x]\na=1\n%include stuck.rc\n%include b.rc\n",
+        );
+        write_file(dir.path().join("b.rc"), "[x]\nb=2\n");
+        // Opening a FIFO without a writer blocks, like a hung network mount.
+        let fifo = dir.path().join("stuck.rc");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let fifo = fifo.canonicalize().unwrap();
+
+        let timeout = Duration::from_millis(200);
+        let check_timeout = |errors: &[Error], started: Instant| {
+            assert!(started.elapsed() < timeout + Duration::from_secs(5));
+            assert_eq!(errors.len(), 1, "{:?}", errors);
+            match &errors[0] {
+                Error::Timeout { path, elapsed } => {
+                    assert_eq!(path, &fifo);
+                    assert!(*elapsed >= timeout, "{:?}", elapsed);
+                }
+                error => panic!("unexpected error: {:?}", error),
+            }
+        };
+
+        // The partially loaded config is rolled back.
+        let mut cfg = ConfigSet::new();
+        let started = Instant::now();
+        let errors = cfg.load_path_with_deadline(
+            dir.path().join("rootrc"),
+            &"test".into(),
+            started + timeout,
+        );
+        check_timeout(&errors, started);
+        assert_eq!(cfg.get("x", "a"), None);
+        assert!(cfg.files().is_empty());
+
+        // With `Options::deadline`, it is kept as loaded so far.
+        let mut cfg = ConfigSet::new();
+        let started = Instant::now();
+        let opts = Options::from("test").deadline(started + timeout);
+        let errors = cfg.load_path(dir.path().join("rootrc"), &opts);
+        check_timeout(&errors, started);
+        assert_eq!(cfg.get("x", "a"), Some(Text::from("1")));
+        assert_eq!(cfg.get("x", "b"), None);
+    }
+
     #[test]
     fn test_named() {
         let mut cfg = ConfigSet::new();
@@ -1061,6 +2582,80 @@ space_list=value1.a value1.b
         assert_eq!(cfg.sections(), cfg2.sections());
     }
 
+    #[test]
+    fn test_serialize_keeps_original_text() {
+        let dir = TempDir::new("test_serialize_keeps_original_text").unwrap();
+        write_file(
+            dir.path().join("hgrc"),
+            "# Top of the file.\n\
+             [ui]\n\
+             # Who I am.\n\
+             username = Foo Bar <foo@example.com>\n\
+             editor = vim\n\
+             \n\
+             [extensions]\n\
+             ; Enabled by default.\n\
+             rebase =\n\
+             %unset histedit\n\
+             [paths]\n\
+             default = ssh://server//repo\n\
+             \n\
+             [merge-patterns]\n\
+             list = a\n\
+             # Not in the value.\n\
+             \x20   b\n\
+             \x20     c\n",
+        );
+
+        let mut cfg = ConfigSet::new();
+        let errors = cfg.load_path(dir.path().join("hgrc"), &"test".into());
+        assert!(errors.is_empty(), "{:?}", errors);
+        cfg.set("ui", "editor", Some("emacs"), &"api".into());
+        cfg.set("paths", "default", None::<&str>, &"api".into());
+        cfg.set("paths", "new", Some("a\nb"), &"api".into());
+
+        let serialized = cfg.serialize();
+        assert_eq!(
+            serialized,
+            "[ui]\n\
+             # Who I am.\n\
+             username = Foo Bar <foo@example.com>\n\
+             editor=emacs\n\
+             \n\
+             [extensions]\n\
+             ; Enabled by default.\n\
+             rebase =\n\
+             %unset histedit\n\
+             \n\
+             [paths]\n\
+             %unset default\n\
+             new=a\n b\n\
+             \n\
+             [merge-patterns]\n\
+             list = a\n\
+             # Not in the value.\n\
+             \x20   b\n\
+             \x20     c\n\
+             \n"
+        );
+
+        // Loading the result gives the same config.
+        let mut cfg2 = ConfigSet::new();
+        let errors = cfg2.parse(serialized, &"".into());
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(cfg.sections(), cfg2.sections());
+        for section in cfg.sections().iter() {
+            assert_eq!(cfg.keys(section), cfg2.keys(section));
+            for key in cfg.keys(section) {
+                assert_eq!(
+                    cfg.get_considering_unset(section, &key),
+                    cfg2.get_considering_unset(section, &key),
+                );
+            }
+        }
+        assert_eq!(cfg2.get("merge-patterns", "list"), Some("a\nb\nc".into()));
+    }
+
     #[test]
     fn test_allowed_locations() {
         let mut cfg = ConfigSet::new();
@@ -1080,6 +2675,7 @@ space_list=value1.a value1.b
                     path: Arc::new(Path::new(location).to_owned()),
                     content: Text::from_static(""),
                     location: 0..1,
+                    included: false,
                 }),
                 &Options::new().source(Text::from_static("source")),
             );
@@ -1175,6 +2771,36 @@ x = 2
         );
     }
 
+    #[test]
+    fn test_key_handle() {
+        let mut cfg = ConfigSet::new();
+        let x = KeyHandle::new("a", "x");
+        let y = KeyHandle::new("a", "y");
+        assert_eq!(x.get(&cfg), None);
+
+        cfg.parse("[a]\nx = 1\ny = 2\n%unset y\n", &"test".into());
+        assert_eq!(x.get(&cfg), Some("1".into()));
+        assert_eq!(x.get_opt::<u32>(&cfg).unwrap(), Some(1));
+        assert_eq!(y.get_considering_unset(&cfg), Some(None));
+
+        // Handles see changes, including new sections and items that move
+        // their positions.
+        let mut cloned = cfg.clone();
+        cloned.parse("[b]\nz = 3\n[a]\nx = 4\n", &"test".into());
+        assert_eq!(x.get(&cloned), Some("4".into()));
+        assert_eq!(x.get(&cfg), Some("1".into()));
+        let z = cloned.key_handle("b", "z");
+        assert_eq!(z.get(&cloned), Some("3".into()));
+        assert_eq!(z.get(&cfg), None);
+
+        // Items missing from the config are read from the secondary.
+        let mut primary = ConfigSet::new();
+        primary.secondary(Arc::new(cloned));
+        assert_eq!(z.get(&primary), Some("3".into()));
+        primary.set("b", "z", Some("5"), &"set".into());
+        assert_eq!(z.get(&primary), Some("5".into()));
+    }
+
     #[test]
     fn test_verifier_removal() {
         let mut cfg = ConfigSet::new();
@@ -1194,6 +2820,7 @@ x = 2
                     path: Arc::new(Path::new(location).to_owned()),
                     content: Text::from_static(""),
                     location: 0..1,
+                    included: false,
                 }),
                 &Options::new().source(Text::from_static("source")),
             );
@@ -1278,4 +2905,117 @@ x = 2
         );
         assert_eq!(cfg.get_or("foo", "float", || 42f32).unwrap(), 1.42f32);
     }
+
+    fn counting_resolver(calls: Arc<AtomicUsize>) -> SecretResolver {
+        Arc::new(move |reference| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            match reference {
+                "keychain/token" => Ok(Bytes::from_static(b"hunter2")),
+                _ => Err(anyhow::anyhow!("no such item")),
+            }
+        })
+    }
+
+    #[test]
+    fn test_secret_resolution() {
+        let mut cfg = ConfigSet::new();
+        cfg.parse(
+            "[auth]\ntoken = secret:keychain/token\nliteral = \\secret:not-a-ref\nplain = x\n",
+            &"test".into(),
+        );
+
+        // No resolver: values are read as written.
+        assert_eq!(
+            cfg.get("auth", "token"),
+            Some("secret:keychain/token".into())
+        );
+        assert_eq!(
+            cfg.get("auth", "literal"),
+            Some("\\secret:not-a-ref".into())
+        );
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        cfg.set_secret_resolver(counting_resolver(calls.clone()));
+        assert_eq!(cfg.get("auth", "token"), Some("hunter2".into()));
+        assert_eq!(
+            cfg.get_opt::<String>("auth", "token").unwrap(),
+            Some("hunter2".to_string())
+        );
+        assert_eq!(cfg.clone().get("auth", "token"), Some("hunter2".into()));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        cfg.purge_secrets();
+        assert_eq!(cfg.get("auth", "token"), Some("hunter2".into()));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        assert_eq!(cfg.get("auth", "literal"), Some("secret:not-a-ref".into()));
+        assert_eq!(cfg.get("auth", "plain"), Some("x".into()));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // A custom prefix.
+        cfg.set_secret_prefix("vault:");
+        cfg.set(
+            "auth",
+            "other",
+            Some("vault:keychain/token"),
+            &"test".into(),
+        );
+        assert_eq!(cfg.get("auth", "other"), Some("hunter2".into()));
+        assert_eq!(
+            cfg.get("auth", "token"),
+            Some("secret:keychain/token".into())
+        );
+    }
+
+    #[test]
+    fn test_secret_resolution_failure() {
+        let mut cfg = ConfigSet::new();
+        cfg.set("auth", "token", Some("secret:missing"), &"test".into());
+        cfg.set_secret_resolver(counting_resolver(Default::default()));
+
+        let err = cfg.get_opt::<String>("auth", "token").unwrap_err();
+        match &err {
+            Error::Secret {
+                section,
+                name,
+                reference,
+                ..
+            } => assert_eq!(
+                (&section[..], &name[..], &reference[..]),
+                ("auth", "token", "missing")
+            ),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+        assert_eq!(
+            err.to_string(),
+            "cannot resolve secret missing of auth.token: no such item"
+        );
+        assert!(cfg.must_get::<String>("auth", "token").is_err());
+
+        // The infallible getter treats it as not set.
+        assert_eq!(cfg.get("auth", "token"), None);
+    }
+
+    #[test]
+    fn test_secret_references_are_not_resolved_when_listed() {
+        let mut cfg = ConfigSet::new();
+        cfg.set(
+            "auth",
+            "token",
+            Some("secret:keychain/token"),
+            &"test".into(),
+        );
+        cfg.set_secret_resolver(counting_resolver(Default::default()));
+        assert_eq!(cfg.get("auth", "token"), Some("hunter2".into()));
+
+        assert_eq!(cfg.to_string(), "[auth]\ntoken=secret:keychain/token\n\n");
+        let sources = cfg.get_sources("auth", "token");
+        assert_eq!(sources[0].value(), &Some("secret:keychain/token".into()));
+        assert_eq!(
+            cfg.get_unresolved("auth", "token"),
+            Some(Some("secret:keychain/token".into()))
+        );
+        let items = cfg.items_with_defaults("auth");
+        assert_eq!(items[0].value, Some("secret:keychain/token".into()));
+    }
 }
@@ -0,0 +1,353 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Defaults registered by the crates reading configs, so the default of a
+//! config can be found in one place instead of at each call site.
+//!
+//! Registered defaults don't change what `Config::get` returns. They are
+//! only used by the `*_with_default` methods, and are listed by
+//! `ConfigSet::items_with_defaults`.
+
+use configmodel::convert::FromConfigValue;
+use configmodel::Config;
+use configmodel::Error;
+use configmodel::Result;
+use indexmap::IndexMap;
+use indexmap::IndexSet;
+use minibytes::Text;
+
+use crate::config::ConfigSet;
+use crate::diff::EffectiveValue;
+use crate::diff::ValueComparison;
+
+/// A default registered with `ConfigSet::register_defaults`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RegisteredDefault {
+    pub value: Text,
+    /// Description of the config.
+    pub doc: Text,
+}
+
+/// A config of `ConfigSet::items_with_defaults`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ItemWithDefault {
+    pub name: Text,
+    /// The value set in the config, if any.
+    pub value: Option<Text>,
+    pub default: Option<RegisteredDefault>,
+}
+
+impl ItemWithDefault {
+    /// The set value, or else the default.
+    pub fn effective_value(&self) -> Option<&Text> {
+        self.value
+            .as_ref()
+            .or_else(|| self.default.as_ref().map(|d| &d.value))
+    }
+
+    /// Whether a default is registered and the effective value is the
+    /// default, either because the config is not set or because it's set to
+    /// the same value.
+    pub fn is_default(&self) -> bool {
+        match (&self.value, &self.default) {
+            (_, None) => false,
+            (None, Some(_)) => true,
+            (Some(value), Some(default)) => value == &default.value,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct RegisteredDefaults {
+    sections: IndexMap<Text, IndexMap<Text, RegisteredDefault>>,
+}
+
+impl RegisteredDefaults {
+    fn get(&self, section: &str, name: &str) -> Option<&RegisteredDefault> {
+        self.sections.get(section)?.get(name)
+    }
+
+    pub(crate) fn sections(&self) -> impl Iterator<Item = &Text> {
+        self.sections.keys()
+    }
+
+    fn names(&self, section: &str) -> impl Iterator<Item = &Text> {
+        self.sections
+            .get(section)
+            .into_iter()
+            .flat_map(|s| s.keys())
+    }
+}
+
+impl ConfigSet {
+    /// Register the defaults of configs in `section`, as
+    /// `(name, default_value, doc)` tuples.
+    ///
+    /// Registering a default again is a no-op, so several crates can
+    /// register defaults of a config they all read. Registering a different
+    /// default for a config fails with `Error::DefaultConflict`, and none of
+    /// `defaults` is registered.
+    pub fn register_defaults(
+        &mut self,
+        section: &str,
+        defaults: Vec<(&str, &str, &str)>,
+    ) -> Result<()> {
+        let mut new_defaults: IndexMap<&str, (&str, &str)> = IndexMap::new();
+        for (name, value, doc) in defaults {
+            let registered = self
+                .defaults()
+                .get(section, name)
+                .map(|d| d.value.as_ref())
+                .or_else(|| new_defaults.get(name).map(|(value, _)| *value));
+            match registered {
+                Some(registered) if registered != value => {
+                    return Err(Error::DefaultConflict {
+                        section: section.to_string(),
+                        name: name.to_string(),
+                        registered: registered.to_string(),
+                        conflicting: value.to_string(),
+                    });
+                }
+                Some(_) => {}
+                None => {
+                    new_defaults.insert(name, (value, doc));
+                }
+            }
+        }
+
+        if new_defaults.is_empty() {
+            return Ok(());
+        }
+        let section = self
+            .defaults_mut()
+            .sections
+            .entry(Text::copy_from_slice(section))
+            .or_default();
+        for (name, (value, doc)) in new_defaults {
+            section.insert(
+                Text::copy_from_slice(name),
+                RegisteredDefault {
+                    value: Text::copy_from_slice(value),
+                    doc: Text::copy_from_slice(doc),
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// The default registered for a config, if any.
+    pub fn registered_default(&self, section: &str, name: &str) -> Option<&RegisteredDefault> {
+        self.defaults().get(section, name)
+    }
+
+    /// Get a config, or its registered default if it's not set.
+    pub fn get_with_default(&self, section: &str, name: &str) -> Option<Text> {
+        self.get(section, name).or_else(|| {
+            self.registered_default(section, name)
+                .map(|d| d.value.clone())
+        })
+    }
+
+    /// Like `get_with_default`, converted to type `T`.
+    pub fn get_opt_with_default<T: FromConfigValue>(
+        &self,
+        section: &str,
+        name: &str,
+    ) -> Result<Option<T>> {
+        self.get_with_default(section, name)
+            .map(|value| T::try_from_str(&value))
+            .transpose()
+    }
+
+    /// List the configs of `section` that are set or have a registered
+    /// default. Set configs come first, in the order of `Config::keys`,
+    /// followed by the unset configs with a default, in registration order.
+    pub fn items_with_defaults(&self, section: &str) -> Vec<ItemWithDefault> {
+        let names: IndexSet<Text> = self
+            .keys(section)
+            .into_iter()
+            .chain(self.defaults().names(section).cloned())
+            .collect();
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let value = self.get_unresolved(section, &name).flatten();
+                let default = self.registered_default(section, &name).cloned();
+                if value.is_none() && default.is_none() {
+                    // Unset, without default.
+                    return None;
+                }
+                Some(ItemWithDefault {
+                    name,
+                    value,
+                    default,
+                })
+            })
+            .collect()
+    }
+
+    /// List configs set to a value other than their registered default, as
+    /// `(section, name, EffectiveValue::Overridden, sources)` tuples like
+    /// `non_default_items`. Values are compared as specified by
+    /// `comparison`.
+    pub fn non_registered_default_items(
+        &self,
+        comparison: ValueComparison,
+    ) -> Vec<(Text, Text, EffectiveValue, Vec<configmodel::ValueSource>)> {
+        let mut result = Vec::new();
+        for section in self.defaults().sections() {
+            for item in self.items_with_defaults(section) {
+                if let (Some(value), Some(default)) = (item.value, item.default) {
+                    if comparison.equal(&value, &default.value) {
+                        continue;
+                    }
+                    let sources = self.get_sources(section, &item.name).into_owned();
+                    let effective = EffectiveValue::Overridden {
+                        default: default.value,
+                        value,
+                    };
+                    result.push((section.clone(), item.name, effective, sources));
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ConfigSet {
+        let mut cfg = ConfigSet::new();
+        cfg.register_defaults(
+            "ui",
+            vec![
+                ("merge", "internal:merge", "Merge tool."),
+                ("paginate", "true", "Use a pager."),
+            ],
+        )
+        .unwrap();
+        cfg
+    }
+
+    #[test]
+    fn test_explicit_value_over_default() {
+        let mut cfg = config();
+        cfg.parse("[ui]\nmerge = vimdiff\n%unset paginate\n", &"user".into());
+
+        // `get` is not affected by defaults.
+        assert_eq!(cfg.get("ui", "paginate"), None);
+        assert_eq!(cfg.get("ui", "missing"), None);
+
+        assert_eq!(cfg.get_with_default("ui", "merge"), Some("vimdiff".into()));
+        assert_eq!(cfg.get_with_default("ui", "paginate"), Some("true".into()));
+        assert_eq!(cfg.get_with_default("ui", "missing"), None);
+        assert_eq!(
+            cfg.get_opt_with_default::<bool>("ui", "paginate").unwrap(),
+            Some(true)
+        );
+
+        cfg.set("ui", "paginate", Some("no"), &"flag".into());
+        assert_eq!(
+            cfg.get_opt_with_default::<bool>("ui", "paginate").unwrap(),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_items_with_defaults() {
+        let mut cfg = config();
+        cfg.parse("[ui]\nusername = alice\npaginate = true\n", &"user".into());
+
+        let items: Vec<_> = cfg
+            .items_with_defaults("ui")
+            .into_iter()
+            .map(|item| {
+                (
+                    item.name.to_string(),
+                    item.effective_value().map(|v| v.to_string()),
+                    item.is_default(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            items,
+            vec![
+                ("username".to_string(), Some("alice".to_string()), false),
+                ("paginate".to_string(), Some("true".to_string()), true),
+                (
+                    "merge".to_string(),
+                    Some("internal:merge".to_string()),
+                    true
+                ),
+            ]
+        );
+        assert_eq!(
+            cfg.items_with_defaults("ui")[2].default,
+            Some(RegisteredDefault {
+                value: "internal:merge".into(),
+                doc: "Merge tool.".into(),
+            })
+        );
+        assert!(cfg.items_with_defaults("diff").is_empty());
+    }
+
+    #[test]
+    fn test_register_conflict() {
+        let mut cfg = config();
+
+        // The same default can be registered again.
+        cfg.register_defaults("ui", vec![("merge", "internal:merge", "")])
+            .unwrap();
+
+        let err = cfg
+            .register_defaults("ui", vec![("color", "auto", ""), ("merge", "vimdiff", "")])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "conflicting defaults registered for ui.merge: \"internal:merge\" and \"vimdiff\""
+        );
+        // Nothing was registered.
+        assert_eq!(cfg.registered_default("ui", "color"), None);
+        assert_eq!(
+            cfg.get_with_default("ui", "merge"),
+            Some("internal:merge".into())
+        );
+
+        // Conflicts within one registration are detected too.
+        assert!(cfg
+            .register_defaults("diff", vec![("git", "true", ""), ("git", "false", "")])
+            .is_err());
+        assert_eq!(cfg.registered_default("diff", "git"), None);
+    }
+
+    #[test]
+    fn test_non_registered_default_items() {
+        let mut cfg = config();
+        cfg.parse(
+            "[ui]\nmerge = vimdiff\npaginate = true\nusername = alice\n",
+            &"user".into(),
+        );
+
+        let items = cfg.non_registered_default_items(ValueComparison::Exact);
+        assert_eq!(items.len(), 1);
+        assert_eq!(
+            (&items[0].0, &items[0].1, &items[0].2),
+            (
+                &Text::from("ui"),
+                &Text::from("merge"),
+                &EffectiveValue::Overridden {
+                    default: "internal:merge".into(),
+                    value: "vimdiff".into(),
+                }
+            )
+        );
+    }
+}
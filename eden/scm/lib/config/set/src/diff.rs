@@ -0,0 +1,216 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Comparison of a config against a set of defaults, to answer "what did the
+//! user change" without diffing config files by hand.
+
+use configmodel::Config;
+use configmodel::ValueSource;
+use indexmap::IndexSet;
+use minibytes::Text;
+
+use crate::config::ConfigSet;
+
+/// How the effective value of a config differs from its default.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EffectiveValue {
+    /// Set by both, to different values.
+    Overridden { default: Text, value: Text },
+    /// Set by the defaults, but unset.
+    Unset { default: Text },
+    /// Set, but not set by the defaults.
+    Added { value: Text },
+}
+
+/// How values are compared by `ConfigSet::non_default_items_with`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ValueComparison {
+    /// Values must be byte-for-byte equal.
+    #[default]
+    Exact,
+    /// Values are equal if they only differ in whitespace, including
+    /// leading, trailing and line breaks of multi-line values.
+    IgnoreWhitespace,
+}
+
+impl ValueComparison {
+    pub(crate) fn equal(self, a: &str, b: &str) -> bool {
+        match self {
+            Self::Exact => a == b,
+            Self::IgnoreWhitespace => {
+                let a = a.chars().filter(|c| !c.is_whitespace());
+                let b = b.chars().filter(|c| !c.is_whitespace());
+                a.eq(b)
+            }
+        }
+    }
+}
+
+impl ConfigSet {
+    /// List configs whose effective value differs from `defaults`, usually
+    /// the builtin layers (see `configloader::hg::load_defaults`).
+    ///
+    /// Returns `(section, name, effective_value, sources)` tuples. `sources`
+    /// are the sources of the config in `self`, the last one being
+    /// effective, so the file responsible for the difference can be found.
+    /// Values are compared byte-for-byte.
+    pub fn non_default_items(
+        &self,
+        defaults: &ConfigSet,
+    ) -> Vec<(Text, Text, EffectiveValue, Vec<ValueSource>)> {
+        self.non_default_items_with(defaults, ValueComparison::Exact)
+    }
+
+    /// Like `non_default_items`, but values are compared as specified by
+    /// `comparison`.
+    pub fn non_default_items_with(
+        &self,
+        defaults: &ConfigSet,
+        comparison: ValueComparison,
+    ) -> Vec<(Text, Text, EffectiveValue, Vec<ValueSource>)> {
+        let sections: IndexSet<Text> = self
+            .sections()
+            .iter()
+            .chain(defaults.sections().iter())
+            .cloned()
+            .collect();
+
+        let mut result = Vec::new();
+        for section in sections {
+            let names: IndexSet<Text> = self
+                .keys(&section)
+                .into_iter()
+                .chain(defaults.keys(&section))
+                .collect();
+            for name in names {
+                let effective = match (self.get(&section, &name), defaults.get(&section, &name)) {
+                    (Some(value), Some(default)) => {
+                        if comparison.equal(&value, &default) {
+                            continue;
+                        }
+                        EffectiveValue::Overridden { default, value }
+                    }
+                    (None, Some(default)) => EffectiveValue::Unset { default },
+                    (Some(value), None) => EffectiveValue::Added { value },
+                    (None, None) => continue,
+                };
+                let sources = self.get_sources(&section, &name).into_owned();
+                result.push((section.clone(), name, effective, sources));
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> ConfigSet {
+        let mut cfg = ConfigSet::new();
+        cfg.parse(
+            "[ui]\nusername = default\nmerge = internal:merge\n\
+             [diff]\ngit = true\n%unset nodates\n",
+            &"builtin".into(),
+        );
+        cfg
+    }
+
+    fn classify(cfg: &ConfigSet, defaults: &ConfigSet) -> Vec<(String, EffectiveValue)> {
+        cfg.non_default_items(defaults)
+            .into_iter()
+            .map(|(section, name, value, _)| (format!("{}.{}", section, name), value))
+            .collect()
+    }
+
+    #[test]
+    fn test_non_default_items() {
+        let defaults = defaults();
+        let mut cfg = defaults.clone();
+        cfg.parse(
+            "[ui]\nusername = alice\nmerge = internal:merge\n\
+             [diff]\n%unset git\nnodates = true\n\
+             [extensions]\nrebase =\n",
+            &"user".into(),
+        );
+
+        assert_eq!(
+            classify(&cfg, &defaults),
+            vec![
+                (
+                    "ui.username".to_string(),
+                    EffectiveValue::Overridden {
+                        default: "default".into(),
+                        value: "alice".into(),
+                    }
+                ),
+                (
+                    "diff.git".to_string(),
+                    EffectiveValue::Unset {
+                        default: "true".into()
+                    }
+                ),
+                (
+                    "diff.nodates".to_string(),
+                    EffectiveValue::Added {
+                        value: "true".into()
+                    }
+                ),
+                (
+                    "extensions.rebase".to_string(),
+                    EffectiveValue::Added { value: "".into() }
+                ),
+            ]
+        );
+
+        // Identical configs have no differences.
+        assert!(defaults.non_default_items(&defaults).is_empty());
+    }
+
+    #[test]
+    fn test_non_default_items_sources() {
+        let defaults = defaults();
+        let mut cfg = defaults.clone();
+        cfg.parse("[ui]\nusername = alice\n", &"user".into());
+
+        let items = cfg.non_default_items(&defaults);
+        assert_eq!(items.len(), 1);
+        let sources: Vec<&str> = items[0].3.iter().map(|s| s.source().as_ref()).collect();
+        assert_eq!(sources, ["builtin", "user"]);
+    }
+
+    #[test]
+    fn test_non_default_items_secondary() {
+        let mut cfg = ConfigSet::new();
+        cfg.secondary(std::sync::Arc::new(defaults()));
+        cfg.parse("[diff]\n%unset git\n", &"user".into());
+
+        assert_eq!(
+            classify(&cfg, &defaults()),
+            vec![(
+                "diff.git".to_string(),
+                EffectiveValue::Unset {
+                    default: "true".into()
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_non_default_items_whitespace() {
+        let defaults = defaults();
+        let mut cfg = defaults.clone();
+        cfg.parse("[ui]\nmerge = internal: merge\n", &"user".into());
+
+        assert_eq!(cfg.non_default_items(&defaults).len(), 1);
+        assert!(cfg
+            .non_default_items_with(&defaults, ValueComparison::IgnoreWhitespace)
+            .is_empty());
+    }
+}
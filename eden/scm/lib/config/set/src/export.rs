@@ -0,0 +1,522 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Export of the effective config in structured formats, for tooling that
+//! would otherwise have to parse `hg config --debug` output.
+//!
+//! The exported document maps sections to names to items:
+//!
+//! ```plain,ignore
+//! {
+//!   "section": {
+//!     "name": {
+//!       "value": "...",
+//!       "sources": [{"source": "user", "path": "/home/x/.hgrc", "line": 3}]
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! Sections and names are in the same order as `Config::sections` and
+//! `Config::keys`. Sources are in load order, the last one being effective.
+//! Configs that are effectively unset are not exported.
+//!
+//! With `ExportOptions::defaults`, configs with a default registered by
+//! `ConfigSet::register_defaults` also have a `default` and an `is_default`
+//! field, and unset ones are exported with their default as `value`.
+//!
+//! Config values are always valid UTF-8. File paths may not be. A path that
+//! is not valid UTF-8 is exported as `path_base64` instead of `path`,
+//! holding the raw bytes of the path (UTF-16 code units, little-endian, on
+//! Windows).
+
+use configmodel::Config;
+use configmodel::Error;
+use configmodel::Result;
+use configmodel::ValueSource;
+use indexmap::IndexMap;
+use indexmap::IndexSet;
+use minibytes::Text;
+use serde::Serialize;
+
+use crate::config::ConfigSet;
+
+/// Value exported in place of redacted values.
+pub const REDACTED: &str = "<redacted>";
+
+/// Format of `ConfigSet::export`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ExportFormat {
+    Json,
+    #[cfg(feature = "export-toml")]
+    Toml,
+}
+
+/// Options that affect `ConfigSet::export`.
+#[derive(Clone, Debug)]
+pub struct ExportOptions {
+    sources: bool,
+    redact: Vec<String>,
+    sections: Option<Vec<String>>,
+    defaults: bool,
+}
+
+#[derive(Serialize)]
+struct ExportedItem {
+    value: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    redacted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sources: Option<Vec<ExportedSource>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    is_default: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct ExportedSource {
+    source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path_base64: Option<String>,
+    /// 1-based line of the value in `path`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            sources: true,
+            redact: Vec::new(),
+            sections: None,
+            defaults: false,
+        }
+    }
+}
+
+impl ExportOptions {
+    /// Create a default `ExportOptions`: all sections, with sources and
+    /// without redaction.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether the sources of each value are exported.
+    pub fn sources(mut self, sources: bool) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    /// Redact values of configs matching `pattern`. The pattern is matched
+    /// against `section.name`, and `*` matches any sequence of characters,
+    /// including `.`. For example, `auth.*.password`.
+    pub fn redact(mut self, pattern: impl Into<String>) -> Self {
+        self.redact.push(pattern.into());
+        self
+    }
+
+    /// Only export the given sections.
+    pub fn sections<S: Into<String>>(mut self, sections: impl IntoIterator<Item = S>) -> Self {
+        self.sections = Some(sections.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Set whether registered defaults are exported.
+    pub fn defaults(mut self, defaults: bool) -> Self {
+        self.defaults = defaults;
+        self
+    }
+
+    fn is_redacted(&self, section: &str, name: &str) -> bool {
+        let full_name = format!("{}.{}", section, name);
+        self.redact
+            .iter()
+            .any(|pattern| glob_match(pattern, &full_name))
+    }
+}
+
+impl ConfigSet {
+    /// Export the effective config, including configs from secondary layers.
+    /// See the `export` module for the format.
+    pub fn export(&self, format: ExportFormat, opts: &ExportOptions) -> Result<String> {
+        let mut sections: IndexSet<Text> = self.sections().iter().cloned().collect();
+        if opts.defaults {
+            sections.extend(self.defaults().sections().cloned());
+        }
+        let mut exported: IndexMap<String, IndexMap<String, ExportedItem>> = IndexMap::new();
+        for section in sections.iter() {
+            if let Some(sections) = &opts.sections {
+                if !sections.iter().any(|s| s == section.as_ref()) {
+                    continue;
+                }
+            }
+            let mut items = IndexMap::new();
+            for (name, value, default) in self.export_values(section, opts.defaults) {
+                let value = match value {
+                    Some(value) => value,
+                    None => continue,
+                };
+                let redacted = opts.is_redacted(section, &name);
+                let sources = if opts.sources {
+                    let sources = self.get_sources(section, &name);
+                    Some(sources.iter().map(export_source).collect())
+                } else {
+                    None
+                };
+                let redact = |value: &Text| {
+                    if redacted {
+                        REDACTED.to_string()
+                    } else {
+                        value.to_string()
+                    }
+                };
+                let item = ExportedItem {
+                    value: redact(&value),
+                    redacted,
+                    sources,
+                    default: default.as_ref().map(redact),
+                    is_default: default.map(|default| default == value),
+                };
+                items.insert(name.to_string(), item);
+            }
+            if !items.is_empty() {
+                exported.insert(section.to_string(), items);
+            }
+        }
+
+        match format {
+            ExportFormat::Json => {
+                serde_json::to_string_pretty(&exported).map_err(|e| Error::General(e.to_string()))
+            }
+            #[cfg(feature = "export-toml")]
+            ExportFormat::Toml => {
+                toml::to_string(&exported).map_err(|e| Error::General(e.to_string()))
+            }
+        }
+    }
+}
+
+impl ConfigSet {
+    /// Effective values of configs of `section`, as `(name, value,
+    /// default)`, where `default` is the registered default, if any, and
+    /// only listed if `defaults` is set.
+    fn export_values(
+        &self,
+        section: &str,
+        defaults: bool,
+    ) -> Vec<(Text, Option<Text>, Option<Text>)> {
+        if defaults {
+            self.items_with_defaults(section)
+                .into_iter()
+                .map(|item| {
+                    let value = item.effective_value().cloned();
+                    (item.name, value, item.default.map(|d| d.value))
+                })
+                .collect()
+        } else {
+            self.keys(section)
+                .into_iter()
+                .map(|name| {
+                    let value = self.get_unresolved(section, &name).flatten();
+                    (name, value, None)
+                })
+                .collect()
+        }
+    }
+}
+
+fn export_source(source: &ValueSource) -> ExportedSource {
+    let mut exported = ExportedSource {
+        source: source.source().to_string(),
+        path: None,
+        path_base64: None,
+        line: None,
+    };
+    if let Some(location) = &source.location {
+        match location.path.to_str() {
+            Some(path) => exported.path = Some(path.to_string()),
+            None => exported.path_base64 = Some(base64::encode(path_bytes(&location.path))),
+        }
+        let offset = location.location.start.min(location.content.len());
+        let line = location.content.as_bytes()[..offset]
+            .iter()
+            .filter(|&&b| b == b'\n')
+            .count()
+            + 1;
+        exported.line = Some(line);
+    }
+    exported
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &std::path::Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(windows)]
+fn path_bytes(path: &std::path::Path) -> Vec<u8> {
+    use std::os::windows::ffi::OsStrExt;
+    path.as_os_str()
+        .encode_wide()
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+/// Match `text` against `pattern`, where `*` matches any sequence of
+/// characters.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => match text.strip_prefix(prefix) {
+            None => false,
+            Some(text) => text
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain(std::iter::once(text.len()))
+                .any(|i| glob_match(rest, &text[i..])),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::config::Options;
+
+    fn export_json(cfg: &ConfigSet, opts: &ExportOptions) -> serde_json::Value {
+        let exported = cfg.export(ExportFormat::Json, opts).unwrap();
+        serde_json::from_str(&exported).unwrap()
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("auth.*.password", "auth.server.password"));
+        assert!(glob_match("auth.*.password", "auth.a.b.password"));
+        assert!(glob_match("auth.*", "auth.x"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("auth.*.password", "auth.server.username"));
+        assert!(!glob_match("auth.x", "auth.xy"));
+    }
+
+    #[test]
+    fn test_export_fixture() {
+        let dir = TempDir::new("test_export_fixture").unwrap();
+        let path = dir.path().join("hgrc");
+        fs::write(
+            &path,
+            "[ui]\nusername = Foo\n\n[auth]\nserver.prefix = example.com\nserver.password = secret\n\n[ui]\n%unset username\nverbose = true\n",
+        )
+        .unwrap();
+        let mut cfg = ConfigSet::new();
+        cfg.set("ui", "username", Some("Bar"), &"--config".into());
+        cfg.load_path(&path, &"user".into());
+        cfg.set("extensions", "rebase", Some(""), &"--config".into());
+        let path = path.to_str().unwrap();
+
+        let exported = export_json(&cfg, &ExportOptions::new().redact("auth.*.password"));
+        assert_eq!(
+            exported,
+            serde_json::json!({
+                "ui": {
+                    "verbose": {
+                        "value": "true",
+                        "sources": [{"source": "user", "path": path, "line": 10}],
+                    },
+                },
+                "auth": {
+                    "server.prefix": {
+                        "value": "example.com",
+                        "sources": [{"source": "user", "path": path, "line": 5}],
+                    },
+                    "server.password": {
+                        "value": REDACTED,
+                        "redacted": true,
+                        "sources": [{"source": "user", "path": path, "line": 6}],
+                    },
+                },
+                "extensions": {
+                    "rebase": {
+                        "value": "",
+                        "sources": [{"source": "--config"}],
+                    },
+                },
+            })
+        );
+
+        // Without redaction, the export round-trips into the same effective
+        // config.
+        let exported = export_json(&cfg, &ExportOptions::new().sources(false));
+        let mut round_trip = ConfigSet::new();
+        for (section, items) in exported.as_object().unwrap() {
+            for (name, item) in items.as_object().unwrap() {
+                assert_eq!(item.as_object().unwrap().len(), 1);
+                let value = item["value"].as_str().unwrap();
+                round_trip.set(section, name, Some(value), &"export".into());
+            }
+        }
+        for section in cfg.sections().iter() {
+            for name in cfg.keys(section) {
+                assert_eq!(cfg.get(section, &name), round_trip.get(section, &name));
+            }
+        }
+
+        let exported = export_json(&cfg, &ExportOptions::new().sources(false).sections(["ui"]));
+        assert_eq!(
+            exported,
+            serde_json::json!({"ui": {"verbose": {"value": "true"}}})
+        );
+    }
+
+    #[test]
+    fn test_export_secondary() {
+        let mut secondary = ConfigSet::new();
+        secondary.set("a", "x", Some("1"), &"secondary".into());
+        secondary.set("a", "y", Some("2"), &"secondary".into());
+        let mut cfg = ConfigSet::new();
+        cfg.secondary(std::sync::Arc::new(secondary));
+        cfg.set("a", "y", Some("3"), &"primary".into());
+
+        let exported = export_json(&cfg, &ExportOptions::new());
+        assert_eq!(
+            exported,
+            serde_json::json!({
+                "a": {
+                    "x": {"value": "1", "sources": [{"source": "secondary"}]},
+                    "y": {
+                        "value": "3",
+                        "sources": [{"source": "secondary"}, {"source": "primary"}],
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_export_secret_reference() {
+        let mut cfg = ConfigSet::new();
+        cfg.set(
+            "auth",
+            "token",
+            Some("secret:keychain/token"),
+            &"test".into(),
+        );
+        cfg.set_secret_resolver(std::sync::Arc::new(|_| Ok("hunter2".into())));
+        assert_eq!(cfg.get("auth", "token"), Some("hunter2".into()));
+
+        let exported = export_json(&cfg, &ExportOptions::new());
+        assert_eq!(
+            exported,
+            serde_json::json!({
+                "auth": {"token": {"value": "secret:keychain/token", "sources": [{"source": "test"}]}},
+            })
+        );
+    }
+
+    #[test]
+    fn test_export_defaults() {
+        let mut cfg = ConfigSet::new();
+        cfg.register_defaults(
+            "ui",
+            vec![("merge", "internal:merge", ""), ("paginate", "true", "")],
+        )
+        .unwrap();
+        cfg.register_defaults("diff", vec![("git", "true", "")])
+            .unwrap();
+        cfg.set("ui", "merge", Some("vimdiff"), &"user".into());
+        cfg.set("ui", "paginate", Some("true"), &"user".into());
+        cfg.set("ui", "verbose", Some("true"), &"user".into());
+
+        // Defaults are not exported unless asked for.
+        let opts = ExportOptions::new().sources(false);
+        assert_eq!(
+            export_json(&cfg, &opts),
+            serde_json::json!({
+                "ui": {
+                    "merge": {"value": "vimdiff"},
+                    "paginate": {"value": "true"},
+                    "verbose": {"value": "true"},
+                },
+            })
+        );
+
+        assert_eq!(
+            export_json(&cfg, &opts.defaults(true)),
+            serde_json::json!({
+                "ui": {
+                    "merge": {"value": "vimdiff", "default": "internal:merge", "is_default": false},
+                    "paginate": {"value": "true", "default": "true", "is_default": true},
+                    "verbose": {"value": "true"},
+                },
+                "diff": {
+                    "git": {"value": "true", "default": "true", "is_default": true},
+                },
+            })
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_export_non_utf8_path() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = TempDir::new("test_export_non_utf8_path").unwrap();
+        let name = OsStr::from_bytes(b"hg\xffrc");
+        let path = dir.path().join(name);
+        fs::write(&path, "[a]\nb = c\n").unwrap();
+        let mut cfg = ConfigSet::new();
+        let errors = cfg.load_path(&path, &Options::from("user"));
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let exported = export_json(&cfg, &ExportOptions::new());
+        let source = &exported["a"]["b"]["sources"][0];
+        assert!(source.get("path").is_none());
+        let encoded = source["path_base64"].as_str().unwrap();
+        assert_eq!(
+            base64::decode(encoded).unwrap(),
+            path.as_os_str().as_bytes()
+        );
+        assert_eq!(source["line"], 2);
+    }
+
+    #[cfg(feature = "export-toml")]
+    #[test]
+    fn test_export_toml() {
+        let mut cfg = ConfigSet::new();
+        cfg.set(
+            "auth",
+            "server.password",
+            Some("secret"),
+            &"--config".into(),
+        );
+        cfg.set("ui", "verbose", Some("true"), &"--config".into());
+        let opts = ExportOptions::new()
+            .sources(false)
+            .redact("auth.*.password");
+        let exported = cfg.export(ExportFormat::Toml, &opts).unwrap();
+        let exported: toml::Value = toml::from_str(&exported).unwrap();
+        assert_eq!(
+            exported["auth"]["server.password"]["value"].as_str(),
+            Some(REDACTED)
+        );
+        assert_eq!(
+            exported["auth"]["server.password"]["redacted"].as_bool(),
+            Some(true)
+        );
+        assert_eq!(exported["ui"]["verbose"]["value"].as_str(), Some("true"));
+    }
+}
@@ -0,0 +1,191 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Expansion of `%include` paths with glob patterns, like `conf.d/*.rc`.
+
+use std::fs;
+use std::io;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Whether `path` of a `%include` is a glob pattern.
+pub(crate) fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// List the files matching `pattern`, sorted by path.
+///
+/// Path components can use `*` (any characters), `?` (one character) and
+/// `[...]` (one of the characters, or a range like `a-z`, negated with a
+/// leading `!`). A `**` component matches any number of directories,
+/// without following symlinks to directories. Like in shells, wildcards
+/// don't match names starting with `.` unless the pattern does.
+///
+/// Only files, or symlinks to files, are listed. Directories that don't
+/// exist match nothing.
+pub(crate) fn expand_glob(pattern: &Path) -> io::Result<Vec<PathBuf>> {
+    let components: Vec<Component> = pattern.components().collect();
+    // Start from the longest prefix without wildcards.
+    let literal_len = components
+        .iter()
+        .position(|c| is_glob(&c.as_os_str().to_string_lossy()))
+        .unwrap_or(components.len());
+    let base: PathBuf = components[..literal_len].iter().collect();
+    let patterns: Vec<String> = components[literal_len..]
+        .iter()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+
+    let mut matches = Vec::new();
+    walk(&base, &patterns, &mut matches)?;
+    matches.sort();
+    // `**` can reach a file in several ways, like `a/**/**/b`.
+    matches.dedup();
+    Ok(matches)
+}
+
+fn walk(dir: &Path, patterns: &[String], matches: &mut Vec<PathBuf>) -> io::Result<()> {
+    let (pattern, rest) = match patterns.split_first() {
+        Some(split) => split,
+        None => {
+            // `fs::metadata` follows symlinks. A dangling symlink is skipped.
+            if fs::metadata(dir).is_ok_and(|m| m.is_file()) {
+                matches.push(dir.to_path_buf());
+            }
+            return Ok(());
+        }
+    };
+    if !is_glob(pattern) {
+        return walk(&dir.join(pattern), rest, matches);
+    }
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) if !dir.is_dir() => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if pattern == "**" {
+        walk(dir, rest, matches)?;
+    }
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if pattern == "**" {
+            if !name.starts_with('.') && entry.file_type()?.is_dir() {
+                walk(&entry.path(), patterns, matches)?;
+            }
+        } else if matches_name(pattern, &name) {
+            walk(&entry.path(), rest, matches)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the file name `name` matches the `pattern` of a path component.
+fn matches_name(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_chars(&pattern, &name)
+}
+
+fn matches_chars(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| matches_chars(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && matches_chars(rest, &name[1..]),
+        Some(('[', rest)) => match (name.split_first(), parse_class(rest)) {
+            (Some((c, name_rest)), Some((matches_class, len))) => {
+                matches_class(*c) && matches_chars(&rest[len..], name_rest)
+            }
+            (None, _) => false,
+            // An unclosed `[` is matched literally.
+            (Some((c, name_rest)), None) => *c == '[' && matches_chars(rest, name_rest),
+        },
+        Some((p, rest)) => name.first() == Some(p) && matches_chars(rest, &name[1..]),
+    }
+}
+
+/// Parse a character class following `[`. Return a predicate and the
+/// length of the class, including the closing `]`.
+fn parse_class(pattern: &[char]) -> Option<(impl Fn(char) -> bool + '_, usize)> {
+    let (negated, start) = match pattern.first() {
+        Some('!') => (true, 1),
+        _ => (false, 0),
+    };
+    // A `]` right after the opening `[` is part of the class.
+    let end = start + 1 + pattern.get(start + 1..)?.iter().position(|c| *c == ']')?;
+    let class = &pattern[start..end];
+    let predicate = move |c: char| {
+        let mut i = 0;
+        let mut found = false;
+        while i < class.len() {
+            if i + 2 < class.len() && class[i + 1] == '-' {
+                found |= class[i] <= c && c <= class[i + 2];
+                i += 3;
+            } else {
+                found |= class[i] == c;
+                i += 1;
+            }
+        }
+        found != negated
+    };
+    Some((predicate, end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_matches_name() {
+        assert!(matches_name("*.rc", "a.rc"));
+        assert!(!matches_name("*.rc", ".rc"));
+        assert!(matches_name(".*.rc", ".a.rc"));
+        assert!(matches_name("a?c", "abc"));
+        assert!(!matches_name("a?c", "ac"));
+        assert!(matches_name("[a-c]*", "b.rc"));
+        assert!(!matches_name("[!a-c]*", "b.rc"));
+        assert!(matches_name("[]]", "]"));
+        assert!(matches_name("a[", "a["));
+        assert!(!matches_name("*.rc", "a.rc~"));
+    }
+
+    #[test]
+    fn test_expand_glob() {
+        let dir = TempDir::new("test_expand_glob").unwrap();
+        for path in ["b.rc", "a.rc", "x.txt", ".hidden.rc", "d/c.rc", "d/e/f.rc"] {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let names = |pattern: &str| -> Vec<String> {
+            expand_glob(&dir.path().join(pattern))
+                .unwrap()
+                .iter()
+                .map(|path| {
+                    let path = path.strip_prefix(dir.path()).unwrap();
+                    path.to_string_lossy().replace('\\', "/")
+                })
+                .collect()
+        };
+        assert_eq!(names("*.rc"), ["a.rc", "b.rc"]);
+        assert_eq!(names("**/*.rc"), ["a.rc", "b.rc", "d/c.rc", "d/e/f.rc"]);
+        assert_eq!(names("d/**/*.rc"), ["d/c.rc", "d/e/f.rc"]);
+        assert_eq!(names("*/c.rc"), ["d/c.rc"]);
+        // Directories are not listed.
+        assert_eq!(names("*"), ["a.rc", "b.rc", "x.txt"]);
+        assert!(names("missing/*.rc").is_empty());
+        assert!(names("a.rc/*").is_empty());
+    }
+}
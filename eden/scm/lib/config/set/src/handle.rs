@@ -0,0 +1,129 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Handles to config items read over and over, like `ui.quiet`.
+
+use std::sync::atomic::fence;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+use configmodel::convert::FromConfigValue;
+use configmodel::Result;
+use minibytes::Text;
+
+use crate::config::ConfigSet;
+
+/// A config item whose position in a `ConfigSet` is remembered after the
+/// first read, so that reading it again indexes into the `ConfigSet`
+/// instead of looking up the section and the name.
+///
+/// The position is looked up again once the `ConfigSet` was changed, or if
+/// the handle is used with another `ConfigSet`. Values are the same as the
+/// ones of `Config::get`, including resolved secrets and values of the
+/// secondary config.
+///
+/// Handles can be kept in a `static`, one per call site.
+pub struct KeyHandle {
+    section: Text,
+    name: Text,
+    // Generation of the `ConfigSet` `index` was looked up in, `UNRESOLVED`,
+    // or `LOCKED` while `index` is changed. Together, they are a seqlock:
+    // reads don't take a lock, so they are cheaper than the lookup.
+    generation: AtomicU64,
+    // Position of the item, packed by `pack`.
+    index: AtomicU64,
+}
+
+const UNRESOLVED: u64 = u64::MAX - 1;
+const LOCKED: u64 = u64::MAX;
+const NOT_SET: u64 = u64::MAX;
+
+impl KeyHandle {
+    /// Return a handle to `section.name`. It is resolved on first read.
+    pub fn new(section: &str, name: &str) -> Self {
+        Self {
+            section: Text::copy_from_slice(section),
+            name: Text::copy_from_slice(name),
+            generation: AtomicU64::new(UNRESOLVED),
+            index: AtomicU64::new(NOT_SET),
+        }
+    }
+
+    pub fn section(&self) -> &str {
+        &self.section
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Like `Config::get`.
+    pub fn get(&self, config: &ConfigSet) -> Option<Text> {
+        self.get_considering_unset(config)?
+    }
+
+    /// Like `Config::get_considering_unset`.
+    pub fn get_considering_unset(&self, config: &ConfigSet) -> Option<Option<Text>> {
+        config.get_at(self.index(config), &self.section, &self.name)
+    }
+
+    /// Like `ConfigExt::get_opt`.
+    pub fn get_opt<T: FromConfigValue>(&self, config: &ConfigSet) -> Result<Option<T>> {
+        self.get(config)
+            .map(|value| T::try_from_str(&value))
+            .transpose()
+    }
+
+    fn index(&self, config: &ConfigSet) -> Option<(usize, usize)> {
+        let generation = config.generation();
+        let resolved = self.generation.load(Ordering::Acquire);
+        if resolved == generation {
+            let index = self.index.load(Ordering::Relaxed);
+            fence(Ordering::Acquire);
+            if self.generation.load(Ordering::Relaxed) == generation {
+                return unpack(index);
+            }
+        }
+
+        let index = config.item_index(&self.section, &self.name);
+        // Only one thread updates the position. The others use the one
+        // they looked up.
+        if let Some(packed) = pack(index) {
+            if resolved != LOCKED
+                && self
+                    .generation
+                    .compare_exchange(resolved, LOCKED, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok()
+            {
+                fence(Ordering::Release);
+                self.index.store(packed, Ordering::Relaxed);
+                self.generation.store(generation, Ordering::Release);
+            }
+        }
+        index
+    }
+}
+
+/// Pack a position into a `u64`, unless its indexes are too large.
+fn pack(index: Option<(usize, usize)>) -> Option<u64> {
+    match index {
+        None => Some(NOT_SET),
+        Some((section, item)) => {
+            let section = u32::try_from(section).ok()?;
+            let item = u32::try_from(item).ok().filter(|item| *item != u32::MAX)?;
+            Some(((section as u64) << 32) | item as u64)
+        }
+    }
+}
+
+fn unpack(packed: u64) -> Option<(usize, usize)> {
+    if packed == NOT_SET {
+        None
+    } else {
+        Some(((packed >> 32) as usize, packed as u32 as usize))
+    }
+}
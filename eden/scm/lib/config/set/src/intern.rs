@@ -0,0 +1,61 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use minibytes::Text;
+
+/// Deduplicates values, sources and paths stored in a `ConfigSet`, so that
+/// the same value set by many config files, or set again and again, is
+/// backed by a single buffer.
+#[derive(Clone, Default)]
+pub(crate) struct Interner {
+    texts: HashSet<Text>,
+    paths: HashSet<Arc<PathBuf>>,
+}
+
+impl Interner {
+    /// Return a `Text` equal to `text`, sharing the buffer of the first equal
+    /// `Text` that was interned.
+    pub(crate) fn intern(&mut self, text: Text) -> Text {
+        match self.texts.get(text.as_ref()) {
+            Some(interned) => interned.clone(),
+            None => {
+                self.texts.insert(text.clone());
+                text
+            }
+        }
+    }
+
+    /// Like `intern`, but only copies `s` if no equal `Text` was interned.
+    pub(crate) fn intern_str(&mut self, s: &str) -> Text {
+        match self.texts.get(s) {
+            Some(interned) => interned.clone(),
+            None => {
+                let text = Text::copy_from_slice(s);
+                self.texts.insert(text.clone());
+                text
+            }
+        }
+    }
+
+    /// Return a shared `path`.
+    pub(crate) fn intern_path(&mut self, path: &Path) -> Arc<PathBuf> {
+        let path = path.to_path_buf();
+        match self.paths.get(&path) {
+            Some(interned) => interned.clone(),
+            None => {
+                let path = Arc::new(path);
+                self.paths.insert(path.clone());
+                path
+            }
+        }
+    }
+}
@@ -0,0 +1,310 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Immutable config layers, and stacks of them that can be pushed and popped
+//! without parsing the layers below again.
+//!
+//! A long running process can keep the system and user configs parsed as
+//! base layers, then for each request push the repo and command line layers
+//! on a cheap clone of the stack.
+
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use configmodel::Config;
+use configmodel::ValueSource;
+use minibytes::Text;
+
+use crate::config::merge_cow_list;
+use crate::config::ConfigSet;
+
+/// A parsed config layer. It can no longer be changed, and clones share the
+/// parsed config.
+///
+/// Build a `ConfigSet` with `load_path`, `load_reader`, `parse` or `set`,
+/// then convert it into a layer. The layer is named after the `ConfigSet`.
+#[derive(Clone)]
+pub struct ConfigLayer {
+    config: Arc<ConfigSet>,
+}
+
+/// A stack of `ConfigLayer`s. Configs are looked up from the top layer down,
+/// so `%unset` in a layer hides the values of the layers below it.
+///
+/// Cloning a stack only clones the pointers to its layers.
+#[derive(Clone, Default)]
+pub struct ConfigStack {
+    layers: Vec<ConfigLayer>,
+    // Number of layers passed to `new`, which `pop` leaves in place.
+    base_len: usize,
+}
+
+impl ConfigLayer {
+    /// The config of this layer.
+    pub fn config(&self) -> &ConfigSet {
+        &self.config
+    }
+
+    /// The name of this layer.
+    pub fn name(&self) -> Text {
+        self.config.layer_name()
+    }
+}
+
+impl From<ConfigSet> for ConfigLayer {
+    fn from(config: ConfigSet) -> Self {
+        Self {
+            config: Arc::new(config),
+        }
+    }
+}
+
+impl ConfigStack {
+    /// Create a stack of `base_layers`, the last one being the top one.
+    pub fn new(base_layers: impl IntoIterator<Item = ConfigLayer>) -> Self {
+        let layers: Vec<_> = base_layers.into_iter().collect();
+        let base_len = layers.len();
+        Self { layers, base_len }
+    }
+
+    /// Push `layer` on top of the stack. It overrides all other layers.
+    pub fn push(&mut self, layer: ConfigLayer) -> &mut Self {
+        self.layers.push(layer);
+        self
+    }
+
+    /// Remove the top layer, restoring the values it overrode.
+    ///
+    /// Return `None`, without removing anything, if only the base layers
+    /// are left.
+    pub fn pop(&mut self) -> Option<ConfigLayer> {
+        if self.layers.len() > self.base_len {
+            self.layers.pop()
+        } else {
+            None
+        }
+    }
+
+    /// The layer the effective value of a config, including an unset one,
+    /// comes from.
+    ///
+    /// Return `None` if no layer has the config.
+    pub fn owning_layer(&self, section: &str, name: &str) -> Option<&ConfigLayer> {
+        // Check the sources, so a value that cannot be computed, like a
+        // secret that cannot be resolved, still belongs to its layer.
+        self.layers
+            .iter()
+            .rev()
+            .find(|layer| !layer.get_sources(section, name).is_empty())
+    }
+}
+
+impl Config for ConfigLayer {
+    fn keys(&self, section: &str) -> Vec<Text> {
+        self.config.keys(section)
+    }
+
+    fn get_considering_unset(&self, section: &str, name: &str) -> Option<Option<Text>> {
+        self.config.get_considering_unset(section, name)
+    }
+
+    fn try_get_considering_unset(
+        &self,
+        section: &str,
+        name: &str,
+    ) -> configmodel::Result<Option<Option<Text>>> {
+        self.config.try_get_considering_unset(section, name)
+    }
+
+    fn sections(&self) -> Cow<'_, [Text]> {
+        self.config.sections()
+    }
+
+    fn get_sources(&self, section: &str, name: &str) -> Cow<'_, [ValueSource]> {
+        self.config.get_sources(section, name)
+    }
+
+    fn files(&self) -> Cow<'_, [PathBuf]> {
+        Config::files(&*self.config)
+    }
+
+    fn layer_name(&self) -> Text {
+        self.config.layer_name()
+    }
+
+    fn layers(&self) -> Vec<Arc<dyn Config>> {
+        self.config.layers()
+    }
+}
+
+impl Config for ConfigStack {
+    /// Get config names under a section, in the order of the layers.
+    fn keys(&self, section: &str) -> Vec<Text> {
+        self.layers
+            .iter()
+            .fold(Cow::Borrowed(&[][..]), |keys, layer| {
+                merge_cow_list(keys, Cow::Owned(layer.keys(section)))
+            })
+            .into_owned()
+    }
+
+    /// Get the value of the top layer that has the config, which is `Some(None)`
+    /// if that layer unsets it.
+    fn get_considering_unset(&self, section: &str, name: &str) -> Option<Option<Text>> {
+        self.owning_layer(section, name)?
+            .get_considering_unset(section, name)
+    }
+
+    fn try_get_considering_unset(
+        &self,
+        section: &str,
+        name: &str,
+    ) -> configmodel::Result<Option<Option<Text>>> {
+        match self.owning_layer(section, name) {
+            Some(layer) => layer.try_get_considering_unset(section, name),
+            None => Ok(None),
+        }
+    }
+
+    fn sections(&self) -> Cow<'_, [Text]> {
+        self.layers
+            .iter()
+            .fold(Cow::Borrowed(&[][..]), |sections, layer| {
+                merge_cow_list(sections, layer.sections())
+            })
+    }
+
+    /// Get the sources of all layers, from the bottom one up.
+    fn get_sources(&self, section: &str, name: &str) -> Cow<'_, [ValueSource]> {
+        self.layers
+            .iter()
+            .fold(Cow::Borrowed(&[][..]), |sources, layer| {
+                let layer_sources = layer.get_sources(section, name);
+                if sources.is_empty() {
+                    layer_sources
+                } else if layer_sources.is_empty() {
+                    sources
+                } else {
+                    Cow::Owned([sources, layer_sources].concat())
+                }
+            })
+    }
+
+    fn files(&self) -> Cow<'_, [PathBuf]> {
+        self.layers
+            .iter()
+            .fold(Cow::Borrowed(&[][..]), |files, layer| {
+                merge_cow_list(files, layer.files())
+            })
+    }
+
+    fn layer_name(&self) -> Text {
+        Text::from_static("ConfigStack")
+    }
+
+    fn layers(&self) -> Vec<Arc<dyn Config>> {
+        self.layers
+            .iter()
+            .map(|layer| Arc::new(layer.clone()) as Arc<dyn Config>)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn layer(name: &str, content: &str) -> ConfigLayer {
+        let mut config = ConfigSet::new();
+        config.named(name);
+        let errors = config.parse(content.to_string(), &name.to_string().into());
+        assert!(errors.is_empty(), "{:?}", errors);
+        config.into()
+    }
+
+    #[test]
+    fn test_push_and_pop() {
+        let system = layer("system", "[a]\nx = 1\ny = 1\n");
+        let user = layer("user", "[a]\ny = 2\n[b]\nz = 2\n");
+        let mut stack = ConfigStack::new(vec![system, user]);
+        assert_eq!(stack.get("a", "x"), Some("1".into()));
+        assert_eq!(stack.get("a", "y"), Some("2".into()));
+        assert_eq!(stack.sections().as_ref(), ["a", "b"]);
+
+        stack.push(layer("repo", "[a]\n%unset x\n[c]\nw = 3\n"));
+        assert_eq!(stack.get_considering_unset("a", "x"), Some(None));
+        assert_eq!(stack.owning_layer("a", "x").unwrap().name(), "repo");
+        assert_eq!(stack.owning_layer("a", "y").unwrap().name(), "user");
+        assert!(stack.owning_layer("a", "w").is_none());
+        assert_eq!(stack.keys("a"), ["x", "y"]);
+        assert_eq!(stack.sections().as_ref(), ["a", "b", "c"]);
+        let sources = stack.get_sources("a", "x");
+        let sources: Vec<_> = sources.iter().map(|s| s.source().as_ref()).collect();
+        assert_eq!(sources, ["system", "repo"]);
+
+        stack.push(layer("cli", "[a]\nx = 4\n"));
+        assert_eq!(stack.get("a", "x"), Some("4".into()));
+
+        // Popping restores the values overridden by each layer, including
+        // the one unsetting `a.x`.
+        assert_eq!(stack.pop().unwrap().name(), "cli");
+        assert_eq!(stack.get_considering_unset("a", "x"), Some(None));
+        assert_eq!(stack.pop().unwrap().name(), "repo");
+        assert_eq!(stack.get("a", "x"), Some("1".into()));
+        assert_eq!(stack.owning_layer("a", "x").unwrap().name(), "system");
+        assert_eq!(stack.sections().as_ref(), ["a", "b"]);
+
+        // Base layers stay.
+        assert!(stack.pop().is_none());
+        assert_eq!(stack.get("b", "z"), Some("2".into()));
+    }
+
+    #[test]
+    fn test_stacks_sharing_base_layers() {
+        let base = ConfigStack::new(vec![
+            layer("system", "[a]\nx = 1\n"),
+            layer("user", "[a]\ny = 1\n"),
+        ]);
+
+        thread::scope(|scope| {
+            for i in 0..8 {
+                let mut stack = base.clone();
+                scope.spawn(move || {
+                    let value = i.to_string();
+                    let mut config = ConfigSet::new();
+                    config.named("repo");
+                    config.set("a", "y", Some(&value), &"repo".into());
+                    if i % 2 == 0 {
+                        config.set("a", "x", None::<&str>, &"repo".into());
+                    }
+                    stack.push(config.into());
+                    for _ in 0..100 {
+                        assert_eq!(stack.get("a", "y"), Some(Text::from(value.clone())));
+                        if i % 2 == 0 {
+                            assert_eq!(stack.get_considering_unset("a", "x"), Some(None));
+                        } else {
+                            assert_eq!(stack.get("a", "x"), Some("1".into()));
+                        }
+                    }
+                    stack.pop();
+                    assert_eq!(stack.get("a", "x"), Some("1".into()));
+                    assert_eq!(stack.get("a", "y"), Some("1".into()));
+                });
+            }
+        });
+
+        // The base layers are shared, not copied.
+        let stack = base.clone();
+        for (a, b) in stack.layers.iter().zip(base.layers.iter()) {
+            assert!(Arc::ptr_eq(&a.config, &b.config));
+        }
+        assert_eq!(base.get("a", "y"), Some("1".into()));
+    }
+}
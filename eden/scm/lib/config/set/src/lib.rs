@@ -47,8 +47,54 @@
 //! ```
 //!
 //! The include path is relative to the directory of the current config
-//! file being parsed. If it's a directory, files with names ending
-//! with `.rc` in it will be read.
+//! file being parsed. If it's a directory, files directly inside it are
+//! read, using the following rules so the result is the same on every
+//! platform and filesystem:
+//!
+//! - Only names ending with `.rc` are read. Names starting with `.`
+//!   (hidden files) and editor backups ending with `~` or `.bak` are
+//!   skipped.
+//! - Symlinks to files are followed. Subdirectories, including symlinks
+//!   to directories, are not recursed into.
+//! - Files are read in byte-wise order of their names, so a later file
+//!   overrides values set by an earlier one.
+//!
+//! `ConfigSet::dir_includes` reports what each included directory expanded
+//! to.
+//!
+//! The include path can also be a glob pattern, expanded relative to the
+//! same directory, after `~` and environment variables:
+//!
+//! ```plain,ignore
+//! %include conf.d/*.rc
+//! %include ~/rc/**/*.rc
+//! ```
+//!
+//! `*`, `?` and `[...]` match within a path component, and a `**` component
+//! matches any number of directories. Like in shells, wildcards don't match
+//! names starting with `.`. Matched files are read in path order. A pattern
+//! matching nothing is not an error, like the include of a missing file.
+//! A pattern matching a file being loaded, like the current file or one
+//! including it, is reported as `Error::IncludeCycle` with the chain of
+//! includes.
+//!
+//! Relative include paths can use both `/` and `\\` as separators on every
+//! platform. Windows absolute paths, like `C:\\rc\\hg.rc` or
+//! `\\\\server\\share\\hg.rc`, are included on Windows, and reported as
+//! `Error::WindowsInclude` elsewhere. On Windows, files whose paths differ
+//! only by case are loaded once.
+//!
+//! The include path can be an `http://` or `https://` URL, fetched by the
+//! `RemoteFetcher` set with `Options::remote_fetcher`:
+//!
+//! ```plain,ignore
+//! %include https://config.example.com/team.rc
+//! ```
+//!
+//! Only URLs starting with a prefix allowed by `Options::allow_remote_prefix`
+//! are fetched, up to `Options::max_remote_size` bytes. Relative includes of
+//! fetched content are resolved against its URL. Content served from a
+//! stale cache is loaded, and reported by `ConfigSet::warnings`.
 //!
 //! ### Unset a config
 //!
@@ -72,13 +118,63 @@
 
 mod builtin;
 pub mod config;
+pub mod defaults;
+pub mod diff;
+#[cfg(feature = "export")]
+pub mod export;
+mod glob;
+pub mod handle;
+mod intern;
+pub mod layer;
+pub mod remote;
+pub mod secret;
+mod winpath;
 
+pub use config::ConfigSet;
+pub use config::Options;
 pub use configmodel;
 pub use configmodel::convert;
 pub use configmodel::error;
 pub use configmodel::Config;
+pub use configmodel::ConfigExt;
 pub use configmodel::Error;
 pub use configmodel::Result;
+pub use configmodel::ValueLocation;
+pub use configmodel::ValueSource;
+pub use defaults::ItemWithDefault;
+pub use defaults::RegisteredDefault;
 pub use error::Errors;
+#[cfg(feature = "export")]
+pub use export::ExportFormat;
+#[cfg(feature = "export")]
+pub use export::ExportOptions;
+pub use handle::KeyHandle;
+pub use layer::ConfigLayer;
+pub use layer::ConfigStack;
+pub use remote::CacheValidity;
+pub use remote::RemoteFetcher;
+pub use remote::Warning;
 // Re-export
 pub use minibytes::Text;
+pub use secret::SecretResolver;
+
+/// The supported types for loading and reading configs.
+///
+/// ```
+/// use configset::prelude::*;
+///
+/// let mut config = ConfigSet::new();
+/// config.parse("[a]\nb = 1\n", &Options::default().source("doc"));
+/// assert_eq!(config.get_opt::<u32>("a", "b").unwrap(), Some(1));
+/// ```
+pub mod prelude {
+    pub use crate::Config;
+    pub use crate::ConfigExt;
+    pub use crate::ConfigSet;
+    pub use crate::Error;
+    pub use crate::Options;
+    pub use crate::Result;
+    pub use crate::Text;
+    pub use crate::ValueLocation;
+    pub use crate::ValueSource;
+}
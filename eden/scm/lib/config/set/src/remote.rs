@@ -0,0 +1,176 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! `%include` of `http://` and `https://` URLs, fetched by a
+//! `RemoteFetcher` set with `Options::remote_fetcher`.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use minibytes::Bytes;
+
+/// The default of `Options::max_remote_size`.
+pub const DEFAULT_MAX_REMOTE_SIZE: usize = 1 << 20;
+
+/// Whether fetched content is up to date.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheValidity {
+    Fresh,
+    /// The content was served from a cache that could not be refreshed, and
+    /// may be outdated. It is loaded, with a `Warning::StaleRemoteInclude`.
+    Stale,
+}
+
+/// Fetch the content of remote includes.
+pub trait RemoteFetcher: Send + Sync {
+    fn fetch(&self, url: &str) -> anyhow::Result<(Bytes, CacheValidity)>;
+}
+
+/// Something worth reporting that didn't stop loading config, see
+/// `ConfigSet::warnings`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Warning {
+    /// The remote include `url` of `path` was served stale.
+    ///
+    /// Displayed as `"<path>": %include <url> was served from a stale cache`.
+    StaleRemoteInclude { path: PathBuf, url: String },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::StaleRemoteInclude { path, url } => {
+                write!(
+                    f,
+                    "{:?}: %include {} was served from a stale cache",
+                    path, url
+                )
+            }
+        }
+    }
+}
+
+/// How remote includes are fetched, set by `Options`.
+#[derive(Clone)]
+pub(crate) struct RemoteIncludes {
+    pub(crate) fetcher: Option<Arc<dyn RemoteFetcher>>,
+    pub(crate) allowed_prefixes: Vec<String>,
+    pub(crate) max_size: usize,
+}
+
+impl Default for RemoteIncludes {
+    fn default() -> Self {
+        Self {
+            fetcher: None,
+            allowed_prefixes: Vec::new(),
+            max_size: DEFAULT_MAX_REMOTE_SIZE,
+        }
+    }
+}
+
+impl RemoteIncludes {
+    pub(crate) fn is_allowed(&self, url: &str) -> bool {
+        self.allowed_prefixes
+            .iter()
+            .any(|prefix| url.starts_with(prefix.as_str()))
+    }
+}
+
+/// Whether `include` is a URL rather than a path.
+pub(crate) fn is_remote(include: &str) -> bool {
+    include.starts_with("http://") || include.starts_with("https://")
+}
+
+/// Resolve `include`, of the remote include `base`, into a URL.
+///
+/// URLs are kept as is. `//host/path` keeps the scheme of `base`, and
+/// `/path` its scheme and host. Other includes are relative to the
+/// directory of `base`, with `.` and `..` segments resolved.
+pub(crate) fn resolve_url(base: &str, include: &str) -> String {
+    if is_remote(include) {
+        return include.to_string();
+    }
+    // `base` is remote, so it has a scheme and a host.
+    let (scheme, rest) = base.split_once("://").unwrap_or(("https", base));
+    if let Some(rest) = include.strip_prefix("//") {
+        return format!("{}://{}", scheme, rest);
+    }
+    let (host, base_path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+    let joined = if include.starts_with('/') {
+        include.to_string()
+    } else {
+        // Drop the query and fragment, then the last segment.
+        let base_path = base_path.split(['?', '#']).next().unwrap_or_default();
+        let dir = &base_path[..base_path.rfind('/').map_or(0, |index| index + 1)];
+        format!("{}{}", dir, include)
+    };
+
+    let mut segments: Vec<&str> = Vec::new();
+    let mut parts = joined.split('/').skip(1).peekable();
+    while let Some(part) = parts.next() {
+        let is_last = parts.peek().is_none();
+        match part {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => {
+                segments.push(part);
+                continue;
+            }
+        }
+        // `a/.` and `a/..` name a directory.
+        if is_last {
+            segments.push("");
+        }
+    }
+    format!("{}://{}/{}", scheme, host, segments.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_url() {
+        let base = "https://example.com/conf/team/main.rc?rev=1";
+        assert_eq!(
+            resolve_url(base, "extra.rc"),
+            "https://example.com/conf/team/extra.rc"
+        );
+        assert_eq!(
+            resolve_url(base, "./a/b.rc"),
+            "https://example.com/conf/team/a/b.rc"
+        );
+        assert_eq!(
+            resolve_url(base, "../common.rc"),
+            "https://example.com/conf/common.rc"
+        );
+        assert_eq!(
+            resolve_url(base, "../../../x.rc"),
+            "https://example.com/x.rc"
+        );
+        assert_eq!(resolve_url(base, "/root.rc"), "https://example.com/root.rc");
+        assert_eq!(
+            resolve_url(base, "//other.com/x.rc"),
+            "https://other.com/x.rc"
+        );
+        assert_eq!(
+            resolve_url(base, "http://other.com/x.rc"),
+            "http://other.com/x.rc"
+        );
+        assert_eq!(
+            resolve_url("https://example.com", "x.rc"),
+            "https://example.com/x.rc"
+        );
+    }
+}
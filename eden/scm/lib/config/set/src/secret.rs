@@ -0,0 +1,102 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Config values referring to a secret store, like
+//! `auth.token = secret:keychain/item`, resolved when they are read.
+//!
+//! Only reading a value resolves it. `get_sources`, serialization and export
+//! show the reference as written in the config file.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use configmodel::Error;
+use configmodel::Result;
+use minibytes::Bytes;
+use minibytes::Text;
+
+/// Resolve the reference of a secret, which is the config value without the
+/// prefix, into the secret.
+pub type SecretResolver = Arc<dyn Fn(&str) -> anyhow::Result<Bytes> + Send + Sync>;
+
+/// The default prefix of config values referring to a secret.
+pub const DEFAULT_SECRET_PREFIX: &str = "secret:";
+
+/// Prefix of a value starting with the secret prefix that is not a reference.
+const ESCAPE: char = '\\';
+
+#[derive(Clone, Default)]
+pub(crate) struct Secrets {
+    resolver: Option<SecretResolver>,
+    // `None` means `DEFAULT_SECRET_PREFIX`.
+    prefix: Option<Text>,
+    // Resolved secrets by reference. Shared by clones.
+    cache: Arc<Mutex<HashMap<Text, Text>>>,
+}
+
+impl Secrets {
+    pub(crate) fn set_resolver(&mut self, resolver: SecretResolver) {
+        self.resolver = Some(resolver);
+        self.cache = Default::default();
+    }
+
+    pub(crate) fn set_prefix(&mut self, prefix: &str) {
+        self.prefix = Some(Text::copy_from_slice(prefix));
+        self.cache = Default::default();
+    }
+
+    pub(crate) fn purge(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    fn prefix(&self) -> &str {
+        self.prefix.as_deref().unwrap_or(DEFAULT_SECRET_PREFIX)
+    }
+
+    /// Resolve `value` of `section.name` if it refers to a secret. Unescape
+    /// it if it is an escaped value starting with the prefix.
+    pub(crate) fn resolve(&self, section: &str, name: &str, value: Text) -> Result<Text> {
+        let resolver = match &self.resolver {
+            None => return Ok(value),
+            Some(resolver) => resolver,
+        };
+        let prefix = self.prefix();
+        if let Some(escaped) = value.strip_prefix(ESCAPE) {
+            if escaped.starts_with(prefix) {
+                return Ok(value.slice_to_bytes(escaped));
+            }
+        }
+        let reference = match value.strip_prefix(prefix) {
+            None => return Ok(value),
+            Some(reference) => value.slice_to_bytes(reference),
+        };
+
+        if let Some(secret) = self.cache.lock().unwrap().get(&reference) {
+            return Ok(secret.clone());
+        }
+        let error = |source| Error::Secret {
+            section: section.to_string(),
+            name: name.to_string(),
+            reference: reference.to_string(),
+            source,
+        };
+        let secret = resolver(&reference).map_err(error)?;
+        let secret = match String::from_utf8(secret.to_vec()) {
+            Ok(secret) => Text::from(secret),
+            Err(_) => return Err(error(anyhow::anyhow!("secret is not valid UTF-8"))),
+        };
+        tracing::debug!(
+            section,
+            name,
+            reference = reference.as_ref(),
+            "resolved secret"
+        );
+        self.cache.lock().unwrap().insert(reference, secret.clone());
+        Ok(secret)
+    }
+}
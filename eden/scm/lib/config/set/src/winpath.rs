@@ -0,0 +1,109 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Windows path semantics of `%include`, applied on every platform so a
+//! config written on Windows resolves the same way elsewhere.
+
+use std::borrow::Cow;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Whether `path` is not relative on Windows: it starts with a drive
+/// letter, like `C:\x` or `C:/x`, is a UNC path, like `\\server\share\x`,
+/// or is rooted on the current drive, like `\x`.
+pub(crate) fn is_windows_absolute(path: &str) -> bool {
+    match path.as_bytes() {
+        [drive, b':', b'\\' | b'/', ..] => drive.is_ascii_alphabetic(),
+        [b'\\', ..] => true,
+        _ => false,
+    }
+}
+
+/// Replace both `/` and `\` in the relative path `path` with `separator`.
+pub(crate) fn normalize_separators(path: &str, separator: char) -> Cow<'_, str> {
+    if path.contains(|c| (c == '/' || c == '\\') && c != separator) {
+        Cow::Owned(path.replace(['/', '\\'], &separator.to_string()))
+    } else {
+        Cow::Borrowed(path)
+    }
+}
+
+/// Strip the `\\?\` prefix of the verbatim path `path`, which
+/// `canonicalize` returns on Windows. `\\?\UNC\server\share` becomes
+/// `\\server\share`. Returns `None` if `path` is not verbatim.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) fn strip_verbatim(path: &str) -> Option<Cow<'_, str>> {
+    if let Some(rest) = path.strip_prefix(r"\\?\UNC\") {
+        Some(Cow::Owned(format!(r"\\{}", rest)))
+    } else {
+        path.strip_prefix(r"\\?\").map(Cow::Borrowed)
+    }
+}
+
+/// The key of the canonicalized `path` to detect files loaded before.
+/// Paths differing only by case are the same file if `case_insensitive`,
+/// like on Windows.
+pub(crate) fn visited_key(path: &Path, case_insensitive: bool) -> PathBuf {
+    match path.to_str() {
+        Some(s) if case_insensitive => PathBuf::from(s.to_lowercase()),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_windows_absolute() {
+        assert!(is_windows_absolute(r"C:\x\hg.rc"));
+        assert!(is_windows_absolute("c:/x/hg.rc"));
+        assert!(is_windows_absolute(r"\\server\share\hg.rc"));
+        assert!(!is_windows_absolute("C:hg.rc"));
+        assert!(!is_windows_absolute(r"x\hg.rc"));
+        assert!(is_windows_absolute(r"\x\hg.rc"));
+        assert!(!is_windows_absolute("/x/hg.rc"));
+        assert!(!is_windows_absolute("1:/x"));
+        assert!(!is_windows_absolute(""));
+    }
+
+    #[test]
+    fn test_normalize_separators() {
+        assert_eq!(normalize_separators(r"a\b/c", '/'), "a/b/c");
+        assert_eq!(normalize_separators(r"a\b/c", '\\'), r"a\b\c");
+        assert!(matches!(
+            normalize_separators("a/b", '/'),
+            Cow::Borrowed("a/b")
+        ));
+        assert_eq!(
+            normalize_separators(r"..\conf.d\*.rc", '/'),
+            "../conf.d/*.rc"
+        );
+    }
+
+    #[test]
+    fn test_strip_verbatim() {
+        assert_eq!(strip_verbatim(r"\\?\C:\x\.\y").unwrap(), r"C:\x\.\y");
+        assert_eq!(
+            strip_verbatim(r"\\?\UNC\server\share\hg.rc").unwrap(),
+            r"\\server\share\hg.rc"
+        );
+        assert_eq!(strip_verbatim(r"C:\x"), None);
+        assert_eq!(strip_verbatim(r"\\server\share"), None);
+    }
+
+    #[test]
+    fn test_visited_key() {
+        let path = Path::new(r"C:\Users\Me\HG.rc");
+        assert_eq!(visited_key(path, true), Path::new(r"c:\users\me\hg.rc"));
+        assert_eq!(visited_key(path, false), path);
+        assert_eq!(
+            visited_key(Path::new(r"C:\users\me\hg.RC"), true),
+            visited_key(path, true)
+        );
+    }
+}
@@ -0,0 +1,552 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Check the public API of `configset` against `tests/public_api.txt`, so it
+//! does not change by accident.
+//!
+//! The API is read from the source files: public modules, items, fields,
+//! variants, methods and trait implementations (including derives) of public
+//! types, and re-exports. Items behind a `cfg` are listed with it, so the
+//! result does not depend on the enabled features. Items re-exported from
+//! other crates are listed by path only.
+//!
+//! After an intended change, update the snapshot with:
+//!
+//! ```plain,ignore
+//! UPDATE_PUBLIC_API=1 cargo test -p configset --test public_api
+//! ```
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use quote::ToTokens;
+use syn::Attribute;
+use syn::Fields;
+use syn::ImplItem;
+use syn::Item;
+use syn::TraitItem;
+use syn::UseTree;
+use syn::Visibility;
+
+const CRATE: &str = "configset";
+const SNAPSHOT: &str = "tests/public_api.txt";
+const HEADER: &str = "\
+# Public API of configset, checked by tests/public_api.rs.
+# Update with: UPDATE_PUBLIC_API=1 cargo test -p configset --test public_api
+";
+
+/// A module of the crate, parsed from its source file.
+struct Module {
+    /// Path like `configset::config`.
+    path: String,
+    /// `cfg` attributes of the module and its parents.
+    cfg: String,
+    /// Whether the module is reachable from outside the crate.
+    public: bool,
+    items: Vec<Item>,
+}
+
+fn main_file() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("src/lib.rs")
+}
+
+fn parse_file(path: &Path) -> syn::File {
+    let content = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e));
+    syn::parse_file(&content).unwrap_or_else(|e| panic!("cannot parse {}: {}", path.display(), e))
+}
+
+/// Parse the crate into modules, skipping `#[cfg(test)]` code.
+fn collect_modules() -> Vec<Module> {
+    let mut modules = Vec::new();
+    let root = parse_file(&main_file());
+    let dir = main_file().parent().unwrap().to_path_buf();
+    collect_module(
+        CRATE.to_string(),
+        String::new(),
+        true,
+        root.items,
+        &dir,
+        &mut modules,
+    );
+    modules
+}
+
+fn collect_module(
+    path: String,
+    cfg: String,
+    public: bool,
+    items: Vec<Item>,
+    dir: &Path,
+    modules: &mut Vec<Module>,
+) {
+    for item in &items {
+        let item_mod = match item {
+            Item::Mod(item_mod) if !is_test(&item_mod.attrs) => item_mod,
+            _ => continue,
+        };
+        let name = item_mod.ident.to_string();
+        let (sub_items, sub_dir) = match &item_mod.content {
+            Some((_, sub_items)) => (sub_items.clone(), dir.join(&name)),
+            None => {
+                let file = dir.join(format!("{}.rs", name));
+                let file = if file.exists() {
+                    file
+                } else {
+                    dir.join(&name).join("mod.rs")
+                };
+                (parse_file(&file).items, dir.join(&name))
+            }
+        };
+        collect_module(
+            format!("{}::{}", path, name),
+            cfg.clone() + &cfg_prefix(&item_mod.attrs),
+            public && is_pub(&item_mod.vis),
+            sub_items,
+            &sub_dir,
+            modules,
+        );
+    }
+    modules.push(Module {
+        path,
+        cfg,
+        public,
+        items,
+    });
+}
+
+fn is_pub(vis: &Visibility) -> bool {
+    matches!(vis, Visibility::Public(_))
+}
+
+fn is_test(attrs: &[Attribute]) -> bool {
+    attrs
+        .iter()
+        .any(|attr| attr.path.is_ident("cfg") && tokens(&attr.tokens) == "(test)")
+}
+
+/// `cfg` and `non_exhaustive` attributes, which change what users can do.
+fn cfg_prefix(attrs: &[Attribute]) -> String {
+    attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("cfg"))
+        .map(|attr| format!("#[cfg{}] ", tokens(&attr.tokens)))
+        .collect()
+}
+
+fn attr_prefix(attrs: &[Attribute]) -> String {
+    let non_exhaustive = attrs
+        .iter()
+        .any(|attr| attr.path.is_ident("non_exhaustive"));
+    let prefix = cfg_prefix(attrs);
+    if non_exhaustive {
+        prefix + "#[non_exhaustive] "
+    } else {
+        prefix
+    }
+}
+
+/// Traits of `#[derive(..)]` attributes.
+fn derives(attrs: &[Attribute]) -> Vec<String> {
+    attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("derive"))
+        .flat_map(|attr| {
+            let list = tokens(&attr.tokens);
+            let list = list.trim_start_matches('(').trim_end_matches(')');
+            list.split(',')
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Render tokens with less whitespace than `TokenStream::to_string`, closer
+/// to how rustfmt writes them.
+fn tokens(tokens: &impl ToTokens) -> String {
+    let mut s = tokens.to_token_stream().to_string();
+    for (from, to) in [
+        (" :: ", "::"),
+        (":: ", "::"),
+        (" : ", ": "),
+        (" ,", ","),
+        ("( ", "("),
+        (" )", ")"),
+        (",)", ")"),
+        ("[ ", "["),
+        (" ]", "]"),
+        (" < ", "<"),
+        ("< ", "<"),
+        (" >", ">"),
+        ("& ", "&"),
+        (" ;", ";"),
+        ("' ", "'"),
+        (" !", "!"),
+    ] {
+        s = s.replace(from, to);
+    }
+    // Remove the space between a name and `(`, like in `fn name (` or
+    // `Fn (`, but not in `-> (` or `, (`.
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        let before_paren = c == ' ' && chars.peek() == Some(&'(');
+        if !(before_paren && out.ends_with(|c: char| c.is_alphanumeric() || c == '_' || c == '>')) {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Join `cfg` prefixes, dropping repeated attributes.
+fn join_cfgs(prefixes: &[&str]) -> String {
+    let mut seen = Vec::new();
+    for prefix in prefixes {
+        for attr in prefix.split_inclusive("] ") {
+            if !seen.contains(&attr) {
+                seen.push(attr);
+            }
+        }
+    }
+    seen.concat()
+}
+
+/// `fn name(..)` of `sig`, with `name` replaced by `path::name`.
+fn signature(path: &str, sig: &syn::Signature) -> String {
+    let sig_tokens = tokens(sig);
+    let name = format!("fn {}", sig.ident);
+    sig_tokens.replacen(&name, &format!("fn {}::{}", path, sig.ident), 1)
+}
+
+fn fields(fields: &Fields) -> Vec<String> {
+    fields
+        .iter()
+        .enumerate()
+        .filter(|(_, field)| is_pub(&field.vis))
+        .map(|(index, field)| {
+            let name = match &field.ident {
+                Some(ident) => ident.to_string(),
+                None => index.to_string(),
+            };
+            format!(
+                "{}{}: {}",
+                cfg_prefix(&field.attrs),
+                name,
+                tokens(&field.ty)
+            )
+        })
+        .collect()
+}
+
+fn use_paths(tree: &UseTree, prefix: &str, out: &mut Vec<String>) {
+    let join = |name: String| {
+        if prefix.is_empty() {
+            name
+        } else {
+            format!("{}::{}", prefix, name)
+        }
+    };
+    match tree {
+        UseTree::Path(path) => use_paths(&path.tree, &join(path.ident.to_string()), out),
+        UseTree::Name(name) => out.push(join(name.ident.to_string())),
+        UseTree::Rename(rename) => out.push(format!(
+            "{} as {}",
+            join(rename.ident.to_string()),
+            rename.rename
+        )),
+        UseTree::Glob(_) => out.push(join("*".to_string())),
+        UseTree::Group(group) => {
+            for tree in &group.items {
+                use_paths(tree, prefix, out);
+            }
+        }
+    }
+}
+
+/// Name of the type of an `impl` block, without generics.
+fn self_type_name(ty: &syn::Type) -> Option<String> {
+    match ty {
+        syn::Type::Path(path) => path.path.segments.last().map(|s| s.ident.to_string()),
+        _ => None,
+    }
+}
+
+/// The public API, one line per item, sorted.
+fn public_api() -> BTreeSet<String> {
+    let modules = collect_modules();
+    let mut api = BTreeSet::new();
+
+    // Public types by name, to find out which impl blocks are public API.
+    // Maps to the path and `cfg` of the type.
+    let mut types: BTreeMap<String, (String, String)> = BTreeMap::new();
+    for module in modules.iter().filter(|m| m.public) {
+        for item in &module.items {
+            let (ident, vis, attrs) = match item {
+                Item::Struct(s) => (&s.ident, &s.vis, &s.attrs),
+                Item::Enum(e) => (&e.ident, &e.vis, &e.attrs),
+                _ => continue,
+            };
+            if is_pub(vis) && !is_test(attrs) {
+                types.insert(
+                    ident.to_string(),
+                    (
+                        format!("{}::{}", module.path, ident),
+                        join_cfgs(&[&module.cfg, &cfg_prefix(attrs)]),
+                    ),
+                );
+            }
+        }
+    }
+
+    for module in &modules {
+        if module.public && module.path != CRATE {
+            api.insert(format!("{}pub mod {}", module.cfg, module.path));
+        }
+        for item in &module.items {
+            if module.public {
+                public_item(module, item, &mut api);
+            }
+            let item_impl = match item {
+                Item::Impl(item_impl) if !is_test(&item_impl.attrs) => item_impl,
+                _ => continue,
+            };
+            let (path, type_cfg) =
+                match self_type_name(&item_impl.self_ty).and_then(|name| types.get(&name)) {
+                    Some(found) => found,
+                    None => continue,
+                };
+            let cfg = join_cfgs(&[type_cfg, &module.cfg, &cfg_prefix(&item_impl.attrs)]);
+            if let Some((_, trait_path, _)) = &item_impl.trait_ {
+                api.insert(format!(
+                    "{}impl{} {} for {}",
+                    cfg,
+                    tokens(&item_impl.generics),
+                    tokens(trait_path),
+                    path
+                ));
+                continue;
+            }
+            for impl_item in &item_impl.items {
+                match impl_item {
+                    ImplItem::Method(method) if is_pub(&method.vis) && !is_test(&method.attrs) => {
+                        api.insert(format!(
+                            "{}pub {}",
+                            join_cfgs(&[&cfg, &cfg_prefix(&method.attrs)]),
+                            signature(path, &method.sig)
+                        ));
+                    }
+                    ImplItem::Const(c) if is_pub(&c.vis) => {
+                        api.insert(format!(
+                            "{}pub const {}::{}: {}",
+                            cfg,
+                            path,
+                            c.ident,
+                            tokens(&c.ty)
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    api
+}
+
+fn public_item(module: &Module, item: &Item, api: &mut BTreeSet<String>) {
+    let path = |ident: &syn::Ident| format!("{}::{}", module.path, ident);
+    let cfg = &module.cfg;
+    match item {
+        Item::Use(u) if is_pub(&u.vis) => {
+            let mut paths = Vec::new();
+            use_paths(&u.tree, "", &mut paths);
+            for used in paths {
+                // `a::b as c` is `c`, and `a::b` is `b`.
+                let name = used.rsplit([' ', ':']).next().unwrap_or_default();
+                api.insert(format!(
+                    "{}pub use {}::{} = {}",
+                    join_cfgs(&[cfg, &cfg_prefix(&u.attrs)]),
+                    module.path,
+                    name,
+                    used
+                ));
+            }
+        }
+        Item::Fn(f) if is_pub(&f.vis) && !is_test(&f.attrs) => {
+            api.insert(format!(
+                "{}{}pub {}",
+                cfg,
+                attr_prefix(&f.attrs),
+                signature(&module.path, &f.sig)
+            ));
+        }
+        Item::Struct(s) if is_pub(&s.vis) && !is_test(&s.attrs) => {
+            let prefix = format!("{}{}", cfg, cfg_prefix(&s.attrs));
+            api.insert(format!(
+                "{}{}pub struct {}{}",
+                cfg,
+                attr_prefix(&s.attrs),
+                path(&s.ident),
+                tokens(&s.generics)
+            ));
+            for field in fields(&s.fields) {
+                api.insert(format!("{}pub {}::{}", prefix, path(&s.ident), field));
+            }
+            for derive in derives(&s.attrs) {
+                api.insert(format!("{}impl {} for {}", prefix, derive, path(&s.ident)));
+            }
+        }
+        Item::Enum(e) if is_pub(&e.vis) && !is_test(&e.attrs) => {
+            let prefix = format!("{}{}", cfg, cfg_prefix(&e.attrs));
+            api.insert(format!(
+                "{}{}pub enum {}{}",
+                cfg,
+                attr_prefix(&e.attrs),
+                path(&e.ident),
+                tokens(&e.generics)
+            ));
+            for variant in &e.variants {
+                let shape = match &variant.fields {
+                    Fields::Named(_) => format!(" {{ {} }}", fields_of_variant(&variant.fields)),
+                    Fields::Unnamed(_) => format!("({})", fields_of_variant(&variant.fields)),
+                    Fields::Unit => String::new(),
+                };
+                api.insert(format!(
+                    "{}{}pub {}::{}{}",
+                    prefix,
+                    attr_prefix(&variant.attrs),
+                    path(&e.ident),
+                    variant.ident,
+                    shape
+                ));
+            }
+            for derive in derives(&e.attrs) {
+                api.insert(format!("{}impl {} for {}", prefix, derive, path(&e.ident)));
+            }
+        }
+        Item::Trait(t) if is_pub(&t.vis) && !is_test(&t.attrs) => {
+            let prefix = format!("{}{}", cfg, cfg_prefix(&t.attrs));
+            api.insert(format!("{}pub trait {}", prefix, path(&t.ident)));
+            for trait_item in &t.items {
+                match trait_item {
+                    TraitItem::Method(m) => {
+                        api.insert(format!("{}{}", prefix, signature(&path(&t.ident), &m.sig)));
+                    }
+                    TraitItem::Type(ty) => {
+                        api.insert(format!("{}{}::type {}", prefix, path(&t.ident), ty.ident));
+                    }
+                    TraitItem::Const(c) => {
+                        api.insert(format!(
+                            "{}{}::const {}: {}",
+                            prefix,
+                            path(&t.ident),
+                            c.ident,
+                            tokens(&c.ty)
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Item::Type(t) if is_pub(&t.vis) => {
+            api.insert(format!(
+                "{}{}pub type {}{} = {}",
+                cfg,
+                cfg_prefix(&t.attrs),
+                path(&t.ident),
+                tokens(&t.generics),
+                tokens(&t.ty)
+            ));
+        }
+        Item::Const(c) if is_pub(&c.vis) => {
+            api.insert(format!(
+                "{}{}pub const {}: {}",
+                cfg,
+                cfg_prefix(&c.attrs),
+                path(&c.ident),
+                tokens(&c.ty)
+            ));
+        }
+        Item::Static(s) if is_pub(&s.vis) => {
+            api.insert(format!(
+                "{}{}pub static {}: {}",
+                cfg,
+                cfg_prefix(&s.attrs),
+                path(&s.ident),
+                tokens(&s.ty)
+            ));
+        }
+        _ => {}
+    }
+}
+
+/// Fields of an enum variant, which are public without `pub`.
+fn fields_of_variant(fields: &Fields) -> String {
+    fields
+        .iter()
+        .map(|field| match &field.ident {
+            Some(ident) => format!("{}: {}", ident, tokens(&field.ty)),
+            None => tokens(&field.ty),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn render(api: &BTreeSet<String>) -> String {
+    let mut out = HEADER.to_string();
+    for line in api {
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+#[test]
+fn test_public_api() {
+    let actual = render(&public_api());
+    let snapshot = Path::new(env!("CARGO_MANIFEST_DIR")).join(SNAPSHOT);
+    if std::env::var_os("UPDATE_PUBLIC_API").is_some() {
+        fs::write(&snapshot, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&snapshot).unwrap_or_default();
+    if actual == expected {
+        return;
+    }
+
+    let actual_lines: BTreeSet<&str> = actual.lines().collect();
+    let expected_lines: BTreeSet<&str> = expected.lines().collect();
+    let mut diff = String::new();
+    for line in expected_lines.difference(&actual_lines) {
+        diff.push_str(&format!("- {}\n", line));
+    }
+    for line in actual_lines.difference(&expected_lines) {
+        diff.push_str(&format!("+ {}\n", line));
+    }
+    panic!(
+        "The public API of configset changed:\n\n{}\n\
+         If this is intended, update {} with:\n\n  \
+         UPDATE_PUBLIC_API=1 cargo test -p configset --test public_api\n\n\
+         Removing or changing items breaks users of the crate.",
+        diff, SNAPSHOT
+    );
+}
+
+#[test]
+fn test_internals_are_private() {
+    let api = public_api();
+    for internal in ["intern", "builtin", "Secrets", "include_dir_entries"] {
+        assert!(
+            !api.iter().any(|line| line.contains(internal)),
+            "{} should not be public",
+            internal
+        );
+    }
+}
@@ -18,6 +18,10 @@ use self::freebsd::fstype as fstype_imp;
 use self::linux::fstype as fstype_imp;
 #[cfg(target_os = "macos")]
 use self::macos::fstype as fstype_imp;
+#[cfg(unix)]
+use self::unix::available_space as available_space_imp;
+#[cfg(windows)]
+use self::windows::available_space as available_space_imp;
 #[cfg(windows)]
 use self::windows::fstype as fstype_imp;
 
@@ -69,8 +73,10 @@ mod windows {
     use winapi::shared::minwindef::DWORD;
     use winapi::shared::minwindef::MAX_PATH;
     use winapi::shared::minwindef::ULONG;
+    use winapi::shared::ntdef::ULARGE_INTEGER;
     use winapi::shared::winerror::ERROR_NOT_A_REPARSE_POINT;
     use winapi::um::fileapi::CreateFileW;
+    use winapi::um::fileapi::GetDiskFreeSpaceExW;
     use winapi::um::fileapi::GetVolumeInformationByHandleW;
     use winapi::um::fileapi::OPEN_EXISTING;
     use winapi::um::handleapi::CloseHandle;
@@ -233,6 +239,18 @@ mod windows {
 
         Ok(fstype.into())
     }
+
+    pub fn available_space(path: &Path) -> Result<u64> {
+        let mut root: Vec<u16> = path.as_os_str().encode_wide().collect();
+        root.push(0);
+        let mut available: ULARGE_INTEGER = unsafe { std::mem::zeroed() };
+        let success =
+            unsafe { GetDiskFreeSpaceExW(root.as_ptr(), &mut available, null_mut(), null_mut()) };
+        if success == 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(unsafe { *available.QuadPart() })
+    }
 }
 
 #[cfg(unix)]
@@ -252,6 +270,16 @@ mod unix {
             Err(io::Error::last_os_error().into())
         }
     }
+
+    pub fn available_space(path: &Path) -> Result<u64> {
+        let cstr = CString::new(path.as_os_str().as_bytes())?;
+        let mut fs_stat: libc::statvfs = unsafe { zeroed() };
+        if unsafe { libc::statvfs(cstr.as_ptr(), &mut fs_stat) } == 0 {
+            Ok(fs_stat.f_bavail as u64 * fs_stat.f_frsize as u64)
+        } else {
+            Err(io::Error::last_os_error().into())
+        }
+    }
 }
 
 #[cfg(target_os = "linux")]
@@ -419,3 +447,11 @@ pub fn fstype(path: impl AsRef<Path>) -> Result<FsType> {
 
     fstype_imp(path).with_context(|| format!("Cannot determine filesystem type for {:?}", path))
 }
+
+/// Get the number of bytes available to the current user on the filesystem
+/// containing `path`.
+pub fn available_space(path: impl AsRef<Path>) -> Result<u64> {
+    let path = path.as_ref();
+    available_space_imp(path)
+        .with_context(|| format!("Cannot determine available space for {:?}", path))
+}
@@ -90,6 +90,10 @@ impl ReadFileContents for ArcFileStore {
             })
             .boxed()
     }
+
+    fn read_file_sizes(&self, keys: Vec<Key>) -> Option<BoxStream<Result<(u64, Key)>>> {
+        Some(stream_sizes_from_scmstore(self.0.clone(), keys).boxed())
+    }
 }
 
 impl RefreshableReadFileContents for ArcFileStore {
@@ -188,3 +192,40 @@ fn stream_data_from_scmstore(
         })
         .flatten()
 }
+
+fn stream_sizes_from_scmstore(
+    store: Arc<FileStore>,
+    keys: Vec<Key>,
+) -> impl Stream<Item = Result<(u64, Key)>> {
+    stream::iter(keys.into_iter())
+        .chunks(PREFETCH_CHUNK_SIZE)
+        .map(move |chunk| {
+            let store = store.clone();
+            Handle::current().spawn_blocking(move || {
+                let mut data = vec![];
+                for result in store.fetch(
+                    chunk.iter().cloned(),
+                    FileAttributes::AUX,
+                    FetchMode::AllowRemote,
+                ) {
+                    let result = match result {
+                        Err(err) => Err(err.into()),
+                        Ok((key, file)) => file.aux_data().map(|aux| (aux.total_size, key)),
+                    };
+                    let is_err = result.is_err();
+                    data.push(result);
+                    if is_err {
+                        break;
+                    }
+                }
+                stream::iter(data.into_iter())
+            })
+        })
+        .buffer_unordered(FETCH_PARALLELISM)
+        .map(|r| {
+            r.unwrap_or_else(|_| {
+                stream::iter(vec![Err(anyhow!("background fetch join error"))].into_iter())
+            })
+        })
+        .flatten()
+}
@@ -54,6 +54,18 @@ pub trait ReadFileContents {
         &self,
         keys: Vec<Key>,
     ) -> BoxStream<Result<(Key, Option<Key>), Self::Error>>;
+
+    /// Read the sizes of the contents of specified files, from metadata
+    /// rather than the contents themselves.
+    ///
+    /// Returns `None` if the store can't tell sizes without reading the
+    /// contents. Callers can then sum up `read_file_contents` instead.
+    fn read_file_sizes(
+        &self,
+        _keys: Vec<Key>,
+    ) -> Option<BoxStream<Result<(u64, Key), Self::Error>>> {
+        None
+    }
 }
 
 pub trait RefreshableReadFileContents: ReadFileContents {
@@ -5,15 +5,24 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::Duration;
 
 use anyhow::Result;
 use crossbeam::channel;
 use crossbeam::channel::Receiver;
 use crossbeam::channel::Sender;
 use minibytes::Bytes;
+use once_cell::sync::Lazy;
 use tokio::sync::oneshot;
+use types::RepoPath;
 use types::RepoPathBuf;
 
 use crate::UpdateFlag;
@@ -24,6 +33,58 @@ pub struct AsyncVfsWriter {
     handles: Vec<JoinHandle<()>>,
 }
 
+/// Longest delay between two attempts of the same operation.
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How `AsyncVfsWriter` retries failed operations.
+///
+/// Only errors that are likely to go away on their own, such as a file being
+/// briefly locked by another process, are retried. Each operation of a batch
+/// is retried individually.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of times an operation is retried before giving up.
+    pub retries: u32,
+    /// Delay before the first retry. It doubles for each further retry, up
+    /// to one second.
+    pub backoff: Duration,
+    /// On unix, give the owner write permission on the parent directory of
+    /// files that can't be removed without it, then try again. Read-only
+    /// files are always made writable before being removed on Windows.
+    pub fix_parent_dir_permissions: bool,
+}
+
+/// Counters of operations retried by `AsyncVfsWriter`.
+#[derive(Debug, Default)]
+pub struct RetryStats {
+    retried: AtomicUsize,
+    failed: AtomicUsize,
+    permission_fixups: AtomicUsize,
+}
+
+impl RetryStats {
+    /// Operations that succeeded after being retried.
+    pub fn retried(&self) -> usize {
+        self.retried.load(Ordering::Relaxed)
+    }
+
+    /// Operations that were retried but still failed.
+    pub fn failed(&self) -> usize {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Files whose permissions, or their parent directory's, were changed so
+    /// they could be removed.
+    pub fn permission_fixups(&self) -> usize {
+        self.permission_fixups.load(Ordering::Relaxed)
+    }
+}
+
+struct Retrier {
+    policy: RetryPolicy,
+    stats: Arc<RetryStats>,
+}
+
 struct WorkItem {
     res: oneshot::Sender<Result<usize>>,
     action: Action,
@@ -42,13 +103,30 @@ enum Action {
 /// Drop handler for `AsyncVfsWriter` blocks until underlyning threads terminate.
 impl AsyncVfsWriter {
     pub fn spawn_new(vfs: VFS, workers: usize) -> Self {
+        Self::spawn_with_retry(vfs, workers, RetryPolicy::default(), Default::default())
+    }
+
+    /// Same as `spawn_new`, but retries transient failures according to
+    /// `policy`, counting them in `stats`.
+    pub fn spawn_with_retry(
+        vfs: VFS,
+        workers: usize,
+        policy: RetryPolicy,
+        stats: Arc<RetryStats>,
+    ) -> Self {
         let (sender, receiver) = channel::unbounded();
         let sender = Some(sender);
         let mut handles = Vec::with_capacity(workers);
         for _ in 0..workers {
             let receiver = receiver.clone();
             let vfs = vfs.clone();
-            handles.push(thread::spawn(move || async_vfs_worker(vfs, receiver)));
+            let retrier = Retrier {
+                policy,
+                stats: stats.clone(),
+            };
+            handles.push(thread::spawn(move || {
+                async_vfs_worker(vfs, receiver, retrier)
+            }));
         }
         Self { sender, handles }
     }
@@ -97,7 +175,7 @@ impl AsyncVfsWriter {
     }
 }
 
-fn async_vfs_worker(vfs: VFS, receiver: Receiver<WorkItem>) {
+fn async_vfs_worker(vfs: VFS, receiver: Receiver<WorkItem>, retrier: Retrier) {
     for item in receiver {
         // Quickcheck - if caller future dropped while item was in queue, no reason to execute
         // One use case for this - if calling stream in checkout encounters an error, the stream is dropped
@@ -105,26 +183,124 @@ fn async_vfs_worker(vfs: VFS, receiver: Receiver<WorkItem>) {
         if item.res.is_closed() {
             continue;
         }
-        let result = execute_action(&vfs, item.action);
+        let result = execute_action(&vfs, item.action, &retrier);
         item.res.send(result).ok();
     }
 }
 
-fn execute_action(vfs: &VFS, action: Action) -> Result<usize> {
+fn execute_action(vfs: &VFS, action: Action, retrier: &Retrier) -> Result<usize> {
     match action {
-        Action::Write(path, data, flag) => vfs.write(&path, &data, flag),
-        Action::Remove(path) => vfs.remove(&path).map(|_| 0),
-        Action::SetExecutable(path, flag) => vfs.set_executable(&path, flag).map(|_| 0),
+        Action::Write(path, data, flag) => retrier.run(|| {
+            fail::fail_point!("async-vfs-write-transient", |arg: Option<String>| {
+                if inject_transient_failure(&path, arg) {
+                    Err(io::Error::from(io::ErrorKind::Interrupted).into())
+                } else {
+                    vfs.write(&path, &data, flag)
+                }
+            });
+            vfs.write(&path, &data, flag)
+        }),
+        Action::Remove(path) => retrier.run(|| retrier.remove(vfs, &path)).map(|_| 0),
+        Action::SetExecutable(path, flag) => {
+            retrier.run(|| vfs.set_executable(&path, flag)).map(|_| 0)
+        }
         Action::Batch(batch) => {
             let mut total = 0;
             for action in batch {
-                total += execute_action(vfs, action)?;
+                total += execute_action(vfs, action, retrier)?;
             }
             Ok(total)
         }
     }
 }
 
+/// Failpoint helper. `arg` is `<path>:<count>`, meaning the first `count`
+/// writes to `path` fail. Failures are counted per path, so writes to other
+/// paths don't use them up.
+fn inject_transient_failure(path: &RepoPath, arg: Option<String>) -> bool {
+    static FAILED: Lazy<Mutex<HashMap<RepoPathBuf, usize>>> = Lazy::new(Default::default);
+    let arg = match arg {
+        Some(arg) => arg,
+        None => return false,
+    };
+    let (target, count) = match arg.rsplit_once(':') {
+        Some((target, count)) => (target, count.parse().unwrap_or(0)),
+        None => (arg.as_str(), usize::MAX),
+    };
+    if path.as_str() != target {
+        return false;
+    }
+    let mut failed = FAILED.lock().unwrap();
+    let failed = failed.entry(path.to_owned()).or_default();
+    *failed += 1;
+    *failed <= count
+}
+
+impl Retrier {
+    fn run<T>(&self, mut op: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut attempt = 0;
+        let mut backoff = self.policy.backoff;
+        loop {
+            match op() {
+                Ok(value) => {
+                    if attempt > 0 {
+                        self.stats.retried.fetch_add(1, Ordering::Relaxed);
+                    }
+                    return Ok(value);
+                }
+                Err(err) if attempt < self.policy.retries && is_transient(&err) => {
+                    tracing::debug!("retrying after transient error: {:?}", err);
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+                    attempt += 1;
+                }
+                Err(err) => {
+                    if attempt > 0 {
+                        self.stats.failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Remove `path`, fixing the permissions preventing it.
+    fn remove(&self, vfs: &VFS, path: &RepoPath) -> Result<()> {
+        if vfs.remove_fixing_permissions(path, self.policy.fix_parent_dir_permissions)? {
+            self.stats.permission_fixups.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+/// Whether `err` is likely caused by a temporary condition, so the failed
+/// operation may succeed if tried again.
+fn is_transient(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|e| e.downcast_ref::<io::Error>())
+        .any(|e| {
+            if matches!(
+                e.kind(),
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+            ) {
+                return true;
+            }
+            #[cfg(unix)]
+            if matches!(e.raw_os_error(), Some(libc::EBUSY | libc::ETXTBSY)) {
+                return true;
+            }
+            // Files opened by other processes (virus scanners, indexers) can
+            // not be written or removed until they are closed.
+            #[cfg(windows)]
+            if e.kind() == io::ErrorKind::PermissionDenied
+                || matches!(e.raw_os_error(), Some(32 | 33))
+            {
+                return true;
+            }
+            false
+        })
+}
+
 impl Drop for AsyncVfsWriter {
     // Good citizen behavior - waiting until threads stop when AsyncVfs is dropped
     // This also will propagate panic from a worker thread into caller
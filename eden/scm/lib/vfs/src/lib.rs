@@ -12,7 +12,10 @@ mod vfs;
 pub use util::lock::PathLock;
 
 pub use crate::async_vfs::AsyncVfsWriter;
+pub use crate::async_vfs::RetryPolicy;
+pub use crate::async_vfs::RetryStats;
 pub use crate::pathauditor::AuditError;
 pub use crate::pathauditor::PathAuditor;
+pub use crate::vfs::extended_length_path;
 pub use crate::vfs::UpdateFlag;
 pub use crate::vfs::VFS;
@@ -16,6 +16,8 @@ use once_cell::sync::Lazy;
 use types::RepoPath;
 use types::RepoPathBuf;
 
+use crate::vfs::join_repo_path;
+
 /// Audit repositories path to make sure that it is safe to write/remove through them.
 ///
 /// This uses caching internally to avoid the heavy cost of querying the OS for each directory in
@@ -62,7 +64,7 @@ impl PathAuditor {
     /// outside of the repo is not supported.
     /// XXX: more checks
     fn audit_fs(&self, path: &RepoPath) -> Result<(), AuditError> {
-        let full_path = self.root.join(path.as_str());
+        let full_path = join_repo_path(&self.root, path);
 
         // XXX: Maybe filter by specific errors?
         if let Ok(metadata) = symlink_metadata(&full_path) {
@@ -107,9 +109,7 @@ impl PathAuditor {
             }
         }
 
-        let mut filepath = self.root.to_owned();
-        filepath.push(path.as_str());
-        Ok(filepath)
+        Ok(join_repo_path(&self.root, path))
     }
 }
 
@@ -5,6 +5,7 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::HashSet;
 use std::fs;
 use std::fs::create_dir_all;
 use std::fs::remove_dir;
@@ -87,7 +88,26 @@ impl VFS {
     }
 
     pub fn join(&self, path: &RepoPath) -> PathBuf {
-        self.inner.root.join(path.as_str())
+        join_repo_path(&self.inner.root, path)
+    }
+
+    /// Length of the on-disk path of `path`, in the unit of `max_path_len`:
+    /// bytes on Unix, UTF-16 code units on Windows.
+    pub fn path_len(&self, path: &RepoPath) -> usize {
+        os_path_len(&self.join(path))
+    }
+
+    /// The longest path the OS accepts for files of this VFS, not counting
+    /// the terminating NUL.
+    ///
+    /// On Windows, this is `MAX_PATH` unless the root is an extended-length
+    /// path, see [`extended_length_path`].
+    pub fn max_path_len(&self) -> usize {
+        if cfg!(windows) && !is_extended_length_path(&self.inner.root) {
+            WINDOWS_MAX_PATH - 1
+        } else {
+            os_max_path_len()
+        }
     }
 
     pub fn metadata(&self, path: &RepoPath) -> Result<Metadata> {
@@ -300,9 +320,28 @@ impl VFS {
     ///
     /// The parent directories of this file will be removed recursively if they are empty.
     pub fn remove(&self, path: &RepoPath) -> Result<()> {
-        let mut filepath = self.inner.auditor.audit(path)?;
-        self.remove_keep_path(&filepath)?;
+        let filepath = self.inner.auditor.audit(path)?;
+        self.remove_keep_path(&filepath, false)?;
+        self.remove_empty_parents(filepath);
+        Ok(())
+    }
 
+    /// Same as `remove`, but fix the permissions preventing the removal.
+    ///
+    /// On Windows, the read-only attribute of the file is cleared. On unix,
+    /// if `fix_parent_dir` is set and removing the file is denied, the owner
+    /// is given write permission on the parent directory and the removal is
+    /// tried again once.
+    ///
+    /// Return whether permissions were changed.
+    pub fn remove_fixing_permissions(&self, path: &RepoPath, fix_parent_dir: bool) -> Result<bool> {
+        let filepath = self.inner.auditor.audit(path)?;
+        let fixed = self.remove_keep_path(&filepath, cfg!(windows) || fix_parent_dir)?;
+        self.remove_empty_parents(filepath);
+        Ok(fixed)
+    }
+
+    fn remove_empty_parents(&self, mut filepath: PathBuf) {
         // Mercurial doesn't track empty directories, remove them
         // recursively.
         loop {
@@ -314,7 +353,32 @@ impl VFS {
                 break;
             }
         }
-        Ok(())
+    }
+
+    /// Remove the parent directories of `paths` that are empty, deepest
+    /// first, so a directory emptied of its subdirectories is removed too.
+    /// The root is never removed, nor are directories that are not empty,
+    /// like those still containing untracked or ignored files.
+    ///
+    /// `remove` already removes the directories it leaves empty, but
+    /// removals running concurrently in the same directory can each see the
+    /// other's file, and keep it. Call this once they are done, with the
+    /// removed paths; each directory is tried once.
+    pub fn remove_empty_parents_of<'a>(&self, paths: impl IntoIterator<Item = &'a RepoPath>) {
+        let mut dirs: Vec<&RepoPath> = paths
+            .into_iter()
+            .flat_map(|path| path.parents().skip(1))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        // Children before their parents.
+        dirs.sort_unstable_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+        for dir in dirs {
+            if let Ok(dirpath) = self.inner.auditor.audit(dir) {
+                // Fails for directories that are not empty, or were removed.
+                let _ = remove_dir(dirpath);
+            }
+        }
     }
 
     // Reads file content
@@ -338,12 +402,51 @@ impl VFS {
     }
 
     /// Removes file, but unlike Self::remove, does not delete empty directories.
-    fn remove_keep_path(&self, filepath: &PathBuf) -> Result<()> {
+    ///
+    /// If `fix_permissions` is set, permissions preventing the removal are
+    /// fixed as described in `remove_fixing_permissions`. Return whether they
+    /// were.
+    fn remove_keep_path(&self, filepath: &PathBuf, fix_permissions: bool) -> Result<bool> {
+        let mut fixed = false;
         if let Ok(metadata) = symlink_metadata(&filepath) {
             let file_type = metadata.file_type();
             if file_type.is_file() || file_type.is_symlink() {
-                let result = remove_file(&filepath)
-                    .with_context(|| format!("Can't remove file {:?}", filepath));
+                // `remove_file` renames the file before removing it on
+                // Windows, so a failed removal leaves it under another name.
+                // Clear the read-only attribute beforehand instead.
+                #[cfg(windows)]
+                if fix_permissions && metadata.permissions().readonly() {
+                    let mut permissions = metadata.permissions();
+                    #[allow(clippy::permissions_set_readonly_false)]
+                    permissions.set_readonly(false);
+                    fs::set_permissions(filepath, permissions)
+                        .with_context(|| format!("Can't make file {:?} writable", filepath))?;
+                    fixed = true;
+                }
+
+                // Symlinks to directories are directories on Windows, and
+                // `remove_file` fails on them.
+                #[cfg(windows)]
+                let remove = |path: &PathBuf| {
+                    if std::os::windows::fs::FileTypeExt::is_symlink_dir(&file_type) {
+                        remove_dir(path)
+                    } else {
+                        remove_file(path)
+                    }
+                };
+                #[cfg(not(windows))]
+                let remove = |path: &PathBuf| remove_file(path);
+
+                let mut result = remove(filepath);
+                #[cfg(unix)]
+                if fix_permissions
+                    && matches!(&result, Err(e) if e.kind() == ErrorKind::PermissionDenied)
+                    && make_parent_writable(filepath)?
+                {
+                    fixed = true;
+                    result = remove(filepath);
+                }
+                let result = result.with_context(|| format!("Can't remove file {:?}", filepath));
                 if let Err(e) = result {
                     if let Some(io_error) = e.downcast_ref::<io::Error>() {
                         ensure!(io_error.kind() == ErrorKind::NotFound, e);
@@ -354,7 +457,7 @@ impl VFS {
             }
         }
 
-        Ok(())
+        Ok(fixed)
     }
 
     pub fn supports_symlinks(&self) -> bool {
@@ -366,6 +469,28 @@ impl VFS {
     }
 }
 
+/// Give the owner write permission on the parent directory of `filepath`,
+/// so the file can be removed.
+///
+/// Return `false` if the owner could already write to it.
+#[cfg(unix)]
+fn make_parent_writable(filepath: &Path) -> Result<bool> {
+    let dir = match filepath.parent() {
+        Some(dir) => dir,
+        None => return Ok(false),
+    };
+    let mode = symlink_metadata(dir)
+        .with_context(|| format!("Can't read permissions of {:?}", dir))?
+        .permissions()
+        .mode();
+    if mode & 0o200 != 0 {
+        return Ok(false);
+    }
+    set_permissions(dir, Permissions::from_mode((mode | 0o200) & 0o7777))
+        .with_context(|| format!("Can't make directory {:?} writable", dir))?;
+    Ok(true)
+}
+
 #[cfg(unix)]
 #[cfg(test)]
 mod unix_tests {
@@ -434,6 +559,78 @@ mod unix_tests {
     }
 }
 
+/// Limit of Windows APIs for paths without the extended-length prefix.
+const WINDOWS_MAX_PATH: usize = 260;
+
+#[cfg(windows)]
+const EXTENDED_LENGTH_PREFIX: &str = r"\\?\";
+
+/// Return `path` with the extended-length `\\?\` prefix on Windows, so a VFS
+/// rooted at it can write files whose path is longer than `MAX_PATH`.
+///
+/// Relative paths, and paths on other platforms, are returned as is.
+#[cfg(windows)]
+pub fn extended_length_path(path: &Path) -> PathBuf {
+    if is_extended_length_path(path) || !path.is_absolute() {
+        return path.to_path_buf();
+    }
+    let path = path.as_os_str().to_string_lossy().replace('/', "\\");
+    match path.strip_prefix(r"\\") {
+        Some(unc) => PathBuf::from(format!(r"{}UNC\{}", EXTENDED_LENGTH_PREFIX, unc)),
+        None => PathBuf::from(format!("{}{}", EXTENDED_LENGTH_PREFIX, path)),
+    }
+}
+
+#[cfg(not(windows))]
+pub fn extended_length_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// Join `path` to `root`. Windows doesn't convert "/" to separators in
+/// extended-length paths, so they are converted here.
+pub(crate) fn join_repo_path(root: &Path, path: &RepoPath) -> PathBuf {
+    if is_extended_length_path(root) {
+        root.join(path.as_str().replace('/', "\\"))
+    } else {
+        root.join(path.as_str())
+    }
+}
+
+#[cfg(windows)]
+fn is_extended_length_path(path: &Path) -> bool {
+    path.as_os_str()
+        .to_string_lossy()
+        .starts_with(EXTENDED_LENGTH_PREFIX)
+}
+
+#[cfg(not(windows))]
+fn is_extended_length_path(_path: &Path) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn os_path_len(path: &Path) -> usize {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().len()
+}
+
+#[cfg(windows)]
+fn os_path_len(path: &Path) -> usize {
+    use std::os::windows::ffi::OsStrExt;
+    path.as_os_str().encode_wide().count()
+}
+
+#[cfg(unix)]
+fn os_max_path_len() -> usize {
+    libc::PATH_MAX as usize - 1
+}
+
+#[cfg(windows)]
+fn os_max_path_len() -> usize {
+    // Limit of extended-length paths.
+    32767 - 1
+}
+
 fn supports_symlinks(path: &Path) -> Result<bool> {
     if std::env::var("SL_DEBUG_DISABLE_SYMLINKS").is_ok() {
         return Ok(false);
@@ -523,4 +720,62 @@ mod tests {
         #[cfg(target_os = "macos")]
         assert!(!case_sensitive);
     }
+
+    #[test]
+    fn test_remove_empty_parents_of() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let vfs = VFS::new(root.to_path_buf()).unwrap();
+        for dir in ["a/b/c", "a/d", "keep/e", "other"] {
+            create_dir_all(root.join(dir)).unwrap();
+        }
+        File::create(root.join("keep/untracked")).unwrap();
+
+        let paths = ["a/b/c/x", "a/b/c/y", "a/d/z", "keep/e/f/x", "keep/y", "x"]
+            .map(|path| RepoPath::from_str(path).unwrap());
+        vfs.remove_empty_parents_of(paths);
+
+        assert!(!root.join("a").exists());
+        assert!(!root.join("keep/e").exists());
+        assert!(root.join("keep/untracked").exists());
+        // Only parents of the paths are removed.
+        assert!(root.join("other").exists());
+        assert!(root.exists());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_extended_length_path() {
+        use types::RepoPathBuf;
+
+        assert_eq!(
+            extended_length_path(Path::new(r"C:\repo\dir")),
+            PathBuf::from(r"\\?\C:\repo\dir")
+        );
+        assert_eq!(
+            extended_length_path(Path::new(r"\\server\share\repo")),
+            PathBuf::from(r"\\?\UNC\server\share\repo")
+        );
+        assert_eq!(
+            extended_length_path(Path::new(r"\\?\C:\repo")),
+            PathBuf::from(r"\\?\C:\repo")
+        );
+
+        let tmp = tempfile::tempdir().unwrap();
+        let vfs = VFS::new(tmp.path().to_path_buf()).unwrap();
+        assert_eq!(vfs.max_path_len(), WINDOWS_MAX_PATH - 1);
+
+        let vfs = VFS::new(extended_length_path(tmp.path())).unwrap();
+        assert!(vfs.max_path_len() > WINDOWS_MAX_PATH);
+        let long_path = RepoPathBuf::from_string(
+            std::iter::repeat("a".repeat(100))
+                .take(4)
+                .collect::<Vec<_>>()
+                .join("/"),
+        )
+        .unwrap();
+        assert!(vfs.path_len(&long_path) > WINDOWS_MAX_PATH);
+        vfs.write(&long_path, b"abc", UpdateFlag::Regular).unwrap();
+        assert_eq!(vfs.read(&long_path).unwrap(), b"abc");
+    }
 }